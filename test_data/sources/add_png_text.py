@@ -0,0 +1,31 @@
+#!/usr/bin/env python3
+"""Helper script to add a tEXt chunk just before a PNG file's IEND chunk
+
+(So that there's an ancillary chunk after the image data, where decoders which
+stop once they have the pixels will never look at it.)
+"""
+
+__author__ = "Stephan Sokolow (deitarion/SSokolow)"
+__license__ = "Public Domain"
+
+import struct, sys, zlib
+
+
+def main():
+    """The main entry point, compatible with setuptools entry points."""
+    in_path, out_path = sys.argv[1:3]
+    with open(in_path, 'rb') as fobj:
+        data = fobj.read()
+
+    payload = b'tEXt' + b'Comment\x00Test data for verify_files'
+    chunk = (struct.pack('>I', len(payload) - 4) + payload +
+             struct.pack('>I', zlib.crc32(payload)))
+    iend = len(data) - 12
+    assert data[iend + 4:iend + 8] == b'IEND'
+
+    with open(out_path, 'wb') as fobj:
+        fobj.write(data[:iend] + chunk + data[iend:])
+
+
+if __name__ == '__main__':
+    main()
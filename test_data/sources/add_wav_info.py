@@ -0,0 +1,38 @@
+#!/usr/bin/env python3
+"""Helper script to append a LIST/INFO chunk after a WAV file's data chunk
+
+(So that there's a metadata chunk after the audio data, where players which
+stop once they have the samples will never look at it.)
+"""
+
+__author__ = "Stephan Sokolow (deitarion/SSokolow)"
+__license__ = "Public Domain"
+
+import struct, sys
+
+
+def chunk(fourcc, payload):
+    """Build a RIFF chunk, including its padding byte if needed"""
+    return (fourcc + struct.pack('<I', len(payload)) + payload +
+            b'\x00' * (len(payload) % 2))
+
+
+def main():
+    """The main entry point, compatible with setuptools entry points."""
+    in_path, out_path = sys.argv[1:3]
+    with open(in_path, 'rb') as fobj:
+        data = fobj.read()
+
+    assert data[:4] == b'RIFF' and data[8:12] == b'WAVE'
+    assert struct.unpack('<I', data[4:8])[0] == len(data) - 8
+    info = chunk(b'LIST', b'INFO' +
+                 chunk(b'INAM', b'Test data for verify_files\x00') +
+                 chunk(b'ISFT', b'add_wav_info.py\x00'))
+    data += info
+
+    with open(out_path, 'wb') as fobj:
+        fobj.write(data[:4] + struct.pack('<I', len(data) - 8) + data[8:])
+
+
+if __name__ == '__main__':
+    main()
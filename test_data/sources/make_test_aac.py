@@ -0,0 +1,97 @@
+#!/usr/bin/env python3
+"""Helper script to generate a small raw AAC (ADTS) file from scratch
+
+(No AAC encoder is needed, since every raw data block is a single channel
+element with no spectral data, which decodes to silence. This writes one second
+of mono AAC-LC audio at 44.1 kHz, with an ID3v2 tag at the start.
+
+Add --damage to drop a byte from the middle of the stream, so that the frame it
+was in runs into the next one and frame sync is lost.)
+"""
+
+__author__ = "Stephan Sokolow (deitarion/SSokolow)"
+__license__ = "Public Domain"
+
+import struct
+import sys
+
+SAMPLE_RATE_INDEX = 4  # 44.1 kHz
+FRAMES = 44
+
+
+class BitWriter(object):
+    """Pack values most significant bit first, as AAC does"""
+
+    def __init__(self):
+        self.value, self.count = 0, 0
+
+    def write(self, value, count):
+        assert 0 <= value < (1 << count)
+        self.value = (self.value << count) | value
+        self.count += count
+
+    def getvalue(self):
+        """Return the bits written so far, padded out to a whole byte"""
+        pad = -self.count % 8
+        return (self.value << pad).to_bytes((self.count + pad) // 8, 'big')
+
+
+def silent_block():
+    """Encode a raw data block holding one single channel element with no
+    scale factor bands, followed by the terminating element"""
+    bits = BitWriter()
+    bits.write(0, 3)     # id_syn_ele: ID_SCE
+    bits.write(0, 4)     # element_instance_tag
+    bits.write(100, 8)   # global_gain
+    bits.write(0, 1)     # ics_reserved_bit
+    bits.write(0, 2)     # window_sequence: ONLY_LONG_SEQUENCE
+    bits.write(0, 1)     # window_shape
+    bits.write(0, 6)     # max_sfb
+    bits.write(0, 1)     # predictor_data_present
+    bits.write(0, 3)     # pulse, TNS, and gain control data present
+    bits.write(7, 3)     # id_syn_ele: ID_END
+    return bits.getvalue()
+
+
+def adts_frame(payload):
+    """Wrap a raw data block in an unprotected MPEG-4 AAC-LC ADTS frame"""
+    bits = BitWriter()
+    bits.write(0xFFF, 12)                # syncword
+    bits.write(0, 1)                     # ID: MPEG-4
+    bits.write(0, 2)                     # layer
+    bits.write(1, 1)                     # protection_absent
+    bits.write(1, 2)                     # profile: AAC LC
+    bits.write(SAMPLE_RATE_INDEX, 4)    # sampling_frequency_index
+    bits.write(0, 1)                     # private_bit
+    bits.write(1, 3)                     # channel_configuration: mono
+    bits.write(0, 4)                     # original/copy, home, copyright bits
+    bits.write(7 + len(payload), 13)     # frame_length
+    bits.write(0x7FF, 11)                # adts_buffer_fullness: VBR
+    bits.write(0, 2)                     # number_of_raw_data_blocks_in_frame
+    return bits.getvalue() + payload
+
+
+def id3v2_tag(title):
+    """Build an ID3v2.4 tag holding just a title"""
+    frame = b'\x03' + title  # UTF-8
+    frame = b'TIT2' + struct.pack('>I', len(frame)) + b'\0\0' + frame
+    size = len(frame)
+    synchsafe = bytes((size >> shift) & 0x7F for shift in (21, 14, 7, 0))
+    return b'ID3\x04\0\0' + synchsafe + frame
+
+
+def main():
+    """The main entry point, compatible with setuptools entry points."""
+    args = sys.argv[1:]
+    out_path, = [x for x in args if not x.startswith('--')]
+    frames = [adts_frame(silent_block()) for _ in range(FRAMES)]
+    if '--damage' in args:
+        frames[FRAMES // 2] = frames[FRAMES // 2][:-1]
+    data = id3v2_tag(b'Test File') + b''.join(frames)
+
+    with open(out_path, 'wb') as fobj:
+        fobj.write(data)
+
+
+if __name__ == '__main__':
+    main()
@@ -0,0 +1,95 @@
+#!/usr/bin/env python3
+"""Helper script to compress a synthetic input with Brotli at a given quality
+
+(The "mixed" input combines text, incompressible noise, and a table of binary
+numbers, then repeats some of them 2MiB later, so that across quality levels
+0 to 11 the streams use multiple meta-blocks, block type switches, context
+maps, dictionary references, and distances of over 2MiB, which still fit in
+the 16MiB window `brotli` uses without --large_window. The "noise" input is
+incompressible, so every quality level stores it as an uncompressed meta-block.
+Both inputs are the same every time, so decoders can be checked against their
+SHA-256 hashes.)
+"""
+
+__author__ = "Stephan Sokolow (deitarion/SSokolow)"
+__license__ = "Public Domain"
+
+import struct, sys
+
+try:
+    import brotli
+except ImportError:
+    import brotlicffi as brotli
+
+WORDS = [b'the', b'quick', b'brown', b'fox', b'jumps', b'over', b'lazy', b'dog',
+         b'The', b'Quick', b'and', b'of', b'to', b'in', b'is', b'it', b'that',
+         b'was', b'for', b'on', b'are', b'with', b'as', b'I', b'his', b'they',
+         b'be', b'at', b'one', b'have', b'this', b'from', b'or', b'had', b'by',
+         b'word', b'but', b'what', b'some', b'we', b'can', b'out', b'other',
+         b'were', b'all', b'there', b'when', b'up', b'use', b'your', b'how',
+         b'said', b'an', b'each', b'she', b'which', b'do', b'their', b'time',
+         b'if', b'will', b'way', b'about', b'many', b'then', b'them', b'write']
+
+# How far after the first copy of each section the second copy starts
+GAP = 2 * 1024 * 1024
+
+
+def xorshift(seed):
+    """Yield an endless stream of pseudo-random 32-bit values
+
+    (Used instead of `random` so the input can't change between Python
+    versions.)
+    """
+    state = seed
+    while True:
+        state ^= (state << 13) & 0xFFFFFFFF
+        state ^= state >> 17
+        state ^= (state << 5) & 0xFFFFFFFF
+        yield state
+
+
+def make_noise(rand, length):
+    """Build `length` bytes of incompressible data"""
+    return bytes(next(rand) & 0xFF for _ in range(length))
+
+
+def make_mixed():
+    """Build the data to be compressed for the "mixed" input"""
+    rand = xorshift(0x2545F491)
+
+    lines, line = [], []
+    for _ in range(2000):
+        line.append(WORDS[next(rand) % len(WORDS)])
+        if sum(len(x) + 1 for x in line) > 64:
+            lines.append(b' '.join(line) + b'.\n')
+            line = []
+    text = b''.join(lines)
+
+    noise = make_noise(rand, 6144)
+    table = b''.join(struct.pack('<I', x * x) for x in range(4096))
+
+    data = noise + text + table
+    return data + b'\x00' * (GAP - len(data)) + text[:8192] + noise[:4096]
+
+
+INPUTS = {
+    'mixed': make_mixed,
+    'noise': lambda: make_noise(xorshift(0x2545F491), 16384),
+}
+
+
+def main():
+    """The main entry point, compatible with setuptools entry points."""
+    args = sys.argv[1:]
+    if len(args) != 3 or args[0] not in INPUTS:
+        print("Usage: {} <mixed|noise> <quality> <output>".format(sys.argv[0]))
+        sys.exit(1)
+    make_input, quality, out_path = INPUTS[args[0]], int(args[1]), args[2]
+
+    # `lgwin=24` matches the `brotli` command's default
+    with open(out_path, 'wb') as fobj:
+        fobj.write(brotli.compress(make_input(), quality=quality, lgwin=24))
+
+
+if __name__ == '__main__':
+    main()
@@ -0,0 +1,108 @@
+#!/usr/bin/env python3
+"""Helper script to generate small MSZIP-compressed Microsoft Cabinet files
+
+(lcab only writes uncompressed cabinets, so this builds them by hand. The first
+file is followed by enough generated text to need several CFDATA blocks, so the
+compressor's history has to carry over from one block to the next, as MSZIP
+allows. Pass --part=1 or --part=2 to instead write one half of a two-cabinet
+set, split partway through a CFDATA block.)
+"""
+
+__author__ = "Stephan Sokolow (deitarion/SSokolow)"
+__license__ = "Public Domain"
+
+import struct, sys, zlib
+
+BLOCK_SIZE = 32768
+FLAG_PREV_CABINET = 0x0001
+FLAG_NEXT_CABINET = 0x0002
+TYPE_MSZIP = 1
+IFOLD_CONTINUED_FROM_PREV = 0xFFFD
+IFOLD_CONTINUED_TO_NEXT = 0xFFFE
+SET_ID = 0x1234
+PART_NAMES = (b'testfile.part1.cab', b'testfile.part2.cab')
+
+
+def checksum(data, seed=0):
+    """The CFDATA checksum, as computed by libmspack"""
+    whole = len(data) - len(data) % 4
+    for word, in struct.iter_unpack('<I', data[:whole]):
+        seed ^= word
+    tail = 0
+    for byte in data[whole:]:
+        tail = (tail << 8) | byte
+    return seed ^ tail
+
+
+def cfdata(data, uncompressed_size):
+    """Build a CFDATA record"""
+    fields = struct.pack('<HH', len(data), uncompressed_size)
+    return struct.pack('<I', checksum(fields, checksum(data))) + fields + data
+
+
+def mszip_blocks(data):
+    """Compress `data` as MSZIP blocks, returning (compressed, size) pairs"""
+    blocks = []
+    for start in range(0, len(data), BLOCK_SIZE):
+        history = data[max(0, start - BLOCK_SIZE):start]
+        compressor = (zlib.compressobj(9, zlib.DEFLATED, -15, zdict=history)
+                      if history else zlib.compressobj(9, zlib.DEFLATED, -15))
+        chunk = data[start:start + BLOCK_SIZE]
+        blocks.append((b'CK' + compressor.compress(chunk) + compressor.flush(),
+                       len(chunk)))
+    return blocks
+
+
+def cabinet(files, blocks, flags=0, index=0):
+    """Assemble a one-folder cabinet from (name, size, offset, folder) tuples
+    and (compressed, size) blocks"""
+    strings = b''
+    if flags & FLAG_PREV_CABINET:
+        strings += PART_NAMES[index - 1] + b'\0\0'
+    if flags & FLAG_NEXT_CABINET:
+        strings += PART_NAMES[index + 1] + b'\0\0'
+    files_offset = 36 + len(strings) + 8
+    entries = b''.join(
+        struct.pack('<IIHHHH', size, offset, folder, 0x4E21, 0x6000, 0x20) +
+        name + b'\0' for name, size, offset, folder in files)
+    data = b''.join(cfdata(compressed, size) for compressed, size in blocks)
+    data_offset = files_offset + len(entries)
+    total = data_offset + len(data)
+
+    header = struct.pack('<4sIIIIIBBHHHHH', b'MSCF', 0, total, 0, files_offset,
+                         0, 3, 1, 1, len(files), flags, SET_ID, index)
+    folder = struct.pack('<IHH', data_offset, len(blocks), TYPE_MSZIP)
+    return header + strings + folder + entries + data
+
+
+def main():
+    """The main entry point, compatible with setuptools entry points."""
+    args = sys.argv[1:]
+    in_path, out_path = [x for x in args if not x.startswith('--')]
+
+    with open(in_path, 'rb') as fobj:
+        first = fobj.read()
+    filler = b''.join(b'Line %d of the filler text\n' % x for x in range(4000))
+    blocks = mszip_blocks(first + filler)
+    files = [(b'testfile.txt', len(first), 0, 0),
+             (b'filler.txt', len(filler), len(first), 0)]
+
+    if '--part=1' in args:
+        split, size = blocks[1]
+        blocks = blocks[:1] + [(split[:len(split) // 2], 0)]
+        files[1] = files[1][:3] + (IFOLD_CONTINUED_TO_NEXT,)
+        data = cabinet(files, blocks, FLAG_NEXT_CABINET, 0)
+    elif '--part=2' in args:
+        split, size = blocks[1]
+        blocks = [(split[len(split) // 2:], size)] + blocks[2:]
+        files = [files[1][:3] + (IFOLD_CONTINUED_FROM_PREV,)]
+        data = cabinet(files, blocks, FLAG_PREV_CABINET, 1)
+    else:
+        data = cabinet(files, blocks)
+
+    with open(out_path, 'wb') as fobj:
+        fobj.write(data)
+
+
+if __name__ == '__main__':
+    main()
@@ -0,0 +1,152 @@
+#!/usr/bin/env python3
+"""Helper script to generate a minimal DICOM file holding a two-frame image
+
+(No DICOM toolkit is needed. The data set has a sequence of each kind, one with
+an undefined length and one with a defined length, and a 16x16 8-bit greyscale
+gradient for each frame.
+
+Pass --implicit or --deflated to use the Implicit VR Little Endian or Deflated
+Explicit VR Little Endian transfer syntax, --rle to store the pixel data as
+encapsulated RLE Lossless fragments with a Basic Offset Table, or --truncated to
+cut the end off the pixel data.)
+"""
+
+__author__ = "Stephan Sokolow (deitarion/SSokolow)"
+__license__ = "Public Domain"
+
+import struct, sys, zlib
+
+EXPLICIT_LE = '1.2.840.10008.1.2.1'
+IMPLICIT_LE = '1.2.840.10008.1.2'
+DEFLATED_LE = '1.2.840.10008.1.2.1.99'
+RLE_LOSSLESS = '1.2.840.10008.1.2.5'
+
+LONG_VRS = {'OB', 'OD', 'OF', 'OL', 'OV', 'OW', 'SQ', 'SV', 'UC', 'UN', 'UR',
+            'UT', 'UV'}
+UNDEFINED = 0xFFFFFFFF
+
+SOP_CLASS = '1.2.840.10008.5.1.4.1.1.7.2'  # Multi-frame Grayscale Byte SC
+INSTANCE = '2.25.185207553157436590710998577081039472895'
+ROWS = COLUMNS = 16
+FRAMES = 2
+
+
+def element(group, elem, vr, value, explicit, length=None):
+    """Encode a data element, padding its value to an even length"""
+    if isinstance(value, str):
+        value = value.encode('ascii')
+        value += (b'\0' if vr == 'UI' else b' ') * (len(value) % 2)
+    value += b'\0' * (len(value) % 2)
+    length = len(value) if length is None else length
+
+    head = struct.pack('<HH', group, elem)
+    if not explicit:
+        return head + struct.pack('<I', length) + value
+    elif vr in LONG_VRS:
+        return head + vr.encode() + struct.pack('<xxI', length) + value
+    return head + vr.encode() + struct.pack('<H', length) + value
+
+
+def item(body, length=None):
+    """Encode a sequence item or fragment"""
+    return struct.pack('<HHI', 0xFFFE, 0xE000,
+                       len(body) if length is None else length) + body
+
+
+def delimiter(elem):
+    """Encode an item (0xE00D) or sequence (0xE0DD) delimiter"""
+    return struct.pack('<HHI', 0xFFFE, elem, 0)
+
+
+def rle_frame(pixels):
+    """Encode one 8-bit frame as a single RLE segment of literal runs"""
+    segment = b''
+    for start in range(0, len(pixels), 128):
+        run = pixels[start:start + 128]
+        segment += bytes([len(run) - 1]) + run
+    segment += b'\0' * (len(segment) % 2)
+    return struct.pack('<16I', 1, 64, *[0] * 14) + segment
+
+
+def main():
+    """The main entry point, compatible with setuptools entry points."""
+    args = sys.argv[1:]
+    out_path = [x for x in args if not x.startswith('--')][0]
+    syntax = EXPLICIT_LE
+    if '--implicit' in args:
+        syntax = IMPLICIT_LE
+    elif '--deflated' in args:
+        syntax = DEFLATED_LE
+    elif '--rle' in args:
+        syntax = RLE_LOSSLESS
+    explicit = syntax != IMPLICIT_LE
+
+    def elem(group, num, vr, value, length=None):
+        return element(group, num, vr, value, explicit, length)
+
+    frames = [bytes((x + y + 64 * frame) % 256
+                    for y in range(ROWS) for x in range(COLUMNS))
+              for frame in range(FRAMES)]
+    if syntax == RLE_LOSSLESS:
+        fragments = [rle_frame(x) for x in frames]
+        offsets, position = [], 0
+        for fragment in fragments:
+            offsets.append(position)
+            position += 8 + len(fragment)
+        pixel_data = elem(0x7FE0, 0x0010, 'OB', b'', UNDEFINED) + item(
+            struct.pack('<%dI' % len(offsets), *offsets)) + b''.join(
+                item(x) for x in fragments) + delimiter(0xE0DD)
+    else:
+        pixel_data = elem(0x7FE0, 0x0010, 'OB', b''.join(frames))
+
+    referenced = (elem(0x0008, 0x1150, 'UI', SOP_CLASS) +
+                  elem(0x0008, 0x1155, 'UI', INSTANCE + '.1'))
+    requested = elem(0x0040, 0x1001, 'SH', 'REQ1')
+    data_set = b''.join([
+        elem(0x0008, 0x0016, 'UI', SOP_CLASS),
+        elem(0x0008, 0x0018, 'UI', INSTANCE),
+        elem(0x0008, 0x0060, 'CS', 'OT'),
+        elem(0x0008, 0x1140, 'SQ', b'', UNDEFINED) + item(referenced, UNDEFINED) +
+        delimiter(0xE00D) + delimiter(0xE0DD),
+        elem(0x0010, 0x0010, 'PN', 'Test^Patient'),
+        elem(0x0010, 0x0020, 'LO', 'TEST'),
+        elem(0x0020, 0x000D, 'UI', INSTANCE + '.2'),
+        elem(0x0020, 0x000E, 'UI', INSTANCE + '.3'),
+        elem(0x0028, 0x0002, 'US', struct.pack('<H', 1)),
+        elem(0x0028, 0x0004, 'CS', 'MONOCHROME2'),
+        elem(0x0028, 0x0008, 'IS', str(FRAMES)),
+        elem(0x0028, 0x0010, 'US', struct.pack('<H', ROWS)),
+        elem(0x0028, 0x0011, 'US', struct.pack('<H', COLUMNS)),
+        elem(0x0028, 0x0100, 'US', struct.pack('<H', 8)),
+        elem(0x0028, 0x0101, 'US', struct.pack('<H', 8)),
+        elem(0x0028, 0x0102, 'US', struct.pack('<H', 7)),
+        elem(0x0028, 0x0103, 'US', struct.pack('<H', 0)),
+        elem(0x0040, 0x0275, 'SQ', item(requested)),
+        pixel_data,
+    ])
+    if syntax == DEFLATED_LE:
+        compressor = zlib.compressobj(9, zlib.DEFLATED, -15)
+        data_set = compressor.compress(data_set) + compressor.flush()
+        data_set += b'\0' * (len(data_set) % 2)
+
+    # The File Meta Information is always Explicit VR Little Endian
+    meta = b''.join(element(0x0002, num, vr, value, True) for num, vr, value in [
+        (0x0001, 'OB', b'\0\1'),
+        (0x0002, 'UI', SOP_CLASS),
+        (0x0003, 'UI', INSTANCE),
+        (0x0010, 'UI', syntax),
+        (0x0012, 'UI', '2.25.1'),
+        (0x0013, 'SH', 'VERIFY_FILES'),
+    ])
+    meta = element(0x0002, 0x0000, 'UL', struct.pack('<I', len(meta)),
+                   True) + meta
+
+    output = b'\0' * 128 + b'DICM' + meta + data_set
+    if '--truncated' in args:
+        output = output[:-100]
+    with open(out_path, 'wb') as fobj:
+        fobj.write(output)
+
+
+if __name__ == '__main__':
+    main()
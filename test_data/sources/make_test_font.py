@@ -0,0 +1,146 @@
+#!/usr/bin/env python3
+"""Helper script to generate a minimal TrueType font (or collection) from scratch
+
+(A single glyph for "A" plus .notdef, with every table checksum and the head
+table's checkSumAdjustment filled in properly, so checkers have something small
+to verify. Pass --bad to flip one bit inside the glyf table afterwards without
+fixing the checksums.)
+"""
+
+__author__ = "Stephan Sokolow (deitarion/SSokolow)"
+__license__ = "Public Domain"
+
+import struct, sys
+
+FAMILY = "VerifyFilesTest"
+
+
+def checksum(data):
+    """Compute an OpenType table checksum, padding with zeroes as needed"""
+    data += b'\x00' * (-len(data) % 4)
+    return sum(struct.unpack('>%dI' % (len(data) // 4), data)) & 0xFFFFFFFF
+
+
+def box_glyph(x_min, y_min, x_max, y_max):
+    """Build a simple glyph consisting of one rectangular contour"""
+    xs = [x_min, 0, x_max - x_min, 0]
+    ys = [y_min, y_max - y_min, 0, y_min - y_max]
+    glyph = struct.pack('>h4hHH', 1, x_min, y_min, x_max, y_max, 3, 0)
+    glyph += b'\x01' * 4 + struct.pack('>4h', *xs) + struct.pack('>4h', *ys)
+    return glyph + b'\x00' * (len(glyph) % 2)
+
+
+def name_table(style):
+    """Build a name table with Windows-platform family, style, and full names"""
+    strings = [(1, FAMILY), (2, style), (4, FAMILY + ' ' + style),
+               (6, FAMILY + '-' + style)]
+    records, storage = b'', b''
+    for name_id, text in strings:
+        encoded = text.encode('utf-16-be')
+        records += struct.pack('>6H', 3, 1, 0x409, name_id, len(encoded),
+                               len(storage))
+        storage += encoded
+    return struct.pack('>3H', 0, len(strings),
+                       6 + len(records)) + records + storage
+
+
+def tables(style):
+    """Build the tables of a font with the given style name"""
+    glyphs = [box_glyph(50, 0, 450, 700), box_glyph(100, 0, 500, 700)]
+    loca = [0, len(glyphs[0]), len(glyphs[0]) + len(glyphs[1])]
+    segments = [(0x41, 0x41, 1 - 0x41), (0xFFFF, 0xFFFF, 1)]
+    cmap4 = struct.pack('>7H', 4, 16 + 8 * len(segments), 0,
+                        2 * len(segments), 4, 1, 0)
+    cmap4 += struct.pack('>2H', *[x[1] for x in segments]) + b'\x00\x00'
+    cmap4 += struct.pack('>2H', *[x[0] for x in segments])
+    cmap4 += struct.pack('>2h', *[x[2] for x in segments]) + b'\x00' * 4
+    return {
+        b'OS/2': struct.pack(
+            '>Hh3H11h10s4I4s3H3h2H2I2h3H', 4, 600, 400, 5, 0, 650, 600,
+            0, 75, 650, 600, 0, 350, 50, 300, 0, b'\x00' * 10, 1, 0, 0, 0,
+            b'NONE', 0x40, 0x41, 0x41, 800, -200, 0, 800, 200, 1, 0, 500,
+            700, 0, 0x20, 1),
+        b'cmap': struct.pack('>2H2HI', 0, 1, 3, 1, 12) + cmap4,
+        b'glyf': b''.join(glyphs),
+        b'head': struct.pack('>3IIHHqq4hHHhhh', 0x00010000, 0x00010000, 0,
+                             0x5F0F3CF5, 0x000B, 1000, 0, 0, 50, 0, 500,
+                             700, 0, 8, 2, 0, 0),
+        b'hhea': struct.pack('>I3hH3h3h4hhH', 0x00010000, 800, -200, 0, 600,
+                             50, 50, 500, 1, 0, 0, 0, 0, 0, 0, 0, 2),
+        b'hmtx': struct.pack('>HhHh', 500, 50, 600, 100),
+        b'loca': struct.pack('>3H', *[x // 2 for x in loca]),
+        b'maxp': struct.pack('>I14H', 0x00010000, 2, 4, 1, 0, 0, 2, 0, 0, 0,
+                             0, 0, 0, 0, 0),
+        b'name': name_table(style),
+        b'post': struct.pack('>IIhhIIIII', 0x00030000, 0, -100, 50, 0, 0, 0,
+                             0, 0),
+    }
+
+
+def directory(font, offsets, num_tables):
+    """Build the table directory for `font`, whose tables are at `offsets`"""
+    entry_selector = num_tables.bit_length() - 1
+    search_range = 16 * (1 << entry_selector)
+    out = struct.pack('>I4H', 0x00010000, num_tables, search_range,
+                      entry_selector, num_tables * 16 - search_range)
+    for tag in sorted(font):
+        out += struct.pack('>4s3I', tag, checksum(font[tag]), offsets[tag],
+                           len(font[tag]))
+    return out
+
+
+def build(fonts):
+    """Lay out one font, or a collection if given several, sharing tables
+    which are identical between them"""
+    num_tables = len(fonts[0])
+    header_len = 12 + 4 * len(fonts) if len(fonts) > 1 else 0
+    data_start = header_len + len(fonts) * (12 + 16 * num_tables)
+
+    blobs, offsets = b'', []
+    for font in fonts:
+        font_offsets = {}
+        for tag in sorted(font):
+            found = blobs.find(font[tag])
+            if found == -1 or found % 4:
+                found = len(blobs)
+                blobs += font[tag] + b'\x00' * (-len(font[tag]) % 4)
+            font_offsets[tag] = data_start + found
+        offsets.append(font_offsets)
+
+    out = b''
+    if len(fonts) > 1:
+        out += struct.pack('>4sII', b'ttcf', 0x00010000, len(fonts))
+        for i in range(len(fonts)):
+            out += struct.pack('>I', header_len + i * (12 + 16 * num_tables))
+    for font, font_offsets in zip(fonts, offsets):
+        out += directory(font, font_offsets, num_tables)
+    out += blobs
+
+    # There's only one head table, so a collection gets one adjustment too
+    head = offsets[0][b'head']
+    adjustment = (0xB1B0AFBA - checksum(out)) & 0xFFFFFFFF
+    return out[:head + 8] + struct.pack('>I', adjustment) + out[head + 12:]
+
+
+def main():
+    """The main entry point, compatible with setuptools entry points."""
+    args = sys.argv[1:]
+    collection, bad = '--collection' in args, '--bad' in args
+    out_path = [x for x in args if not x.startswith('--')][0]
+
+    fonts = [tables('Regular')]
+    if collection:
+        fonts.append(tables('Alternate'))
+    data = build(fonts)
+
+    if bad:
+        glyf = data.find(fonts[0][b'glyf'])
+        offset = glyf + len(fonts[0][b'glyf']) // 2
+        data = data[:offset] + bytes([data[offset] ^ 1]) + data[offset + 1:]
+
+    with open(out_path, 'wb') as fobj:
+        fobj.write(data)
+
+
+if __name__ == '__main__':
+    main()
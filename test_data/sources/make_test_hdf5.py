@@ -0,0 +1,290 @@
+#!/usr/bin/env python3
+"""Helper script to generate a minimal HDF5 file holding one dataset
+
+(h5py and the HDF5 tools aren't needed. The root group holds a dataset named
+"data", containing the input file as an array of bytes, and an empty group
+named "sub".
+
+By default, this writes the original format: a version 0 superblock, version 1
+object headers, and groups made of B-trees, symbol table nodes, and local heaps,
+none of which have checksums. Pass --v2 to write a version 2 superblock and
+version 2 object headers instead, with their links stored in the headers and
+their metadata checksummed. Pass --userblock to put a 512-byte user block in
+front of the superblock.
+
+For the bad files, --truncate cuts the end off the dataset's contents, and
+--damage flips a bit in the dataset's object header.)
+"""
+
+__author__ = "Stephan Sokolow (deitarion/SSokolow)"
+__license__ = "Public Domain"
+
+import struct, sys
+
+SIGNATURE = b'\x89HDF\r\n\x1a\n'
+UNDEF = 0xFFFFFFFFFFFFFFFF
+LEAF_K, INTERNAL_K = 4, 16
+
+# Message types
+NIL, DATASPACE, LINK_INFO, DATATYPE, FILL_VALUE = 0x00, 0x01, 0x02, 0x03, 0x05
+LINK, LAYOUT, GROUP_INFO = 0x06, 0x08, 0x0A
+CONTINUATION, SYMBOL_TABLE = 0x10, 0x11
+
+
+def lookup3(data):
+    """Bob Jenkins's lookup3 hashlittle(), as used for HDF5 checksums"""
+    mask = 0xFFFFFFFF
+
+    def rot(x, k):
+        return ((x << k) | (x >> (32 - k))) & mask
+
+    a = b = c = (0xDEADBEEF + len(data)) & mask
+    while len(data) > 12:
+        x, y, z = struct.unpack('<III', data[:12])
+        a, b, c = (a + x) & mask, (b + y) & mask, (c + z) & mask
+        a = ((a - c) & mask) ^ rot(c, 4); c = (c + b) & mask
+        b = ((b - a) & mask) ^ rot(a, 6); a = (a + c) & mask
+        c = ((c - b) & mask) ^ rot(b, 8); b = (b + a) & mask
+        a = ((a - c) & mask) ^ rot(c, 16); c = (c + b) & mask
+        b = ((b - a) & mask) ^ rot(a, 19); a = (a + c) & mask
+        c = ((c - b) & mask) ^ rot(b, 4); b = (b + a) & mask
+        data = data[12:]
+    if not data:
+        return c
+    x, y, z = struct.unpack('<III', data.ljust(12, b'\0'))
+    a, b, c = (a + x) & mask, (b + y) & mask, (c + z) & mask
+    c = ((c ^ b) - rot(b, 14)) & mask
+    a = ((a ^ c) - rot(c, 11)) & mask
+    b = ((b ^ a) - rot(a, 25)) & mask
+    c = ((c ^ b) - rot(b, 16)) & mask
+    a = ((a ^ c) - rot(c, 4)) & mask
+    b = ((b ^ a) - rot(a, 14)) & mask
+    return ((c ^ b) - rot(b, 24)) & mask
+
+
+def pad8(data):
+    """Pad data out to a multiple of 8 bytes"""
+    return data + b'\0' * (-len(data) % 8)
+
+
+def offsets(*values):
+    """Encode 8-byte file addresses or lengths"""
+    return b''.join(struct.pack('<Q', x) for x in values)
+
+
+class Layout(object):
+    """Hand out addresses for structures in the order they're laid out"""
+    def __init__(self, start):
+        self.end = start
+
+    def alloc(self, size):
+        """Reserve size bytes (rounded up to a multiple of 8)"""
+        addr = self.end
+        self.end += size + (-size % 8)
+        return addr
+
+
+def v1_messages(messages):
+    """Encode the messages of a version 1 object header or continuation block"""
+    return b''.join(struct.pack('<HHB3x', kind, len(pad8(data)), 0) + pad8(data)
+                    for kind, data in messages)
+
+
+def v1_header(messages, continued=0):
+    """Encode a version 1 object header, with continued more messages elsewhere"""
+    body = v1_messages(messages)
+    return struct.pack('<BBHII4x', 1, 0, len(messages) + continued, 1,
+                       len(body)) + body
+
+
+def v2_messages(messages):
+    """Encode the messages of a version 2 object header or continuation block"""
+    return b''.join(struct.pack('<BHB', kind, len(data), 0) + data
+                    for kind, data in messages)
+
+
+def v2_header(messages):
+    """Encode a version 2 object header with a one-byte chunk size"""
+    body = v2_messages(messages)
+    data = b'OHDR' + struct.pack('<BBB', 2, 0, len(body)) + body
+    return data + struct.pack('<I', lookup3(data))
+
+
+def v2_continuation(messages):
+    """Encode a version 2 object header continuation block"""
+    data = b'OCHK' + v2_messages(messages)
+    return data + struct.pack('<I', lookup3(data))
+
+
+def dataset_messages(length, data_addr, v2):
+    """The messages for a one-dimensional dataset of length unsigned bytes
+
+    The layout message is returned separately, so it can be put in a
+    continuation block.
+    """
+    if v2:
+        dataspace = struct.pack('<BBBB', 2, 1, 0, 1) + offsets(length)
+        fill_value = struct.pack('<BB', 3, 0x0A)
+    else:
+        dataspace = struct.pack('<BBB5x', 1, 1, 0) + offsets(length)
+        fill_value = struct.pack('<BBBB', 2, 2, 2, 0)
+    datatype = struct.pack('<B3sIHH', 0x10, b'\0\0\0', 1, 0, 8)
+    layout = struct.pack('<BB', 3, 1) + offsets(data_addr, length)
+    return [(DATASPACE, dataspace), (DATATYPE, datatype),
+            (FILL_VALUE, fill_value)], (LAYOUT, layout)
+
+
+def local_heap(names, addr):
+    """Encode a local heap holding names, and the heap offset of each
+
+    The data segment follows the header, with a free block at the end.
+    """
+    segment, positions = b'', {}
+    for name in [b''] + names:
+        positions[name] = len(segment)
+        segment += pad8(name + b'\0')
+    free_offset = len(segment)
+    segment += struct.pack('<QQ', 1, 64) + b'\0' * 48
+    header = b'HEAP' + struct.pack('<B3x', 0) + offsets(
+        len(segment), free_offset, addr + 32)
+    return header + segment, positions
+
+
+def btree_leaf(children, keys):
+    """Encode a group B-tree leaf node, with space for 2K children"""
+    data = b'TREE' + struct.pack('<BBH', 0, 0, len(children)) + offsets(UNDEF, UNDEF)
+    for key, child in zip(keys, children):
+        data += offsets(key, child)
+    data += offsets(keys[len(children)])
+    return data.ljust(24 + (4 * INTERNAL_K + 1) * 8, b'\0')
+
+
+def symbol_node(entries):
+    """Encode a symbol table node from (name offset, address, scratch) entries"""
+    data = b'SNOD' + struct.pack('<BBH', 1, 0, len(entries))
+    for name, addr, scratch in entries:
+        cache_type = 1 if scratch else 0
+        data += offsets(name, addr) + struct.pack('<II', cache_type, 0)
+        data += (offsets(*scratch) if scratch else b'').ljust(16, b'\0')
+    return data.ljust(8 + 2 * LEAF_K * 40, b'\0')
+
+
+def build_v0(contents):
+    """Lay out a file with a version 0 superblock"""
+    blocks = {}
+    root_heap, names = local_heap([b'data', b'sub'], 0)
+    sub_heap = local_heap([], 0)[0]
+    group_header = v1_header([(SYMBOL_TABLE, offsets(0, 0))])
+    btree = btree_leaf([], [0])
+    messages, layout_message = dataset_messages(len(contents), 0, False)
+    continued = [layout_message, (NIL, b'')]
+    data_header = v1_header(messages + [(CONTINUATION, offsets(0, 0))],
+                            len(continued))
+    continuation = v1_messages(continued)
+
+    # Work out the addresses first, since most structures point at others
+    layout = Layout(96)
+    root_header, root_btree, root_heap_addr, root_snod = [layout.alloc(x) for x in [
+        len(group_header), len(btree), len(root_heap), len(symbol_node([]))]]
+    sub_header, sub_btree, sub_heap_addr = [layout.alloc(x) for x in [
+        len(group_header), len(btree), len(sub_heap)]]
+    data_header_addr, continuation_addr = [layout.alloc(x) for x in [
+        len(data_header), len(continuation)]]
+    data = layout.alloc(len(contents))
+
+    blocks[root_heap_addr] = local_heap([b'data', b'sub'], root_heap_addr)[0]
+    blocks[root_header] = v1_header([(SYMBOL_TABLE, offsets(root_btree, root_heap_addr))])
+    blocks[root_btree] = btree_leaf([root_snod], [0, names[b'sub']])
+    blocks[root_snod] = symbol_node([
+        (names[b'data'], data_header_addr, None),
+        (names[b'sub'], sub_header, (sub_btree, sub_heap_addr)),
+    ])
+
+    blocks[sub_heap_addr] = local_heap([], sub_heap_addr)[0]
+    blocks[sub_header] = v1_header([(SYMBOL_TABLE, offsets(sub_btree, sub_heap_addr))])
+    blocks[sub_btree] = btree
+
+    messages, layout_message = dataset_messages(len(contents), data, False)
+    continued = [layout_message, (NIL, b'')]
+    blocks[data_header_addr] = v1_header(messages + [
+        (CONTINUATION, offsets(continuation_addr, len(continuation)))], len(continued))
+    blocks[continuation_addr] = v1_messages(continued)
+    blocks[data] = contents
+
+    superblock = SIGNATURE + struct.pack('<BBBBBBBBHHI', 0, 0, 0, 0, 0, 8, 8, 0,
+                                         LEAF_K, INTERNAL_K, 0)
+    superblock += offsets(0, UNDEF, layout.end, UNDEF)
+    superblock += offsets(0, root_header) + struct.pack('<II', 1, 0)
+    superblock += offsets(root_btree, root_heap_addr)
+    blocks[0] = superblock
+    return blocks, layout.end, data_header_addr
+
+
+def build_v2(contents):
+    """Lay out a file with a version 2 superblock"""
+    blocks = {}
+    group_info = (GROUP_INFO, struct.pack('<BB', 0, 0))
+    link_info = (LINK_INFO, struct.pack('<BB', 0, 0) + offsets(UNDEF, UNDEF))
+
+    def link(name, addr):
+        return (LINK, struct.pack('<BBB', 1, 0, len(name)) + name + offsets(addr))
+
+    # The headers' sizes don't depend on the addresses in them, so lay them
+    # out with placeholder addresses first
+    messages, layout_message = dataset_messages(len(contents), 0, True)
+    sizes = [
+        len(v2_header([link_info, group_info, link(b'data', 0), link(b'sub', 0)])),
+        len(v2_header([link_info, group_info])),
+        len(v2_header(messages + [(CONTINUATION, offsets(0, 0))])),
+        len(v2_continuation([layout_message])),
+    ]
+    layout = Layout(48)
+    root, sub, data_header, data_continuation = [layout.alloc(x) for x in sizes]
+    data = layout.alloc(len(contents))
+
+    messages, layout_message = dataset_messages(len(contents), data, True)
+    blocks[root] = v2_header([link_info, group_info, link(b'data', data_header),
+                              link(b'sub', sub)])
+    blocks[sub] = v2_header([link_info, group_info])
+    blocks[data_header] = v2_header(messages + [
+        (CONTINUATION, offsets(data_continuation, sizes[3]))])
+    blocks[data_continuation] = v2_continuation([layout_message])
+    blocks[data] = contents
+
+    superblock = SIGNATURE + struct.pack('<BBBB', 2, 8, 8, 0)
+    superblock += offsets(0, UNDEF, layout.end, root)
+    blocks[0] = superblock + struct.pack('<I', lookup3(superblock))
+    return blocks, layout.end, data_header
+
+
+def main():
+    """The main entry point, compatible with setuptools entry points."""
+    args = sys.argv[1:]
+    in_path, out_path = [x for x in args if not x.startswith('--')]
+    with open(in_path, 'rb') as fobj:
+        contents = fobj.read()
+
+    blocks, end, damage_at = (build_v2 if '--v2' in args else build_v0)(contents)
+    output = bytearray(end)
+    for addr, block in blocks.items():
+        output[addr:addr + len(block)] = block
+
+    if '--damage' in args:
+        output[damage_at + 20] ^= 0x01
+    if '--truncate' in args:
+        output = output[:-100]
+    if '--userblock' in args:
+        # Addresses are relative to the superblock, so only the base address
+        # (and the checksum covering it) need to change
+        base = 12 if '--v2' in args else 24
+        output[base:base + 8] = struct.pack('<Q', 512)
+        if '--v2' in args:
+            output[44:48] = struct.pack('<I', lookup3(bytes(output[:44])))
+        output = b'A user block, which HDF5 leaves alone.\n'.ljust(512, b'\0') + output
+
+    with open(out_path, 'wb') as fobj:
+        fobj.write(output)
+
+
+if __name__ == '__main__':
+    main()
@@ -0,0 +1,170 @@
+#!/usr/bin/env python3
+"""Helper script to generate small HEIF and AVIF images from scratch
+
+(No HEIF library is needed, since the box structure is all that gets checked.
+The coded image data is filler, so these won't decode.
+
+By default, this writes a HEIC file whose primary item is a 2x1 grid, stored in
+the `idat` box, of two HEVC tiles, with a thumbnail and an Exif item referring
+to it. Pass --avif for an AVIF file with an alpha plane as an auxiliary image,
+using a base offset in its `iloc` box.
+
+Add --damage to cut the HEIC file short, with an `mdat` box that runs to the end
+of the file so only `iloc` shows it, or, for --avif, to associate the primary
+item with a property that doesn't exist.)
+"""
+
+__author__ = "Stephan Sokolow (deitarion/SSokolow)"
+__license__ = "Public Domain"
+
+import struct
+import sys
+
+
+def box(kind, payload):
+    """Encode a box"""
+    return struct.pack('>I', 8 + len(payload)) + kind + payload
+
+
+def full(kind, version, flags, payload):
+    """Encode a full box"""
+    return box(kind, struct.pack('>I', (version << 24) | flags) + payload)
+
+
+def infe(item_id, item_type, name=b''):
+    """Encode a version 2 item info entry"""
+    return full(b'infe', 2, 0, struct.pack('>HH', item_id, 0) + item_type +
+                name + b'\0')
+
+
+def iinf(entries):
+    """Encode an item info box"""
+    return full(b'iinf', 0, 0, struct.pack('>H', len(entries)) +
+                b''.join(entries))
+
+
+def iref(references):
+    """Encode a version 0 item reference box from (type, from, [to]) tuples"""
+    return full(b'iref', 0, 0, b''.join(
+        box(kind, struct.pack('>HH', src, len(dests)) +
+            b''.join(struct.pack('>H', x) for x in dests))
+        for kind, src, dests in references))
+
+
+def ispe(width, height):
+    """Encode an image spatial extents property"""
+    return full(b'ispe', 0, 0, struct.pack('>II', width, height))
+
+
+def iprp(properties, associations):
+    """Encode an item properties box from (item, [1-based property]) pairs"""
+    ipma = struct.pack('>I', len(associations))
+    for item_id, indices in associations:
+        ipma += struct.pack('>HB', item_id, len(indices))
+        ipma += bytes(0x80 | x for x in indices)
+    return box(b'iprp', box(b'ipco', b''.join(properties)) +
+               full(b'ipma', 0, 0, ipma))
+
+
+def ftyp(major, compatible):
+    """Encode a file type box"""
+    return box(b'ftyp', major + b'\0\0\0\0' + b''.join(compatible))
+
+
+def hdlr():
+    """Encode the handler box for a still image"""
+    return full(b'hdlr', 0, 0, b'\0\0\0\0pict' + b'\0' * 12 + b'\0')
+
+
+def iloc(version, base_offset_size, items):
+    """Encode an iloc box with 32-bit offsets and lengths
+
+    `items` holds (item ID, construction method, base offset, [(offset,
+    length)]) tuples.
+    """
+    payload = bytes([0x44, base_offset_size << 4])
+    payload += struct.pack('>H', len(items))
+    for item_id, method, base, extents in items:
+        payload += struct.pack('>H', item_id)
+        if version:
+            payload += struct.pack('>H', method)
+        payload += struct.pack('>H', 0)
+        payload += base.to_bytes(base_offset_size, 'big')
+        payload += struct.pack('>H', len(extents))
+        for offset, length in extents:
+            payload += struct.pack('>II', offset, length)
+    return full(b'iloc', version, 0, payload)
+
+
+def heic(damage):
+    """Build a HEIC file with a grid image as its primary item"""
+    tiles = [b'\0\0\0\x10\x26\x01' + bytes([x]) * 10 for x in (0xAF, 0xB0)]
+    thumb = b'\0\0\0\x0c\x26\x01' + b'\x55' * 6
+    exif = b'\0\0\0\x06Exif\0\0MM\0\x2a\0\0\0\x08\0\0'
+    grid = bytes([0, 0, 0, 1]) + struct.pack('>HH', 128, 64)
+    hvcc = box(b'hvcC', b'\x01' + b'\0' * 22)
+    properties = [hvcc, ispe(64, 64), ispe(128, 64), ispe(32, 16)]
+
+    def build(mdat_start):
+        offset, extents = mdat_start, []
+        for data in tiles + [thumb, exif]:
+            extents.append((offset, len(data)))
+            offset += len(data)
+        meta = full(b'meta', 0, 0, hdlr() + full(b'pitm', 0, 0, b'\0\x01') +
+            iinf([infe(1, b'grid'), infe(2, b'hvc1'), infe(3, b'hvc1'),
+                  infe(4, b'hvc1'), infe(5, b'Exif')]) +
+            iref([(b'dimg', 1, [2, 3]), (b'thmb', 4, [1]),
+                  (b'cdsc', 5, [1])]) +
+            iprp(properties, [(1, [3]), (2, [1, 2]), (3, [1, 2]),
+                              (4, [1, 4])]) +
+            box(b'idat', grid) +
+            iloc(1, 0, [(1, 1, 0, [(0, len(grid))])] +
+                 [(x + 2, 0, 0, [extent]) for x, extent in enumerate(extents)]))
+        head = ftyp(b'heic', [b'mif1', b'heic', b'miaf']) + meta
+        return head, extents
+
+    head, _ = build(0)
+    head, _ = build(len(head) + 8)
+    mdat = b''.join(tiles + [thumb, exif])
+    if damage:
+        # An `mdat` box which runs to the end of the file, as some writers
+        # leave it, so cutting it short doesn't break the box structure
+        return head + struct.pack('>I', 0) + b'mdat' + mdat[:len(mdat) - 12]
+    return head + box(b'mdat', mdat)
+
+
+def avif(damage):
+    """Build an AVIF file with an alpha plane"""
+    colour = b'\x12\0\x0a\x0a' + b'\x33' * 12
+    alpha = b'\x12\0\x0a\x06' + b'\x44' * 6
+    av1c = box(b'av1C', b'\x81\x00\x0c\x00')
+    aux = full(b'auxC', 0, 0, b'urn:mpeg:mpegB:cicp:systems:auxiliary:alpha\0')
+    properties = [av1c, ispe(16, 16), full(b'pixi', 0, 0, b'\x03\x08\x08\x08'),
+                  aux]
+
+    def build(base):
+        meta = full(b'meta', 0, 0, hdlr() + full(b'pitm', 0, 0, b'\0\x01') +
+            iinf([infe(1, b'av01', b'Color'), infe(2, b'av01', b'Alpha')]) +
+            iref([(b'auxl', 2, [1])]) +
+            iprp(properties, [(1, [1, 2, 5 if damage else 3]),
+                              (2, [1, 2, 4])]) +
+            iloc(0, 4, [(1, 0, base, [(0, len(colour))]),
+                        (2, 0, base, [(len(colour), len(alpha))])]))
+        return ftyp(b'avif', [b'avif', b'mif1', b'miaf', b'MA1B']) + meta
+
+    head = build(len(build(0)) + 8)
+    return head + box(b'mdat', colour + alpha)
+
+
+def main():
+    """The main entry point, compatible with setuptools entry points."""
+    args = sys.argv[1:]
+    out_path, = [x for x in args if not x.startswith('--')]
+    damage = '--damage' in args
+    data = avif(damage) if '--avif' in args else heic(damage)
+    with open(out_path, 'wb') as fobj:
+        fobj.write(data)
+
+
+if __name__ == '__main__':
+    main()
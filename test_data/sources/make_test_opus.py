@@ -0,0 +1,108 @@
+#!/usr/bin/env python3
+"""Helper script to generate small Ogg Opus files from scratch
+
+(No Opus library is needed, since the audio packets are the 3-byte CELT silence
+packet, which decoders accept. The pages are built by hand, with valid CRCs, so
+the plain Ogg page walker passes all of these, damaged or not.
+
+By default, this writes one second of stereo audio, with the last packet on the
+first audio page padded and split across pages to exercise continued packets.
+Pass --chained for a two-link chained file whose second link is mono. Add --damage to give the
+OpusHead packet a mapping family of 0 but 3 channels or, for --chained, to make
+the second link's granule positions go backwards.)
+"""
+
+__author__ = "Stephan Sokolow (deitarion/SSokolow)"
+__license__ = "Public Domain"
+
+import struct
+import sys
+
+SILENCE = b'\xf8\xff\xfe'  # A 20ms CELT frame of silence
+# The same frame, with Opus padding to make it 255 bytes long
+PADDED = b'\xfb\x41\xfa\xff\xfe' + b'\0' * 250
+FRAME_SAMPLES = 960
+PRE_SKIP = 312
+FLAG_CONTINUED, FLAG_BOS, FLAG_EOS = 0x01, 0x02, 0x04
+
+
+def crc32(data):
+    """The CRC used by Ogg pages (polynomial 0x04C11DB7, not reflected)"""
+    crc = 0
+    for byte in data:
+        crc ^= byte << 24
+        for _ in range(8):
+            crc = ((crc << 1) ^ 0x04C11DB7 if crc & 0x80000000 else crc << 1)
+            crc &= 0xFFFFFFFF
+    return crc
+
+
+def page(flags, granule, serial, sequence, packets, partial=b''):
+    """Encode a page holding `packets` whole, then the start of `partial`"""
+    lacing, body = bytearray(), b''
+    for packet in packets:
+        lacing += b'\xff' * (len(packet) // 255) + bytes([len(packet) % 255])
+        body += packet
+    if partial:
+        assert len(partial) % 255 == 0
+        lacing += b'\xff' * (len(partial) // 255)
+        body += partial
+    header = b'OggS' + struct.pack('<BBqII', 0, flags, granule, serial,
+                                   sequence)
+    data = header + b'\0\0\0\0' + bytes([len(lacing)]) + lacing + body
+    return data[:22] + struct.pack('<I', crc32(data)) + data[26:]
+
+
+def link(serial, channels, frames, head_channels=None, backwards=False):
+    """Build one logical Opus stream holding `frames` frames of silence"""
+    head = b'OpusHead' + struct.pack('<BBHIhB', 1, head_channels or channels,
+                                     PRE_SKIP, 44100, 0, 0)
+    tags = b'OpusTags' + struct.pack('<I', 17) + b'make_test_opus.py'
+    comments = [b'TITLE=Test File', b'ENCODER=make_test_opus.py']
+    tags += struct.pack('<I', len(comments))
+    tags += b''.join(struct.pack('<I', len(x)) + x for x in comments)
+    out = page(FLAG_BOS, 0, serial, 0, [head])
+    out += page(0, 0, serial, 1, [tags])
+
+    # Each page holds ten frames, except that the tenth frame of the first
+    # audio page is padded to 255 bytes, so its terminating lacing value of 0
+    # has to go on the next page
+    pages, granule = [], 0
+    while granule < frames:
+        pages.append([SILENCE] * min(10, frames - granule))
+        granule += len(pages[-1])
+    pages[0][-1] = PADDED
+    granule = 0
+    for index, packets in enumerate(pages):
+        flags, partial = 0, b''
+        if index == 0:
+            packets, partial = packets[:-1], PADDED
+        elif index == 1:
+            flags, packets = FLAG_CONTINUED, [b''] + packets
+        if index == len(pages) - 1:
+            flags |= FLAG_EOS
+        granule += sum(1 for x in packets if x) + (1 if index == 1 else 0)
+        position = granule * FRAME_SAMPLES
+        if backwards and index == len(pages) - 1:
+            position -= 30 * FRAME_SAMPLES
+        out += page(flags, position, serial, index + 2, packets, partial)
+    return out
+
+
+def main():
+    """The main entry point, compatible with setuptools entry points."""
+    args = sys.argv[1:]
+    out_path, = [x for x in args if not x.startswith('--')]
+    damage = '--damage' in args
+    if '--chained' in args:
+        data = link(0x1234ABCD, 2, 30)
+        data += link(0x5678EF01, 1, 30, backwards=damage)
+    else:
+        data = link(0x0EEDFACE, 2, 50, head_channels=3 if damage else None)
+
+    with open(out_path, 'wb') as fobj:
+        fobj.write(data)
+
+
+if __name__ == '__main__':
+    main()
@@ -0,0 +1,73 @@
+#!/usr/bin/env python3
+"""Helper script to generate a minimal checksummed PE32+ DLL from scratch
+
+(One .text section holding a bare `ret`, with the optional header's CheckSum
+filled in the way `link /RELEASE` would, so checkers have a checksum to verify.
+Pass --bad to flip one bit inside the .text section afterwards without fixing
+the checksum.)
+"""
+
+__author__ = "Stephan Sokolow (deitarion/SSokolow)"
+__license__ = "Public Domain"
+
+import struct, sys
+
+FILE_ALIGNMENT = 0x200
+SECTION_ALIGNMENT = 0x1000
+LFANEW = 0x40
+
+
+def checksum(data, checksum_offset):
+    """Compute the PE checksum, treating the CheckSum field as zero"""
+    padded = bytearray(data) + b'\x00' * (len(data) % 2)
+    padded[checksum_offset:checksum_offset + 4] = b'\x00' * 4
+    total = sum(struct.unpack('<%dH' % (len(padded) // 2), padded))
+    while total > 0xFFFF:
+        total = (total & 0xFFFF) + (total >> 16)
+    return total + len(data)
+
+
+def build():
+    """Build the DLL, returning it and the offset of its CheckSum field"""
+    dos_header = b'MZ' + b'\x00' * (0x3C - 2) + struct.pack('<I', LFANEW)
+
+    # IMAGE_FILE_EXECUTABLE_IMAGE | IMAGE_FILE_LARGE_ADDRESS_AWARE | IMAGE_FILE_DLL
+    coff = b'PE\x00\x00' + struct.pack('<HHIIIHH', 0x8664, 1, 0, 0, 0,
+                                       112 + 16 * 8, 0x2022)
+
+    optional = struct.pack('<HBBIIIII', 0x20B, 14, 0, FILE_ALIGNMENT, 0, 0,
+                           0, SECTION_ALIGNMENT)
+    optional += struct.pack('<QII6HIIIIHH4QII', 0x180000000,
+                            SECTION_ALIGNMENT, FILE_ALIGNMENT, 6, 0, 0, 0, 6,
+                            0, 0, 2 * SECTION_ALIGNMENT, FILE_ALIGNMENT, 0, 2,
+                            0x160, 0x100000, 0x1000, 0x100000, 0x1000, 0, 16)
+    optional += b'\x00' * (16 * 8)
+
+    section = struct.pack('<8sIIIIIIHHI', b'.text', 1, SECTION_ALIGNMENT,
+                          FILE_ALIGNMENT, FILE_ALIGNMENT, 0, 0, 0, 0,
+                          0x60000020)
+
+    headers = dos_header + coff + optional + section
+    headers += b'\x00' * (FILE_ALIGNMENT - len(headers))
+    text = b'\xc3' + b'\x00' * (FILE_ALIGNMENT - 1)
+    return headers + text, LFANEW + len(coff) + 64
+
+
+def main():
+    """The main entry point, compatible with setuptools entry points."""
+    args = sys.argv[1:]
+    out_path = [x for x in args if not x.startswith('--')][0]
+
+    data, checksum_offset = build()
+    data = bytearray(data)
+    data[checksum_offset:checksum_offset + 4] = struct.pack(
+        '<I', checksum(data, checksum_offset))
+    if '--bad' in args:
+        data[FILE_ALIGNMENT + 0x100] ^= 0x10
+
+    with open(out_path, 'wb') as fobj:
+        fobj.write(data)
+
+
+if __name__ == '__main__':
+    main()
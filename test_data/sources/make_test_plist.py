@@ -0,0 +1,55 @@
+#!/usr/bin/env python3
+"""Helper script to generate Apple property lists with plistlib
+
+(The property list holds the input file's contents along with one of every
+kind of value the format has. Pass --binary for the binary form instead of
+XML, and --damage to flip a bit: for XML, it turns `<integer>42</integer>` into
+`<integer>4r</integer>`, and for binary, it makes the root dictionary refer to
+an object which doesn't exist.)
+"""
+
+__author__ = "Stephan Sokolow (deitarion/SSokolow)"
+__license__ = "Public Domain"
+
+import datetime
+import plistlib
+import sys
+
+
+def main():
+    """The main entry point, compatible with setuptools entry points."""
+    args = sys.argv[1:]
+    in_path, out_path = [x for x in args if not x.startswith('--')]
+    binary = '--binary' in args
+    with open(in_path, 'rb') as fobj:
+        contents = fobj.read()
+
+    value = {
+        'Contents': contents,
+        'Text': contents.decode('utf8'),
+        'Unicode': 'Café ☕ \U0001F4BE',
+        'Answer': 42,
+        'Negative': -1,
+        'Large': 2 ** 63,
+        'Ratio': 0.5,
+        'Written': datetime.datetime(2024, 2, 29, 12, 34, 56),
+        'Flags': [True, False],
+        'Nested': {'Empty': [], 'Also Empty': {}},
+    }
+    data = plistlib.dumps(value, fmt=plistlib.FMT_BINARY if binary else
+                          plistlib.FMT_XML, sort_keys=True)
+
+    if '--damage' in args and binary:
+        # The root dictionary is the first object, and its first key reference
+        # follows its one-byte marker, so set the reference's top bit
+        data = bytearray(data)
+        data[9] |= 0x80
+    elif '--damage' in args:
+        data = data.replace(b'<integer>42</integer>', b'<integer>4r</integer>')
+
+    with open(out_path, 'wb') as fobj:
+        fobj.write(data)
+
+
+if __name__ == '__main__':
+    main()
@@ -0,0 +1,99 @@
+#!/usr/bin/env python3
+"""Helper script to generate small Photoshop documents from scratch
+
+(No Photoshop library is needed, since only the sections and image resources
+get checked. The image is a 24x16 RGB gradient with no layers, and it has a
+resolution resource and a named IPTC resource with an odd length, to exercise
+the padding rules.
+
+By default, this writes a PSD file with RLE-compressed image data. Pass --psb
+for a PSB (large document format) file with uncompressed image data instead.
+Add --damage to change the signature of the second image resource or, for
+--psb, to cut the image data short.)
+"""
+
+__author__ = "Stephan Sokolow (deitarion/SSokolow)"
+__license__ = "Public Domain"
+
+import struct
+import sys
+
+WIDTH, HEIGHT, CHANNELS = 24, 16, 3
+
+
+def packbits(row):
+    """Compress a row with PackBits, using runs where they help"""
+    out, pos = bytearray(), 0
+    while pos < len(row):
+        run = 1
+        while pos + run < len(row) and run < 128 and row[pos + run] == row[pos]:
+            run += 1
+        if run > 1:
+            out += bytes([257 - run, row[pos]])
+            pos += run
+        else:
+            end = pos + 1
+            while (end < len(row) and end - pos < 128 and
+                   (end + 1 >= len(row) or row[end] != row[end + 1])):
+                end += 1
+            out += bytes([end - pos - 1]) + row[pos:end]
+            pos = end
+    return bytes(out)
+
+
+def resource(signature, res_id, name, data):
+    """Encode an image resource block, with its name and data padded to even
+    lengths"""
+    name = bytes([len(name)]) + name
+    if len(name) % 2:
+        name += b'\0'
+    block = signature + struct.pack('>H', res_id) + name
+    block += struct.pack('>I', len(data)) + data
+    return block + b'\0' * (len(data) % 2)
+
+
+def main():
+    """The main entry point, compatible with setuptools entry points."""
+    args = sys.argv[1:]
+    out_path, = [x for x in args if not x.startswith('--')]
+    psb, damage = '--psb' in args, '--damage' in args
+    size = 8 if psb else 4
+
+    # Each channel is a gradient in a different direction
+    planes = [
+        [bytes((x * 10) & 0xFF for x in range(WIDTH)) for _ in range(HEIGHT)],
+        [bytes([y * 16] * WIDTH) for y in range(HEIGHT)],
+        [bytes([0x80] * WIDTH) for _ in range(HEIGHT)],
+    ]
+
+    data = b'8BPS' + struct.pack('>H', 2 if psb else 1) + b'\0' * 6
+    data += struct.pack('>HIIHH', CHANNELS, HEIGHT, WIDTH, 8, 3)
+    data += struct.pack('>I', 0)  # No color mode data for RGB
+
+    # 72 DPI, horizontally and vertically, as 16.16 fixed point
+    resolution = struct.pack('>IHHIHH', 72 << 16, 1, 1, 72 << 16, 1, 1)
+    resources = resource(b'8BIM', 0x03ED, b'', resolution)
+    resources += resource(b'8BIN' if damage and not psb else b'8BIM', 0x0404,
+                          b'Name', b'\x1c\x02\x00\x00\x01Hi')
+    data += struct.pack('>I', len(resources)) + resources
+
+    # No layers, so the layer info and global layer mask info are empty
+    layers = b'\0' * size + struct.pack('>I', 0)
+    data += (len(layers).to_bytes(size, 'big')) + layers
+
+    if psb:
+        data += struct.pack('>H', 0) + b''.join(b''.join(x) for x in planes)
+        if damage:
+            data = data[:-10]
+    else:
+        rows = [packbits(row) for plane in planes for row in plane]
+        data += struct.pack('>H', 1)
+        data += b''.join(struct.pack('>H', len(x)) for x in rows)
+        data += b''.join(rows)
+
+    with open(out_path, 'wb') as fobj:
+        fobj.write(data)
+
+
+if __name__ == '__main__':
+    main()
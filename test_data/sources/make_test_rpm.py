@@ -0,0 +1,122 @@
+#!/usr/bin/env python3
+"""Helper script to generate a minimal RPM package holding a single file
+
+(rpmbuild needs a spec file and a whole build root, so this assembles the lead,
+headers, and cpio payload by hand. The result has the same digests a modern
+rpmbuild would record: an MD5 of the header and payload and a SHA-256 of the
+header in the signature header, plus SHA-256 digests of the payload, both
+compressed and uncompressed, in the main header. Pass --legacy to leave out
+everything but the MD5, as older packages do, and pass --compressor=NAME to
+use something other than xz.)
+"""
+
+__author__ = "Stephan Sokolow (deitarion/SSokolow)"
+__license__ = "Public Domain"
+
+import gzip, hashlib, lzma, os, struct, sys
+
+HEADER_MAGIC = b'\x8e\xad\xe8\x01\0\0\0\0'
+INT32, STRING, BIN, STRING_ARRAY, I18NSTRING = 4, 6, 7, 8, 9
+
+# Region tags, which cover the whole header
+HEADERSIGNATURES, HEADERIMMUTABLE = 62, 63
+
+COMPRESSORS = {
+    'gzip': lambda data: gzip.compress(data, 9, mtime=0),
+    'xz': lambda data: lzma.compress(data, check=lzma.CHECK_CRC32),
+}
+
+
+def cpio(name, data):
+    """Build a "newc" cpio archive holding one file"""
+    def member(name, data, mode):
+        fields = (0, mode, 0, 0, 1, 0, len(data), 0, 0, 0, 0, len(name) + 1, 0)
+        head = b'070701' + b''.join(b'%08X' % x for x in fields) + name + b'\0'
+        return (head + b'\0' * (-len(head) % 4) + data +
+                b'\0' * (-len(data) % 4))
+    return member(name, data, 0o100644) + member(b'TRAILER!!!', b'', 0)
+
+
+def header(region, entries):
+    """Build a header from (tag, type, value) entries, sorted by tag
+
+    Values are lists of ints for INT32, a byte string for BIN, and a list of
+    byte strings for the string types.
+    """
+    index, store = [], b''
+    for tag, kind, value in sorted(entries):
+        if kind == INT32:
+            store += b'\0' * (-len(store) % 4)
+            data, count = b''.join(struct.pack('>I', x) for x in value), len(value)
+        elif kind == BIN:
+            data, count = value, len(value)
+        else:
+            data, count = b''.join(x + b'\0' for x in value), len(value)
+        index.append(struct.pack('>IIiI', tag, kind, len(store), count))
+        store += data
+
+    # The region tag's data is a trailer pointing back at the start of the index
+    count = len(index) + 1
+    index.insert(0, struct.pack('>IIiI', region, BIN, len(store), 16))
+    store += struct.pack('>IIiI', region, BIN, -16 * count, 16)
+    return (HEADER_MAGIC + struct.pack('>II', count, len(store)) +
+            b''.join(index) + store)
+
+
+def main():
+    """The main entry point, compatible with setuptools entry points."""
+    args = sys.argv[1:]
+    in_path, out_path = [x for x in args if not x.startswith('--')]
+    legacy = '--legacy' in args
+    compressor = 'xz'
+    for arg in args:
+        if arg.startswith('--compressor='):
+            compressor = arg.split('=', 1)[1]
+
+    with open(in_path, 'rb') as fobj:
+        archive = cpio(b'./usr/share/doc/testfile/' +
+                       os.path.basename(in_path).encode(), fobj.read())
+    payload = COMPRESSORS[compressor](archive)
+
+    main_entries = [
+        (100, STRING_ARRAY, [b'C']),
+        (1000, STRING, [b'testfile']),
+        (1001, STRING, [b'1.0']),
+        (1002, STRING, [b'1']),
+        (1004, I18NSTRING, [b'A test file for verify_files']),
+        (1022, STRING, [b'noarch']),
+        (1124, STRING, [b'cpio']),
+        (1125, STRING, [compressor.encode()]),
+        (1126, STRING, [b'9']),
+    ]
+    if not legacy:
+        main_entries += [
+            (5092, STRING_ARRAY, [hashlib.sha256(payload).hexdigest().encode()]),
+            (5093, INT32, [8]),
+            (5097, STRING_ARRAY, [hashlib.sha256(archive).hexdigest().encode()]),
+        ]
+    main_header = header(HEADERIMMUTABLE, main_entries)
+
+    signature_entries = [
+        (1000, INT32, [len(main_header) + len(payload)]),
+        (1004, BIN, hashlib.md5(main_header + payload).digest()),
+    ]
+    if not legacy:
+        signature_entries += [
+            (269, STRING, [hashlib.sha1(main_header).hexdigest().encode()]),
+            (273, STRING, [hashlib.sha256(main_header).hexdigest().encode()]),
+            (1007, INT32, [len(archive)]),
+        ]
+    signature = header(HEADERSIGNATURES, signature_entries)
+    signature += b'\0' * (-len(signature) % 8)
+
+    # Format 3.0, binary package, noarch, Linux, header-style signature
+    lead = struct.pack('>4sBBHH66sHH16s', b'\xed\xab\xee\xdb', 3, 0, 0, 0,
+                       b'testfile-1.0-1', 1, 5, b'')
+
+    with open(out_path, 'wb') as fobj:
+        fobj.write(lead + signature + main_header + payload)
+
+
+if __name__ == '__main__':
+    main()
@@ -0,0 +1,136 @@
+#!/usr/bin/env python3
+"""Helper script to generate a small WebAssembly module from scratch
+
+(Every non-custom section of the WebAssembly 2.0 spec plus a "name" custom
+section, with function bodies exercising block nesting, branch tables, indirect
+calls, and bulk memory instructions, so checkers have something to walk. Pass
+--bad to flip one bit so a `call` refers to a function which doesn't exist.)
+"""
+
+__author__ = "Stephan Sokolow (deitarion/SSokolow)"
+__license__ = "Public Domain"
+
+import sys
+
+I32 = 0x7F
+FUNCREF = 0x70
+
+# The bytes of the `call` in `main`, whose function index --bad damages
+CALL_MARKER = b'\x10\x01\x1a'
+
+
+def uleb(value):
+    """Encode an unsigned LEB128 integer"""
+    out = bytearray()
+    while True:
+        byte, value = value & 0x7F, value >> 7
+        out.append(byte | (0x80 if value else 0))
+        if not value:
+            return bytes(out)
+
+
+def vec(items):
+    """Encode a vector of already-encoded items"""
+    return uleb(len(items)) + b''.join(items)
+
+
+def name(text):
+    """Encode a name"""
+    return vec([bytes([x]) for x in text.encode('utf8')])
+
+
+def section(section_id, contents):
+    """Encode a section"""
+    return bytes([section_id]) + uleb(len(contents)) + contents
+
+
+def body(locals_, code):
+    """Encode a function body"""
+    contents = vec(locals_) + code + b'\x0b'
+    return uleb(len(contents)) + contents
+
+
+def build():
+    """Build the module"""
+    types = vec([
+        b'\x60' + vec([bytes([I32])]) + vec([]),                # (i32) -> ()
+        b'\x60' + vec([bytes([I32])]) + vec([bytes([I32])]),    # (i32) -> i32
+        b'\x60' + vec([]) + vec([]),                            # () -> ()
+    ])
+    imports = vec([name('env') + name('log') + b'\x00' + uleb(0)])
+    functions = vec([uleb(1), uleb(2)])
+    tables = vec([bytes([FUNCREF, 0x01]) + uleb(2) + uleb(2)])
+    memories = vec([b'\x01' + uleb(1) + uleb(16)])
+    globals_ = vec([bytes([I32, 0x01]) + b'\x41' + uleb(42) + b'\x0b'])
+    exports = vec([name('square_ish') + b'\x00' + uleb(1),
+                   name('memory') + b'\x02' + uleb(0),
+                   name('counter') + b'\x03' + uleb(0)])
+    start = uleb(2)
+    elements = vec([b'\x00' + b'\x41\x00\x0b' + vec([uleb(1), uleb(2)])])
+    data_count = uleb(2)
+
+    # square_ish: a loop with an if/else, a br_table, and a load/store
+    square_ish = body([uleb(1) + bytes([I32])], bytes([
+        0x02, 0x40,                         # block
+        0x03, 0x40,                         #  loop
+        0x20, 0x00, 0x45, 0x0d, 0x01,       #   local.get 0; i32.eqz; br_if 1
+        0x20, 0x00, 0x41, 0x01, 0x6b,       #   local.get 0; i32.const 1; i32.sub
+        0x22, 0x00, 0x41, 0x02, 0x70,       #   local.tee 0; i32.const 2; i32.rem_u
+        0x04, 0x40,                         #   if
+        0x20, 0x01, 0x41, 0x03, 0x6a,       #    local.get 1; i32.const 3; i32.add
+        0x21, 0x01,                         #    local.set 1
+        0x05,                               #   else
+        0x20, 0x00,                         #    local.get 0
+        0x0e, 0x02, 0x00, 0x01, 0x02,       #    br_table 0 1 2
+        0x0b,                               #   end
+        0x0c, 0x00,                         #   br 0
+        0x0b,                               #  end
+        0x0b,                               # end
+        0x41, 0x00, 0x20, 0x01,             # i32.const 0; local.get 1
+        0x36, 0x02, 0x00,                   # i32.store align=2 offset=0
+        0x41, 0x00, 0x28, 0x02, 0x00,       # i32.const 0; i32.load
+        0x23, 0x00, 0x6a,                   # global.get 0; i32.add
+    ]))
+
+    # main: bulk memory, an indirect call, and a direct call
+    main = body([], bytes([
+        0x41, 0x00, 0x41, 0x00, 0x41, 0x04,  # i32.const 0 0 4
+        0xfc, 0x08, 0x01, 0x00,             # memory.init 1 0
+        0xfc, 0x09, 0x01,                   # data.drop 1
+        0x41, 0x05, 0x41, 0x00,             # i32.const 5; i32.const 0
+        0x11, 0x01, 0x00,                   # call_indirect (type 1) 0
+        0x10, 0x01, 0x1a,                   # call 1; drop
+        0x41, 0x07, 0x10, 0x00,             # i32.const 7; call 0
+    ]))
+    code = vec([square_ish, main])
+
+    data = vec([b'\x00' + b'\x41\x10\x0b' + vec([bytes([x]) for x in b'hello']),
+                b'\x01' + vec([bytes([x]) for x in b'wasm'])])
+
+    function_names = vec([uleb(0) + name('log'), uleb(1) + name('square_ish'),
+                          uleb(2) + name('main')])
+    names = name('name') + b'\x01' + uleb(len(function_names)) + function_names
+
+    return (b'\x00asm\x01\x00\x00\x00' + section(1, types) +
+            section(2, imports) + section(3, functions) + section(4, tables) +
+            section(5, memories) + section(6, globals_) +
+            section(7, exports) + section(8, start) + section(9, elements) +
+            section(12, data_count) + section(10, code) + section(11, data) +
+            section(0, names))
+
+
+def main():
+    """The main entry point, compatible with setuptools entry points."""
+    args = sys.argv[1:]
+    out_path = [x for x in args if not x.startswith('--')][0]
+
+    data = bytearray(build())
+    if '--bad' in args:
+        data[data.index(CALL_MARKER) + 1] ^= 0x40
+
+    with open(out_path, 'wb') as fobj:
+        fobj.write(data)
+
+
+if __name__ == '__main__':
+    main()
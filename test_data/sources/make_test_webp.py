@@ -0,0 +1,349 @@
+#!/usr/bin/env python3
+"""Helper script to generate WebP files in the formats ImageMagick doesn't write
+
+(No WebP library is needed. Lossless bitstreams come from a minimal VP8L
+encoder using the subtract green and predictor transforms, a color cache,
+backward references, and two groups of prefix codes. Lossy image data is copied
+from the simple-format file passed as the input.
+
+Pass --lossless for a 16x16 simple-format VP8L file, --alpha for an extended
+format file with a losslessly compressed ALPH chunk and an XMP chunk, or
+--animated for a two-frame animation mixing a lossless and a lossy frame. Add
+--damage to cut the VP8L bitstream short (fixing up the chunk sizes) or, for
+--animated, to put the second frame outside the canvas.)
+"""
+
+__author__ = "Stephan Sokolow (deitarion/SSokolow)"
+__license__ = "Public Domain"
+
+import struct, sys
+
+CODE_LENGTH_ORDER = [17, 18, 0, 1, 2, 3, 4, 5, 16, 6, 7, 8, 9, 10, 11, 12, 13,
+                     14, 15]
+FLAG_ALPHA, FLAG_XMP, FLAG_ANIMATION = 0x10, 0x04, 0x02
+XMP = b'<x:xmpmeta xmlns:x="adobe:ns:meta/"/>'
+
+
+class BitWriter(object):
+    """Pack values least significant bit first, as VP8L does"""
+
+    def __init__(self):
+        self.out, self.bits, self.count = bytearray(), 0, 0
+
+    def write(self, value, count):
+        assert 0 <= value < (1 << count) or count == value == 0
+        self.bits |= value << self.count
+        self.count += count
+        while self.count >= 8:
+            self.out.append(self.bits & 0xFF)
+            self.bits >>= 8
+            self.count -= 8
+
+    def code(self, code):
+        """Write a prefix code given as (code, length), most significant first"""
+        value, length = code
+        for bit in reversed(range(length)):
+            self.write((value >> bit) & 1, 1)
+
+    def getvalue(self):
+        return bytes(self.out) + (bytes([self.bits]) if self.count else b'')
+
+
+def canonical(lengths):
+    """Assign canonical codes to symbols with the given code lengths"""
+    used = [(length, sym) for sym, length in enumerate(lengths) if length]
+    if len(used) == 1:
+        return {used[0][1]: (0, 0)}
+    codes, code, prev = {}, 0, 0
+    for length, sym in sorted(used):
+        code <<= length - prev
+        codes[sym], code, prev = (code, length), code + 1, length
+    return codes
+
+
+def complete_lengths(symbols, alphabet_size):
+    """Give each symbol one of two adjacent lengths so the code is complete"""
+    lengths = [0] * alphabet_size
+    symbols = sorted(set(symbols))
+    if len(symbols) == 1:
+        lengths[symbols[0]] = 1
+        return lengths
+    bits = (len(symbols) - 1).bit_length()
+    short = (1 << bits) - len(symbols)
+    for idx, sym in enumerate(symbols):
+        lengths[sym] = bits - 1 if idx < short else bits
+    return lengths
+
+
+def write_code(out, symbols, alphabet_size):
+    """Write a prefix code covering `symbols`, returning its codes"""
+    symbols = sorted(set(symbols)) or [0]
+    if len(symbols) <= 2 and symbols[-1] < 256:
+        out.write(1, 1)
+        out.write(len(symbols) - 1, 1)
+        out.write(int(symbols[0] > 1), 1)
+        out.write(symbols[0], 8 if symbols[0] > 1 else 1)
+        if len(symbols) == 2:
+            out.write(symbols[1], 8)
+        return canonical(complete_lengths(symbols, alphabet_size))
+
+    lengths = complete_lengths(symbols, alphabet_size)
+    tokens, idx, prev = [], 0, 8
+    while idx < len(lengths):
+        run = 1
+        while idx + run < len(lengths) and lengths[idx + run] == lengths[idx]:
+            run += 1
+        if lengths[idx] == 0 and run >= 11:
+            run = min(run, 138)
+            tokens.append((18, run - 11, 7))
+        elif lengths[idx] == 0 and run >= 3:
+            run = min(run, 10)
+            tokens.append((17, run - 3, 3))
+        elif lengths[idx] == prev and run >= 3:
+            run = min(run, 6)
+            tokens.append((16, run - 3, 2))
+        else:
+            run = 1
+            tokens.append((lengths[idx], 0, 0))
+            prev = lengths[idx] or prev
+        idx += run
+
+    # Leave off the trailing zeroes using max_symbol
+    while tokens and tokens[-1][0] in (0, 17, 18):
+        tokens.pop()
+    length_codes = canonical(complete_lengths([x[0] for x in tokens], 19))
+    length_lengths = [length_codes.get(x, (0, 0))[1] for x in range(19)]
+    if len(length_codes) == 1:
+        length_lengths[list(length_codes)[0]] = 1
+    count = max(4, max(idx for idx, sym in enumerate(CODE_LENGTH_ORDER)
+                       if length_lengths[sym]) + 1)
+    out.write(0, 1)
+    out.write(count - 4, 4)
+    for sym in CODE_LENGTH_ORDER[:count]:
+        out.write(length_lengths[sym], 3)
+    out.write(1, 1)
+    out.write(3, 3)
+    out.write(len(tokens) - 2, 8)
+    for sym, extra, bits in tokens:
+        out.code(length_codes[sym])
+        out.write(extra, bits)
+    return canonical(lengths)
+
+
+def prefix_encode(value):
+    """Split a length or distance into (prefix, extra bit count, extra bits)"""
+    if value <= 4:
+        return value - 1, 0, 0
+    value -= 1
+    highest = value.bit_length() - 1
+    second = (value >> (highest - 1)) & 1
+    return (2 * highest + second, highest - 1,
+            value & ((1 << (highest - 1)) - 1))
+
+
+def cache_index(pixel, bits):
+    return ((0x1E35A7BD * pixel) & 0xFFFFFFFF) >> (32 - bits)
+
+
+def write_image(out, width, pixels, cache_bits=0, main=False, prefix_bits=0,
+                group_of=None):
+    """Write an entropy-coded image, copying any row which matches the last
+
+    (`main` images get the meta prefix flag and, if `prefix_bits` is set, an
+    entropy image choosing the group for each block with `group_of(x, y)`.)
+    """
+    group_of = group_of or (lambda x, y: 0)
+    out.write(int(bool(cache_bits)), 1)
+    if cache_bits:
+        out.write(cache_bits, 4)
+    if main:
+        out.write(int(bool(prefix_bits)), 1)
+    if prefix_bits:
+        out.write(prefix_bits - 2, 3)
+        size = 1 << prefix_bits
+        blocks_wide = -(-width // size)
+        blocks_high = -(-(len(pixels) // width) // size)
+        write_image(out, blocks_wide, [
+            group_of(x * size, y * size) << 8
+            for y in range(blocks_high) for x in range(blocks_wide)])
+
+    # Tokenize first, since the prefix codes depend on which symbols are used
+    tokens, cache, pos = [], {}, 0
+    while pos < len(pixels):
+        group = group_of(pos % width, pos // width)
+        row = pixels[pos:pos + width]
+        if pos >= width and pos % width == 0 and row == pixels[pos - width:pos]:
+            tokens.append((group, 'copy', width))
+            added = row
+        elif cache_bits and cache.get(
+                cache_index(pixels[pos], cache_bits)) == pixels[pos]:
+            tokens.append((group, 'cache', cache_index(pixels[pos], cache_bits)))
+            added = [pixels[pos]]
+        else:
+            tokens.append((group, 'literal', pixels[pos]))
+            added = [pixels[pos]]
+        for pixel in added:
+            if cache_bits:
+                cache[cache_index(pixel, cache_bits)] = pixel
+        pos += len(added)
+
+    cache_size = (1 << cache_bits) if cache_bits else 0
+    groups = max(x[0] for x in tokens) + 1
+    codes = []
+    for group in range(groups):
+        symbols = [[], [], [], [], []]
+        for token_group, kind, value in tokens:
+            if token_group != group:
+                continue
+            if kind == 'literal':
+                symbols[0].append((value >> 8) & 0xFF)
+                symbols[1].append((value >> 16) & 0xFF)
+                symbols[2].append(value & 0xFF)
+                symbols[3].append(value >> 24)
+            elif kind == 'cache':
+                symbols[0].append(280 + value)
+            else:
+                symbols[0].append(256 + prefix_encode(value)[0])
+                symbols[4].append(prefix_encode(1)[0])
+        codes.append([write_code(out, syms, size) for syms, size in zip(
+            symbols, [280 + cache_size, 256, 256, 256, 40])])
+
+    for group, kind, value in tokens:
+        green, red, blue, alpha, distance = codes[group]
+        if kind == 'literal':
+            out.code(green[(value >> 8) & 0xFF])
+            out.code(red[(value >> 16) & 0xFF])
+            out.code(blue[value & 0xFF])
+            out.code(alpha[value >> 24])
+        elif kind == 'cache':
+            out.code(green[280 + value])
+        else:
+            # Distance code 1 is the pixel directly above
+            prefix, bits, extra = prefix_encode(value)
+            out.code(green[256 + prefix])
+            out.write(extra, bits)
+            out.code(distance[prefix_encode(1)[0]])
+
+
+def argb(alpha, red, green, blue):
+    return (alpha & 0xFF) << 24 | (red & 0xFF) << 16 | (green & 0xFF) << 8 | (
+        blue & 0xFF)
+
+
+def lossless_image(width, height):
+    """Encode a gradient with the subtract green and predictor transforms"""
+    pixels = [[(255 if x < 12 else 128, x * 16, y * 16, 128)
+               for x in range(width)] for y in range(height)]
+    pixels = [[(a, r - g, g, b - g) for a, r, g, b in row] for row in pixels]
+
+    # Predict from the left pixel (mode 1), except along the top and left edges
+    residuals = []
+    for y in range(height):
+        for x in range(width):
+            if x == 0 and y == 0:
+                pred = (255, 0, 0, 0)
+            elif x == 0:
+                pred = pixels[y - 1][x]
+            else:
+                pred = pixels[y][x - 1]
+            residuals.append(argb(*[c - p for c, p in zip(pixels[y][x], pred)]))
+
+    out = BitWriter()
+    out.write(0x2F, 8)
+    out.write(width - 1, 14)
+    out.write(height - 1, 14)
+    out.write(1, 1)
+    out.write(0, 3)
+    out.write(1, 1)
+    out.write(2, 2)
+    out.write(1, 1)
+    out.write(0, 2)
+    out.write(0, 3)
+    blocks = -(-width // 4) * -(-height // 4)
+    write_image(out, -(-width // 4), [argb(255, 0, 1, 0)] * blocks)
+    out.write(0, 1)
+    write_image(out, width, residuals, cache_bits=4, main=True, prefix_bits=2,
+                group_of=lambda x, y: int(y >= height // 2))
+    return out.getvalue()
+
+
+def lossless_alpha(values, width):
+    """Encode alpha values as a headerless VP8L stream using a color table"""
+    palette = sorted(set(values))
+    bits = 1 if len(palette) <= 2 else 2 if len(palette) <= 4 else 4
+    per_pixel = 8 // bits
+    packed = []
+    for y in range(len(values) // width):
+        row = values[y * width:(y + 1) * width]
+        for start in range(0, width, per_pixel):
+            code = 0
+            for idx, value in enumerate(row[start:start + per_pixel]):
+                code |= palette.index(value) << (bits * idx)
+            packed.append(argb(255, 0, code, 0))
+
+    out = BitWriter()
+    out.write(1, 1)
+    out.write(3, 2)
+    out.write(len(palette) - 1, 8)
+    write_image(out, len(palette), [argb(255 if i == 0 else 0, 0, value - (
+        palette[i - 1] if i else 0), 0) for i, value in enumerate(palette)])
+    out.write(0, 1)
+    write_image(out, -(-width // per_pixel), packed, main=True)
+    return out.getvalue()
+
+
+def chunk(fourcc, data):
+    return fourcc + struct.pack('<I', len(data)) + data + b'\0' * (len(data) % 2)
+
+
+def vp8x(flags, width, height):
+    return chunk(b'VP8X', struct.pack('<I', flags) +
+                 struct.pack('<I', width - 1)[:3] +
+                 struct.pack('<I', height - 1)[:3])
+
+
+def anmf(left, top, width, height, data):
+    fields = b''.join(struct.pack('<I', x)[:3] for x in (
+        left // 2, top // 2, width - 1, height - 1, 100))
+    return chunk(b'ANMF', fields + b'\0' + data)
+
+
+def main():
+    """The main entry point, compatible with setuptools entry points."""
+    args = sys.argv[1:]
+    in_path, out_path = [x for x in args if not x.startswith('--')]
+    damage = '--damage' in args
+    with open(in_path, 'rb') as fobj:
+        lossy = fobj.read()
+    assert lossy[12:16] == b'VP8 '
+    vp8 = lossy[12:12 + 8 + struct.unpack('<I', lossy[16:20])[0]]
+    vp8_width, vp8_height = [x & 0x3FFF for x in struct.unpack('<HH', vp8[14:18])]
+
+    lossless = lossless_image(16, 16)
+    if damage:
+        lossless = lossless[:len(lossless) // 2]
+
+    if '--lossless' in args:
+        body = chunk(b'VP8L', lossless)
+    elif '--alpha' in args:
+        alpha = [255, 200, 100, 255, 0, 50][:vp8_width * vp8_height]
+        alpha += [255] * (vp8_width * vp8_height - len(alpha))
+        body = (vp8x(FLAG_ALPHA | FLAG_XMP, vp8_width, vp8_height) +
+                chunk(b'ALPH', b'\x01' + lossless_alpha(alpha, vp8_width)) +
+                vp8 + chunk(b'XMP ', XMP))
+    elif '--animated' in args:
+        offset = 16 if damage else 4
+        body = (vp8x(FLAG_ALPHA | FLAG_ANIMATION, 16, 16) +
+                chunk(b'ANIM', struct.pack('<IH', 0xFFFFFFFF, 0)) +
+                anmf(0, 0, 16, 16, chunk(b'VP8L', lossless_image(16, 16))) +
+                anmf(offset, offset, vp8_width, vp8_height, chunk(
+                    b'ALPH', b'\0' + b'\x80' * (vp8_width * vp8_height)) + vp8))
+    else:
+        raise SystemExit("Pass --lossless, --alpha, or --animated")
+
+    with open(out_path, 'wb') as fobj:
+        fobj.write(chunk(b'RIFF', b'WEBP' + body))
+
+
+if __name__ == '__main__':
+    main()
@@ -0,0 +1,222 @@
+#!/usr/bin/env python3
+"""Helper script to wrap a TrueType font in WOFF or WOFF2
+
+(Written rather than using fontTools so it can generate exactly the structures
+the checkers need to see: WOFF with zlib-compressed tables and a metadata block,
+and WOFF2 with the glyf/loca transform applied. Only simple glyphs are
+supported, since that's all make_test_font.py generates. Pass --bad to damage
+the compressed data afterwards.)
+"""
+
+__author__ = "Stephan Sokolow (deitarion/SSokolow)"
+__license__ = "Public Domain"
+
+import struct, sys, zlib
+
+try:
+    import brotli
+except ImportError:
+    import brotlicffi as brotli
+
+METADATA = (b'<?xml version="1.0" encoding="UTF-8"?>\n'
+            b'<metadata version="1.0">\n'
+            b'  <uniqueid id="org.example.verify-files.test" />\n'
+            b'</metadata>\n')
+
+WOFF2_TAGS = [b'cmap', b'head', b'hhea', b'hmtx', b'maxp', b'name', b'OS/2',
+              b'post', b'cvt ', b'fpgm', b'glyf', b'loca', b'prep']
+
+
+def pad(data):
+    """Pad `data` to a four-byte boundary"""
+    return data + b'\x00' * (-len(data) % 4)
+
+
+def read_font(data):
+    """Return the sfnt version and a list of (tag, checksum, table) tuples"""
+    version, num_tables = struct.unpack('>4sH', data[:6])
+    tables = []
+    for i in range(num_tables):
+        tag, checksum, offset, length = struct.unpack(
+            '>4s3I', data[12 + i * 16:28 + i * 16])
+        tables.append((tag, checksum, data[offset:offset + length]))
+    return version, tables
+
+
+def base128(value):
+    """Encode a WOFF2 UIntBase128"""
+    out = bytes([value & 0x7F])
+    value >>= 7
+    while value:
+        out = bytes([0x80 | (value & 0x7F)]) + out
+        value >>= 7
+    return out
+
+
+def u255(value):
+    """Encode a WOFF2 255UInt16"""
+    if value < 253:
+        return bytes([value])
+    return b'\xfd' + struct.pack('>H', value)
+
+
+def parse_glyph(glyph):
+    """Split a simple glyph into contour ends, points, and instructions"""
+    contours, = struct.unpack('>h', glyph[:2])
+    if contours < 0:
+        raise NotImplementedError("Composite glyphs aren't supported")
+    bbox = glyph[2:10]
+    ends = struct.unpack('>%dH' % contours, glyph[10:10 + 2 * contours])
+    pos = 10 + 2 * contours
+    insn_len, = struct.unpack('>H', glyph[pos:pos + 2])
+    insns = glyph[pos + 2:pos + 2 + insn_len]
+    pos += 2 + insn_len
+
+    flags = []
+    while len(flags) < ends[-1] + 1:
+        flag = glyph[pos]
+        pos += 1
+        repeat = 1
+        if flag & 0x08:
+            repeat += glyph[pos]
+            pos += 1
+        flags += [flag] * repeat
+
+    coords = []
+    for short, same in ((0x02, 0x10), (0x04, 0x20)):
+        deltas = []
+        for flag in flags:
+            if flag & short:
+                deltas.append(glyph[pos] if flag & same else -glyph[pos])
+                pos += 1
+            elif flag & same:
+                deltas.append(0)
+            else:
+                deltas.append(struct.unpack('>h', glyph[pos:pos + 2])[0])
+                pos += 2
+        coords.append(deltas)
+    return bbox, ends, flags, list(zip(*coords)), insns
+
+
+def transform_glyf(glyf, loca, num_glyphs):
+    """Apply the WOFF2 glyf/loca transform, giving every glyph a bounding box"""
+    offsets = [2 * x for x in struct.unpack('>%dH' % (num_glyphs + 1), loca)]
+    streams = [b''] * 7
+    bitmap = bytearray(4 * ((num_glyphs + 31) // 32))
+    for index in range(num_glyphs):
+        glyph = glyf[offsets[index]:offsets[index + 1]]
+        if not glyph:
+            streams[0] += struct.pack('>h', 0)
+            continue
+        bbox, ends, flags, points, insns = parse_glyph(glyph)
+        streams[0] += struct.pack('>h', len(ends))
+        start = 0
+        for end in ends:
+            streams[1] += u255(end + 1 - start)
+            start = end + 1
+        for flag, (dx, dy) in zip(flags, points):
+            # Always use the 16-bit triplet encoding, with the signs in the flag
+            sign = (dx >= 0) + 2 * (dy >= 0)
+            streams[2] += bytes([124 + sign | (0 if flag & 1 else 0x80)])
+            streams[3] += struct.pack('>HH', abs(dx), abs(dy))
+        streams[3] += u255(len(insns))
+        streams[6] += insns
+        bitmap[index >> 3] |= 0x80 >> (index & 7)
+        streams[5] += bbox
+    streams[5] = bytes(bitmap) + streams[5]
+    return struct.pack('>4H7I', 0, 0, num_glyphs, 0,
+                       *[len(x) for x in streams]) + b''.join(streams)
+
+
+def damage(data, check):
+    """Flip the first bit (from the middle onward) which makes `check` fail"""
+    for bit in range(len(data) * 4, len(data) * 8):
+        damaged = bytearray(data)
+        damaged[bit // 8] ^= 1 << (bit % 8)
+        try:
+            check(bytes(damaged))
+        except Exception:  # pylint: disable=broad-except
+            return bytes(damaged)
+    raise ValueError("No single-bit error was detectable")
+
+
+def make_woff(version, tables, bad=False):
+    """Wrap the tables in WOFF, with a metadata block"""
+    directory, blobs = b'', b''
+    data_start = 44 + 20 * len(tables)
+    sfnt_size = 12 + 16 * len(tables)
+    for tag, checksum, table in tables:
+        compressed = zlib.compress(table, 9)
+        if len(compressed) >= len(table):
+            compressed = table
+        elif bad and tag == b'glyf':
+            compressed = damage(compressed, zlib.decompress)
+        directory += struct.pack('>4s4I', tag, data_start + len(blobs),
+                                 len(compressed), len(table), checksum)
+        blobs += pad(compressed)
+        sfnt_size += len(pad(table))
+
+    metadata = zlib.compress(METADATA, 9)
+    meta_offset = data_start + len(blobs)
+    length = meta_offset + len(metadata)
+    return struct.pack('>4s4sIHHIHH5I', b'wOFF', version, length, len(tables),
+                       0, sfnt_size, 1, 0, meta_offset, len(metadata),
+                       len(METADATA), 0, 0) + directory + blobs + metadata
+
+
+def make_woff2(version, tables, bad=False):
+    """Wrap the tables in WOFF2, transforming glyf and loca"""
+    by_tag = {tag: table for tag, _, table in tables}
+    num_glyphs, = struct.unpack('>H', by_tag[b'maxp'][4:6])
+
+    # The transformed loca table has to come straight after glyf
+    order = [x for x in sorted(by_tag) if x != b'loca']
+    order.insert(order.index(b'glyf') + 1, b'loca')
+
+    directory, stream = b'', b''
+    sfnt_size = 12 + 16 * len(tables)
+    for tag in order:
+        table = by_tag[tag]
+        directory += bytes([WOFF2_TAGS.index(tag) if tag in WOFF2_TAGS
+                            else 63])
+        if tag not in WOFF2_TAGS:
+            directory += tag
+        directory += base128(len(table))
+        if tag == b'glyf':
+            transformed = transform_glyf(table, by_tag[b'loca'], num_glyphs)
+            directory += base128(len(transformed))
+            stream += transformed
+        elif tag == b'loca':
+            directory += base128(0)
+        else:
+            stream += table
+        sfnt_size += len(pad(table))
+
+    compressed = brotli.compress(stream, quality=11, mode=brotli.MODE_FONT)
+    if bad:
+        compressed = damage(compressed, brotli.decompress)
+    metadata = brotli.compress(METADATA, quality=11, mode=brotli.MODE_TEXT)
+    meta_offset = 48 + len(directory) + len(pad(compressed))
+    length = meta_offset + len(metadata)
+    return struct.pack('>4s4sIHHIIHH5I', b'wOF2', version, length, len(tables),
+                       0, sfnt_size, len(compressed), 1, 0, meta_offset,
+                       len(metadata), len(METADATA), 0, 0) + directory + \
+        pad(compressed) + metadata
+
+
+def main():
+    """The main entry point, compatible with setuptools entry points."""
+    args = sys.argv[1:]
+    woff2, bad = '--woff2' in args, '--bad' in args
+    in_path, out_path = [x for x in args if not x.startswith('--')]
+
+    with open(in_path, 'rb') as fobj:
+        version, tables = read_font(fobj.read())
+    data = (make_woff2 if woff2 else make_woff)(version, tables, bad)
+
+    with open(out_path, 'wb') as fobj:
+        fobj.write(data)
+
+
+if __name__ == '__main__':
+    main()
@@ -0,0 +1,41 @@
+#!/usr/bin/env python3
+"""Helper script to generate a small Zip file which uses Zip64 records anyway
+
+(Python's zipfile only writes Zip64 records when an archive needs them, so this
+lowers its limits to force them: every entry gets Zip64 size fields and the
+archive gets a Zip64 end of central directory record and locator. Pass --bad
+to then corrupt the central directory offset in the Zip64 record.)
+"""
+
+__author__ = "Stephan Sokolow (deitarion/SSokolow)"
+__license__ = "Public Domain"
+
+import struct, sys, zipfile
+
+ZIP64_EOCD_SIGNATURE = b'PK\x06\x06'
+
+
+def main():
+    """The main entry point, compatible with setuptools entry points."""
+    args = sys.argv[1:]
+    in_path, out_path = [x for x in args if not x.startswith('--')]
+
+    zipfile.ZIP64_LIMIT = 1
+    zipfile.ZIP_FILECOUNT_LIMIT = 1
+    with zipfile.ZipFile(out_path, 'w', zipfile.ZIP_DEFLATED) as zobj:
+        zobj.write(in_path, 'testfile.txt')
+        zobj.writestr('stored.txt', b'Stored without compression\n',
+                      zipfile.ZIP_STORED)
+
+    if '--bad' in args:
+        with open(out_path, 'rb') as fobj:
+            data = bytearray(fobj.read())
+        offset = data.rindex(ZIP64_EOCD_SIGNATURE) + 48
+        cd_offset, = struct.unpack_from('<Q', data, offset)
+        struct.pack_into('<Q', data, offset, cd_offset + 3)
+        with open(out_path, 'wb') as fobj:
+            fobj.write(data)
+
+
+if __name__ == '__main__':
+    main()
@@ -0,0 +1,35 @@
+#!/usr/bin/env python3
+"""Helper script to make a Zip file's local file header disagree with its
+central directory record
+
+(The first entry's file name is changed in the local header only, leaving its
+data and CRC intact, as a partially overwritten archive might.)
+"""
+
+__author__ = "Stephan Sokolow (deitarion/SSokolow)"
+__license__ = "Public Domain"
+
+import struct, sys, zipfile
+
+LOCAL_HEADER_LEN = 30
+
+
+def main():
+    """The main entry point, compatible with setuptools entry points."""
+    in_path, out_path = sys.argv[1:]
+
+    with zipfile.ZipFile(in_path) as zobj:
+        offset = zobj.infolist()[0].header_offset
+
+    with open(in_path, 'rb') as fobj:
+        data = bytearray(fobj.read())
+    name_len, = struct.unpack_from('<H', data, offset + 26)
+    assert name_len, "The first entry must have a file name to change"
+    data[offset + LOCAL_HEADER_LEN] ^= 0x20
+
+    with open(out_path, 'wb') as fobj:
+        fobj.write(data)
+
+
+if __name__ == '__main__':
+    main()
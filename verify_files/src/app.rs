@@ -3,23 +3,36 @@
 // Parts Copyright 2017-2020, Stephan Sokolow
 
 // Standard library imports
-use std::path::PathBuf;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, FileType};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
 
 // 3rd-party crate imports
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use clap::{
     builder::styling::{AnsiColor, Styles},
     //builder::{PathBufValueParser, TypedValueParser},
-    Parser,
+    Parser, ValueEnum,
 };
 use clap_verbosity_flag::{Verbosity, WarnLevel};
-use ignore::WalkBuilder;
+use globset::GlobSetBuilder;
+use ignore::{Walk, WalkBuilder};
 
 use log::{debug, error, info, trace, warn};
 
 // Local Imports
-use crate::builtin_handlers::ALL as BUILTIN_HANDLERS;
-use crate::config;
+use crate::builtin_handlers::{Confidence, FailureType, ALL as BUILTIN_HANDLERS};
+use crate::config::{self, Handler, Platform, Root};
+use crate::dispatch::{self, DirectoryClaims, Dispatcher, Outcome, ResolvedHandler};
+use crate::hardlinks::{Claim, FileId, Tracker};
+use crate::subprocess;
 use crate::validators::path_input_file_or_dir;
 
 /// The contents of the default configuration file that is used if nothing else is found
@@ -33,6 +46,15 @@ fn styles() -> Styles {
         .placeholder(AnsiColor::Green.on_default())
 }
 
+/// The formats `--dump-config` can write
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum ConfigFormat {
+    /// The same format as `verifiers.toml`
+    Toml,
+    /// JSON, for consumption by scripts
+    Json,
+}
+
 /// Command-line argument schema
 #[derive(Parser, Debug)]
 #[clap(about = "A simple tool to recursively walk a set of paths and report corrupted files.",
@@ -47,52 +69,1048 @@ pub struct CliOpts {
     #[arg(short, long, value_name = "resolution")]
     pub timestamp: Option<stderrlog::Timestamp>,
 
+    /// Apply this configuration file on top of the built-in default rather than searching for
+    /// one in the usual places ($XDG_CONFIG_HOME/verify_files/verifiers.toml,
+    /// ~/.config/verify_files/verifiers.toml, or %APPDATA%\verify_files\verifiers.toml)
+    ///
+    /// (Its filetypes and handlers replace default ones with the same ID, its overrides are
+    /// added after the default ones, and a [disabled] table can list filetypes = [...] and
+    /// handlers = [...] to remove.)
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Ignore any user configuration file and use the built-in default (eg. for reproducible
+    /// runs)
+    #[arg(long, conflicts_with = "config")]
+    no_user_config: bool,
+
+    /// Treat keys in the configuration file which don't mean anything (eg. typos) and likely
+    /// mistakes (eg. unrecognized handler IDs) as errors rather than warnings
+    ///
+    /// (Equivalent to putting strict = true at the top of the file.)
+    #[arg(long)]
+    strict_config: bool,
+
     /// File(s) to use as input
     // **TODO:** Restore use of `path_input_file_or_dir` validator
     inpath: Vec<PathBuf>,
 
+    /// Also validate the contents of supported archives, as if they were separate files
+    #[arg(short, long)]
+    recurse: bool,
+
+    /// The maximum number of archives-within-archives to recurse into
+    #[arg(long, value_name = "N", default_value_t = dispatch::Options::default().max_depth)]
+    max_archive_depth: usize,
+
+    /// Warn about files whose header contradicts their extension (the default)
+    #[arg(long, overrides_with = "no_warn_mismatched_extensions")]
+    warn_mismatched_extensions: bool,
+
+    /// Don't warn about files whose header contradicts their extension
+    #[arg(long, overrides_with = "warn_mismatched_extensions")]
+    no_warn_mismatched_extensions: bool,
+
+    /// Kill external handlers which run longer than this (eg. 90s, 5m, 1h30m) unless they set
+    /// their own timeout
+    #[arg(long, value_name = "DURATION", value_parser = config::parse_duration)]
+    handler_timeout: Option<Duration>,
+
+    /// Skip files larger than this (eg. 500MB, 2GiB) unless their filetype sets its own
+    /// `max_size`
+    #[arg(long, value_name = "SIZE", value_parser = config::parse_size)]
+    max_size: Option<u64>,
+
+    /// The maximum number of external handlers to run at once [default: the number of CPUs]
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    subprocess_jobs: Option<u16>,
+
+    /// Lower the CPU priority of everything (built-in and external handlers alike) by this much,
+    /// from 1 to 19, so verification doesn't get in the way of using the machine
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u8).range(1..=19))]
+    nice: Option<u8>,
+
+    /// Create temporary directories (eg. for handlers' scratch files) inside this directory
+    /// rather than the system's temporary directory
+    #[arg(long, value_name = "DIR")]
+    scratch_dir: Option<PathBuf>,
+
+    /// Verify every hardlink to the same file separately, rather than reusing the first verdict
+    #[arg(long)]
+    no_dedupe_hardlinks: bool,
+
+    /// Follow symbolic links, including ones which point outside the paths being walked
+    #[arg(short = 'L', long, overrides_with = "no_follow_links")]
+    follow_links: bool,
+
+    /// Don't follow symbolic links (the default)
+    #[arg(long, overrides_with = "follow_links")]
+    no_follow_links: bool,
+
+    /// Count FIFOs, sockets, and device nodes as (unchecked) passes rather than skipping them
+    #[arg(long)]
+    include_special: bool,
+
     /// Just quickly identify files that have no checker registered
+    ///
+    /// (Only filetype detection is performed, and no handlers are run.)
     #[arg(long)]
     list_unrecognized: bool,
 
+    /// Terminate the paths printed by --list-unrecognized with NUL rather than newline
+    #[arg(short = '0', long = "null", requires = "list_unrecognized")]
+    null: bool,
+
+    /// Just validate a configuration file (by default, whichever one would be used) and report
+    /// any problems, exiting with status 1 if there were errors
+    ///
+    /// (This includes likely mistakes, like unrecognized handler IDs, which are normally only
+    /// warned about.)
+    #[arg(long, value_name = "PATH", num_args = 0..=1, require_equals = true)]
+    check_config: Option<Option<PathBuf>>,
+
+    /// Just print the effective configuration (the built-in default with any user configuration
+    /// file applied) and exit
+    ///
+    /// (The TOML output can be used as a starting point for a customized configuration file.)
+    #[arg(long, value_name = "FORMAT", num_args = 0..=1, require_equals = true,
+          default_missing_value = "toml")]
+    dump_config: Option<ConfigFormat>,
+
     /// Just list the built-in handlers which are available for use in the configuration file
     #[arg(long)]
     list_builtins: bool,
+
+    /// Just report which external handlers are installed and which filetypes can't be verified
+    /// without the missing ones
+    ///
+    /// (Exits with status 2 if any handlers are missing.)
+    #[arg(long)]
+    check_handlers: bool,
+
+    /// Print what external handlers wrote to stdout and stderr (up to 4 KiB of each) for files
+    /// which fail verification
+    ///
+    /// (It's always available in the debug-level log output.)
+    #[arg(long)]
+    show_handler_output: bool,
+}
+
+/// Log the result of processing a single file at a level appropriate to its severity
+///
+/// `note` is extra context to show alongside the path, such as the `message` of the
+/// `[override.<glob>]` which forced the handler, and `show_output` requests that any output
+/// captured from a failed external handler be shown too.
+fn report(path: &Path, outcome: &Outcome<'_>, note: Option<&str>, show_output: bool) {
+    // Show notes on the same line as the verdict they explain
+    let shown = match note {
+        Some(note) => format!("{} ({})", path.display(), note),
+        None => path.display().to_string(),
+    };
+    match outcome {
+        Outcome::Passed { filetype, handler, confidence: Some(confidence) } => {
+            info!("OK ({}, checked by {}, {}): {}", filetype, handler, confidence.label(), shown);
+        },
+        Outcome::Passed { filetype, handler, confidence: None } => {
+            info!("OK ({}, checked by {}): {}", filetype, handler, shown);
+        },
+        Outcome::Failed { filetype, handler, reason, output } => {
+            let summary = match reason {
+                FailureType::InvalidContent(_) => "FAILED",
+                FailureType::IoError(_) => "Could not read",
+                FailureType::UnsupportedFormat(_)
+                | FailureType::HandlerUnavailable(_)
+                | FailureType::InternalError(_) => "Could not verify",
+            };
+            error!("{} ({}, checked by {}): {}\n\t{}", summary, filetype, handler,
+                   shown, reason);
+            if let Some(output) = output.as_ref().filter(|x| show_output && !x.is_empty()) {
+                error!("Output from {} for {}:\n{}", handler, path.display(), output);
+            }
+        },
+        Outcome::Mismatched { by_extension, by_header } => {
+            warn!("Extension suggests {} but header suggests {}: {}", by_extension, by_header,
+                  shown);
+        },
+        Outcome::TooLarge { filetype } => {
+            info!("Skipped (exceeds size limit for {}): {}", filetype, shown);
+        },
+        Outcome::Empty { filetype, valid: true } => {
+            info!("OK ({}, empty file): {}", filetype, shown);
+        },
+        Outcome::Empty { filetype, valid: false } => {
+            error!("FAILED ({}): {}\n\tEmpty file", filetype, shown);
+        },
+        Outcome::Unverified { filetype, reasons } => {
+            warn!("Could not verify ({}): {}\n\t{}", filetype, shown,
+                  reasons.join("\n\t"));
+        },
+        Outcome::Unrecognized => match path.extension() {
+            Some(ext) => {
+                warn!("No filetype registered for extension {:?}: {}", ext, shown);
+            },
+            None => warn!("No filetype registered for extensionless file: {}", shown),
+        },
+        Outcome::Unreadable(reason) => {
+            error!("Could not read file: {}\n\t{}", shown, reason);
+        },
+        Outcome::Special { kind, included: true } => {
+            info!("OK ({}, not checked): {}", kind, shown);
+        },
+        Outcome::Special { kind, included: false } => {
+            info!("Skipped (special file: {}): {}", kind, shown);
+        },
+        Outcome::BrokenSymlink(Some(target)) => {
+            error!("Broken symlink: {} -> {}", shown, target.display());
+        },
+        Outcome::BrokenSymlink(None) => error!("Broken symlink: {}", shown),
+    }
+}
+
+/// Identify file types which must never be opened by a handler, returning a name for them
+#[cfg(unix)]
+fn special_file_kind(file_type: FileType) -> Option<&'static str> {
+    use std::os::unix::fs::FileTypeExt;
+
+    if file_type.is_fifo() {
+        Some("FIFO")
+    } else if file_type.is_socket() {
+        Some("socket")
+    } else if file_type.is_block_device() {
+        Some("block device")
+    } else if file_type.is_char_device() {
+        Some("character device")
+    } else {
+        None
+    }
+}
+
+/// Identify file types which must never be opened by a handler, returning a name for them
+///
+/// **TODO:** Decide whether anything on non-Unix platforms needs to be caught here.
+#[cfg(not(unix))]
+fn special_file_kind(_file_type: FileType) -> Option<&'static str> {
+    None
+}
+
+/// Check whether the given path is a symbolic link whose target can't be resolved
+fn is_broken_symlink(path: &Path) -> bool {
+    path.symlink_metadata().map_or(false, |x| x.file_type().is_symlink())
+        && fs::metadata(path).is_err()
+}
+
+/// Handle an error from the directory walker without aborting the walk
+///
+/// Broken symlinks are reported as such and symlink loops are merely warned about, since
+/// neither says anything about the integrity of the files being checked.
+#[allow(clippy::wildcard_enum_match_arm)]
+fn report_walk_error(err: &ignore::Error) {
+    let mut current = err;
+    let mut path = None;
+    loop {
+        match current {
+            ignore::Error::WithPath { path: inner_path, err } => {
+                path = Some(inner_path.as_path());
+                current = err;
+            },
+            ignore::Error::WithDepth { err, .. } | ignore::Error::WithLineNumber { err, .. } => {
+                current = err;
+            },
+            ignore::Error::Loop { ancestor, child } => {
+                return warn!("Not following symlink loop: {} -> {}", child.display(),
+                             ancestor.display());
+            },
+            _ => break,
+        }
+    }
+
+    match path {
+        Some(path) if is_broken_symlink(path) => {
+            report(path, &Outcome::BrokenSymlink(fs::read_link(path).ok()), None, false);
+        },
+        _ => error!("Error while walking the filesystem: {}", err),
+    }
+}
+
+/// Express a path found by the walker relative to whichever of the given roots it came from
+///
+/// (If a root is a file rather than a directory, its filename is used instead.)
+fn relative_to_root<'a>(path: &'a Path, roots: &[PathBuf]) -> &'a Path {
+    roots
+        .iter()
+        .filter_map(|root| path.strip_prefix(root).ok())
+        .find(|x| !x.as_os_str().is_empty())
+        .or_else(|| path.file_name().map(Path::new))
+        .unwrap_or(path)
+}
+
+/// How many of the most common extensions to list in the `--list-unrecognized` summary
+const UNRECOGNIZED_BREAKDOWN_LEN: usize = 10;
+
+/// Write a path to stdout for `--list-unrecognized`, byte-for-byte where the platform allows
+fn print_path(out: &mut impl Write, path: &Path, terminator: u8) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        out.write_all(path.as_os_str().as_bytes())?;
+    }
+    #[cfg(not(unix))]
+    out.write_all(path.to_string_lossy().as_bytes())?;
+    out.write_all(&[terminator])
+}
+
+/// Summarize the `--list-unrecognized` results, with the most common extensions first
+fn summarize_unrecognized(by_extension: &BTreeMap<String, usize>) -> String {
+    let total: usize = by_extension.values().sum();
+    if total == 0 {
+        return "No unrecognized files found".to_owned();
+    }
+
+    // Sort by descending count, falling back to the BTreeMap's alphabetical order for ties
+    let mut counts: Vec<_> = by_extension.iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(a.1));
+    let mut summary = format!("{} unrecognized file(s) found. Most common extensions:", total);
+    for (ext, count) in counts.iter().take(UNRECOGNIZED_BREAKDOWN_LEN) {
+        summary.push_str(&format!("\n\t{:>8}  {}", count, ext));
+    }
+    if counts.len() > UNRECOGNIZED_BREAKDOWN_LEN {
+        summary.push_str(&format!("\n\t(and {} more)", counts.len() - UNRECOGNIZED_BREAKDOWN_LEN));
+    }
+    summary
+}
+
+/// Summarize how many files passed at each [`Confidence`] level, from least to most reliable
+/// (and then the ones where it's unknown), so weakly protected files stand out
+fn summarize_passes(by_confidence: &BTreeMap<Option<Confidence>, usize>) -> String {
+    let total: usize = by_confidence.values().sum();
+    let mut summary = format!("{} file(s) passed. By confidence:", total);
+    let (unknown, known): (Vec<_>, Vec<_>) = by_confidence.iter().partition(|x| x.0.is_none());
+    for (confidence, count) in known.into_iter().chain(unknown) {
+        let label = confidence.map_or("confidence not specified", Confidence::label);
+        summary.push_str(&format!("\n\t{:>8}  {}", count, label));
+    }
+    summary
+}
+
+/// Count `outcome` in the [`summarize_passes`] tally if it's a pass
+///
+/// (Special files which `--include-special` lets through count at the lowest confidence, since
+/// nothing about them was checked.)
+fn count_pass(passes: &PassCounts, outcome: &Outcome<'_>) {
+    #[allow(clippy::wildcard_enum_match_arm)]
+    let confidence = match *outcome {
+        Outcome::Passed { confidence, .. } => confidence,
+        Outcome::Special { included: true, .. } => Some(Confidence::WellFormed),
+        _ => return,
+    };
+    *passes.borrow_mut().entry(confidence).or_insert(0) += 1;
+}
+
+/// The key used to group a file in the `--list-unrecognized` summary
+fn unrecognized_key(path: &Path) -> String {
+    match path.extension() {
+        Some(ext) => format!(".{}", ext.to_string_lossy().to_lowercase()),
+        None => "(no extension)".to_owned(),
+    }
+}
+
+/// Flags recording which `[override.<glob>]` entries (by position in the config) matched a path
+type OverrideUsage = Arc<Vec<AtomicBool>>;
+
+/// Directories set aside by the walker for directory handlers, waiting to be processed
+type ClaimedDirs = Arc<Mutex<Vec<PathBuf>>>;
+
+/// How many files passed at each [`Confidence`] level, as summarized by [`summarize_passes`]
+type PassCounts = RefCell<BTreeMap<Option<Confidence>, usize>>;
+
+/// Flag the `[override.<glob>]` for the given glob (if any) as having matched something
+fn mark_used(config: &Root, usage: &OverrideUsage, glob: Option<&str>) {
+    if let Some(idx) = glob.and_then(|x| config.overrides.get_index_of(x)) {
+        usage[idx].store(true, Ordering::Relaxed);
+    }
+}
+
+/// Run directory handlers on any directories the walker has set aside for them
+fn process_claimed_dirs(dispatcher: &Dispatcher<'_>, claimed: &ClaimedDirs, roots: &[PathBuf],
+                        usage: &OverrideUsage, config: &Root, show_output: bool,
+                        passes: &PassCounts) {
+    let dirs: Vec<_> =
+        claimed.lock().unwrap_or_else(PoisonError::into_inner).drain(..).collect();
+    for path in dirs {
+        let rel_path = relative_to_root(&path, roots);
+        let override_ = dispatcher.match_directory_override(rel_path);
+        mark_used(config, usage, override_.map(|(glob, _)| glob));
+        let message = override_.and_then(|(_, x)| x.message.as_deref());
+        debug!("Processing directory {}", path.display());
+        dispatcher.process_directory(&path, rel_path, &mut |path, outcome| {
+            report(path, &outcome, message, show_output);
+            count_pass(passes, &outcome);
+        });
+    }
+}
+
+/// Log the `message` of each `[override.<glob>]` which didn't match anything, so stale entries
+/// can be pruned from the config
+fn report_unused_overrides(config: &Root, usage: &OverrideUsage) {
+    for ((glob, override_), used) in config.overrides.iter().zip(usage.iter()) {
+        if let (Some(message), false) = (&override_.message, used.load(Ordering::Relaxed)) {
+            info!("Override never matched anything: {} ({})", glob, message);
+        }
+    }
+}
+
+/// Build a walker over the given roots which skips paths matched by `ignore` globs
+///
+/// Matching directories aren't descended into and, like overrides, globs are matched against
+/// the path relative to the root it was found under.
+///
+/// Directories matched by `claims` are added to `claimed` instead of being walked into.
+fn build_walker(roots: &[PathBuf], config: &Root, follow_links: bool,
+                claims: DirectoryClaims, claimed: &ClaimedDirs) -> Result<Walk> {
+    // XXX: Fix this once https://github.com/BurntSushi/ripgrep/issues/1761 is resolved.
+    let (first, rest) = roots.split_first().ok_or_else(|| anyhow!("No paths to walk"))?;
+    let mut builder = WalkBuilder::new(first);
+    for path in rest {
+        builder.add(path);
+    }
+    // TODO: Allow the standard filters to be toggled individually in the config file or via
+    //       command-line arguments
+    // TODO: Support all WalkBuilder arguments that don't make sense in the config file as
+    //       command-line options.
+    builder.standard_filters(false).follow_links(follow_links);
+
+    let mut globs = GlobSetBuilder::new();
+    for glob in &config.ignore {
+        globs.add(config::build_glob(glob).map_err(|err| anyhow!(err))?);
+    }
+    let ignores = globs.build()?;
+    let (roots, claimed) = (roots.to_vec(), Arc::clone(claimed));
+    builder.filter_entry(move |entry| {
+        let rel_path = relative_to_root(entry.path(), &roots);
+        if ignores.is_match(rel_path) {
+            debug!("Ignoring: {}", entry.path().display());
+            false
+        } else if entry.file_type().map_or(false, |x| x.is_dir()) && claims.claims(rel_path) {
+            let mut claimed = claimed.lock().unwrap_or_else(PoisonError::into_inner);
+            claimed.push(entry.path().to_owned());
+            false
+        } else {
+            true
+        }
+    });
+    Ok(builder.build())
+}
+
+/// The exit status for `--check-handlers` when some handlers aren't installed
+const EXIT_MISSING_HANDLERS: u8 = 2;
+
+/// The result of checking which `[handler.*]` entries are installed
+struct HandlerAvailability<'cfg> {
+    /// Every external handler for this platform which is reachable from a filetype or override,
+    /// and whether it's installed
+    handlers: BTreeMap<&'cfg str, bool>,
+    /// Filetypes for which every handler in the fallback chain is missing
+    unverifiable: Vec<&'cfg str>,
+}
+
+/// Check which of the handlers the configuration actually uses are available
+///
+/// (`is_installed` is a parameter so tests don't depend on what's installed on the system.)
+fn check_handlers<'cfg>(config: &'cfg Root, dispatcher: &Dispatcher<'cfg>,
+                        is_installed: &dyn Fn(&Handler) -> bool) -> HandlerAvailability<'cfg> {
+    let chains = config
+        .filetypes
+        .values()
+        .filter_map(|x| dispatcher.handler_chains(x).ok())
+        .flatten()
+        .chain(config.overrides.values().filter_map(|x| x.handler.as_deref()));
+
+    let mut handlers = BTreeMap::new();
+    for id in chains.flatten() {
+        match dispatcher.resolve_handler(id) {
+            Some(ResolvedHandler::External(handler)) if dispatcher.runs_here(handler) => {
+                handlers.entry(id.as_str()).or_insert_with(|| is_installed(handler));
+            },
+            // Handlers meant for other platforms shouldn't be asked for here
+            _ => {},
+        }
+    }
+
+    let is_available = |id: &String| match dispatcher.resolve_handler(id) {
+        Some(ResolvedHandler::Builtin(_)) => true,
+        Some(ResolvedHandler::External(_)) => handlers.get(id.as_str()) == Some(&true),
+        None => false,
+    };
+    let unverifiable = config
+        .filetypes
+        .iter()
+        .filter(|(_, filetype)| {
+            dispatcher.handler_chains(filetype)
+                .map_or(true, |chains| !chains.into_iter().flatten().any(is_available))
+        })
+        .map(|(id, _)| id.as_str())
+        .collect();
+
+    HandlerAvailability { handlers, unverifiable }
+}
+
+/// Print the results of [`check_handlers`] as a table, returning whether everything was found
+fn print_handler_availability(config: &Root, availability: &HandlerAvailability<'_>) -> bool {
+    println!("{:16} {:9} Description", "Handler", "Status");
+    for (id, &installed) in &availability.handlers {
+        let handler = &config.handlers[*id];
+        let description = handler.description.as_deref().unwrap_or(&handler.argv[0]);
+        println!("{:16} {:9} {}", id, if installed { "OK" } else { "MISSING" }, description);
+        if !installed {
+            for source in handler.sources_for(Platform::current()) {
+                println!("{:27}{}", "", source);
+            }
+        }
+    }
+
+    if !availability.unverifiable.is_empty() {
+        println!("\nFiletypes which can't be verified with the installed handlers:");
+        for id in &availability.unverifiable {
+            println!("  {:14} {}", id, config.filetypes[*id].description);
+        }
+    }
+    availability.handlers.values().all(|x| *x)
+}
+
+/// Decide which user configuration file should be applied, if any, as described on
+/// [`load_config`]
+fn find_config(explicit: Option<&Path>, search: bool) -> Option<PathBuf> {
+    match explicit {
+        Some(path) => Some(path.to_owned()),
+        None if search => {
+            config::user_config_paths(&|x| env::var_os(x)).into_iter().find(|x| x.is_file())
+        },
+        None => None,
+    }
+}
+
+/// The exit status for `--check-config` when the configuration has errors
+const EXIT_INVALID_CONFIG: u8 = 1;
+
+/// Validate the given user configuration file (or the built-in default if `None`) and print
+/// everything found, returning whether it was free of errors
+///
+/// (Findings are printed as `file:line:column: severity: message` where the position is known,
+/// so editors and CI tools can jump to them.)
+fn check_config(path: Option<&Path>, strict: bool) -> bool {
+    let is_builtin = |x: &str| BUILTIN_HANDLERS.contains_key(x);
+    let (name, result) = match path {
+        Some(path) => (path.display().to_string(), fs::read_to_string(path)
+            .with_context(|| "Could not read configuration file")
+            .and_then(|x| {
+                config::check_text(Some(DEFAULT_CONFIG), &x, Some(path), &is_builtin, strict)
+            })),
+        None => ("(built-in default)".to_owned(),
+                 config::check_text(None, DEFAULT_CONFIG, None, &is_builtin, strict)),
+    };
+
+    let findings = match result {
+        Ok(findings) => findings,
+        Err(err) => {
+            println!("{}: error: {:#}", name, err);
+            return false;
+        },
+    };
+    for (finding, position) in &findings {
+        match position {
+            Some((line, column)) => println!("{}:{}:{}: {}: {}", name, line, column,
+                                             finding.severity, finding.message),
+            None => println!("{}: {}: {}", name, finding.severity, finding.message),
+        }
+    }
+    let is_ok = findings.iter().all(|(x, _)| x.severity < config::Severity::Error);
+    if is_ok {
+        println!("OK");
+    }
+    is_ok
+}
+
+/// Load the configuration from `explicit` if given or, if `search` is set, from the first user
+/// configuration file found, applied on top of [`DEFAULT_CONFIG`] as a [`config::Overlay`]
+///
+/// (An explicitly requested file which can't be read is an error rather than a reason to fall
+/// back, since silently using a different configuration would be worse.)
+fn load_config(explicit: Option<&Path>, search: bool, strict: bool) -> Result<Root> {
+    let is_builtin = |x: &str| BUILTIN_HANDLERS.contains_key(x);
+    match find_config(explicit, search) {
+        Some(path) => {
+            info!("Using configuration file {}", path.display());
+            let toml_str = fs::read_to_string(&path).with_context(|| {
+                format!("Could not read configuration file {}", path.display())
+            })?;
+            config::parse_overlay(DEFAULT_CONFIG, &toml_str, Some(&path), &is_builtin, strict)
+                .with_context(|| format!("Invalid configuration file {}", path.display()))
+        },
+        None => {
+            info!("Using built-in default configuration");
+            config::parse(DEFAULT_CONFIG, &is_builtin)
+        },
+    }
 }
 
 /// The actual `main()`
-pub fn main(mut opts: CliOpts) -> Result<()> {
+pub fn main(opts: CliOpts) -> Result<ExitCode> {
     if opts.list_builtins {
-        for (id, (description, _)) in BUILTIN_HANDLERS.iter() {
-            println!("{:10}\t{}", id, description);
+        for (id, builtin) in BUILTIN_HANDLERS.iter() {
+            println!("{:10}\t{}", id, builtin.description);
         }
-        return Ok(());
+        return Ok(ExitCode::SUCCESS);
     }
 
-    // TODO: Support reading a custom config before using the embedded one
-    let config = config::parse(DEFAULT_CONFIG, &|x| BUILTIN_HANDLERS.contains_key(x))?;
+    // Do this before anything spawns threads, since they only inherit it on creation
+    if let Some(nice) = opts.nice {
+        subprocess::lower_own_priority(nice);
+    }
+
+    if let Some(ref path) = opts.check_config {
+        let path = path.clone().or_else(|| find_config(opts.config.as_deref(),
+                                                       !opts.no_user_config));
+        return Ok(if check_config(path.as_deref(), opts.strict_config) {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::from(EXIT_INVALID_CONFIG)
+        });
+    }
+
+    let config = load_config(opts.config.as_deref(), !opts.no_user_config, opts.strict_config)?;
+    match opts.dump_config {
+        Some(ConfigFormat::Toml) => {
+            print!("{}", config.to_toml()?);
+            return Ok(ExitCode::SUCCESS);
+        },
+        Some(ConfigFormat::Json) => {
+            println!("{}", serde_json::to_string_pretty(&config)?);
+            return Ok(ExitCode::SUCCESS);
+        },
+        None => {},
+    }
+    let defaults = dispatch::Options::default();
+    let options = dispatch::Options {
+        recurse: opts.recurse,
+        max_depth: opts.max_archive_depth,
+        warn_mismatched: !opts.no_warn_mismatched_extensions,
+        handler_timeout: opts.handler_timeout,
+        scratch_dir: opts.scratch_dir.clone(),
+        subprocess_jobs: opts.subprocess_jobs.map_or(defaults.subprocess_jobs, usize::from),
+        max_size: opts.max_size,
+        nice: opts.nice,
+        platform: defaults.platform,
+    };
+    let dispatcher = Dispatcher::new(&config, &BUILTIN_HANDLERS, options);
+
+    if opts.check_handlers {
+        let is_installed = |x: &Handler| subprocess::find_executable(&x.argv[0]).is_some();
+        let availability = check_handlers(&config, &dispatcher, &is_installed);
+        return Ok(if print_handler_availability(&config, &availability) {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::from(EXIT_MISSING_HANDLERS)
+        });
+    }
+
+    let hardlinks = Tracker::new();
+    let mut unrecognized = BTreeMap::new();
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    let terminator = if opts.null { b'\0' } else { b'\n' };
+
+    let passes = PassCounts::default();
+    let usage: OverrideUsage =
+        Arc::new(config.overrides.iter().map(|_| AtomicBool::new(false)).collect());
+    let roots = &opts.inpath;
+    if !roots.is_empty() {
+        let follow_links = opts.follow_links && !opts.no_follow_links;
+        let claimed = ClaimedDirs::default();
+        let walker =
+            build_walker(roots, &config, follow_links, dispatcher.directory_claims(), &claimed)?;
+        let flush_claimed = || {
+            if opts.list_unrecognized {
+                claimed.lock().unwrap_or_else(PoisonError::into_inner).clear();
+            } else {
+                process_claimed_dirs(&dispatcher, &claimed, roots, &usage, &config,
+                                     opts.show_handler_output, &passes);
+            }
+        };
+        for result in walker {
+            flush_claimed();
+            let entry = match result {
+                Ok(entry) => entry,
+                Err(err) => {
+                    report_walk_error(&err);
+                    continue;
+                },
+            };
+            if is_broken_symlink(entry.path()) {
+                let target = fs::read_link(entry.path()).ok();
+                report(entry.path(), &Outcome::BrokenSymlink(target), None, false);
+                continue;
+            } else if let Some(kind) = entry.file_type().and_then(special_file_kind) {
+                let outcome = Outcome::Special { kind, included: opts.include_special };
+                report(entry.path(), &outcome, None, false);
+                count_pass(&passes, &outcome);
+                continue;
+            } else if !entry.file_type().map_or(false, |x| x.is_file()) {
+                trace!("Skipping non-file: {}", entry.path().display());
+                continue;
+            }
 
-    // XXX: Fix this once https://github.com/BurntSushi/ripgrep/issues/1761 is resolved.
-    if let Some(path1) = opts.inpath.pop() {
-        let mut builder = WalkBuilder::new(path1);
-        builder.standard_filters(false);
-        for ignore_pat in config.overrides.iter().filter(|x| x.ignore) {
-            // TODO: Integration test the proper handling of ignores
-            builder.add_custom_ignore_filename(&ignore_pat.path);
-        }
-        // TODO: Allow the standard filters to be toggled individually in the config file or via
-        //       command-line arguments
-        // TODO: Support all WalkBuilder arguments that don't make sense in the config file as
-        //       command-line options.
-        for path in opts.inpath {
-            builder.add(path);
-        }
-        for result in builder.build() {
             // TODO: Have an internal validator (which can be turned off) which runs in addition to
             // the regular check and just looks for Win32-incompatible filenames.
-            error!("TODO: Implement processing of {:?}", result?);
+            debug!("Processing {}", entry.path().display());
+            let rel_path = relative_to_root(entry.path(), roots);
+            let override_ = dispatcher.match_override(rel_path);
+            mark_used(&config, &usage, override_.map(|(glob, _)| glob));
+
+            if opts.list_unrecognized {
+                if override_.is_some() {
+                    continue;
+                }
+                match dispatcher.identify(entry.path()) {
+                    Ok(Some(_)) => {},
+                    Ok(None) => {
+                        print_path(&mut stdout, entry.path(), terminator)?;
+                        *unrecognized.entry(unrecognized_key(entry.path())).or_insert(0) += 1;
+                    },
+                    Err(err) => {
+                        error!("Could not read file: {}\n\t{}", entry.path().display(), err);
+                    },
+                }
+            } else {
+                let metadata = entry.metadata().ok();
+                let file_id = metadata
+                    .as_ref()
+                    .filter(|_| !opts.no_dedupe_hardlinks)
+                    .and_then(|x| FileId::for_path(entry.path(), x));
+                let ticket = match file_id.map(|x| hardlinks.claim(x, entry.path())) {
+                    Some(Claim::Duplicate(first, outcome)) => {
+                        let note = format!("verified via hardlink to {}", first.display());
+                        report(entry.path(), &outcome, Some(&note), opts.show_handler_output);
+                        count_pass(&passes, &outcome);
+                        continue;
+                    },
+                    Some(Claim::First(ticket)) => Some(ticket),
+                    None => None,
+                };
+
+                // Archive members don't get the message, since the override didn't match them
+                let message = override_.and_then(|(_, x)| x.message.as_deref());
+                let mut verdict = None;
+                let len = metadata.map(|x| x.len());
+                dispatcher.process(entry.path(), rel_path, len, &mut |path, outcome| {
+                    let is_top_level = path == entry.path();
+                    report(path, &outcome, message.filter(|_| is_top_level),
+                           opts.show_handler_output);
+                    count_pass(&passes, &outcome);
+                    let is_verdict = !matches!(outcome, Outcome::Mismatched { .. });
+                    if is_top_level && is_verdict && verdict.is_none() {
+                        verdict = Some(outcome);
+                    }
+                });
+                if let (Some(ticket), Some(verdict)) = (ticket, verdict) {
+                    ticket.finish(entry.path(), verdict);
+                }
+            }
+        }
+        flush_claimed();
+    }
+
+    report_unused_overrides(&config, &usage);
+    if !passes.borrow().is_empty() {
+        info!("{}", summarize_passes(&passes.borrow()));
+    }
+    if opts.list_unrecognized {
+        stdout.flush()?;
+        if unrecognized.is_empty() {
+            info!("{}", summarize_unrecognized(&unrecognized));
+        } else {
+            warn!("{}", summarize_unrecognized(&unrecognized));
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tempdir::TempDir;
+
+    #[test]
+    fn test_relative_to_root() {
+        let roots = [PathBuf::from("/a/b"), PathBuf::from("/c"), PathBuf::from("/d/e.zip")];
+        assert_eq!(relative_to_root(Path::new("/a/b/c/d.zip"), &roots), Path::new("c/d.zip"));
+        assert_eq!(relative_to_root(Path::new("/c/d.zip"), &roots), Path::new("d.zip"));
+        assert_eq!(relative_to_root(Path::new("/d/e.zip"), &roots), Path::new("e.zip"));
+    }
+
+    #[test]
+    fn test_unrecognized_summary() {
+        let mut counts = BTreeMap::new();
+        assert_eq!(summarize_unrecognized(&counts), "No unrecognized files found");
+
+        for path in &["a.DAT", "b.dat", "c", "d.xyz", "e/f.dat", "g.xyz", "h.abc", "i"] {
+            *counts.entry(unrecognized_key(Path::new(path))).or_insert(0) += 1;
         }
+        assert_eq!(summarize_unrecognized(&counts),
+                   "8 unrecognized file(s) found. Most common extensions:\n\
+                    \t       3  .dat\n\
+                    \t       2  (no extension)\n\
+                    \t       2  .xyz\n\
+                    \t       1  .abc");
+
+        for idx in 0..UNRECOGNIZED_BREAKDOWN_LEN {
+            counts.insert(format!(".ext{}", idx), 1);
+        }
+        assert!(summarize_unrecognized(&counts).ends_with("\n\t(and 4 more)"));
+
+        let mut out = Vec::new();
+        print_path(&mut out, Path::new("a\nb"), b'\0').unwrap();
+        print_path(&mut out, Path::new("c"), b'\0').unwrap();
+        assert_eq!(out, b"a\nb\0c\0");
     }
 
-    Ok(())
+    #[test]
+    fn test_pass_summary() {
+        let mut counts = BTreeMap::new();
+        counts.insert(None, 2);
+        counts.insert(Some(Confidence::FullHash), 1);
+        counts.insert(Some(Confidence::WellFormed), 4);
+        assert_eq!(summarize_passes(&counts),
+                   "7 file(s) passed. By confidence:\n\
+                    \t       4  well-formedness only\n\
+                    \t       1  fully hashed\n\
+                    \t       2  confidence not specified");
+    }
+
+    #[test]
+    fn test_pass_counting() {
+        let passes = PassCounts::default();
+        count_pass(&passes, &Outcome::Passed { filetype: "Foo", handler: "foo", confidence: None });
+        count_pass(&passes, &Outcome::Special { kind: "FIFO", included: true });
+        count_pass(&passes, &Outcome::Special { kind: "FIFO", included: false });
+        count_pass(&passes, &Outcome::Unrecognized);
+        let expected: BTreeMap<_, _> =
+            vec![(None, 1), (Some(Confidence::WellFormed), 1)].into_iter().collect();
+        assert_eq!(*passes.borrow(), expected);
+    }
+
+    /// Ignore globs must exclude matching files and keep the walker out of matching dirs
+    #[test]
+    fn test_ignore_globs() {
+        let config = config::parse(r#"ignore = ["*/skipped", "*.partial"]"#, &|_| true).unwrap();
+
+        let tempdir = TempDir::new().unwrap();
+        let root = tempdir.path().join("root");
+        for dir in &["kept", "kept/skipped/deeper", "skipped"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in &["a.txt", "b.partial", "kept/c.txt", "kept/skipped/d.txt",
+                      "kept/skipped/deeper/e.txt", "skipped/f.txt"] {
+            fs::write(root.join(file), b"x").unwrap();
+        }
+
+        // Top-level `skipped` is relative path `skipped`, which `*/skipped` doesn't match
+        let claims = Dispatcher::new(&config, &BUILTIN_HANDLERS, dispatch::Options::default())
+            .directory_claims();
+        let claimed = ClaimedDirs::default();
+        let walked: Vec<_> = build_walker(&[root.clone()], &config, false, claims.clone(), &claimed)
+            .unwrap()
+            .map(|x| x.unwrap().path().strip_prefix(&root).unwrap().to_owned())
+            .filter(|x| !x.as_os_str().is_empty())
+            .collect();
+        let mut walked: Vec<_> = walked.iter().map(|x| x.to_str().unwrap()).collect();
+        walked.sort_unstable();
+        assert_eq!(walked, vec!["a.txt", "kept", "kept/c.txt", "skipped", "skipped/f.txt"]);
+
+        assert!(build_walker(&[], &config, false, claims, &claimed).is_err());
+    }
+
+    /// Explicitly requested config files must be used, and problems with them must name them
+    #[test]
+    fn test_load_config() {
+        let tempdir = TempDir::new().unwrap();
+        let path = tempdir.path().join("custom.toml");
+        fs::write(&path, "[handler.custom]\nargv = [\"custom\"]\n").unwrap();
+        let loaded = load_config(Some(&path), true, false).unwrap();
+        assert!(loaded.handlers.contains_key("custom"));
+        assert!(loaded.filetypes.contains_key("zip"), "Defaults should still be present");
+        assert!(!load_config(None, false, false).unwrap().handlers.contains_key("custom"));
+
+        let missing = tempdir.path().join("missing.toml");
+        let err = format!("{:#}", load_config(Some(&missing), true, false).unwrap_err());
+        assert!(err.contains("missing.toml"), "{}", err);
+
+        fs::write(&path, "[handler.custom]\nargv = []\n").unwrap();
+        let err = format!("{:#}", load_config(Some(&path), true, false).unwrap_err());
+        assert!(err.contains("custom.toml"), "{}", err);
+    }
+
+    /// Likely mistakes should only fail `--check-config` if they're errors
+    #[test]
+    fn test_check_config() {
+        let tempdir = TempDir::new().unwrap();
+        let path = tempdir.path().join("verifiers.toml");
+        let check = |toml_str: &str| {
+            fs::write(&path, toml_str).unwrap();
+            check_config(Some(&path), false)
+        };
+
+        assert!(check("[handler.custom]\nargv = [\"custom\"]\n"));
+        assert!(check("ignore = [\"*\"]\n"), "Warnings are OK");
+        assert!(!check("[override.\"*.bak\"]\nhandler = \"typo\"\n"));
+        assert!(!check("[handler.custom\n"));
+        assert!(!check_config(Some(&tempdir.path().join("missing.toml")), false));
+    }
+
+    /// Filetypes should only be unverifiable if no handler in their chain is available
+    #[test]
+    #[rustfmt::skip]
+    fn test_check_handlers() {
+        let config = config::parse(r#"
+            [handler.present]
+            argv = ["present"]
+
+            [handler.missing]
+            argv = ["missing"]
+
+            [handler.unused]
+            argv = ["unused"]
+
+            [handler.elsewhere]
+            argv = ["present"]
+            platforms = ["windows"]
+
+            [filetype.covered]
+            description = "Falls back to an installed handler"
+            extension = "a"
+            handler = ["elsewhere", "missing", "present"]
+
+            [filetype.builtin]
+            description = "Falls back to a builtin"
+            extension = "b"
+            handler = ["missing", "json"]
+
+            [filetype.uncovered]
+            description = "Has no installed handlers"
+            extension = "c"
+            handler = "missing"
+
+            [filetype.foreign]
+            description = "Only has handlers for other platforms"
+            extension = "e"
+            handler = "elsewhere"
+
+            [filetype.inherited]
+            container = "uncovered"
+            description = "Inherits its container's handlers"
+            extension = "d"
+        "#, &|x| BUILTIN_HANDLERS.contains_key(x)).unwrap();
+        let options = dispatch::Options { platform: Some(Platform::Linux),
+                                          ..dispatch::Options::default() };
+        let dispatcher = Dispatcher::new(&config, &BUILTIN_HANDLERS, options);
+
+        let availability = check_handlers(&config, &dispatcher, &|x| x.argv[0] == "present");
+        assert_eq!(availability.handlers.into_iter().collect::<Vec<_>>(),
+                   vec![("missing", false), ("present", true)]);
+        assert_eq!(availability.unverifiable, vec!["foreign", "inherited", "uncovered"]);
+    }
+
+    /// Directories claimed by directory handlers must be set aside rather than walked into
+    #[test]
+    fn test_directory_claims() {
+        let config = config::parse(r#"
+            [handler.dvd]
+            argv = ["dvdisaster"]
+            input = "directory"
+
+            [filetype.video_ts]
+            description = "DVD-Video"
+            directory_name = "VIDEO_TS"
+            handler = "dvd"
+
+            [override."*/repo"]
+            handler = "dvd"
+        "#, &|_| false).unwrap();
+        let dispatcher = Dispatcher::new(&config, &BUILTIN_HANDLERS, dispatch::Options::default());
+
+        let tempdir = TempDir::new().unwrap();
+        let root = tempdir.path().join("root");
+        for dir in &["a/VIDEO_TS", "b/repo", "repo", "VIDEO_TS.bak"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+            fs::write(root.join(dir).join("file.txt"), b"x").unwrap();
+        }
+        fs::write(root.join("a/VIDEO_TS.txt"), b"x").unwrap();
+
+        let claimed = ClaimedDirs::default();
+        let walker =
+            build_walker(&[root.clone()], &config, false, dispatcher.directory_claims(), &claimed);
+        let mut walked: Vec<_> = walker.unwrap()
+            .map(Result::unwrap)
+            .filter(|x| x.file_type().map_or(false, |x| x.is_file()))
+            .map(|x| x.path().strip_prefix(&root).unwrap().to_owned())
+            .collect();
+        walked.sort_unstable();
+        assert_eq!(walked, vec![Path::new("VIDEO_TS.bak/file.txt"), Path::new("a/VIDEO_TS.txt"),
+                                Path::new("repo/file.txt")]);
+
+        let mut claimed = claimed.lock().unwrap().clone();
+        claimed.sort_unstable();
+        assert_eq!(claimed, vec![root.join("a/VIDEO_TS"), root.join("b/repo")]);
+
+        // The handler chain from the matching rule is used
+        let mut outcomes = Vec::new();
+        for path in &claimed {
+            let rel_path = relative_to_root(path, &[root.clone()]);
+            dispatcher.process_directory(path, rel_path, &mut |_, x| outcomes.push(x));
+        }
+        assert!(matches!(outcomes[0], Outcome::Unverified { filetype: "DVD-Video", .. }));
+        assert!(matches!(outcomes[1], Outcome::Unverified { filetype: "*/repo", .. }));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_broken_symlink_detection() {
+        use std::os::unix::fs::symlink;
+
+        let tempdir = TempDir::new().unwrap();
+        let (target, good, broken) = (tempdir.path().join("target"),
+            tempdir.path().join("good"), tempdir.path().join("broken"));
+        fs::write(&target, b"x").unwrap();
+        symlink(&target, &good).unwrap();
+        symlink(tempdir.path().join("missing"), &broken).unwrap();
+
+        assert!(!is_broken_symlink(&target));
+        assert!(!is_broken_symlink(&good));
+        assert!(is_broken_symlink(&broken));
+        assert!(!is_broken_symlink(&tempdir.path().join("missing")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_special_file_detection() {
+        use std::process::Command;
+
+        let tempdir = TempDir::new().unwrap();
+        let (fifo, file) = (tempdir.path().join("fifo"), tempdir.path().join("file"));
+        fs::write(&file, b"x").unwrap();
+        assert!(Command::new("mkfifo").arg(&fifo).status().unwrap().success());
+
+        let kind = |path: &Path| special_file_kind(fs::symlink_metadata(path).unwrap().file_type());
+        assert_eq!(kind(&fifo), Some("FIFO"));
+        assert_eq!(kind(Path::new("/dev/null")), Some("character device"));
+        assert_eq!(kind(&file), None);
+        assert_eq!(kind(tempdir.path()), None);
+
+        // ...and the walker must report them without ever opening them (which would block)
+        let entries: Vec<_> = WalkBuilder::new(tempdir.path()).build()
+            .filter_map(|x| x.unwrap().file_type().and_then(special_file_kind)).collect();
+        assert_eq!(entries, vec!["FIFO"]);
+    }
 }
@@ -11,37 +11,232 @@
 //!
 
 use std::collections::BTreeMap;
+use std::fmt;
 use std::fs::{self, File};
-use std::io::{self, BufReader, Read};
+use std::io::{self, BufRead, BufReader, Read};
 use std::path::Path;
 
-use flate2::bufread::MultiGzDecoder;
+use flate2::DecompressError;
 
 use image::error::ImageError;
 use image::io::Reader as ImageReader;
 
 use lazy_static::lazy_static;
 
+use log::{debug, info};
+
+use serde::{Deserialize, Serialize};
+
 use zip::read::ZipArchive;
 use zip::result::{ZipError, ZipResult};
 
+use crate::config::HandlerArgs;
+
+mod aac;
+mod brotli;
+mod bytes;
+mod bzip2;
+mod cab;
+mod checksums;
+mod csv;
+mod dicom;
+mod elf;
+mod epub;
+mod font;
+mod gzip;
+mod hdf5;
+mod heif;
+mod iso9660;
+mod json;
+mod lzma;
+mod mp3;
+mod mp4;
+mod ogg;
+mod ooxml;
+mod opendocument;
+mod opus;
+mod pe;
+mod plist;
+mod png;
+mod psd;
+mod rar;
+mod riff;
+mod rpm;
+mod sevenz;
+mod sqlite;
+mod svg;
+mod tar;
+mod text;
+mod toml;
+mod wasm;
+mod webp;
+mod woff;
+mod xml;
+mod xz;
+mod yaml;
+mod zip_structure;
+mod zstd;
+
+use self::brotli::BrotliDecoder;
+use self::bzip2::Bzip2Decoder;
+pub use self::gzip::GzipDecoder;
+use self::xz::XzDecoder;
+use self::zip_structure::EncryptedEntries;
+use self::zstd::ZstdDecoder;
+
+/// What a handler reports for a file
+///
+/// `Ok(None)` is a pass at the [`confidence`](Builtin::confidence) the handler was registered with,
+/// while `Ok(Some(..))` is a pass at a lower confidence because the file lacked something the
+/// handler usually checks, such as the optional content checksum in a Zstandard frame.
+pub type HandlerResult = Result<Option<Confidence>, FailureType>;
+
 /// The function signature for file-type handler implementations
-pub type HandlerFn = fn(&Path) -> Result<(), FailureType>;
+///
+/// (The second argument is the filetype's [`handler_args`](crate::config::Filetype::handler_args).)
+pub type HandlerFn = fn(&Path, &HandlerArgs) -> HandlerResult;
+
+/// The function signature for handler implementations which can validate a stream of data
+///
+/// (Used to validate data that never exists as a file on disk, such as the decompressed contents
+/// of a GZip file.)
+pub type StreamHandlerFn = fn(&mut dyn Read, &HandlerArgs) -> HandlerResult;
+
+/// A registry entry for a built-in handler
+pub struct Builtin {
+    /// A description suitable for display to end-users
+    pub description: &'static str,
+    /// The implementation which validates a file on disk
+    pub handler: HandlerFn,
+    /// An alternative implementation for handlers which don't need to seek within their input
+    pub stream_handler: Option<StreamHandlerFn>,
+    /// The `handler_args` keys which the handler understands
+    ///
+    /// (Others are ignored, but mentioned in the debug output in case they're typos.)
+    pub args: &'static [&'static str],
+    /// How much a pass from this handler says about the file
+    pub confidence: Confidence,
+}
+
+impl Builtin {
+    /// Shorthand for defining a handler which can only validate files on disk
+    const fn path_only(description: &'static str, confidence: Confidence, handler: HandlerFn)
+            -> Self {
+        Self { description, handler, stream_handler: None, args: &[], confidence }
+    }
+
+    /// Shorthand for defining a handler which can also validate streams
+    const fn streaming(description: &'static str, confidence: Confidence, handler: HandlerFn,
+                       stream_handler: StreamHandlerFn) -> Self {
+        Self { description, handler, stream_handler: Some(stream_handler), args: &[], confidence }
+    }
+
+    /// Declare the `handler_args` keys which the handler understands
+    const fn with_args(mut self, args: &'static [&'static str]) -> Self {
+        self.args = args;
+        self
+    }
+}
 
 // Chosen because it's already a transitive dependency, unlike `phf`
 lazy_static! {
-    /// A registry of all built-in handlers, with descriptions suitable for display to end-users,
-    /// keyed by the IDs exposed to the config file.
+    /// A registry of all built-in handlers, keyed by the IDs exposed to the config file.
     ///
     /// (Uses a BTreeMap to control the ordering of user-visible readouts without an extra sort)
-    pub static ref ALL: BTreeMap<&'static str, (&'static str, HandlerFn)> = {
+    pub static ref ALL: BTreeMap<&'static str, Builtin> = {
         let mut m = BTreeMap::new();
-        m.insert("gzip", ("GZip CRC check (built-in)", gzip as HandlerFn));
-        m.insert("image", ("BMP/GIF/ICO/JPEG/PNG/PNM/TGA/TIFF handler (built-in)",
-                image as HandlerFn));
-        m.insert("json", ("JSON well-formedness check (built-in)", json as HandlerFn));
-        m.insert("toml", ("TOML well-formedness check (built-in)", toml as HandlerFn));
-        m.insert("zip", ("STORE/DEFLATE-compressed Zip CRC check (built-in)", zip as HandlerFn));
+        m.insert("aac", Builtin::streaming("AAC ADTS frame structure check (built-in)",
+            Confidence::WellFormed, aac, aac_stream));
+        m.insert("brotli", Builtin::streaming(
+            "Brotli decompression check (built-in; Brotli has no checksum to verify)",
+            Confidence::WellFormed, brotli, brotli_stream));
+        m.insert("cab", Builtin::path_only(
+            "Microsoft CAB checksum and MSZIP decompression check (built-in)",
+            Confidence::DataHash, cab));
+        m.insert("csv", Builtin::streaming("CSV structure check (built-in)",
+            Confidence::WellFormed, csv, csv_stream).with_args(&["uniform_fields"]));
+        m.insert("dicom", Builtin::streaming("DICOM data element structure check (built-in)",
+            Confidence::WellFormed, dicom, dicom_stream));
+        m.insert("elf", Builtin::path_only("ELF header and table bounds check (built-in)",
+            Confidence::WellFormed, elf));
+        m.insert("epub", Builtin::path_only("EPUB container check (built-in)",
+            Confidence::DataHashAndMetaParity, epub));
+        m.insert("font", Builtin::path_only("TrueType/OpenType table checksum check (built-in)",
+            Confidence::DataHash, font));
+        m.insert("gzip", Builtin::streaming("GZip CRC check (built-in)", Confidence::DataHash,
+            gzip, gzip_stream));
+        m.insert("hdf5", Builtin::path_only(
+            "HDF5 superblock and object header check (built-in)", Confidence::WellFormed, hdf5));
+        m.insert("heif", Builtin::path_only("HEIF/AVIF box structure and item check (built-in)",
+            Confidence::WellFormed, heif));
+        m.insert("image", Builtin::path_only(
+            "BMP/GIF/ICO/JPEG/PNG/PNM/TGA/TIFF handler (built-in)", Confidence::WellFormed, image));
+        m.insert("iso9660", Builtin::path_only("ISO 9660 filesystem structure check (built-in)",
+            Confidence::WellFormed, iso9660));
+        m.insert("json", Builtin::streaming("JSON well-formedness check (built-in)",
+            Confidence::WellFormed, json, json_stream));
+        m.insert("mp3", Builtin::streaming("MPEG audio frame structure check (built-in)",
+            Confidence::WellFormed, mp3, mp3_stream));
+        m.insert("mp4", Builtin::path_only("MP4/QuickTime box structure check (built-in)",
+            Confidence::WellFormed, mp4));
+        m.insert("ogg", Builtin::streaming("Ogg page CRC check (built-in)", Confidence::DataHash,
+            ogg, ogg_stream));
+        m.insert("ooxml", Builtin::path_only("OOXML (Office Open XML) package check (built-in)",
+            Confidence::DataHashAndMetaParity, ooxml));
+        m.insert("opendocument", Builtin::path_only("OpenDocument container check (built-in)",
+            Confidence::DataHashAndMetaParity, opendocument));
+        m.insert("opus", Builtin::streaming("Ogg page CRC and Opus header check (built-in)",
+            Confidence::DataHashAndMetaParity, opus, opus_stream));
+        m.insert("pe", Builtin::path_only("PE/COFF header and section bounds check (built-in)",
+            Confidence::WellFormed, pe));
+        m.insert("pe_checksum", Builtin::path_only("PE image checksum verification (built-in)",
+            Confidence::DataHash, pe_checksum));
+        m.insert("plist", Builtin::streaming("Binary and XML property list parse (built-in)",
+            Confidence::WellFormed, plist, plist_stream));
+        m.insert("png", Builtin::streaming("PNG chunk CRC check (built-in)", Confidence::DataHash,
+            png, png_stream));
+        m.insert("psd", Builtin::path_only("PSD/PSB header and section length check (built-in)",
+            Confidence::WellFormed, psd));
+        m.insert("rar", Builtin::path_only("RAR header CRC check (built-in)",
+            Confidence::WellFormed, rar));
+        m.insert("riff", Builtin::streaming("RIFF chunk structure check (built-in)",
+            Confidence::WellFormed, riff, riff_stream));
+        m.insert("rpm", Builtin::streaming(
+            "RPM header structure and payload digest check (built-in)", Confidence::DataHash, rpm,
+            rpm_stream));
+        m.insert("sevenz", Builtin::path_only(
+            "Copy/LZMA/LZMA2/Deflate-compressed 7-Zip CRC check (built-in)", Confidence::DataHash,
+            sevenz));
+        m.insert("sqlite", Builtin::path_only("SQLite 3 header and page structure check (built-in)",
+            Confidence::WellFormed, sqlite).with_args(&["integrity_check"]));
+        m.insert("svg", Builtin::streaming("SVG well-formedness and root element check (built-in)",
+            Confidence::WellFormed, svg, svg_stream));
+        m.insert("tar", Builtin::streaming("Tar header checksum and structure check (built-in)",
+            Confidence::WellFormed, tar, tar_stream));
+        m.insert("tar_compressed", Builtin::streaming(
+            "GZip/Zstandard/XZ/BZip2-compressed tar checksum and structure check (built-in)",
+            Confidence::DataHash, tar_compressed, tar_compressed_stream));
+        m.insert("text", Builtin::streaming("UTF-8/16/32 plaintext check (built-in)",
+            Confidence::WellFormed, text, text_stream));
+        m.insert("toml", Builtin::streaming("TOML well-formedness check (built-in)",
+            Confidence::WellFormed, toml, toml_stream));
+        m.insert("wasm", Builtin::streaming("WebAssembly module validation (built-in)",
+            Confidence::WellFormed, wasm, wasm_stream));
+        m.insert("webp", Builtin::streaming("WebP chunk and bitstream structure check (built-in)",
+            Confidence::WellFormed, webp, webp_stream));
+        m.insert("woff", Builtin::path_only("WOFF/WOFF2 decompression and table check (built-in)",
+            Confidence::WellFormed, woff));
+        m.insert("xml", Builtin::streaming("XML well-formedness check (built-in)",
+            Confidence::WellFormed, xml, xml_stream));
+        m.insert("xz", Builtin::streaming("XZ block check verification (built-in)",
+            Confidence::DataHash, xz, xz_stream));
+        m.insert("yaml", Builtin::streaming("YAML well-formedness check (built-in)",
+            Confidence::WellFormed, yaml, yaml_stream));
+        m.insert("zip", Builtin::path_only(
+            "STORE/DEFLATE-compressed Zip CRC and header consistency check (built-in)",
+            Confidence::DataHashAndMetaParity, zip).with_args(&["reject_encrypted"]));
+        m.insert("zstd", Builtin::streaming("Zstandard content checksum verification (built-in)",
+            Confidence::DataHash, zstd, zstd_stream));
         m
     };
 }
@@ -50,6 +245,7 @@ lazy_static! {
 /// corrupted or because it uses features not supported by the validator.
 ///
 /// (And, as such, whether the testing system should abort or continue down the fallback chain.)
+#[derive(Clone, Debug)]
 pub enum FailureType {
     /// The handler detected some form of corruption or fatal spec-noncompliance
     ///
@@ -82,6 +278,12 @@ pub enum FailureType {
     /// on its container, like EPUB, JAR, or OpenDocument only supporting STORE or DEFLATE.
     UnsupportedFormat(/** Stringified form of the internal error message */ String),
 
+    /// The handler couldn't be run at all (eg. an external tool which isn't installed)
+    ///
+    /// (Log a status message and try the next handler in the fallback chain, as with
+    /// [`UnsupportedFormat`](Self::UnsupportedFormat), since it says nothing about the file.)
+    HandlerUnavailable(/** Stringified form of the internal error message */ String),
+
     /// The file cannot be read for some reason
     ///
     /// (Log a status message and move on to the next file)
@@ -102,14 +304,31 @@ pub enum FailureType {
     InternalError(/** Stringified form of the internal error message */ String),
 }
 
+impl fmt::Display for FailureType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FailureType::InvalidContent(msg) => write!(f, "Invalid content: {}", msg),
+            FailureType::UnsupportedFormat(msg) => write!(f, "Unsupported format: {}", msg),
+            FailureType::HandlerUnavailable(msg) => write!(f, "Handler unavailable: {}", msg),
+            FailureType::IoError(msg) => write!(f, "I/O error: {}", msg),
+            FailureType::InternalError(msg) => write!(f, "Internal error: {}", msg),
+        }
+    }
+}
+
 /// A return value to indicate how reliable a validator's verdict of "no problems" is.
 ///
 /// **TODO:** Decide on whether a meaningful total ordering can be had if I split
 /// `DataHashAndMetaParity` so it's possible to specify data and metadata protection level
 /// completely independently.
 ///
-/// **TODO:** Decide whether this should instead serve as a metadata key that's applied to each
-/// validator definition for **pre**-selection of the most reliable validator available.
+/// **TODO:** Decide whether this should also be used for **pre**-selection of the most reliable
+/// validator available.
+///
+/// (Written in the configuration file in kebab-case, like `confidence = "data-hash"`, and
+/// ordered from least to most reliable.)
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum Confidence {
     /// The validator checks the basic well-formedness of the data but does no further checking.
     ///
@@ -137,6 +356,19 @@ pub enum Confidence {
     FullHash,
 }
 
+impl Confidence {
+    /// A short description of what a pass at this level means, for use in reports
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::WellFormed => "well-formedness only",
+            Self::DataParity => "parity only",
+            Self::DataHash => "checksummed",
+            Self::DataHashAndMetaParity => "checksummed and cross-checked",
+            Self::FullHash => "fully hashed",
+        }
+    }
+}
+
 /// Helper for APIs that validate lazily and need to have their `Read`-ers read through to the end
 fn exhaust_reader(mut reader: impl Read) -> Result<(), io::Error> {
     let mut scratch_buffer = [0; 0xFFFF];
@@ -151,23 +383,243 @@ fn exhaust_reader(mut reader: impl Read) -> Result<(), io::Error> {
     }
 }
 
-/// Handler: Use the `flate2` crate to validate a stream of one or more gzipped files
+/// Shorthand for the error the built-in decompressors use to report corrupted data
+fn corrupt(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_owned())
+}
+
+/// Report truncation within a built-in decompressor as corruption, since `read_exact` callers
+/// treat `UnexpectedEof` as a normal end of stream
+fn eof_is_corrupt<T>(result: io::Result<T>) -> io::Result<T> {
+    #[allow(clippy::wildcard_enum_match_arm)]
+    result.map_err(|err| match err.kind() {
+        io::ErrorKind::UnexpectedEof => corrupt("Unexpected end of file"),
+        _ => err,
+    })
+}
+
+/// Shorthand for the error the built-in decompressors use to report features they don't implement
+fn unsupported(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, message.to_owned())
+}
+
+/// Helper to classify the errors reported by the built-in decompressors
+fn decompressor_failure(err: io::Error) -> FailureType {
+    #[allow(clippy::wildcard_enum_match_arm)]
+    match err.kind() {
+        io::ErrorKind::Unsupported => FailureType::UnsupportedFormat(err.to_string()),
+        io::ErrorKind::InvalidData => FailureType::InvalidContent(err.to_string()),
+        _ => FailureType::IoError(err.to_string()),
+    }
+}
+
+/// Helper to read a `"true"` or `"false"` handler argument, which defaults to `false`
+fn bool_arg(args: &HandlerArgs, key: &str) -> Result<bool, FailureType> {
+    match args.get(key).map(String::as_str) {
+        None | Some("false") => Ok(false),
+        Some("true") => Ok(true),
+        Some(other) => Err(FailureType::InternalError(format!(
+            "Invalid value for the {} handler argument: {:?}", key, other))),
+    }
+}
+
+/// Helper to open a file for a handler which then defers to its stream-based counterpart
+fn open_for_stream(path: &Path, args: &HandlerArgs, stream_handler: StreamHandlerFn)
+        -> HandlerResult {
+    let file = File::open(path).map_err(|err| FailureType::IoError(err.to_string()))?;
+    stream_handler(&mut BufReader::new(file), args)
+}
+
+/// Handler: Walk the frames of a raw AAC stream in ADTS framing, checking that none are malformed
 ///
-/// **TODO:** Decide on the best API for selecting whether this should operate recursively to
-/// validate the data that it must extract anyway to check the CRC.
+/// (Like [`mp3`], this can mostly only catch corruption which damages the frame headers, or
+/// truncation, though the header CRCs of frames holding several raw data blocks are verified.
+/// LATM/LOAS and ADIF streams are reported as unsupported.)
+pub fn aac(path: &Path, args: &HandlerArgs) -> HandlerResult {
+    open_for_stream(path, args, aac_stream)
+}
+
+/// Stream-based counterpart to [`aac`]
+pub fn aac_stream(reader: &mut dyn Read, _args: &HandlerArgs) -> HandlerResult {
+    let summary = aac::verify(reader).map_err(decompressor_failure)?;
+    let seconds = (summary.blocks * aac::BLOCK_SAMPLES) as f64 / f64::from(summary.sample_rate);
+    debug!("Walked {} ADTS frame(s) holding {:.2} second(s) of audio, verifying {} header CRC(s)",
+        summary.frames, seconds, summary.crcs);
+    Ok(None)
+}
+
+/// Handler: Decompress a raw Brotli stream to check that it's structurally sound
+///
+/// (Brotli has no checksum, so damage which still decodes can't be caught. It has no magic number
+/// either, so the filetype can only be recognized by its extension.)
+pub fn brotli(path: &Path, args: &HandlerArgs) -> HandlerResult {
+    open_for_stream(path, args, brotli_stream)
+}
+
+/// Stream-based counterpart to [`brotli`]
+pub fn brotli_stream(reader: &mut dyn Read, _args: &HandlerArgs) -> HandlerResult {
+    let mut decoder = BrotliDecoder::new(BufReader::new(reader));
+    exhaust_reader(&mut decoder).map_err(decompressor_failure)?;
+    if decoder.into_inner().fill_buf().map_err(|e| FailureType::IoError(e.to_string()))?
+            .is_empty() {
+        Ok(None)
+    } else {
+        Err(FailureType::InvalidContent("trailing data after Brotli stream".to_owned()))
+    }
+}
+
+/// Handler: Walk a Microsoft Cabinet archive, verifying its data blocks' checksums and
+/// decompressing any MSZIP-compressed folders
+///
+/// (LZX and Quantum folders are only checked as far as their checksums go. Only the cabinet given
+/// is checked when it's part of a set, since data continued from or into its neighbours can't be
+/// decompressed without them.)
+pub fn cab(path: &Path, _args: &HandlerArgs) -> HandlerResult {
+    let file = File::open(path).map_err(|err| FailureType::IoError(err.to_string()))?;
+    let file_len = file.metadata().map_err(|err| FailureType::IoError(err.to_string()))?.len();
+    let summary = cab::verify(BufReader::new(file), file_len).map_err(decompressor_failure)?;
+    if summary.multi_part {
+        info!("{} is part of a multi-cabinet set, so only the data it holds itself was checked",
+            path.display());
+    }
+    if summary.unchecksummed > 0 {
+        info!("{} CFDATA block(s) in {} had no checksum, so only their structure was checked",
+            summary.unchecksummed, path.display());
+    }
+    debug!("Walked {} files in {} CFDATA blocks", summary.files, summary.blocks);
+    Ok(None)
+}
+
+/// Handler: Parse a CSV file record by record, checking that its quoting is intact and that it
+/// doesn't stop partway through a record
+///
+/// Setting the `uniform_fields` handler argument to `"true"` also requires every record to have
+/// as many fields as the header. (It's off by default because ragged CSV is common in the wild.)
+pub fn csv(path: &Path, args: &HandlerArgs) -> HandlerResult {
+    open_for_stream(path, args, csv_stream)
+}
+
+/// Stream-based counterpart to [`csv`]
+pub fn csv_stream(reader: &mut dyn Read, args: &HandlerArgs) -> HandlerResult {
+    csv::verify(reader, bool_arg(args, "uniform_fields")?).map_err(decompressor_failure)?;
+    Ok(None)
+}
+
+/// Handler: Walk the data elements of a DICOM file, checking that each fits within its container
+///
+/// (DICOM has no checksums, so this mainly catches truncation and damage to the element headers.
+/// Encapsulated pixel data has its fragments checked against the Basic Offset Table, but isn't
+/// decoded. Bare data sets without the DICOM preamble are reported as unsupported.)
+pub fn dicom(path: &Path, args: &HandlerArgs) -> HandlerResult {
+    open_for_stream(path, args, dicom_stream)
+}
+
+/// Stream-based counterpart to [`dicom`]
+pub fn dicom_stream(reader: &mut dyn Read, _args: &HandlerArgs) -> HandlerResult {
+    let summary = dicom::verify(reader).map_err(decompressor_failure)?;
+    debug!("Transfer syntax {}, with {} fragment(s) of encapsulated pixel data",
+           summary.transfer_syntax, summary.fragments);
+    Ok(None)
+}
+
+/// Handler: Check that an ELF file's headers are sane and everything they describe is present
+///
+/// (ELF has no checksums, so this mainly catches truncation and damage to the headers. Nothing in
+/// the file is ever loaded or executed.)
+pub fn elf(path: &Path, _args: &HandlerArgs) -> HandlerResult {
+    let file = File::open(path).map_err(|err| FailureType::IoError(err.to_string()))?;
+    let file_len = file.metadata().map_err(|err| FailureType::IoError(err.to_string()))?.len();
+    elf::verify(BufReader::new(file), file_len).map_err(decompressor_failure)?;
+    Ok(None)
+}
+
+/// Handler: Check a Zip file's CRCs like [`zip`], then the rules EPUB adds on top of Zip
+///
+/// (The `mimetype` entry must come first, uncompressed, and `META-INF/container.xml` and the
+/// package documents it lists must be well-formed XML. Breaking those rules is reported as
+/// invalid content, since a reading system may refuse the book even if it unzips fine.)
+pub fn epub(path: &Path, _args: &HandlerArgs) -> HandlerResult {
+    let mut zip = verify_zip(path, false)?;
+    epub::verify(&mut zip).map_err(decompressor_failure)?;
+    Ok(None)
+}
+
+/// Handler: Verify the table checksums of a TrueType or OpenType font or font collection
+///
+/// (Standalone fonts also have their whole-file checksum checked. The tables themselves aren't
+/// parsed, so this can't catch corruption which happened before the checksums were calculated.)
+pub fn font(path: &Path, _args: &HandlerArgs) -> HandlerResult {
+    let file = File::open(path).map_err(|err| FailureType::IoError(err.to_string()))?;
+    let file_len = file.metadata().map_err(|err| FailureType::IoError(err.to_string()))?.len();
+    font::verify(BufReader::new(file), file_len).map_err(decompressor_failure)?;
+    Ok(None)
+}
+
+/// Handler: Validate a stream of one or more gzipped files, checking each member's CRC and
+/// length and rejecting anything after the last member
+///
+/// (The dispatcher handles validating the decompressed data, since it must be extracted anyway
+/// to check the CRC, as a means to detect corruption that occurred before the compression was
+/// applied.)
+pub fn gzip(path: &Path, args: &HandlerArgs) -> HandlerResult {
+    open_for_stream(path, args, gzip_stream)
+}
+
+/// Stream-based counterpart to [`gzip`]
+pub fn gzip_stream(reader: &mut dyn Read, _args: &HandlerArgs) -> HandlerResult {
+    let mut decoder = GzipDecoder::new(BufReader::new(reader));
+    exhaust_reader(&mut decoder).map_err(decompressor_failure)?;
+    debug!("Decompressed {} bytes from {} gzip member(s)", decoder.total_out(),
+           decoder.members());
+    Ok(None)
+}
+
+/// Handler: Walk the metadata of an HDF5 file from its superblock through every reachable group
 ///
-/// (As a means to detect corruption that occurred before the compression was applied.)
-pub fn gzip(path: &Path) -> Result<(), FailureType> {
-    let reader = File::open(path).map_err(|err| FailureType::IoError(err.to_string()))?;
-    exhaust_reader(MultiGzDecoder::new(BufReader::new(reader)))
-        .map_err(|err| FailureType::InvalidContent(err.to_string()))
+/// (Files with version 2 or 3 superblocks also have their superblock and object header checksums
+/// verified. Dataset contents aren't read, so this mainly catches truncation and damage to the
+/// metadata, and groups which store their links in fractal heaps aren't walked into.)
+pub fn hdf5(path: &Path, _args: &HandlerArgs) -> HandlerResult {
+    let file = File::open(path).map_err(|err| FailureType::IoError(err.to_string()))?;
+    let file_len = file.metadata().map_err(|err| FailureType::IoError(err.to_string()))?.len();
+    let summary = hdf5::verify(BufReader::new(file), file_len).map_err(decompressor_failure)?;
+    if summary.unchecksummed > 0 {
+        info!("{} object header(s) in {} predate HDF5's metadata checksums, so only their \
+            structure was checked", summary.unchecksummed, path.display());
+    }
+    if summary.dense_groups > 0 {
+        info!("{} group(s) in {} store their links in fractal heaps, so their members weren't \
+            checked", summary.dense_groups, path.display());
+    }
+    if summary.open_for_writing {
+        info!("{} is marked as still open for writing, so it may not have been closed cleanly",
+            path.display());
+    }
+    debug!("Walked {} objects and verified {} checksums in a version {} HDF5 file",
+        summary.objects, summary.checksums, summary.superblock_version);
+    Ok(None)
+}
+
+/// Handler: Walk the box structure of a HEIF or AVIF image and check the items in its `meta` box
+///
+/// (Catches truncation, since the `iloc` box says where each item's data is, and damage to the
+/// `meta` box which leaves references to items or properties that don't exist. The image data
+/// itself isn't decoded.)
+pub fn heif(path: &Path, _args: &HandlerArgs) -> HandlerResult {
+    let file = File::open(path).map_err(|err| FailureType::IoError(err.to_string()))?;
+    let file_len = file.metadata().map_err(|err| FailureType::IoError(err.to_string()))?.len();
+    let summary = heif::verify(BufReader::new(file), file_len).map_err(decompressor_failure)?;
+    debug!("Checked {} item(s), {} of them part of the primary image, and {} data extent(s) in \
+        a HEIF file{}", summary.items, summary.primary_parts, summary.extents,
+        if summary.sequence { ", plus an image sequence" } else { "" });
+    Ok(None)
 }
 
 /// Handler: Use the `image` crate to validate the formats it supports
 ///
 /// **TODO:** Test how thoroughly each format can be checked, and also check whether enabling WebP
 /// support will validate well enough to be useful even though it doesn't support chroma yet.
-pub fn image(path: &Path) -> Result<(), FailureType> {
+pub fn image(path: &Path, _args: &HandlerArgs) -> HandlerResult {
     #[allow(clippy::wildcard_enum_match_arm)]
     ImageReader::open(path)
         .map_err(|err| FailureType::IoError(err.to_string()))?
@@ -180,71 +632,636 @@ pub fn image(path: &Path) -> Result<(), FailureType> {
             ImageError::IoError(e) => FailureType::IoError(e.to_string()),
             e => FailureType::InternalError(e.to_string()),
         })?;
-    Ok(())
+    Ok(None)
+}
+
+/// Handler: Walk the volume descriptors and directory trees of an ISO 9660 disc image
+///
+/// (Catches truncation and damaged metadata, but not corrupted file contents, since ISO 9660 has
+/// no checksums. Pure UDF images are reported as unsupported, and dvdisaster error correction data
+/// is noticed but left for the `dvdisaster` handler to check.)
+pub fn iso9660(path: &Path, _args: &HandlerArgs) -> HandlerResult {
+    let file = File::open(path).map_err(|err| FailureType::IoError(err.to_string()))?;
+    let file_len = file.metadata().map_err(|err| FailureType::IoError(err.to_string()))?.len();
+    let ecc = iso9660::verify(BufReader::new(file), file_len).map_err(decompressor_failure)?;
+    if let Some(method) = ecc {
+        info!("{} has dvdisaster {} error correction data, which wasn't checked",
+            path.display(), method);
+    }
+    Ok(None)
 }
 
-/// Handler: Use the `json` crate to do a basic well-formedness check
+/// Handler: Stream a JSON document through `serde_json` to do a basic well-formedness check
+///
+/// (No DOM is built, so memory use stays flat regardless of file size. Trailing data after the
+/// top-level value is reported as corruption.)
 ///
 /// **TODO:** Decide on an API and some real-world test data to allow detecting potential
 /// corruption in string variables using the UTF-8 subset of the plaintext handler's checks.
-pub fn json(path: &Path) -> Result<(), FailureType> {
-    #[allow(clippy::wildcard_enum_match_arm)]
-    let raw_data = fs::read_to_string(path).map_err(|err| match err.kind() {
-        // If we can't String it, then report a validation error because JSON must be UTF-8
-        io::ErrorKind::InvalidData => FailureType::InvalidContent(err.to_string()),
-        // ...otherwise, report an OS-level error.
-        _ => FailureType::IoError(err.to_string()),
-    })?;
+pub fn json(path: &Path, args: &HandlerArgs) -> HandlerResult {
+    open_for_stream(path, args, json_stream)
+}
 
-    // TODO: See if there's a Read-based API that could be used to reduce the memory footprint
-    json::parse(&raw_data).map_err(|err| FailureType::InvalidContent(err.to_string()))?;
-    Ok(())
+/// Stream-based counterpart to [`json`]
+pub fn json_stream(reader: &mut dyn Read, _args: &HandlerArgs) -> HandlerResult {
+    json::verify(reader).map_err(decompressor_failure)?;
+    Ok(None)
+}
+
+/// Handler: Walk the frames of an MPEG audio stream (eg. MP3), checking that none are malformed
+///
+/// (MPEG audio has no checksums worth speaking of, so this can only catch corruption which damages
+/// the frame headers, or truncation. Free-format streams are reported as unsupported.)
+pub fn mp3(path: &Path, args: &HandlerArgs) -> HandlerResult {
+    open_for_stream(path, args, mp3_stream)
+}
+
+/// Stream-based counterpart to [`mp3`]
+pub fn mp3_stream(reader: &mut dyn Read, _args: &HandlerArgs) -> HandlerResult {
+    mp3::verify(reader).map_err(decompressor_failure)?;
+    Ok(None)
+}
+
+/// Handler: Walk the box structure of an MP4 or QuickTime file and check its sample tables
+///
+/// (Catches truncation, like an `mdat` box which runs past the end of the file, and damage to the
+/// box headers or the `moov` box. Fragmented files are reported as unsupported.)
+pub fn mp4(path: &Path, _args: &HandlerArgs) -> HandlerResult {
+    let file = File::open(path).map_err(|err| FailureType::IoError(err.to_string()))?;
+    let file_len = file.metadata().map_err(|err| FailureType::IoError(err.to_string()))?.len();
+    mp4::verify(BufReader::new(file), file_len).map_err(decompressor_failure)?;
+    Ok(None)
+}
+
+/// Handler: Walk the pages of an Ogg file, verifying their CRCs and that no stream is missing any
+///
+/// (The packets aren't decoded, so this works the same for any codec in an Ogg container, but
+/// corruption which happened before the file was muxed can't be detected.)
+pub fn ogg(path: &Path, args: &HandlerArgs) -> HandlerResult {
+    open_for_stream(path, args, ogg_stream)
+}
+
+/// Stream-based counterpart to [`ogg`]
+pub fn ogg_stream(reader: &mut dyn Read, _args: &HandlerArgs) -> HandlerResult {
+    ogg::verify(reader).map_err(decompressor_failure)?;
+    Ok(None)
+}
+
+/// Handler: Walk the pages of an Ogg Opus file like [`ogg`], then check its Opus headers and
+/// granule positions
+///
+/// (Every stream needs a valid `OpusHead` and `OpusTags` packet, which a mis-muxed file can lack
+/// even though all of its page CRCs pass, and must not end before its pre-skip has been played.
+/// Chained files are accepted, but the audio packets aren't decoded.)
+pub fn opus(path: &Path, args: &HandlerArgs) -> HandlerResult {
+    open_for_stream(path, args, opus_stream)
+}
+
+/// Stream-based counterpart to [`opus`]
+pub fn opus_stream(reader: &mut dyn Read, _args: &HandlerArgs) -> HandlerResult {
+    let summary = opus::verify(reader).map_err(decompressor_failure)?;
+    debug!("Checked {} Opus stream(s) holding {:.2} second(s) of audio", summary.streams,
+        summary.samples as f64 / opus::GRANULE_RATE as f64);
+    Ok(None)
+}
+
+/// Handler: Check a Zip file's CRCs like [`zip`], then the packaging rules of Word, Excel, and
+/// PowerPoint files
+///
+/// (`[Content_Types].xml`, the relationship parts, and the main part must be well-formed XML, and
+/// every relationship must point to a part which exists. OLE2 files, like `.doc` files renamed to
+/// `.docx`, are reported as unsupported rather than as broken Zip files.)
+pub fn ooxml(path: &Path, _args: &HandlerArgs) -> HandlerResult {
+    let file = File::open(path).map_err(|err| FailureType::IoError(err.to_string()))?;
+    ooxml::check_container(file).map_err(decompressor_failure)?;
+    let mut zip = verify_zip(path, false)?;
+    ooxml::verify(&mut zip).map_err(decompressor_failure)?;
+    Ok(None)
+}
+
+/// Handler: Check a Zip file's CRCs like [`zip`], then the rules OpenDocument adds on top of Zip
+///
+/// (The `mimetype` entry must come first, uncompressed, the manifest, `content.xml`, and
+/// `styles.xml` must be well-formed XML, and everything the manifest lists must be present.
+/// Password-protected documents are reported as unsupported, since their parts can't be parsed.)
+pub fn opendocument(path: &Path, _args: &HandlerArgs) -> HandlerResult {
+    let mut zip = verify_zip(path, false)?;
+    opendocument::verify(&mut zip).map_err(decompressor_failure)?;
+    Ok(None)
+}
+
+/// Handler: Check that a PE image's headers are sane and all of its sections are present
+///
+/// (The `CheckSum` field is checked too when it's set and nothing has been appended to the image,
+/// but passing doesn't mean it was, so this only claims well-formedness. See [`pe_checksum`].)
+pub fn pe(path: &Path, _args: &HandlerArgs) -> HandlerResult {
+    let file = File::open(path).map_err(|err| FailureType::IoError(err.to_string()))?;
+    let file_len = file.metadata().map_err(|err| FailureType::IoError(err.to_string()))?.len();
+    pe::verify(BufReader::new(file), file_len, false).map_err(decompressor_failure)?;
+    Ok(None)
+}
+
+/// Handler: Check a PE image like [`pe`], but require that its `CheckSum` field is set and covers
+/// the whole file
+///
+/// (Most images don't have a checksum, so this reports them as unsupported and should be
+/// followed by [`pe`] in a fallback chain.)
+pub fn pe_checksum(path: &Path, _args: &HandlerArgs) -> HandlerResult {
+    let file = File::open(path).map_err(|err| FailureType::IoError(err.to_string()))?;
+    let file_len = file.metadata().map_err(|err| FailureType::IoError(err.to_string()))?.len();
+    pe::verify(BufReader::new(file), file_len, true).map_err(decompressor_failure)?;
+    Ok(None)
 }
 
-/// Handler: Use the `toml` crate to do a basic well-formedness check
+/// Handler: Parse an Apple property list in either its binary or its XML form
+///
+/// (Binary property lists have every object in their offset table decoded and their references
+/// checked, while XML ones get a well-formedness check plus a check that every element holds a
+/// valid value. Old-style OpenStep text property lists are reported as unsupported.)
+pub fn plist(path: &Path, args: &HandlerArgs) -> HandlerResult {
+    open_for_stream(path, args, plist_stream)
+}
+
+/// Stream-based counterpart to [`plist`]
+pub fn plist_stream(reader: &mut dyn Read, _args: &HandlerArgs) -> HandlerResult {
+    let summary = plist::verify(reader).map_err(decompressor_failure)?;
+    debug!("Parsed {} property list with {} object(s)",
+        if summary.binary { "a binary" } else { "an XML" }, summary.objects);
+    Ok(None)
+}
+
+/// Handler: Walk the chunks of a PNG file, verifying all of their CRCs without decoding the image
+///
+/// (This is cheaper than the `image` handler and also covers ancillary chunks which decoders
+/// skip, but corruption which happened before the file was written can't be detected.)
+pub fn png(path: &Path, args: &HandlerArgs) -> HandlerResult {
+    open_for_stream(path, args, png_stream)
+}
+
+/// Stream-based counterpart to [`png`]
+pub fn png_stream(reader: &mut dyn Read, _args: &HandlerArgs) -> HandlerResult {
+    png::verify(reader).map_err(decompressor_failure)?;
+    Ok(None)
+}
+
+/// Handler: Walk the sections of an Adobe Photoshop document (PSD or PSB)
+///
+/// (The header's fields are checked against the specification's limits, every section's length
+/// must fit in the file, and the image resource blocks are parsed. When the merged image is
+/// uncompressed or RLE-compressed, its size is checked too, but nothing gets decoded.)
+pub fn psd(path: &Path, _args: &HandlerArgs) -> HandlerResult {
+    let file = File::open(path).map_err(|err| FailureType::IoError(err.to_string()))?;
+    let file_len = file.metadata().map_err(|err| FailureType::IoError(err.to_string()))?.len();
+    let summary = psd::verify(BufReader::new(file), file_len).map_err(decompressor_failure)?;
+    debug!("Checked a {}x{} {} file with {} channel(s) and {} image resource(s)", summary.width,
+        summary.height, if summary.large { "PSB" } else { "PSD" }, summary.channels,
+        summary.resources);
+    Ok(None)
+}
+
+/// Handler: Walk the headers of a RAR archive, verifying their CRCs and that none are truncated
+///
+/// (The file data isn't decompressed, so archives with intact headers are reported as unsupported
+/// to let the fallback chain hand them to `unrar` for a full check. Only the volume given is
+/// checked when the archive is split into several.)
+pub fn rar(path: &Path, _args: &HandlerArgs) -> HandlerResult {
+    let file = File::open(path).map_err(|err| FailureType::IoError(err.to_string()))?;
+    let file_len = file.metadata().map_err(|err| FailureType::IoError(err.to_string()))?.len();
+    rar::verify(BufReader::new(file), file_len).map_err(decompressor_failure)?;
+    Ok(None)
+}
+
+/// Handler: Walk the chunks of a RIFF file (WAV, AVI, WebP, etc.), checking that they nest properly
+///
+/// (RIFF has no checksums, so this only catches truncation and damaged chunk headers, plus
+/// `WAVE` files whose `data` chunk doesn't agree with their `fmt ` chunk.)
+pub fn riff(path: &Path, args: &HandlerArgs) -> HandlerResult {
+    open_for_stream(path, args, riff_stream)
+}
+
+/// Stream-based counterpart to [`riff`]
+pub fn riff_stream(reader: &mut dyn Read, _args: &HandlerArgs) -> HandlerResult {
+    riff::verify(reader).map_err(decompressor_failure)?;
+    Ok(None)
+}
+
+/// Handler: Walk an RPM package's headers, verify the digests they record for the header and
+/// payload, and decompress the payload
+///
+/// (Older packages only have an MD5 digest, and packages whose payload can't be decompressed here
+/// are only checked against their digests. GPG signatures are parsed as part of the signature
+/// header, but not verified, since that needs the packager's public key.)
+pub fn rpm(path: &Path, args: &HandlerArgs) -> HandlerResult {
+    open_for_stream(path, args, rpm_stream)
+}
+
+/// Stream-based counterpart to [`rpm`]
+pub fn rpm_stream(reader: &mut dyn Read, _args: &HandlerArgs) -> HandlerResult {
+    let summary = rpm::verify(reader).map_err(decompressor_failure)?;
+    match summary.compressor {
+        Some(format) => debug!("Decompressed {}-compressed RPM payload", format),
+        None => info!("RPM payload uses a compression format which isn't supported here, so it \
+            was only checked against its digests"),
+    }
+    if summary.digests.is_empty() {
+        info!("RPM package has no digests, so only its structure and compression were checked");
+    } else {
+        debug!("Verified RPM digests: {}", summary.digests.join(", "));
+    }
+    Ok(None)
+}
+
+/// Handler: Decompress every folder in a `.7z` archive, verifying the CRCs of the files within
+///
+/// Codecs other than Copy, LZMA, LZMA2, and Deflate (including encryption) are reported as
+/// unsupported so the fallback chain can hand the file to an external tool.
+pub fn sevenz(path: &Path, _args: &HandlerArgs) -> HandlerResult {
+    let file = File::open(path).map_err(|err| FailureType::IoError(err.to_string()))?;
+    sevenz::verify(file).map_err(decompressor_failure)?;
+    Ok(None)
+}
+
+/// Handler: Check an SQLite 3 database's header and length and, optionally, its page structure
+///
+/// Setting the `integrity_check` handler argument to `"true"` walks every b-tree, overflow chain,
+/// and freelist page, which covers the structural part of `PRAGMA integrity_check`. (SQLite itself
+/// isn't linked in, so indexes aren't compared against their tables.)
+///
+/// If a non-empty `-wal` or `-journal` file sits next to the database, only the header is
+/// checked, since committed or rolled-back changes may not have reached the main file yet.
+pub fn sqlite(path: &Path, args: &HandlerArgs) -> HandlerResult {
+    let full = bool_arg(args, "integrity_check")?;
+
+    let mut file = File::open(path).map_err(|err| FailureType::IoError(err.to_string()))?;
+    let file_len = file.metadata().map_err(|err| FailureType::IoError(err.to_string()))?.len();
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push("-wal");
+    let has_wal = fs::metadata(&sidecar).map_or(false, |meta| meta.len() > 0);
+    sidecar = path.as_os_str().to_owned();
+    sidecar.push("-journal");
+    if has_wal || fs::metadata(&sidecar).map_or(false, |meta| meta.len() > 0) {
+        info!("Only checking the header of {} because it has a WAL or rollback journal",
+              path.display());
+        sqlite::read_header(&mut file).map_err(decompressor_failure)?;
+        return Ok(None);
+    }
+    sqlite::verify(BufReader::new(file), file_len, full).map_err(decompressor_failure)?;
+    Ok(None)
+}
+
+/// Handler: Check that an SVG image is well-formed XML with an `<svg>` root element in the SVG
+/// namespace
+///
+/// (Gzip-compressed `.svgz` files are detected by their header and decompressed on the fly, with
+/// the gzip layer checked too. External entities and DTDs are never fetched.)
+pub fn svg(path: &Path, args: &HandlerArgs) -> HandlerResult {
+    open_for_stream(path, args, svg_stream)
+}
+
+/// Stream-based counterpart to [`svg`]
+pub fn svg_stream(reader: &mut dyn Read, _args: &HandlerArgs) -> HandlerResult {
+    svg::verify(reader).map_err(decompressor_failure)?;
+    Ok(None)
+}
+
+/// Handler: Walk a tar archive's headers, checking their checksums and that it isn't truncated
+///
+/// (Tar doesn't checksum the data itself, so this can only catch damage to the headers and the
+/// archive's structure.)
+pub fn tar(path: &Path, args: &HandlerArgs) -> HandlerResult {
+    open_for_stream(path, args, tar_stream)
+}
+
+/// Stream-based counterpart to [`tar`]
+pub fn tar_stream(reader: &mut dyn Read, _args: &HandlerArgs) -> HandlerResult {
+    let entries = tar::verify(reader).map_err(decompressor_failure)?;
+    debug!("Walked {} tar entries", entries);
+    Ok(None)
+}
+
+/// Handler: Decompress a compressed tar archive, verifying the compression layer's checksums and
+/// walking the tar headers in a single pass
+///
+/// The compression format (gzip, Zstandard, xz, or bzip2) is detected from the file's header, and
+/// anything else is reported as unsupported. Failures in the compression layer are labelled as
+/// such, so a truncated compressed stream can be told apart from a complete one which holds a
+/// truncated archive.
+pub fn tar_compressed(path: &Path, args: &HandlerArgs) -> HandlerResult {
+    open_for_stream(path, args, tar_compressed_stream)
+}
+
+/// Stream-based counterpart to [`tar_compressed`]
+pub fn tar_compressed_stream(reader: &mut dyn Read, _args: &HandlerArgs)
+        -> HandlerResult {
+    let mut magic = Vec::with_capacity(6);
+    reader.take(6).read_to_end(&mut magic).map_err(|e| FailureType::IoError(e.to_string()))?;
+    let input = BufReader::new(io::Cursor::new(magic.clone()).chain(reader));
+
+    let (format, inner): (_, Box<dyn Read + '_>) = if magic.starts_with(&[0x1F, 0x8B]) {
+        ("gzip", Box::new(GzipDecoder::new(input)))
+    } else if magic.starts_with(&[0x28, 0xB5, 0x2F, 0xFD]) {
+        ("Zstandard", Box::new(ZstdDecoder::new(input)))
+    } else if magic.starts_with(&[0xFD, b'7', b'z', b'X', b'Z', 0x00]) {
+        ("xz", Box::new(XzDecoder::new(input)))
+    } else if magic.starts_with(b"BZh") {
+        ("bzip2", Box::new(Bzip2Decoder::new(input)))
+    } else {
+        return Err(FailureType::UnsupportedFormat(
+            "Not compressed with gzip, Zstandard, xz, or bzip2".to_owned()));
+    };
+    let mut decoder = tar::Labelled::new(inner, format);
+
+    // Damage to the compression layer would explain any damage to the archive inside, so its
+    // verdict takes precedence
+    let walked = tar::verify(&mut decoder);
+    if !decoder.failed() {
+        exhaust_reader(&mut decoder).map_err(decompressor_failure)?;
+    }
+    let entries = walked.map_err(decompressor_failure)?;
+    debug!("Walked {} tar entries ({} compressed)", entries, format);
+    Ok(None)
+}
+
+/// Handler: Check that a plaintext file is validly encoded and free of spliced-in binary data
+///
+/// (The text is streamed, so there's no limit on file size. Apart from UTF-8, UTF-16 and UTF-32
+/// are accepted if they start with a BOM or an ASCII character.)
+pub fn text(path: &Path, args: &HandlerArgs) -> HandlerResult {
+    open_for_stream(path, args, text_stream)
+}
+
+/// Stream-based counterpart to [`text`]
+pub fn text_stream(reader: &mut dyn Read, _args: &HandlerArgs) -> HandlerResult {
+    text::verify(reader).map_err(decompressor_failure)?;
+    Ok(None)
+}
+
+/// Handler: Parse a whole TOML document with `toml_edit` to do a basic well-formedness check
+///
+/// (Document-level problems like duplicate keys are caught too, and errors give the line and
+/// column. Files over 64 MiB are reported as unsupported rather than read into memory.)
 ///
 /// **TODO:** Decide on an API and some real-world test data to allow detecting potential
 /// corruption in string variables using the UTF-8 subset of the plaintext handler's checks.
-pub fn toml(path: &Path) -> Result<(), FailureType> {
-    #[allow(clippy::wildcard_enum_match_arm)]
-    let raw_data = fs::read_to_string(path).map_err(|err| match err.kind() {
-        // If we can't String it, then report a validation error because JSON must be UTF-8
-        io::ErrorKind::InvalidData => FailureType::InvalidContent(err.to_string()),
-        // ...otherwise, report an OS-level error.
-        _ => FailureType::IoError(err.to_string()),
-    })?;
+pub fn toml(path: &Path, args: &HandlerArgs) -> HandlerResult {
+    open_for_stream(path, args, toml_stream)
+}
+
+/// Stream-based counterpart to [`toml`]
+pub fn toml_stream(reader: &mut dyn Read, _args: &HandlerArgs) -> HandlerResult {
+    toml::verify(reader).map_err(decompressor_failure)?;
+    Ok(None)
+}
+
+/// Handler: Validate a WebAssembly module, type-checking its function bodies and constant
+/// expressions
+///
+/// (WebAssembly has no checksums, so this catches corruption by checking everything a validator
+/// would. Components and modules which rely on proposals it doesn't know, like exception
+/// handling or GC, are reported as unsupported.)
+pub fn wasm(path: &Path, args: &HandlerArgs) -> HandlerResult {
+    open_for_stream(path, args, wasm_stream)
+}
+
+/// Stream-based counterpart to [`wasm`]
+pub fn wasm_stream(reader: &mut dyn Read, _args: &HandlerArgs) -> HandlerResult {
+    wasm::verify(reader).map_err(decompressor_failure)?;
+    Ok(None)
+}
+
+/// Handler: Walk the chunks of a WebP image, checking their order and the image dimensions they
+/// declare, and decode the entropy coding of any lossless bitstreams
+///
+/// (WebP has no checksums. Lossy bitstreams only have their frame headers checked, so damage to
+/// their compressed data will usually go unnoticed.)
+pub fn webp(path: &Path, args: &HandlerArgs) -> HandlerResult {
+    open_for_stream(path, args, webp_stream)
+}
+
+/// Stream-based counterpart to [`webp`]
+pub fn webp_stream(reader: &mut dyn Read, _args: &HandlerArgs) -> HandlerResult {
+    let summary = webp::verify(reader).map_err(decompressor_failure)?;
+    debug!("Walked a {}x{} WebP image with {} frame(s) and {} lossless bitstream(s)",
+        summary.width, summary.height, summary.frames, summary.lossless);
+    Ok(None)
+}
+
+/// Handler: Decompress a WOFF or WOFF2 web font and check its tables
+///
+/// (WOFF keeps the checksum of each table, but WOFF2 doesn't, so the latter is only checked for
+/// decompressing cleanly and having tables which agree with each other.)
+pub fn woff(path: &Path, _args: &HandlerArgs) -> HandlerResult {
+    let data = fs::read(path).map_err(|err| FailureType::IoError(err.to_string()))?;
+    woff::verify(&data).map_err(decompressor_failure)?;
+    Ok(None)
+}
+
+/// Handler: Parse an XML document to do a basic well-formedness check
+///
+/// (The document is streamed, so there's no limit on file size, and entities are never expanded.
+/// External DTDs aren't fetched, so references to entities they declare are taken on faith.)
+pub fn xml(path: &Path, args: &HandlerArgs) -> HandlerResult {
+    open_for_stream(path, args, xml_stream)
+}
 
+/// Stream-based counterpart to [`xml`]
+pub fn xml_stream(reader: &mut dyn Read, _args: &HandlerArgs) -> HandlerResult {
+    xml::verify(reader).map_err(decompressor_failure)?;
+    Ok(None)
+}
+
+/// Handler: Decompress a file made of one or more `.xz` streams, verifying every block's check
+///
+/// Filters other than LZMA2 and check types other than CRC32, CRC64, and SHA-256 are reported as
+/// unsupported so the fallback chain can hand the file to an external tool.
+pub fn xz(path: &Path, args: &HandlerArgs) -> HandlerResult {
+    open_for_stream(path, args, xz_stream)
+}
+
+/// Stream-based counterpart to [`xz`]
+pub fn xz_stream(reader: &mut dyn Read, _args: &HandlerArgs) -> HandlerResult {
+    exhaust_reader(XzDecoder::new(BufReader::new(reader))).map_err(decompressor_failure)?;
+    Ok(None)
+}
+
+/// Handler: Parse every document in a YAML stream to do a basic well-formedness check
+///
+/// (Aliases are counted rather than expanded, so a file which would expand to an unreasonable
+/// size when loaded is reported as an internal error instead of being passed or failed.)
+pub fn yaml(path: &Path, args: &HandlerArgs) -> HandlerResult {
+    open_for_stream(path, args, yaml_stream)
+}
+
+/// Stream-based counterpart to [`yaml`]
+pub fn yaml_stream(reader: &mut dyn Read, _args: &HandlerArgs) -> HandlerResult {
     // TODO: See if there's a Read-based API that could be used to reduce the memory footprint
-    raw_data
-        .parse::<toml_edit::Item>()
-        .map_err(|err| FailureType::InvalidContent(err.to_string()))?;
-    Ok(())
+    let raw_data = yaml::read_limited(reader)?;
+    yaml::verify(&raw_data)?;
+    Ok(None)
 }
 
-/// Handler: Use the `zip` crate to validate Zip files which use STORE or DEFLATE compression
+/// Handler: Decompress a file made of one or more Zstandard frames, verifying their checksums
 ///
-/// **TODO:** Decide on the best API for selecting whether this should operate recursively to
-/// validate files that it must extract anyway to check their CRCs.
+/// Frames without a content checksum can only be checked for being decodable, so a file with any
+/// passes as [`WellFormed`](Confidence::WellFormed) rather than
+/// [`DataHash`](Confidence::DataHash). Frames which need a dictionary are reported as unsupported.
+pub fn zstd(path: &Path, args: &HandlerArgs) -> HandlerResult {
+    open_for_stream(path, args, zstd_stream)
+}
+
+/// Stream-based counterpart to [`zstd`]
+pub fn zstd_stream(reader: &mut dyn Read, _args: &HandlerArgs) -> HandlerResult {
+    let mut decoder = ZstdDecoder::new(BufReader::new(reader));
+    exhaust_reader(&mut decoder).map_err(decompressor_failure)?;
+    if decoder.unchecked_frames() > 0 {
+        info!("{} Zstandard frame(s) had no content checksum, so only their structure was checked",
+              decoder.unchecked_frames());
+        return Ok(Some(Confidence::WellFormed));
+    }
+    Ok(None)
+}
+
+/// Reclassify the errors the `zip` crate reports while reading a member, which arrive as generic
+/// I/O errors even when they mean the member's data is corrupt
+fn zip_member_error(err: io::Error) -> io::Error {
+    #[allow(clippy::wildcard_enum_match_arm)]
+    let is_corrupt = match err.kind() {
+        // flate2's "corrupt deflate stream"
+        io::ErrorKind::InvalidInput => true,
+        // A CRC mismatch, or flate2 rejecting the Deflate data
+        io::ErrorKind::Other => err.to_string() == "Invalid checksum"
+            || err.get_ref().map_or(false, |inner| inner.is::<DecompressError>()),
+        _ => false,
+    };
+    if is_corrupt { corrupt(&err.to_string()) } else { err }
+}
+
+/// Helper to open a Zip file and verify the CRCs of all its members, returning it so handlers for
+/// Zip-based formats can go on to check their own rules
 ///
-/// (As a means to detect corruption that occurred before the archive was generated.)
-pub fn zip(path: &Path) -> Result<(), FailureType> {
+/// (Zip64 records are checked against each other and the file size first. If they look sound but
+/// the `zip` crate still rejects the archive, that's reported as unsupported rather than corrupt,
+/// so a more capable external tool can take over.)
+///
+/// Encrypted entries are skipped so the rest can still be checked, and then reported as
+/// unsupported, or as invalid content if `reject_encrypted` is set.
+fn verify_zip(path: &Path, reject_encrypted: bool) -> Result<ZipArchive<File>, FailureType> {
     /// Helper for `?` use pending the availability of `try` blocks in stable channel
-    fn zip_inner(reader: &File) -> ZipResult<()> {
+    fn zip_inner(reader: File) -> ZipResult<(ZipArchive<File>, EncryptedEntries)> {
         let mut zip = ZipArchive::new(reader)?;
+        let mut encrypted = EncryptedEntries::default();
         for i in 0..zip.len() {
-            exhaust_reader(zip.by_index(i)?)?; // Trigger CRC32 validation
+            // Trigger CRC32 validation
+            match zip.by_index(i)
+                    .and_then(|entry| Ok(exhaust_reader(entry).map_err(zip_member_error)?)) {
+                Ok(()) => {},
+                Err(ZipError::UnsupportedArchive(msg)) if msg == ZipError::PASSWORD_REQUIRED => {
+                    encrypted.add(zip.by_index_raw(i)?.extra_data());
+                },
+                Err(err) => return Err(err),
+            }
         }
-        Ok(())
+        Ok((zip, encrypted))
     }
 
-    let reader = File::open(path).map_err(|e| FailureType::IoError(e.to_string()))?;
-    zip_inner(&reader).map_err(|err| match err {
-        ZipError::Io(e) => FailureType::IoError(e.to_string()),
+    let mut reader = File::open(path).map_err(|e| FailureType::IoError(e.to_string()))?;
+    let file_len = reader.metadata().map_err(|e| FailureType::IoError(e.to_string()))?.len();
+    let directory = zip_structure::locate_central_directory(&mut reader, file_len)
+        .map_err(decompressor_failure)?;
+    if let Some(ref directory) = directory {
+        zip_structure::check_local_headers(&mut reader, directory).map_err(decompressor_failure)?;
+    }
+    let is_zip64 = directory.map_or(false, |x| x.is_zip64);
+    let (zip, encrypted) = zip_inner(reader).map_err(|err| match err {
+        ZipError::Io(e) => decompressor_failure(e),
+        ZipError::InvalidArchive(e) if is_zip64 => FailureType::UnsupportedFormat(format!(
+            "The zip crate couldn't read this Zip64 archive: {}", e)),
         ZipError::InvalidArchive(e) => FailureType::InvalidContent(e.to_string()),
         ZipError::UnsupportedArchive(e) => FailureType::UnsupportedFormat(e.to_string()),
         ZipError::FileNotFound => FailureType::InternalError(
             "'file not found' when reading Zip file by bounded index".to_string(),
         ),
     })?;
-    Ok(())
+
+    if encrypted.total() == 0 {
+        Ok(zip)
+    } else if reject_encrypted {
+        Err(FailureType::InvalidContent(format!("{} (and reject_encrypted is set)",
+                                                encrypted.describe(zip.len()))))
+    } else {
+        Err(FailureType::UnsupportedFormat(encrypted.describe(zip.len())))
+    }
+}
+
+/// Handler: Use the `zip` crate to validate Zip files which use STORE or DEFLATE compression
+///
+/// **TODO:** Decide on the best API for selecting whether this should operate recursively to
+/// validate files that it must extract anyway to check their CRCs.
+///
+/// (As a means to detect corruption that occurred before the archive was generated.)
+///
+/// Before the CRCs are checked, each central directory record is cross-checked against the local
+/// file header it points to (and any data descriptor), since those disagreeing is a strong sign of
+/// a damaged archive even when the CRCs still pass.
+///
+/// Encrypted entries can't be checked without the password, so an archive with any is reported as
+/// unsupported once the rest have been checked, letting an external tool which can be given the
+/// password take over. Setting the `reject_encrypted` handler argument to `"true"` makes them a
+/// failure instead.
+pub fn zip(path: &Path, args: &HandlerArgs) -> HandlerResult {
+    verify_zip(path, bool_arg(args, "reject_encrypted")?)?;
+    Ok(None)
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::config::NO_HANDLER_ARGS;
+    use crate::tempdir::TempDir;
+
+    /// Check that `handler` reports `path` as corrupt rather than unreadable
+    fn assert_invalid(handler: HandlerFn, path: &Path) {
+        match handler(path, &NO_HANDLER_ARGS) {
+            Err(FailureType::InvalidContent(_)) => {},
+            other => panic!("{}: expected InvalidContent, got {:?}", path.display(), other),
+        }
+    }
+
+    #[test]
+    fn test_zip_member_corruption() {
+        let good = fs::read("../test_data/good/testfile.zip").unwrap();
+        assert!(zip(Path::new("../test_data/good/testfile.zip"), &NO_HANDLER_ARGS).is_ok());
+
+        // Flip a byte of the (stored) first member's data, leaving every header intact
+        let name_len = usize::from(u16::from_le_bytes([good[26], good[27]]));
+        let extra_len = usize::from(u16::from_le_bytes([good[28], good[29]]));
+        let mut bad = good.clone();
+        bad[30 + name_len + extra_len] ^= 0x01;
+        let tempdir = TempDir::new().unwrap();
+        let path = tempdir.path().join("flipped.zip");
+        fs::write(&path, bad).unwrap();
+        assert_invalid(zip, &path);
+
+        // Damaged Deflate data and CRC mismatches in the formats built on Zip
+        assert_invalid(epub, Path::new("../test_data/bad/testfile.epub"));
+        assert_invalid(ooxml, Path::new("../test_data/bad/testfile.docx"));
+        assert_invalid(ooxml, Path::new("../test_data/bad/testfile.xlsx"));
+        assert_invalid(opendocument, Path::new("../test_data/bad/testfile.odt"));
+        assert_invalid(zip, Path::new("../test_data/bad/testfile.cbz"));
+    }
+
+    #[test]
+    fn test_zstd_confidence() {
+        let checked = fs::read("../test_data/good/testfile.txt.zst").unwrap();
+        assert_eq!(zstd_stream(&mut &checked[..], &NO_HANDLER_ARGS).unwrap(), None);
+
+        // A frame holding "Hello" in one raw block, without a content checksum
+        let unchecked = b"\x28\xB5\x2F\xFD\x20\x05\x29\x00\x00Hello";
+        assert_eq!(zstd_stream(&mut &unchecked[..], &NO_HANDLER_ARGS).unwrap(),
+                   Some(Confidence::WellFormed));
+    }
 }
@@ -0,0 +1,290 @@
+//! A walker for raw AAC audio in ADTS (Audio Data Transport Stream) framing (`.aac`)
+//!
+//! (Like MPEG audio, ADTS has no checksum covering the whole file, so this checks that skipping
+//! from frame to frame by their declared lengths always lands on another valid frame header with
+//! the same stream parameters. Frames can carry a CRC, but when a frame holds a single raw data
+//! block, that CRC also covers the start of each syntactic element inside it, which can't be found
+//! without decoding the audio, so only the header CRCs of frames with several blocks are checked.)
+
+// Standard library imports
+use std::io::{self, Read};
+
+// Local Imports
+use super::checksums::Crc16;
+use super::mp3::{acceptable_tail, at_acceptable_end, skip_ape, skip_id3v2, Lookahead};
+use super::{corrupt, unsupported};
+
+/// The length of an ADTS frame header, not counting the CRC or raw data block positions
+const HEADER_LEN: usize = 7;
+
+/// How many samples each raw data block decodes to, per channel
+pub const BLOCK_SAMPLES: u64 = 1024;
+
+/// Sample rates in Hz, indexed by the sampling frequency index
+///
+/// (Indexes 13 and 14 are reserved, and 15, an explicit rate, can't be expressed in ADTS.)
+const SAMPLE_RATES: [u32; 13] =
+    [96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350];
+
+/// The fields of a frame header which must stay the same throughout the stream
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct StreamKind {
+    /// Whether the MPEG version bit says MPEG-2, rather than MPEG-4
+    mpeg2: bool,
+    /// The profile (the MPEG-4 audio object type, minus one)
+    profile: u8,
+    /// The sampling frequency index
+    sample_rate_idx: u8,
+    /// The channel configuration (with 0 meaning that it's given in the audio data instead)
+    channels: u8,
+}
+
+/// The parts of a frame header which are needed to check and skip the frame
+#[derive(Debug, PartialEq, Eq)]
+struct Frame {
+    /// The stream parameters
+    kind: StreamKind,
+    /// The length of the frame, including its header
+    len: usize,
+    /// Whether there's a CRC after the header
+    protected: bool,
+    /// How many raw data blocks the frame holds
+    blocks: usize,
+}
+
+/// What was found in an ADTS stream
+#[derive(Debug, Default)]
+pub struct Summary {
+    /// How many frames there were
+    pub frames: u64,
+    /// How many raw data blocks those frames held
+    pub blocks: u64,
+    /// The sample rate, in Hz
+    pub sample_rate: u32,
+    /// How many header CRCs were verified
+    pub crcs: u64,
+}
+
+/// Parse a frame header
+///
+/// Returns `None` if `bytes` doesn't start with a valid header.
+fn frame_header(bytes: &[u8]) -> Option<Frame> {
+    let header = match bytes {
+        [a, b, c, d, e, f, g, ..] => u64::from_be_bytes([0, *a, *b, *c, *d, *e, *f, *g]),
+        _ => return None,
+    };
+    let field = |shift: u32, bits: u32| ((header >> shift) & ((1 << bits) - 1)) as u8;
+    let kind = StreamKind {
+        mpeg2: field(43, 1) == 1,
+        profile: field(38, 2),
+        sample_rate_idx: field(34, 4),
+        channels: field(30, 3),
+    };
+    let len = ((header >> 13) & 0x1FFF) as usize;
+    if header >> 44 != 0xFFF || field(41, 2) != 0
+            || usize::from(kind.sample_rate_idx) >= SAMPLE_RATES.len() {
+        return None;
+    }
+    Some(Frame { kind, len, protected: field(40, 1) == 0, blocks: usize::from(field(0, 2)) + 1 })
+}
+
+/// Describe the first field which differs between `kind` and `expected`
+fn changed_field(kind: StreamKind, expected: StreamKind) -> &'static str {
+    if kind.mpeg2 != expected.mpeg2 {
+        "MPEG version"
+    } else if kind.profile != expected.profile {
+        "profile"
+    } else if kind.sample_rate_idx != expected.sample_rate_idx {
+        "sample rate"
+    } else {
+        "channel configuration"
+    }
+}
+
+/// Check the header CRC of a frame with several raw data blocks, where it covers the header and
+/// the positions of the blocks after the first
+fn check_header_crc(frame: &[u8], blocks: usize, pos: u64) -> io::Result<()> {
+    let covered = HEADER_LEN + 2 * (blocks - 1);
+    let stored = u16::from_be_bytes([frame[covered], frame[covered + 1]]);
+    let mut crc = Crc16::new();
+    crc.update(&frame[..covered]);
+    if crc.finish() != stored {
+        return Err(corrupt(&format!("ADTS frame header CRC mismatch at byte {}", pos)));
+    }
+    Ok(())
+}
+
+/// Check the structure of the ADTS stream read from `input`
+///
+/// As with [`mp3::verify`](super::mp3::verify), ID3v2 tags at the start and APEv2 tags with
+/// headers are skipped, and an ID3v1 tag plus a little junk is tolerated at the end. LATM/LOAS
+/// and ADIF streams are reported as unsupported.
+pub fn verify(input: impl Read) -> io::Result<Summary> {
+    let mut input = Lookahead::new(input);
+    skip_id3v2(&mut input)?;
+    match input.peek(4)? {
+        [0x56, second, ..] if second & 0xE0 == 0xE0 => {
+            return Err(unsupported("LATM/LOAS AAC streams aren't supported"));
+        },
+        b"ADIF" => return Err(unsupported("ADIF AAC files aren't supported")),
+        _ => {},
+    }
+
+    let (mut kind, mut summary) = (None, Summary::default());
+    loop {
+        let pos = input.pos();
+        if input.peek(1)?.is_empty() && summary.frames > 0 {
+            return Ok(summary);
+        }
+        if skip_ape(&mut input)? {
+            continue;
+        }
+
+        let frame = match frame_header(input.peek(HEADER_LEN)?) {
+            Some(frame) => frame,
+            None => {
+                if summary.frames > 0 && at_acceptable_end(&mut input)? {
+                    return Ok(summary);
+                }
+                return Err(corrupt(&if summary.frames == 0 {
+                    "No ADTS frame at the start of the stream".to_owned()
+                } else {
+                    format!("Lost ADTS frame sync at byte {}", pos)
+                }));
+            },
+        };
+        if let Some(expected) = kind.filter(|&expected| expected != frame.kind) {
+            return Err(corrupt(&format!("ADTS {} changes at byte {}",
+                                        changed_field(frame.kind, expected), pos)));
+        }
+        kind = Some(frame.kind);
+
+        let header_len = HEADER_LEN + if frame.protected { 2 * frame.blocks } else { 0 };
+        if frame.len <= header_len {
+            return Err(corrupt(&format!("ADTS frame at byte {} is too short for its header",
+                                        pos)));
+        }
+        let data = input.peek(frame.len)?;
+        if data.len() < frame.len {
+            if summary.frames > 0 && acceptable_tail(data) {
+                return Ok(summary);
+            }
+            return Err(corrupt("Last ADTS frame is truncated"));
+        }
+        if frame.protected && frame.blocks > 1 {
+            check_header_crc(data, frame.blocks, pos)?;
+            summary.crcs += 1;
+        }
+        input.skip(frame.len as u64)?;
+        summary.frames += 1;
+        summary.blocks += frame.blocks as u64;
+        summary.sample_rate = SAMPLE_RATES[usize::from(frame.kind.sample_rate_idx)];
+    }
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build an MPEG-4 AAC-LC frame at 44.1 kHz, filled out to `len` bytes, with a valid header
+    /// CRC if it's `protected` and has several blocks
+    fn frame(channels: u8, blocks: usize, protected: bool, len: usize) -> Vec<u8> {
+        let header = 0xFFF << 44 | u64::from(!protected) << 40 | 1 << 38 | 4 << 34
+            | u64::from(channels) << 30 | (len as u64) << 13 | 0x7FF << 2 | (blocks as u64 - 1);
+        let mut frame = header.to_be_bytes()[1..].to_vec();
+        frame.resize(len, 0x55);
+        if protected && blocks > 1 {
+            let covered = HEADER_LEN + 2 * (blocks - 1);
+            let mut crc = Crc16::new();
+            crc.update(&frame[..covered]);
+            frame[covered..covered + 2].copy_from_slice(&crc.finish().to_be_bytes());
+        }
+        frame
+    }
+
+    /// Verify `input`, returning the error message on failure
+    fn verify_bytes(input: &[u8]) -> Result<Summary, String> {
+        verify(input).map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn test_frame_header() {
+        let good = frame(2, 3, true, 200);
+        let kind = StreamKind { mpeg2: false, profile: 1, sample_rate_idx: 4, channels: 2 };
+        assert_eq!(frame_header(&good),
+                   Some(Frame { kind, len: 200, protected: true, blocks: 3 }));
+        assert_eq!(frame_header(&good[..6]), None);
+
+        let mut bad = good.clone();
+        bad[0] = 0x7F;
+        assert_eq!(frame_header(&bad), None);
+        bad = good.clone();
+        bad[1] |= 0x02;
+        assert_eq!(frame_header(&bad), None);
+        bad = good.clone();
+        bad[2] = (bad[2] & 0xC3) | 13 << 2;
+        assert_eq!(frame_header(&bad), None);
+    }
+
+    #[test]
+    fn test_stream_structure() {
+        let good = frame(1, 1, false, 200).repeat(4);
+        let summary = verify_bytes(&good).unwrap();
+        assert_eq!((summary.frames, summary.blocks, summary.sample_rate, summary.crcs),
+                   (4, 4, 44100, 0));
+
+        let mut tagged = vec![b'I', b'D', b'3', 4, 0, 0, 0, 0, 1, 0];
+        tagged.resize(138, 0);
+        tagged.extend(&good);
+        tagged.extend(b"TAG");
+        tagged.resize(tagged.len() + 125, b' ');
+        assert_eq!(verify_bytes(&tagged).unwrap().frames, 4);
+
+        assert_eq!(verify_bytes(&good[..700]).unwrap().frames, 3);
+        assert_eq!(verify_bytes(&good[..750]).unwrap_err(), "Last ADTS frame is truncated");
+
+        let mut lost_sync = good.clone();
+        lost_sync[400] = 0;
+        assert_eq!(verify_bytes(&lost_sync).unwrap_err(), "Lost ADTS frame sync at byte 400");
+
+        let mut mixed = good.clone();
+        mixed.extend(frame(2, 1, false, 200));
+        assert_eq!(verify_bytes(&mixed).unwrap_err(),
+                   "ADTS channel configuration changes at byte 800");
+
+        let mut short = good.clone();
+        short.extend(frame(1, 2, true, 11));
+        assert_eq!(verify_bytes(&short).unwrap_err(),
+                   "ADTS frame at byte 800 is too short for its header");
+
+        for bad in &[&b""[..], b"junk"] {
+            assert_eq!(verify_bytes(bad).unwrap_err(), "No ADTS frame at the start of the stream");
+        }
+        assert_eq!(verify(&tagged[..100]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        for unsupported in &[&[0x56, 0xE0, 0x20, 0x00][..], b"ADIF\0\0\0\0"] {
+            assert_eq!(verify(*unsupported).unwrap_err().kind(), io::ErrorKind::Unsupported);
+        }
+    }
+
+    #[test]
+    fn test_header_crc() {
+        let mut good = frame(2, 1, true, 200);
+        good.extend(frame(2, 3, true, 300).repeat(2));
+        let summary = verify_bytes(&good).unwrap();
+        assert_eq!((summary.frames, summary.blocks, summary.crcs), (3, 7, 2));
+
+        let mut bad = good.clone();
+        bad[500 + HEADER_LEN] ^= 0x01;
+        assert_eq!(verify_bytes(&bad).unwrap_err(), "ADTS frame header CRC mismatch at byte 500");
+    }
+
+    #[test]
+    fn test_fixtures() {
+        let summary = verify(&include_bytes!("../../../test_data/good/testfile.aac")[..]).unwrap();
+        assert_eq!((summary.frames, summary.blocks, summary.sample_rate), (44, 44, 44100));
+        assert_eq!(verify(&include_bytes!("../../../test_data/bad/testfile.aac")[..])
+                       .unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
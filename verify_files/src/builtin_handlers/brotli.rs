@@ -0,0 +1,860 @@
+//! A minimal Brotli decompressor, following RFC 7932
+//!
+//! (Brotli has no checksum, so this can only catch damage which breaks the structure of the
+//! stream, but nearly every bit of a compressed meta-block is part of some prefix code, so that's
+//! most of it.)
+
+// Standard library imports
+use std::convert::TryFrom;
+use std::io::{self, BufRead, Read};
+
+// Local Imports
+use super::bytes::low32;
+use super::{corrupt, unsupported};
+
+/// The static dictionary from Appendix A of RFC 7932
+static DICTIONARY: &[u8; 122_784] = include_bytes!("brotli_dictionary.bin");
+
+/// How many bits of a dictionary reference select the word, indexed by word length
+const DICTIONARY_BITS: [u32; 25] = [
+    0, 0, 0, 0, 10, 10, 11, 11, 10, 10, 10, 10, 10, 9, 9, 8, 7, 7, 8, 7, 7, 6, 6, 5, 5,
+];
+
+/// The longest a prefix code's codes may be
+const MAX_CODE_LENGTH: usize = 15;
+
+/// The order in which the code lengths of the code length code are stored
+const CODE_LENGTH_ORDER: [usize; 18] =
+    [1, 2, 3, 4, 0, 5, 17, 6, 16, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+
+/// The baseline and number of extra bits for each block count code
+const BLOCK_COUNT_CODES: [(u32, u32); 26] = [
+    (1, 2), (5, 2), (9, 2), (13, 2), (17, 3), (25, 3), (33, 3), (41, 3), (49, 4), (65, 4),
+    (81, 4), (97, 4), (113, 5), (145, 5), (177, 5), (209, 5), (241, 6), (305, 6), (369, 7),
+    (497, 8), (753, 9), (1265, 10), (2289, 11), (4337, 12), (8433, 13), (16625, 24),
+];
+
+/// The baseline and number of extra bits for each insert length code
+const INSERT_LENGTH_CODES: [(u32, u32); 24] = [
+    (0, 0), (1, 0), (2, 0), (3, 0), (4, 0), (5, 0), (6, 1), (8, 1), (10, 2), (14, 2), (18, 3),
+    (26, 3), (34, 4), (50, 4), (66, 5), (98, 5), (130, 6), (194, 7), (322, 8), (578, 9),
+    (1090, 10), (2114, 12), (6210, 14), (22594, 24),
+];
+
+/// The baseline and number of extra bits for each copy length code
+const COPY_LENGTH_CODES: [(u32, u32); 24] = [
+    (2, 0), (3, 0), (4, 0), (5, 0), (6, 0), (7, 0), (8, 0), (9, 0), (10, 1), (12, 1), (14, 2),
+    (18, 2), (22, 3), (30, 3), (38, 4), (54, 4), (70, 5), (102, 5), (134, 6), (198, 7),
+    (326, 8), (582, 9), (1094, 10), (2118, 24),
+];
+
+/// The first insert and copy length codes of each 64-symbol cell of the insert-and-copy alphabet
+const COMMAND_CELLS: [(u16, u16); 11] = [
+    (0, 0), (0, 8), (0, 0), (0, 8), (8, 0), (8, 8), (0, 16), (16, 0), (8, 16), (16, 8), (16, 16),
+];
+
+/// The context contributed by the last byte in UTF-8 context mode
+const UTF8_CONTEXT: [u8; 128] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 4, 4, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    8, 12, 16, 12, 12, 20, 12, 16, 24, 28, 12, 12, 32, 12, 36, 12,
+    44, 44, 44, 44, 44, 44, 44, 44, 44, 44, 32, 32, 24, 40, 28, 12,
+    12, 48, 52, 52, 52, 48, 52, 52, 52, 48, 52, 52, 52, 52, 52, 48,
+    52, 52, 52, 52, 52, 48, 52, 52, 52, 52, 52, 24, 12, 28, 12, 12,
+    12, 56, 60, 60, 60, 56, 60, 60, 60, 56, 60, 60, 60, 60, 60, 56,
+    60, 60, 60, 60, 60, 56, 60, 60, 60, 60, 60, 24, 12, 28, 12, 0,
+];
+
+/// The ways a dictionary word can be altered before its prefix and suffix are added
+#[derive(Clone, Copy)]
+enum Transform {
+    /// Leave the word as-is
+    Identity,
+    /// Drop this many bytes from the end of the word
+    OmitLast(usize),
+    /// Drop this many bytes from the start of the word
+    OmitFirst(usize),
+    /// Uppercase the first character
+    UppercaseFirst,
+    /// Uppercase every character
+    UppercaseAll,
+}
+
+use self::Transform::{Identity, OmitFirst, OmitLast, UppercaseAll, UppercaseFirst};
+
+/// The transforms in Appendix B of RFC 7932, as (prefix, transform, suffix)
+const TRANSFORMS: [(&[u8], Transform, &[u8]); 121] = [
+    (b"", Identity, b""), (b"", Identity, b" "), (b" ", Identity, b" "), (b"", OmitFirst(1), b""),
+    (b"", UppercaseFirst, b" "), (b"", Identity, b" the "), (b" ", Identity, b""),
+    (b"s ", Identity, b" "), (b"", Identity, b" of "), (b"", UppercaseFirst, b""),
+    (b"", Identity, b" and "), (b"", OmitFirst(2), b""), (b"", OmitLast(1), b""),
+    (b", ", Identity, b" "), (b"", Identity, b", "), (b" ", UppercaseFirst, b" "),
+    (b"", Identity, b" in "), (b"", Identity, b" to "), (b"e ", Identity, b" "),
+    (b"", Identity, b"\""), (b"", Identity, b"."), (b"", Identity, b"\">"), (b"", Identity, b"\n"),
+    (b"", OmitLast(3), b""), (b"", Identity, b"]"), (b"", Identity, b" for "),
+    (b"", OmitFirst(3), b""), (b"", OmitLast(2), b""), (b"", Identity, b" a "),
+    (b"", Identity, b" that "), (b" ", UppercaseFirst, b""), (b"", Identity, b". "),
+    (b".", Identity, b""), (b" ", Identity, b", "), (b"", OmitFirst(4), b""),
+    (b"", Identity, b" with "), (b"", Identity, b"'"), (b"", Identity, b" from "),
+    (b"", Identity, b" by "), (b"", OmitFirst(5), b""), (b"", OmitFirst(6), b""),
+    (b" the ", Identity, b""), (b"", OmitLast(4), b""), (b"", Identity, b". The "),
+    (b"", UppercaseAll, b""), (b"", Identity, b" on "), (b"", Identity, b" as "),
+    (b"", Identity, b" is "), (b"", OmitLast(7), b""), (b"", OmitLast(1), b"ing "),
+    (b"", Identity, b"\n\t"), (b"", Identity, b":"), (b" ", Identity, b". "),
+    (b"", Identity, b"ed "), (b"", OmitFirst(9), b""), (b"", OmitFirst(7), b""),
+    (b"", OmitLast(6), b""), (b"", Identity, b"("), (b"", UppercaseFirst, b", "),
+    (b"", OmitLast(8), b""), (b"", Identity, b" at "), (b"", Identity, b"ly "),
+    (b" the ", Identity, b" of "), (b"", OmitLast(5), b""), (b"", OmitLast(9), b""),
+    (b" ", UppercaseFirst, b", "), (b"", UppercaseFirst, b"\""), (b".", Identity, b"("),
+    (b"", UppercaseAll, b" "), (b"", UppercaseFirst, b"\">"), (b"", Identity, b"=\""),
+    (b" ", Identity, b"."), (b".com/", Identity, b""), (b" the ", Identity, b" of the "),
+    (b"", UppercaseFirst, b"'"), (b"", Identity, b". This "), (b"", Identity, b","),
+    (b".", Identity, b" "), (b"", UppercaseFirst, b"("), (b"", UppercaseFirst, b"."),
+    (b"", Identity, b" not "), (b" ", Identity, b"=\""), (b"", Identity, b"er "),
+    (b" ", UppercaseAll, b" "), (b"", Identity, b"al "), (b" ", UppercaseAll, b""),
+    (b"", Identity, b"='"), (b"", UppercaseAll, b"\""), (b"", UppercaseFirst, b". "),
+    (b" ", Identity, b"("), (b"", Identity, b"ful "), (b" ", UppercaseFirst, b". "),
+    (b"", Identity, b"ive "), (b"", Identity, b"less "), (b"", UppercaseAll, b"'"),
+    (b"", Identity, b"est "), (b" ", UppercaseFirst, b"."), (b"", UppercaseAll, b"\">"),
+    (b" ", Identity, b"='"), (b"", UppercaseFirst, b","), (b"", Identity, b"ize "),
+    (b"", UppercaseAll, b"."), (b"\xC2\xA0", Identity, b""), (b" ", Identity, b","),
+    (b"", UppercaseFirst, b"=\""), (b"", UppercaseAll, b"=\""), (b"", Identity, b"ous "),
+    (b"", UppercaseAll, b", "), (b"", UppercaseFirst, b"='"), (b" ", UppercaseFirst, b","),
+    (b" ", UppercaseAll, b"=\""), (b" ", UppercaseAll, b", "), (b"", UppercaseAll, b","),
+    (b"", UppercaseAll, b"("), (b"", UppercaseAll, b". "), (b" ", UppercaseAll, b"."),
+    (b"", UppercaseAll, b"='"), (b" ", UppercaseAll, b". "), (b" ", UppercaseFirst, b"=\""),
+    (b" ", UppercaseAll, b"='"), (b" ", UppercaseFirst, b"='"),
+];
+
+/// A reader for the little-endian bitstream Brotli is packed into
+///
+/// (Bytes are only taken from the input as they're needed, so the input is left positioned just
+/// past the end of the stream.)
+struct BitReader<R> {
+    /// The compressed data
+    input: R,
+    /// Bits taken from the input but not yet consumed
+    bits: u64,
+    /// How many bits are in `bits`
+    count: u32,
+}
+
+impl<R: BufRead> BitReader<R> {
+    /// Consume and return the next `count` bits (at most 32)
+    fn read(&mut self, count: u32) -> io::Result<u32> {
+        while self.count < count {
+            let Some(&byte) = self.input.fill_buf()?.first() else {
+                return Err(corrupt("Unexpected end of Brotli stream"));
+            };
+            self.input.consume(1);
+            self.bits |= u64::from(byte) << self.count;
+            self.count += 8;
+        }
+        let value = low32(self.bits & ((1 << count) - 1));
+        self.bits >>= count;
+        self.count -= count;
+        Ok(value)
+    }
+
+    /// Consume and return the next bit
+    fn bit(&mut self) -> io::Result<u32> {
+        self.read(1)
+    }
+
+    /// Skip to the next byte boundary, checking that the padding bits are zero
+    fn align(&mut self) -> io::Result<()> {
+        if self.read(self.count % 8)? != 0 {
+            return Err(corrupt("Non-zero padding bits in Brotli stream"));
+        }
+        Ok(())
+    }
+
+    /// Read `len` bytes from the input into `out`, once aligned to a byte boundary
+    fn copy_bytes(&mut self, len: u64, out: &mut impl io::Write) -> io::Result<()> {
+        if io::copy(&mut (&mut self.input).take(len), out)? != len {
+            return Err(corrupt("Unexpected end of Brotli stream"));
+        }
+        Ok(())
+    }
+}
+
+/// A canonical prefix code, decoded one bit at a time
+struct PrefixCode {
+    /// How many codes there are of each length
+    counts: [u16; MAX_CODE_LENGTH + 1],
+    /// The symbols, ordered by code length and then by value
+    symbols: Vec<u16>,
+}
+
+impl PrefixCode {
+    /// Build the code for the given (already validated) code lengths
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0; MAX_CODE_LENGTH + 1];
+        for &len in lengths {
+            counts[usize::from(len)] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0; MAX_CODE_LENGTH + 2];
+        for len in 1..=MAX_CODE_LENGTH {
+            offsets[len + 1] = offsets[len] + usize::from(counts[len]);
+        }
+        let mut symbols = vec![0; offsets[MAX_CODE_LENGTH + 1]];
+        for (symbol, &len) in (0..=u16::MAX).zip(lengths).filter(|(_, &len)| len != 0) {
+            symbols[offsets[usize::from(len)]] = symbol;
+            offsets[usize::from(len)] += 1;
+        }
+        Self { counts, symbols }
+    }
+
+    /// Decode one symbol
+    ///
+    /// (A code with only one symbol takes up no bits at all.)
+    fn decode<R: BufRead>(&self, bits: &mut BitReader<R>) -> io::Result<u16> {
+        if self.symbols.len() == 1 {
+            return Ok(self.symbols[0]);
+        }
+        let (mut code, mut first, mut index) = (0, 0, 0);
+        for &count in &self.counts[1..] {
+            code |= bits.bit()?;
+            let count = u32::from(count);
+            if code < first + count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(corrupt("Invalid Brotli prefix code"))
+    }
+
+    /// Read a prefix code description for an alphabet of `alphabet_size` symbols
+    fn read<R: BufRead>(bits: &mut BitReader<R>, alphabet_size: usize) -> io::Result<Self> {
+        let mut lengths = vec![0; alphabet_size];
+        let skip = bits.read(2)?;
+        if skip == 1 {
+            let symbol_bits = usize::BITS - (alphabet_size - 1).leading_zeros();
+            let count = bits.read(2)? as usize + 1;
+            let mut symbols = Vec::with_capacity(count);
+            for _ in 0..count {
+                let symbol = bits.read(symbol_bits)? as usize;
+                if symbol >= alphabet_size || symbols.contains(&symbol) {
+                    return Err(corrupt("Invalid simple prefix code in Brotli stream"));
+                }
+                symbols.push(symbol);
+            }
+            let simple_lengths: &[u8] = match count {
+                1 => &[1],
+                2 => &[1, 1],
+                3 => &[1, 2, 2],
+                _ if bits.bit()? == 0 => &[2, 2, 2, 2],
+                _ => &[1, 2, 3, 3],
+            };
+            for (&symbol, &len) in symbols.iter().zip(simple_lengths) {
+                lengths[symbol] = len;
+            }
+            return Ok(Self::new(&lengths));
+        }
+
+        // Complex prefix codes are described by code lengths, which have a prefix code of their own
+        let mut code_length_lengths = [0; 18];
+        let (mut space, mut nonzero) = (32, 0);
+        for &symbol in &CODE_LENGTH_ORDER[skip as usize..] {
+            let len = match bits.read(2)? {
+                0 => 0,
+                1 => 4,
+                2 => 3,
+                _ => match bits.bit()? {
+                    0 => 2,
+                    _ => if bits.bit()? == 0 { 1 } else { 5 },
+                },
+            };
+            code_length_lengths[symbol] = len;
+            if len != 0 {
+                space -= 32 >> len;
+                nonzero += 1;
+                if space <= 0 {
+                    break;
+                }
+            }
+        }
+        if nonzero != 1 && space != 0 {
+            return Err(corrupt("Invalid code length code in Brotli stream"));
+        }
+        let code_length_code = Self::new(&code_length_lengths);
+
+        let oversubscribed = || corrupt("Brotli prefix code is incomplete or oversubscribed");
+        let (mut space, mut symbol) = (1_usize << MAX_CODE_LENGTH, 0);
+        let (mut previous, mut repeat, mut repeat_len) = (8, 0, 0);
+        while symbol < alphabet_size && space > 0 {
+            let code = code_length_code.decode(bits)?;
+            if let Ok(len @ 0..=15) = u8::try_from(code) {
+                repeat = 0;
+                lengths[symbol] = len;
+                symbol += 1;
+                if len != 0 {
+                    previous = len;
+                    space = space.checked_sub((1 << MAX_CODE_LENGTH) >> len)
+                        .ok_or_else(oversubscribed)?;
+                }
+                continue;
+            }
+
+            // Repeat codes extend the previous run if they follow one of the same kind
+            let (extra_bits, len) = if code == 16 { (2, previous) } else { (3, 0) };
+            if repeat_len != len {
+                repeat = 0;
+                repeat_len = len;
+            }
+            let old_repeat = repeat;
+            if repeat > 0 {
+                repeat = (repeat - 2) << extra_bits;
+            }
+            repeat += bits.read(extra_bits)? as usize + 3;
+            let added = repeat - old_repeat;
+            if symbol + added > alphabet_size {
+                return Err(corrupt("Too many code lengths in Brotli prefix code"));
+            }
+            lengths[symbol..symbol + added].fill(len);
+            symbol += added;
+            if len != 0 {
+                space = space.checked_sub(added * ((1 << MAX_CODE_LENGTH) >> len))
+                    .ok_or_else(oversubscribed)?;
+            }
+        }
+        if space != 0 {
+            return Err(oversubscribed());
+        }
+        Ok(Self::new(&lengths))
+    }
+}
+
+/// Read a value stored in the variable-length format used for counts of up to 255
+fn var_len_u8<R: BufRead>(bits: &mut BitReader<R>) -> io::Result<u32> {
+    if bits.bit()? == 0 {
+        return Ok(0);
+    }
+    match bits.read(3)? {
+        0 => Ok(1),
+        count => Ok((1 << count) + bits.read(count)?),
+    }
+}
+
+/// Read a block count using the given block count code
+fn block_count<R: BufRead>(code: &PrefixCode, bits: &mut BitReader<R>) -> io::Result<u32> {
+    let (base, extra_bits) = BLOCK_COUNT_CODES[usize::from(code.decode(bits)?)];
+    Ok(base + bits.read(extra_bits)?)
+}
+
+/// The block switching state for one kind of symbol (literals, commands, or distances)
+struct Blocks {
+    /// How many block types there are
+    types: u32,
+    /// The codes for block types and block counts, if there's more than one type
+    codes: Option<(PrefixCode, PrefixCode)>,
+    /// The current block type
+    current: u32,
+    /// The block type before the current one
+    previous: u32,
+    /// How many more symbols are left in the current block
+    remaining: u32,
+}
+
+impl Blocks {
+    /// Read the block switching description at the start of a meta-block
+    fn read<R: BufRead>(bits: &mut BitReader<R>) -> io::Result<Self> {
+        let types = var_len_u8(bits)? + 1;
+        let (codes, remaining) = if types == 1 {
+            (None, 0)
+        } else {
+            let type_code = PrefixCode::read(bits, types as usize + 2)?;
+            let count_code = PrefixCode::read(bits, BLOCK_COUNT_CODES.len())?;
+            let remaining = block_count(&count_code, bits)?;
+            (Some((type_code, count_code)), remaining)
+        };
+        Ok(Self { types, codes, current: 0, previous: 1, remaining })
+    }
+
+    /// Get the block type of the next symbol, switching blocks first if the current one is done
+    fn next<R: BufRead>(&mut self, bits: &mut BitReader<R>) -> io::Result<usize> {
+        if let Some((type_code, count_code)) = &self.codes {
+            if self.remaining == 0 {
+                let next = match type_code.decode(bits)? {
+                    0 => self.previous,
+                    1 => self.current + 1,
+                    code => u32::from(code) - 2,
+                } % self.types;
+                self.previous = self.current;
+                self.current = next;
+                self.remaining = block_count(count_code, bits)?;
+            }
+            self.remaining -= 1;
+        }
+        Ok(self.current as usize)
+    }
+}
+
+/// Read a context map with `size` entries mapping to `trees` prefix codes
+fn context_map<R: BufRead>(bits: &mut BitReader<R>, size: usize, trees: u32)
+        -> io::Result<Vec<u8>> {
+    if trees == 1 {
+        return Ok(vec![0; size]);
+    }
+    let max_run_prefix = if bits.bit()? == 0 { 0 } else { bits.read(4)? + 1 };
+    let code = PrefixCode::read(bits, (trees + max_run_prefix) as usize)?;
+
+    let mut map = Vec::with_capacity(size);
+    while map.len() < size {
+        match u32::from(code.decode(bits)?) {
+            0 => map.push(0),
+            prefix if prefix <= max_run_prefix => {
+                let run = (1 << prefix) + bits.read(prefix)? as usize;
+                if map.len() + run > size {
+                    return Err(corrupt("Brotli context map is too long"));
+                }
+                map.resize(map.len() + run, 0);
+            },
+            symbol => map.push(u8::try_from(symbol - max_run_prefix).expect("at most 256 trees")),
+        }
+    }
+
+    // Undo the move-to-front transform, if it was applied
+    if bits.bit()? == 1 {
+        let mut order: Vec<u8> = (0..=255).collect();
+        for value in &mut map {
+            let index = usize::from(*value);
+            *value = order.remove(index);
+            order.insert(0, *value);
+        }
+    }
+    Ok(map)
+}
+
+/// Calculate the context of a literal from the two bytes before it
+fn literal_context(mode: u32, last: u8, before_last: u8) -> usize {
+    /// The category of a byte used by the signed context mode
+    fn signed(byte: u8) -> u8 {
+        match byte {
+            0 => 0,
+            1..=15 => 1,
+            16..=63 => 2,
+            64..=127 => 3,
+            128..=191 => 4,
+            192..=239 => 5,
+            240..=254 => 6,
+            255 => 7,
+        }
+    }
+
+    /// The context contributed by the byte before last in UTF-8 context mode
+    fn utf8_before_last(byte: u8) -> u8 {
+        match byte {
+            b'0'..=b'9' | b'A'..=b'Z' | 224..=255 => 2,
+            b'a'..=b'z' => 3,
+            b'!'..=b'~' => 1,
+            _ => 0,
+        }
+    }
+
+    usize::from(match mode {
+        0 => last & 0x3F,
+        1 => last >> 2,
+        2 => utf8_before_last(before_last) | match last {
+            0..=127 => UTF8_CONTEXT[usize::from(last)],
+            128..=191 => last & 1,
+            _ => 2 + (last & 1),
+        },
+        _ => (signed(last) << 3) | signed(before_last),
+    })
+}
+
+/// Apply `transform` to the dictionary word `word`, appending the result to `out`
+fn transform_word(word: &[u8], transform: usize, out: &mut Vec<u8>) {
+    /// Uppercase the character at `pos` the way RFC 7932 does, returning its length
+    fn uppercase(word: &mut [u8], pos: usize) -> usize {
+        let (len, target, mask) = match word[pos] {
+            0..=0xBF => (1, pos, if word[pos].is_ascii_lowercase() { 0x20 } else { 0 }),
+            0xC0..=0xDF => (2, pos + 1, 0x20),
+            _ => (3, pos + 2, 0x05),
+        };
+        if let Some(byte) = word.get_mut(target) {
+            *byte ^= mask;
+        }
+        len
+    }
+
+    let (prefix, kind, suffix) = TRANSFORMS[transform];
+    out.extend(prefix);
+    let start = out.len();
+    match kind {
+        Identity | UppercaseFirst | UppercaseAll => out.extend(word),
+        OmitLast(count) => out.extend(&word[..word.len().saturating_sub(count)]),
+        OmitFirst(count) => out.extend(&word[count.min(word.len())..]),
+    }
+    match kind {
+        UppercaseFirst if out.len() > start => {
+            uppercase(&mut out[start..], 0);
+        },
+        UppercaseAll => {
+            let mut pos = 0;
+            while start + pos < out.len() {
+                pos += uppercase(&mut out[start..], pos);
+            }
+        },
+        _ => {},
+    }
+    out.extend(suffix);
+}
+
+/// A streaming decompressor for Brotli streams
+pub struct BrotliDecoder<R> {
+    /// The compressed data
+    bits: BitReader<R>,
+    /// How far back copies may reach (zero until the stream header has been read)
+    window_size: usize,
+    /// Recently decompressed data (which copies are made from)
+    history: Vec<u8>,
+    /// How much of `history` has been read
+    history_pos: usize,
+    /// How much decompressed data has been dropped from the start of `history`
+    discarded: u64,
+    /// The last four distances used, most recent first
+    distances: [u32; 4],
+    /// Whether the last meta-block has been decoded
+    finished: bool,
+}
+
+impl<R: BufRead> BrotliDecoder<R> {
+    /// Prepare to decompress `input`
+    pub fn new(input: R) -> Self {
+        Self {
+            bits: BitReader { input, bits: 0, count: 0 },
+            window_size: 0,
+            history: Vec::new(),
+            history_pos: 0,
+            discarded: 0,
+            distances: [4, 11, 15, 16],
+            finished: false,
+        }
+    }
+
+    /// Get back the input, positioned just past the end of the stream if it was read to the end
+    pub fn into_inner(self) -> R {
+        self.bits.input
+    }
+
+    /// Get the byte `back` bytes before the end of the output, or zero before the stream starts
+    fn recent(&self, back: usize) -> u8 {
+        self.history.len().checked_sub(back).map_or(0, |pos| self.history[pos])
+    }
+
+    /// Read the stream header, which gives the window size
+    fn stream_header(&mut self) -> io::Result<()> {
+        let window_bits = if self.bits.bit()? == 0 {
+            16
+        } else {
+            match self.bits.read(3)? {
+                0 => match self.bits.read(3)? {
+                    0 => 17,
+                    1 => return Err(unsupported("Large-window Brotli streams are not supported")),
+                    bits => 8 + bits,
+                },
+                bits => 17 + bits,
+            }
+        };
+        self.window_size = (1 << window_bits) - 16;
+        Ok(())
+    }
+
+    /// Decode the next meta-block into `history`
+    fn meta_block(&mut self) -> io::Result<()> {
+        let last = self.bits.bit()? == 1;
+        if last && self.bits.bit()? == 1 {
+            self.finished = true;
+            return self.bits.align();
+        }
+
+        let nibbles = match self.bits.read(2)? {
+            3 => 0,
+            count => count + 4,
+        };
+        if nibbles == 0 {
+            if last || self.bits.bit()? != 0 {
+                return Err(corrupt("Invalid Brotli metadata block header"));
+            }
+            let len_bytes = self.bits.read(2)?;
+            let mut len = 0;
+            for index in 0..len_bytes {
+                let byte = self.bits.read(8)?;
+                if byte == 0 && index + 1 == len_bytes && len_bytes > 1 {
+                    return Err(corrupt("Brotli metadata length has a superfluous zero byte"));
+                }
+                len |= u64::from(byte) << (8 * index);
+            }
+            self.bits.align()?;
+            return self.bits.copy_bytes(if len_bytes == 0 { 0 } else { len + 1 }, &mut io::sink());
+        }
+
+        let mut len = 0;
+        for index in 0..nibbles {
+            let nibble = self.bits.read(4)?;
+            if nibble == 0 && index + 1 == nibbles && nibbles > 4 {
+                return Err(corrupt("Brotli meta-block length has a superfluous zero nibble"));
+            }
+            len |= (nibble as usize) << (4 * index);
+        }
+        let len = len + 1;
+        let uncompressed = !last && self.bits.bit()? == 1;
+
+        // Discard history which copies can no longer reach, once there's a fair amount of it
+        let excess = self.history.len().saturating_sub(self.window_size);
+        if excess > self.window_size {
+            self.history.drain(..excess);
+            self.discarded += excess as u64;
+        }
+        self.history_pos = self.history.len();
+
+        if uncompressed {
+            self.bits.align()?;
+            self.bits.copy_bytes(len as u64, &mut self.history)?;
+        } else {
+            self.compressed(len)?;
+        }
+        if last {
+            self.finished = true;
+            self.bits.align()?;
+        }
+        Ok(())
+    }
+
+    /// Resolve a distance code to a distance (without updating the recent distances)
+    fn distance(&mut self, code: u32, postfix_bits: u32, direct: u32) -> io::Result<u32> {
+        match code {
+            0..=3 => Ok(self.distances[code as usize]),
+            4..=15 => {
+                let base = i64::from(self.distances[usize::from(code >= 10)]);
+                let delta = [-1, 1, -2, 2, -3, 3][(code as usize - 4) % 6];
+                u32::try_from(base + delta).ok().filter(|&x| x > 0)
+                    .ok_or_else(|| corrupt("Invalid distance in Brotli stream"))
+            },
+            _ if code < 16 + direct => Ok(code - 15),
+            _ => {
+                let code = code - direct - 16;
+                let extra_bits = 1 + (code >> (postfix_bits + 1));
+                let offset = ((2 + ((code >> postfix_bits) & 1)) << extra_bits) - 4;
+                let postfix = code & ((1 << postfix_bits) - 1);
+                Ok(((offset + self.bits.read(extra_bits)?) << postfix_bits) + postfix + direct + 1)
+            },
+        }
+    }
+
+    /// Decode a compressed meta-block which decompresses to `len` bytes
+    fn compressed(&mut self, len: usize) -> io::Result<()> {
+        let bits = &mut self.bits;
+        let mut literal_blocks = Blocks::read(bits)?;
+        let mut command_blocks = Blocks::read(bits)?;
+        let mut distance_blocks = Blocks::read(bits)?;
+        let postfix_bits = bits.read(2)?;
+        let direct = bits.read(4)? << postfix_bits;
+        let modes = (0..literal_blocks.types).map(|_| bits.read(2))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let literal_trees = var_len_u8(bits)? + 1;
+        let literal_map = context_map(bits, 64 * literal_blocks.types as usize, literal_trees)?;
+        let distance_trees = var_len_u8(bits)? + 1;
+        let distance_map = context_map(bits, 4 * distance_blocks.types as usize, distance_trees)?;
+        let read_codes = |bits: &mut BitReader<R>, count: u32, alphabet_size: u32| {
+            (0..count).map(|_| PrefixCode::read(bits, alphabet_size as usize))
+                .collect::<io::Result<Vec<_>>>()
+        };
+        let literal_codes = read_codes(bits, literal_trees, 256)?;
+        let command_codes = read_codes(bits, command_blocks.types, 704)?;
+        let distance_codes = read_codes(bits, distance_trees, 16 + direct + (48 << postfix_bits))?;
+
+        let end = self.history.len() + len;
+        loop {
+            let command = command_codes[command_blocks.next(&mut self.bits)?]
+                .decode(&mut self.bits)?;
+            let (insert_cell, copy_cell) = COMMAND_CELLS[usize::from(command >> 6)];
+            let (base, extra_bits) =
+                INSERT_LENGTH_CODES[usize::from(insert_cell + ((command >> 3) & 7))];
+            let insert = (base + self.bits.read(extra_bits)?) as usize;
+            let (base, extra_bits) = COPY_LENGTH_CODES[usize::from(copy_cell + (command & 7))];
+            let copy = (base + self.bits.read(extra_bits)?) as usize;
+
+            if self.history.len() + insert > end {
+                return Err(corrupt("Brotli meta-block is longer than its header says"));
+            }
+            for _ in 0..insert {
+                let block_type = literal_blocks.next(&mut self.bits)?;
+                let context = literal_context(modes[block_type], self.recent(1), self.recent(2));
+                let tree = usize::from(literal_map[64 * block_type + context]);
+                let literal = literal_codes[tree].decode(&mut self.bits)?;
+                self.history.push(u8::try_from(literal)
+                    .map_err(|_| corrupt("Invalid literal in Brotli stream"))?);
+            }
+            if self.history.len() == end {
+                return Ok(());
+            }
+
+            // The first two cells of commands reuse the last distance without a distance code
+            let code = if command < 128 {
+                0
+            } else {
+                let block_type = distance_blocks.next(&mut self.bits)?;
+                let tree = usize::from(distance_map[4 * block_type + copy.min(5) - 2]);
+                u32::from(distance_codes[tree].decode(&mut self.bits)?)
+            };
+            let resolved = self.distance(code, postfix_bits, direct)?;
+            let distance = resolved as usize;
+
+            let position = self.discarded + self.history.len() as u64;
+            let max_distance = usize::try_from(position).map_or(self.window_size, |x| {
+                x.min(self.window_size)
+            });
+            if distance > max_distance {
+                // Distances beyond the window refer to the static dictionary
+                if !(4..=24).contains(&copy) {
+                    return Err(corrupt("Invalid Brotli dictionary reference"));
+                }
+                let word_bits = DICTIONARY_BITS[copy];
+                let word_id = distance - max_distance - 1;
+                let transform = word_id >> word_bits;
+                if transform >= TRANSFORMS.len() {
+                    return Err(corrupt("Invalid Brotli dictionary reference"));
+                }
+                let offset: usize = (4..copy).map(|x| x << DICTIONARY_BITS[x]).sum();
+                let start = offset + (word_id & ((1 << word_bits) - 1)) * copy;
+                transform_word(&DICTIONARY[start..start + copy], transform, &mut self.history);
+                if self.history.len() > end {
+                    return Err(corrupt("Brotli meta-block is longer than its header says"));
+                }
+            } else {
+                if code != 0 {
+                    self.distances.rotate_right(1);
+                    self.distances[0] = resolved;
+                }
+                if self.history.len() + copy > end {
+                    return Err(corrupt("Brotli meta-block is longer than its header says"));
+                }
+                for _ in 0..copy {
+                    self.history.push(self.history[self.history.len() - distance]);
+                }
+            }
+            if self.history.len() == end {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Read for BrotliDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.history_pos == self.history.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            if self.window_size == 0 {
+                self.stream_header()?;
+            }
+            self.meta_block()?;
+        }
+        let available = &self.history[self.history_pos..];
+        let len = buf.len().min(available.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.history_pos += len;
+        Ok(len)
+    }
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtin_handlers::checksums::Sha256;
+
+    /// The text compressed into `COMPRESSED`
+    const TEXT: &[u8] = b"The quick brown fox jumps over the lazy dog. \
+        The Quick Brown Fox uses THE DICTIONARY, doesn't it?\n";
+
+    /// `TEXT`, as compressed by `brotli -q 11` (which makes use of the dictionary and transforms)
+    const COMPRESSED: &[u8] = &[
+        0x1B, 0x61, 0x00, 0x00, 0x8C, 0x94, 0xEE, 0x3E, 0xA2, 0x24, 0x3B, 0x19, 0x75, 0x35, 0xDA,
+        0x98, 0xF5, 0x0B, 0xD1, 0x9B, 0x14, 0x41, 0x83, 0xB8, 0xFC, 0xCC, 0xD5, 0xC9, 0xF1, 0x41,
+        0xF5, 0xDF, 0x93, 0x30, 0xC8, 0x2F, 0x9A, 0x49, 0x6E, 0x63, 0x67, 0x8A, 0x7A, 0x7C, 0x56,
+        0x34, 0xE8, 0x89, 0x43, 0xCA, 0xC5, 0xE4, 0x3E, 0x1F, 0x7B, 0x2C, 0x75, 0xB0, 0xEA, 0xE5,
+        0x5B, 0x41, 0x47, 0x3F, 0xB9, 0x19, 0x00, 0xA5, 0xD4, 0x07, 0x26, 0xAF, 0x51, 0xF5, 0xC2,
+        0xAC, 0xCC, 0x8D, 0x00,
+    ];
+
+    /// Decompress `input`, returning the error kind on failure
+    fn decode(input: &[u8]) -> Result<Vec<u8>, io::ErrorKind> {
+        let mut output = Vec::new();
+        BrotliDecoder::new(input).read_to_end(&mut output).map_err(|err| err.kind())?;
+        Ok(output)
+    }
+
+    #[test]
+    fn test_streams() {
+        assert_eq!(decode(COMPRESSED).unwrap(), TEXT);
+        assert_eq!(decode(b"\x3B").unwrap(), b"");
+        assert_eq!(decode(b"\x0B\x07\x80Hello, Brotli!\n\x03").unwrap(), b"Hello, Brotli!\n");
+
+        // A metadata block followed by the empty last meta-block
+        assert_eq!(decode(b"\x2C\x01xyz\x03").unwrap(), b"");
+
+        // The input is left just past the end of the stream
+        let mut trailing = COMPRESSED.to_vec();
+        trailing.extend(b"junk");
+        let mut decoder = BrotliDecoder::new(&trailing[..]);
+        assert_eq!(io::copy(&mut decoder, &mut io::sink()).unwrap(), TEXT.len() as u64);
+        assert_eq!(decoder.into_inner(), b"junk");
+    }
+
+    #[test]
+    fn test_fixtures() {
+        // The output of `make_test_brotli.py`, which documents what each input exercises
+        let mixed = (2_109_440, "18855f0aa99dfa5a6933be3a219d4720854d49e8d05ef5e2c271c6aaa9435b5b");
+        let noise = (16384, "7b956a45f652b6e4e1a3f1b0a149784deb84cc62e197a4a7562acde4f1ecea44");
+        let fixtures: &[(&str, &[u8], _)] = &[
+            ("q0", include_bytes!("../../../test_data/good/testfile.mixed.q0.br"), mixed),
+            ("q1", include_bytes!("../../../test_data/good/testfile.mixed.q1.br"), mixed),
+            ("q2", include_bytes!("../../../test_data/good/testfile.mixed.q2.br"), mixed),
+            ("q3", include_bytes!("../../../test_data/good/testfile.mixed.q3.br"), mixed),
+            ("q4", include_bytes!("../../../test_data/good/testfile.mixed.q4.br"), mixed),
+            ("q5", include_bytes!("../../../test_data/good/testfile.mixed.q5.br"), mixed),
+            ("q6", include_bytes!("../../../test_data/good/testfile.mixed.q6.br"), mixed),
+            ("q7", include_bytes!("../../../test_data/good/testfile.mixed.q7.br"), mixed),
+            ("q8", include_bytes!("../../../test_data/good/testfile.mixed.q8.br"), mixed),
+            ("q9", include_bytes!("../../../test_data/good/testfile.mixed.q9.br"), mixed),
+            ("q10", include_bytes!("../../../test_data/good/testfile.mixed.q10.br"), mixed),
+            ("q11", include_bytes!("../../../test_data/good/testfile.mixed.q11.br"), mixed),
+            ("noise", include_bytes!("../../../test_data/good/testfile.noise.br"), noise),
+        ];
+        for (name, compressed, (len, digest)) in fixtures {
+            let output = decode(compressed).unwrap();
+            let mut sha = Sha256::new();
+            sha.update(&output);
+            let hex = sha.finish().iter().fold(String::new(), |hex, x| hex + &format!("{x:02x}"));
+            assert_eq!((output.len(), hex.as_str()), (*len, *digest), "{name}");
+        }
+    }
+
+    #[test]
+    fn test_bad_streams() {
+        for cut in &[0, 1, 10, COMPRESSED.len() - 1] {
+            assert_eq!(decode(&COMPRESSED[..*cut]), Err(io::ErrorKind::InvalidData), "{cut}");
+        }
+        assert_eq!(decode(b"\xFB"), Err(io::ErrorKind::InvalidData), "non-zero padding");
+        assert_eq!(decode(b"\x0B\x07\x80Hello, Brotli!\n"), Err(io::ErrorKind::InvalidData),
+                   "missing last meta-block");
+        assert_eq!(decode(b"\x11\x00"), Err(io::ErrorKind::Unsupported), "large window");
+
+        // Bit flips which the reference decoder also rejects
+        for (offset, mask) in &[(1, 0x02), (20, 0x02), (20, 0x80), (40, 0x02)] {
+            let mut damaged = COMPRESSED.to_vec();
+            damaged[*offset] ^= mask;
+            assert_eq!(decode(&damaged), Err(io::ErrorKind::InvalidData), "{offset}");
+        }
+    }
+}
@@ -0,0 +1,54 @@
+//! Helpers for reading the headers and tables which the built-in handlers parse
+
+// Standard library imports
+use std::io::{self, Read, Seek, SeekFrom};
+
+// Local Imports
+use super::corrupt;
+
+/// Read a big-endian `u16` from the start of `bytes`
+pub fn be16(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+/// Read a big-endian `u32` from the start of `bytes`
+pub fn be32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Read a big-endian `u64` from the start of `bytes`
+pub fn be64(bytes: &[u8]) -> u64 {
+    (u64::from(be32(bytes)) << 32) | u64::from(be32(&bytes[4..]))
+}
+
+/// Read a little-endian `u16` from the start of `bytes`
+pub fn le16(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes([bytes[0], bytes[1]])
+}
+
+/// Read a little-endian `u32` from the start of `bytes`
+pub fn le32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Get the low 32 bits of `value`, for bit buffers which hold more than they hand out at once
+pub fn low32(value: u64) -> u32 {
+    let [b0, b1, b2, b3, ..] = value.to_le_bytes();
+    u32::from_le_bytes([b0, b1, b2, b3])
+}
+
+/// Read `buf.len()` bytes from `offset`, which the caller must have checked against the length
+pub fn read_at<R: Read + Seek>(input: &mut R, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+    input.seek(SeekFrom::Start(offset))?;
+    input.read_exact(buf)
+}
+
+/// Check that the `len` bytes at `offset`, which hold `what`, lie within the file
+pub fn check_bounds(what: &str, offset: u64, len: u64, file_len: u64) -> io::Result<()> {
+    match offset.checked_add(len) {
+        Some(end) if end <= file_len => Ok(()),
+        _ => Err(corrupt(&format!(
+            "{} runs past the end of the file (truncated?): offset {:#x} + size {:#x} > {:#x}",
+            what, offset, len, file_len))),
+    }
+}
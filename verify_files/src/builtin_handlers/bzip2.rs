@@ -0,0 +1,502 @@
+//! A reader for bzip2 files which verifies every block CRC and each stream's combined CRC
+//!
+//! (Concatenated streams, as written by `pbzip2` and `lbzip2`, are decoded one after the other.
+//! The long-deprecated "randomised" blocks are reported as `ErrorKind::Unsupported` so an
+//! external tool can take over.)
+
+// Standard library imports
+use std::convert::TryFrom;
+use std::io::{self, BufRead, Read};
+
+// Local Imports
+use super::{corrupt, eof_is_corrupt, unsupported};
+
+/// The magic number at the start of every stream, followed by the block size digit
+const STREAM_MAGIC: [u8; 3] = *b"BZh";
+
+/// The 48-bit magic number at the start of every block (the BCD digits of pi)
+const BLOCK_MAGIC: u64 = 0x3141_5926_5359;
+
+/// The 48-bit magic number at the end of every stream (the BCD digits of the square root of pi)
+const END_MAGIC: u64 = 0x1772_4538_5090;
+
+/// The longest a Huffman code can be
+const MAX_CODE_LEN: usize = 20;
+
+/// How many symbols are coded with each selected Huffman table
+const GROUP_SIZE: usize = 50;
+
+/// How many selectors the reference decoder keeps, ignoring any beyond that
+const MAX_SELECTORS: usize = 18002;
+
+/// The symbols which encode runs of the symbol at the front of the move-to-front list
+const RUN_A: usize = 0;
+/// (See [`RUN_A`])
+const RUN_B: usize = 1;
+
+/// The lookup table for the big-endian CRC-32 bzip2 uses
+const CRC_TABLE: [u32; 256] = crc_table();
+
+/// Build [`CRC_TABLE`]
+#[allow(clippy::as_conversions, clippy::cast_possible_truncation, clippy::indexing_slicing)]
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < table.len() {
+        let mut crc = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000_0000 == 0 { crc << 1 } else { (crc << 1) ^ 0x04C1_1DB7 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Update a bzip2 CRC with one byte
+fn crc_update(crc: u32, byte: u8) -> u32 {
+    (crc << 8) ^ CRC_TABLE[usize::from(u8::try_from(crc >> 24).expect("8 bits") ^ byte)]
+}
+
+/// An MSB-first bit reader
+struct Bits<R> {
+    /// The compressed data
+    input: R,
+    /// Bits which have been read from `input` but not consumed
+    buffer: u64,
+    /// How many bits of `buffer` are unconsumed
+    count: u32,
+}
+
+impl<R: BufRead> Bits<R> {
+    /// Read an `n`-bit big-endian value, for `n` of at most 48
+    fn bits(&mut self, n: u32) -> io::Result<u64> {
+        while self.count < n {
+            let byte = match self.input.fill_buf()?.first() {
+                Some(&byte) => byte,
+                None => return Err(io::ErrorKind::UnexpectedEof.into()),
+            };
+            self.input.consume(1);
+            self.buffer = (self.buffer << 8) | u64::from(byte);
+            self.count += 8;
+        }
+        self.count -= n;
+        Ok((self.buffer >> self.count) & ((1 << n) - 1))
+    }
+
+    /// Read a single bit
+    fn bit(&mut self) -> io::Result<bool> {
+        Ok(self.bits(1)? == 1)
+    }
+
+    /// Discard the padding bits up to the next byte boundary
+    fn align(&mut self) {
+        self.count -= self.count % 8;
+    }
+
+    /// Check whether the input has been exhausted (only meaningful when aligned)
+    fn is_empty(&mut self) -> io::Result<bool> {
+        Ok(self.count == 0 && self.input.fill_buf()?.is_empty())
+    }
+}
+
+/// A canonical Huffman decoding table
+struct Huffman {
+    /// The first code of each length
+    first: [u32; MAX_CODE_LEN + 1],
+    /// How many codes there are of each length
+    counts: [u32; MAX_CODE_LEN + 1],
+    /// Where the symbols for each length start in `symbols`
+    starts: [usize; MAX_CODE_LEN + 1],
+    /// The symbols, ordered by code length and then by value
+    symbols: Vec<usize>,
+}
+
+impl Huffman {
+    /// Build a table from the code length of each symbol
+    fn new(lengths: &[usize]) -> Self {
+        let mut table = Self {
+            first: [0; MAX_CODE_LEN + 1],
+            counts: [0; MAX_CODE_LEN + 1],
+            starts: [0; MAX_CODE_LEN + 1],
+            symbols: Vec::with_capacity(lengths.len()),
+        };
+        for &len in lengths {
+            table.counts[len] += 1;
+        }
+        let mut code = 0;
+        for len in 1..=MAX_CODE_LEN {
+            table.first[len] = code;
+            table.starts[len] = table.symbols.len();
+            table.symbols.extend(lengths.iter().enumerate()
+                .filter(|&(_, &x)| x == len)
+                .map(|(symbol, _)| symbol));
+            code = (code + table.counts[len]) << 1;
+        }
+        table
+    }
+
+    /// Decode one symbol
+    fn decode<R: BufRead>(&self, bits: &mut Bits<R>) -> io::Result<usize> {
+        let mut code = 0;
+        for len in 1..=MAX_CODE_LEN {
+            code = (code << 1) | u32::from(bits.bit()?);
+            if code >= self.first[len] && code - self.first[len] < self.counts[len] {
+                let offset = usize::try_from(code - self.first[len]).expect("20 bits");
+                return Ok(self.symbols[self.starts[len] + offset]);
+            }
+        }
+        Err(corrupt("Invalid Huffman code in bzip2 block"))
+    }
+}
+
+/// Read the Huffman tables, selectors, and symbols of a block, returning the bytes that were fed
+/// to the Burrows-Wheeler transform, with their counts
+fn read_block_data<R: BufRead>(bits: &mut Bits<R>, max_len: usize)
+        -> io::Result<(Vec<u32>, [usize; 256])> {
+    // Which byte values appear in the block, as a two-level bitmap
+    let ranges = bits.bits(16)?;
+    let mut in_use = Vec::new();
+    for range in (0..16).filter(|x| ranges & (0x8000 >> x) != 0) {
+        let used = bits.bits(16)?;
+        in_use.extend((0..16).filter(|x| used & (0x8000 >> x) != 0).map(|x| range * 16 + x));
+    }
+    if in_use.is_empty() {
+        return Err(corrupt("bzip2 block uses no symbols"));
+    }
+    let alphabet_len = in_use.len() + 2;
+
+    let groups = usize::try_from(bits.bits(3)?).expect("3 bits");
+    if !(2..=6).contains(&groups) {
+        return Err(corrupt("Invalid number of Huffman tables in bzip2 block"));
+    }
+    let selector_count = usize::try_from(bits.bits(15)?).expect("15 bits");
+    if selector_count == 0 {
+        return Err(corrupt("bzip2 block has no Huffman table selectors"));
+    }
+
+    // The selectors are move-to-front coded in unary
+    let mut order: Vec<usize> = (0..groups).collect();
+    let mut selectors = Vec::with_capacity(selector_count.min(MAX_SELECTORS));
+    for _ in 0..selector_count {
+        let mut index = 0;
+        while bits.bit()? {
+            index += 1;
+            if index >= groups {
+                return Err(corrupt("Invalid Huffman table selector in bzip2 block"));
+            }
+        }
+        order[..=index].rotate_right(1);
+        if selectors.len() < MAX_SELECTORS {
+            selectors.push(order[0]);
+        }
+    }
+
+    // The code lengths are delta coded, starting from a 5-bit value
+    let mut tables = Vec::with_capacity(groups);
+    for _ in 0..groups {
+        let mut len = usize::try_from(bits.bits(5)?).expect("5 bits");
+        let mut lengths = Vec::with_capacity(alphabet_len);
+        for _ in 0..alphabet_len {
+            loop {
+                if !(1..=MAX_CODE_LEN).contains(&len) {
+                    return Err(corrupt("Invalid Huffman code length in bzip2 block"));
+                }
+                if !bits.bit()? {
+                    break;
+                }
+                if bits.bit()? {
+                    len -= 1;
+                } else {
+                    len += 1;
+                }
+            }
+            lengths.push(len);
+        }
+        tables.push(Huffman::new(&lengths));
+    }
+
+    // The symbols are run-length and move-to-front coded
+    let end_of_block = alphabet_len - 1;
+    let too_long = || corrupt("bzip2 block is longer than its stream's block size allows");
+    let mut mtf: Vec<u8> = in_use.iter().map(|&x| u8::try_from(x).expect("< 256")).collect();
+    let mut data = Vec::new();
+    let mut counts = [0; 256];
+    let (mut run, mut run_bit) = (0, 1);
+    let mut selectors = selectors.into_iter();
+    let mut table = &tables[0];
+    for decoded in 0.. {
+        if decoded % GROUP_SIZE == 0 {
+            table = &tables[selectors.next()
+                .ok_or_else(|| corrupt("bzip2 block runs past its last selector"))?];
+        }
+        let symbol = table.decode(bits)?;
+        if symbol == RUN_A || symbol == RUN_B {
+            if run_bit > max_len {
+                return Err(too_long());
+            }
+            run += run_bit << symbol;
+            run_bit <<= 1;
+            continue;
+        }
+        if run > 0 {
+            if data.len() + run > max_len {
+                return Err(too_long());
+            }
+            data.resize(data.len() + run, u32::from(mtf[0]));
+            counts[usize::from(mtf[0])] += run;
+            run = 0;
+            run_bit = 1;
+        }
+        if symbol == end_of_block {
+            break;
+        }
+        if data.len() == max_len {
+            return Err(too_long());
+        }
+        mtf[..symbol].rotate_right(1);
+        data.push(u32::from(mtf[0]));
+        counts[usize::from(mtf[0])] += 1;
+    }
+    Ok((data, counts))
+}
+
+/// Decode a block (after its magic number), appending its contents to `out` and returning its
+/// CRC once that's been verified
+fn read_block<R: BufRead>(bits: &mut Bits<R>, max_len: usize, out: &mut Vec<u8>)
+        -> io::Result<u32> {
+    let expected_crc = u32::try_from(bits.bits(32)?).expect("32 bits");
+    if bits.bit()? {
+        return Err(unsupported("Randomised bzip2 blocks aren't supported"));
+    }
+    let origin = usize::try_from(bits.bits(24)?).expect("24 bits");
+    let (mut data, counts) = read_block_data(bits, max_len)?;
+    if origin >= data.len() {
+        return Err(corrupt("bzip2 block's Burrows-Wheeler origin pointer is out of range"));
+    }
+
+    // Invert the Burrows-Wheeler transform, threading the links through the upper 24 bits
+    let mut starts = [0; 256];
+    let mut sum = 0;
+    for (start, count) in starts.iter_mut().zip(counts.iter()) {
+        *start = sum;
+        sum += count;
+    }
+    for i in 0..data.len() {
+        let byte = usize::try_from(data[i] & 0xFF).expect("8 bits");
+        data[starts[byte]] |= u32::try_from(i).expect("block size fits in 24 bits") << 8;
+        starts[byte] += 1;
+    }
+
+    // Undo the initial run-length encoding (four repeats followed by a count) as bytes come out
+    let mut crc = 0xFFFF_FFFF;
+    let mut pos = usize::try_from(data[origin] >> 8).expect("24 bits");
+    let (mut last, mut repeats) = (None, 0);
+    for _ in 0..data.len() {
+        let byte = u8::try_from(data[pos] & 0xFF).expect("8 bits");
+        pos = usize::try_from(data[pos] >> 8).expect("24 bits");
+        if repeats == 4 {
+            let previous = last.take().expect("set by the repeats");
+            for _ in 0..byte {
+                crc = crc_update(crc, previous);
+                out.push(previous);
+            }
+            repeats = 0;
+            continue;
+        }
+        if last == Some(byte) {
+            repeats += 1;
+        } else {
+            last = Some(byte);
+            repeats = 1;
+        }
+        crc = crc_update(crc, byte);
+        out.push(byte);
+    }
+
+    let crc = !crc;
+    if crc == expected_crc {
+        Ok(crc)
+    } else {
+        Err(corrupt("bzip2 block CRC mismatch"))
+    }
+}
+
+/// Where the decoder is in the file's structure
+enum State {
+    /// Expecting a stream header (or, after the first stream, EOF)
+    StreamHeader {
+        /// Whether this is the first stream, which can't be replaced by EOF
+        first: bool,
+    },
+    /// Expecting a block or the end-of-stream marker
+    Blocks {
+        /// The maximum length of a block, from the stream header
+        max_len: usize,
+        /// The combination of the block CRCs so far
+        combined_crc: u32,
+    },
+    /// Finished
+    Done,
+}
+
+/// A streaming decompressor for bzip2 files
+pub struct Bzip2Decoder<R> {
+    /// The compressed data
+    input: Bits<R>,
+    /// Where the reader is in the file's structure
+    state: State,
+    /// Decompressed data which hasn't been read yet
+    buffer: Vec<u8>,
+    /// How much of `buffer` has been read
+    buffer_pos: usize,
+}
+
+impl<R: BufRead> Bzip2Decoder<R> {
+    /// Prepare to decompress `input`
+    pub fn new(input: R) -> Self {
+        Self {
+            input: Bits { input, buffer: 0, count: 0 },
+            state: State::StreamHeader { first: true },
+            buffer: Vec::new(),
+            buffer_pos: 0,
+        }
+    }
+
+    /// Decompress the next block into `buffer`, returning `false` once there are no more
+    fn fill(&mut self) -> io::Result<bool> {
+        self.buffer.clear();
+        self.buffer_pos = 0;
+        loop {
+            match self.state {
+                State::StreamHeader { first } => {
+                    if !first && self.input.is_empty()? {
+                        self.state = State::Done;
+                        continue;
+                    }
+                    let mut header = [0; 4];
+                    for byte in &mut header {
+                        *byte = u8::try_from(self.input.bits(8)?).expect("8 bits");
+                    }
+                    if header[..3] != STREAM_MAGIC || !(b'1'..=b'9').contains(&header[3]) {
+                        return Err(corrupt(if first { "Not a bzip2 stream" }
+                                           else { "trailing data after bzip2 stream" }));
+                    }
+                    let max_len = usize::from(header[3] - b'0') * 100_000;
+                    self.state = State::Blocks { max_len, combined_crc: 0 };
+                },
+                State::Blocks { max_len, combined_crc } => match self.input.bits(48)? {
+                    BLOCK_MAGIC => {
+                        let crc = read_block(&mut self.input, max_len, &mut self.buffer)?;
+                        let combined_crc = combined_crc.rotate_left(1) ^ crc;
+                        self.state = State::Blocks { max_len, combined_crc };
+                        return Ok(true);
+                    },
+                    END_MAGIC => {
+                        if self.input.bits(32)? != u64::from(combined_crc) {
+                            return Err(corrupt("bzip2 stream CRC mismatch"));
+                        }
+                        self.input.align();
+                        self.state = State::StreamHeader { first: false };
+                    },
+                    _ => return Err(corrupt("Bad bzip2 block magic number")),
+                },
+                State::Done => return Ok(false),
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Read for Bzip2Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.buffer_pos == self.buffer.len() {
+            let more = eof_is_corrupt(self.fill())?;
+            if !more {
+                return Ok(0);
+            }
+        }
+        let len = buf.len().min(self.buffer.len() - self.buffer_pos);
+        buf[..len].copy_from_slice(&self.buffer[self.buffer_pos..self.buffer_pos + len]);
+        self.buffer_pos += len;
+        Ok(len)
+    }
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A single-block stream, compressed by the reference `bzip2`
+    const GOOD: &[u8] = include_bytes!("../../../test_data/good/testfile.txt.bz2");
+
+    /// A stream with no blocks
+    const EMPTY: &[u8] = b"BZh9\x17\x72\x45\x38\x50\x90\0\0\0\0";
+
+    /// Decompress `input`, returning the error message on failure
+    fn decode(input: &[u8]) -> Result<Vec<u8>, String> {
+        let mut output = Vec::new();
+        Bzip2Decoder::new(input).read_to_end(&mut output).map_err(|err| err.to_string())?;
+        Ok(output)
+    }
+
+    #[test]
+    fn test_crc() {
+        // The check value from the CRC catalogue's entry for CRC-32/BZIP2
+        assert_eq!(!b"123456789".iter().fold(0xFFFF_FFFF, |crc, &x| crc_update(crc, x)),
+                   0xFC89_1918);
+    }
+
+    #[test]
+    fn test_fixtures() {
+        let text = include_bytes!("../../../test_data/sources/testfile.txt");
+        assert_eq!(decode(GOOD).unwrap(), text);
+        assert_eq!(decode(include_bytes!("../../../test_data/good/testfile.tbz2")).unwrap().len(),
+                   10240);
+        assert!(decode(include_bytes!("../../../test_data/bad/testfile.txt.bz2")).is_err());
+        assert!(decode(include_bytes!("../../../test_data/bad/testfile.tbz2")).is_err());
+    }
+
+    #[test]
+    fn test_streams() {
+        assert_eq!(decode(EMPTY).unwrap(), b"");
+
+        let mut multi = GOOD.to_vec();
+        multi.extend(EMPTY);
+        multi.extend(GOOD);
+        assert_eq!(decode(&multi).unwrap().len(), 2 * decode(GOOD).unwrap().len());
+
+        for cut in &[0, 3, 4, 10, GOOD.len() / 2, GOOD.len() - 1] {
+            assert_eq!(decode(&GOOD[..*cut]).unwrap_err(), "Unexpected end of file", "{}", cut);
+        }
+
+        let mut trailing = GOOD.to_vec();
+        trailing.extend(b"junk");
+        assert_eq!(decode(&trailing).unwrap_err(), "trailing data after bzip2 stream");
+        assert_eq!(decode(b"BZh0").unwrap_err(), "Not a bzip2 stream");
+        assert_eq!(decode(b"BZh9\x31\x41\x59\x26\x53\x58").unwrap_err(),
+                   "Bad bzip2 block magic number");
+    }
+
+    #[test]
+    fn test_block_damage() {
+        // The block CRC comes right after the byte-aligned block magic number
+        let mut bad_crc = GOOD.to_vec();
+        bad_crc[10] ^= 1;
+        assert_eq!(decode(&bad_crc).unwrap_err(), "bzip2 block CRC mismatch");
+
+        // ...followed by the "randomised" flag
+        let mut randomised = GOOD.to_vec();
+        randomised[14] |= 0x80;
+        let mut output = Vec::new();
+        let err = Bzip2Decoder::new(&randomised[..]).read_to_end(&mut output).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+
+        let mut bad_combined = EMPTY.to_vec();
+        bad_combined[10] = 1;
+        assert_eq!(decode(&bad_combined).unwrap_err(), "bzip2 stream CRC mismatch");
+    }
+}
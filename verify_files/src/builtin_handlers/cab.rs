@@ -0,0 +1,490 @@
+//! A walker for Microsoft Cabinet (`.cab`) archives
+//!
+//! (Every CFDATA block may carry a checksum of its compressed data, which is verified whatever the
+//! compression method. MSZIP is just DEFLATE with the history carried over from one block to the
+//! next, so MSZIP folders are also decompressed, while LZX and Quantum folders are only checked as
+//! far as their checksums go.)
+
+// Standard library imports
+use std::convert::TryFrom;
+use std::io::{self, Read, Seek, SeekFrom};
+
+// 3rd-party imports
+use flate2::{Decompress, FlushDecompress, Status};
+
+// Local Imports
+use super::bytes::{le16, le32};
+use super::{corrupt, unsupported};
+
+/// The signature at the start of a CFHEADER
+const SIGNATURE: &[u8] = b"MSCF";
+
+/// The length of the fixed part of a CFHEADER
+const HEADER_LEN: usize = 36;
+/// The length of a CFFOLDER, not counting its reserved area
+const FOLDER_LEN: usize = 8;
+/// The length of the fixed part of a CFFILE
+const FILE_LEN: usize = 16;
+/// The length of a CFDATA header, not counting its reserved area
+const DATA_LEN: usize = 8;
+
+/// The longest file or cabinet name allowed, including its terminating NUL
+const MAX_NAME_LEN: usize = 256;
+/// The most data a CFDATA block may decompress to
+const MAX_UNCOMPRESSED: usize = 32768;
+/// The most compressed data a CFDATA block may hold
+const MAX_COMPRESSED: usize = MAX_UNCOMPRESSED + 6144;
+
+/// CFHEADER flag: This cabinet continues a set from a previous cabinet
+const FLAG_PREV_CABINET: u16 = 0x0001;
+/// CFHEADER flag: This cabinet's set continues into a following cabinet
+const FLAG_NEXT_CABINET: u16 = 0x0002;
+/// CFHEADER flag: The header has sizes for reserved areas in the header, folders, and blocks
+const FLAG_RESERVE_PRESENT: u16 = 0x0004;
+
+/// CFFILE folder index: The file starts in the previous cabinet, in this cabinet's first folder
+const CONTINUED_FROM_PREV: u16 = 0xFFFD;
+/// CFFILE folder index: The file continues into the next cabinet, from this cabinet's last folder
+const CONTINUED_TO_NEXT: u16 = 0xFFFE;
+/// CFFILE folder index: The file spans this cabinet, starting and ending in others
+const CONTINUED_PREV_AND_NEXT: u16 = 0xFFFF;
+
+/// The bits of a CFFOLDER's `typeCompress` which hold the compression method
+const COMPRESSION_MASK: u16 = 0x000F;
+/// Compression method: None
+const COMPRESSION_NONE: u16 = 0;
+/// Compression method: MSZIP
+const COMPRESSION_MSZIP: u16 = 1;
+/// Compression method: LZX (the highest method number defined)
+const COMPRESSION_LZX: u16 = 3;
+
+/// The signature at the start of each MSZIP block's DEFLATE data
+const MSZIP_SIGNATURE: &[u8] = b"CK";
+
+/// The CFDATA checksum, continuing from `seed`
+///
+/// (A XOR of little-endian words, with any leftover bytes combined big-endian, as cabextract and
+/// Microsoft's own tools compute it.)
+fn checksum(data: &[u8], seed: u32) -> u32 {
+    let mut words = data.chunks_exact(4);
+    let sum = words.by_ref().fold(seed, |sum, word| sum ^ le32(word));
+    sum ^ words.remainder().iter().fold(0, |tail, byte| (tail << 8) | u32::from(*byte))
+}
+
+/// Read exactly `buf.len()` bytes, reporting an early EOF as truncation of `what`
+fn read_record<R: Read>(input: &mut R, buf: &mut [u8], what: &str) -> io::Result<()> {
+    #[allow(clippy::wildcard_enum_match_arm)]
+    input.read_exact(buf).map_err(|err| match err.kind() {
+        io::ErrorKind::UnexpectedEof =>
+            corrupt(&format!("{} runs past the end of the cabinet (truncated?)", what)),
+        _ => err,
+    })
+}
+
+/// Read a NUL-terminated name, dropping the terminator
+fn read_name<R: Read>(input: &mut R, what: &str) -> io::Result<Vec<u8>> {
+    let mut name = Vec::new();
+    loop {
+        let mut byte = [0];
+        read_record(input, &mut byte, what)?;
+        if byte[0] == 0 {
+            return Ok(name);
+        }
+        name.push(byte[0]);
+        if name.len() >= MAX_NAME_LEN {
+            return Err(corrupt(&format!("{} is longer than the format allows", what)));
+        }
+    }
+}
+
+/// What [`verify`] found while walking a cabinet
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    /// The number of CFFILE entries
+    pub files: usize,
+    /// The number of CFDATA blocks
+    pub blocks: usize,
+    /// The number of CFDATA blocks with no checksum, which could only be checked for structure
+    pub unchecksummed: usize,
+    /// Whether the cabinet is part of a set, so some of its data could only be checked in part
+    pub multi_part: bool,
+}
+
+/// A CFFOLDER entry
+struct Folder {
+    /// The offset of the folder's first CFDATA block
+    data_offset: u32,
+    /// The number of CFDATA blocks in this cabinet
+    blocks: u16,
+    /// The compression method, with any method-specific parameters in the upper bits
+    compression: u16,
+}
+
+/// A CFFILE entry
+struct Entry {
+    /// The file's name, as stored
+    name: Vec<u8>,
+    /// The file's uncompressed size
+    size: u32,
+    /// The file's offset within its folder's uncompressed data
+    offset: u32,
+    /// The index of the folder holding the file, or one of the `CONTINUED_*` values
+    folder: u16,
+}
+
+/// An MSZIP decompressor, which keeps the last 32KiB of output for the next block to refer back to
+#[derive(Default)]
+struct Mszip {
+    /// The end of the previous block's decompressed data
+    history: Vec<u8>,
+}
+
+impl Mszip {
+    /// Decompress one block, which must produce exactly `expected_len` bytes, describing any
+    /// problem for [`verify_folder`] to attach to the block's location
+    fn inflate(&mut self, block: &[u8], expected_len: usize) -> Result<(), String> {
+        let data = block.strip_prefix(MSZIP_SIGNATURE)
+            .ok_or("doesn't start with the MSZIP signature")?;
+
+        // flate2 can only preset a dictionary for zlib streams, so feed the history in as a
+        // non-final stored block in front of the real data instead
+        let history_len = u16::try_from(self.history.len()).expect("at most 32KiB of history");
+        let mut input = Vec::with_capacity(5 + self.history.len() + data.len());
+        input.push(0);
+        input.extend(&history_len.to_le_bytes());
+        input.extend(&(!history_len).to_le_bytes());
+        input.extend(&self.history);
+        input.extend(data);
+
+        let mut output = vec![0; self.history.len() + expected_len + 1];
+        let mut inflater = Decompress::new(false);
+        let status = inflater.decompress(&input, &mut output, FlushDecompress::Finish)
+            .map_err(|err| format!("has bad MSZIP data: {}", err))?;
+        let total_out = usize::try_from(inflater.total_out()).expect("bounded by output.len()");
+        if status != Status::StreamEnd {
+            return Err(if total_out == output.len() {
+                format!("decompresses to more than the {} bytes its header says", expected_len)
+            } else {
+                "has truncated MSZIP data".to_owned()
+            });
+        }
+        if inflater.total_in() != input.len() as u64 {
+            return Err("has trailing data after its MSZIP stream".to_owned());
+        }
+        if total_out - self.history.len() != expected_len {
+            return Err(format!("decompresses to {} bytes instead of the {} its header says",
+                total_out - self.history.len(), expected_len));
+        }
+
+        output.truncate(total_out);
+        self.history = output.split_off(total_out.saturating_sub(MAX_UNCOMPRESSED));
+        Ok(())
+    }
+}
+
+/// Walk the CFDATA blocks of a folder, verifying their checksums (and decompressing them, for
+/// MSZIP) and returning how much data they decompress to
+///
+/// (`from_prev` and `to_next` say whether the folder is continued from or into another cabinet, and
+/// so starts or ends with part of a block which can't be decompressed on its own.)
+#[allow(clippy::too_many_arguments)]
+fn verify_folder<R: Read + Seek>(input: &mut R, folder: &Folder, index: usize, data_reserve: usize,
+        file_len: u64, from_prev: bool, to_next: bool, summary: &mut Summary) -> io::Result<u64> {
+    let method = folder.compression & COMPRESSION_MASK;
+    if method > COMPRESSION_LZX {
+        return Err(unsupported(&format!(
+            "Cabinet folder {} uses unknown compression method {}", index, method)));
+    }
+    if folder.blocks > 0 && u64::from(folder.data_offset) >= file_len {
+        return Err(corrupt(&format!(
+            "Data for cabinet folder {} starts past the end of the cabinet (truncated?)", index)));
+    }
+    input.seek(SeekFrom::Start(u64::from(folder.data_offset)))?;
+
+    // A folder continued from a previous cabinet needs that cabinet's history to decompress
+    let mut mszip = if method == COMPRESSION_MSZIP && !from_prev {
+        Some(Mszip::default())
+    } else {
+        None
+    };
+    let mut total = 0;
+    for block in 0..folder.blocks {
+        let what = format!("CFDATA block {} of cabinet folder {}", block, index);
+        let mut header = vec![0; DATA_LEN + data_reserve];
+        read_record(input, &mut header, &what)?;
+        let (expected, compressed_len, uncompressed_len) =
+            (le32(&header), usize::from(le16(&header[4..])), usize::from(le16(&header[6..])));
+        if compressed_len > MAX_COMPRESSED || uncompressed_len > MAX_UNCOMPRESSED {
+            return Err(corrupt(&format!("{} has an invalid size", what)));
+        }
+        let mut data = vec![0; compressed_len];
+        read_record(input, &mut data, &what)?;
+
+        if expected == 0 {
+            summary.unchecksummed += 1;
+        } else if checksum(&header[4..DATA_LEN], checksum(&data, 0)) != expected {
+            return Err(corrupt(&format!("{} has a bad checksum", what)));
+        }
+        summary.blocks += 1;
+
+        // A block split across cabinets records its uncompressed size in the second half only
+        if uncompressed_len == 0 {
+            if block + 1 == folder.blocks && to_next {
+                continue;
+            }
+            return Err(corrupt(&format!("{} is empty", what)));
+        }
+        let split = block == 0 && from_prev;
+        if method == COMPRESSION_NONE && !split && compressed_len != uncompressed_len {
+            return Err(corrupt(&format!(
+                "{} is stored uncompressed, but its compressed and uncompressed sizes differ",
+                what)));
+        }
+        if let Some(decompressor) = &mut mszip {
+            decompressor.inflate(&data, uncompressed_len)
+                .map_err(|msg| corrupt(&format!("{} {}", what, msg)))?;
+        }
+        total += uncompressed_len as u64;
+    }
+    Ok(total)
+}
+
+/// Walk the Microsoft Cabinet in `input` (which is `file_len` bytes long), checking that its
+/// folders, files, and data blocks all stay in bounds and verifying the data blocks' checksums
+///
+/// MSZIP-compressed folders are also decompressed. A cabinet from a multi-cabinet set is checked
+/// on its own, without looking for the others, so the data it shares with its neighbours is
+/// only checked as far as this cabinet holds it.
+pub fn verify<R: Read + Seek>(mut input: R, file_len: u64) -> io::Result<Summary> {
+    let mut header = [0; HEADER_LEN];
+    let len = usize::try_from(file_len.min(SIGNATURE.len() as u64)).expect("at most 4");
+    input.read_exact(&mut header[..len])?;
+    if !header.starts_with(SIGNATURE) {
+        return Err(corrupt("Not a Microsoft Cabinet (bad signature)"));
+    }
+    read_record(&mut input, &mut header[len..], "Cabinet header")?;
+
+    let (minor_version, major_version) = (header[24], header[25]);
+    if major_version != 1 {
+        return Err(unsupported(&format!("Cabinet format version {}.{} isn't supported",
+            major_version, minor_version)));
+    }
+    let cabinet_len = u64::from(le32(&header[8..]));
+    if cabinet_len != file_len {
+        return Err(corrupt(&format!(
+            "Cabinet header says it's {} bytes long, but the file is {} bytes{}", cabinet_len,
+            file_len, if cabinet_len > file_len { " (truncated?)" } else { "" })));
+    }
+    let files_offset = le32(&header[16..]);
+    let (folder_count, file_count, flags) =
+        (le16(&header[26..]), le16(&header[28..]), le16(&header[30..]));
+    let (from_prev, to_next) = (flags & FLAG_PREV_CABINET != 0, flags & FLAG_NEXT_CABINET != 0);
+
+    let (mut folder_reserve, mut data_reserve) = (0, 0);
+    if flags & FLAG_RESERVE_PRESENT != 0 {
+        let mut sizes = [0; 4];
+        read_record(&mut input, &mut sizes, "Cabinet header")?;
+        folder_reserve = usize::from(sizes[2]);
+        data_reserve = usize::from(sizes[3]);
+        let mut reserve = vec![0; usize::from(le16(&sizes))];
+        read_record(&mut input, &mut reserve, "Cabinet header")?;
+    }
+    for (present, what) in &[(from_prev, "Previous cabinet name"), (to_next, "Next cabinet name")] {
+        if *present {
+            read_name(&mut input, what)?;
+            read_name(&mut input, what)?;
+        }
+    }
+
+    let mut folders = Vec::with_capacity(usize::from(folder_count));
+    for index in 0..folder_count {
+        let mut record = vec![0; FOLDER_LEN + folder_reserve];
+        read_record(&mut input, &mut record, &format!("CFFOLDER {}", index))?;
+        folders.push(Folder {
+            data_offset: le32(&record),
+            blocks: le16(&record[4..]),
+            compression: le16(&record[6..]),
+        });
+    }
+
+    if file_count > 0 && u64::from(files_offset) >= file_len {
+        return Err(corrupt("Cabinet file list starts past the end of the cabinet (truncated?)"));
+    }
+    input.seek(SeekFrom::Start(u64::from(files_offset)))?;
+    let mut entries = Vec::with_capacity(usize::from(file_count));
+    for index in 0..file_count {
+        let what = format!("CFFILE {}", index);
+        let mut record = [0; FILE_LEN];
+        read_record(&mut input, &mut record, &what)?;
+        entries.push(Entry {
+            name: read_name(&mut input, &what)?,
+            size: le32(&record),
+            offset: le32(&record[4..]),
+            folder: le16(&record[8..]),
+        });
+    }
+
+    let mut summary = Summary {
+        files: entries.len(),
+        multi_part: from_prev || to_next,
+        ..Summary::default()
+    };
+    let mut folder_sizes = Vec::with_capacity(folders.len());
+    for (index, folder) in folders.iter().enumerate() {
+        folder_sizes.push(verify_folder(&mut input, folder, index, data_reserve, file_len,
+            index == 0 && from_prev, index + 1 == folders.len() && to_next, &mut summary)?);
+    }
+
+    for entry in &entries {
+        let name = String::from_utf8_lossy(&entry.name);
+        let continued = match entry.folder {
+            CONTINUED_FROM_PREV => from_prev,
+            CONTINUED_TO_NEXT => to_next,
+            CONTINUED_PREV_AND_NEXT => from_prev && to_next,
+            index => {
+                let size = folder_sizes.get(usize::from(index)).ok_or_else(|| corrupt(&format!(
+                    "Cabinet file {:?} is in folder {}, but there are only {} folders", name,
+                    index, folders.len())))?;
+
+                // Offsets in a folder continued from a previous cabinet count from its start there
+                if !(index == 0 && from_prev)
+                        && u64::from(entry.offset) + u64::from(entry.size) > *size {
+                    return Err(corrupt(&format!(
+                        "Cabinet file {:?} runs past the end of its folder's data", name)));
+                }
+                continue;
+            },
+        };
+        if !continued {
+            return Err(corrupt(&format!("Cabinet file {:?} is marked as continued in another \
+                cabinet, but the cabinet header doesn't say it's part of a set", name)));
+        }
+    }
+    Ok(summary)
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// A cabinet with one uncompressed file, made by `lcab`
+    const STORED: &[u8] = include_bytes!("../../../test_data/good/testfile.microsoft.cab");
+    /// A cabinet with one MSZIP folder, spread across several blocks
+    const MSZIP: &[u8] = include_bytes!("../../../test_data/good/testfile.mszip.cab");
+
+    /// Check `data` the way the handler would
+    fn check(data: &[u8]) -> io::Result<Summary> {
+        verify(Cursor::new(data), data.len() as u64)
+    }
+
+    /// Overwrite the CFDATA checksum at `offset` so it's correct for the block it belongs to
+    fn fix_checksum(data: &mut [u8], offset: usize) {
+        let len = usize::from(le16(&data[offset + 4..]));
+        let sum = checksum(&data[offset + 4..offset + 8],
+            checksum(&data[offset + 8..offset + 8 + len], 0));
+        data[offset..offset + 4].copy_from_slice(&sum.to_le_bytes());
+    }
+
+    #[test]
+    fn test_checksum() {
+        // Leftover bytes are combined big-endian
+        assert_eq!(checksum(b"\x01\x02\x03\x04\x05\x06\x07", 0), 0x0403_0201 ^ 0x0005_0607);
+        assert_eq!(checksum(b"\x05", 0x0100), 0x0105);
+        assert_eq!(checksum(b"", 0x1234), 0x1234);
+    }
+
+    #[test]
+    fn test_good_cabinets() {
+        assert_eq!(check(STORED).map_err(|err| err.to_string()),
+            Ok(Summary { files: 1, blocks: 1, ..Summary::default() }));
+        assert_eq!(check(MSZIP).map_err(|err| err.to_string()),
+            Ok(Summary { files: 2, blocks: 4, ..Summary::default() }));
+
+        // Both halves of a set, split partway through a block, which can't be decompressed
+        for (name, data, files, blocks) in &[
+            ("part1", &include_bytes!("../../../test_data/good/testfile.part1.cab")[..], 2, 2),
+            ("part2", include_bytes!("../../../test_data/good/testfile.part2.cab"), 1, 3),
+        ] {
+            assert_eq!(check(data).map_err(|err| err.to_string()),
+                Ok(Summary { files: *files, blocks: *blocks, unchecksummed: 0,
+                    multi_part: true }), "{}", name);
+        }
+
+        // Checksums are optional
+        let mut unchecksummed = MSZIP.to_vec();
+        let offset = usize::try_from(le32(&MSZIP[36..])).unwrap();
+        unchecksummed[offset..offset + 4].copy_from_slice(&[0; 4]);
+        assert_eq!(check(&unchecksummed).map(|summary| summary.unchecksummed).ok(), Some(1));
+    }
+
+    #[test]
+    fn test_bad_cabinets() {
+        let data_offset = usize::try_from(le32(&MSZIP[36..])).unwrap();
+        let block_len = usize::from(le16(&MSZIP[data_offset + 4..]));
+
+        // Damaged deflate data, with the checksum fixed up or removed so only MSZIP can notice
+        let mut bad_deflate = MSZIP.to_vec();
+        bad_deflate[data_offset + 8 + block_len / 2] ^= 0x55;
+        fix_checksum(&mut bad_deflate, data_offset);
+
+        // A second block with a wrong uncompressed size (so the history went astray)
+        let mut bad_size = MSZIP.to_vec();
+        let second = data_offset + 8 + block_len;
+        bad_size[second + 6..second + 8].copy_from_slice(&0x7FFF_u16.to_le_bytes());
+        fix_checksum(&mut bad_size, second);
+
+        // A file which claims more data than its folder holds
+        let mut bad_file = STORED.to_vec();
+        bad_file[0x2C] = 0x0D;
+
+        // A file claiming to be continued when the cabinet isn't part of a set
+        let mut bad_continued = STORED.to_vec();
+        bad_continued[0x34..0x36].copy_from_slice(&CONTINUED_TO_NEXT.to_le_bytes());
+
+        // A stored block whose sizes disagree
+        let mut bad_stored = STORED.to_vec();
+        bad_stored[0x4F] = 0x0B;
+        fix_checksum(&mut bad_stored, 0x49);
+
+        let mut trailing = STORED.to_vec();
+        trailing.push(0);
+
+        for (name, data, message) in &[
+            ("bad checksum", &include_bytes!("../../../test_data/bad/testfile.mszip.cab")[..],
+                "bad checksum"),
+            ("bad flags", include_bytes!("../../../test_data/bad/testfile.microsoft.cab"),
+                "past the end"),
+            ("bad deflate", &bad_deflate, "MSZIP"),
+            ("bad size", &bad_size, "decompresses to"),
+            ("bad file", &bad_file, "runs past the end of its folder"),
+            ("bad continued", &bad_continued, "part of a set"),
+            ("bad stored", &bad_stored, "sizes differ"),
+            ("trailing", &trailing, "but the file is 94 bytes"),
+            ("not a cabinet", b"PK\x03\x04 not a cabinet at all", "Not a Microsoft Cabinet"),
+            ("empty", b"", "Not a Microsoft Cabinet"),
+        ] {
+            let err = check(data).expect_err(name);
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}: {}", name, err);
+            assert!(err.to_string().contains(message), "{}: {}", name, err);
+        }
+    }
+
+    #[test]
+    fn test_truncated() {
+        for data in &[STORED, MSZIP] {
+            for len in 1..data.len() {
+                // Also shorten the size in the header, so the walk itself has to notice
+                let mut truncated = data[..len].to_vec();
+                if len >= 12 {
+                    truncated[8..12].copy_from_slice(&u32::try_from(len).unwrap().to_le_bytes());
+                }
+                let err = check(&truncated).expect_err("truncated");
+                assert_eq!(err.kind(), io::ErrorKind::InvalidData, "cut to {}: {}", len, err);
+            }
+        }
+    }
+}
@@ -0,0 +1,679 @@
+//! Checksum algorithms needed by the built-in handlers beyond the CRC32 which `crc32fast`
+//! already provides
+
+// Standard library imports
+use std::convert::TryInto;
+
+/// The (unreflected) polynomial used by the MPEG audio variant of CRC-16
+const CRC16_POLY: u16 = 0x8005;
+
+/// A lookup table for computing [`Crc16`] a byte at a time
+const CRC16_TABLE: [u16; 256] = {
+    let mut table = [0; 256];
+    let mut idx = 0;
+    while idx < 256 {
+        let mut crc = (idx as u16) << 8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000 == 0 { crc << 1 } else { (crc << 1) ^ CRC16_POLY };
+            bit += 1;
+        }
+        table[idx] = crc;
+        idx += 1;
+    }
+    table
+};
+
+/// An incremental CRC-16 (the variant which MPEG audio and AAC ADTS frames use, starting from
+/// `0xFFFF`)
+pub struct Crc16 {
+    /// The CRC of everything so far
+    state: u16,
+}
+
+impl Crc16 {
+    /// Start a new checksum
+    pub fn new() -> Self {
+        Self { state: 0xFFFF }
+    }
+
+    /// Add `data` to the checksum
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            let idx = usize::from(byte ^ (self.state >> 8) as u8);
+            self.state = CRC16_TABLE[idx] ^ (self.state << 8);
+        }
+    }
+
+    /// The checksum of everything added so far
+    pub fn finish(&self) -> u16 {
+        self.state
+    }
+}
+
+/// The reflected ECMA-182 polynomial used by the `.xz` variant of CRC-64
+const CRC64_POLY: u64 = 0xC96C_5795_D787_0F42;
+
+/// A lookup table for computing [`Crc64`] a byte at a time
+const CRC64_TABLE: [u64; 256] = {
+    let mut table = [0; 256];
+    let mut idx = 0;
+    while idx < 256 {
+        let mut crc = idx as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ CRC64_POLY } else { crc >> 1 };
+            bit += 1;
+        }
+        table[idx] = crc;
+        idx += 1;
+    }
+    table
+};
+
+/// An incremental CRC-64 (the ECMA-182 variant, as used by `.xz`)
+pub struct Crc64 {
+    /// The inverted CRC of everything so far
+    state: u64,
+}
+
+impl Crc64 {
+    /// Start a new checksum
+    pub fn new() -> Self {
+        Self { state: !0 }
+    }
+
+    /// Add `data` to the checksum
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.state = CRC64_TABLE[usize::from(byte ^ self.state as u8)] ^ (self.state >> 8);
+        }
+    }
+
+    /// The checksum of everything added so far
+    pub fn finish(&self) -> u64 {
+        !self.state
+    }
+}
+
+/// Bob Jenkins's `lookup3` hash (`hashlittle`, with an initial value of 0), as used by HDF5 to
+/// checksum its metadata
+///
+/// (Unlike the others, this needs to know the length up front, so it isn't incremental.)
+pub fn lookup3(data: &[u8]) -> u32 {
+    /// Mix three 32-bit values reversibly
+    fn mix(a: &mut u32, b: &mut u32, c: &mut u32) {
+        *a = a.wrapping_sub(*c) ^ c.rotate_left(4);
+        *c = c.wrapping_add(*b);
+        *b = b.wrapping_sub(*a) ^ a.rotate_left(6);
+        *a = a.wrapping_add(*c);
+        *c = c.wrapping_sub(*b) ^ b.rotate_left(8);
+        *b = b.wrapping_add(*a);
+        *a = a.wrapping_sub(*c) ^ c.rotate_left(16);
+        *c = c.wrapping_add(*b);
+        *b = b.wrapping_sub(*a) ^ a.rotate_left(19);
+        *a = a.wrapping_add(*c);
+        *c = c.wrapping_sub(*b) ^ b.rotate_left(4);
+        *b = b.wrapping_add(*a);
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    let seed = 0xdead_beef_u32.wrapping_add(data.len() as u32);
+    let (mut a, mut b, mut c) = (seed, seed, seed);
+    let word = |bytes: &[u8]| {
+        let mut padded = [0; 4];
+        padded[..bytes.len()].copy_from_slice(bytes);
+        u32::from_le_bytes(padded)
+    };
+
+    let mut rest = data;
+    while rest.len() > 12 {
+        a = a.wrapping_add(word(&rest[..4]));
+        b = b.wrapping_add(word(&rest[4..8]));
+        c = c.wrapping_add(word(&rest[8..12]));
+        mix(&mut a, &mut b, &mut c);
+        rest = &rest[12..];
+    }
+    if rest.is_empty() {
+        return c;
+    }
+    a = a.wrapping_add(word(&rest[..rest.len().min(4)]));
+    b = b.wrapping_add(word(rest.get(4..rest.len().min(8)).unwrap_or_default()));
+    c = c.wrapping_add(word(rest.get(8..).unwrap_or_default()));
+
+    // The final mix
+    for &(x, y, z) in &[(14, 11, 25), (16, 4, 14)] {
+        c = (c ^ b).wrapping_sub(b.rotate_left(x));
+        a = (a ^ c).wrapping_sub(c.rotate_left(y));
+        b = (b ^ a).wrapping_sub(a.rotate_left(z));
+    }
+    (c ^ b).wrapping_sub(b.rotate_left(24))
+}
+
+/// The MD5 round constants
+const MD5_K: [u32; 64] = [
+    0xd76a_a478, 0xe8c7_b756, 0x2420_70db, 0xc1bd_ceee, 0xf57c_0faf, 0x4787_c62a, 0xa830_4613,
+    0xfd46_9501, 0x6980_98d8, 0x8b44_f7af, 0xffff_5bb1, 0x895c_d7be, 0x6b90_1122, 0xfd98_7193,
+    0xa679_438e, 0x49b4_0821, 0xf61e_2562, 0xc040_b340, 0x265e_5a51, 0xe9b6_c7aa, 0xd62f_105d,
+    0x0244_1453, 0xd8a1_e681, 0xe7d3_fbc8, 0x21e1_cde6, 0xc337_07d6, 0xf4d5_0d87, 0x455a_14ed,
+    0xa9e3_e905, 0xfcef_a3f8, 0x676f_02d9, 0x8d2a_4c8a, 0xfffa_3942, 0x8771_f681, 0x6d9d_6122,
+    0xfde5_380c, 0xa4be_ea44, 0x4bde_cfa9, 0xf6bb_4b60, 0xbebf_bc70, 0x289b_7ec6, 0xeaa1_27fa,
+    0xd4ef_3085, 0x0488_1d05, 0xd9d4_d039, 0xe6db_99e5, 0x1fa2_7cf8, 0xc4ac_5665, 0xf429_2244,
+    0x432a_ff97, 0xab94_23a7, 0xfc93_a039, 0x655b_59c3, 0x8f0c_cc92, 0xffef_f47d, 0x8584_5dd1,
+    0x6fa8_7e4f, 0xfe2c_e6e0, 0xa301_4314, 0x4e08_11a1, 0xf753_7e82, 0xbd3a_f235, 0x2ad7_d2bb,
+    0xeb86_d391,
+];
+
+/// The per-step rotation amounts for each of MD5's four rounds
+const MD5_SHIFTS: [[u32; 4]; 4] =
+    [[7, 12, 17, 22], [5, 9, 14, 20], [4, 11, 16, 23], [6, 10, 15, 21]];
+
+/// An incremental MD5 hash (only for checking formats which still use it, such as RPM)
+pub struct Md5 {
+    /// The hash state after the last complete block
+    state: [u32; 4],
+    /// Bytes not yet hashed because they don't make up a complete block
+    pending: [u8; 64],
+    /// How many bytes of `pending` are in use
+    pending_len: usize,
+    /// How many bytes have been added in total
+    total_len: u64,
+}
+
+impl Md5 {
+    /// Start a new hash
+    pub fn new() -> Self {
+        Self {
+            state: [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476],
+            pending: [0; 64],
+            pending_len: 0,
+            total_len: 0,
+        }
+    }
+
+    /// Add `data` to the hash
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.total_len += data.len() as u64;
+        if self.pending_len > 0 {
+            let len = data.len().min(64 - self.pending_len);
+            self.pending[self.pending_len..self.pending_len + len].copy_from_slice(&data[..len]);
+            self.pending_len += len;
+            data = &data[len..];
+            if self.pending_len < 64 {
+                return;
+            }
+            let block = self.pending;
+            self.compress(&block);
+            self.pending_len = 0;
+        }
+        let mut blocks = data.chunks_exact(64);
+        for block in &mut blocks {
+            self.compress(block);
+        }
+        let rest = blocks.remainder();
+        self.pending[..rest.len()].copy_from_slice(rest);
+        self.pending_len = rest.len();
+    }
+
+    /// The hash of everything added so far
+    pub fn finish(mut self) -> [u8; 16] {
+        let bit_len = self.total_len.wrapping_mul(8);
+        // The same padding as SHA-256, but with the length stored little-endian
+        let padding_len = (119 - self.pending_len) % 64 + 1;
+        let mut padding = [0; 72];
+        padding[0] = 0x80;
+        padding[padding_len..padding_len + 8].copy_from_slice(&bit_len.to_le_bytes());
+        self.update(&padding[..padding_len + 8]);
+
+        let mut digest = [0; 16];
+        for (chunk, word) in digest.chunks_exact_mut(4).zip(&self.state) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        digest
+    }
+
+    /// Mix one 64-byte block into the hash state
+    fn compress(&mut self, block: &[u8]) {
+        let mut words = [0_u32; 16];
+        for (word, bytes) in words.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+
+        let [mut a, mut b, mut c, mut d] = self.state;
+        for (idx, k) in MD5_K.iter().enumerate() {
+            let (mixed, word) = match idx / 16 {
+                0 => ((b & c) | (!b & d), idx),
+                1 => ((d & b) | (!d & c), (5 * idx + 1) % 16),
+                2 => (b ^ c ^ d, (3 * idx + 5) % 16),
+                _ => (c ^ (b | !d), (7 * idx) % 16),
+            };
+            let sum = a.wrapping_add(mixed).wrapping_add(*k).wrapping_add(words[word]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(sum.rotate_left(MD5_SHIFTS[idx / 16][idx % 4]));
+        }
+        for (state, value) in self.state.iter_mut().zip(&[a, b, c, d]) {
+            *state = state.wrapping_add(*value);
+        }
+    }
+}
+
+/// The (unreflected) polynomial used by Ogg's variant of CRC-32
+const OGG_CRC_POLY: u32 = 0x04C1_1DB7;
+
+/// A lookup table for computing [`OggCrc`] a byte at a time
+const OGG_CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut idx = 0;
+    while idx < 256 {
+        let mut crc = (idx as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000_0000 == 0 { crc << 1 } else { (crc << 1) ^ OGG_CRC_POLY };
+            bit += 1;
+        }
+        table[idx] = crc;
+        idx += 1;
+    }
+    table
+};
+
+/// An incremental CRC-32 (the variant used by Ogg pages, which isn't the one `crc32fast` does)
+pub struct OggCrc {
+    /// The CRC of everything so far
+    state: u32,
+}
+
+impl OggCrc {
+    /// Start a new checksum
+    pub fn new() -> Self {
+        Self { state: 0 }
+    }
+
+    /// Add `data` to the checksum
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            let idx = usize::from(byte ^ (self.state >> 24) as u8);
+            self.state = OGG_CRC_TABLE[idx] ^ (self.state << 8);
+        }
+    }
+
+    /// The checksum of everything added so far
+    pub fn finish(&self) -> u32 {
+        self.state
+    }
+}
+
+/// An incremental OpenType table checksum (the wrapping sum of the data as big-endian `u32`s,
+/// with the last one padded out with zeroes)
+pub struct SfntChecksum {
+    /// The sum of everything so far
+    state: u32,
+    /// How far into a `u32` the next byte falls
+    position: u8,
+}
+
+impl SfntChecksum {
+    /// Start a new checksum
+    pub fn new() -> Self {
+        Self { state: 0, position: 0 }
+    }
+
+    /// Add `data` to the checksum
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.state = self.state.wrapping_add(u32::from(byte) << (24 - 8 * self.position));
+            self.position = (self.position + 1) % 4;
+        }
+    }
+
+    /// The checksum of everything added so far
+    pub fn finish(&self) -> u32 {
+        self.state
+    }
+}
+
+/// The SHA-256 round constants
+const SHA256_K: [u32; 64] = [
+    0x428a_2f98, 0x7137_4491, 0xb5c0_fbcf, 0xe9b5_dba5, 0x3956_c25b, 0x59f1_11f1, 0x923f_82a4,
+    0xab1c_5ed5, 0xd807_aa98, 0x1283_5b01, 0x2431_85be, 0x550c_7dc3, 0x72be_5d74, 0x80de_b1fe,
+    0x9bdc_06a7, 0xc19b_f174, 0xe49b_69c1, 0xefbe_4786, 0x0fc1_9dc6, 0x240c_a1cc, 0x2de9_2c6f,
+    0x4a74_84aa, 0x5cb0_a9dc, 0x76f9_88da, 0x983e_5152, 0xa831_c66d, 0xb003_27c8, 0xbf59_7fc7,
+    0xc6e0_0bf3, 0xd5a7_9147, 0x06ca_6351, 0x1429_2967, 0x27b7_0a85, 0x2e1b_2138, 0x4d2c_6dfc,
+    0x5338_0d13, 0x650a_7354, 0x766a_0abb, 0x81c2_c92e, 0x9272_2c85, 0xa2bf_e8a1, 0xa81a_664b,
+    0xc24b_8b70, 0xc76c_51a3, 0xd192_e819, 0xd699_0624, 0xf40e_3585, 0x106a_a070, 0x19a4_c116,
+    0x1e37_6c08, 0x2748_774c, 0x34b0_bcb5, 0x391c_0cb3, 0x4ed8_aa4a, 0x5b9c_ca4f, 0x682e_6ff3,
+    0x748f_82ee, 0x78a5_636f, 0x84c8_7814, 0x8cc7_0208, 0x90be_fffa, 0xa450_6ceb, 0xbef9_a3f7,
+    0xc671_78f2,
+];
+
+/// An incremental SHA-256 hash
+pub struct Sha256 {
+    /// The hash state after the last complete block
+    state: [u32; 8],
+    /// Bytes not yet hashed because they don't make up a complete block
+    pending: [u8; 64],
+    /// How many bytes of `pending` are in use
+    pending_len: usize,
+    /// How many bytes have been added in total
+    total_len: u64,
+}
+
+impl Sha256 {
+    /// Start a new hash
+    pub fn new() -> Self {
+        Self {
+            state: [
+                0x6a09_e667, 0xbb67_ae85, 0x3c6e_f372, 0xa54f_f53a, 0x510e_527f, 0x9b05_688c,
+                0x1f83_d9ab, 0x5be0_cd19,
+            ],
+            pending: [0; 64],
+            pending_len: 0,
+            total_len: 0,
+        }
+    }
+
+    /// Add `data` to the hash
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.total_len += data.len() as u64;
+        if self.pending_len > 0 {
+            let len = data.len().min(64 - self.pending_len);
+            self.pending[self.pending_len..self.pending_len + len].copy_from_slice(&data[..len]);
+            self.pending_len += len;
+            data = &data[len..];
+            if self.pending_len < 64 {
+                return;
+            }
+            let block = self.pending;
+            self.compress(&block);
+            self.pending_len = 0;
+        }
+        let mut blocks = data.chunks_exact(64);
+        for block in &mut blocks {
+            self.compress(block);
+        }
+        let rest = blocks.remainder();
+        self.pending[..rest.len()].copy_from_slice(rest);
+        self.pending_len = rest.len();
+    }
+
+    /// The hash of everything added so far
+    pub fn finish(mut self) -> [u8; 32] {
+        let bit_len = self.total_len.wrapping_mul(8);
+        // Pad with a 1 bit and then zeroes, leaving room for the length in the last block
+        let padding_len = (119 - self.pending_len) % 64 + 1;
+        let mut padding = [0; 72];
+        padding[0] = 0x80;
+        padding[padding_len..padding_len + 8].copy_from_slice(&bit_len.to_be_bytes());
+        self.update(&padding[..padding_len + 8]);
+
+        let mut digest = [0; 32];
+        for (chunk, word) in digest.chunks_exact_mut(4).zip(&self.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    /// Mix one 64-byte block into the hash state
+    fn compress(&mut self, block: &[u8]) {
+        let mut schedule = [0_u32; 64];
+        for (word, bytes) in schedule.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for idx in 16..64 {
+            let (early, late) = (schedule[idx - 15], schedule[idx - 2]);
+            let s0 = early.rotate_right(7) ^ early.rotate_right(18) ^ (early >> 3);
+            let s1 = late.rotate_right(17) ^ late.rotate_right(19) ^ (late >> 10);
+            schedule[idx] = schedule[idx - 16]
+                .wrapping_add(s0)
+                .wrapping_add(schedule[idx - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for (k, word) in SHA256_K.iter().zip(&schedule) {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let choice = (e & f) ^ (!e & g);
+            let temp1 =
+                h.wrapping_add(s1).wrapping_add(choice).wrapping_add(*k).wrapping_add(*word);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let majority = (a & b) ^ (a & c) ^ (b & c);
+            let temp2 = s0.wrapping_add(majority);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(temp1);
+            d = c;
+            c = b;
+            b = a;
+            a = temp1.wrapping_add(temp2);
+        }
+        for (state, value) in self.state.iter_mut().zip(&[a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(*value);
+        }
+    }
+}
+
+/// The primes XXH64 is built from
+const XXH_PRIMES: [u64; 5] = [
+    0x9E37_79B1_85EB_CA87, 0xC2B2_AE3D_27D4_EB4F, 0x1656_67B1_9E37_79F9, 0x85EB_CA77_C2B2_AE63,
+    0x27D4_EB2F_1656_67C5,
+];
+
+/// Read a little-endian `u64` from the start of `bytes`
+fn le_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes[..8].try_into().expect("8-byte slice"))
+}
+
+/// Mix one 64-bit lane into an XXH64 accumulator
+fn xxh_round(acc: u64, input: u64) -> u64 {
+    acc.wrapping_add(input.wrapping_mul(XXH_PRIMES[1])).rotate_left(31).wrapping_mul(XXH_PRIMES[0])
+}
+
+/// An incremental XXH64 hash with a seed of zero (as used by Zstandard)
+pub struct Xxh64 {
+    /// The four accumulators which 32-byte stripes are mixed into
+    acc: [u64; 4],
+    /// Bytes not yet mixed in because they don't make up a complete stripe
+    pending: [u8; 32],
+    /// How many bytes of `pending` are in use
+    pending_len: usize,
+    /// How many bytes have been added in total
+    total_len: u64,
+}
+
+impl Xxh64 {
+    /// Start a new hash
+    pub fn new() -> Self {
+        let [p1, p2, ..] = XXH_PRIMES;
+        Self {
+            acc: [p1.wrapping_add(p2), p2, 0, 0_u64.wrapping_sub(p1)],
+            pending: [0; 32],
+            pending_len: 0,
+            total_len: 0,
+        }
+    }
+
+    /// Add `data` to the hash
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.total_len += data.len() as u64;
+        if self.pending_len > 0 {
+            let len = data.len().min(32 - self.pending_len);
+            self.pending[self.pending_len..self.pending_len + len].copy_from_slice(&data[..len]);
+            self.pending_len += len;
+            data = &data[len..];
+            if self.pending_len < 32 {
+                return;
+            }
+            let stripe = self.pending;
+            self.stripe(&stripe);
+            self.pending_len = 0;
+        }
+        let mut stripes = data.chunks_exact(32);
+        for stripe in &mut stripes {
+            self.stripe(stripe);
+        }
+        let rest = stripes.remainder();
+        self.pending[..rest.len()].copy_from_slice(rest);
+        self.pending_len = rest.len();
+    }
+
+    /// Mix one 32-byte stripe into the accumulators
+    fn stripe(&mut self, stripe: &[u8]) {
+        for (acc, lane) in self.acc.iter_mut().zip(stripe.chunks_exact(8)) {
+            *acc = xxh_round(*acc, le_u64(lane));
+        }
+    }
+
+    /// The hash of everything added so far
+    pub fn finish(&self) -> u64 {
+        let [p1, p2, p3, p4, p5] = XXH_PRIMES;
+        let mut hash = if self.total_len >= 32 {
+            let [a, b, c, d] = self.acc;
+            let mut hash = a
+                .rotate_left(1)
+                .wrapping_add(b.rotate_left(7))
+                .wrapping_add(c.rotate_left(12))
+                .wrapping_add(d.rotate_left(18));
+            for acc in &self.acc {
+                hash = (hash ^ xxh_round(0, *acc)).wrapping_mul(p1).wrapping_add(p4);
+            }
+            hash
+        } else {
+            p5
+        };
+        hash = hash.wrapping_add(self.total_len);
+
+        let mut rest = &self.pending[..self.pending_len];
+        while rest.len() >= 8 {
+            hash ^= xxh_round(0, le_u64(rest));
+            hash = hash.rotate_left(27).wrapping_mul(p1).wrapping_add(p4);
+            rest = &rest[8..];
+        }
+        if rest.len() >= 4 {
+            let word = u32::from_le_bytes(rest[..4].try_into().expect("4-byte slice"));
+            hash ^= u64::from(word).wrapping_mul(p1);
+            hash = hash.rotate_left(23).wrapping_mul(p2).wrapping_add(p3);
+            rest = &rest[4..];
+        }
+        for &byte in rest {
+            hash ^= u64::from(byte).wrapping_mul(p5);
+            hash = hash.rotate_left(11).wrapping_mul(p1);
+        }
+
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(p2);
+        hash ^= hash >> 29;
+        hash = hash.wrapping_mul(p3);
+        hash ^ (hash >> 32)
+    }
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The `lookup3` hashes of the first 0 to 26 letters of the alphabet
+    const LOOKUP3_LENGTHS: [u32; 27] = [0xdead_beef, 0x58d6_8708, 0xfbb3_a8df, 0x0e39_7631,
+        0xb5f4_889c, 0x026d_72de, 0xd6fa_502e, 0xb11a_d4a5, 0x2995_c3be, 0xac65_72b4, 0x8bf7_d2ef,
+        0x5f61_edf8, 0x4012_f87b, 0x9281_28f9, 0x2bb8_4ef8, 0xa9ce_8fb6, 0x1134_7272, 0x8938_634e,
+        0x1cea_f360, 0x02a8_0e47, 0x3727_07b2, 0xdfa3_b04b, 0xa975_2892, 0x4e25_bfff, 0x1b63_1fea,
+        0x6c29_c5e2, 0x7538_b5bd];
+
+    #[test]
+    fn test_crc16() {
+        let mut crc = Crc16::new();
+        assert_eq!(crc.finish(), 0xFFFF);
+        crc.update(b"12345");
+        crc.update(b"6789");
+        assert_eq!(crc.finish(), 0xAEE7);
+    }
+
+    #[test]
+    fn test_crc64() {
+        let mut crc = Crc64::new();
+        assert_eq!(crc.finish(), 0);
+        crc.update(b"12345");
+        crc.update(b"6789");
+        assert_eq!(crc.finish(), 0x995D_C9BB_DF19_39FA);
+    }
+
+    #[test]
+    fn test_lookup3() {
+        // The test vectors from lookup3.c itself, plus each length the tail handling distinguishes
+        assert_eq!(lookup3(b""), 0xdead_beef);
+        assert_eq!(lookup3(b"Four score and seven years ago"), 0x1777_0551);
+        for (len, expected) in LOOKUP3_LENGTHS.iter().enumerate() {
+            assert_eq!(lookup3(&b"abcdefghijklmnopqrstuvwxyz"[..len]), *expected, "{}", len);
+        }
+    }
+
+    #[test]
+    fn test_md5() {
+        let hex = |digest: [u8; 16]| {
+            digest.iter().map(|x| format!("{:02x}", x)).collect::<String>()
+        };
+        assert_eq!(hex(Md5::new().finish()), "d41d8cd98f00b204e9800998ecf8427e");
+
+        // Split across calls at awkward points to exercise the buffering
+        let mut md5 = Md5::new();
+        md5.update(b"12345678901234567890123456789012345678901234567890");
+        md5.update(b"123456789012345678901234567890");
+        assert_eq!(hex(md5.finish()), "57edf4a22be3c955ac49da2e2107b67a");
+    }
+
+    #[test]
+    fn test_ogg_crc() {
+        let mut crc = OggCrc::new();
+        assert_eq!(crc.finish(), 0);
+        crc.update(b"12345");
+        crc.update(b"6789");
+        assert_eq!(crc.finish(), 0x89A1_897F);
+    }
+
+    #[test]
+    fn test_xxh64() {
+        assert_eq!(Xxh64::new().finish(), 0xEF46_DB37_51D8_E999);
+        let mut xxh = Xxh64::new();
+        xxh.update(b"a");
+        xxh.update(b"bc");
+        assert_eq!(xxh.finish(), 0x44BC_2CF5_AD77_0999);
+    }
+
+    #[test]
+    fn test_sfnt_checksum() {
+        let mut sum = SfntChecksum::new();
+        assert_eq!(sum.finish(), 0);
+        sum.update(b"\x00\x00\x00");
+        sum.update(b"\x01\xFF\xFF\xFF\xFF");
+        assert_eq!(sum.finish(), 0);
+        sum.update(b"\x12\x34");
+        assert_eq!(sum.finish(), 0x1234_0000);
+    }
+
+    #[test]
+    fn test_sha256() {
+        let hex = |digest: [u8; 32]| {
+            digest.iter().map(|x| format!("{:02x}", x)).collect::<String>()
+        };
+        assert_eq!(hex(Sha256::new().finish()),
+                   "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+
+        // Split across calls at awkward points to exercise the buffering
+        let mut sha = Sha256::new();
+        sha.update(b"abcdbcdecdefdefgefghfghighijhi");
+        sha.update(b"jkijkljklmklmnlmnomnopnopq");
+        assert_eq!(hex(sha.finish()),
+                   "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1");
+
+        let mut sha = Sha256::new();
+        for _ in 0..1000 {
+            sha.update(&[b'a'; 1000]);
+        }
+        assert_eq!(hex(sha.finish()),
+                   "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0");
+    }
+}
@@ -0,0 +1,211 @@
+//! A structural checker for CSV files, following RFC 4180 with the usual real-world leniency
+//!
+//! (Any line terminator is accepted, blank lines are skipped, a UTF-8 byte order mark is ignored,
+//! and a `"` inside an unquoted field is taken literally. What's refused is what truncation or
+//! damage to the quoting leaves behind: invalid UTF-8, characters between a closing quote and the
+//! next delimiter, a quoted field which never ends, and a last record which stops partway.)
+
+// Standard library imports
+use std::io::{self, BufReader, Read};
+
+// Local Imports
+use super::corrupt;
+
+/// The byte order mark some tools put at the start of UTF-8 CSV files
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Where the parser is within a record
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    /// At the start of a field
+    FieldStart,
+    /// Inside a field which didn't start with a quote
+    Unquoted,
+    /// Inside a quoted field
+    Quoted,
+    /// Just after a quote inside a quoted field, which either closes it or escapes another quote
+    QuoteInQuoted,
+}
+
+/// The record being parsed and what's known about the ones before it
+struct Parser {
+    /// Whether every record must have as many fields as the header
+    uniform: bool,
+    /// The number of the current record, counting the header as record 1
+    record: u64,
+    /// The line the current record started on
+    record_line: u64,
+    /// How many fields of the current record have ended so far
+    fields: usize,
+    /// How many fields the header had, once it has been parsed
+    header_fields: Option<usize>,
+    /// The contents of the current field, kept until it ends so it can be checked as UTF-8
+    field: Vec<u8>,
+}
+
+impl Parser {
+    /// Build an error message about the current record
+    fn error(&self, message: &str) -> io::Error {
+        corrupt(&format!("CSV record {} (line {}) {}", self.record, self.record_line, message))
+    }
+
+    /// Finish the current field
+    fn end_field(&mut self) -> io::Result<()> {
+        if std::str::from_utf8(&self.field).is_err() {
+            return Err(self.error("isn't valid UTF-8"));
+        }
+        self.field.clear();
+        self.fields += 1;
+        Ok(())
+    }
+
+    /// Finish the current record, which must have had its last field ended already
+    fn end_record(&mut self) -> io::Result<()> {
+        match self.header_fields {
+            None => self.header_fields = Some(self.fields),
+            Some(expected) if self.uniform && self.fields != expected => {
+                return Err(self.error(&format!("has {} field(s), but the header has {}",
+                                               self.fields, expected)));
+            },
+            Some(_) => {},
+        }
+        self.record += 1;
+        self.fields = 0;
+        Ok(())
+    }
+}
+
+/// Check that the CSV data read from `input` parses cleanly, record by record, to the end
+///
+/// If `uniform` is set, every record must also have as many fields as the first one. Otherwise,
+/// ragged records are accepted, except that a last record which has a different number of fields
+/// than the header and no line terminator is reported as cut off, since that's what truncation
+/// looks like.
+pub fn verify(mut input: impl Read, uniform: bool) -> io::Result<()> {
+    let mut head = Vec::with_capacity(UTF8_BOM.len());
+    input.by_ref().take(UTF8_BOM.len() as u64).read_to_end(&mut head)?;
+    if head == UTF8_BOM {
+        head.clear();
+    }
+
+    let mut parser = Parser { uniform, record: 1, record_line: 1, fields: 0, header_fields: None,
+                              field: Vec::new() };
+    let (mut state, mut line, mut prev) = (State::FieldStart, 1, 0);
+    for byte in BufReader::new(head.as_slice().chain(input)).bytes() {
+        let byte = byte?;
+        if prev == b'\n' || (prev == b'\r' && byte != b'\n') {
+            line += 1;
+        }
+        prev = byte;
+
+        state = match (state, byte) {
+            (State::FieldStart, b'\r' | b'\n') if parser.fields == 0 => State::FieldStart,
+            (State::FieldStart | State::Unquoted | State::QuoteInQuoted, b'\r' | b'\n') => {
+                parser.end_field()?;
+                parser.end_record()?;
+                State::FieldStart
+            },
+            (State::FieldStart | State::Unquoted | State::QuoteInQuoted, b',') => {
+                if parser.fields == 0 && state == State::FieldStart {
+                    parser.record_line = line;
+                }
+                parser.end_field()?;
+                State::FieldStart
+            },
+            (State::FieldStart, _) => {
+                if parser.fields == 0 {
+                    parser.record_line = line;
+                }
+                if byte == b'"' {
+                    State::Quoted
+                } else {
+                    parser.field.push(byte);
+                    State::Unquoted
+                }
+            },
+            (State::Quoted, b'"') => State::QuoteInQuoted,
+            (State::QuoteInQuoted, b'"') => {
+                parser.field.push(byte);
+                State::Quoted
+            },
+            (State::Quoted | State::Unquoted, _) => {
+                parser.field.push(byte);
+                state
+            },
+            (State::QuoteInQuoted, _) => {
+                return Err(parser.error("has characters after the closing quote of a field"));
+            },
+        };
+    }
+
+    match state {
+        State::Quoted => Err(parser.error("has a quoted field which is never closed")),
+        State::FieldStart if parser.fields == 0 => Ok(()),
+        _ => {
+            parser.end_field()?;
+            if parser.header_fields.map_or(false, |expected| parser.fields != expected) {
+                return Err(parser.error("is cut off at the end of the file"));
+            }
+            parser.end_record()
+        },
+    }
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Verify `input`, returning the error message on failure
+    fn verify_str(input: &str, uniform: bool) -> Result<(), String> {
+        verify(input.as_bytes(), uniform).map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn test_good() {
+        for input in &[
+            "",
+            "\r\n\n",
+            "a,b,c\n1,2,3\n",
+            "a,b,c\r\n1,2,3\r\n4,5,6",
+            "a,b,c\r1,2,3\r",
+            "\u{FEFF}a,b\n1,2\n",
+            "a,b\n\n1,2\n\r\n3,4\n",
+            "a,\"b, with a comma\"\n\"1\",\"2 \"\"quoted\"\"\"\n",
+            "a,b\n\"multi\r\nline\",\"\"\n",
+            "a,b\n1,2\"3\n",
+            "a,b\n,\n",
+            "\u{E9}t\u{E9},na\u{EF}ve\n",
+        ] {
+            assert_eq!(verify_str(input, true), Ok(()), "{:?}", input);
+        }
+        assert_eq!(verify_str("a,b,c\n1,2\n3,4,5,6\n7,8,9", false), Ok(()));
+    }
+
+    #[test]
+    fn test_bad() {
+        for (input, uniform, message) in &[
+            (&b"a,b\n1,\"2\n"[..], false,
+             "CSV record 2 (line 2) has a quoted field which is never closed"),
+            (b"a,b\n1,\"2\"x\n", false,
+             "CSV record 2 (line 2) has characters after the closing quote of a field"),
+            (b"a,b,c\n\n1,2\n", true, "CSV record 2 (line 3) has 2 field(s), but the header has 3"),
+            (b"a,b,c\n\"1\n2\",3,4,5\n", true,
+             "CSV record 2 (line 2) has 4 field(s), but the header has 3"),
+            (b"a,b,c\n1,2,3\n4,5", false,
+             "CSV record 3 (line 3) is cut off at the end of the file"),
+            (b"a,b\n1,\xFF\n", false, "CSV record 2 (line 2) isn't valid UTF-8"),
+        ] {
+            assert_eq!(verify(*input, *uniform).unwrap_err().to_string(), *message);
+        }
+    }
+
+    #[test]
+    fn test_fixtures() {
+        verify(&include_bytes!("../../../test_data/good/testfile.csv")[..], true).unwrap();
+        assert_eq!(verify(&include_bytes!("../../../test_data/bad/testfile.csv")[..], false)
+                       .unwrap_err().to_string(),
+                   "CSV record 2 (line 2) has characters after the closing quote of a field");
+    }
+}
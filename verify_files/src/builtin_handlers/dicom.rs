@@ -0,0 +1,626 @@
+//! A walker for DICOM files (as defined by PS3.10), checking the structure of the data set
+//!
+//! (DICOM has no checksums, but every data element is length-prefixed, so walking all of them
+//! catches truncation and most damage to the element headers. Sequences and items are checked for
+//! proper nesting, and the fragments of encapsulated pixel data are checked against the Basic
+//! Offset Table. The values themselves aren't decoded, and a file cut off exactly between two
+//! top-level elements can't be told apart from one with a shorter data set.)
+
+// Standard library imports
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read};
+
+// 3rd-party imports
+use flate2::{Decompress, FlushDecompress, Status};
+
+// Local Imports
+use super::{corrupt, unsupported};
+
+/// The length of the preamble which comes before the magic number
+const PREAMBLE_LEN: usize = 128;
+
+/// The magic number which follows the preamble
+const MAGIC: &[u8; 4] = b"DICM";
+
+/// The value length which marks an element as delimited rather than length-prefixed
+const UNDEFINED_LENGTH: u32 = 0xFFFF_FFFF;
+
+/// How deeply sequences may be nested before the file is refused
+const MAX_DEPTH: usize = 64;
+
+/// The longest Transfer Syntax UID which will be accepted (UIDs are limited to 64 characters)
+const MAX_UID_LEN: u32 = 64;
+
+/// Value representations whose explicit-VR element headers have a 32-bit length
+const LONG_VRS: [[u8; 2]; 13] = [*b"OB", *b"OD", *b"OF", *b"OL", *b"OV", *b"OW", *b"SQ", *b"SV",
+    *b"UC", *b"UN", *b"UR", *b"UT", *b"UV"];
+
+/// Transfer Syntax UID: Implicit VR Little Endian
+const IMPLICIT_VR_LITTLE_ENDIAN: &str = "1.2.840.10008.1.2";
+/// Transfer Syntax UID: Explicit VR Big Endian (retired, but still found in old archives)
+const EXPLICIT_VR_BIG_ENDIAN: &str = "1.2.840.10008.1.2.2";
+/// Transfer Syntax UID: Deflated Explicit VR Little Endian
+const DEFLATED_EXPLICIT_VR_LITTLE_ENDIAN: &str = "1.2.840.10008.1.2.1.99";
+
+/// A data element tag, as a group and element number
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Tag(u16, u16);
+
+impl fmt::Display for Tag {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(formatter, "({:04X},{:04X})", self.0, self.1)
+    }
+}
+
+/// The group which holds the File Meta Information
+const FILE_META_GROUP: u16 = 0x0002;
+/// The first element of the File Meta Information, which gives the length of the rest
+const FILE_META_GROUP_LENGTH: Tag = Tag(FILE_META_GROUP, 0x0000);
+/// The File Meta Information element which says how the data set is encoded
+const TRANSFER_SYNTAX_UID: Tag = Tag(FILE_META_GROUP, 0x0010);
+/// The element holding the image, which is a sequence of fragments when encapsulated
+const PIXEL_DATA: Tag = Tag(0x7FE0, 0x0010);
+/// An item in a sequence, or a fragment of encapsulated pixel data
+const ITEM: Tag = Tag(0xFFFE, 0xE000);
+/// The end of an item with an undefined length
+const ITEM_DELIMITER: Tag = Tag(0xFFFE, 0xE00D);
+/// The end of a sequence with an undefined length
+const SEQUENCE_DELIMITER: Tag = Tag(0xFFFE, 0xE0DD);
+
+/// How the data elements being walked are encoded
+#[derive(Clone, Copy)]
+struct Encoding {
+    /// Whether each element header includes its value representation
+    explicit_vr: bool,
+    /// Whether numbers are big-endian
+    big_endian: bool,
+}
+
+impl Encoding {
+    /// The encoding of the File Meta Information, unknown private transfer syntaxes, and all of
+    /// the compressed ones
+    const EXPLICIT_LITTLE: Self = Self { explicit_vr: true, big_endian: false };
+
+    /// The encoding of the default transfer syntax, and of the contents of `UN` sequences
+    const IMPLICIT_LITTLE: Self = Self { explicit_vr: false, big_endian: false };
+
+    /// Read a `u16` in this encoding's byte order
+    fn u16(self, bytes: [u8; 2]) -> u16 {
+        if self.big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) }
+    }
+
+    /// Read a `u32` in this encoding's byte order
+    fn u32(self, bytes: [u8; 4]) -> u32 {
+        if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) }
+    }
+}
+
+/// The header of a data element, item, or delimiter
+struct Element {
+    /// The tag identifying it
+    tag: Tag,
+    /// The value representation, if the encoding is explicit and this isn't an item or delimiter
+    vr: Option<[u8; 2]>,
+    /// The length of the value, which may be [`UNDEFINED_LENGTH`]
+    len: u32,
+    /// Where the header starts
+    offset: u64,
+}
+
+/// Where a run of data elements ends
+#[derive(Clone, Copy)]
+enum End {
+    /// At the end of the input, which may only come between elements
+    Eof,
+    /// At a delimiter, so the input may not end first
+    Delimiter,
+    /// At the given offset, which is the end of the named container
+    At(u64, &'static str),
+}
+
+/// What was found in a DICOM file
+#[derive(Debug)]
+pub struct Summary {
+    /// The Transfer Syntax UID, which says how the data set (and pixel data) are encoded
+    pub transfer_syntax: String,
+    /// How many fragments of encapsulated pixel data there were, if any
+    pub fragments: u64,
+}
+
+/// A streaming decoder for the raw Deflate stream of a deflated data set
+///
+/// (`flate2`'s `DeflateDecoder` treats a truncated stream as a normal end of file.)
+struct Inflate<R> {
+    /// The compressed data
+    input: R,
+    /// The raw Deflate decoder
+    inflate: Decompress,
+    /// Whether the end of the Deflate stream has been reached
+    done: bool,
+}
+
+impl<R: BufRead> Read for Inflate<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while !self.done && !buf.is_empty() {
+            let input = self.input.fill_buf()?;
+            if input.is_empty() {
+                return Err(corrupt("Deflated data set is cut short by the end of the file"));
+            }
+            let (before_in, before_out) = (self.inflate.total_in(), self.inflate.total_out());
+            let status = self.inflate.decompress(input, buf, FlushDecompress::None)
+                .map_err(|err| corrupt(&format!("Invalid Deflate data in the data set: {}", err)))?;
+            let consumed = usize::try_from(self.inflate.total_in() - before_in)
+                .expect("no more than the input");
+            let produced = usize::try_from(self.inflate.total_out() - before_out)
+                .expect("no more than the output");
+            self.input.consume(consumed);
+
+            if status == Status::StreamEnd {
+                self.done = true;
+            } else if consumed == 0 && produced == 0 {
+                return Err(corrupt("Deflate data stalled in the data set"));
+            }
+            if produced > 0 {
+                return Ok(produced);
+            }
+        }
+        Ok(0)
+    }
+}
+
+/// A cursor over a stream of data elements
+struct Walker<R> {
+    /// The data elements
+    input: R,
+    /// How far into `input` (plus the preamble, for undeflated data) the cursor is
+    offset: u64,
+    /// What `input` is, for error messages
+    source: &'static str,
+    /// How the data elements are currently encoded
+    encoding: Encoding,
+    /// How many sequences deep the cursor is
+    depth: usize,
+    /// How many fragments of encapsulated pixel data have been seen
+    fragments: u64,
+}
+
+impl<R: Read> Walker<R> {
+    /// Start walking `input`, which starts `offset` bytes into the data
+    fn new(input: R, offset: u64, source: &'static str, encoding: Encoding) -> Self {
+        Self { input, offset, source, encoding, depth: 0, fragments: 0 }
+    }
+
+    /// Read as much of `buf` as the input can fill, returning how much that was
+    fn fill(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut total = 0;
+        while total < buf.len() {
+            match self.input.read(&mut buf[total..]) {
+                Ok(0) => break,
+                Ok(len) => total += len,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {},
+                Err(err) => return Err(err),
+            }
+        }
+        self.offset += total as u64;
+        Ok(total)
+    }
+
+    /// Read the next element header, if `end` says there may be one
+    fn header(&mut self, end: End) -> io::Result<Option<Element>> {
+        let offset = self.offset;
+        if let End::At(limit, _) = end {
+            if offset >= limit {
+                return Ok(None);
+            }
+        }
+
+        let mut head = [0; 8];
+        match self.fill(&mut head)? {
+            0 if matches!(end, End::Eof) => return Ok(None),
+            0 => return Err(corrupt(&format!("Unexpected end of the {} at offset {}",
+                                             self.source, offset))),
+            8 => {},
+            _ => return Err(self.header_truncated(offset)),
+        }
+
+        let tag = Tag(self.encoding.u16([head[0], head[1]]), self.encoding.u16([head[2], head[3]]));
+        let (vr, len) = if tag.0 == ITEM.0 || !self.encoding.explicit_vr {
+            (None, self.encoding.u32([head[4], head[5], head[6], head[7]]))
+        } else {
+            let vr = [head[4], head[5]];
+            if !vr.iter().all(u8::is_ascii_uppercase) {
+                return Err(corrupt(&format!("Data element {} at offset {} has an invalid VR",
+                                            tag, offset)));
+            }
+            if LONG_VRS.contains(&vr) {
+                let mut len = [0; 4];
+                if self.fill(&mut len)? < len.len() {
+                    return Err(self.header_truncated(offset));
+                }
+                (Some(vr), self.encoding.u32(len))
+            } else {
+                (Some(vr), u32::from(self.encoding.u16([head[6], head[7]])))
+            }
+        };
+
+        let element = Element { tag, vr, len, offset };
+        if let End::At(limit, container) = end {
+            if self.offset > limit {
+                return Err(element.overrun(container));
+            }
+        }
+        Ok(Some(element))
+    }
+
+    /// The error for an element header cut short by the end of the input
+    fn header_truncated(&self, offset: u64) -> io::Error {
+        corrupt(&format!("Data element header at offset {} is cut short by the end of the {}",
+                         offset, self.source))
+    }
+
+    /// Check that the value of `element`, whose header was just read, fits within `end`,
+    /// returning where it ends
+    fn value_end(&self, element: &Element, end: End) -> io::Result<u64> {
+        let value_end = self.offset + u64::from(element.len);
+        match end {
+            End::At(limit, container) if value_end > limit => Err(element.overrun(container)),
+            End::At(..) | End::Eof | End::Delimiter => Ok(value_end),
+        }
+    }
+
+    /// Skip over the value of `element`, whose header was just read
+    fn skip(&mut self, element: &Element) -> io::Result<()> {
+        let len = u64::from(element.len);
+        let skipped = io::copy(&mut (&mut self.input).take(len), &mut io::sink())?;
+        self.offset += skipped;
+        if skipped < len {
+            return Err(corrupt(&format!(
+                "Data element {} at offset {} is {} bytes long, but the {} ends after {}",
+                element.tag, element.offset, len, self.source, skipped)));
+        }
+        Ok(())
+    }
+
+    /// Read the value of `element`, whose header was just read, which the caller has checked is
+    /// of a reasonable length
+    fn read_value(&mut self, element: &Element) -> io::Result<Vec<u8>> {
+        let mut value = Vec::new();
+        (&mut self.input).take(u64::from(element.len)).read_to_end(&mut value)?;
+        self.offset += value.len() as u64;
+        if value.len() as u64 != u64::from(element.len) {
+            return Err(corrupt(&format!(
+                "Data element {} at offset {} is {} bytes long, but the {} ends after {}",
+                element.tag, element.offset, element.len, self.source, value.len())));
+        }
+        Ok(value)
+    }
+
+    /// Read the File Meta Information group, returning the Transfer Syntax UID
+    fn file_meta(&mut self) -> io::Result<String> {
+        let group_length = self.header(End::Delimiter)?
+            .filter(|x| x.tag == FILE_META_GROUP_LENGTH && x.vr == Some(*b"UL") && x.len == 4)
+            .ok_or_else(|| corrupt("File Meta Information doesn't start with its group length"))?;
+        let value = self.read_value(&group_length)?;
+        let len = self.encoding.u32([value[0], value[1], value[2], value[3]]);
+        let end = End::At(self.offset + u64::from(len), "File Meta Information");
+
+        let mut syntax = None;
+        while let Some(element) = self.header(end)? {
+            if element.tag.0 != FILE_META_GROUP {
+                return Err(corrupt(&format!(
+                    "File Meta Information group length is wrong: data element {} at offset {} \
+                     isn't part of the group", element.tag, element.offset)));
+            } else if element.len == UNDEFINED_LENGTH {
+                return Err(corrupt(&format!(
+                    "File Meta Information element {} at offset {} has an undefined length",
+                    element.tag, element.offset)));
+            }
+            self.value_end(&element, end)?;
+
+            if element.tag != TRANSFER_SYNTAX_UID {
+                self.skip(&element)?;
+            } else if element.len > MAX_UID_LEN {
+                return Err(corrupt(&format!("Transfer Syntax UID is {} bytes long", element.len)));
+            } else {
+                syntax = Some(self.read_value(&element)?);
+            }
+        }
+
+        let syntax = syntax
+            .ok_or_else(|| corrupt("File Meta Information has no Transfer Syntax UID"))?;
+        let syntax = String::from_utf8_lossy(&syntax)
+            .trim_end_matches(|x| x == '\0' || x == ' ').to_owned();
+        if syntax.is_empty() || !syntax.chars().all(|x| x.is_ascii_digit() || x == '.') {
+            return Err(corrupt(&format!("Invalid Transfer Syntax UID {:?}", syntax)));
+        }
+        Ok(syntax)
+    }
+
+    /// Walk data elements until `end`
+    fn elements(&mut self, end: End) -> io::Result<()> {
+        while let Some(element) = self.header(end)? {
+            match element.tag {
+                ITEM_DELIMITER if matches!(end, End::Delimiter) => return element.delimiter(),
+                ITEM | ITEM_DELIMITER | SEQUENCE_DELIMITER => return Err(corrupt(&format!(
+                    "Unexpected item or delimiter {} at offset {}", element.tag, element.offset))),
+                Tag(FILE_META_GROUP, _) if self.depth == 0 => return Err(corrupt(&format!(
+                    "File Meta Information group length is wrong: element {} at offset {} comes \
+                     after the end of the group", element.tag, element.offset))),
+                _ => self.value(&element, end)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Walk or skip the value of `element`, whose header was just read
+    fn value(&mut self, element: &Element, end: End) -> io::Result<()> {
+        if element.len != UNDEFINED_LENGTH {
+            let value_end = self.value_end(element, end)?;
+            return if element.vr == Some(*b"SQ") {
+                self.sequence(element, End::At(value_end, "sequence"))
+            } else {
+                self.skip(element)
+            };
+        }
+
+        match element.vr {
+            Some(vr) if element.tag == PIXEL_DATA && (&vr == b"OB" || &vr == b"OW") =>
+                self.fragments(element, end),
+            None | Some([b'S', b'Q']) => self.sequence(element, End::Delimiter),
+            // Sequences of unknown type are always encoded as implicit VR little endian
+            Some([b'U', b'N']) => {
+                let encoding = self.encoding;
+                self.encoding = Encoding::IMPLICIT_LITTLE;
+                let walked = self.sequence(element, End::Delimiter);
+                self.encoding = encoding;
+                walked
+            },
+            Some(vr) => Err(corrupt(&format!(
+                "Data element {} at offset {} has an undefined length, which VR {} doesn't allow",
+                element.tag, element.offset, String::from_utf8_lossy(&vr)))),
+        }
+    }
+
+    /// Walk the items of `sequence`, whose header was just read, until `end`
+    fn sequence(&mut self, sequence: &Element, end: End) -> io::Result<()> {
+        if self.depth >= MAX_DEPTH {
+            return Err(corrupt(&format!("Sequence {} at offset {} is nested more than {} deep",
+                                        sequence.tag, sequence.offset, MAX_DEPTH)));
+        }
+        self.depth += 1;
+        while let Some(item) = self.header(end)? {
+            match item.tag {
+                ITEM if item.len == UNDEFINED_LENGTH => self.elements(End::Delimiter)?,
+                ITEM => {
+                    let item_end = self.value_end(&item, end)?;
+                    self.elements(End::At(item_end, "item"))?;
+                },
+                SEQUENCE_DELIMITER if matches!(end, End::Delimiter) => {
+                    item.delimiter()?;
+                    break;
+                },
+                _ => return Err(corrupt(&format!(
+                    "Sequence {} at offset {} holds {} at offset {}, which isn't an item",
+                    sequence.tag, sequence.offset, item.tag, item.offset))),
+            }
+        }
+        self.depth -= 1;
+        Ok(())
+    }
+
+    /// Walk the fragments of encapsulated `pixel_data`, whose header was just read, and check
+    /// them against the Basic Offset Table
+    fn fragments(&mut self, pixel_data: &Element, end: End) -> io::Result<()> {
+        let not_fragment = |offset| corrupt(&format!(
+            "Encapsulated pixel data at offset {} holds something other than a fragment at \
+             offset {}", pixel_data.offset, offset));
+        let bounds = if let End::At(..) = end { end } else { End::Delimiter };
+
+        // The Basic Offset Table comes first, as an item of its own which may be empty
+        let mut table = None;
+        let mut starts = Vec::new();
+        let mut first = None;
+        loop {
+            let item = self.header(bounds)?
+                .ok_or_else(|| pixel_data.overrun("item"))?;
+            match item.tag {
+                SEQUENCE_DELIMITER => {
+                    item.delimiter()?;
+                    break;
+                },
+                ITEM if item.len != UNDEFINED_LENGTH => {},
+                _ => return Err(not_fragment(item.offset)),
+            }
+            self.value_end(&item, bounds)?;
+
+            if table.is_none() {
+                if item.len % 4 != 0 {
+                    return Err(corrupt(&format!(
+                        "Basic Offset Table at offset {} is {} bytes long, which isn't a \
+                         multiple of 4", item.offset, item.len)));
+                }
+                table = Some(self.read_value(&item)?.chunks_exact(4)
+                    .map(|x| u64::from(u32::from_le_bytes([x[0], x[1], x[2], x[3]])))
+                    .collect::<Vec<_>>());
+            } else {
+                let base = *first.get_or_insert(item.offset);
+                starts.push(item.offset - base);
+                self.skip(&item)?;
+            }
+        }
+
+        if starts.is_empty() {
+            return Err(corrupt(&format!("Encapsulated pixel data at offset {} has no fragments",
+                                        pixel_data.offset)));
+        }
+        // Entries must be in order, and the first must point at the first fragment
+        let mut previous = None;
+        for (frame, offset) in table.unwrap_or_default().into_iter().enumerate() {
+            let in_order = previous.map_or(offset == 0, |x| x < offset);
+            previous = Some(offset);
+            if !in_order || starts.binary_search(&offset).is_err() {
+                return Err(corrupt(&format!(
+                    "Basic Offset Table of the pixel data at offset {} is wrong: frame {} doesn't \
+                     start at the start of a fragment", pixel_data.offset, frame + 1)));
+            }
+        }
+        self.fragments += starts.len() as u64;
+        Ok(())
+    }
+}
+
+impl Element {
+    /// The error for this element running past the end of its container
+    fn overrun(&self, container: &str) -> io::Error {
+        corrupt(&format!("Data element {} at offset {} runs past the end of the {} it's in",
+                         self.tag, self.offset, container))
+    }
+
+    /// Check that this delimiter has the zero length it's required to have
+    fn delimiter(&self) -> io::Result<()> {
+        if self.len != 0 {
+            return Err(corrupt(&format!("Delimiter {} at offset {} has a non-zero length",
+                                        self.tag, self.offset)));
+        }
+        Ok(())
+    }
+}
+
+/// Walk the File Meta Information and data set of a DICOM file
+///
+/// Files without the preamble and `DICM` magic number (bare data sets, as used on the network and
+/// by some very old software) are reported as unsupported, since there's no reliable way to tell
+/// what they are or how they're encoded.
+pub fn verify(mut input: impl Read) -> io::Result<Summary> {
+    let mut preamble = Vec::with_capacity(PREAMBLE_LEN + MAGIC.len());
+    (&mut input).take((PREAMBLE_LEN + MAGIC.len()) as u64).read_to_end(&mut preamble)?;
+    if preamble.get(PREAMBLE_LEN..) != Some(&MAGIC[..]) {
+        return Err(unsupported("No DICOM preamble (bare data sets aren't supported)"));
+    }
+
+    let start = preamble.len() as u64;
+    let mut walker = Walker::new(input, start, "file", Encoding::EXPLICIT_LITTLE);
+    let transfer_syntax = walker.file_meta()?;
+    let fragments = if transfer_syntax == DEFLATED_EXPLICIT_VR_LITTLE_ENDIAN {
+        let inflate = Inflate {
+            input: BufReader::new(walker.input),
+            inflate: Decompress::new(false),
+            done: false,
+        };
+        let mut walker = Walker::new(inflate, 0, "inflated data set", Encoding::EXPLICIT_LITTLE);
+        walker.elements(End::Eof)?;
+        walker.fragments
+    } else {
+        walker.encoding = match transfer_syntax.as_str() {
+            IMPLICIT_VR_LITTLE_ENDIAN => Encoding::IMPLICIT_LITTLE,
+            EXPLICIT_VR_BIG_ENDIAN => Encoding { explicit_vr: true, big_endian: true },
+            _ => Encoding::EXPLICIT_LITTLE,
+        };
+        walker.elements(End::Eof)?;
+        walker.fragments
+    };
+    Ok(Summary { transfer_syntax, fragments })
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::*;
+
+    /// The explicit VR little endian fixture
+    const EXPLICIT: &[u8] = include_bytes!("../../../test_data/good/testfile.dcm");
+
+    /// The RLE Lossless fixture, with two fragments and a Basic Offset Table
+    const RLE: &[u8] = include_bytes!("../../../test_data/good/testfile.rle.dcm");
+
+    /// The deflated explicit VR little endian fixture
+    const DEFLATED: &[u8] = include_bytes!("../../../test_data/good/testfile.deflated.dcm");
+
+    /// Replace the first occurrence of `from` in `data` with `to`
+    fn patch(data: &[u8], from: &[u8], to: &[u8]) -> Vec<u8> {
+        let start = data.windows(from.len()).position(|x| x == from).expect("pattern present");
+        let mut data = data.to_vec();
+        data[start..start + to.len()].copy_from_slice(to);
+        data
+    }
+
+    /// The preamble and File Meta Information of `data`, with the group length at offset 140
+    fn file_meta(data: &[u8]) -> Vec<u8> {
+        let len = u32::from_le_bytes([data[140], data[141], data[142], data[143]]);
+        data[..144 + usize::try_from(len).unwrap()].to_vec()
+    }
+
+    #[test]
+    fn test_fixtures() {
+        for (name, data, syntax, fragments) in &[
+            ("explicit", EXPLICIT, "1.2.840.10008.1.2.1", 0),
+            ("implicit", &include_bytes!("../../../test_data/good/testfile.implicit.dcm")[..],
+                IMPLICIT_VR_LITTLE_ENDIAN, 0),
+            ("deflated", DEFLATED, DEFLATED_EXPLICIT_VR_LITTLE_ENDIAN, 0),
+            ("RLE", RLE, "1.2.840.10008.1.2.5", 2),
+        ] {
+            let summary = verify(*data).unwrap_or_else(|err| panic!("{}: {}", name, err));
+            assert_eq!(summary.transfer_syntax, *syntax, "{}", name);
+            assert_eq!(summary.fragments, *fragments, "{}", name);
+        }
+
+        let err = verify(&include_bytes!("../../../test_data/bad/testfile.dcm")[..])
+            .expect_err("truncated");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("(7FE0,0010) at offset 804 is 512 bytes long, but the \
+                                          file ends after 412"), "{}", err);
+    }
+
+    #[test]
+    fn test_no_preamble() {
+        for (name, data) in &[("bare", &EXPLICIT[128..]), ("empty", &b""[..]),
+                              ("short", &EXPLICIT[..131])] {
+            let err = verify(*data).expect_err(name);
+            assert_eq!(err.kind(), io::ErrorKind::Unsupported, "{}: {}", name, err);
+        }
+    }
+
+    #[test]
+    fn test_damage() {
+        let mut nested = file_meta(EXPLICIT);
+        for _ in 0..=MAX_DEPTH {
+            nested.extend_from_slice(b"\x08\0\x40\x11SQ\0\0\xFF\xFF\xFF\xFF");
+            nested.extend_from_slice(b"\xFE\xFF\0\xE0\xFF\xFF\xFF\xFF");
+        }
+        let mut deflated = DEFLATED.to_vec();
+        deflated.truncate(deflated.len() - 20);
+
+        for (name, data, message) in &[
+            ("short group length", patch(EXPLICIT, b"UL\x04\0\xA4", b"UL\x04\0\xA2"),
+                "(0002,0013) at offset 288 runs past the end of the File Meta Information"),
+            ("group length one short", patch(EXPLICIT, b"UL\x04\0\xA4", b"UL\x04\0\x90"),
+                "group length is wrong: element (0002,0013) at offset 288 comes after the end"),
+            ("long group length", patch(EXPLICIT, b"UL\x04\0\xA4", b"UL\x04\0\xE4"),
+                "group length is wrong: data element (0008,0016) at offset 308 isn't part of"),
+            ("no transfer syntax", patch(EXPLICIT, b"\x02\0\x10\0", b"\x02\0\x11\0"),
+                "no Transfer Syntax UID"),
+            ("invalid VR", patch(EXPLICIT, b"CS", b"cS"),
+                "(0008,0060) at offset 396 has an invalid VR"),
+            ("bad item", patch(EXPLICIT, b"\xFE\xFF\x00\xE0", b"\xFE\xFF\x0D\xE0"),
+                "Sequence (0008,1140) at offset 406 holds (FFFE,E00D) at offset 418"),
+            ("missing delimiter", patch(EXPLICIT, b"\xFE\xFF\x0D\xE0", b"\xFE\xFF\xDD\xE0"),
+                "Unexpected item or delimiter (FFFE,E0DD)"),
+            ("item overrun", patch(EXPLICIT, b"\xFE\xFF\0\xE0\x0C", b"\xFE\xFF\0\xE0\x0E"),
+                "runs past the end of the sequence it's in"),
+            ("nested too deep", nested, "nested more than 64 deep"),
+            ("bad offset table", patch(RLE, b"\0\0\0\0\x4A\x01", b"\0\0\0\0\x4C\x01"),
+                "frame 2 doesn't start at the start of a fragment"),
+            ("undefined fragment", patch(RLE, b"\x42\x01\0\0", b"\xFF\xFF\xFF\xFF"),
+                "holds something other than a fragment"),
+            ("truncated deflate", deflated, "cut short"),
+        ] {
+            let err = verify(&data[..]).expect_err(name);
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}: {}", name, err);
+            assert!(err.to_string().contains(message), "{}: {}", name, err);
+        }
+    }
+}
@@ -0,0 +1,419 @@
+//! A checker for ELF executables, shared libraries, and object files
+//!
+//! (ELF has no checksums, so all that can be checked is that the headers are self-consistent and
+//! that everything they point at lies within the file. That's still enough to catch truncation
+//! and most damage to the headers themselves. Nothing is ever loaded, mapped, or executed.)
+
+// Standard library imports
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::io::{self, Read, Seek};
+
+// Local Imports
+use super::bytes::{check_bounds, read_at};
+use super::corrupt;
+
+/// The magic number at the start of every ELF file
+const MAGIC: &[u8; 4] = b"\x7fELF";
+
+/// The size of `e_ident`, which is the same for every class
+const IDENT_SIZE: usize = 16;
+
+/// The only version of the ELF format which has ever been defined (`EV_CURRENT`)
+const CURRENT_VERSION: u8 = 1;
+
+/// `p_type` of a loadable segment
+const PT_LOAD: u32 = 1;
+
+/// `sh_type` of a string table
+const SHT_STRTAB: u32 = 3;
+
+/// `sh_type` of a section which takes up no space in the file (eg. `.bss`)
+const SHT_NOBITS: u32 = 8;
+
+/// The `e_phnum` value which means the real count is in the `sh_info` of section 0
+const PN_XNUM: u16 = 0xFFFF;
+
+/// The `e_shstrndx` value which means the real index is in the `sh_link` of section 0
+const SHN_XINDEX: u16 = 0xFFFF;
+
+/// How to decode the fields of a particular file, as given by its `e_ident`
+#[derive(Clone, Copy)]
+struct Layout {
+    /// Whether addresses and offsets are 64 bits wide (`ELFCLASS64`) rather than 32
+    is_64: bool,
+    /// Whether fields are big-endian (`ELFDATA2MSB`) rather than little-endian
+    big_endian: bool,
+}
+
+impl Layout {
+    /// The size of the file header
+    fn header_size(self) -> usize {
+        if self.is_64 { 64 } else { 52 }
+    }
+
+    /// The minimum size of a program header table entry
+    fn phdr_size(self) -> u16 {
+        if self.is_64 { 56 } else { 32 }
+    }
+
+    /// The minimum size of a section header table entry
+    fn shdr_size(self) -> u16 {
+        if self.is_64 { 64 } else { 40 }
+    }
+}
+
+/// A reader for the fields of one header or table entry
+struct Fields<'a> {
+    /// The header being read
+    data: &'a [u8],
+    /// How much of `data` has been read
+    pos: usize,
+    /// How to decode the fields
+    layout: Layout,
+}
+
+impl<'a> Fields<'a> {
+    /// Take the next `N` bytes, which the caller must have checked are present, in big-endian
+    /// order regardless of the file's byte order
+    fn take<const N: usize>(&mut self) -> [u8; N] {
+        let mut bytes = [0; N];
+        bytes.copy_from_slice(&self.data[self.pos..self.pos + N]);
+        self.pos += N;
+        if !self.layout.big_endian {
+            bytes.reverse();
+        }
+        bytes
+    }
+
+    /// Read a 16-bit field (`ElfN_Half`)
+    fn half(&mut self) -> u16 {
+        u16::from_be_bytes(self.take())
+    }
+
+    /// Read a 32-bit field (`ElfN_Word`)
+    fn word(&mut self) -> u32 {
+        u32::from_be_bytes(self.take())
+    }
+
+    /// Read an address, offset, or size field, which is as wide as the file's class
+    fn addr(&mut self) -> u64 {
+        if self.layout.is_64 {
+            u64::from_be_bytes(self.take())
+        } else {
+            u64::from(self.word())
+        }
+    }
+}
+
+/// The fields of the file header that matter for finding everything else
+struct Header {
+    /// The offset of the program header table
+    phoff: u64,
+    /// The offset of the section header table, or 0 if there isn't one
+    shoff: u64,
+    /// The size of a program header table entry
+    phentsize: u16,
+    /// The number of program header table entries (or [`PN_XNUM`])
+    phnum: u16,
+    /// The size of a section header table entry
+    shentsize: u16,
+    /// The number of section header table entries (or 0 if that's in section 0)
+    shnum: u16,
+    /// The index of the section name string table (or [`SHN_XINDEX`])
+    shstrndx: u16,
+}
+
+/// The fields of a section header that matter for bounds checking
+struct Section {
+    /// The offset of the section's name in the section name string table
+    name: u32,
+    /// The section's type
+    kind: u32,
+    /// The offset of the section's contents
+    offset: u64,
+    /// The size of the section's contents
+    size: u64,
+    /// The `sh_link` field, which section 0 repurposes for [`SHN_XINDEX`]
+    link: u32,
+    /// The `sh_info` field, which section 0 repurposes for [`PN_XNUM`]
+    info: u32,
+}
+
+impl Section {
+    /// Parse a section header entry
+    fn read(data: &[u8], layout: Layout) -> Self {
+        let mut fields = Fields { data, pos: 0, layout };
+        let name = fields.word();
+        let kind = fields.word();
+        fields.addr();
+        fields.addr();
+        let offset = fields.addr();
+        let size = fields.addr();
+        let link = fields.word();
+        let info = fields.word();
+        Self { name, kind, offset, size, link, info }
+    }
+}
+
+/// Check the bounds of a table of `count` entries of `entsize` bytes at `offset` and read it
+fn read_table<R: Read + Seek>(input: &mut R, what: &str, offset: u64, entsize: u16, count: u64,
+        file_len: u64) -> io::Result<Vec<u8>> {
+    let len = u64::from(entsize) * count;
+    check_bounds(what, offset, len, file_len)?;
+    let mut table = vec![0; usize::try_from(len).expect("no larger than the file")];
+    read_at(input, offset, &mut table)?;
+    Ok(table)
+}
+
+/// Read and check `e_ident` and the rest of the file header
+fn read_header<R: Read + Seek>(input: &mut R, file_len: u64) -> io::Result<(Layout, Header)> {
+    if file_len < IDENT_SIZE as u64 {
+        return Err(corrupt("ELF file is truncated (too short for e_ident)"));
+    }
+    let mut ident = [0; IDENT_SIZE];
+    read_at(input, 0, &mut ident)?;
+    if !ident.starts_with(MAGIC) {
+        return Err(corrupt("Not an ELF file (bad magic number)"));
+    }
+    let is_64 = match ident[4] {
+        1 => false,
+        2 => true,
+        class => return Err(corrupt(&format!("ELF file has an invalid class: {}", class))),
+    };
+    let big_endian = match ident[5] {
+        1 => false,
+        2 => true,
+        encoding => return Err(corrupt(&format!(
+            "ELF file has an invalid data encoding: {}", encoding))),
+    };
+    if ident[6] != CURRENT_VERSION {
+        return Err(corrupt(&format!("ELF file has an unknown e_ident version: {}", ident[6])));
+    }
+
+    let layout = Layout { is_64, big_endian };
+    if file_len < layout.header_size() as u64 {
+        return Err(corrupt("ELF file is truncated (too short for the file header)"));
+    }
+    let mut data = vec![0; layout.header_size()];
+    read_at(input, 0, &mut data)?;
+    let mut fields = Fields { data: &data, pos: IDENT_SIZE, layout };
+    fields.half();
+    fields.half();
+    let version = fields.word();
+    fields.addr();
+    let phoff = fields.addr();
+    let shoff = fields.addr();
+    fields.word();
+    let ehsize = fields.half();
+    let header = Header {
+        phoff,
+        shoff,
+        phentsize: fields.half(),
+        phnum: fields.half(),
+        shentsize: fields.half(),
+        shnum: fields.half(),
+        shstrndx: fields.half(),
+    };
+
+    if version != u32::from(CURRENT_VERSION) {
+        return Err(corrupt(&format!("ELF file has an unknown e_version: {}", version)));
+    }
+    if usize::from(ehsize) < layout.header_size() {
+        return Err(corrupt(&format!("ELF e_ehsize is too small: {} < {}",
+            ehsize, layout.header_size())));
+    }
+    Ok((layout, header))
+}
+
+/// Look up the name of the section `name` bytes into the section name string table
+fn section_name<'a>(strtab: &'a [u8], index: usize, name: u32) -> io::Result<Cow<'a, str>> {
+    strtab.get(name as usize..)
+        .and_then(|rest| rest.split(|x| *x == 0).next().filter(|x| x.len() < rest.len()))
+        .map(String::from_utf8_lossy)
+        .ok_or_else(|| corrupt(&format!(
+            "ELF section {}'s name runs past the end of the section name string table", index)))
+}
+
+/// Check the ELF file in `input` (which is `file_len` bytes long)
+///
+/// The identification bytes and file header are checked for sanity, and the program header
+/// table, section header table, and every segment and section they describe are checked to lie
+/// within the file. The section name string table must exist if referenced, and every section's
+/// name must lie within it. Both classes (32- and 64-bit) and byte orders are supported.
+pub fn verify<R: Read + Seek>(mut input: R, file_len: u64) -> io::Result<()> {
+    let (layout, header) = read_header(&mut input, file_len)?;
+
+    // Section 0 holds the real counts when they don't fit in the file header
+    let mut sections = Vec::new();
+    if header.shoff != 0 {
+        if header.shentsize < layout.shdr_size() {
+            return Err(corrupt(&format!("ELF e_shentsize is too small: {} < {}",
+                header.shentsize, layout.shdr_size())));
+        }
+        let first = read_table(&mut input, "ELF section header table", header.shoff,
+            header.shentsize, 1, file_len)?;
+        let first = Section::read(&first, layout);
+        let count = match header.shnum {
+            0 => first.size,
+            count => u64::from(count),
+        };
+        let table = read_table(&mut input, "ELF section header table", header.shoff,
+            header.shentsize, count, file_len)?;
+        sections = table.chunks(usize::from(header.shentsize))
+            .map(|x| Section::read(x, layout)).collect();
+    } else if header.shnum != 0 {
+        return Err(corrupt("ELF file has sections but no section header table"));
+    }
+
+    let strtab_index = match (header.shstrndx, sections.first()) {
+        (SHN_XINDEX, Some(first)) => first.link as usize,
+        (index, _) => usize::from(index),
+    };
+    let mut strtab = Vec::new();
+    if strtab_index != 0 {
+        let section = sections.get(strtab_index).ok_or_else(|| corrupt(&format!(
+            "ELF e_shstrndx is out of range: {} (there are {} sections)",
+            strtab_index, sections.len())))?;
+        if section.kind != SHT_STRTAB {
+            return Err(corrupt(&format!(
+                "ELF e_shstrndx refers to section {}, which isn't a string table",
+                strtab_index)));
+        }
+        strtab = read_table(&mut input, "ELF section name string table", section.offset, 1,
+            section.size, file_len)?;
+    }
+    for (index, section) in sections.iter().enumerate().skip(1) {
+        let name = if strtab_index == 0 {
+            Cow::Borrowed("")
+        } else {
+            section_name(&strtab, index, section.name)?
+        };
+        if section.kind != SHT_NOBITS {
+            check_bounds(&format!("ELF section {} ('{}')", index, name), section.offset,
+                section.size, file_len)?;
+        }
+    }
+
+    let segment_count = match (header.phnum, sections.first()) {
+        (PN_XNUM, Some(first)) => u64::from(first.info),
+        (count, _) => u64::from(count),
+    };
+    if segment_count == 0 {
+        return Ok(());
+    }
+    if header.phentsize < layout.phdr_size() {
+        return Err(corrupt(&format!("ELF e_phentsize is too small: {} < {}",
+            header.phentsize, layout.phdr_size())));
+    }
+    let table = read_table(&mut input, "ELF program header table", header.phoff,
+        header.phentsize, segment_count, file_len)?;
+    for (index, entry) in table.chunks(usize::from(header.phentsize)).enumerate() {
+        let mut fields = Fields { data: entry, pos: 0, layout };
+        let kind = fields.word();
+        if layout.is_64 {
+            fields.word();
+        }
+        let offset = fields.addr();
+        fields.addr();
+        fields.addr();
+        let (filesz, memsz) = (fields.addr(), fields.addr());
+
+        check_bounds(&format!("ELF segment {}", index), offset, filesz, file_len)?;
+        if kind == PT_LOAD && filesz > memsz {
+            return Err(corrupt(&format!(
+                "ELF segment {} is larger in the file than in memory: {:#x} > {:#x}",
+                index, filesz, memsz)));
+        }
+    }
+    Ok(())
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// A minimal 64-bit little-endian executable, built by `test_data/sources/make_test_elf.py`
+    const ELF64: &[u8] = include_bytes!("../../../test_data/good/testfile.elf");
+
+    /// The 32-bit big-endian counterpart to `ELF64`
+    const ELF32: &[u8] = include_bytes!("../../../test_data/good/testfile.ppc32.elf");
+
+    /// A real-world executable (a RAR self-extractor stub, with the archive appended)
+    const SFX: &[u8] = include_bytes!("../../../test_data/good/testfile.rar5.linux_sfx.bin");
+
+    /// Check `data` the way the handler would
+    fn check(data: &[u8]) -> io::Result<()> {
+        verify(Cursor::new(data), data.len() as u64)
+    }
+
+    /// Copy `data` with `value` written over the bytes at `offset` in the given byte order
+    fn patched(data: &[u8], offset: usize, value: &[u8], big_endian: bool) -> Vec<u8> {
+        let mut data = data.to_vec();
+        let mut value = value.to_vec();
+        if !big_endian {
+            value.reverse();
+        }
+        data[offset..offset + value.len()].copy_from_slice(&value);
+        data
+    }
+
+    #[test]
+    fn test_good_files() {
+        for (name, data) in &[("64-bit LE", ELF64), ("32-bit BE", ELF32), ("real-world", SFX)] {
+            assert_eq!(check(data).map_err(|err| err.to_string()), Ok(()), "{}", name);
+        }
+
+        // Stripping the section header table entirely is legal
+        let mut no_sections = patched(ELF64, 0x28, &[0; 8], false);
+        no_sections[0x3C..0x40].copy_from_slice(&[0; 4]);
+        assert_eq!(check(&no_sections).map_err(|err| err.to_string()), Ok(()));
+    }
+
+    #[test]
+    fn test_bad_files() {
+        let truncated = include_bytes!("../../../test_data/bad/testfile.elf");
+        let err = check(truncated).expect_err("truncated section header table");
+        assert!(err.to_string().starts_with("ELF section header table runs past the end"),
+            "{}", err);
+
+        // The `.text` section of ELF64 is at 0x78, and its section header is the second entry
+        let err = check(&patched(ELF64, 0x98 + 64 + 0x20, &0x1000_u64.to_be_bytes(), false))
+            .expect_err("oversized .text");
+        assert!(err.to_string().starts_with("ELF section 1 ('.text') runs past"), "{}", err);
+        let err = check(&patched(ELF32, 0x34 + 0x10, &0x1000_u32.to_be_bytes(), true))
+            .expect_err("oversized segment in ELF32");
+        assert!(err.to_string().starts_with("ELF segment 0 runs past"), "{}", err);
+        let err = check(&patched(ELF64, 0x3E, &[0, 2], false)).expect_err("e_shstrndx is .bss");
+        assert!(err.to_string().contains("which isn't a string table"), "{}", err);
+
+        for (name, data) in &[
+            ("empty", &b""[..]),
+            ("truncated e_ident", &ELF64[..8]),
+            ("truncated header", &ELF64[..40]),
+            ("truncated 32-bit header", &ELF32[..40]),
+            ("truncated real-world", &SFX[..SFX.len() / 2]),
+            ("bad magic", &patched(ELF64, 0, b"\x7fELG", true)),
+            ("bad class", &patched(ELF64, 4, &[3], true)),
+            ("bad data encoding", &patched(ELF64, 5, &[0], true)),
+            ("bad e_ident version", &patched(ELF64, 6, &[2], true)),
+            ("bad e_version", &patched(ELF32, 0x14, &[0, 0, 0, 2], true)),
+            ("bad e_ehsize", &patched(ELF64, 0x34, &[0, 52], false)),
+            ("bad e_phoff", &patched(ELF64, 0x20, &u64::MAX.to_be_bytes(), false)),
+            ("bad e_phentsize", &patched(ELF32, 0x2A, &[0, 16], true)),
+            ("bad e_shentsize", &patched(ELF64, 0x3A, &[0, 40], false)),
+            ("bad e_shnum", &patched(ELF64, 0x3C, &[0, 40], false)),
+            ("bad e_shstrndx", &patched(ELF32, 0x32, &[0, 4], true)),
+            ("sections without a table", &patched(ELF64, 0x28, &[0; 8], false)),
+            ("filesz > memsz", &patched(ELF64, 0x40 + 0x28, &[0; 8], false)),
+            ("bad section name", &patched(ELF64, 0x98 + 64, &[0, 0, 1, 0], false)),
+        ] {
+            let err = check(data).expect_err(name);
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}: {}", name, err);
+        }
+    }
+}
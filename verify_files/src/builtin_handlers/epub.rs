@@ -0,0 +1,214 @@
+//! Checks for the rules the EPUB Open Container Format (OCF) imposes on top of Zip
+//!
+//! (The Zip CRCs are left to the caller, so this only looks at the layout of the archive and at
+//! the XML files a reading system needs to find the book. Content documents aren't parsed.)
+
+// Standard library imports
+use std::io::{self, Read, Seek};
+
+// 3rd-party imports
+use zip::read::ZipArchive;
+use zip::result::ZipError;
+use zip::CompressionMethod;
+
+// Local Imports
+use super::{corrupt, xml};
+
+/// The exact contents required of the `mimetype` entry
+const MIMETYPE: &[u8] = b"application/epub+zip";
+
+/// The most of the `mimetype` entry which will be read
+const MAX_MIMETYPE_SIZE: u64 = 256;
+
+/// The path of the file which points to the package documents
+const CONTAINER_PATH: &str = "META-INF/container.xml";
+
+/// The media type which identifies a package document in `container.xml`
+const PACKAGE_MEDIA_TYPE: &str = "application/oebps-package+xml";
+
+/// Shorthand for reporting a violated OCF rule
+fn violation(rule: &str) -> io::Error {
+    corrupt(&format!("EPUB rule violated: {}", rule))
+}
+
+/// Reword a well-formedness error from the XML parser to name the entry it was found in
+fn in_entry(err: io::Error, path: &str) -> io::Error {
+    #[allow(clippy::wildcard_enum_match_arm)]
+    match err.kind() {
+        io::ErrorKind::InvalidData => violation(&format!("{} must be well-formed XML ({})",
+            path, err)),
+        _ => err,
+    }
+}
+
+/// Find the first entry in the archive, which must be named `mimetype` and stored uncompressed,
+/// and return its contents (or as much as is needed to tell that they're wrong)
+///
+/// (OpenDocument has the same rule, since EPUB's container format was derived from it.)
+pub fn read_mimetype<R: Read + Seek>(zip: &mut ZipArchive<R>, violation: fn(&str) -> io::Error)
+        -> io::Result<Vec<u8>> {
+    let mut first = None;
+    for i in 0..zip.len() {
+        let start = zip.by_index_raw(i)?.header_start();
+        if first.map_or(true, |(_, x)| start < x) {
+            first = Some((i, start));
+        }
+    }
+    let (index, _) = first
+        .ok_or_else(|| violation("the mimetype entry must come first (archive is empty)"))?;
+
+    let mut entry = zip.by_index(index)?;
+    if entry.name() != "mimetype" {
+        return Err(violation(&format!("the mimetype entry must come first (found {:?})",
+            entry.name())));
+    }
+    if entry.compression() != CompressionMethod::Stored {
+        return Err(violation("the mimetype entry must be stored without compression"));
+    }
+    let mut contents = Vec::new();
+    (&mut entry).take(MAX_MIMETYPE_SIZE).read_to_end(&mut contents)?;
+    Ok(contents)
+}
+
+/// Parse `META-INF/container.xml`, returning the paths of the rootfiles it lists, each with
+/// whether it's a package document
+fn read_container<R: Read + Seek>(zip: &mut ZipArchive<R>) -> io::Result<Vec<(String, bool)>> {
+    let entry = match zip.by_name(CONTAINER_PATH) {
+        Ok(entry) => entry,
+        Err(ZipError::FileNotFound) => return Err(violation(&format!("{} must exist",
+            CONTAINER_PATH))),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut rootfiles = Vec::new();
+    let mut reader = xml::Reader::new(entry);
+    while let Some(event) = reader.next_event().map_err(|err| in_entry(err, CONTAINER_PATH))? {
+        if let xml::Event::Start(name, attributes) = event {
+            if xml::local_name(&name) != "rootfile" {
+                continue;
+            }
+            let attribute = |wanted| attributes.iter().find(|(x, _)| x == wanted);
+            let (_, path) = attribute("full-path").ok_or_else(|| violation(&format!(
+                "every rootfile in {} must have a full-path", CONTAINER_PATH)))?;
+            let is_package =
+                attribute("media-type").map(|(_, x)| x.as_str()) == Some(PACKAGE_MEDIA_TYPE);
+            rootfiles.push((path.clone(), is_package));
+        }
+    }
+    Ok(rootfiles)
+}
+
+/// Check the OCF rules for the EPUB file in `zip`
+///
+/// The `mimetype` entry must be first, uncompressed, and correct, `META-INF/container.xml` must be
+/// well-formed and list at least one package document, every rootfile it lists must exist, and
+/// the package documents must be well-formed.
+pub fn verify<R: Read + Seek>(zip: &mut ZipArchive<R>) -> io::Result<()> {
+    if read_mimetype(zip, violation)? != MIMETYPE {
+        return Err(violation("the mimetype entry must contain exactly \"application/epub+zip\""));
+    }
+    let rootfiles = read_container(zip)?;
+    if !rootfiles.iter().any(|(_, is_package)| *is_package) {
+        return Err(violation(&format!("{} must list a rootfile of type {}", CONTAINER_PATH,
+            PACKAGE_MEDIA_TYPE)));
+    }
+    for (path, is_package) in rootfiles {
+        match zip.by_name(&path) {
+            Ok(entry) if is_package => xml::verify(entry).map_err(|err| in_entry(err, &path))?,
+            Ok(_) => {},
+            Err(ZipError::FileNotFound) => return Err(violation(&format!(
+                "the rootfile {} listed in {} must exist", path, CONTAINER_PATH))),
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(())
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use zip::write::{FileOptions, ZipWriter};
+
+    use super::*;
+
+    /// A `container.xml` pointing at `OEBPS/book.opf`
+    const CONTAINER: &[u8] = b"<?xml version='1.0'?>
+        <container version='1.0' xmlns='urn:oasis:names:tc:opendocument:xmlns:container'>
+          <rootfiles>
+            <rootfile full-path='OEBPS/book.opf' media-type='application/oebps-package+xml'/>
+          </rootfiles>
+        </container>";
+
+    /// A (very) minimal package document
+    const PACKAGE: &[u8] = b"<package xmlns='http://www.idpf.org/2007/opf' version='3.0'/>";
+
+    /// Build a Zip file out of `entries`, compressing those with the flag set
+    fn build(entries: &[(&str, &[u8], bool)]) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data, deflate) in entries {
+            let method =
+                if *deflate { CompressionMethod::Deflated } else { CompressionMethod::Stored };
+            zip.start_file(*name, FileOptions::default().compression_method(method))
+                .expect("writing to memory");
+            zip.write_all(data).expect("writing to memory");
+        }
+        zip.finish().expect("writing to memory").into_inner()
+    }
+
+    /// Check the OCF rules for the Zip file built from `entries`, returning the error message
+    fn verify_entries(entries: &[(&str, &[u8], bool)]) -> Result<(), String> {
+        let mut zip = ZipArchive::new(Cursor::new(build(entries))).expect("valid Zip");
+        verify(&mut zip).map_err(|err| {
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            err.to_string()
+        })
+    }
+
+    #[test]
+    fn test_good_epub() {
+        assert_eq!(verify_entries(&[
+            ("mimetype", MIMETYPE, false),
+            ("META-INF/container.xml", CONTAINER, true),
+            ("OEBPS/book.opf", PACKAGE, true),
+            ("OEBPS/index.xhtml", b"not even XML", true),
+        ]), Ok(()));
+
+        let fixture = include_bytes!("../../../test_data/good/testfile.epub");
+        let mut zip = ZipArchive::new(Cursor::new(&fixture[..])).expect("valid Zip");
+        assert_eq!(verify(&mut zip).map_err(|err| err.to_string()), Ok(()));
+    }
+
+    #[test]
+    fn test_ocf_violations() {
+        let mimetype = ("mimetype", MIMETYPE, false);
+        let container = ("META-INF/container.xml", CONTAINER, true);
+        let package = ("OEBPS/book.opf", PACKAGE, true);
+        for (entries, rule) in &[
+            (vec![], "must come first"),
+            (vec![container, mimetype, package], "must come first"),
+            (vec![("mimetype", MIMETYPE, true), container, package], "without compression"),
+            (vec![("mimetype", b"application/epub+zip\n", false), container, package],
+             "contain exactly"),
+            (vec![("mimetype", b"application/zip", false), container, package],
+             "contain exactly"),
+            (vec![mimetype, package], "container.xml must exist"),
+            (vec![mimetype, ("META-INF/container.xml", b"<container>", true), package],
+             "container.xml must be well-formed"),
+            (vec![mimetype, ("META-INF/container.xml", b"<container/>", true), package],
+             "must list a rootfile"),
+            (vec![mimetype, ("META-INF/container.xml", b"<c><rootfile/></c>", true), package],
+             "must have a full-path"),
+            (vec![mimetype, container],
+             "OEBPS/book.opf listed in META-INF/container.xml must exist"),
+            (vec![mimetype, container, ("OEBPS/book.opf", b"<package>", true)],
+             "OEBPS/book.opf must be well-formed"),
+        ] {
+            let message = verify_entries(entries).expect_err(rule);
+            assert!(message.starts_with("EPUB rule violated: "), "{}", message);
+            assert!(message.contains(rule), "{:?} should mention {:?}", message, rule);
+        }
+    }
+}
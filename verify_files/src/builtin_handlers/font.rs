@@ -0,0 +1,242 @@
+//! A checker for TrueType and OpenType fonts (and collections of them)
+//!
+//! (Every table in an OpenType font has a checksum in the table directory, and the `head` table
+//! holds an adjustment which makes the whole file sum to a known value, so corruption anywhere in
+//! the font can be caught without understanding the tables themselves.)
+
+// Standard library imports
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::io::{self, Read, Seek, SeekFrom};
+
+// Local Imports
+use super::bytes::{be16, be32, read_at};
+use super::checksums::SfntChecksum;
+use super::corrupt;
+
+/// The `sfntVersion` values of the outline formats this understands the table directory of
+///
+/// (TrueType, CFF-based OpenType, and Apple's older TrueType and PostScript-in-sfnt tags)
+const SFNT_VERSIONS: [&[u8; 4]; 4] = [b"\x00\x01\x00\x00", b"OTTO", b"true", b"typ1"];
+
+/// The tag at the start of a TrueType/OpenType Collection
+const COLLECTION_TAG: &[u8; 4] = b"ttcf";
+
+/// The value the checksum of a whole font file must come to, thanks to `checkSumAdjustment`
+const WHOLE_FONT_CHECKSUM: u32 = 0xB1B0_AFBA;
+
+/// The magic number in every `head` table
+const HEAD_MAGIC: u32 = 0x5F0F_3CF5;
+
+/// The size of version 1.0 of the `head` table
+const HEAD_SIZE: u32 = 54;
+
+/// The offset of `checkSumAdjustment` within the `head` table
+const ADJUSTMENT_OFFSET: u64 = 8;
+
+/// Render a table tag for use in error messages
+fn name(tag: &[u8]) -> String {
+    String::from_utf8_lossy(tag).trim_end().to_owned()
+}
+
+/// Compute the checksum of the `len` bytes at `offset`
+fn checksum<R: Read + Seek>(input: &mut R, offset: u64, len: u64) -> io::Result<u32> {
+    input.seek(SeekFrom::Start(offset))?;
+    let mut sum = SfntChecksum::new();
+    let mut buf = vec![0; 64 * 1024];
+    let mut remaining = len;
+    while remaining > 0 {
+        let chunk = usize::try_from(remaining).map_or(buf.len(), |x| x.min(buf.len()));
+        input.read_exact(&mut buf[..chunk])?;
+        sum.update(&buf[..chunk]);
+        remaining -= chunk as u64;
+    }
+    Ok(sum.finish())
+}
+
+/// Check the table directory at `offset` and the checksums of the tables it lists, skipping those
+/// already in `checked`, and return the location of the `head` table if there is one
+fn verify_font<R: Read + Seek>(input: &mut R, offset: u64, file_len: u64,
+        checked: &mut HashSet<(u32, u32)>) -> io::Result<Option<u64>> {
+    if offset + 12 > file_len {
+        return Err(corrupt("Font file is truncated (table directory is missing)"));
+    }
+    let mut header = [0; 12];
+    read_at(input, offset, &mut header)?;
+    if !SFNT_VERSIONS.iter().any(|x| header.starts_with(*x)) {
+        return Err(corrupt("Not a TrueType or OpenType font (unrecognized sfnt version)"));
+    }
+    let num_tables = u64::from(be16(&header[4..]));
+    if num_tables == 0 {
+        return Err(corrupt("Font has no tables"));
+    }
+    if offset + 12 + num_tables * 16 > file_len {
+        return Err(corrupt("Font file is truncated (table directory runs past the end)"));
+    }
+    let mut records = vec![0; usize::try_from(num_tables * 16).expect("at most 1 MiB")];
+    read_at(input, offset + 12, &mut records)?;
+
+    let mut head = None;
+    for record in records.chunks(16) {
+        let (tag, expected) = (&record[..4], be32(&record[4..]));
+        let (table_offset, len) = (be32(&record[8..]), be32(&record[12..]));
+        if u64::from(table_offset) + u64::from(len) > file_len {
+            return Err(corrupt(&format!(
+                "Font table '{}' runs past the end of the file (truncated?)", name(tag))));
+        }
+        let table_offset_u64 = u64::from(table_offset);
+
+        // The checksum of `head` is calculated as if `checkSumAdjustment` were zero
+        let mut adjustment = 0;
+        if tag == b"head" {
+            if len < HEAD_SIZE {
+                return Err(corrupt("Font table 'head' is too short"));
+            }
+            let mut fields = [0; 16];
+            read_at(input, table_offset_u64, &mut fields)?;
+            if be32(&fields[12..]) != HEAD_MAGIC {
+                return Err(corrupt("Font table 'head' has the wrong magic number"));
+            }
+            adjustment = be32(&fields[ADJUSTMENT_OFFSET as usize..]);
+            head = Some(table_offset_u64);
+        }
+
+        if !checked.insert((table_offset, len)) {
+            continue;
+        }
+        let actual = checksum(input, table_offset_u64, u64::from(len))?.wrapping_sub(adjustment);
+        if actual != expected {
+            return Err(corrupt(&format!("Font table '{}' has a bad checksum", name(tag))));
+        }
+    }
+    Ok(head)
+}
+
+/// Verify the checksums in the TrueType/OpenType font or font collection in `input` (which is
+/// `file_len` bytes long)
+///
+/// Every table's checksum is checked, as is the whole-file checksum in the `head` table of a
+/// standalone font. (Collections share one `head` table between several fonts, so the latter can't
+/// be relied on for them.) The contents of the tables aren't otherwise examined.
+pub fn verify<R: Read + Seek>(mut input: R, file_len: u64) -> io::Result<()> {
+    let mut header = [0; 12];
+    if file_len < header.len() as u64 {
+        return Err(corrupt("Font file is truncated (too short for a header)"));
+    }
+    read_at(&mut input, 0, &mut header)?;
+    let mut checked = HashSet::new();
+
+    if !header.starts_with(COLLECTION_TAG) {
+        if let Some(head) = verify_font(&mut input, 0, file_len, &mut checked)? {
+            if checksum(&mut input, 0, file_len)? != WHOLE_FONT_CHECKSUM {
+                let mut adjustment = [0; 4];
+                read_at(&mut input, head + ADJUSTMENT_OFFSET, &mut adjustment)?;
+                return Err(corrupt(&format!(
+                    "Font file checksum doesn't match head.checkSumAdjustment ({:#010X})",
+                    be32(&adjustment))));
+            }
+        }
+        return Ok(());
+    }
+
+    let major_version = be16(&header[4..]);
+    if major_version != 1 && major_version != 2 {
+        return Err(corrupt(&format!("Font collection has an unknown version: {}",
+            major_version)));
+    }
+    let num_fonts = u64::from(be32(&header[8..]));
+    if num_fonts == 0 {
+        return Err(corrupt("Font collection contains no fonts"));
+    }
+    if 12 + num_fonts * 4 > file_len {
+        return Err(corrupt("Font collection is truncated (font offsets run past the end)"));
+    }
+    let mut offsets = vec![0; usize::try_from(num_fonts * 4).expect("less than file_len")];
+    read_at(&mut input, 12, &mut offsets)?;
+    for offset in offsets.chunks(4) {
+        verify_font(&mut input, u64::from(be32(offset)), file_len, &mut checked)?;
+    }
+    Ok(())
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// A minimal TrueType font, built by `test_data/sources/make_test_font.py`
+    const TTF: &[u8] = include_bytes!("../../../test_data/good/testfile.ttf");
+
+    /// A collection of two fonts sharing most of their tables
+    const TTC: &[u8] = include_bytes!("../../../test_data/good/testfile.ttc");
+
+    /// Check `data` the way the handler would
+    fn check(data: &[u8]) -> io::Result<()> {
+        verify(Cursor::new(data), data.len() as u64)
+    }
+
+    /// Find the offset of the table tagged `tag` in the font at the start of `data`
+    fn table_offset(data: &[u8], tag: &[u8]) -> usize {
+        let record = data[12..].chunks(16).find(|x| x.starts_with(tag)).expect("table present");
+        be32(&record[8..]) as usize
+    }
+
+    #[test]
+    fn test_good_fonts() {
+        assert_eq!(check(TTF).map_err(|err| err.to_string()), Ok(()));
+        assert_eq!(check(TTC).map_err(|err| err.to_string()), Ok(()));
+
+        // CFF-based OpenType fonts only differ in the sfnt version (and the outline tables)
+        let mut otf = TTF.to_vec();
+        otf[..4].copy_from_slice(b"OTTO");
+        let adjustment_offset = table_offset(&otf, b"head") + ADJUSTMENT_OFFSET as usize;
+        let adjustment = be32(&otf[adjustment_offset..])
+            .wrapping_sub(be32(b"OTTO")).wrapping_add(be32(b"\x00\x01\x00\x00"));
+        otf[adjustment_offset..adjustment_offset + 4].copy_from_slice(&adjustment.to_be_bytes());
+        assert_eq!(check(&otf).map_err(|err| err.to_string()), Ok(()));
+    }
+
+    #[test]
+    fn test_bad_fonts() {
+        let bad_glyf = include_bytes!("../../../test_data/bad/testfile.ttf");
+        let err = check(bad_glyf).expect_err("flipped bit in glyf");
+        assert_eq!(err.to_string(), "Font table 'glyf' has a bad checksum");
+
+        // Damage which only the whole-file checksum catches
+        let mut bad_directory = TTF.to_vec();
+        bad_directory[6] ^= 1;
+        let err = check(&bad_directory).expect_err("flipped bit in searchRange");
+        assert!(err.to_string().contains("checkSumAdjustment"), "{}", err);
+
+        let mut bad_magic = TTF.to_vec();
+        bad_magic[table_offset(TTF, b"head") + 12] ^= 1;
+        let mut bad_version = TTF.to_vec();
+        bad_version[0] = 2;
+        let mut no_tables = TTF.to_vec();
+        no_tables[4..6].copy_from_slice(&[0, 0]);
+        let mut bad_member = TTC.to_vec();
+        let member_glyf = table_offset(&TTC[be32(&TTC[16..]) as usize..], b"glyf");
+        bad_member[member_glyf + 4] ^= 1;
+        let mut empty_collection = TTC.to_vec();
+        empty_collection[8..12].copy_from_slice(&[0; 4]);
+
+        for (name, data) in &[
+            ("empty", &b""[..]),
+            ("truncated header", &TTF[..6]),
+            ("truncated", &TTF[..TTF.len() - 1]),
+            ("truncated directory", &TTF[..40]),
+            ("bad head magic", &bad_magic),
+            ("bad sfnt version", &bad_version),
+            ("no tables", &no_tables),
+            ("bad collection member", &bad_member),
+            ("empty collection", &empty_collection),
+            ("truncated collection", &TTC[..TTC.len() - 4]),
+        ] {
+            let err = check(data).expect_err(name);
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}: {}", name, err);
+        }
+    }
+}
@@ -0,0 +1,327 @@
+//! A member-by-member gzip decompressor, following RFC 1952
+//!
+//! (`flate2`'s `MultiGzDecoder` quietly stops at the first thing which doesn't look like another
+//! member, which lets appended garbage and truncated trailing members pass. This parses the
+//! headers and trailers itself and leaves only the Deflate decoding to `flate2`.)
+
+// Standard library imports
+use std::convert::TryFrom;
+use std::io::{self, BufRead, Read};
+
+// 3rd-party imports
+use crc32fast::Hasher;
+use flate2::{Decompress, FlushDecompress, Status};
+
+// Local Imports
+use super::{corrupt, eof_is_corrupt};
+
+/// The magic number at the start of every gzip member
+const MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+/// The only compression method gzip defines (Deflate)
+const CM_DEFLATE: u8 = 8;
+
+/// Header flag: A CRC16 of the header follows the other optional fields
+const FHCRC: u8 = 0x02;
+/// Header flag: An extra field is present
+const FEXTRA: u8 = 0x04;
+/// Header flag: A zero-terminated original file name is present
+const FNAME: u8 = 0x08;
+/// Header flag: A zero-terminated comment is present
+const FCOMMENT: u8 = 0x10;
+/// Header flags which RFC 1952 reserves and requires to be zero
+const FRESERVED: u8 = 0xE0;
+
+/// The state of the member currently being decompressed
+struct Member {
+    /// The CRC32 of the output so far
+    crc: Hasher,
+    /// How much has been decompressed so far
+    len: u64,
+}
+
+/// A streaming decompressor for `.gz` files which insists that they hold nothing but members
+pub struct GzipDecoder<R> {
+    /// The compressed data
+    input: R,
+    /// The raw Deflate decoder, which is reset between members
+    inflate: Decompress,
+    /// The member currently being decompressed, if any
+    member: Option<Member>,
+    /// How many members have been started
+    members: usize,
+    /// How much has been decompressed across all members
+    total_out: u64,
+}
+
+impl<R: BufRead> GzipDecoder<R> {
+    /// Prepare to decompress `input`
+    pub fn new(input: R) -> Self {
+        Self { input, inflate: Decompress::new(false), member: None, members: 0, total_out: 0 }
+    }
+
+    /// How many members have been read so far
+    pub fn members(&self) -> usize {
+        self.members
+    }
+
+    /// How much has been decompressed so far, across all members
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+
+    /// Read exactly `N` bytes from the input, feeding them into `header_crc`
+    fn read_array<const N: usize>(&mut self, header_crc: &mut Hasher) -> io::Result<[u8; N]> {
+        let mut data = [0; N];
+        self.input.read_exact(&mut data)?;
+        header_crc.update(&data);
+        Ok(data)
+    }
+
+    /// Skip a zero-terminated header field, feeding it into `header_crc`
+    fn skip_string(&mut self, header_crc: &mut Hasher) -> io::Result<()> {
+        let mut field = Vec::new();
+        self.input.read_until(0, &mut field)?;
+        if field.last() != Some(&0) {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        header_crc.update(&field);
+        Ok(())
+    }
+
+    /// Read the header of the next member, returning `false` at the end of the file
+    fn header(&mut self) -> io::Result<bool> {
+        if self.input.fill_buf()?.is_empty() {
+            return if self.members > 0 { Ok(false) } else { Err(corrupt("Empty file")) };
+        }
+
+        // Anything which isn't the start of another member (even a lone byte) is trailing junk
+        let mut header_crc = Hasher::new();
+        let is_member = match self.read_array(&mut header_crc) {
+            Ok(magic) => magic == MAGIC,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => false,
+            Err(err) => return Err(err),
+        };
+        if !is_member {
+            return Err(corrupt(if self.members > 0 {
+                "trailing data after gzip stream"
+            } else {
+                "Not a gzip file"
+            }));
+        }
+        self.members += 1;
+
+        let [method, flags, ..] = self.read_array::<8>(&mut header_crc)?;
+        if method != CM_DEFLATE {
+            return Err(corrupt(&format!("Unknown compression method in gzip member {}: {}",
+                                        self.members, method)));
+        }
+        if flags & FRESERVED != 0 {
+            return Err(corrupt(&format!("Reserved flags set in gzip member {}", self.members)));
+        }
+        if flags & FEXTRA != 0 {
+            let len = u16::from_le_bytes(self.read_array(&mut header_crc)?);
+            let mut extra = Vec::with_capacity(usize::from(len));
+            (&mut self.input).take(u64::from(len)).read_to_end(&mut extra)?;
+            if extra.len() < usize::from(len) {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            header_crc.update(&extra);
+        }
+        if flags & FNAME != 0 {
+            self.skip_string(&mut header_crc)?;
+        }
+        if flags & FCOMMENT != 0 {
+            self.skip_string(&mut header_crc)?;
+        }
+        if flags & FHCRC != 0 {
+            let stored = u16::from_le_bytes(self.read_array(&mut Hasher::new())?);
+            if u32::from(stored) != header_crc.finalize() & 0xFFFF {
+                return Err(corrupt(&format!("Header CRC mismatch in gzip member {}",
+                                            self.members)));
+            }
+        }
+
+        self.inflate.reset(false);
+        self.member = Some(Member { crc: Hasher::new(), len: 0 });
+        Ok(true)
+    }
+
+    /// Check the trailer of a member once its Deflate stream has ended
+    fn trailer(&mut self, member: Member) -> io::Result<()> {
+        let trailer: [u8; 8] = self.read_array(&mut Hasher::new())?;
+        let [crc, isize] = [0, 4].map(|x| {
+            u32::from_le_bytes(<[u8; 4]>::try_from(&trailer[x..x + 4]).expect("4 bytes"))
+        });
+        if crc != member.crc.finalize() {
+            return Err(corrupt(&format!("CRC mismatch in gzip member {}", self.members)));
+        }
+        if u64::from(isize) != member.len & 0xFFFF_FFFF {
+            return Err(corrupt(&format!("Length mismatch (ISIZE) in gzip member {}",
+                                        self.members)));
+        }
+        Ok(())
+    }
+
+    /// Decompress some data into `buf`, returning 0 at the end of the file
+    fn read_inner(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.member.is_none() && !self.header()? {
+                return Ok(0);
+            }
+
+            let members = self.members;
+            let input = self.input.fill_buf()?;
+            if input.is_empty() {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let (before_in, before_out) = (self.inflate.total_in(), self.inflate.total_out());
+            let status = self.inflate.decompress(input, buf, FlushDecompress::None)
+                .map_err(|err| corrupt(&format!("Invalid Deflate data in gzip member {}: {}",
+                                                members, err)))?;
+            let consumed = usize::try_from(self.inflate.total_in() - before_in)
+                .expect("no more than the input");
+            let produced = usize::try_from(self.inflate.total_out() - before_out)
+                .expect("no more than the output");
+            self.input.consume(consumed);
+
+            let member = self.member.as_mut().expect("set by header()");
+            member.crc.update(&buf[..produced]);
+            member.len += produced as u64;
+            self.total_out += produced as u64;
+
+            if status == Status::StreamEnd {
+                let member = self.member.take().expect("set by header()");
+                self.trailer(member)?;
+            } else if consumed == 0 && produced == 0 {
+                return Err(corrupt(&format!("Deflate data stalled in gzip member {}", members)));
+            }
+            if produced > 0 || buf.is_empty() {
+                return Ok(produced);
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Read for GzipDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        eof_is_corrupt(self.read_inner(buf))
+    }
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use flate2::write::GzEncoder;
+    use flate2::{Compression, GzBuilder};
+    use std::io::Write;
+
+    /// Compress `data` as a single gzip member
+    fn member(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// Decompress `input`, returning the error message on failure
+    fn decode(input: &[u8]) -> Result<Vec<u8>, String> {
+        let mut output = Vec::new();
+        GzipDecoder::new(input).read_to_end(&mut output).map_err(|err| {
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", err);
+            err.to_string()
+        })?;
+        Ok(output)
+    }
+
+    #[test]
+    fn test_members() {
+        assert_eq!(decode(&member(b"Hello, gzip!\n")).unwrap(), b"Hello, gzip!\n");
+        assert_eq!(decode(&member(b"")).unwrap(), b"");
+
+        let mut multi = member(b"one ");
+        multi.extend(member(b""));
+        multi.extend(member(b"two"));
+        let mut decoder = GzipDecoder::new(&multi[..]);
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).unwrap();
+        assert_eq!(output, b"one two");
+        assert_eq!((decoder.members(), decoder.total_out()), (3, 7));
+
+        // Every optional header field, including the header CRC
+        let mut encoder = GzBuilder::new()
+            .filename("name.txt")
+            .comment("comment")
+            .extra(&b"xx\x02\0ab"[..])
+            .write(Vec::new(), Compression::default());
+        encoder.write_all(b"fields").unwrap();
+        let fields = encoder.finish().unwrap();
+        assert_eq!(decode(&fields).unwrap(), b"fields");
+
+        // flate2 doesn't write FHCRC, so splice a header with one onto a plain member
+        let mut hcrc = b"\x1F\x8B\x08\x0A\0\0\0\0\0\xFFname\0".to_vec();
+        hcrc.extend(&(crc32fast::hash(&hcrc) as u16).to_le_bytes());
+        let crc_pos = hcrc.len() - 2;
+        hcrc.extend(&member(b"fields")[10..]);
+        assert_eq!(decode(&hcrc).unwrap(), b"fields");
+        hcrc[crc_pos] ^= 1;
+        assert!(decode(&hcrc).unwrap_err().starts_with("Header CRC mismatch"));
+    }
+
+    #[test]
+    fn test_damage() {
+        let good = member(b"Some text to compress, and some more text to compress");
+        assert!(decode(&[]).unwrap_err().starts_with("Empty file"));
+        assert!(decode(b"Not gzip").unwrap_err().starts_with("Not a gzip file"));
+
+        let mut junk = good.clone();
+        junk.push(0);
+        assert_eq!(decode(&junk).unwrap_err(), "trailing data after gzip stream");
+        let mut junk = good.clone();
+        junk.extend(b"\x1F\x8C\x08\0");
+        assert_eq!(decode(&junk).unwrap_err(), "trailing data after gzip stream");
+
+        let mut truncated_member = good.clone();
+        truncated_member.extend(&good[..good.len() / 2]);
+        assert_eq!(decode(&truncated_member).unwrap_err(), "Unexpected end of file");
+        for len in [5, 12, good.len() - 9, good.len() - 1] {
+            assert_eq!(decode(&good[..len]).unwrap_err(), "Unexpected end of file", "{}", len);
+        }
+
+        let len = good.len();
+        let mut bad_crc = good.clone();
+        bad_crc[len - 8] ^= 1;
+        assert!(decode(&bad_crc).unwrap_err().starts_with("CRC mismatch in gzip member 1"));
+        let mut bad_isize = good.clone();
+        bad_isize[len - 1] ^= 1;
+        assert!(decode(&bad_isize).unwrap_err().starts_with("Length mismatch (ISIZE)"));
+
+        let mut multi = good.clone();
+        multi.extend(&bad_isize);
+        assert!(decode(&multi).unwrap_err().ends_with("gzip member 2"));
+
+        let mut bad_method = good.clone();
+        bad_method[2] = 7;
+        assert!(decode(&bad_method).unwrap_err().starts_with("Unknown compression method"));
+        let mut reserved = good;
+        reserved[3] |= 0x80;
+        assert!(decode(&reserved).unwrap_err().starts_with("Reserved flags"));
+    }
+
+    #[test]
+    fn test_fixtures() {
+        let multi = include_bytes!("../../../test_data/good/testfile.multi.txt.gz");
+        let mut decoder = GzipDecoder::new(&multi[..]);
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).unwrap();
+        assert_eq!(decoder.members(), 2);
+        assert_eq!(output, include_bytes!("../../../test_data/sources/testfile.txt"));
+
+        let isize = include_bytes!("../../../test_data/bad/testfile.isize.txt.gz");
+        assert!(decode(isize).unwrap_err().starts_with("Length mismatch (ISIZE)"));
+        let junk = include_bytes!("../../../test_data/bad/testfile.junk.txt.gz");
+        assert_eq!(decode(junk).unwrap_err(), "trailing data after gzip stream");
+    }
+}
@@ -0,0 +1,698 @@
+//! A structural walker for HDF5 files
+//!
+//! (Starting at the superblock, this follows the root group down through every group it can reach,
+//! checking that each object header, B-tree node, symbol table node, and local heap is where it
+//! should be and fits within the end-of-file address. Files in the newer format also have their
+//! superblock and object headers checked against their `lookup3` checksums. The contents of
+//! datasets aren't covered by any checksum unless a Fletcher-32 filter was used, so they aren't
+//! read, and groups whose links are stored in fractal heaps aren't walked into.)
+
+// Standard library imports
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::io::{self, Read, Seek, SeekFrom};
+
+// Local Imports
+use super::checksums::lookup3;
+use super::{corrupt, unsupported};
+
+/// The signature at the start of the superblock
+const SIGNATURE: &[u8; 8] = b"\x89HDF\r\n\x1a\n";
+
+/// The superblock may be preceded by a user block of 512, 1024, 2048, ... bytes
+const FIRST_USER_BLOCK_SIZE: u64 = 512;
+
+/// Enough to hold any version of the superblock with 8-byte addresses
+const MAX_SUPERBLOCK_LEN: u64 = 128;
+
+/// The largest piece of metadata which will be read into memory at once
+const MAX_BLOCK_LEN: u64 = 64 * 1024 * 1024;
+
+/// Object header message: Where a group's links are stored, if not in its object header
+const MSG_LINK_INFO: u16 = 0x02;
+/// Object header message: A link to another object, stored in a group's object header
+const MSG_LINK: u16 = 0x06;
+/// Object header message: Where a dataset's contents are stored
+const MSG_LAYOUT: u16 = 0x08;
+/// Object header message: Where the next chunk of object header messages is
+const MSG_CONTINUATION: u16 = 0x10;
+/// Object header message: Where an old-style group's B-tree and local heap are
+const MSG_SYMBOL_TABLE: u16 = 0x11;
+
+/// The local heap free list offset which means there are no free blocks
+const NO_FREE_BLOCKS: u64 = 1;
+
+/// What was found in an HDF5 file
+#[derive(Debug, Default)]
+pub struct Summary {
+    /// The version of the superblock (0 and 1 predate metadata checksums)
+    pub superblock_version: u8,
+    /// How many object headers were walked
+    pub objects: u64,
+    /// How many pieces of metadata had their checksums verified
+    pub checksums: u64,
+    /// How many object headers were in the old format, which has no checksum
+    pub unchecksummed: u64,
+    /// How many groups stored their links in fractal heaps, which weren't walked
+    pub dense_groups: u64,
+    /// Whether the superblock says the file is still open for writing
+    pub open_for_writing: bool,
+}
+
+/// The links out of an object, if it's a group
+#[derive(Default)]
+struct Links {
+    /// The addresses of an old-style group's B-tree and local heap
+    symbol_table: Option<(u64, u64)>,
+    /// The targets of hard links stored in the object header
+    targets: Vec<u64>,
+    /// Whether the links are stored in a fractal heap instead
+    dense: bool,
+}
+
+/// A cursor over a piece of metadata which has been read into memory
+struct Cursor<'a> {
+    /// The metadata
+    data: &'a [u8],
+    /// How far into `data` the cursor is
+    pos: usize,
+    /// What the metadata is, for error messages
+    what: &'static str,
+    /// Where the metadata is, for error messages
+    addr: u64,
+}
+
+impl<'a> Cursor<'a> {
+    /// Start reading `data`, which is the `what` at `addr`
+    fn new(data: &'a [u8], what: &'static str, addr: u64) -> Self {
+        Self { data, pos: 0, what, addr }
+    }
+
+    /// How much is left to read
+    fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    /// The address of the next byte
+    fn addr(&self) -> u64 {
+        self.addr + self.pos as u64
+    }
+
+    /// Read the next `len` bytes
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let bytes = self.pos.checked_add(len).and_then(|end| self.data.get(self.pos..end))
+            .ok_or_else(|| corrupt(&format!("{} at address {} is truncated", self.what,
+                                            self.addr)))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    /// Read a little-endian unsigned integer of `len` (at most 8) bytes
+    fn uint(&mut self, len: usize) -> io::Result<u64> {
+        let mut bytes = [0; 8];
+        bytes[..len].copy_from_slice(self.take(len)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    /// Read a byte
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// Read a little-endian `u16`
+    fn u16(&mut self) -> io::Result<u16> {
+        let value = self.uint(2)?;
+        Ok(u16::try_from(value).expect("two bytes"))
+    }
+
+    /// Read a little-endian `u32`
+    fn u32(&mut self) -> io::Result<u32> {
+        let value = self.uint(4)?;
+        Ok(u32::try_from(value).expect("four bytes"))
+    }
+}
+
+/// The state of a walk through an HDF5 file
+struct Walker<R> {
+    /// The file
+    input: R,
+    /// Where the superblock is, which all addresses are relative to
+    base: u64,
+    /// The end-of-file address from the superblock, relative to `base`
+    eof: u64,
+    /// How many bytes addresses take up
+    offset_size: usize,
+    /// How many bytes lengths take up
+    length_size: usize,
+    /// Half the maximum number of entries in a symbol table node
+    leaf_k: u16,
+    /// Half the maximum number of children of a group B-tree node
+    internal_k: u16,
+    /// What has been found so far
+    summary: Summary,
+}
+
+impl<R: Read + Seek> Walker<R> {
+    /// Read the `what` at `addr`, which is `len` bytes long
+    fn read(&mut self, addr: u64, len: u64, what: &'static str) -> io::Result<Vec<u8>> {
+        if addr.checked_add(len).is_none_or(|end| end > self.eof) {
+            return Err(corrupt(&format!(
+                "{} at address {} ({} bytes) runs past the end-of-file address ({})",
+                what, addr, len, self.eof)));
+        } else if len > MAX_BLOCK_LEN {
+            return Err(unsupported(&format!("{} at address {} is larger than {} MiB",
+                                            what, addr, MAX_BLOCK_LEN / 1024 / 1024)));
+        }
+        let mut data = vec![0; usize::try_from(len).expect("no more than MAX_BLOCK_LEN")];
+        self.input.seek(SeekFrom::Start(self.base + addr))?;
+        self.input.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    /// Read as much of the `what` at `addr` as there could be, up to `len` bytes
+    fn read_up_to(&mut self, addr: u64, len: u64, what: &'static str) -> io::Result<Vec<u8>> {
+        self.read(addr, len.min(self.eof.saturating_sub(addr)), what)
+    }
+
+    /// Read an address, which is `None` if it's the "undefined address"
+    fn address(&self, cursor: &mut Cursor<'_>) -> io::Result<Option<u64>> {
+        let value = cursor.uint(self.offset_size)?;
+        Ok(if value == undefined(self.offset_size) { None } else { Some(value) })
+    }
+
+    /// Read an address which must be defined
+    fn defined_address(&self, cursor: &mut Cursor<'_>, what: &str) -> io::Result<u64> {
+        let addr = cursor.addr();
+        self.address(cursor)?.ok_or_else(|| corrupt(&format!(
+            "{what} at address {addr} is the undefined address")))
+    }
+
+    /// Read a length
+    fn length(&self, cursor: &mut Cursor<'_>) -> io::Result<u64> {
+        cursor.uint(self.length_size)
+    }
+
+    /// Check the `lookup3` checksum in the last four bytes of `data`
+    fn checksum(&mut self, data: &[u8], what: &str, addr: u64) -> io::Result<()> {
+        let (covered, stored) = data.split_at(data.len() - 4);
+        if lookup3(covered).to_le_bytes() != stored {
+            return Err(corrupt(&format!("Checksum mismatch in {what} at address {addr}")));
+        }
+        self.summary.checksums += 1;
+        Ok(())
+    }
+
+    /// Read the superblock at `base`, returning the address of the root group's object header
+    /// and anything else which should be walked
+    fn superblock(&mut self, file_len: u64) -> io::Result<Vec<u64>> {
+        let len = MAX_SUPERBLOCK_LEN.min(file_len - self.base);
+        self.eof = len;
+        let data = self.read(0, len, "Superblock")?;
+        let mut cursor = Cursor::new(&data, "Superblock", 0);
+        cursor.take(SIGNATURE.len())?;
+        let version = cursor.u8()?;
+        self.summary.superblock_version = version;
+
+        let mut pending = Vec::new();
+        if version <= 1 {
+            let versions = cursor.take(4)?;
+            if versions != [0, 0, 0, 0] {
+                return Err(corrupt("Superblock has unknown free space, symbol table, or shared \
+                                    header message versions"));
+            }
+            self.sizes(cursor.u8()?, cursor.u8()?)?;
+            cursor.take(1)?;
+            self.leaf_k = cursor.u16()?;
+            self.internal_k = cursor.u16()?;
+            if self.leaf_k == 0 || self.internal_k == 0 {
+                return Err(corrupt("Superblock has a B-tree K value of zero"));
+            }
+            cursor.take(if version == 1 { 8 } else { 4 })?;
+            self.address(&mut cursor)?;
+            self.address(&mut cursor)?;
+            self.eof = self.defined_address(&mut cursor, "End-of-file address")?;
+            let driver_info = self.address(&mut cursor)?;
+
+            pending.push(self.symbol_table_entry(&mut cursor)?.1);
+            self.check_eof(file_len)?;
+            if let Some(addr) = driver_info {
+                let data = self.read(addr, 16, "Driver information block")?;
+                let mut cursor = Cursor::new(&data, "Driver information block", addr);
+                cursor.take(4)?;
+                let info_len = cursor.u32()?;
+                self.read(addr + 16, u64::from(info_len), "Driver information block")?;
+            }
+        } else if version <= 3 {
+            self.sizes(cursor.u8()?, cursor.u8()?)?;
+            self.summary.open_for_writing = cursor.u8()? != 0;
+            self.address(&mut cursor)?;
+            let extension = self.address(&mut cursor)?;
+            self.eof = self.defined_address(&mut cursor, "End-of-file address")?;
+            pending.push(self.defined_address(&mut cursor, "Root group object header")?);
+            let covered = cursor.pos;
+            cursor.take(4)?;
+            self.checksum(&data[..covered + 4], "the superblock", 0)?;
+            self.check_eof(file_len)?;
+            pending.extend(extension);
+        } else {
+            return Err(unsupported(&format!(
+                "HDF5 superblock version {version} isn't supported")));
+        }
+        Ok(pending)
+    }
+
+    /// Set the sizes of addresses and lengths
+    fn sizes(&mut self, offset_size: u8, length_size: u8) -> io::Result<()> {
+        for size in &[offset_size, length_size] {
+            if ![2, 4, 8].contains(size) {
+                return Err(unsupported(&format!(
+                    "{size}-byte addresses or lengths aren't supported")));
+            }
+        }
+        self.offset_size = usize::from(offset_size);
+        self.length_size = usize::from(length_size);
+        Ok(())
+    }
+
+    /// Check the end-of-file address against the file's actual length
+    fn check_eof(&self, file_len: u64) -> io::Result<()> {
+        let eof = self.base.saturating_add(self.eof);
+        if eof > file_len {
+            return Err(corrupt(&format!(
+                "HDF5 file is truncated: its superblock says it ends at byte {eof}, but it's only \
+                 {file_len} bytes long")));
+        }
+        Ok(())
+    }
+
+    /// Walk the object header at `addr`, returning its links if it's a group
+    fn object(&mut self, addr: u64) -> io::Result<Links> {
+        let head = self.read_up_to(addr, 40, "Object header")?;
+        let mut cursor = Cursor::new(&head, "Object header", addr);
+        let mut links = Links::default();
+        let mut continuations = Vec::new();
+
+        if head.get(..4) == Some(b"OHDR") {
+            cursor.take(4)?;
+            let version = cursor.u8()?;
+            let flags = cursor.u8()?;
+            if version != 2 || flags & 0xC0 != 0 {
+                return Err(corrupt(&format!(
+                    "Object header at address {addr} has an unknown version or flags")));
+            }
+            cursor.take(if flags & 0x20 == 0 { 0 } else { 16 })?;
+            cursor.take(if flags & 0x10 == 0 { 0 } else { 4 })?;
+            let chunk_len = cursor.uint(1 << (flags & 0x03))?;
+            let prefix_len = cursor.pos as u64;
+            let creation_order = flags & 0x04 != 0;
+
+            let data = self.read(addr, prefix_len.saturating_add(chunk_len).saturating_add(4),
+                                 "Object header")?;
+            self.checksum(&data, "the object header", addr)?;
+            let messages = &data[cursor.pos..data.len() - 4];
+            self.v2_messages(messages, addr + prefix_len, creation_order, &mut links,
+                             &mut continuations)?;
+
+            let mut seen = HashSet::new();
+            while let Some((chunk_addr, chunk_len)) = continuations.pop() {
+                if !seen.insert(chunk_addr) || chunk_len < 8 {
+                    return Err(corrupt(&format!(
+                        "Object header at address {addr} has a bad continuation block")));
+                }
+                let data = self.read(chunk_addr, chunk_len, "Object header continuation block")?;
+                if data.get(..4) != Some(b"OCHK") {
+                    return Err(corrupt(&format!(
+                        "No object header continuation block at address {chunk_addr}")));
+                }
+                self.checksum(&data, "the object header continuation block", chunk_addr)?;
+                self.v2_messages(&data[4..data.len() - 4], chunk_addr + 4, creation_order,
+                                 &mut links, &mut continuations)?;
+            }
+        } else if head.first() == Some(&1) {
+            cursor.take(2)?;
+            let expected = cursor.u16()?;
+            cursor.take(4)?;
+            let chunk_len = cursor.u32()?;
+            cursor.take(4)?;
+            let data = self.read(addr + 16, u64::from(chunk_len), "Object header")?;
+            let mut count = self.v1_messages(&data, addr + 16, &mut links, &mut continuations)?;
+
+            let mut seen = HashSet::new();
+            while let Some((chunk_addr, chunk_len)) = continuations.pop() {
+                if !seen.insert(chunk_addr) {
+                    return Err(corrupt(&format!(
+                        "Object header at address {addr} has a continuation loop")));
+                }
+                let data = self.read(chunk_addr, chunk_len, "Object header continuation block")?;
+                count += self.v1_messages(&data, chunk_addr, &mut links, &mut continuations)?;
+            }
+            if count != usize::from(expected) {
+                return Err(corrupt(&format!(
+                    "Object header at address {addr} says it has {expected} messages but has \
+                     {count}")));
+            }
+            self.summary.unchecksummed += 1;
+        } else {
+            return Err(corrupt(&format!("No object header at address {addr}")));
+        }
+        self.summary.objects += 1;
+        Ok(links)
+    }
+
+    /// Walk the messages in a chunk of a version 1 object header, which starts at `addr`,
+    /// returning how many there were
+    fn v1_messages(&self, data: &[u8], addr: u64, links: &mut Links,
+                   continuations: &mut Vec<(u64, u64)>) -> io::Result<usize> {
+        let mut cursor = Cursor::new(data, "Object header message", addr);
+        let mut count = 0;
+        while cursor.remaining() > 0 {
+            let msg_addr = cursor.addr();
+            let kind = cursor.u16()?;
+            let len = cursor.u16()?;
+            cursor.take(4)?;
+            if len % 8 != 0 {
+                return Err(corrupt(&format!(
+                    "Object header message at address {msg_addr} isn't a multiple of 8 bytes \
+                     long")));
+            }
+            let body = cursor.take(usize::from(len))?;
+            self.message(kind, body, msg_addr, links, continuations)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Walk the messages in a chunk of a version 2 object header, which starts at `addr`
+    fn v2_messages(&self, data: &[u8], addr: u64, creation_order: bool, links: &mut Links,
+                   continuations: &mut Vec<(u64, u64)>) -> io::Result<()> {
+        let header_len = if creation_order { 6 } else { 4 };
+        let mut cursor = Cursor::new(data, "Object header message", addr);
+
+        // Anything too short to be a message header is a gap left by a deleted message
+        while cursor.remaining() >= header_len {
+            let msg_addr = cursor.addr();
+            let kind = u16::from(cursor.u8()?);
+            let len = cursor.u16()?;
+            cursor.take(header_len - 3)?;
+            let body = cursor.take(usize::from(len))?;
+            self.message(kind, body, msg_addr, links, continuations)?;
+        }
+        Ok(())
+    }
+
+    /// Check an object header message, recording any links or continuations it holds
+    fn message(&self, kind: u16, body: &[u8], addr: u64, links: &mut Links,
+               continuations: &mut Vec<(u64, u64)>) -> io::Result<()> {
+        let mut cursor = Cursor::new(body, "Object header message", addr);
+        match kind {
+            MSG_CONTINUATION => {
+                let chunk_addr = self.defined_address(&mut cursor, "Continuation block")?;
+                continuations.push((chunk_addr, self.length(&mut cursor)?));
+            },
+            MSG_SYMBOL_TABLE => {
+                let btree = self.defined_address(&mut cursor, "Group B-tree")?;
+                let heap = self.defined_address(&mut cursor, "Group local heap")?;
+                links.symbol_table = Some((btree, heap));
+            },
+            MSG_LINK_INFO => {
+                cursor.u8()?;
+                let flags = cursor.u8()?;
+                cursor.take(if flags & 0x01 == 0 { 0 } else { 8 })?;
+                links.dense |= self.address(&mut cursor)?.is_some();
+            },
+            MSG_LINK => {
+                if cursor.u8()? != 1 {
+                    return Err(corrupt(&format!("Link message at address {addr} has an unknown \
+                                                 version")));
+                }
+                let flags = cursor.u8()?;
+                let link_type = if flags & 0x08 == 0 { 0 } else { cursor.u8()? };
+                cursor.take(if flags & 0x04 == 0 { 0 } else { 8 })?;
+                cursor.take(usize::from(flags & 0x10 != 0))?;
+                let name_len = cursor.uint(1 << (flags & 0x03))?;
+                cursor.take(usize::try_from(name_len).unwrap_or(usize::MAX))?;
+                if link_type == 0 {
+                    links.targets.push(self.defined_address(&mut cursor, "Hard link")?);
+                }
+            },
+            MSG_LAYOUT => {
+                // Only versions 3 and 4 put contiguous storage in a predictable place
+                let version = cursor.u8()?;
+                if (version == 3 || version == 4) && cursor.u8()? == 1 {
+                    if let Some(data_addr) = self.address(&mut cursor)? {
+                        let len = self.length(&mut cursor)?;
+                        if data_addr.checked_add(len).is_none_or(|end| end > self.eof) {
+                            return Err(corrupt(&format!(
+                                "Dataset contents at address {} ({} bytes) run past the \
+                                 end-of-file address ({})", data_addr, len, self.eof)));
+                        }
+                    }
+                }
+            },
+            _ => {},
+        }
+        Ok(())
+    }
+
+    /// Read the local heap at `addr`, returning its data segment
+    fn local_heap(&mut self, addr: u64) -> io::Result<Vec<u8>> {
+        let header_len = 8 + 2 * self.length_size + self.offset_size;
+        let data = self.read(addr, header_len as u64, "Local heap")?;
+        let mut cursor = Cursor::new(&data, "Local heap", addr);
+        if cursor.take(4)? != b"HEAP" || cursor.u8()? != 0 {
+            return Err(corrupt(&format!("No local heap at address {addr}")));
+        }
+        cursor.take(3)?;
+        let len = self.length(&mut cursor)?;
+        let free = self.length(&mut cursor)?;
+        let data_addr = self.defined_address(&mut cursor, "Local heap data segment")?;
+        if free != NO_FREE_BLOCKS && free != undefined(self.length_size) && free >= len {
+            return Err(corrupt(&format!("Local heap at address {addr} has a free list which points \
+                                         outside it")));
+        }
+        self.read(data_addr, len, "Local heap data segment")
+    }
+
+    /// Walk the group B-tree rooted at `addr`, returning the symbol table nodes it points to
+    fn group_btree(&mut self, addr: u64, heap: &[u8]) -> io::Result<Vec<u64>> {
+        let entries_max = 2 * usize::from(self.internal_k);
+        let node_len = 8 + 2 * self.offset_size
+            + (entries_max + 1) * self.length_size + entries_max * self.offset_size;
+        let mut symbol_nodes = Vec::new();
+        let mut seen = HashSet::new();
+        let mut pending = vec![(addr, None)];
+        while let Some((node_addr, expected_level)) = pending.pop() {
+            if !seen.insert(node_addr) {
+                return Err(corrupt(&format!(
+                    "B-tree node at address {node_addr} is reachable twice")));
+            }
+            let data = self.read(node_addr, node_len as u64, "B-tree node")?;
+            let mut cursor = Cursor::new(&data, "B-tree node", node_addr);
+            if cursor.take(4)? != b"TREE" || cursor.u8()? != 0 {
+                return Err(corrupt(&format!("No group B-tree node at address {node_addr}")));
+            }
+            let level = cursor.u8()?;
+            let entries = usize::from(cursor.u16()?);
+            if expected_level.is_some_and(|x| x != level) || entries > entries_max {
+                return Err(corrupt(&format!("B-tree node at address {node_addr} has a bad level or \
+                                             number of entries")));
+            }
+            cursor.take(2 * self.offset_size)?;
+            for _ in 0..entries {
+                heap_name(heap, self.length(&mut cursor)?, node_addr)?;
+                let child = self.defined_address(&mut cursor, "B-tree child")?;
+                if level == 0 {
+                    symbol_nodes.push(child);
+                } else {
+                    pending.push((child, Some(level - 1)));
+                }
+            }
+            heap_name(heap, self.length(&mut cursor)?, node_addr)?;
+        }
+        Ok(symbol_nodes)
+    }
+
+    /// Read the symbol table node at `addr`, returning the object headers its entries point to
+    fn symbol_node(&mut self, addr: u64, heap: &[u8]) -> io::Result<Vec<u64>> {
+        let entries_max = 2 * usize::from(self.leaf_k);
+        let entry_len = 2 * self.offset_size + 24;
+        let data = self.read(addr, (8 + entries_max * entry_len) as u64, "Symbol table node")?;
+        let mut cursor = Cursor::new(&data, "Symbol table node", addr);
+        if cursor.take(4)? != b"SNOD" || cursor.u8()? != 1 {
+            return Err(corrupt(&format!("No symbol table node at address {addr}")));
+        }
+        cursor.take(1)?;
+        let entries = usize::from(cursor.u16()?);
+        if entries > entries_max {
+            return Err(corrupt(&format!(
+                "Symbol table node at address {addr} has too many entries")));
+        }
+
+        let mut objects = Vec::with_capacity(entries);
+        for _ in 0..entries {
+            let (name, object) = self.symbol_table_entry(&mut cursor)?;
+            heap_name(heap, name, addr)?;
+            objects.push(object);
+        }
+        Ok(objects)
+    }
+
+    /// Read a symbol table entry, returning its name's heap offset and its object header address
+    fn symbol_table_entry(&self, cursor: &mut Cursor<'_>) -> io::Result<(u64, u64)> {
+        let addr = cursor.addr();
+        let name = cursor.uint(self.offset_size)?;
+        let object = self.defined_address(cursor, "Symbol table entry")?;
+        if cursor.u32()? > 2 {
+            return Err(corrupt(&format!(
+                "Symbol table entry at address {addr} has an unknown cache type")));
+        }
+        cursor.take(20)?;
+        Ok((name, object))
+    }
+}
+
+/// The "undefined address" for `size`-byte fields, which has every bit set
+fn undefined(size: usize) -> u64 {
+    u64::MAX >> (64 - 8 * size)
+}
+
+/// Check that `offset` points at a NUL-terminated name in the local heap data segment `heap`,
+/// for the structure at `addr`
+fn heap_name(heap: &[u8], offset: u64, addr: u64) -> io::Result<()> {
+    let name = usize::try_from(offset).ok().and_then(|x| heap.get(x..));
+    if !name.is_some_and(|x| x.contains(&0)) {
+        return Err(corrupt(&format!(
+            "Structure at address {addr} refers to a name outside its group's local heap")));
+    }
+    Ok(())
+}
+
+/// Find the superblock, which is either at the start of the file or after a user block
+fn find_superblock<R: Read + Seek>(input: &mut R, file_len: u64) -> io::Result<u64> {
+    let mut offset = 0;
+    while offset + SIGNATURE.len() as u64 <= file_len {
+        let mut signature = [0; SIGNATURE.len()];
+        input.seek(SeekFrom::Start(offset))?;
+        input.read_exact(&mut signature)?;
+        if &signature == SIGNATURE {
+            return Ok(offset);
+        }
+        offset = if offset == 0 { FIRST_USER_BLOCK_SIZE } else { offset * 2 };
+    }
+    Err(corrupt("No HDF5 superblock signature found"))
+}
+
+/// Walk an HDF5 file from its superblock, through every group reachable from the root group
+///
+/// The end-of-file address is checked against `file_len` before anything else is read, so a
+/// truncated file is reported as such rather than as whatever happens to be missing first.
+pub fn verify<R: Read + Seek>(mut input: R, file_len: u64) -> io::Result<Summary> {
+    let base = find_superblock(&mut input, file_len)?;
+    let mut walker = Walker { input, base, eof: 0, offset_size: 8, length_size: 8, leaf_k: 0,
+                              internal_k: 0, summary: Summary::default() };
+    let mut pending = walker.superblock(file_len)?;
+
+    // Hard links can legitimately lead to the same object more than once
+    let mut visited = HashSet::new();
+    while let Some(addr) = pending.pop() {
+        if !visited.insert(addr) {
+            continue;
+        }
+        let links = walker.object(addr)?;
+        if let Some((btree, heap_addr)) = links.symbol_table {
+            let heap = walker.local_heap(heap_addr)?;
+            for node in walker.group_btree(btree, &heap)? {
+                pending.extend(walker.symbol_node(node, &heap)?);
+            }
+        }
+        pending.extend(links.targets);
+        walker.summary.dense_groups += u64::from(links.dense);
+    }
+    Ok(walker.summary)
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The original format fixture, with a version 0 superblock and no checksums
+    const V0: &[u8] = include_bytes!("../../../test_data/good/testfile.h5");
+
+    /// The newer format fixture, with a version 2 superblock and checksummed object headers
+    const V2: &[u8] = include_bytes!("../../../test_data/good/testfile.v2.h5");
+
+    /// Walk `data` as an in-memory file
+    fn check(data: &[u8]) -> io::Result<Summary> {
+        verify(io::Cursor::new(data), data.len() as u64)
+    }
+
+    /// Walk `data`, expecting a failure of `kind` whose message contains `message`
+    fn check_err(data: &[u8], kind: io::ErrorKind, message: &str) {
+        let err = check(data).expect_err(message);
+        assert_eq!(err.kind(), kind, "{err}");
+        assert!(err.to_string().contains(message), "{}", err);
+    }
+
+    #[test]
+    fn test_fixtures() {
+        for (name, data, version, checksums, unchecksummed) in &[
+            ("v0", V0, 0, 0, 3),
+            ("v2", V2, 2, 5, 0),
+            ("user block", &include_bytes!("../../../test_data/good/testfile.userblock.h5")[..],
+                2, 5, 0),
+        ] {
+            let summary = check(data).unwrap_or_else(|err| panic!("{}: {}", name, err));
+            assert_eq!(summary.superblock_version, *version, "{name}");
+            assert_eq!(summary.objects, 3, "{name}");
+            assert_eq!(summary.checksums, *checksums, "{name}");
+            assert_eq!(summary.unchecksummed, *unchecksummed, "{name}");
+            assert_eq!(summary.dense_groups, 0, "{name}");
+            assert!(!summary.open_for_writing, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_truncated() {
+        check_err(include_bytes!("../../../test_data/bad/testfile.h5"), io::ErrorKind::InvalidData,
+            "truncated: its superblock says it ends at byte 1976, but it's only 1876 bytes long");
+
+        let mut with_user_block = vec![0; 512];
+        with_user_block.extend_from_slice(V0);
+        with_user_block.pop();
+        check_err(&with_user_block, io::ErrorKind::InvalidData,
+            "ends at byte 2488, but it's only 2487 bytes long");
+    }
+
+    #[test]
+    fn test_damaged_metadata() {
+        check_err(include_bytes!("../../../test_data/bad/testfile.v2.h5"),
+            io::ErrorKind::InvalidData, "Checksum mismatch in the object header at address 168");
+
+        let mut damaged = V2.to_vec();
+        damaged[20] ^= 0x01;
+        check_err(&damaged, io::ErrorKind::InvalidData, "Checksum mismatch in the superblock");
+
+        // The root group's object header is at 96, with its message count at 98
+        let mut miscounted = V0.to_vec();
+        miscounted[98] += 1;
+        check_err(&miscounted, io::ErrorKind::InvalidData,
+            "Object header at address 96 says it has 2 messages but has 1");
+    }
+
+    #[test]
+    fn test_not_hdf5() {
+        check_err(b"", io::ErrorKind::InvalidData, "No HDF5 superblock signature found");
+        check_err(b"Not an HDF5 file", io::ErrorKind::InvalidData, "No HDF5 superblock signature");
+
+        // The superblock may only follow a user block of 512 bytes or a power of two beyond that
+        let mut misplaced = vec![0; 100];
+        misplaced.extend_from_slice(V0);
+        check_err(&misplaced, io::ErrorKind::InvalidData, "No HDF5 superblock signature found");
+
+        let mut future = V2.to_vec();
+        future[8] = 4;
+        check_err(&future, io::ErrorKind::Unsupported, "superblock version 4 isn't supported");
+    }
+}
@@ -0,0 +1,436 @@
+//! A checker for HEIF images (HEIC, AVIF, etc.)
+//!
+//! (These are ISO Base Media files, so their box structure is walked the same way as MP4. After
+//! that, the `meta` box is checked: every extent listed in `iloc` must lie within the file or the
+//! `idat` box, every item named by `iref` or `ipma` must exist, and the items which make up the
+//! primary image must have data. The coded image data itself isn't decoded.)
+
+// Standard library imports
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::io::{self, Read, Seek, SeekFrom};
+
+// Local Imports
+use super::mp4::{check_movie, children, full_box, name, only_child, top_level_boxes};
+use super::{corrupt, unsupported};
+
+/// The largest `meta` box which will be read into memory to check the items
+const MAX_META_SIZE: u64 = 64 * 1024 * 1024;
+
+/// `ftyp` brands which identify a file as HEIF, for still images and image sequences, or AVIF
+const BRANDS: [&[u8; 4]; 14] = [
+    b"mif1", b"mif2", b"msf1", b"heic", b"heix", b"heim", b"heis", b"hevc", b"hevx", b"hevm",
+    b"hevs", b"avif", b"avis", b"avio",
+];
+
+/// Item types which don't store any data of their own
+const DATALESS_TYPES: [&[u8; 4]; 1] = [b"iden"];
+
+/// What was found in a HEIF file
+#[derive(Debug, Default)]
+pub struct Summary {
+    /// How many items the `meta` box describes
+    pub items: usize,
+    /// How many extents of item data were checked against the end of the file or `idat` box
+    pub extents: usize,
+    /// How many items make up the primary image, including the primary item itself
+    pub primary_parts: usize,
+    /// Whether there's also a `moov` box holding an image sequence
+    pub sequence: bool,
+}
+
+/// A cursor over the fields of a box's payload
+struct Fields<'a> {
+    /// The box, for use in error messages
+    kind: &'static str,
+    /// The fields which haven't been read yet
+    data: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    /// Read the next `len` bytes
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() < len {
+            return Err(corrupt(&format!("HEIF {} box is too short", self.kind)));
+        }
+        let (field, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(field)
+    }
+
+    /// Read a big-endian unsigned integer which is `len` bytes long (possibly zero)
+    fn uint(&mut self, len: usize) -> io::Result<u64> {
+        Ok(self.take(len)?.iter().fold(0, |acc, &byte| (acc << 8) | u64::from(byte)))
+    }
+
+    /// Read an item ID, which is 16 bits long in version 0 boxes and 32 bits long otherwise
+    fn item_id(&mut self, version: u8) -> io::Result<u32> {
+        self.uint(if version == 0 { 2 } else { 4 }).map(|id| id as u32)
+    }
+}
+
+/// Find the child of type `kind`, if there is one, complaining if there are several
+fn optional_child<'a>(parent: &str, boxes: &[([u8; 4], &'a [u8])], kind: &[u8; 4])
+        -> io::Result<Option<&'a [u8]>> {
+    match boxes.iter().filter(|(found, _)| found == kind).count() {
+        0 => Ok(None),
+        _ => only_child(parent, boxes, kind).map(Some),
+    }
+}
+
+/// Check that the `ftyp` box's brands identify the file as HEIF or AVIF
+fn check_brands(ftyp: &[u8]) -> io::Result<()> {
+    if ftyp.len() < 8 || ftyp.len() % 4 != 0 {
+        return Err(corrupt("HEIF ftyp box is malformed"));
+    }
+    // Skip the minor version, which isn't a brand
+    let mut brands = ftyp[..4].chunks(4).chain(ftyp[8..].chunks(4));
+    if brands.any(|brand| BRANDS.iter().any(|known| &known[..] == brand)) {
+        Ok(())
+    } else {
+        Err(corrupt("HEIF ftyp box doesn't list a HEIF or AVIF brand"))
+    }
+}
+
+/// Read the `iinf` box, returning the type of each item
+///
+/// (Items described by version 0 or 1 `infe` boxes, which predate item types, get four spaces.)
+fn item_info(iinf: &[u8]) -> io::Result<BTreeMap<u32, [u8; 4]>> {
+    let (_, body) = full_box("HEIF iinf", iinf, &[2, 4])?;
+    let mut fields = Fields { kind: "iinf", data: body };
+    let count = fields.uint(if iinf[0] == 0 { 2 } else { 4 })?;
+    let entries = children("HEIF iinf", fields.data)?;
+    if entries.len() as u64 != count {
+        return Err(corrupt("HEIF iinf box's entry count doesn't match its contents"));
+    }
+
+    let mut items = BTreeMap::new();
+    for (kind, infe) in entries {
+        if &kind != b"infe" {
+            return Err(corrupt(&format!(
+                "HEIF iinf box contains something other than infe boxes ({})", name(kind))));
+        }
+        let (_, body) = full_box("HEIF infe", infe, &[4, 4, 8, 10])?;
+        let version = infe[0];
+        let mut fields = Fields { kind: "infe", data: body };
+        let id = fields.item_id(if version < 3 { 0 } else { 1 })?;
+        fields.take(2)?;
+        let item_type = if version < 2 { *b"    " } else {
+            <[u8; 4]>::try_from(fields.take(4)?).expect("took 4 bytes")
+        };
+        if items.insert(id, item_type).is_some() {
+            return Err(corrupt(&format!("HEIF item {} is described more than once", id)));
+        }
+    }
+    Ok(items)
+}
+
+/// Read the `iloc` box, checking that each extent of item data stored in this file lies within
+/// the file or the `idat` box, and returning the number of extents in each item
+fn item_locations(iloc: &[u8], file_len: u64, idat_len: Option<u64>, summary: &mut Summary)
+        -> io::Result<BTreeMap<u32, u16>> {
+    let (_, body) = full_box("HEIF iloc", iloc, &[4, 4, 6])?;
+    let version = iloc[0];
+    let mut fields = Fields { kind: "iloc", data: body };
+    let sizes = fields.take(2)?;
+    let (offset_size, length_size) = (usize::from(sizes[0] >> 4), usize::from(sizes[0] & 0xF));
+    let base_offset_size = usize::from(sizes[1] >> 4);
+    let index_size = if version == 0 { 0 } else { usize::from(sizes[1] & 0xF) };
+    if [offset_size, length_size, base_offset_size, index_size].iter().any(|&size|
+            ![0, 4, 8].contains(&size)) {
+        return Err(corrupt("HEIF iloc box has an invalid field size"));
+    }
+
+    let mut locations = BTreeMap::new();
+    for _ in 0..fields.uint(if version < 2 { 2 } else { 4 })? {
+        let id = fields.item_id(if version < 2 { 0 } else { 1 })?;
+        let method = if version == 0 { 0 } else { fields.uint(2)? & 0xF };
+        let data_reference = fields.uint(2)?;
+        let base_offset = fields.uint(base_offset_size)?;
+        let extent_count = fields.uint(2)? as u16;
+
+        // Item data can also come from another file (when the data reference isn't 0) or be made
+        // of pieces of other items (construction method 2), neither of which can be checked here
+        let limit = match (method, data_reference) {
+            (0, 0) => Some((file_len, "the end of the file (truncated?)")),
+            (1, _) => Some((idat_len.ok_or_else(|| corrupt(&format!(
+                "HEIF item {} is stored in the idat box, but there isn't one", id)))?,
+                "the end of the idat box")),
+            (0, _) | (2, _) => None,
+            _ => return Err(unsupported(&format!(
+                "Unsupported HEIF iloc construction method {}", method))),
+        };
+        for _ in 0..extent_count {
+            fields.uint(index_size)?;
+            let offset = fields.uint(offset_size)?;
+            let length = fields.uint(length_size)?;
+            if let Some((limit, problem)) = limit {
+                // A length of 0 means "all of the data that's there"
+                let end = base_offset.checked_add(offset)
+                    .and_then(|start| start.checked_add(length.max(1)));
+                if end.map_or(true, |end| end > limit) {
+                    return Err(corrupt(&format!("HEIF item {} has data past {}", id, problem)));
+                }
+                summary.extents += 1;
+            }
+        }
+        if locations.insert(id, extent_count).is_some() {
+            return Err(corrupt(&format!("HEIF item {} is located more than once", id)));
+        }
+    }
+    if !fields.data.is_empty() {
+        return Err(corrupt("HEIF iloc box has data after its last item"));
+    }
+    Ok(locations)
+}
+
+/// Read the `iref` box, returning the type, source item, and target items of each reference
+fn item_references(iref: &[u8]) -> io::Result<Vec<([u8; 4], u32, Vec<u32>)>> {
+    let (_, body) = full_box("HEIF iref", iref, &[0, 0])?;
+    let version = iref[0];
+    let mut references = Vec::new();
+    for (kind, payload) in children("HEIF iref", body)? {
+        let mut fields = Fields { kind: "iref", data: payload };
+        let from = fields.item_id(version)?;
+        let targets = (0..fields.uint(2)?).map(|_| fields.item_id(version))
+            .collect::<io::Result<_>>()?;
+        if !fields.data.is_empty() {
+            return Err(corrupt(&format!(
+                "HEIF {} reference's item count doesn't match its size", name(kind))));
+        }
+        references.push((kind, from, targets));
+    }
+    Ok(references)
+}
+
+/// Read the `iprp` box, checking that each property association refers to a property which
+/// exists, and returning the items which have properties
+fn item_properties(iprp: &[u8]) -> io::Result<BTreeSet<u32>> {
+    let boxes = children("HEIF iprp", iprp)?;
+    let properties = children("HEIF ipco", only_child("HEIF iprp", &boxes, b"ipco")?)?.len();
+
+    let mut items = BTreeSet::new();
+    for (_, ipma) in boxes.iter().filter(|(kind, _)| kind == b"ipma") {
+        let (flags, body) = full_box("HEIF ipma", ipma, &[4, 4])?;
+        let version = ipma[0];
+        let mut fields = Fields { kind: "ipma", data: body };
+        for _ in 0..fields.uint(4)? {
+            let id = fields.item_id(version)?;
+            for _ in 0..fields.uint(1)? {
+                // The top bit marks the property as essential
+                let index = if flags & 1 == 0 { fields.uint(1)? & 0x7F } else {
+                    fields.uint(2)? & 0x7FFF
+                };
+                if index as usize > properties {
+                    return Err(corrupt(&format!(
+                        "HEIF item {} is associated with property {}, but there are only {}",
+                        id, index, properties)));
+                }
+            }
+            if !items.insert(id) {
+                return Err(corrupt(&format!(
+                    "HEIF item {} has its properties listed more than once", id)));
+            }
+        }
+    }
+    Ok(items)
+}
+
+/// Check the items described by the `meta` box
+fn check_meta(meta: &[u8], file_len: u64, summary: &mut Summary) -> io::Result<()> {
+    let (_, body) = full_box("HEIF meta", meta, &[0])?;
+    let boxes = children("HEIF meta", body)?;
+
+    let (_, hdlr) = full_box("HEIF hdlr", only_child("HEIF meta", &boxes, b"hdlr")?, &[20])?;
+    if &hdlr[4..8] != b"pict" {
+        return Err(corrupt(&format!(
+            "HEIF meta box has a {} handler, rather than pict", String::from_utf8_lossy(
+                &hdlr[4..8]))));
+    }
+
+    let pitm = only_child("HEIF meta", &boxes, b"pitm")?;
+    let (_, pitm_body) = full_box("HEIF pitm", pitm, &[2, 4])?;
+    let primary = Fields { kind: "pitm", data: pitm_body }.item_id(pitm[0])?;
+
+    let items = item_info(only_child("HEIF meta", &boxes, b"iinf")?)?;
+    summary.items = items.len();
+    let exists = |id: &u32| items.contains_key(id);
+    if !exists(&primary) {
+        return Err(corrupt(&format!("HEIF primary item {} doesn't exist", primary)));
+    }
+
+    let idat_len = optional_child("HEIF meta", &boxes, b"idat")?.map(|idat| idat.len() as u64);
+    let locations = item_locations(only_child("HEIF meta", &boxes, b"iloc")?, file_len,
+                                   idat_len, summary)?;
+    if let Some(id) = locations.keys().find(|id| !exists(id)) {
+        return Err(corrupt(&format!("HEIF iloc box locates item {}, which doesn't exist", id)));
+    }
+
+    let references = match optional_child("HEIF meta", &boxes, b"iref")? {
+        Some(iref) => item_references(iref)?,
+        None => Vec::new(),
+    };
+    for (kind, from, targets) in &references {
+        if let Some(id) = Some(from).filter(|id| !exists(id)).or_else(||
+                targets.iter().find(|id| !exists(id))) {
+            return Err(corrupt(&format!(
+                "HEIF {} reference from item {} names item {}, which doesn't exist",
+                name(*kind), from, id)));
+        }
+    }
+
+    if let Some(iprp) = optional_child("HEIF meta", &boxes, b"iprp")? {
+        if let Some(id) = item_properties(iprp)?.iter().find(|id| !exists(id)) {
+            return Err(corrupt(&format!(
+                "HEIF ipma box lists properties for item {}, which doesn't exist", id)));
+        }
+    }
+
+    // Follow the references out of the primary item (eg. to the tiles of a grid image) to find
+    // everything it's made of, all of which must have data
+    let (mut parts, mut pending) = (BTreeSet::new(), vec![primary]);
+    while let Some(id) = pending.pop() {
+        if !parts.insert(id) {
+            continue;
+        }
+        let dataless = DATALESS_TYPES.iter().any(|&kind| kind == &items[&id]);
+        if !dataless && locations.get(&id).map_or(true, |&extents| extents == 0) {
+            return Err(corrupt(&format!(
+                "HEIF item {}, which is part of the primary image, has no data", id)));
+        }
+        pending.extend(references.iter().filter(|(_, from, _)| *from == id)
+            .flat_map(|(_, _, targets)| targets));
+    }
+    summary.primary_parts = parts.len();
+    Ok(())
+}
+
+/// Check the structure of the HEIF or AVIF file read from `input`, which is `file_len` bytes long
+///
+/// The file must start with an `ftyp` box listing a HEIF or AVIF brand and have a `meta` box
+/// describing its items. If it also has a `moov` box (for an image sequence), that gets the same
+/// checks as in an MP4 file.
+pub fn verify<R: Read + Seek>(mut input: R, file_len: u64) -> io::Result<Summary> {
+    let boxes = top_level_boxes(&mut input, file_len, "HEIF")?;
+    let mut summary = Summary::default();
+
+    match boxes.first() {
+        Some(&(kind, start, end)) if &kind == b"ftyp" => {
+            let mut ftyp = vec![0; usize::try_from(end - start).unwrap_or(usize::MAX).min(1024)];
+            input.seek(SeekFrom::Start(start))?;
+            input.read_exact(&mut ftyp)?;
+            check_brands(&ftyp)?;
+        },
+        _ => return Err(corrupt("HEIF file doesn't start with an ftyp box")),
+    }
+    if boxes.iter().any(|(kind, _, _)| kind == b"moof") {
+        return Err(unsupported("Fragmented HEIF image sequences aren't supported"));
+    }
+
+    let find = |wanted: &[u8; 4]| {
+        let mut found = boxes.iter().filter(move |(kind, _, _)| kind == wanted);
+        match (found.next(), found.next()) {
+            (Some(_), Some(_)) => Err(corrupt(&format!(
+                "HEIF file has more than one {} box", name(*wanted)))),
+            (found, _) => Ok(found.map(|&(_, start, end)| (start, end))),
+        }
+    };
+    let (meta, moov) = (find(b"meta")?, find(b"moov")?);
+    if let Some((start, end)) = moov {
+        check_movie(&mut input, start, end, file_len)?;
+        summary.sequence = true;
+    }
+    match meta {
+        Some((start, end)) if end - start > MAX_META_SIZE => {
+            Err(unsupported("HEIF meta box is too large to check"))
+        },
+        Some((start, end)) => {
+            let mut meta = vec![0; (end - start) as usize];
+            input.seek(SeekFrom::Start(start))?;
+            input.read_exact(&mut meta)?;
+            check_meta(&meta, file_len, &mut summary)?;
+            Ok(summary)
+        },
+        None if summary.sequence => Ok(summary),
+        None => Err(corrupt("HEIF file has no meta box")),
+    }
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The HEIC fixture, with a grid of two tiles as its primary item
+    const HEIC: &[u8] = include_bytes!("../../../test_data/good/testfile.heic");
+
+    /// Walk `data` as an in-memory file
+    fn check(data: &[u8]) -> io::Result<Summary> {
+        verify(io::Cursor::new(data), data.len() as u64)
+    }
+
+    /// Walk `data`, expecting a failure whose message contains `message`
+    fn check_err(data: &[u8], message: &str) {
+        let err = check(data).expect_err(message);
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", err);
+        assert!(err.to_string().contains(message), "{}", err);
+    }
+
+    /// Find where the payload of the first `kind` box in `data` starts
+    fn payload_of(data: &[u8], kind: &[u8; 4]) -> usize {
+        data.windows(4).position(|x| x == kind).expect("box type present") + 4
+    }
+
+    #[test]
+    fn test_fixtures() {
+        for (name, data, items, parts) in &[
+            ("heic", HEIC, 5, 3),
+            ("avif", &include_bytes!("../../../test_data/good/testfile.avif")[..], 2, 1),
+        ] {
+            let summary = check(data).unwrap_or_else(|err| panic!("{}: {}", name, err));
+            assert_eq!(summary.items, *items, "{}", name);
+            assert_eq!(summary.extents, *items, "{}", name);
+            assert_eq!(summary.primary_parts, *parts, "{}", name);
+            assert!(!summary.sequence, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_bad_fixtures() {
+        check_err(include_bytes!("../../../test_data/bad/testfile.heic"),
+            "HEIF item 5 has data past the end of the file (truncated?)");
+        check_err(include_bytes!("../../../test_data/bad/testfile.avif"),
+            "HEIF item 1 is associated with property 5, but there are only 4");
+
+        for cut in &[10, 100, HEIC.len() - 20, HEIC.len() - 1] {
+            check_err(&HEIC[..*cut], "");
+        }
+    }
+
+    #[test]
+    fn test_brands() {
+        let mut data = HEIC.to_vec();
+        data[8..28].copy_from_slice(b"mp42\0\0\0\0isomiso2mp41");
+        check_err(&data, "HEIF ftyp box doesn't list a HEIF or AVIF brand");
+
+        // The brand only needs to be somewhere in the list
+        data[24..28].copy_from_slice(b"mif1");
+        check(&data).expect("compatible brand");
+    }
+
+    #[test]
+    fn test_item_references() {
+        let mut data = HEIC.to_vec();
+        data[payload_of(HEIC, b"pitm") + 5] = 7;
+        check_err(&data, "HEIF primary item 7 doesn't exist");
+
+        let mut data = HEIC.to_vec();
+        data[payload_of(HEIC, b"dimg") + 7] = 9;
+        check_err(&data, "HEIF dimg reference from item 1 names item 9, which doesn't exist");
+
+        let mut data = HEIC.to_vec();
+        data[payload_of(HEIC, b"idat") - 4..][..4].copy_from_slice(b"free");
+        check_err(&data, "HEIF item 1 is stored in the idat box, but there isn't one");
+    }
+}
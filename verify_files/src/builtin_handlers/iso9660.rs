@@ -0,0 +1,474 @@
+//! A walker for the volume descriptors and directory trees of ISO 9660 disc images
+//!
+//! (ISO 9660 has no checksums, but it stores most numbers in both byte orders and every extent
+//! has to fit inside the volume, so damaged metadata and truncated images are easy to spot. File
+//! contents aren't read, and Joliet and Rock Ridge are only walked as far as the base format
+//! requires.)
+
+// Standard library imports
+use std::collections::HashSet;
+use std::io::{self, Read, Seek, SeekFrom};
+
+// Local Imports
+use super::{corrupt, unsupported};
+
+/// The size of the sectors volume descriptors are stored in
+const SECTOR_SIZE: u64 = 2048;
+
+/// The sector the volume descriptor set starts at (after the system area)
+const FIRST_DESCRIPTOR_SECTOR: u64 = 16;
+
+/// The identifier every ISO 9660 volume descriptor carries
+const STANDARD_ID: &[u8] = b"CD001";
+
+/// The identifiers of the ECMA-167 descriptors which start a UDF volume recognition sequence
+const UDF_IDS: [&[u8]; 6] = [b"BEA01", b"BOOT2", b"CDW02", b"NSR02", b"NSR03", b"TEA01"];
+
+/// Volume descriptor type: Primary volume descriptor
+const PRIMARY: u8 = 1;
+/// Volume descriptor type: Supplementary or enhanced volume descriptor (eg. Joliet)
+const SUPPLEMENTARY: u8 = 2;
+/// Volume descriptor type: Volume descriptor set terminator
+const TERMINATOR: u8 = 255;
+
+/// The most volume descriptors which will be read before giving up on finding the terminator
+const MAX_DESCRIPTORS: u64 = 256;
+
+/// The largest directory which will be read into memory
+const MAX_DIRECTORY_SIZE: u32 = 16 * 1024 * 1024;
+
+/// The smallest possible directory record (with a one-byte identifier)
+const MIN_RECORD_SIZE: usize = 34;
+
+/// Directory record flag: The record is for a directory
+const FLAG_DIRECTORY: u8 = 0x02;
+
+/// The cookie at the start of the headers dvdisaster puts in the error correction data it appends
+const DVDISASTER_COOKIE: &[u8] = b"*dvdisaster*";
+
+/// How many sectors past the end of the volume to look for a dvdisaster header
+const MAX_ECC_HEADER_SEARCH: u64 = 16;
+
+/// Read a "both-byte orders" `u16` from the start of `bytes`, requiring that the copies agree
+fn both16(bytes: &[u8], field: &str) -> io::Result<u16> {
+    let little = u16::from_le_bytes([bytes[0], bytes[1]]);
+    if little != u16::from_be_bytes([bytes[2], bytes[3]]) {
+        return Err(corrupt(&format!("ISO 9660 {} field has mismatched byte orders", field)));
+    }
+    Ok(little)
+}
+
+/// Read a "both-byte orders" `u32` from the start of `bytes`, requiring that the copies agree
+fn both32(bytes: &[u8], field: &str) -> io::Result<u32> {
+    let little = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    if little != u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) {
+        return Err(corrupt(&format!("ISO 9660 {} field has mismatched byte orders", field)));
+    }
+    Ok(little)
+}
+
+/// Read the 2048-byte sector at `index`, reporting an early EOF as truncation
+fn read_sector<R: Read + Seek>(input: &mut R, index: u64) -> io::Result<Vec<u8>> {
+    let mut sector = vec![0; SECTOR_SIZE as usize];
+    input.seek(SeekFrom::Start(index * SECTOR_SIZE))?;
+    #[allow(clippy::wildcard_enum_match_arm)]
+    input.read_exact(&mut sector).map_err(|err| match err.kind() {
+        io::ErrorKind::UnexpectedEof => corrupt(&format!(
+            "ISO 9660 image is truncated (sector {} is missing)", index)),
+        _ => err,
+    })?;
+    Ok(sector)
+}
+
+/// The parts of a directory record needed to walk the tree
+struct Record {
+    /// The first logical block of the extent, including any extended attribute record
+    extent: u32,
+    /// The length of the extended attribute record, in logical blocks
+    xattr_len: u8,
+    /// The length of the file section, in bytes
+    data_len: u32,
+    /// The file flags
+    flags: u8,
+    /// The file identifier
+    name: Vec<u8>,
+}
+
+impl Record {
+    /// Parse the directory record at the start of `bytes`, which must be exactly its length
+    fn parse(bytes: &[u8]) -> io::Result<Self> {
+        let name_len = usize::from(bytes[32]);
+        if bytes.len() < MIN_RECORD_SIZE - 1 + name_len {
+            return Err(corrupt("ISO 9660 directory record is too short for its identifier"));
+        }
+        both16(&bytes[28..], "volume sequence number")?;
+        Ok(Self {
+            extent: both32(&bytes[2..], "extent location")?,
+            xattr_len: bytes[1],
+            data_len: both32(&bytes[10..], "data length")?,
+            flags: bytes[25],
+            name: bytes[33..33 + name_len].to_vec(),
+        })
+    }
+
+    /// Check that the extent lies within a volume of `volume_blocks` logical blocks
+    fn check_bounds(&self, block_size: u64, volume_blocks: u64) -> io::Result<()> {
+        // Empty files conventionally point at block 0, since they have no extent
+        if self.data_len == 0 && self.xattr_len == 0 {
+            return Ok(());
+        }
+        let blocks = (u64::from(self.data_len) + block_size - 1) / block_size;
+        if u64::from(self.extent) + u64::from(self.xattr_len) + blocks > volume_blocks {
+            return Err(corrupt(&format!(
+                "ISO 9660 directory entry {:?} points outside the volume (extent at block {})",
+                String::from_utf8_lossy(&self.name), self.extent)));
+        }
+        Ok(())
+    }
+
+    /// The block the file section starts at (after any extended attribute record) and its length
+    fn data_start(&self) -> (u64, u32) {
+        (u64::from(self.extent) + u64::from(self.xattr_len), self.data_len)
+    }
+}
+
+/// The fields of a primary or supplementary volume descriptor that the walk needs
+struct Volume {
+    /// The size of the volume, in logical blocks
+    blocks: u64,
+    /// The size of a logical block, in bytes
+    block_size: u64,
+    /// The directory record for the root directory
+    root: Record,
+}
+
+impl Volume {
+    /// Parse the primary or supplementary volume descriptor in `sector`
+    fn parse(sector: &[u8]) -> io::Result<Self> {
+        let blocks = u64::from(both32(&sector[80..], "volume space size")?);
+        let block_size = u64::from(both16(&sector[128..], "logical block size")?);
+        if !block_size.is_power_of_two() || block_size < 512 || block_size > SECTOR_SIZE {
+            return Err(corrupt(&format!("ISO 9660 logical block size is invalid: {}",
+                block_size)));
+        }
+        if usize::from(sector[156]) != MIN_RECORD_SIZE {
+            return Err(corrupt("ISO 9660 root directory record has the wrong size"));
+        }
+        let root = Record::parse(&sector[156..156 + MIN_RECORD_SIZE])?;
+        if root.flags & FLAG_DIRECTORY == 0 {
+            return Err(corrupt("ISO 9660 root directory record isn't for a directory"));
+        }
+        Ok(Self { blocks, block_size, root })
+    }
+
+    /// Walk the directory tree, checking that every extent lies within the volume
+    fn walk<R: Read + Seek>(&self, input: &mut R) -> io::Result<()> {
+        let mut visited = HashSet::new();
+        self.root.check_bounds(self.block_size, self.blocks)?;
+        let mut pending = vec![self.root.data_start()];
+        while let Some((extent, len)) = pending.pop() {
+            if !visited.insert(extent) {
+                return Err(corrupt(&format!(
+                    "ISO 9660 directory at block {} is reachable more than once", extent)));
+            }
+            for record in read_directory(input, self.block_size, extent, len)? {
+                record.check_bounds(self.block_size, self.blocks)?;
+                // Skip the `.` and `..` entries, which point at this directory and its parent
+                let dots = record.name == [0] || record.name == [1];
+                if record.flags & FLAG_DIRECTORY != 0 && !dots {
+                    pending.push(record.data_start());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Read and parse the records of the `len`-byte directory whose data starts at block `extent`
+fn read_directory<R: Read + Seek>(input: &mut R, block_size: u64, extent: u64, len: u32)
+        -> io::Result<Vec<Record>> {
+    if len > MAX_DIRECTORY_SIZE {
+        return Err(unsupported(&format!(
+            "ISO 9660 directory at block {} is too large to check ({} bytes)", extent, len)));
+    }
+    let mut data = vec![0; len as usize];
+    input.seek(SeekFrom::Start(extent * block_size))?;
+    input.read_exact(&mut data)?;
+
+    // Records can't cross sector boundaries, so a zero length byte means "skip to the next"
+    let mut records = Vec::new();
+    for sector in data.chunks(SECTOR_SIZE as usize) {
+        let mut rest = sector;
+        while let Some(&record_len) = rest.first() {
+            let record_len = usize::from(record_len);
+            if record_len == 0 {
+                break;
+            }
+            if record_len < MIN_RECORD_SIZE || record_len > rest.len() {
+                return Err(corrupt(&format!(
+                    "ISO 9660 directory at block {} has a record with an invalid length",
+                    extent)));
+            }
+            records.push(Record::parse(&rest[..record_len])?);
+            rest = &rest[record_len..];
+        }
+    }
+    if records.first().map_or(true, |x| x.name != [0]) {
+        return Err(corrupt(&format!(
+            "ISO 9660 directory at block {} doesn't start with a \".\" entry", extent)));
+    }
+    Ok(records)
+}
+
+/// Look for the header dvdisaster puts after the end of the volume, returning its method ID
+fn find_ecc_method<R: Read + Seek>(input: &mut R, volume_end: u64, file_len: u64)
+        -> io::Result<Option<String>> {
+    let mut header = [0; 16];
+    for sector in 0..MAX_ECC_HEADER_SEARCH {
+        let offset = volume_end + sector * SECTOR_SIZE;
+        if offset + header.len() as u64 > file_len {
+            break;
+        }
+        input.seek(SeekFrom::Start(offset))?;
+        input.read_exact(&mut header)?;
+        if header.starts_with(DVDISASTER_COOKIE) {
+            let method = &header[DVDISASTER_COOKIE.len()..];
+            return Ok(Some(String::from_utf8_lossy(method).into_owned()));
+        }
+    }
+    Ok(None)
+}
+
+/// Walk the volume descriptors and directory trees of the ISO 9660 image in `input` (which is
+/// `file_len` bytes long)
+///
+/// The volume must fit in the file and every directory entry must point inside it. Images which
+/// also carry a UDF filesystem are only checked as ISO 9660, while pure UDF images are reported
+/// as unsupported.
+///
+/// Returns the method ID of the dvdisaster error correction data appended to the image (eg.
+/// `RS02`), if there is any. It isn't checked.
+pub fn verify<R: Read + Seek>(mut input: R, file_len: u64) -> io::Result<Option<String>> {
+    let mut primary = None;
+    let mut supplementary = Vec::new();
+    for index in FIRST_DESCRIPTOR_SECTOR..FIRST_DESCRIPTOR_SECTOR + MAX_DESCRIPTORS {
+        let sector = read_sector(&mut input, index)?;
+        let id = &sector[1..6];
+        if id != STANDARD_ID {
+            if index == FIRST_DESCRIPTOR_SECTOR && UDF_IDS.contains(&id) {
+                return Err(unsupported("UDF images without an ISO 9660 bridge aren't supported"));
+            } else if index == FIRST_DESCRIPTOR_SECTOR {
+                return Err(corrupt("Not an ISO 9660 image (no volume descriptor at sector 16)"));
+            }
+            return Err(corrupt(&format!(
+                "ISO 9660 volume descriptor at sector {} has a bad signature", index)));
+        }
+
+        // Enhanced volume descriptors are supplementary volume descriptors with version 2
+        let version_ok = sector[6] == 1 || (sector[0] == SUPPLEMENTARY && sector[6] == 2);
+        if !version_ok {
+            return Err(corrupt(&format!(
+                "ISO 9660 volume descriptor at sector {} has an invalid version: {}",
+                index, sector[6])));
+        }
+        match sector[0] {
+            PRIMARY if primary.is_none() => primary = Some(Volume::parse(&sector)?),
+            SUPPLEMENTARY => supplementary.push(Volume::parse(&sector)?),
+            TERMINATOR => break,
+            _ => {},
+        }
+        if index + 1 == FIRST_DESCRIPTOR_SECTOR + MAX_DESCRIPTORS {
+            return Err(corrupt("ISO 9660 volume descriptor set has no terminator"));
+        }
+    }
+    let primary =
+        primary.ok_or_else(|| corrupt("ISO 9660 image has no primary volume descriptor"))?;
+
+    let volume_len = primary.blocks * primary.block_size;
+    if volume_len > file_len {
+        return Err(corrupt(&format!(
+            "ISO 9660 image is truncated (the volume is {} bytes, but the file is {})",
+            volume_len, file_len)));
+    }
+    for volume in std::iter::once(&primary).chain(&supplementary) {
+        if volume.blocks * volume.block_size > volume_len {
+            return Err(corrupt("ISO 9660 supplementary volume is larger than the primary one"));
+        }
+        volume.walk(&mut input)?;
+    }
+    find_ecc_method(&mut input, volume_len, file_len)
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// Encode `value` as a "both-byte orders" `u32`
+    fn both(value: u32) -> Vec<u8> {
+        let mut out = value.to_le_bytes().to_vec();
+        out.extend(&value.to_be_bytes());
+        out
+    }
+
+    /// Build a directory record, with `system_use` standing in for Rock Ridge entries
+    fn record(name: &[u8], extent: u32, len: u32, flags: u8, system_use: &[u8]) -> Vec<u8> {
+        let mut out = vec![0, 0];
+        out.extend(both(extent));
+        out.extend(both(len));
+        out.extend(&[0; 7]);
+        out.extend(&[flags, 0, 0, 1, 0, 0, 1, name.len() as u8]);
+        out.extend(name);
+        if name.len() % 2 == 0 {
+            out.push(0);
+        }
+        out.extend(system_use);
+        out[0] = out.len() as u8;
+        out
+    }
+
+    /// Build a volume descriptor of type `kind` with the given root directory location
+    fn descriptor(kind: u8, blocks: u32, root: u32) -> Vec<u8> {
+        let mut out = vec![kind];
+        out.extend(STANDARD_ID);
+        out.push(1);
+        out.resize(80, 0);
+        out.extend(both(blocks));
+        out.resize(128, 0);
+        out.extend(&[0x00, 0x08, 0x08, 0x00]);
+        out.resize(156, 0);
+        out.extend(record(&[0], root, SECTOR_SIZE as u32, FLAG_DIRECTORY, &[]));
+        out.resize(SECTOR_SIZE as usize, 0);
+        out
+    }
+
+    /// Build a sector holding the given directory records
+    fn directory(records: &[Vec<u8>]) -> Vec<u8> {
+        let mut out: Vec<u8> = records.concat();
+        out.resize(SECTOR_SIZE as usize, 0);
+        out
+    }
+
+    /// Build a sector starting with a non-ISO 9660 volume structure descriptor
+    fn udf_descriptor(id: &[u8]) -> Vec<u8> {
+        let mut out = vec![0];
+        out.extend(id);
+        out.push(1);
+        out.resize(SECTOR_SIZE as usize, 0);
+        out
+    }
+
+    /// The number of logical blocks in the volume built by [`image`]
+    const IMAGE_BLOCKS: u32 = 25;
+
+    /// Build a small image with a Joliet volume, Rock Ridge entries, a subdirectory, and a file
+    ///
+    /// (Sectors 16-18 hold the descriptors, 19-21 are left blank for a UDF volume recognition
+    /// sequence, 22 and 23 hold the root and subdirectory, and 24 holds the file. The primary and
+    /// Joliet volumes share their directories, which real images don't do.)
+    fn image() -> Vec<Vec<u8>> {
+        let rock_ridge = b"SP\x07\x01\xBE\xEF\x00";
+        let mut terminator = udf_descriptor(STANDARD_ID);
+        terminator[0] = TERMINATOR;
+        let mut sectors = vec![vec![0; SECTOR_SIZE as usize]; 16];
+        sectors.push(descriptor(PRIMARY, IMAGE_BLOCKS, 22));
+        sectors.push(descriptor(SUPPLEMENTARY, IMAGE_BLOCKS, 22));
+        sectors.push(terminator);
+        sectors.extend(vec![vec![0; SECTOR_SIZE as usize]; 3]);
+        sectors.push(directory(&[
+            record(&[0], 22, 2048, FLAG_DIRECTORY, rock_ridge),
+            record(&[1], 22, 2048, FLAG_DIRECTORY, &[]),
+            record(b"FILE.TXT;1", 24, 5, 0, b"NM\x0A\x01\x00file.txt"),
+            record(b"EMPTY.TXT;1", 0, 0, 0, &[]),
+            record(b"SUB", 23, 2048, FLAG_DIRECTORY, &[]),
+        ]));
+        sectors.push(directory(&[
+            record(&[0], 23, 2048, FLAG_DIRECTORY, &[]),
+            record(&[1], 22, 2048, FLAG_DIRECTORY, &[]),
+        ]));
+        sectors.push(directory(&[b"hello".to_vec()]));
+        sectors
+    }
+
+    /// Find the offset of the directory record for `name` in `sector`
+    fn find(sector: &[u8], name: &[u8]) -> usize {
+        let start = sector.windows(name.len()).position(|x| x == name).expect("name present");
+        start - 33
+    }
+
+    /// Check the image made of `sectors` the way the handler would
+    fn check(sectors: &[Vec<u8>]) -> io::Result<Option<String>> {
+        let data = sectors.concat();
+        verify(Cursor::new(&data), data.len() as u64)
+    }
+
+    #[test]
+    fn test_good_images() {
+        assert_eq!(check(&image()).map_err(|err| err.to_string()), Ok(None));
+
+        // A UDF bridge follows the ISO 9660 descriptors and is left alone
+        let mut bridged = image();
+        for (i, id) in [b"BEA01", b"NSR02", b"TEA01"].iter().enumerate() {
+            bridged[19 + i] = udf_descriptor(*id);
+        }
+        assert_eq!(check(&bridged).map_err(|err| err.to_string()), Ok(None));
+
+        // Appended data is tolerated, and identified if it's from dvdisaster
+        let mut padded = image();
+        padded.push(vec![0; SECTOR_SIZE as usize]);
+        assert_eq!(check(&padded).map_err(|err| err.to_string()), Ok(None));
+        let mut augmented = image();
+        augmented.push(directory(&[b"*dvdisaster*RS02".to_vec()]));
+        assert_eq!(check(&augmented).map_err(|err| err.to_string()), Ok(Some("RS02".to_owned())));
+    }
+
+    #[test]
+    fn test_pure_udf() {
+        let mut sectors = vec![vec![0; SECTOR_SIZE as usize]; 16];
+        for id in &[b"BEA01", b"NSR03", b"TEA01"] {
+            sectors.push(udf_descriptor(*id));
+        }
+        assert_eq!(check(&sectors).map_err(|err| err.kind()), Err(io::ErrorKind::Unsupported));
+    }
+
+    #[test]
+    fn test_bad_images() {
+        let file = |x: &[u8]| find(x, b"FILE.TXT");
+        let sub = |x: &[u8]| find(x, b"SUB");
+        let cases: Vec<(&str, Box<dyn Fn(&mut Vec<Vec<u8>>)>)> = vec![
+            ("empty", Box::new(|x| x.clear())),
+            ("truncated", Box::new(|x| x.truncate(24))),
+            ("truncated descriptors", Box::new(|x| x.truncate(18))),
+            ("bad signature", Box::new(|x| x[16][1] = b'X')),
+            ("bad later signature", Box::new(|x| x[17][1] = b'X')),
+            ("bad version", Box::new(|x| x[16][6] = 3)),
+            ("no primary", Box::new(|x| x[16][0] = SUPPLEMENTARY)),
+            ("no terminator", Box::new(|x| x[18][0] = 0)),
+            ("mismatched byte orders", Box::new(|x| x[16][84] ^= 1)),
+            ("bad block size", Box::new(|x| x[16][128..132].copy_from_slice(&[0, 3, 3, 0]))),
+            ("extent outside volume", Box::new(move |x| {
+                let offset = file(&x[22]);
+                x[22][offset + 2] = 99;
+            })),
+            ("bad record length", Box::new(move |x| {
+                let offset = file(&x[22]);
+                x[22][offset] = 33;
+            })),
+            ("directory loop", Box::new(move |x| {
+                let offset = sub(&x[22]);
+                x[22][offset + 2] = 22;
+                x[22][offset + 9] = 22;
+            })),
+            ("missing dot entry", Box::new(|x| x[23][33] = b'A')),
+        ];
+        for (name, damage) in cases {
+            let mut sectors = image();
+            damage(&mut sectors);
+            let err = check(&sectors).expect_err(name);
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}: {}", name, err);
+        }
+    }
+}
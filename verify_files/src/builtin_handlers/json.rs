@@ -0,0 +1,135 @@
+//! A streaming well-formedness check for JSON documents
+//!
+//! (The document is parsed straight from the reader and each value is discarded as soon as it has
+//! been read, so no DOM is built and memory use doesn't grow with the size of the file. Only one
+//! string at a time and the nesting depth, which `serde_json` caps at 128 levels, are ever held in
+//! memory.)
+
+// Standard library imports
+use std::fmt;
+use std::io::{self, BufReader, Read};
+
+// 3rd-party imports
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde_json::error::Category;
+
+// Local Imports
+use super::corrupt;
+
+/// A JSON value which has been parsed and then thrown away
+///
+/// (This does the job of [`IgnoredAny`](de::IgnoredAny), but asks for every value to be parsed
+/// rather than skipped, since `serde_json` skips over strings without checking that they're valid
+/// UTF-8.)
+struct Discarded;
+
+impl<'de> Deserialize<'de> for Discarded {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(Discarded)
+    }
+}
+
+impl<'de> Visitor<'de> for Discarded {
+    type Value = Self;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("any JSON value")
+    }
+
+    fn visit_bool<E: de::Error>(self, _value: bool) -> Result<Self, E> { Ok(Self) }
+    fn visit_i64<E: de::Error>(self, _value: i64) -> Result<Self, E> { Ok(Self) }
+    fn visit_u64<E: de::Error>(self, _value: u64) -> Result<Self, E> { Ok(Self) }
+    fn visit_f64<E: de::Error>(self, _value: f64) -> Result<Self, E> { Ok(Self) }
+    fn visit_str<E: de::Error>(self, _value: &str) -> Result<Self, E> { Ok(Self) }
+    fn visit_unit<E: de::Error>(self) -> Result<Self, E> { Ok(Self) }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self, A::Error> {
+        while seq.next_element::<Self>()?.is_some() {}
+        Ok(Self)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self, A::Error> {
+        while map.next_entry::<Self, Self>()?.is_some() {}
+        Ok(Self)
+    }
+}
+
+/// Convert a `serde_json` error into the `io::Error` convention used by the other checkers
+///
+/// (Errors from the underlying reader are passed through untouched, so damage found by a
+/// decompressor further up the chain keeps its original kind.)
+fn classify(err: serde_json::Error) -> io::Error {
+    match err.classify() {
+        Category::Io => err.into(),
+        Category::Syntax | Category::Data | Category::Eof => corrupt(&err.to_string()),
+    }
+}
+
+/// Check that `input` holds exactly one well-formed JSON value, optionally surrounded by
+/// whitespace
+///
+/// Anything else after the top-level value is reported as corruption, since that's what two
+/// concatenated documents or a file overwritten in place by a shorter one look like.
+pub fn verify(input: impl Read) -> io::Result<()> {
+    let mut deserializer = serde_json::Deserializer::from_reader(BufReader::new(input));
+    Discarded::deserialize(&mut deserializer).map_err(classify)?;
+    deserializer.end().map_err(classify)
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_good_documents() {
+        for (name, data) in &[
+            ("fixture", &include_bytes!("../../../test_data/good/testfile.json")[..]),
+            ("scalar", b"42"),
+            ("whitespace", b" \t\r\n{\"a\": [1, 2.5e3, true, null, \"\\u00e9\"]}\n\n"),
+            ("non-ASCII", "[\"\u{e9}t\u{e9}\"]".as_bytes()),
+        ] {
+            assert_eq!(verify(*data).map_err(|err| err.to_string()), Ok(()), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_bad_documents() {
+        for (name, data, message) in &[
+            ("fixture", &include_bytes!("../../../test_data/bad/testfile.json")[..],
+                "at line 1 column 2"),
+            ("empty", b"", "EOF while parsing a value"),
+            ("truncated", b"{\"a\": [1, 2", "EOF while parsing a list"),
+            ("concatenated", b"{\"a\": 1}\n{\"b\": 2}\n", "trailing characters at line 2"),
+            ("overwritten", b"[1, 2]\n, 3]\n", "trailing characters"),
+            ("trailing NULs", b"[]\0\0\0\0", "trailing characters"),
+            ("bad UTF-8", b"[\"\xC3\x28\"]", "invalid unicode code point"),
+            ("bad UTF-8 key", b"{\"\xFF\": 1}", "invalid unicode code point"),
+        ] {
+            let err = verify(*data).expect_err(name);
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}: {}", name, err);
+            assert!(err.to_string().contains(message), "{}: {}", name, err);
+        }
+    }
+
+    #[test]
+    fn test_reader_errors_pass_through() {
+        /// A reader which fails partway through a document
+        struct Failing(&'static [u8]);
+        impl Read for Failing {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.0.is_empty() {
+                    return Err(io::Error::new(io::ErrorKind::PermissionDenied, "no more"));
+                }
+                let len = buf.len().min(self.0.len());
+                buf[..len].copy_from_slice(&self.0[..len]);
+                self.0 = &self.0[len..];
+                Ok(len)
+            }
+        }
+
+        let err = verify(Failing(b"[1, 2")).expect_err("reader error");
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied, "{}", err);
+    }
+}
@@ -0,0 +1,1375 @@
+//! A minimal LZMA/LZMA2 decompressor
+//!
+//! (Just enough to verify `.xz` and `.7z` files without depending on `liblzma`. It favours being
+//! easy to check against the LZMA specification over speed.)
+
+// Standard library imports
+use std::convert::TryFrom;
+use std::io::{self, Read};
+
+// Local Imports
+use super::bytes::low32;
+use super::corrupt;
+
+/// How many bits of precision the adaptive bit probabilities have
+const PROB_BITS: u32 = 11;
+
+/// The initial value of every probability (an even chance of either bit)
+const PROB_INIT: u16 = 1 << (PROB_BITS - 1);
+
+/// How quickly the probabilities adapt
+const MOVE_BITS: u32 = 5;
+
+/// The range coder renormalizes whenever its range drops below this
+const TOP_VALUE: u32 = 1 << 24;
+
+/// The number of states in the LZMA state machine
+const NUM_STATES: usize = 12;
+
+/// The first position slot whose low bits are coded via the align probabilities
+const END_POS_MODEL_INDEX: u32 = 14;
+
+/// The shortest match which can be encoded
+const MATCH_MIN_LEN: usize = 2;
+
+/// The arithmetic decoder underlying LZMA
+pub struct RangeDecoder<R: Read> {
+    /// The compressed data
+    input: R,
+    /// The width of the current interval
+    range: u32,
+    /// The position of the coded value within the current interval
+    code: u32,
+}
+
+impl<R: Read> RangeDecoder<R> {
+    /// Start decoding from `input`, consuming the five initialization bytes
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let mut init = [0; 5];
+        input.read_exact(&mut init)?;
+        if init[0] != 0 {
+            return Err(corrupt("Invalid LZMA range coder initialization"));
+        }
+        let code = u32::from_be_bytes([init[1], init[2], init[3], init[4]]);
+        if code == u32::MAX {
+            return Err(corrupt("Invalid LZMA range coder initialization"));
+        }
+        Ok(Self { input, range: u32::MAX, code })
+    }
+
+    /// Whether the encoder flushed its state here (as it does at the end of the data)
+    pub fn is_finished_ok(&self) -> bool {
+        self.code == 0
+    }
+
+    /// Give back the input once decoding is complete
+    pub fn into_inner(self) -> R {
+        self.input
+    }
+
+    /// Keep `range` within the precision needed by the next bit
+    fn normalize(&mut self) -> io::Result<()> {
+        if self.range < TOP_VALUE {
+            let mut byte = [0];
+            self.input.read_exact(&mut byte)?;
+            self.range <<= 8;
+            self.code = (self.code << 8) | u32::from(byte[0]);
+        }
+        Ok(())
+    }
+
+    /// Decode one bit and adapt `prob` to it
+    pub fn bit(&mut self, prob: &mut u16) -> io::Result<u32> {
+        let bound = (self.range >> PROB_BITS) * u32::from(*prob);
+        let bit = if self.code < bound {
+            *prob += ((1 << PROB_BITS) - *prob) >> MOVE_BITS;
+            self.range = bound;
+            0
+        } else {
+            *prob -= *prob >> MOVE_BITS;
+            self.code -= bound;
+            self.range -= bound;
+            1
+        };
+        self.normalize()?;
+        Ok(bit)
+    }
+
+    /// Decode `count` bits which have a fixed, even probability
+    pub fn direct_bits(&mut self, count: u32) -> io::Result<u32> {
+        let mut result = 0;
+        for _ in 0..count {
+            self.range >>= 1;
+            let bit = if self.code >= self.range {
+                self.code -= self.range;
+                1
+            } else {
+                0
+            };
+            if self.code == self.range {
+                return Err(corrupt("Invalid LZMA range coder state"));
+            }
+            result = (result << 1) | bit;
+            self.normalize()?;
+        }
+        Ok(result)
+    }
+
+    /// Decode a `bits`-wide value, most significant bit first, using a tree of probabilities
+    pub fn bit_tree(&mut self, probs: &mut [u16], bits: u32) -> io::Result<u32> {
+        let mut node = 1;
+        for _ in 0..bits {
+            node = (node << 1) | self.bit(&mut probs[node as usize])?;
+        }
+        Ok(node - (1 << bits))
+    }
+
+    /// Decode a `bits`-wide value, least significant bit first, using a tree of probabilities
+    pub fn bit_tree_reverse(&mut self, probs: &mut [u16], bits: u32) -> io::Result<u32> {
+        let (mut node, mut result) = (1, 0);
+        for idx in 0..bits {
+            let bit = self.bit(&mut probs[node as usize])?;
+            node = (node << 1) | bit;
+            result |= bit << idx;
+        }
+        Ok(result)
+    }
+}
+
+/// The sliding dictionary which matches are copied out of
+///
+/// (Grown on demand so that a large dictionary size in the headers doesn't cost anything unless
+/// the data is actually that large.)
+pub struct Window {
+    /// The most recent output, used as a ring buffer once it reaches `size`
+    buffer: Vec<u8>,
+    /// The dictionary size
+    size: usize,
+    /// Where the next byte goes in `buffer`
+    pos: usize,
+    /// Whether `buffer` has wrapped around at least once
+    full: bool,
+    /// How many bytes have been output since the dictionary was last reset
+    total: u64,
+}
+
+impl Window {
+    /// Create an empty window for a dictionary of `size` bytes
+    pub fn new(size: u32) -> Self {
+        let size = usize::try_from(size.max(4096)).unwrap_or(usize::MAX);
+        Self { buffer: Vec::new(), size, pos: 0, full: false, total: 0 }
+    }
+
+    /// Forget everything that has been output
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+        self.pos = 0;
+        self.full = false;
+        self.total = 0;
+    }
+
+    /// Whether a match may reach `dist` bytes back (where 1 is the most recent byte)
+    fn has_distance(&self, dist: usize) -> bool {
+        dist > 0 && dist <= if self.full { self.size } else { self.pos }
+    }
+
+    /// The byte `dist` bytes back, which must already have been checked with `has_distance`
+    fn get(&self, dist: usize) -> u8 {
+        let idx = if dist <= self.pos { self.pos - dist } else { self.size + self.pos - dist };
+        self.buffer[idx]
+    }
+
+    /// Append a byte to both the window and `out`
+    pub fn put(&mut self, byte: u8, out: &mut Vec<u8>) {
+        if self.buffer.len() < self.size {
+            self.buffer.push(byte);
+        } else {
+            self.buffer[self.pos] = byte;
+        }
+        self.pos += 1;
+        if self.pos == self.size {
+            self.pos = 0;
+            self.full = true;
+        }
+        self.total += 1;
+        out.push(byte);
+    }
+}
+
+/// The probabilities for decoding match lengths
+struct LenDecoder {
+    /// Whether the length is 8 or more
+    choice: u16,
+    /// Whether the length is 16 or more
+    choice2: u16,
+    /// Lengths 0-7 for each position state
+    low: [[u16; 8]; 16],
+    /// Lengths 8-15 for each position state
+    mid: [[u16; 8]; 16],
+    /// Lengths 16-271
+    high: [u16; 256],
+}
+
+impl LenDecoder {
+    /// Create a decoder with every probability at its initial value
+    fn new() -> Self {
+        Self {
+            choice: PROB_INIT,
+            choice2: PROB_INIT,
+            low: [[PROB_INIT; 8]; 16],
+            mid: [[PROB_INIT; 8]; 16],
+            high: [PROB_INIT; 256],
+        }
+    }
+
+    /// Decode a length, not including the minimum match length
+    fn decode<R: Read>(&mut self, rc: &mut RangeDecoder<R>, pos_state: usize) -> io::Result<usize> {
+        Ok(if rc.bit(&mut self.choice)? == 0 {
+            rc.bit_tree(&mut self.low[pos_state], 3)? as usize
+        } else if rc.bit(&mut self.choice2)? == 0 {
+            8 + rc.bit_tree(&mut self.mid[pos_state], 3)? as usize
+        } else {
+            16 + rc.bit_tree(&mut self.high, 8)? as usize
+        })
+    }
+}
+
+/// The literal context, literal position, and position bit counts for an LZMA stream
+#[derive(Clone, Copy)]
+pub struct Properties {
+    /// How many high bits of the previous byte select the literal probabilities
+    pub lc: u32,
+    /// How many low bits of the position select the literal probabilities
+    pub lp: u32,
+    /// How many low bits of the position select the other probabilities
+    pub pb: u32,
+}
+
+impl Properties {
+    /// Unpack the properties from the single byte they're stored as
+    pub fn from_byte(byte: u8) -> io::Result<Self> {
+        if byte >= 9 * 5 * 5 {
+            return Err(corrupt("Invalid LZMA properties"));
+        }
+        let byte = u32::from(byte);
+        Ok(Self { lc: byte % 9, lp: (byte / 9) % 5, pb: byte / 45 })
+    }
+}
+
+/// What `LzmaDecoder::decode` stopped at
+#[derive(Debug, PartialEq, Eq)]
+pub enum Stop {
+    /// It produced as many bytes as it was asked for
+    Limit,
+    /// It found an end-of-payload marker
+    EndMarker,
+}
+
+/// The state of an LZMA decoder, apart from the range coder and dictionary
+pub struct LzmaDecoder {
+    /// The bit counts which determine how the probabilities are indexed
+    props: Properties,
+    /// Probabilities for literals, `0x300` per literal context
+    literal: Vec<u16>,
+    /// Probabilities for the position slot (the high bits of a distance), per length
+    pos_slot: [[u16; 64]; 4],
+    /// Probabilities for the low bits of mid-sized distances
+    pos_special: [u16; 115],
+    /// Probabilities for the low four bits of large distances
+    align: [u16; 16],
+    /// Probabilities of a match rather than a literal, per state and position
+    is_match: [u16; NUM_STATES << 4],
+    /// Probabilities of a repeated distance rather than a new one, per state
+    is_rep: [u16; NUM_STATES],
+    /// Probabilities of reusing the most recent distance, per state
+    is_rep_g0: [u16; NUM_STATES],
+    /// Probabilities of reusing the second most recent distance, per state
+    is_rep_g1: [u16; NUM_STATES],
+    /// Probabilities of reusing the third rather than fourth most recent distance, per state
+    is_rep_g2: [u16; NUM_STATES],
+    /// Probabilities of a repeat being longer than one byte, per state and position
+    is_rep0_long: [u16; NUM_STATES << 4],
+    /// Probabilities for the lengths of new matches
+    len: LenDecoder,
+    /// Probabilities for the lengths of repeated matches
+    rep_len: LenDecoder,
+    /// The state machine's current state
+    state: usize,
+    /// The four most recent distances, minus one
+    reps: [u32; 4],
+    /// How much of the last match didn't fit within the previous call's `limit`
+    pending: usize,
+}
+
+impl LzmaDecoder {
+    /// Create a decoder in its initial state
+    pub fn new(props: Properties) -> Self {
+        Self {
+            props,
+            literal: vec![PROB_INIT; 0x300 << (props.lc + props.lp)],
+            pos_slot: [[PROB_INIT; 64]; 4],
+            pos_special: [PROB_INIT; 115],
+            align: [PROB_INIT; 16],
+            is_match: [PROB_INIT; NUM_STATES << 4],
+            is_rep: [PROB_INIT; NUM_STATES],
+            is_rep_g0: [PROB_INIT; NUM_STATES],
+            is_rep_g1: [PROB_INIT; NUM_STATES],
+            is_rep_g2: [PROB_INIT; NUM_STATES],
+            is_rep0_long: [PROB_INIT; NUM_STATES << 4],
+            len: LenDecoder::new(),
+            rep_len: LenDecoder::new(),
+            state: 0,
+            reps: [0; 4],
+            pending: 0,
+        }
+    }
+
+    /// Return to the initial state without changing the properties
+    pub fn reset(&mut self) {
+        *self = Self::new(self.props);
+    }
+
+    /// Decode a literal byte into the window
+    fn literal<R: Read>(
+        &mut self,
+        rc: &mut RangeDecoder<R>,
+        window: &mut Window,
+        out: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        let Properties { lc, lp, .. } = self.props;
+        let prev_byte = if window.total > 0 { u32::from(window.get(1)) } else { 0 };
+        let pos_bits = low32(window.total & ((1 << lp) - 1));
+        let context = ((pos_bits << lc) + (prev_byte >> (8 - lc))) as usize;
+        let probs = &mut self.literal[0x300 * context..0x300 * (context + 1)];
+
+        let mut symbol = 1;
+        if self.state >= 7 {
+            // After a match, the byte at the match distance is a good predictor
+            let mut match_byte = u32::from(window.get(self.reps[0] as usize + 1));
+            while symbol < 0x100 {
+                let match_bit = (match_byte >> 7) & 1;
+                match_byte <<= 1;
+                let bit = rc.bit(&mut probs[(((1 + match_bit) << 8) + symbol) as usize])?;
+                symbol = (symbol << 1) | bit;
+                if match_bit != bit {
+                    break;
+                }
+            }
+        }
+        while symbol < 0x100 {
+            symbol = (symbol << 1) | rc.bit(&mut probs[symbol as usize])?;
+        }
+        // The leading 1 the bits were shifted in behind ends up just above the low byte
+        let [byte, ..] = symbol.to_le_bytes();
+        window.put(byte, out);
+        Ok(())
+    }
+
+    /// Decode the distance (minus one) of a new match of length `len` (minus two)
+    fn distance<R: Read>(&mut self, rc: &mut RangeDecoder<R>, len: usize) -> io::Result<u32> {
+        let pos_slot = rc.bit_tree(&mut self.pos_slot[len.min(3)], 6)?;
+        if pos_slot < 4 {
+            return Ok(pos_slot);
+        }
+        let direct_bits = (pos_slot >> 1) - 1;
+        let mut dist = (2 | (pos_slot & 1)) << direct_bits;
+        if pos_slot < END_POS_MODEL_INDEX {
+            let probs = &mut self.pos_special[(dist - pos_slot) as usize..];
+            dist += rc.bit_tree_reverse(probs, direct_bits)?;
+        } else {
+            dist += rc.direct_bits(direct_bits - 4)? << 4;
+            dist += rc.bit_tree_reverse(&mut self.align, 4)?;
+        }
+        Ok(dist)
+    }
+
+    /// Whether the last call to `decode` stopped partway through a match
+    ///
+    /// (Fine if more data is to be decoded, but corruption if `limit` was the end of the data.)
+    pub fn has_pending_match(&self) -> bool {
+        self.pending > 0
+    }
+
+    /// Copy up to `limit` bytes of the current match, returning how many were copied
+    fn copy_match(&mut self, window: &mut Window, out: &mut Vec<u8>, limit: u64) -> usize {
+        let dist = self.reps[0] as usize + 1;
+        let len = self.pending.min(usize::try_from(limit).unwrap_or(usize::MAX));
+        for _ in 0..len {
+            let byte = window.get(dist);
+            window.put(byte, out);
+        }
+        self.pending -= len;
+        len
+    }
+
+    /// Decode into `window` (and `out`) until `limit` bytes have been produced or an end marker
+    /// is found
+    ///
+    /// A match which crosses `limit` is finished by the next call.
+    pub fn decode<R: Read>(
+        &mut self,
+        rc: &mut RangeDecoder<R>,
+        window: &mut Window,
+        out: &mut Vec<u8>,
+        limit: u64,
+    ) -> io::Result<Stop> {
+        let pos_mask = (1 << self.props.pb) - 1;
+        let mut remaining = limit - self.copy_match(window, out, limit) as u64;
+        while remaining > 0 {
+            let pos_state = low32(window.total & pos_mask) as usize;
+            let state = self.state;
+
+            if rc.bit(&mut self.is_match[(state << 4) + pos_state])? == 0 {
+                self.literal(rc, window, out)?;
+                self.state = match state {
+                    0..=3 => 0,
+                    4..=9 => state - 3,
+                    _ => state - 6,
+                };
+                remaining -= 1;
+                continue;
+            }
+
+            let len = if rc.bit(&mut self.is_rep[state])? == 0 {
+                let len = self.len.decode(rc, pos_state)?;
+                self.state = if state < 7 { 7 } else { 10 };
+                let dist = self.distance(rc, len)?;
+                if dist == u32::MAX {
+                    return Ok(Stop::EndMarker);
+                }
+                self.reps = [dist, self.reps[0], self.reps[1], self.reps[2]];
+                len
+            } else {
+                if window.total == 0 {
+                    return Err(corrupt("LZMA data repeats a match before any output"));
+                }
+                if rc.bit(&mut self.is_rep_g0[state])? == 0 {
+                    if rc.bit(&mut self.is_rep0_long[(state << 4) + pos_state])? == 0 {
+                        // A "short rep" of a single byte
+                        self.state = if state < 7 { 9 } else { 11 };
+                        let byte = window.get(self.reps[0] as usize + 1);
+                        window.put(byte, out);
+                        remaining -= 1;
+                        continue;
+                    }
+                } else {
+                    let dist = if rc.bit(&mut self.is_rep_g1[state])? == 0 {
+                        self.reps[1]
+                    } else {
+                        let dist = if rc.bit(&mut self.is_rep_g2[state])? == 0 {
+                            self.reps[2]
+                        } else {
+                            let dist = self.reps[3];
+                            self.reps[3] = self.reps[2];
+                            dist
+                        };
+                        self.reps[2] = self.reps[1];
+                        dist
+                    };
+                    self.reps[1] = self.reps[0];
+                    self.reps[0] = dist;
+                }
+                self.state = if state < 7 { 8 } else { 11 };
+                self.rep_len.decode(rc, pos_state)?
+            };
+
+            if !window.has_distance(self.reps[0] as usize + 1) {
+                return Err(corrupt("LZMA match distance is beyond the start of the data"));
+            }
+            self.pending = len + MATCH_MIN_LEN;
+            remaining -= self.copy_match(window, out, remaining) as u64;
+        }
+        Ok(Stop::Limit)
+    }
+}
+
+/// Decode the dictionary size from an LZMA2 properties byte
+pub fn lzma2_dict_size(byte: u8) -> io::Result<u32> {
+    match byte {
+        0..=39 => Ok((2 | u32::from(byte & 1)) << (byte / 2 + 11)),
+        40 => Ok(u32::MAX),
+        _ => Err(corrupt("Invalid LZMA2 dictionary size")),
+    }
+}
+
+/// A decoder for the chunked LZMA2 format
+pub struct Lzma2Decoder {
+    /// The dictionary, shared by every chunk since the last dictionary reset
+    window: Window,
+    /// The LZMA decoder state, once a chunk has set the properties
+    lzma: Option<LzmaDecoder>,
+    /// Whether the next chunk must reset the dictionary (only true at the start)
+    need_dict_reset: bool,
+    /// Whether the next LZMA chunk must set new properties
+    need_props: bool,
+}
+
+impl Lzma2Decoder {
+    /// Create a decoder for a stream with the given dictionary size
+    pub fn new(dict_size: u32) -> Self {
+        Self { window: Window::new(dict_size), lzma: None, need_dict_reset: true, need_props: true }
+    }
+
+    /// Read a big-endian `u16`
+    fn read_u16(input: &mut impl Read) -> io::Result<u16> {
+        let mut bytes = [0; 2];
+        input.read_exact(&mut bytes)?;
+        Ok(u16::from_be_bytes(bytes))
+    }
+
+    /// Decode the next chunk from `input`, appending its contents to `out`
+    ///
+    /// Returns `false` once the end-of-data marker has been read.
+    pub fn decode_chunk(&mut self, input: &mut impl Read, out: &mut Vec<u8>) -> io::Result<bool> {
+        let mut control = [0];
+        input.read_exact(&mut control)?;
+        let control = control[0];
+        if control == 0x00 {
+            return Ok(false);
+        }
+        if (0x03..0x80).contains(&control) {
+            return Err(corrupt("Invalid LZMA2 chunk type"));
+        }
+
+        if control == 0x01 || control >= 0xE0 {
+            self.window.reset();
+            self.need_dict_reset = false;
+            self.need_props = true;
+        } else if self.need_dict_reset {
+            return Err(corrupt("First LZMA2 chunk doesn't reset the dictionary"));
+        }
+
+        if control < 0x80 {
+            // An uncompressed chunk
+            let len = usize::from(Self::read_u16(input)?) + 1;
+            let mut data = vec![0; len];
+            input.read_exact(&mut data)?;
+            for byte in data {
+                self.window.put(byte, out);
+            }
+            return Ok(true);
+        }
+
+        let unpacked = (u64::from(control & 0x1F) << 16) + u64::from(Self::read_u16(input)?) + 1;
+        let packed = usize::from(Self::read_u16(input)?) + 1;
+        if control >= 0xC0 {
+            let mut props = [0];
+            input.read_exact(&mut props)?;
+            let props = Properties::from_byte(props[0])?;
+            if props.lc + props.lp > 4 {
+                return Err(corrupt("Invalid LZMA2 properties"));
+            }
+            self.lzma = Some(LzmaDecoder::new(props));
+            self.need_props = false;
+        } else if self.need_props {
+            return Err(corrupt("LZMA2 chunk doesn't set the properties it needs"));
+        } else if control >= 0xA0 {
+            if let Some(lzma) = self.lzma.as_mut() {
+                lzma.reset();
+            }
+        }
+        let lzma = self.lzma.as_mut().ok_or_else(|| corrupt("Missing LZMA2 properties"))?;
+
+        let mut data = vec![0; packed];
+        input.read_exact(&mut data)?;
+        let mismatch = || corrupt("LZMA2 chunk size doesn't match its contents");
+        #[allow(clippy::wildcard_enum_match_arm)]
+        let window = &mut self.window;
+        let stop = RangeDecoder::new(&data[..]).and_then(|mut rc| {
+            let stop = lzma.decode(&mut rc, window, out, unpacked)?;
+            Ok((stop, rc.is_finished_ok() && rc.into_inner().is_empty()))
+        }).map_err(|err| match err.kind() {
+            io::ErrorKind::UnexpectedEof => mismatch(),
+            _ => err,
+        })?;
+        match stop {
+            (Stop::EndMarker, _) => Err(corrupt("Unexpected end marker in LZMA2 chunk")),
+            (Stop::Limit, _) if lzma.has_pending_match() => {
+                Err(corrupt("LZMA match extends past the end of the data"))
+            },
+            (Stop::Limit, false) => Err(mismatch()),
+            (Stop::Limit, true) => Ok(true),
+        }
+    }
+}
+
+/// A reader for a raw LZMA stream of known length, as found in `.7z` files
+pub struct LzmaReader<R: Read> {
+    /// The compressed data
+    rc: RangeDecoder<R>,
+    /// The dictionary
+    window: Window,
+    /// The decoder state
+    lzma: LzmaDecoder,
+    /// How many bytes are still to be decoded
+    remaining: u64,
+    /// Decoded data which hasn't been read yet
+    buffer: Vec<u8>,
+    /// How much of `buffer` has been read
+    pos: usize,
+}
+
+impl<R: Read> LzmaReader<R> {
+    /// Start decoding `unpacked` bytes from `input`, given the five bytes of properties which
+    /// precede the stream in the `.lzma` format
+    pub fn new(input: R, props: &[u8], unpacked: u64) -> io::Result<Self> {
+        if props.len() != 5 {
+            return Err(corrupt("Invalid LZMA properties"));
+        }
+        let dict_size = u32::from_le_bytes([props[1], props[2], props[3], props[4]]);
+        Ok(Self {
+            rc: RangeDecoder::new(input)?,
+            window: Window::new(dict_size),
+            lzma: LzmaDecoder::new(Properties::from_byte(props[0])?),
+            remaining: unpacked,
+            buffer: Vec::new(),
+            pos: 0,
+        })
+    }
+}
+
+impl<R: Read> Read for LzmaReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.buffer.len() {
+            if self.remaining == 0 {
+                return Ok(0);
+            }
+            self.buffer.clear();
+            self.pos = 0;
+            let limit = self.remaining.min(0x10000);
+            let stop = self.lzma.decode(&mut self.rc, &mut self.window, &mut self.buffer, limit)?;
+            self.remaining -= self.buffer.len() as u64;
+            if stop == Stop::EndMarker && self.remaining > 0 {
+                return Err(corrupt("LZMA end marker before the end of the data"));
+            }
+            if self.remaining == 0 && self.lzma.has_pending_match() {
+                return Err(corrupt("LZMA match extends past the end of the data"));
+            }
+        }
+        let len = buf.len().min(self.buffer.len() - self.pos);
+        buf[..len].copy_from_slice(&self.buffer[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+/// A reader for a raw LZMA2 stream, as found in `.7z` files
+pub struct Lzma2Reader<R: Read> {
+    /// The compressed data
+    input: R,
+    /// The decoder state
+    decoder: Lzma2Decoder,
+    /// Decoded data which hasn't been read yet
+    buffer: Vec<u8>,
+    /// How much of `buffer` has been read
+    pos: usize,
+    /// Whether the end-of-data marker has been read
+    finished: bool,
+}
+
+impl<R: Read> Lzma2Reader<R> {
+    /// Prepare to decode `input` with the given dictionary size
+    pub fn new(input: R, dict_size: u32) -> Self {
+        Self { input, decoder: Lzma2Decoder::new(dict_size), buffer: Vec::new(), pos: 0,
+               finished: false }
+    }
+}
+
+impl<R: Read> Read for Lzma2Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.buffer.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            self.buffer.clear();
+            self.pos = 0;
+            self.finished = !self.decoder.decode_chunk(&mut self.input, &mut self.buffer)?;
+        }
+        let len = buf.len().min(self.buffer.len() - self.pos);
+        buf[..len].copy_from_slice(&self.buffer[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The encoding half of `RangeDecoder`, for building test streams one symbol at a time
+    struct RangeEncoder {
+        /// The bottom of the current interval, plus a carry bit
+        low: u64,
+        /// The width of the current interval
+        range: u32,
+        /// The most recent byte shifted out of `low`, held back in case a carry reaches it
+        cache: u8,
+        /// How many bytes (`cache` and any `0xFF`s after it) are held back
+        cache_size: u64,
+        /// The encoded data
+        out: Vec<u8>,
+    }
+
+    impl RangeEncoder {
+        fn new() -> Self {
+            Self { low: 0, range: u32::MAX, cache: 0, cache_size: 1, out: Vec::new() }
+        }
+
+        fn shift_low(&mut self) {
+            if low32(self.low) < 0xFF00_0000 || self.low >> 32 != 0 {
+                let carry = u8::from(self.low >> 32 != 0);
+                let mut byte = self.cache;
+                while self.cache_size > 0 {
+                    self.out.push(byte.wrapping_add(carry));
+                    byte = 0xFF;
+                    self.cache_size -= 1;
+                }
+                self.cache = self.low.to_le_bytes()[3];
+            }
+            self.cache_size += 1;
+            self.low = (self.low & 0x00FF_FFFF) << 8;
+        }
+
+        fn normalize(&mut self) {
+            while self.range < TOP_VALUE {
+                self.range <<= 8;
+                self.shift_low();
+            }
+        }
+
+        fn bit(&mut self, prob: &mut u16, bit: u32) {
+            let bound = (self.range >> PROB_BITS) * u32::from(*prob);
+            if bit == 0 {
+                *prob += ((1 << PROB_BITS) - *prob) >> MOVE_BITS;
+                self.range = bound;
+            } else {
+                *prob -= *prob >> MOVE_BITS;
+                self.low += u64::from(bound);
+                self.range -= bound;
+            }
+            self.normalize();
+        }
+
+        fn direct_bits(&mut self, value: u32, count: u32) {
+            for idx in (0..count).rev() {
+                self.range >>= 1;
+                if (value >> idx) & 1 == 1 {
+                    self.low += u64::from(self.range);
+                }
+                self.normalize();
+            }
+        }
+
+        fn bit_tree(&mut self, probs: &mut [u16], bits: u32, value: u32) {
+            let mut node = 1;
+            for idx in (0..bits).rev() {
+                let bit = (value >> idx) & 1;
+                self.bit(&mut probs[node as usize], bit);
+                node = (node << 1) | bit;
+            }
+        }
+
+        fn bit_tree_reverse(&mut self, probs: &mut [u16], bits: u32, value: u32) {
+            let mut node = 1;
+            for idx in 0..bits {
+                let bit = (value >> idx) & 1;
+                self.bit(&mut probs[node as usize], bit);
+                node = (node << 1) | bit;
+            }
+        }
+
+        fn finish(mut self) -> Vec<u8> {
+            for _ in 0..5 {
+                self.shift_low();
+            }
+            self.out
+        }
+    }
+
+    /// An LZMA encoder which is told exactly which symbols to emit
+    ///
+    /// (It borrows `LzmaDecoder`'s probabilities and state so that both sides stay in step.)
+    struct Encoder {
+        /// The range coder for the current stream or chunk
+        rc: RangeEncoder,
+        /// The probabilities, state, and recent distances
+        model: LzmaDecoder,
+        /// Everything output since the last dictionary reset
+        history: Vec<u8>,
+    }
+
+    impl Encoder {
+        fn new(props: u8) -> Self {
+            let props = Properties::from_byte(props).expect("valid properties");
+            Self { rc: RangeEncoder::new(), model: LzmaDecoder::new(props), history: Vec::new() }
+        }
+
+        /// Finish the current range coder, keeping the model and history for the next chunk
+        fn chunk(&mut self) -> Vec<u8> {
+            std::mem::replace(&mut self.rc, RangeEncoder::new()).finish()
+        }
+
+        fn pos_state(&self) -> usize {
+            self.history.len() & ((1 << self.model.props.pb) - 1)
+        }
+
+        /// Start a match or literal, returning the position state
+        fn is_match(&mut self, bit: u32) -> usize {
+            let pos_state = self.pos_state();
+            self.rc.bit(&mut self.model.is_match[(self.model.state << 4) + pos_state], bit);
+            pos_state
+        }
+
+        fn length(rc: &mut RangeEncoder, probs: &mut LenDecoder, pos_state: usize, len: usize) {
+            let len = u32::try_from(len - MATCH_MIN_LEN).expect("valid length");
+            if len < 8 {
+                rc.bit(&mut probs.choice, 0);
+                rc.bit_tree(&mut probs.low[pos_state], 3, len);
+            } else if len < 16 {
+                rc.bit(&mut probs.choice, 1);
+                rc.bit(&mut probs.choice2, 0);
+                rc.bit_tree(&mut probs.mid[pos_state], 3, len - 8);
+            } else {
+                rc.bit(&mut probs.choice, 1);
+                rc.bit(&mut probs.choice2, 1);
+                rc.bit_tree(&mut probs.high, 8, len - 16);
+            }
+        }
+
+        /// Copy `len` bytes from the most recent distance, if it's within the history
+        fn copy(&mut self, len: usize) {
+            let dist = self.model.reps[0] as usize + 1;
+            if dist <= self.history.len() {
+                for _ in 0..len {
+                    self.history.push(self.history[self.history.len() - dist]);
+                }
+            }
+        }
+
+        fn literal(&mut self, byte: u8) {
+            let state = self.model.state;
+            self.is_match(0);
+
+            let Properties { lc, lp, .. } = self.model.props;
+            let prev_byte = u32::from(self.history.last().copied().unwrap_or(0));
+            let pos_bits = low32(self.history.len() as u64) & ((1 << lp) - 1);
+            let context = ((pos_bits << lc) + (prev_byte >> (8 - lc))) as usize;
+            let probs = &mut self.model.literal[0x300 * context..0x300 * (context + 1)];
+
+            let (value, mut symbol, mut idx) = (u32::from(byte), 1, 8);
+            if state >= 7 {
+                let dist = self.model.reps[0] as usize + 1;
+                let match_byte = u32::from(self.history[self.history.len() - dist]);
+                while idx > 0 {
+                    idx -= 1;
+                    let (match_bit, bit) = ((match_byte >> idx) & 1, (value >> idx) & 1);
+                    self.rc.bit(&mut probs[(((1 + match_bit) << 8) + symbol) as usize], bit);
+                    symbol = (symbol << 1) | bit;
+                    if match_bit != bit {
+                        break;
+                    }
+                }
+            }
+            while idx > 0 {
+                idx -= 1;
+                let bit = (value >> idx) & 1;
+                self.rc.bit(&mut probs[symbol as usize], bit);
+                symbol = (symbol << 1) | bit;
+            }
+            self.model.state = match state {
+                0..=3 => 0,
+                4..=9 => state - 3,
+                _ => state - 6,
+            };
+            self.history.push(byte);
+        }
+
+        fn literals(&mut self, bytes: &[u8]) {
+            for &byte in bytes {
+                self.literal(byte);
+            }
+        }
+
+        /// Encode a new match, where a `dist` of 1 is the most recent byte (and 0 is the end
+        /// marker)
+        fn new_match(&mut self, dist: u32, len: usize) {
+            let state = self.model.state;
+            let pos_state = self.is_match(1);
+            self.rc.bit(&mut self.model.is_rep[state], 0);
+            Self::length(&mut self.rc, &mut self.model.len, pos_state, len);
+            self.model.state = if state < 7 { 7 } else { 10 };
+
+            let dist = dist.wrapping_sub(1);
+            let pos_slot = if dist < 4 {
+                dist
+            } else {
+                let bits = 32 - dist.leading_zeros();
+                ((bits - 1) << 1) | ((dist >> (bits - 2)) & 1)
+            };
+            let len_state = (len - MATCH_MIN_LEN).min(3);
+            self.rc.bit_tree(&mut self.model.pos_slot[len_state], 6, pos_slot);
+            if pos_slot >= 4 {
+                let direct_bits = (pos_slot >> 1) - 1;
+                let base = (2 | (pos_slot & 1)) << direct_bits;
+                if pos_slot < END_POS_MODEL_INDEX {
+                    let probs = &mut self.model.pos_special[(base - pos_slot) as usize..];
+                    self.rc.bit_tree_reverse(probs, direct_bits, dist - base);
+                } else {
+                    self.rc.direct_bits((dist - base) >> 4, direct_bits - 4);
+                    self.rc.bit_tree_reverse(&mut self.model.align, 4, (dist - base) & 0xF);
+                }
+            }
+            let reps = self.model.reps;
+            self.model.reps = [dist, reps[0], reps[1], reps[2]];
+            self.copy(len);
+        }
+
+        fn end_marker(&mut self) {
+            self.new_match(0, MATCH_MIN_LEN);
+        }
+
+        /// Encode a match which repeats the `idx`th most recent distance
+        fn rep(&mut self, idx: usize, len: usize) {
+            let state = self.model.state;
+            let pos_state = self.is_match(1);
+            let model = &mut self.model;
+            self.rc.bit(&mut model.is_rep[state], 1);
+            if idx == 0 {
+                self.rc.bit(&mut model.is_rep_g0[state], 0);
+                self.rc.bit(&mut model.is_rep0_long[(state << 4) + pos_state], 1);
+            } else {
+                self.rc.bit(&mut model.is_rep_g0[state], 1);
+                if idx == 1 {
+                    self.rc.bit(&mut model.is_rep_g1[state], 0);
+                } else {
+                    self.rc.bit(&mut model.is_rep_g1[state], 1);
+                    self.rc.bit(&mut model.is_rep_g2[state], u32::from(idx == 3));
+                }
+                model.reps[..=idx].rotate_right(1);
+            }
+            model.state = if state < 7 { 8 } else { 11 };
+            Self::length(&mut self.rc, &mut model.rep_len, pos_state, len);
+            self.copy(len);
+        }
+
+        /// Encode a single-byte repeat of the most recent distance
+        fn short_rep(&mut self) {
+            let state = self.model.state;
+            let pos_state = self.is_match(1);
+            self.rc.bit(&mut self.model.is_rep[state], 1);
+            self.rc.bit(&mut self.model.is_rep_g0[state], 0);
+            self.rc.bit(&mut self.model.is_rep0_long[(state << 4) + pos_state], 0);
+            self.model.state = if state < 7 { 9 } else { 11 };
+            self.copy(1);
+        }
+    }
+
+    /// The `lc=3, lp=0, pb=2` properties which `xz` and 7-Zip default to
+    const DEFAULT_PROPS: u8 = 0x5D;
+
+    /// Set up an `LzmaReader` for `unpacked` bytes with a 4KiB dictionary
+    fn lzma_reader(props: u8, data: &[u8], unpacked: u64) -> io::Result<LzmaReader<&[u8]>> {
+        let mut header = vec![props];
+        header.extend(&4096_u32.to_le_bytes());
+        LzmaReader::new(data, &header, unpacked)
+    }
+
+    /// Decode a raw LZMA stream of `unpacked` bytes with a 4KiB dictionary
+    fn decode_lzma(props: u8, data: &[u8], unpacked: u64) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        lzma_reader(props, data, unpacked)?.read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    /// Decode a raw LZMA2 stream with a 4KiB dictionary
+    fn decode_lzma2(data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        Lzma2Reader::new(data, 4096).read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    /// Wrap `packed` in the header for an LZMA2 chunk of the given type
+    fn lzma_chunk(control: u8, unpacked: usize, packed: &[u8], props: Option<u8>) -> Vec<u8> {
+        let unpacked = unpacked - 1;
+        let mut out = vec![control | u8::try_from(unpacked >> 16).expect("valid chunk size")];
+        out.extend(&u16::try_from(unpacked & 0xFFFF).expect("masked").to_be_bytes());
+        out.extend(&u16::try_from(packed.len() - 1).expect("valid chunk size").to_be_bytes());
+        out.extend(props);
+        out.extend(packed);
+        out
+    }
+
+    /// Build an uncompressed LZMA2 chunk, with (`0x01`) or without (`0x02`) a dictionary reset
+    fn uncompressed_chunk(control: u8, data: &[u8]) -> Vec<u8> {
+        let mut out = vec![control];
+        out.extend(&u16::try_from(data.len() - 1).expect("valid chunk size").to_be_bytes());
+        out.extend(data);
+        out
+    }
+
+    /// Assert that `result` failed with an error of the given kind
+    fn assert_kind<T: std::fmt::Debug>(result: io::Result<T>, kind: io::ErrorKind) {
+        match result {
+            Err(err) => assert_eq!(err.kind(), kind, "{err}"),
+            Ok(value) => panic!("Expected {:?} but got Ok({:?})", kind, value),
+        }
+    }
+
+    #[test]
+    fn test_range_decoder_init() {
+        assert!(RangeDecoder::new(&[0, 0x12, 0x34, 0x56, 0x78][..]).is_ok());
+        assert_kind(RangeDecoder::new(&[1, 0, 0, 0, 0][..]).map(|_| ()),
+                    io::ErrorKind::InvalidData);
+        assert_kind(RangeDecoder::new(&[0, 0xFF, 0xFF, 0xFF, 0xFF][..]).map(|_| ()),
+                    io::ErrorKind::InvalidData);
+        assert_kind(RangeDecoder::new(&[0, 0, 0][..]).map(|_| ()), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_range_decoder_normalize() {
+        // Nothing more is read until the range drops below 2^24...
+        let mut rc = RangeDecoder::new(&[0, 0, 0, 0, 0, 0xAB][..]).expect("valid init");
+        assert_eq!(rc.direct_bits(7).expect("enough input"), 0);
+        assert_eq!((rc.range, rc.code, rc.input.len()), (0x01FF_FFFF, 0, 1));
+
+        // ...at which point one byte is shifted into the bottom of the code
+        assert_eq!(rc.direct_bits(1).expect("enough input"), 0);
+        assert_eq!((rc.range, rc.code), (0xFFFF_FF00, 0xAB));
+        assert!(rc.into_inner().is_empty());
+
+        // ...and running out of input to shift in is an error
+        let mut rc = RangeDecoder::new(&[0, 0, 0, 0, 0][..]).expect("valid init");
+        assert_kind(rc.direct_bits(8), io::ErrorKind::UnexpectedEof);
+
+        // An adaptive bit narrows the range in proportion to its probability
+        let mut rc = RangeDecoder::new(&[0, 0, 0, 0, 0, 0xCD][..]).expect("valid init");
+        let mut prob = PROB_INIT;
+        assert_eq!(rc.bit(&mut prob).expect("enough input"), 0);
+        assert_eq!((rc.range, rc.code, rc.input.len()), (0x7FFF_FC00, 0, 1));
+        assert_eq!(prob, PROB_INIT + (PROB_INIT >> MOVE_BITS));
+    }
+
+    #[test]
+    fn test_range_coder_round_trip() {
+        // A skewed pseudo-random sequence, so the range shrinks at varying rates and carries
+        // propagate through the encoder's cached bytes
+        let mut seed = 1_u32;
+        let bits: Vec<u32> = (0..4000).map(|_| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            u32::from((seed >> 16).trailing_zeros() >= 3)
+        }).collect();
+
+        let mut rc = RangeEncoder::new();
+        let mut probs = [PROB_INIT; 2];
+        for (idx, &bit) in bits.iter().enumerate() {
+            rc.bit(&mut probs[idx % 2], bit);
+            if idx % 100 == 0 {
+                rc.direct_bits(u32::try_from(idx).expect("small index"), 12);
+            }
+        }
+        let data = rc.finish();
+
+        let mut rc = RangeDecoder::new(&data[..]).expect("valid init");
+        let mut probs = [PROB_INIT; 2];
+        for (idx, &bit) in bits.iter().enumerate() {
+            assert_eq!(rc.bit(&mut probs[idx % 2]).expect("enough input"), bit);
+            if idx % 100 == 0 {
+                assert_eq!(rc.direct_bits(12).expect("enough input"),
+                           u32::try_from(idx).expect("small index"));
+            }
+        }
+        assert!(rc.is_finished_ok());
+        assert!(rc.into_inner().is_empty());
+    }
+
+    #[test]
+    fn test_properties() {
+        let props = Properties::from_byte(DEFAULT_PROPS).expect("valid properties");
+        assert_eq!((props.lc, props.lp, props.pb), (3, 0, 2));
+        let props = Properties::from_byte(224).expect("valid properties");
+        assert_eq!((props.lc, props.lp, props.pb), (8, 4, 4));
+        assert_kind(Properties::from_byte(225).map(|_| ()), io::ErrorKind::InvalidData);
+
+        assert_eq!(lzma2_dict_size(0).expect("valid size"), 4096);
+        assert_eq!(lzma2_dict_size(1).expect("valid size"), 6144);
+        assert_eq!(lzma2_dict_size(39).expect("valid size"), 0xC000_0000);
+        assert_eq!(lzma2_dict_size(40).expect("valid size"), u32::MAX);
+        assert_kind(lzma2_dict_size(41), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_literals() {
+        // Including the extremes of each property, since they change the literal contexts
+        for &props in &[DEFAULT_PROPS, 0, 8, 36, 180] {
+            let mut enc = Encoder::new(props);
+            enc.literals(b"Hello, World!");
+            let data = enc.rc.finish();
+            assert_eq!(decode_lzma(props, &data, 13).expect("valid stream"), b"Hello, World!");
+        }
+    }
+
+    #[test]
+    fn test_matches() {
+        let mut enc = Encoder::new(DEFAULT_PROPS);
+        enc.literals(b"ab");
+        enc.new_match(2, 5);  // Overlapping its own output
+        enc.new_match(1, 3);
+        enc.literal(b'x');  // Coded relative to the byte at the last match distance
+        let data = enc.rc.finish();
+        assert_eq!(decode_lzma(DEFAULT_PROPS, &data, 11).expect("valid stream"), b"abababaaaax");
+
+        // Every way of coding lengths and distances
+        let mut enc = Encoder::new(DEFAULT_PROPS);
+        let mut seed = 7_u32;
+        for _ in 0..3500 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            enc.literal((seed >> 16).to_le_bytes()[0]);
+        }
+        for &(dist, len) in &[(1, 2), (4, 9), (10, 20), (150, 273), (300, 17), (3000, 4)] {
+            enc.new_match(dist, len);
+            enc.literal(b'!');
+        }
+        let expected = enc.history.clone();
+        let data = enc.rc.finish();
+        let decoded = decode_lzma(DEFAULT_PROPS, &data, expected.len() as u64);
+        assert_eq!(decoded.expect("valid stream"), expected);
+    }
+
+    #[test]
+    fn test_reps() {
+        let mut enc = Encoder::new(DEFAULT_PROPS);
+        enc.literals(b"0123456789");
+        for &dist in &[10, 4, 7, 11] {
+            enc.new_match(dist, 2);
+        }
+        assert_eq!(enc.model.reps, [10, 6, 3, 9]);
+        enc.rep(3, 2);
+        enc.rep(2, 2);
+        enc.rep(1, 2);
+        enc.rep(0, 3);
+        enc.short_rep();
+        let data = enc.rc.finish();
+
+        let expected = b"0123456789018978568997897856";
+        let mut reader = lzma_reader(DEFAULT_PROPS, &data, expected.len() as u64)
+            .expect("valid header");
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).expect("valid stream");
+        assert_eq!(out, &expected[..]);
+        assert_eq!(reader.lzma.reps, [9, 6, 10, 3]);
+        assert_eq!(reader.lzma.state, 11);
+    }
+
+    #[test]
+    fn test_bad_distances() {
+        // Further back than the start of the data
+        let mut enc = Encoder::new(DEFAULT_PROPS);
+        enc.literals(b"ab");
+        enc.new_match(3, 2);
+        assert_kind(decode_lzma(DEFAULT_PROPS, &enc.rc.finish(), 4), io::ErrorKind::InvalidData);
+
+        // Further back than the dictionary, even though the data is long enough
+        let mut enc = Encoder::new(DEFAULT_PROPS);
+        enc.literals(&[b'a'; 5000]);
+        enc.new_match(4097, 2);
+        assert_kind(decode_lzma(DEFAULT_PROPS, &enc.rc.finish(), 5002),
+                    io::ErrorKind::InvalidData);
+
+        // Repeating a distance before there's been any output
+        for idx in 0..4 {
+            let mut enc = Encoder::new(DEFAULT_PROPS);
+            enc.rep(idx, 2);
+            assert_kind(decode_lzma(DEFAULT_PROPS, &enc.rc.finish(), 2),
+                        io::ErrorKind::InvalidData);
+        }
+        let mut enc = Encoder::new(DEFAULT_PROPS);
+        enc.short_rep();
+        assert_kind(decode_lzma(DEFAULT_PROPS, &enc.rc.finish(), 1), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_end_markers() {
+        let mut enc = Encoder::new(DEFAULT_PROPS);
+        enc.literals(b"abc");
+        enc.end_marker();
+        let data = enc.rc.finish();
+
+        // Fine if it's where the data was expected to end anyway...
+        assert_eq!(decode_lzma(DEFAULT_PROPS, &data, 3).expect("valid stream"), b"abc");
+        // ...but not if it cuts the data short
+        assert_kind(decode_lzma(DEFAULT_PROPS, &data, 5), io::ErrorKind::InvalidData);
+
+        // A distance one short of the marker's isn't a marker, and is far out of range
+        let mut enc = Encoder::new(DEFAULT_PROPS);
+        enc.literals(b"abc");
+        enc.new_match(u32::MAX, 2);
+        assert_kind(decode_lzma(DEFAULT_PROPS, &enc.rc.finish(), 5), io::ErrorKind::InvalidData);
+
+        // LZMA2 chunks never contain end markers
+        let mut enc = Encoder::new(DEFAULT_PROPS);
+        enc.literals(b"abc");
+        enc.end_marker();
+        let packed = enc.chunk();
+        for &unpacked in &[3, 5] {
+            let mut stream = lzma_chunk(0xE0, unpacked, &packed, Some(DEFAULT_PROPS));
+            stream.push(0x00);
+            assert_kind(decode_lzma2(&stream), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn test_truncated_lzma() {
+        let mut enc = Encoder::new(DEFAULT_PROPS);
+        enc.literals(&[b'z'; 100]);
+        let data = enc.rc.finish();
+        let truncated = &data[..data.len() / 2];
+        assert_kind(decode_lzma(DEFAULT_PROPS, truncated, 100), io::ErrorKind::UnexpectedEof);
+
+        // The expected length ends partway through a match
+        let mut enc = Encoder::new(DEFAULT_PROPS);
+        enc.literals(b"ab");
+        enc.new_match(2, 10);
+        assert_kind(decode_lzma(DEFAULT_PROPS, &enc.rc.finish(), 6), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_lzma2_chunks() {
+        let mut stream = uncompressed_chunk(0x01, b"hello ");
+        let mut enc = Encoder::new(DEFAULT_PROPS);
+        enc.history.extend(b"hello ");
+
+        // Setting the properties resets the state, but not the dictionary
+        enc.new_match(6, 5);
+        stream.extend(lzma_chunk(0xC0, 5, &enc.chunk(), Some(DEFAULT_PROPS)));
+
+        // With no reset, the state (including the recent distances) carries over
+        enc.literal(b'!');
+        enc.rep(0, 5);
+        stream.extend(lzma_chunk(0x80, 6, &enc.chunk(), None));
+
+        // A state reset forgets the recent distances, but keeps the dictionary
+        enc.model.reset();
+        enc.new_match(12, 4);
+        stream.extend(lzma_chunk(0xA0, 4, &enc.chunk(), None));
+
+        // An uncompressed chunk without a dictionary reset appends to the dictionary
+        stream.extend(uncompressed_chunk(0x02, b"p!"));
+        enc.history.extend(b"p!");
+        enc.literal(b'?');
+        enc.rep(0, 2);
+        stream.extend(lzma_chunk(0x80, 3, &enc.chunk(), None));
+
+        // A dictionary reset starts again from scratch
+        enc.model.reset();
+        enc.history.clear();
+        enc.literal(b'x');
+        enc.new_match(1, 3);
+        stream.extend(lzma_chunk(0xE0, 4, &enc.chunk(), Some(DEFAULT_PROPS)));
+        stream.push(0x00);
+
+        let mut input = &stream[..];
+        let mut decoder = Lzma2Decoder::new(4096);
+        let mut out = Vec::new();
+        let mut next_chunk = |expected: &[u8], reps: [u32; 4], total: u64| {
+            out.clear();
+            assert!(decoder.decode_chunk(&mut input, &mut out).expect("valid chunk"));
+            assert_eq!(out, expected);
+            assert_eq!(decoder.window.total, total);
+            assert_eq!(decoder.lzma.as_ref().map_or([0; 4], |lzma| lzma.reps), reps);
+        };
+        next_chunk(b"hello ", [0; 4], 6);
+        next_chunk(b"hello", [5, 0, 0, 0], 11);
+        next_chunk(b"!hello", [5, 0, 0, 0], 17);
+        next_chunk(b" hel", [11, 0, 0, 0], 21);
+        next_chunk(b"p!", [11, 0, 0, 0], 23);
+        next_chunk(b"?he", [11, 0, 0, 0], 26);
+        next_chunk(b"xxxx", [0; 4], 4);
+        assert!(!decoder.decode_chunk(&mut input, &mut out).expect("valid end marker"));
+        assert!(input.is_empty());
+
+        assert_eq!(decode_lzma2(&stream).expect("valid stream"),
+                   &b"hello hello!hello help!?hexxxx"[..]);
+    }
+
+    #[test]
+    fn test_lzma2_bad_control() {
+        assert_kind(decode_lzma2(&[0x03]), io::ErrorKind::InvalidData);
+        assert_kind(decode_lzma2(&[0x7F]), io::ErrorKind::InvalidData);
+
+        // The first chunk must reset the dictionary
+        let mut stream = uncompressed_chunk(0x02, b"abc");
+        stream.push(0x00);
+        assert_kind(decode_lzma2(&stream), io::ErrorKind::InvalidData);
+
+        let mut enc = Encoder::new(DEFAULT_PROPS);
+        enc.literals(b"abc");
+        let packed = enc.chunk();
+        for &control in &[0x80, 0xA0, 0xC0] {
+            let mut stream = lzma_chunk(control, 3, &packed, Some(DEFAULT_PROPS));
+            stream.push(0x00);
+            assert_kind(decode_lzma2(&stream), io::ErrorKind::InvalidData);
+        }
+
+        // LZMA chunks which don't set properties need an earlier chunk to have set them
+        for &control in &[0x80, 0xA0] {
+            let mut stream = uncompressed_chunk(0x01, b"abc");
+            stream.extend(lzma_chunk(control, 3, &packed, None));
+            stream.push(0x00);
+            assert_kind(decode_lzma2(&stream), io::ErrorKind::InvalidData);
+        }
+
+        // LZMA2 limits lc + lp to 4
+        let mut stream = lzma_chunk(0xE0, 3, &packed, Some(13));
+        stream.push(0x00);
+        assert_kind(decode_lzma2(&stream), io::ErrorKind::InvalidData);
+
+        // A dictionary reset makes earlier data unavailable to matches
+        let mut enc = Encoder::new(DEFAULT_PROPS);
+        enc.new_match(1, 2);
+        let mut stream = uncompressed_chunk(0x01, b"abc");
+        stream.extend(lzma_chunk(0xE0, 2, &enc.chunk(), Some(DEFAULT_PROPS)));
+        stream.push(0x00);
+        assert_kind(decode_lzma2(&stream), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_lzma2_truncated() {
+        let mut enc = Encoder::new(DEFAULT_PROPS);
+        enc.literals(b"abcdefgh");
+        let packed = enc.chunk();
+        let mut good = lzma_chunk(0xE0, 8, &packed, Some(DEFAULT_PROPS));
+        good.push(0x00);
+        assert_eq!(decode_lzma2(&good).expect("valid stream"), b"abcdefgh");
+
+        // Cut off within the chunk headers and data, or before the end-of-data marker
+        for len in &[1, 3, 5, 6, good.len() - 3, good.len() - 1] {
+            assert_kind(decode_lzma2(&good[..*len]), io::ErrorKind::UnexpectedEof);
+        }
+        assert_kind(decode_lzma2(&[0x01, 0x00, 0x05, b'a', b'b']),
+                    io::ErrorKind::UnexpectedEof);
+
+        // The packed size is too small or too large for the data
+        for packed in &[&packed[..packed.len() - 2], &[&packed[..], &[0, 0]].concat()[..]] {
+            let mut stream = lzma_chunk(0xE0, 8, packed, Some(DEFAULT_PROPS));
+            stream.push(0x00);
+            assert_kind(decode_lzma2(&stream), io::ErrorKind::InvalidData);
+        }
+
+        // The unpacked size is too large or too small for the data
+        for &unpacked in &[7, 9] {
+            let mut stream = lzma_chunk(0xE0, unpacked, &packed, Some(DEFAULT_PROPS));
+            stream.push(0x00);
+            assert_kind(decode_lzma2(&stream), io::ErrorKind::InvalidData);
+        }
+
+        // The chunk ends partway through a match
+        let mut enc = Encoder::new(DEFAULT_PROPS);
+        enc.literal(b'a');
+        enc.new_match(1, 10);
+        let mut stream = lzma_chunk(0xE0, 5, &enc.chunk(), Some(DEFAULT_PROPS));
+        stream.push(0x00);
+        assert_kind(decode_lzma2(&stream), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,294 @@
+//! A walker for MPEG-1/2/2.5 audio streams (`.mp3`, `.mp2`, etc.) which checks frame structure
+//!
+//! (MPEG audio has no checksum covering the whole file, so all this can do is check that skipping
+//! from frame to frame by their declared lengths always lands on another valid frame header.)
+
+// Standard library imports
+use std::io::{self, Read};
+
+// Local Imports
+use super::{corrupt, unsupported};
+
+/// How many bytes of junk (or of a truncated final frame) to tolerate at the end of the file
+///
+/// (Small enough that losing most of a frame still counts as truncation.)
+const TRAILING_JUNK_LIMIT: usize = 128;
+
+/// The size of an ID3v1 tag
+const ID3V1_SIZE: usize = 128;
+
+/// The size of an ID3v2 tag's header (and of its optional footer)
+const ID3V2_HEADER_SIZE: usize = 10;
+
+/// The size of an APEv2 tag's header or footer
+const APE_HEADER_SIZE: usize = 32;
+
+/// Bitrates in kbit/s, indexed by `[MPEG-1?][layer - 1][bitrate index]`
+const BITRATES: [[[u32; 15]; 3]; 2] = [
+    [
+        [0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256],
+        [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+        [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+    ],
+    [
+        [0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448],
+        [0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384],
+        [0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320],
+    ],
+];
+
+/// Sample rates in Hz for MPEG-1, indexed by the sample rate index
+///
+/// (MPEG-2 halves these and MPEG-2.5 quarters them.)
+const SAMPLE_RATES: [u32; 3] = [44100, 48000, 32000];
+
+/// The fields of a frame header which must stay the same throughout the stream
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct StreamKind {
+    /// The raw version bits (0 for MPEG-2.5, 2 for MPEG-2, 3 for MPEG-1)
+    version: u8,
+    /// The layer (1, 2, or 3)
+    layer: u8,
+}
+
+/// Parse a frame header, returning the stream's kind and the frame's length
+///
+/// Returns `Ok(None)` if `bytes` doesn't start with a valid header.
+fn frame_header(bytes: &[u8]) -> io::Result<Option<(StreamKind, usize)>> {
+    let header = match bytes {
+        [a, b, c, d, ..] => u32::from_be_bytes([*a, *b, *c, *d]),
+        _ => return Ok(None),
+    };
+    let version = ((header >> 19) & 3) as u8;
+    let layer = 4 - ((header >> 17) & 3) as u8;
+    let bitrate_idx = ((header >> 12) & 0xF) as usize;
+    let sample_rate_idx = ((header >> 10) & 3) as usize;
+    if header >> 21 != 0x7FF || version == 1 || layer == 4 || bitrate_idx == 15
+            || sample_rate_idx == 3 || header & 3 == 2 {
+        return Ok(None);
+    }
+    if bitrate_idx == 0 {
+        return Err(unsupported("Free-format MPEG audio bitrates aren't supported"));
+    }
+
+    let mpeg1 = version == 3;
+    let bitrate = BITRATES[usize::from(mpeg1)][usize::from(layer - 1)][bitrate_idx] * 1000;
+    let sample_rate = SAMPLE_RATES[sample_rate_idx] >> (3 - version.max(1));
+    let padding = (header >> 9) & 1;
+    let len = match layer {
+        1 => (12 * bitrate / sample_rate + padding) * 4,
+        3 if !mpeg1 => 72 * bitrate / sample_rate + padding,
+        _ => 144 * bitrate / sample_rate + padding,
+    };
+    Ok(Some((StreamKind { version, layer }, len as usize)))
+}
+
+/// Whether `tail`, which runs to the end of the file, is acceptable there
+pub fn acceptable_tail(tail: &[u8]) -> bool {
+    let id3v1_start = tail.len().saturating_sub(ID3V1_SIZE);
+    let junk = if tail[id3v1_start..].starts_with(b"TAG") { &tail[..id3v1_start] } else { tail };
+    junk.len() <= TRAILING_JUNK_LIMIT
+}
+
+/// A reader which allows looking ahead by an arbitrary number of bytes
+pub struct Lookahead<R> {
+    /// The data
+    input: R,
+    /// Bytes which have been looked at but not consumed yet
+    buffer: Vec<u8>,
+    /// How many bytes have been consumed so far
+    pos: u64,
+}
+
+impl<R: Read> Lookahead<R> {
+    /// Wrap `input`
+    pub fn new(input: R) -> Self {
+        Self { input, buffer: Vec::new(), pos: 0 }
+    }
+
+    /// How many bytes have been consumed so far
+    pub fn pos(&self) -> u64 {
+        self.pos
+    }
+
+    /// Look at the next `len` bytes (or fewer, if the input ends first) without consuming them
+    pub fn peek(&mut self, len: usize) -> io::Result<&[u8]> {
+        if self.buffer.len() < len {
+            let wanted = (len - self.buffer.len()) as u64;
+            (&mut self.input).take(wanted).read_to_end(&mut self.buffer)?;
+        }
+        Ok(&self.buffer[..len.min(self.buffer.len())])
+    }
+
+    /// Skip `len` bytes, returning `false` if the input ended first
+    pub fn skip(&mut self, len: u64) -> io::Result<bool> {
+        let buffered = len.min(self.buffer.len() as u64);
+        self.buffer.drain(..buffered as usize);
+        let skipped = io::copy(&mut (&mut self.input).take(len - buffered), &mut io::sink())?;
+        self.pos += buffered + skipped;
+        Ok(buffered + skipped == len)
+    }
+}
+
+/// Skip any ID3v2 tags at the current position of `input`, using their declared sizes
+pub fn skip_id3v2<R: Read>(input: &mut Lookahead<R>) -> io::Result<()> {
+    while let [b'I', b'D', b'3', _, _, flags, size @ ..] = input.peek(ID3V2_HEADER_SIZE)? {
+        if size.iter().any(|byte| byte & 0x80 != 0) {
+            return Err(corrupt("Invalid ID3v2 tag size"));
+        }
+        let size = size.iter().fold(0, |acc, &byte| (acc << 7) | u64::from(byte));
+        let footer = if flags & 0x10 == 0 { 0 } else { ID3V2_HEADER_SIZE as u64 };
+        if !input.skip(ID3V2_HEADER_SIZE as u64 + size + footer)? {
+            return Err(corrupt("ID3v2 tag is truncated"));
+        }
+    }
+    Ok(())
+}
+
+/// Skip an APEv2 tag with a header at the current position of `input`, returning whether there
+/// was one
+pub fn skip_ape<R: Read>(input: &mut Lookahead<R>) -> io::Result<bool> {
+    if let [b'A', b'P', b'E', b'T', b'A', b'G', b'E', b'X', _, _, _, _,
+            a, b, c, d, ..] = input.peek(APE_HEADER_SIZE)? {
+        let size = u64::from(u32::from_le_bytes([*a, *b, *c, *d]));
+        if !input.skip(APE_HEADER_SIZE as u64 + size)? {
+            return Err(corrupt("APEv2 tag is truncated"));
+        }
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+/// Whether what's left of `input` after the last frame is [acceptable there](acceptable_tail)
+pub fn at_acceptable_end<R: Read>(input: &mut Lookahead<R>) -> io::Result<bool> {
+    let tail = input.peek(ID3V1_SIZE + TRAILING_JUNK_LIMIT + 1)?;
+    Ok(tail.len() <= ID3V1_SIZE + TRAILING_JUNK_LIMIT && acceptable_tail(tail))
+}
+
+/// Check the structure of the MPEG audio stream read from `input`
+///
+/// ID3v2 tags at the start (and APEv2 tags with headers anywhere) are skipped using their declared
+/// sizes, and an ID3v1 tag plus up to [`TRAILING_JUNK_LIMIT`] bytes of junk are tolerated at the
+/// end.
+pub fn verify(input: impl Read) -> io::Result<()> {
+    let mut input = Lookahead::new(input);
+    skip_id3v2(&mut input)?;
+
+    let (mut kind, mut frames) = (None, 0_u64);
+    loop {
+        let pos = input.pos;
+        if input.peek(1)?.is_empty() && frames > 0 {
+            return Ok(());
+        }
+        if skip_ape(&mut input)? {
+            continue;
+        }
+
+        let (frame_kind, len) = match frame_header(input.peek(4)?)? {
+            Some(frame) => frame,
+            None => {
+                if frames > 0 && at_acceptable_end(&mut input)? {
+                    return Ok(());
+                }
+                return Err(corrupt(&if frames == 0 {
+                    "No MPEG audio frame at the start of the stream".to_owned()
+                } else {
+                    format!("Lost MPEG audio frame sync at byte {}", pos)
+                }));
+            },
+        };
+        if kind.map_or(false, |kind| kind != frame_kind) {
+            return Err(corrupt(&format!(
+                "MPEG audio version or layer changes at byte {}", pos)));
+        }
+        kind = Some(frame_kind);
+
+        let frame = input.peek(len)?;
+        if frame.len() < len {
+            if frames > 0 && acceptable_tail(frame) {
+                return Ok(());
+            }
+            return Err(corrupt("Last MPEG audio frame is truncated"));
+        }
+        input.skip(len as u64)?;
+        frames += 1;
+    }
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The header of a 417-byte MPEG-1 Layer III frame (128 kbit/s at 44.1 kHz, no padding)
+    const MPEG1_L3: [u8; 4] = [0xFF, 0xFB, 0x90, 0x00];
+
+    /// Build a stream of `count` frames with the given header
+    fn frames(header: [u8; 4], count: usize) -> Vec<u8> {
+        let len = frame_header(&header).unwrap().unwrap().1;
+        let mut frame = header.to_vec();
+        frame.resize(len, 0x55);
+        frame.repeat(count)
+    }
+
+    /// Verify `input`, returning the error kind on failure
+    fn verify_bytes(input: &[u8]) -> Result<(), io::ErrorKind> {
+        verify(input).map_err(|err| err.kind())
+    }
+
+    #[test]
+    fn test_frame_lengths() {
+        let len = |header: [u8; 4]| frame_header(&header).unwrap().map(|(_, len)| len);
+        assert_eq!(len(MPEG1_L3), Some(417));
+        assert_eq!(len([0xFF, 0xFB, 0x92, 0x00]), Some(418));
+        assert_eq!(len([0xFF, 0xF3, 0x90, 0x00]), Some(261));
+        assert_eq!(len([0xFF, 0xE3, 0x18, 0x00]), Some(72));
+        assert_eq!(len([0xFF, 0xFD, 0x90, 0x00]), Some(522));
+        assert_eq!(len([0xFF, 0xFF, 0x90, 0x00]), Some(312));
+        assert_eq!(len([0xFF, 0xFB, 0xF0, 0x00]), None);
+        assert_eq!(len([0xFF, 0xFB, 0x9C, 0x00]), None);
+        assert_eq!(len([0xFF, 0xEB, 0x90, 0x00]), None);
+        assert_eq!(len([0xFF, 0xF9, 0x90, 0x00]), None);
+        assert_eq!(len([0xFF, 0xFB, 0x90, 0x02]), None);
+        assert_eq!(len([0x7F, 0xFB, 0x90, 0x00]), None);
+        assert_eq!(frame_header(&[0xFF, 0xFB, 0x00, 0x00]).unwrap_err().kind(),
+                   io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn test_stream_structure() {
+        let good = frames(MPEG1_L3, 4);
+        assert_eq!(verify_bytes(&good), Ok(()));
+
+        let mut tagged = vec![b'I', b'D', b'3', 4, 0, 0, 0, 0, 1, 0];
+        tagged.resize(138, 0);
+        tagged.extend(&good);
+        tagged.extend(b"TAG");
+        tagged.resize(tagged.len() + 125, b' ');
+        assert_eq!(verify_bytes(&tagged), Ok(()));
+
+        let mut junk = good.clone();
+        junk.extend(&[0; TRAILING_JUNK_LIMIT]);
+        assert_eq!(verify_bytes(&junk), Ok(()));
+        junk.push(0);
+        assert_eq!(verify_bytes(&junk), Err(io::ErrorKind::InvalidData));
+
+        assert_eq!(verify_bytes(&good[..417 * 3 + 100]), Ok(()));
+        assert_eq!(verify_bytes(&good[..417 * 3 + 200]), Err(io::ErrorKind::InvalidData));
+
+        let mut lost_sync = good.clone();
+        lost_sync[417 * 2] = 0;
+        assert_eq!(verify_bytes(&lost_sync), Err(io::ErrorKind::InvalidData));
+
+        let mut mixed = good.clone();
+        mixed.extend(frames([0xFF, 0xFD, 0x90, 0x00], 2));
+        assert_eq!(verify_bytes(&mixed), Err(io::ErrorKind::InvalidData));
+
+        for bad in &[&b""[..], b"junk", &tagged[..100]] {
+            assert_eq!(verify_bytes(bad), Err(io::ErrorKind::InvalidData));
+        }
+        assert_eq!(verify_bytes(&[0xFF, 0xFB, 0x00, 0x00, 0x00]),
+                   Err(io::ErrorKind::Unsupported));
+    }
+}
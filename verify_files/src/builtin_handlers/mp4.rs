@@ -0,0 +1,421 @@
+//! A walker for ISO Base Media files (MP4, M4A, 3GP, etc.) and QuickTime movies
+//!
+//! (Like RIFF, these are trees of length-prefixed boxes, so truncation and damaged box headers are
+//! easy to spot. Beyond that, the sample tables in `moov` are cross-checked against each other and
+//! against the file's length, but the media data itself isn't decoded.)
+
+// Standard library imports
+use std::convert::TryFrom;
+use std::io::{self, Read, Seek, SeekFrom};
+
+// Local Imports
+use super::bytes::{be32, be64};
+use super::{corrupt, unsupported};
+
+/// The largest `moov` box which will be read into memory to check the sample tables
+const MAX_MOOV_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Top-level boxes which may start a classic QuickTime movie, which predates `ftyp`
+const QUICKTIME_FIRST_BOXES: [&[u8; 4]; 6] =
+    [b"moov", b"mdat", b"wide", b"free", b"skip", b"pnot"];
+
+/// Render a box type for use in error messages
+pub fn name(kind: [u8; 4]) -> String {
+    String::from_utf8_lossy(&kind).into_owned()
+}
+
+/// Parse a box header from the start of `bytes`, returning the type, header length, and box size
+///
+/// A size of 0 (meaning "to the end of the file") is returned as `None`.
+fn box_header(bytes: &[u8]) -> Option<([u8; 4], usize, Option<u64>)> {
+    let kind = <[u8; 4]>::try_from(bytes.get(4..8)?).ok()?;
+    match be32(bytes) {
+        0 => Some((kind, 8, None)),
+        1 => Some((kind, 16, Some(be64(bytes.get(8..16)?)))),
+        size => Some((kind, 8, Some(u64::from(size)))),
+    }
+}
+
+/// Split the payload of a container box into its children, requiring that they fill it exactly
+///
+/// `parent` names the container in error messages, including the format (eg. "MP4 moov").
+///
+/// (A trailing 32-bit zero is tolerated, since some QuickTime writers terminate containers with
+/// one.)
+pub fn children<'a>(parent: &str, mut data: &'a [u8]) -> io::Result<Vec<([u8; 4], &'a [u8])>> {
+    let mut found = Vec::new();
+    while !data.is_empty() {
+        if data == [0; 4] {
+            break;
+        }
+        let malformed = || corrupt(&format!("Malformed box header inside {} box", parent));
+        let (kind, header_len, size) = box_header(data).ok_or_else(malformed)?;
+        let size = size.and_then(|size| usize::try_from(size).ok()).ok_or_else(malformed)?;
+        if size < header_len || size > data.len() {
+            return Err(corrupt(&format!(
+                "The {} box inside the {} box has an invalid size", name(kind), parent)));
+        }
+        found.push((kind, &data[header_len..size]));
+        data = &data[size..];
+    }
+    Ok(found)
+}
+
+/// Find the only child of type `kind`, complaining if there are none or several
+pub fn only_child<'a>(parent: &str, boxes: &[([u8; 4], &'a [u8])], kind: &[u8; 4])
+        -> io::Result<&'a [u8]> {
+    let mut matches = boxes.iter().filter(|(found, _)| found == kind);
+    match (matches.next(), matches.next()) {
+        (Some((_, payload)), None) => Ok(payload),
+        (None, _) => Err(corrupt(&format!("{} box has no {} box", parent, name(*kind)))),
+        (Some(_), Some(_)) => Err(corrupt(&format!(
+            "{} box has more than one {} box", parent, name(*kind)))),
+    }
+}
+
+/// Check a full box's version and that its payload is long enough, returning its flags and the
+/// rest of the payload
+///
+/// `lengths` gives the minimum length of the rest of the payload for each supported version.
+pub fn full_box<'a>(kind: &str, payload: &'a [u8], lengths: &[usize])
+        -> io::Result<(u32, &'a [u8])> {
+    let header = payload.get(..4)
+        .ok_or_else(|| corrupt(&format!("{} box is too short", kind)))?;
+    let (version, flags) = (header[0], be32(header) & 0x00FF_FFFF);
+    let body = &payload[4..];
+    match lengths.get(usize::from(version)) {
+        Some(&len) if body.len() >= len => Ok((flags, body)),
+        Some(_) => Err(corrupt(&format!("{} box is too short", kind))),
+        None => Err(unsupported(&format!("Unsupported {} box version {}", kind, version))),
+    }
+}
+
+/// Parse a table box made of a 32-bit entry count followed by `entry_len`-byte entries (after
+/// `skip` bytes of other fields), requiring that the entries fill it exactly
+fn table<'a>(kind: &str, payload: &'a [u8], skip: usize, entry_len: usize)
+        -> io::Result<Vec<&'a [u8]>> {
+    let (_, body) = full_box(kind, payload, &[skip + 4])?;
+    let count = be32(&body[skip..]) as usize;
+    let entries = &body[skip + 4..];
+    if count.checked_mul(entry_len) != Some(entries.len()) {
+        return Err(corrupt(&format!("{} box's entry count doesn't match its size", kind)));
+    }
+    Ok(entries.chunks(entry_len).collect())
+}
+
+/// Check the sample tables of one track
+///
+/// `file_len` is used to check the chunk offsets, unless the media is stored in another file.
+fn check_sample_table(stbl: &[u8], file_len: Option<u64>) -> io::Result<()> {
+    let boxes = children("MP4 stbl", stbl)?;
+
+    let stsd = only_child("MP4 stbl", &boxes, b"stsd")?;
+    let (_, stsd_body) = full_box("MP4 stsd", stsd, &[4])?;
+    let descriptions = be32(stsd_body);
+    if children("MP4 stsd", &stsd_body[4..])?.len() != descriptions as usize {
+        return Err(corrupt("MP4 stsd box's entry count doesn't match its contents"));
+    }
+
+    let mut samples = 0_u64;
+    for entry in table("MP4 stts", only_child("MP4 stbl", &boxes, b"stts")?, 0, 8)? {
+        samples += u64::from(be32(entry));
+    }
+
+    let sizes = boxes.iter().filter(|(kind, _)| kind == b"stsz" || kind == b"stz2").count();
+    let sample_count = match boxes.iter().find(|(kind, _)| kind == b"stsz" || kind == b"stz2") {
+        Some((kind, payload)) if sizes == 1 && kind == b"stsz" => {
+            let (_, body) = full_box("MP4 stsz", payload, &[8])?;
+            let count = u64::from(be32(&body[4..]));
+            let expected = if be32(body) == 0 { count * 4 } else { 0 };
+            if body.len() as u64 - 8 != expected {
+                return Err(corrupt("MP4 stsz box's sample count doesn't match its size"));
+            }
+            count
+        },
+        Some((_, payload)) if sizes == 1 => {
+            let (_, body) = full_box("MP4 stz2", payload, &[8])?;
+            let count = u64::from(be32(&body[4..]));
+            let field_size = u64::from(body[3]);
+            if ![4, 8, 16].contains(&field_size)
+                    || body.len() as u64 - 8 != (count * field_size + 7) / 8 {
+                return Err(corrupt("MP4 stz2 box is malformed"));
+            }
+            count
+        },
+        _ => return Err(corrupt("MP4 stbl box needs exactly one stsz or stz2 box")),
+    };
+
+    let offsets: Vec<u64> = match (boxes.iter().find(|(kind, _)| kind == b"stco"),
+                                   boxes.iter().find(|(kind, _)| kind == b"co64")) {
+        (Some((_, stco)), None) =>
+            table("MP4 stco", stco, 0, 4)?.into_iter().map(|x| u64::from(be32(x))).collect(),
+        (None, Some((_, co64))) => table("MP4 co64", co64, 0, 8)?.into_iter().map(be64).collect(),
+        _ => return Err(corrupt("MP4 stbl box needs exactly one stco or co64 box")),
+    };
+    if let Some(file_len) = file_len {
+        if let Some(offset) = offsets.iter().find(|&&offset| offset >= file_len) {
+            return Err(corrupt(&format!(
+                "MP4 chunk offset {} is past the end of the file (truncated?)", offset)));
+        }
+    }
+
+    // Count the samples which the sample-to-chunk table places in the chunks
+    let chunk_count = offsets.len() as u64;
+    let runs = table("MP4 stsc", only_child("MP4 stbl", &boxes, b"stsc")?, 0, 12)?;
+    let mut placed = 0_u64;
+    for (idx, run) in runs.iter().enumerate() {
+        let first = u64::from(be32(run));
+        let next = runs.get(idx + 1).map_or(chunk_count + 1, |next| u64::from(be32(next)));
+        let description = be32(&run[8..]);
+        if (idx == 0 && first != 1) || next <= first || next > chunk_count + 1
+                || description == 0 || description > descriptions {
+            return Err(corrupt("MP4 stsc box is malformed"));
+        }
+        placed += (next - first) * u64::from(be32(&run[4..]));
+    }
+    if runs.is_empty() && chunk_count > 0 {
+        return Err(corrupt("MP4 stsc box is empty, but the track has chunks"));
+    }
+    if placed != sample_count || samples != sample_count {
+        return Err(corrupt("MP4 sample tables disagree about how many samples there are"));
+    }
+    Ok(())
+}
+
+/// Check the structure of one track
+fn check_track(trak: &[u8], file_len: u64) -> io::Result<()> {
+    let boxes = children("MP4 trak", trak)?;
+    full_box("MP4 tkhd", only_child("MP4 trak", &boxes, b"tkhd")?, &[80, 92])?;
+
+    let mdia = children("MP4 mdia", only_child("MP4 trak", &boxes, b"mdia")?)?;
+    full_box("MP4 mdhd", only_child("MP4 mdia", &mdia, b"mdhd")?, &[20, 32])?;
+    full_box("MP4 hdlr", only_child("MP4 mdia", &mdia, b"hdlr")?, &[20])?;
+
+    let minf = children("MP4 minf", only_child("MP4 mdia", &mdia, b"minf")?)?;
+    let mut self_contained = true;
+    if let Some((_, dinf)) = minf.iter().find(|(kind, _)| kind == b"dinf") {
+        let dinf = children("MP4 dinf", dinf)?;
+        let (_, dref) = full_box("MP4 dref", only_child("MP4 dinf", &dinf, b"dref")?, &[4])?;
+        for (_, entry) in children("MP4 dref", &dref[4..])? {
+            let (flags, _) = full_box("MP4 dref entry", entry, &[0, 0])?;
+            self_contained &= flags & 1 != 0;
+        }
+    }
+    check_sample_table(only_child("MP4 minf", &minf, b"stbl")?, Some(file_len).filter(|_|
+        self_contained))
+}
+
+/// Walk the top-level boxes of the ISO Base Media file read from `input`, which is `file_len` bytes
+/// long, returning the type of each with the range of bytes holding its payload
+///
+/// `format` names the file format in error messages.
+pub fn top_level_boxes<R: Read + Seek>(input: &mut R, file_len: u64, format: &str)
+        -> io::Result<Vec<([u8; 4], u64, u64)>> {
+    let (mut pos, mut boxes) = (0, Vec::new());
+    while pos < file_len {
+        let mut header = [0; 16];
+        input.seek(SeekFrom::Start(pos))?;
+        let header_len = input.take(16).read(&mut header)?;
+        let (kind, min_len, size) = box_header(&header[..header_len]).filter(|&(_, len, _)|
+            len <= header_len).ok_or_else(|| corrupt(&format!(
+                "Truncated {} box header at byte {}", format, pos)))?;
+        if !kind.iter().all(|&byte| (0x20..0x7F).contains(&byte)) {
+            return Err(corrupt(&format!("Invalid {} box type at byte {}", format, pos)));
+        }
+        let size = size.unwrap_or(file_len - pos);
+        if size < min_len as u64 {
+            return Err(corrupt(&format!("{} {} box at byte {} is too short",
+                                        format, name(kind), pos)));
+        }
+        if size > file_len - pos {
+            return Err(corrupt(&format!(
+                "{} {} box at byte {} runs past the end of the file (truncated?)",
+                format, name(kind), pos)));
+        }
+        boxes.push((kind, pos + min_len as u64, pos + size));
+        pos += size;
+    }
+    Ok(boxes)
+}
+
+/// Check the `moov` box whose payload spans `start..end` in `input`, which is `file_len` bytes
+/// long, along with the tracks inside it
+pub fn check_movie<R: Read + Seek>(input: &mut R, start: u64, end: u64, file_len: u64)
+        -> io::Result<()> {
+    if end - start > MAX_MOOV_SIZE {
+        return Err(unsupported("MP4 moov box is too large to check"));
+    }
+    let mut moov = vec![0; (end - start) as usize];
+    input.seek(SeekFrom::Start(start))?;
+    input.read_exact(&mut moov)?;
+
+    let moov = children("MP4 moov", &moov)?;
+    if moov.iter().any(|(kind, _)| kind == b"mvex") {
+        return Err(unsupported("Fragmented MP4 files aren't supported"));
+    }
+    full_box("MP4 mvhd", only_child("MP4 moov", &moov, b"mvhd")?, &[96, 108])?;
+    let mut tracks = 0;
+    for (_, trak) in moov.iter().filter(|(kind, _)| kind == b"trak") {
+        check_track(trak, file_len)?;
+        tracks += 1;
+    }
+    if tracks == 0 {
+        return Err(corrupt("MP4 moov box has no tracks"));
+    }
+    Ok(())
+}
+
+/// Check the structure of the MP4 or QuickTime file read from `input`, which is `file_len`
+/// bytes long
+///
+/// Fragmented files (with `moof` boxes) are reported as unsupported.
+pub fn verify<R: Read + Seek>(mut input: R, file_len: u64) -> io::Result<()> {
+    let boxes = top_level_boxes(&mut input, file_len, "MP4")?;
+
+    let ftyp_pos = boxes.iter().position(|(kind, _, _)| kind == b"ftyp");
+    match (ftyp_pos, boxes.first()) {
+        (Some(0), _) => {},
+        (Some(1), Some((kind, _, _))) if kind == b"jP  " => {},
+        (None, Some((kind, _, _))) if QUICKTIME_FIRST_BOXES.contains(&kind) => {},
+        _ => return Err(corrupt("MP4 file doesn't start with an ftyp box")),
+    }
+    if let Some(idx) = ftyp_pos {
+        let (_, start, end) = boxes[idx];
+        if end - start < 8 || (end - start) % 4 != 0 {
+            return Err(corrupt("MP4 ftyp box is malformed"));
+        }
+    }
+    if boxes.iter().any(|(kind, _, _)| kind == b"moof") {
+        return Err(unsupported("Fragmented MP4 files aren't supported"));
+    }
+
+    let mut moovs = boxes.iter().filter(|(kind, _, _)| kind == b"moov");
+    match (moovs.next(), moovs.next()) {
+        (Some(&(_, start, end)), None) => check_movie(&mut input, start, end, file_len),
+        (None, _) => Err(corrupt("MP4 file has no moov box (unfinished recording?)")),
+        (Some(_), Some(_)) => Err(corrupt("MP4 file has more than one moov box")),
+    }
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Build a box
+    fn mp4box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut out = (payload.len() as u32 + 8).to_be_bytes().to_vec();
+        out.extend(kind);
+        out.extend(payload);
+        out
+    }
+
+    /// Build a full box with version 0 and the given flags
+    fn full(kind: &[u8; 4], flags: u32, payload: &[u8]) -> Vec<u8> {
+        mp4box(kind, &[&flags.to_be_bytes()[..], payload].concat())
+    }
+
+    /// Build a table box with the given entries
+    fn table_box(kind: &[u8; 4], prefix: &[u8], entries: &[&[u32]]) -> Vec<u8> {
+        let mut payload = prefix.to_vec();
+        payload.extend(&(entries.len() as u32).to_be_bytes());
+        for field in entries.iter().flat_map(|entry| entry.iter()) {
+            payload.extend(&field.to_be_bytes());
+        }
+        full(kind, 0, &payload)
+    }
+
+    /// Build a one-track file with the given chunk offset box, with `mdat`'s payload at byte 28
+    fn build(offsets: &[u8], extra_stbl: &[u8], mdat: &[u8]) -> Vec<u8> {
+        let stsd = full(b"stsd", 0, &[&1_u32.to_be_bytes()[..], &mp4box(b"mp4a", &[0; 28])]
+                        .concat());
+        let stbl = mp4box(b"stbl", &[
+            stsd,
+            table_box(b"stts", b"", &[&[3, 1024]]),
+            table_box(b"stsc", b"", &[&[1, 3, 1]]),
+            table_box(b"stsz", &0_u32.to_be_bytes(), &[&[10], &[10], &[10]]),
+            offsets.to_vec(),
+            extra_stbl.to_vec(),
+        ].concat());
+        let dinf = mp4box(b"dinf", &full(b"dref", 0, &[&1_u32.to_be_bytes()[..],
+                                                      &full(b"url ", 1, b"")].concat()));
+        let minf = mp4box(b"minf", &[full(b"smhd", 0, &[0; 4]), dinf, stbl].concat());
+        let mdia = mp4box(b"mdia", &[full(b"mdhd", 0, &[0; 20]),
+                                     full(b"hdlr", 0, b"\0\0\0\0soun\0\0\0\0\0\0\0\0\0\0\0\0\0"),
+                                     minf].concat());
+        let trak = mp4box(b"trak", &[full(b"tkhd", 0, &[0; 80]), mdia].concat());
+        let moov = mp4box(b"moov", &[full(b"mvhd", 0, &[0; 96]), trak].concat());
+        [mp4box(b"ftyp", b"M4A \0\0\0\0isom"), mp4box(b"mdat", mdat), moov].concat()
+    }
+
+    /// Verify `input`, returning the error kind on failure
+    fn verify_bytes(input: &[u8]) -> Result<(), io::ErrorKind> {
+        verify(Cursor::new(input), input.len() as u64).map_err(|err| err.kind())
+    }
+
+    #[test]
+    fn test_box_structure() {
+        let stco = table_box(b"stco", b"", &[&[28]]);
+        let good = build(&stco, b"", &[0x55; 30]);
+        assert_eq!(verify_bytes(&good), Ok(()));
+        let co64 = full(b"co64", 0, &[0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 28]);
+        assert_eq!(verify_bytes(&build(&co64, b"", &[0x55; 30])), Ok(()));
+
+        // The classic sign of an incomplete download
+        for cut in &[1, 10, good.len() - 20, good.len() - 1] {
+            assert_eq!(verify_bytes(&good[..*cut]), Err(io::ErrorKind::InvalidData));
+        }
+        let mut trailing = good.clone();
+        trailing.extend(b"junk");
+        assert_eq!(verify_bytes(&trailing), Err(io::ErrorKind::InvalidData));
+
+        // A 64-bit largesize box, and a final box which runs to the end of the file
+        let mut large = good.clone();
+        large.extend(&[0, 0, 0, 1, b'f', b'r', b'e', b'e', 0, 0, 0, 0, 0, 0, 0, 20, 1, 2, 3, 4]);
+        assert_eq!(verify_bytes(&large), Ok(()));
+        let mut to_eof = good.clone();
+        to_eof.extend(&[0, 0, 0, 0, b'f', b'r', b'e', b'e', 9, 9, 9]);
+        assert_eq!(verify_bytes(&to_eof), Ok(()));
+
+        // Boxes in the wrong order or number
+        let ftyp_len = 20;
+        let later_ftyp = [&good[ftyp_len..], &good[..ftyp_len]].concat();
+        assert_eq!(verify_bytes(&later_ftyp), Err(io::ErrorKind::InvalidData));
+        let moov_start = ftyp_len + 38;
+        let no_moov = &good[..moov_start];
+        assert_eq!(verify_bytes(no_moov), Err(io::ErrorKind::InvalidData));
+        let two_moovs = [&good[..], &good[moov_start..]].concat();
+        assert_eq!(verify_bytes(&two_moovs), Err(io::ErrorKind::InvalidData));
+
+        // A QuickTime movie from before `ftyp` existed
+        let quicktime = build(&table_box(b"stco", b"", &[&[16]]), b"", &[0x55; 30]);
+        assert_eq!(verify_bytes(&[&mp4box(b"wide", b"")[..], &quicktime[ftyp_len..]].concat()),
+                   Ok(()));
+
+        let mut fragmented = good.clone();
+        fragmented.extend(mp4box(b"moof", &[0; 8]));
+        assert_eq!(verify_bytes(&fragmented), Err(io::ErrorKind::Unsupported));
+    }
+
+    #[test]
+    fn test_sample_tables() {
+        let past_end = build(&table_box(b"stco", b"", &[&[5000]]), b"", &[0x55; 30]);
+        assert_eq!(verify_bytes(&past_end), Err(io::ErrorKind::InvalidData));
+        let two_chunks = build(&table_box(b"stco", b"", &[&[28], &[38]]), b"", &[0x55; 30]);
+        assert_eq!(verify_bytes(&two_chunks), Err(io::ErrorKind::InvalidData));
+        let no_offsets = build(b"", b"", &[0x55; 30]);
+        assert_eq!(verify_bytes(&no_offsets), Err(io::ErrorKind::InvalidData));
+        let stco = table_box(b"stco", b"", &[&[28]]);
+        let both = build(&stco, &full(b"co64", 0, &[0; 4]), &[0x55; 30]);
+        assert_eq!(verify_bytes(&both), Err(io::ErrorKind::InvalidData));
+
+        // Damage to a table's entry count
+        let good = build(&stco, b"", &[0x55; 30]);
+        let stts_count = good.windows(4).position(|x| x == b"stts").unwrap() + 8;
+        let mut bad_count = good.clone();
+        bad_count[stts_count + 3] = 2;
+        assert_eq!(verify_bytes(&bad_count), Err(io::ErrorKind::InvalidData));
+    }
+}
@@ -0,0 +1,299 @@
+//! A walker for the Ogg container (RFC 3533) which verifies every page's CRC
+//!
+//! (The packets themselves aren't decoded, so this works the same for Vorbis, Opus, Theora, FLAC,
+//! or anything else which has been put in an Ogg container.)
+
+// Standard library imports
+use std::collections::HashMap;
+use std::io::{self, Read};
+
+// Local Imports
+use super::checksums::OggCrc;
+use super::{corrupt, unsupported};
+
+/// The magic number at the start of every page
+const CAPTURE_PATTERN: [u8; 4] = [b'O', b'g', b'g', b'S'];
+
+/// The size of a page header, not counting the segment table
+const HEADER_SIZE: usize = 27;
+
+/// Header flag: This page begins with the rest of a packet from the previous page
+const FLAG_CONTINUED: u8 = 0x01;
+
+/// Header flag: This is the first page of a logical stream
+const FLAG_BOS: u8 = 0x02;
+
+/// Header flag: This is the last page of a logical stream
+const FLAG_EOS: u8 = 0x04;
+
+/// A page which has passed the checks in [`walk`], for codec-specific checks to look into
+pub struct Page<'a> {
+    /// The logical stream the page belongs to
+    pub serial: u32,
+    /// The page's sequence number within its stream
+    pub sequence: u32,
+    /// Whether the page begins with the rest of a packet from the previous page
+    pub continued: bool,
+    /// Whether this is the first page of its stream
+    pub first: bool,
+    /// Whether this is the last page of its stream
+    pub last: bool,
+    /// The codec-specific position of the last packet which ends on this page (or `u64::MAX` if
+    /// none do)
+    pub granule: u64,
+    /// The lacing values which divide `body` into packets
+    pub segments: &'a [u8],
+    /// The page's data
+    pub body: &'a [u8],
+}
+
+impl<'a> Page<'a> {
+    /// Split the page's data into packets, along with whether each one ends on this page (which
+    /// only the last one can fail to)
+    pub fn packets(&self) -> Vec<(&'a [u8], bool)> {
+        let (mut packets, mut start, mut end) = (Vec::new(), 0, 0);
+        for &lacing in self.segments {
+            end += usize::from(lacing);
+            if lacing < 255 {
+                packets.push((&self.body[start..end], true));
+                start = end;
+            }
+        }
+        if start < self.body.len() {
+            packets.push((&self.body[start..], false));
+        }
+        packets
+    }
+}
+
+/// Read as much of `buf` as `input` has left, returning how many bytes that was
+fn read_up_to(input: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match input.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(len) => filled += len,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {},
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(filled)
+}
+
+/// Read exactly enough to fill `buf`, reporting a short read as a truncated page
+fn read_page_part(input: &mut impl Read, buf: &mut [u8]) -> io::Result<()> {
+    if read_up_to(input, buf)? == buf.len() {
+        Ok(())
+    } else {
+        Err(corrupt("Ogg page is truncated"))
+    }
+}
+
+/// Walk every page of the Ogg file read from `input`
+///
+/// Besides each page's CRC, this checks that every logical stream's pages are numbered without
+/// gaps and that every stream is ended by a page with the end-of-stream flag set (which catches
+/// truncation at a page boundary). Chained files, where new streams begin after earlier ones have
+/// ended, are accepted.
+pub fn verify(input: impl Read) -> io::Result<()> {
+    walk(input, |_| Ok(()))
+}
+
+/// Walk every page of the Ogg file read from `input` like [`verify`], passing each one to
+/// `visit` once it has been checked
+pub fn walk(mut input: impl Read, mut visit: impl FnMut(&Page<'_>) -> io::Result<()>)
+        -> io::Result<()> {
+    // The sequence number expected next in each stream which has begun but not ended
+    let mut open_streams = HashMap::new();
+    let (mut header, mut pages) = ([0; HEADER_SIZE], 0_u64);
+    loop {
+        let len = read_up_to(&mut input, &mut header)?;
+        if len == 0 && pages > 0 {
+            break;
+        }
+        let checked = len.min(CAPTURE_PATTERN.len());
+        if len == 0 || header[..checked] != CAPTURE_PATTERN[..checked] {
+            return Err(corrupt(if pages == 0 { "Not an Ogg file" }
+                               else { "Ogg page doesn't follow on from the previous one" }));
+        }
+        if len < HEADER_SIZE {
+            return Err(corrupt("Ogg page is truncated"));
+        }
+        if header[4] != 0 {
+            return Err(unsupported(&format!("Unsupported Ogg version {}", header[4])));
+        }
+        let flags = header[5];
+        if flags & 0xF8 != 0 {
+            return Err(corrupt("Reserved flags set in Ogg page header"));
+        }
+        let granule = u64::from_le_bytes([header[6], header[7], header[8], header[9],
+                                          header[10], header[11], header[12], header[13]]);
+        let serial = u32::from_le_bytes([header[14], header[15], header[16], header[17]]);
+        let sequence = u32::from_le_bytes([header[18], header[19], header[20], header[21]]);
+        let stored_crc = u32::from_le_bytes([header[22], header[23], header[24], header[25]]);
+
+        let mut segments = vec![0; usize::from(header[26])];
+        read_page_part(&mut input, &mut segments)?;
+        let mut body = vec![0; segments.iter().map(|&len| usize::from(len)).sum()];
+        read_page_part(&mut input, &mut body)?;
+
+        header[22..26].copy_from_slice(&[0; 4]);
+        let mut crc = OggCrc::new();
+        crc.update(&header);
+        crc.update(&segments);
+        crc.update(&body);
+        if crc.finish() != stored_crc {
+            return Err(corrupt(&format!("CRC mismatch for page {} of Ogg stream {:08X}",
+                                        sequence, serial)));
+        }
+
+        if flags & FLAG_BOS != 0 {
+            if open_streams.insert(serial, sequence.wrapping_add(1)).is_some() {
+                return Err(corrupt(&format!("Ogg stream {:08X} begins twice", serial)));
+            }
+        } else {
+            let expected = open_streams.get_mut(&serial).ok_or_else(|| corrupt(&format!(
+                "Ogg page belongs to stream {:08X}, which isn't in progress", serial)))?;
+            if sequence != *expected {
+                return Err(corrupt(&format!("Ogg stream {:08X} is missing pages {} to {}",
+                                            serial, expected, sequence.wrapping_sub(1))));
+            }
+            *expected = sequence.wrapping_add(1);
+        }
+        visit(&Page {
+            serial,
+            sequence,
+            continued: flags & FLAG_CONTINUED != 0,
+            first: flags & FLAG_BOS != 0,
+            last: flags & FLAG_EOS != 0,
+            granule,
+            segments: &segments,
+            body: &body,
+        })?;
+        if flags & FLAG_EOS != 0 {
+            open_streams.remove(&serial);
+        }
+        pages += 1;
+    }
+
+    match open_streams.keys().min() {
+        Some(serial) => Err(corrupt(&format!(
+            "Ogg stream {:08X} has no end-of-stream page (truncated?)", serial))),
+        None => Ok(()),
+    }
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a page holding `data` as a single packet
+    fn page(flags: u8, serial: u32, sequence: u32, data: &[u8]) -> Vec<u8> {
+        let mut out = CAPTURE_PATTERN.to_vec();
+        out.extend(&[0, flags, 0, 0, 0, 0, 0, 0, 0, 0]);
+        out.extend(&serial.to_le_bytes());
+        out.extend(&sequence.to_le_bytes());
+        out.extend(&[0; 4]);
+        out.extend(&[1, data.len() as u8]);
+        out.extend(data);
+        let mut crc = OggCrc::new();
+        crc.update(&out);
+        out[22..26].copy_from_slice(&crc.finish().to_le_bytes());
+        out
+    }
+
+    /// Build a complete logical stream of `count` pages
+    fn stream(serial: u32, count: u32) -> Vec<u8> {
+        (0..count).flat_map(|sequence| {
+            let flags = if sequence == 0 { FLAG_BOS } else { 0 } |
+                        if sequence == count - 1 { FLAG_EOS } else { 0 };
+            page(flags, serial, sequence, b"packet")
+        }).collect()
+    }
+
+    /// Verify `input`, returning the error kind on failure
+    fn verify_bytes(input: &[u8]) -> Result<(), io::ErrorKind> {
+        verify(input).map_err(|err| err.kind())
+    }
+
+    #[test]
+    fn test_page_structure() {
+        let single = stream(1, 3);
+        assert_eq!(verify_bytes(&single), Ok(()));
+        assert_eq!(verify_bytes(&stream(1, 1)), Ok(()));
+
+        let mut corrupted = single.clone();
+        corrupted[30] ^= 0x01;
+        assert_eq!(verify_bytes(&corrupted), Err(io::ErrorKind::InvalidData));
+        for cut in &[0, 2, 20, 30, 34, single.len() - 1] {
+            assert_eq!(verify_bytes(&single[..*cut]), Err(io::ErrorKind::InvalidData));
+        }
+        for trailer in &[&b"O"[..], b"junk"] {
+            let mut bad = single.clone();
+            bad.extend(*trailer);
+            assert_eq!(verify_bytes(&bad), Err(io::ErrorKind::InvalidData));
+        }
+        let mut prefixed = b"junk".to_vec();
+        prefixed.extend(&single);
+        assert_eq!(verify_bytes(&prefixed), Err(io::ErrorKind::InvalidData));
+
+        let mut future = page(FLAG_BOS | FLAG_EOS, 1, 0, b"packet");
+        future[4] = 1;
+        assert_eq!(verify_bytes(&future), Err(io::ErrorKind::Unsupported));
+    }
+
+    #[test]
+    fn test_stream_continuity() {
+        let mut chained = stream(1, 3);
+        chained.extend(stream(2, 2));
+        chained.extend(stream(1, 2));
+        assert_eq!(verify_bytes(&chained), Ok(()));
+
+        let mut multiplexed = page(FLAG_BOS, 1, 0, b"a");
+        multiplexed.extend(page(FLAG_BOS, 2, 0, b"b"));
+        multiplexed.extend(page(0, 1, 1, b"c"));
+        multiplexed.extend(page(FLAG_EOS, 2, 1, b"d"));
+        multiplexed.extend(page(FLAG_EOS, 1, 2, b"e"));
+        assert_eq!(verify_bytes(&multiplexed), Ok(()));
+
+        let mut gap = page(FLAG_BOS, 1, 0, b"a");
+        gap.extend(page(FLAG_EOS, 1, 2, b"c"));
+        assert_eq!(verify_bytes(&gap), Err(io::ErrorKind::InvalidData));
+
+        let unterminated = [page(FLAG_BOS, 1, 0, b"a"), page(0, 1, 1, b"b")].concat();
+        assert_eq!(verify_bytes(&unterminated), Err(io::ErrorKind::InvalidData));
+
+        let orphan = [stream(1, 2), page(0, 2, 1, b"a")].concat();
+        assert_eq!(verify_bytes(&orphan), Err(io::ErrorKind::InvalidData));
+
+        let restarted = [page(FLAG_BOS, 1, 0, b"a"), stream(1, 2)].concat();
+        assert_eq!(verify_bytes(&restarted), Err(io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn test_packets() {
+        let body = [&b"abc"[..], &[0; 255], b"de", &[1; 255]].concat();
+        let segments = [3, 255, 0, 2, 255];
+        let page = Page { serial: 1, sequence: 0, continued: false, first: true, last: false,
+                          granule: 0, segments: &segments, body: &body };
+        assert_eq!(page.packets(), vec![(&b"abc"[..], true), (&[0; 255][..], true),
+                                        (&b"de"[..], true), (&[1; 255][..], false)]);
+
+        // Every page reaching the visitor has passed the walker's checks
+        let mut seen = Vec::new();
+        walk(&stream(5, 3)[..], |page| {
+            seen.push((page.serial, page.sequence, page.first, page.last));
+            Ok(())
+        }).expect("valid stream");
+        assert_eq!(seen, vec![(5, 0, true, false), (5, 1, false, false), (5, 2, false, true)]);
+    }
+
+    #[test]
+    fn test_vorbis_fixtures() {
+        assert_eq!(verify_bytes(include_bytes!("../../../test_data/good/testfile.ogg")), Ok(()));
+        assert_eq!(verify_bytes(include_bytes!("../../../test_data/bad/testfile.ogg")),
+                   Err(io::ErrorKind::InvalidData));
+    }
+}
@@ -0,0 +1,325 @@
+//! Checks for the rules the Open Packaging Conventions (OPC) impose on OOXML files
+//!
+//! (Word, Excel, and PowerPoint files are Zip files whose parts are tied together by XML
+//! relationship parts, so a document can pass its CRC checks and still be missing the parts a
+//! reader needs. The Zip CRCs are left to the caller.)
+
+// Standard library imports
+use std::collections::HashSet;
+use std::io::{self, Read, Seek};
+
+// 3rd-party imports
+use zip::read::{ZipArchive, ZipFile};
+use zip::result::ZipError;
+
+// Local Imports
+use super::{corrupt, unsupported, xml};
+
+/// The signature of an OLE2 compound file (used by legacy and password-protected Office files)
+const OLE2_MAGIC: [u8; 8] = [0xD0, 0xCF, 0x11, 0xE0, 0xA1, 0xB1, 0x1A, 0xE1];
+
+/// The path of the part which declares the content types of all the others
+const CONTENT_TYPES_PATH: &str = "[Content_Types].xml";
+
+/// The path of the package-level relationships part
+const PACKAGE_RELS_PATH: &str = "_rels/.rels";
+
+/// The end of the relationship type (transitional or strict) which points at the main part
+const MAIN_PART_TYPE: &str = "/relationships/officeDocument";
+
+/// Shorthand for reporting a violated OPC rule
+fn violation(rule: &str) -> io::Error {
+    corrupt(&format!("OOXML rule violated: {}", rule))
+}
+
+/// Open the part at `path`, which must exist
+fn open_part<'a, R: Read + Seek>(zip: &'a mut ZipArchive<R>, path: &str)
+        -> io::Result<ZipFile<'a>> {
+    match zip.by_name(path) {
+        Ok(entry) => Ok(entry),
+        Err(ZipError::FileNotFound) => Err(violation(&format!("{} must exist", path))),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Reword a well-formedness error from the XML parser to name the part it was found in
+fn in_part(err: io::Error, path: &str) -> io::Error {
+    #[allow(clippy::wildcard_enum_match_arm)]
+    match err.kind() {
+        io::ErrorKind::InvalidData => violation(&format!("{} must be well-formed XML ({})",
+            path, err)),
+        _ => err,
+    }
+}
+
+/// Turn the target of an internal relationship into the name of the part it points to
+///
+/// (Targets are URIs relative to the directory of the part the relationships belong to, so they
+/// need percent-decoding and their `.` and `..` segments resolved. Returns `None` for targets
+/// which climb out of the package.)
+fn resolve_target(rels_path: &str, target: &str) -> Option<String> {
+    let target = target.split('#').next().unwrap_or(target);
+    let mut bytes = Vec::new();
+    let mut rest = target.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let decoded = match tail {
+            [high, low, ..] if byte == b'%' => std::str::from_utf8(&[*high, *low]).ok()
+                .and_then(|x| u8::from_str_radix(x, 16).ok()),
+            _ => None,
+        };
+        if let Some(decoded) = decoded {
+            bytes.push(decoded);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    let target = String::from_utf8(bytes).ok()?;
+
+    // `dir/_rels/name.rels` holds the relationships of `dir/name`
+    let mut segments: Vec<&str> = Vec::new();
+    if !target.starts_with('/') {
+        let source_dir = rels_path.rsplit_once("_rels/").map_or("", |(dir, _)| dir);
+        segments.extend(source_dir.split('/').filter(|x| !x.is_empty()));
+    }
+    for segment in target.split('/') {
+        match segment {
+            "" | "." => {},
+            ".." => { segments.pop()?; },
+            _ => segments.push(segment),
+        }
+    }
+    Some(segments.join("/"))
+}
+
+/// Parse the relationships part at `rels_path`, returning each internal relationship's type and
+/// the name of the part it points to
+fn read_relationships<R: Read + Seek>(zip: &mut ZipArchive<R>, rels_path: &str)
+        -> io::Result<Vec<(String, String)>> {
+    let mut relationships = Vec::new();
+    let mut reader = xml::Reader::new(open_part(zip, rels_path)?);
+    while let Some(event) = reader.next_event().map_err(|err| in_part(err, rels_path))? {
+        let attributes = match event {
+            xml::Event::Start(name, attributes) if xml::local_name(&name) == "Relationship" => {
+                attributes
+            },
+            _ => continue,
+        };
+        let attribute = |wanted| attributes.iter().find(|(x, _)| x == wanted).map(|(_, x)| x);
+        if attribute("TargetMode").map(String::as_str) == Some("External") {
+            continue;
+        }
+        let (rel_type, target) = match (attribute("Type"), attribute("Target")) {
+            (Some(rel_type), Some(target)) => (rel_type, target),
+            _ => return Err(violation(&format!(
+                "every relationship in {} must have a Type and a Target", rels_path))),
+        };
+        let part = resolve_target(rels_path, target).ok_or_else(|| violation(&format!(
+            "the target {:?} of a relationship in {} must be inside the package", target,
+            rels_path)))?;
+        relationships.push((rel_type.clone(), part));
+    }
+    Ok(relationships)
+}
+
+/// Report OLE2 compound files, which can't be OOXML, as unsupported rather than as broken Zips
+///
+/// (Real `.doc` files renamed to `.docx` and password-protected OOXML files both look like this,
+/// and another handler may be able to make sense of them.)
+pub fn check_container(input: impl Read) -> io::Result<()> {
+    let mut header = Vec::new();
+    input.take(OLE2_MAGIC.len() as u64).read_to_end(&mut header)?;
+    if header == OLE2_MAGIC {
+        return Err(unsupported("File is an OLE2 compound file (eg. a legacy or \
+            password-protected Office document) rather than an OOXML package"));
+    }
+    Ok(())
+}
+
+/// Check the packaging rules for the OOXML file in `zip`
+///
+/// `[Content_Types].xml` and `_rels/.rels` must exist and be well-formed, the latter must point
+/// at a main part which exists and is well-formed (unless it's binary, as in `.xlsb`), and the
+/// internal targets of every relationship part in the package must exist. (Part names are
+/// compared case-insensitively, as the OPC specification requires.)
+pub fn verify<R: Read + Seek>(zip: &mut ZipArchive<R>) -> io::Result<()> {
+    xml::verify(open_part(zip, CONTENT_TYPES_PATH)?)
+        .map_err(|err| in_part(err, CONTENT_TYPES_PATH))?;
+
+    let parts: HashSet<String> = zip.file_names().map(str::to_lowercase).collect();
+    let mut rels_paths: Vec<String> = zip.file_names()
+        .filter(|x| x.ends_with(".rels") && (x.starts_with("_rels/") || x.contains("/_rels/")))
+        .map(str::to_owned).collect();
+    rels_paths.sort();
+    if !rels_paths.iter().any(|x| x == PACKAGE_RELS_PATH) {
+        return Err(violation(&format!("{} must exist", PACKAGE_RELS_PATH)));
+    }
+
+    let mut main_part = None;
+    for rels_path in &rels_paths {
+        for (rel_type, part) in read_relationships(zip, rels_path)? {
+            if !parts.contains(&part.to_lowercase()) {
+                return Err(violation(&format!(
+                    "the target {} of a relationship in {} must exist", part, rels_path)));
+            }
+            if rels_path == PACKAGE_RELS_PATH && rel_type.ends_with(MAIN_PART_TYPE) {
+                main_part = Some(part);
+            }
+        }
+    }
+
+    let main_part = main_part.ok_or_else(|| violation(&format!(
+        "{} must have an officeDocument relationship pointing at the main part",
+        PACKAGE_RELS_PATH)))?;
+    if main_part.to_lowercase().ends_with(".xml") {
+        // Look the part up by its name as stored, since the target may differ in case
+        let stored = zip.file_names().find(|x| x.eq_ignore_ascii_case(&main_part))
+            .map(str::to_owned).unwrap_or(main_part);
+        xml::verify(open_part(zip, &stored)?).map_err(|err| in_part(err, &stored))?;
+    }
+    Ok(())
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use zip::write::{FileOptions, ZipWriter};
+
+    use super::*;
+
+    /// A minimal `[Content_Types].xml`
+    const CONTENT_TYPES: &[u8] = b"<?xml version='1.0'?>
+        <Types xmlns='http://schemas.openxmlformats.org/package/2006/content-types'>
+          <Default Extension='xml' ContentType='application/xml'/>
+        </Types>";
+
+    /// Build a relationships part out of `(type, target)` pairs
+    fn rels(relationships: &[(&str, &str)]) -> Vec<u8> {
+        let mut out = b"<Relationships \
+            xmlns='http://schemas.openxmlformats.org/package/2006/relationships'>".to_vec();
+        for (i, (rel_type, target)) in relationships.iter().enumerate() {
+            out.extend(format!(
+                "<Relationship Id='rId{}' Type='http://schemas.openxmlformats.org/\
+                 officeDocument/2006/relationships/{}' Target='{}'/>", i, rel_type, target)
+                .bytes());
+        }
+        out.extend(b"</Relationships>");
+        out
+    }
+
+    /// Build a Zip file out of `entries`
+    fn build(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in entries {
+            zip.start_file(*name, FileOptions::default()).expect("writing to memory");
+            zip.write_all(data).expect("writing to memory");
+        }
+        zip.finish().expect("writing to memory").into_inner()
+    }
+
+    /// Check the packaging rules for the Zip file built from `entries`
+    fn verify_entries(entries: &[(&str, &[u8])]) -> io::Result<()> {
+        let mut zip = ZipArchive::new(Cursor::new(build(entries))).expect("valid Zip");
+        verify(&mut zip)
+    }
+
+    #[test]
+    fn test_resolve_target() {
+        assert_eq!(resolve_target("_rels/.rels", "word/document.xml").as_deref(),
+                   Some("word/document.xml"));
+        assert_eq!(resolve_target("_rels/.rels", "/word/document.xml").as_deref(),
+                   Some("word/document.xml"));
+        assert_eq!(resolve_target("word/_rels/document.xml.rels", "media/a%20b.png").as_deref(),
+                   Some("word/media/a b.png"));
+        assert_eq!(resolve_target("ppt/slides/_rels/slide1.xml.rels", "../slideLayouts/x.xml#y")
+                   .as_deref(), Some("ppt/slideLayouts/x.xml"));
+        assert_eq!(resolve_target("word/_rels/document.xml.rels", "/%7e"), Some("~".to_owned()));
+        assert_eq!(resolve_target("_rels/.rels", "../outside.xml"), None);
+    }
+
+    #[test]
+    fn test_good_packages() {
+        let package = rels(&[("officeDocument", "word/document.xml")]);
+        let document = rels(&[("styles", "styles.xml"), ("image", "/word/media/IMAGE1.png")]);
+        let external = b"<Relationships><Relationship Id='rId1' Type='hyperlink' \
+            Target='https://example.com/' TargetMode='External'/></Relationships>";
+        assert_eq!(verify_entries(&[
+            ("[Content_Types].xml", CONTENT_TYPES),
+            ("_rels/.rels", &package),
+            ("word/document.xml", b"<w:document/>"),
+            ("word/_rels/document.xml.rels", &document),
+            ("word/styles.xml", b"<w:styles/>"),
+            ("word/media/image1.png", b"not XML"),
+            ("word/_rels/styles.xml.rels", external),
+        ]).map_err(|err| err.to_string()), Ok(()));
+
+        // Binary main parts (as in `.xlsb`) aren't parsed
+        let package = rels(&[("officeDocument", "xl/workbook.bin")]);
+        assert_eq!(verify_entries(&[
+            ("[Content_Types].xml", CONTENT_TYPES),
+            ("_rels/.rels", &package),
+            ("xl/workbook.bin", b"\x83\x01\x00"),
+        ]).map_err(|err| err.to_string()), Ok(()));
+
+        for (path, data) in &[
+            ("good/testfile.docm", &include_bytes!("../../../test_data/good/testfile.docm")[..]),
+            ("good/testfile.docx", include_bytes!("../../../test_data/good/testfile.docx")),
+            ("good/testfile.potm", include_bytes!("../../../test_data/good/testfile.potm")),
+            ("good/testfile.ppsx", include_bytes!("../../../test_data/good/testfile.ppsx")),
+            ("good/testfile.pptx", include_bytes!("../../../test_data/good/testfile.pptx")),
+            ("good/testfile.xlsm", include_bytes!("../../../test_data/good/testfile.xlsm")),
+            ("good/testfile.xlsx", include_bytes!("../../../test_data/good/testfile.xlsx")),
+        ] {
+            assert_eq!(check_container(&data[..]).map_err(|err| err.to_string()), Ok(()));
+            let mut zip = ZipArchive::new(Cursor::new(data)).expect("valid Zip");
+            assert_eq!(verify(&mut zip).map_err(|err| err.to_string()), Ok(()), "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_packaging_violations() {
+        let content_types = ("[Content_Types].xml", CONTENT_TYPES);
+        let package = rels(&[("officeDocument", "word/document.xml")]);
+        let package = ("_rels/.rels", &package[..]);
+        let document = ("word/document.xml", &b"<w:document/>"[..]);
+        let no_main = rels(&[("extended-properties", "docProps/app.xml")]);
+        let dangling = rels(&[("styles", "styles.xml")]);
+        for (entries, rule) in &[
+            (vec![package, document], "[Content_Types].xml must exist"),
+            (vec![("[Content_Types].xml", b"<Types>"), package, document],
+             "[Content_Types].xml must be well-formed"),
+            (vec![content_types, document], "_rels/.rels must exist"),
+            (vec![content_types, ("_rels/.rels", b"<Relationships"), document],
+             "_rels/.rels must be well-formed"),
+            (vec![content_types, ("_rels/.rels", &no_main), ("docProps/app.xml", b"<a/>")],
+             "must have an officeDocument relationship"),
+            (vec![content_types, package],
+             "the target word/document.xml of a relationship in _rels/.rels must exist"),
+            (vec![content_types, package, ("word/document.xml", b"<w:document>")],
+             "word/document.xml must be well-formed"),
+            (vec![content_types, package, document, ("word/_rels/document.xml.rels", &dangling)],
+             "the target word/styles.xml of a relationship in word/_rels/document.xml.rels"),
+            (vec![content_types, ("_rels/.rels", b"<Relationships><Relationship/></Relationships>"),
+                  document], "must have a Type and a Target"),
+        ] {
+            let err = verify_entries(entries).expect_err(rule);
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            let message = err.to_string();
+            assert!(message.starts_with("OOXML rule violated: "), "{}", message);
+            assert!(message.contains(rule), "{:?} should mention {:?}", message, rule);
+        }
+    }
+
+    #[test]
+    fn test_ole2() {
+        let mut legacy = OLE2_MAGIC.to_vec();
+        legacy.extend(&[0; 504]);
+        assert_eq!(check_container(&legacy[..]).map_err(|err| err.kind()),
+                   Err(io::ErrorKind::Unsupported));
+        assert_eq!(check_container(&b"PK\x03\x04"[..]).map_err(|err| err.to_string()), Ok(()));
+    }
+}
@@ -0,0 +1,218 @@
+//! Checks for the rules OpenDocument imposes on top of Zip
+//!
+//! (The Zip CRCs are left to the caller. The XML parts are streamed through the XML checker
+//! rather than being read into memory, so even huge spreadsheets cost very little to check.)
+
+// Standard library imports
+use std::io::{self, Read, Seek};
+
+// 3rd-party imports
+use zip::read::{ZipArchive, ZipFile};
+use zip::result::ZipError;
+
+// Local Imports
+use super::epub::read_mimetype;
+use super::{corrupt, unsupported, xml};
+
+/// The prefix shared by the MIME types of all OpenDocument formats
+const MIMETYPE_PREFIX: &[u8] = b"application/vnd.oasis.opendocument.";
+
+/// The path of the manifest
+const MANIFEST_PATH: &str = "META-INF/manifest.xml";
+
+/// The XML parts which every document must have, apart from the manifest
+const REQUIRED_PARTS: [&str; 2] = ["content.xml", "styles.xml"];
+
+/// Shorthand for reporting a violated OpenDocument packaging rule
+fn violation(rule: &str) -> io::Error {
+    corrupt(&format!("OpenDocument rule violated: {}", rule))
+}
+
+/// Open the part at `path`, which must exist
+fn open_part<'a, R: Read + Seek>(zip: &'a mut ZipArchive<R>, path: &str)
+        -> io::Result<ZipFile<'a>> {
+    match zip.by_name(path) {
+        Ok(entry) => Ok(entry),
+        Err(ZipError::FileNotFound) => Err(violation(&format!("{} must exist", path))),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Reword a well-formedness error from the XML parser to name the part it was found in
+fn in_part(err: io::Error, path: &str) -> io::Error {
+    #[allow(clippy::wildcard_enum_match_arm)]
+    match err.kind() {
+        io::ErrorKind::InvalidData => violation(&format!("{} must be well-formed XML ({})",
+            path, err)),
+        _ => err,
+    }
+}
+
+/// Parse the manifest, returning the paths of the files it lists and whether any are encrypted
+fn read_manifest<R: Read + Seek>(zip: &mut ZipArchive<R>) -> io::Result<(Vec<String>, bool)> {
+    let mut paths = Vec::new();
+    let mut encrypted = false;
+    let mut reader = xml::Reader::new(open_part(zip, MANIFEST_PATH)?);
+    while let Some(event) = reader.next_event().map_err(|err| in_part(err, MANIFEST_PATH))? {
+        if let xml::Event::Start(name, attributes) = event {
+            match xml::local_name(&name) {
+                "encryption-data" => encrypted = true,
+                "file-entry" => {
+                    let (_, path) = attributes.into_iter()
+                        .find(|(x, _)| xml::local_name(x) == "full-path")
+                        .ok_or_else(|| violation(&format!(
+                            "every file-entry in {} must have a full-path", MANIFEST_PATH)))?;
+                    paths.push(path);
+                },
+                _ => {},
+            }
+        }
+    }
+    Ok((paths, encrypted))
+}
+
+/// Check the packaging rules for the OpenDocument file in `zip`
+///
+/// The `mimetype` entry must be first, uncompressed, and an OpenDocument MIME type, the manifest,
+/// `content.xml`, and `styles.xml` must exist and be well-formed, and every file the manifest
+/// lists must exist. (Directories in the manifest are exempt, since they may have no entry of
+/// their own.) Password-protected documents are reported as unsupported.
+pub fn verify<R: Read + Seek>(zip: &mut ZipArchive<R>) -> io::Result<()> {
+    if !read_mimetype(zip, violation)?.starts_with(MIMETYPE_PREFIX) {
+        return Err(violation("the mimetype entry must contain an OpenDocument MIME type"));
+    }
+    let (paths, encrypted) = read_manifest(zip)?;
+    if encrypted {
+        return Err(unsupported("Password-protected OpenDocument files aren't supported"));
+    }
+
+    for path in &REQUIRED_PARTS {
+        xml::verify(open_part(zip, path)?).map_err(|err| in_part(err, path))?;
+    }
+    for path in paths.iter().filter(|x| !x.ends_with('/')) {
+        match zip.by_name(path) {
+            Ok(_) => {},
+            Err(ZipError::FileNotFound) => return Err(violation(&format!(
+                "the file {} listed in {} must exist", path, MANIFEST_PATH))),
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(())
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use zip::write::{FileOptions, ZipWriter};
+    use zip::CompressionMethod;
+
+    use super::*;
+
+    /// The MIME type of an OpenDocument text document
+    const MIMETYPE: &[u8] = b"application/vnd.oasis.opendocument.text";
+
+    /// Build a manifest listing `paths`, with the last one encrypted if the flag is set
+    fn manifest(paths: &[&str], encrypted: bool) -> Vec<u8> {
+        let mut out = b"<?xml version='1.0' encoding='UTF-8'?>
+            <manifest:manifest xmlns:manifest='urn:oasis:names:tc:opendocument:xmlns:manifest:1.0'>
+              <manifest:file-entry manifest:full-path='/'
+                                   manifest:media-type='application/vnd.oasis.opendocument.text'/>"
+            .to_vec();
+        for (i, path) in paths.iter().enumerate() {
+            out.extend(format!("<manifest:file-entry manifest:full-path='{}'>", path).bytes());
+            if encrypted && i + 1 == paths.len() {
+                out.extend(b"<manifest:encryption-data manifest:checksum='AAAA'/>");
+            }
+            out.extend(b"</manifest:file-entry>");
+        }
+        out.extend(b"</manifest:manifest>");
+        out
+    }
+
+    /// Build a Zip file out of `entries`, storing `mimetype` uncompressed
+    fn build(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in entries {
+            let method = if *name == "mimetype" {
+                CompressionMethod::Stored
+            } else {
+                CompressionMethod::Deflated
+            };
+            zip.start_file(*name, FileOptions::default().compression_method(method))
+                .expect("writing to memory");
+            zip.write_all(data).expect("writing to memory");
+        }
+        zip.finish().expect("writing to memory").into_inner()
+    }
+
+    /// Check the packaging rules for the Zip file built from `entries`
+    fn verify_entries(entries: &[(&str, &[u8])]) -> io::Result<()> {
+        let mut zip = ZipArchive::new(Cursor::new(build(entries))).expect("valid Zip");
+        verify(&mut zip)
+    }
+
+    #[test]
+    fn test_good_documents() {
+        let listed = manifest(&["content.xml", "styles.xml", "Pictures/", "Pictures/1.png"], false);
+        assert_eq!(verify_entries(&[
+            ("mimetype", MIMETYPE),
+            ("content.xml", b"<office:document-content/>"),
+            ("styles.xml", b"<office:document-styles/>"),
+            ("Pictures/1.png", b"not XML"),
+            ("META-INF/manifest.xml", &listed),
+        ]).map_err(|err| err.to_string()), Ok(()));
+
+        for (path, data) in &[
+            ("good/testfile.odg", &include_bytes!("../../../test_data/good/testfile.odg")[..]),
+            ("good/testfile.odp", include_bytes!("../../../test_data/good/testfile.odp")),
+            ("good/testfile.ods", include_bytes!("../../../test_data/good/testfile.ods")),
+            ("good/testfile.odt", include_bytes!("../../../test_data/good/testfile.odt")),
+            ("good/testfile.ott", include_bytes!("../../../test_data/good/testfile.ott")),
+        ] {
+            let mut zip = ZipArchive::new(Cursor::new(data)).expect("valid Zip");
+            assert_eq!(verify(&mut zip).map_err(|err| err.to_string()), Ok(()), "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_packaging_violations() {
+        let listed = manifest(&["content.xml", "styles.xml"], false);
+        let mimetype = ("mimetype", MIMETYPE);
+        let content = ("content.xml", &b"<office:document-content/>"[..]);
+        let styles = ("styles.xml", &b"<office:document-styles/>"[..]);
+        let manifest_entry = ("META-INF/manifest.xml", &listed[..]);
+        let missing = manifest(&["content.xml", "styles.xml", "meta.xml"], false);
+        for (entries, rule) in &[
+            (vec![content, mimetype, styles, manifest_entry], "must come first"),
+            (vec![("mimetype", b"application/zip"), content, styles, manifest_entry],
+             "OpenDocument MIME type"),
+            (vec![mimetype, content, styles], "META-INF/manifest.xml must exist"),
+            (vec![mimetype, content, styles, ("META-INF/manifest.xml", b"<m>")],
+             "META-INF/manifest.xml must be well-formed"),
+            (vec![mimetype, styles, manifest_entry], "content.xml must exist"),
+            (vec![mimetype, content, manifest_entry], "styles.xml must exist"),
+            (vec![mimetype, ("content.xml", b"<a></b>"), styles, manifest_entry],
+             "content.xml must be well-formed"),
+            (vec![mimetype, content, styles, ("META-INF/manifest.xml", &missing)],
+             "meta.xml listed in META-INF/manifest.xml must exist"),
+        ] {
+            let err = verify_entries(entries).expect_err(rule);
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            let message = err.to_string();
+            assert!(message.starts_with("OpenDocument rule violated: "), "{}", message);
+            assert!(message.contains(rule), "{:?} should mention {:?}", message, rule);
+        }
+
+        // Password-protected documents aren't corrupt, just unreadable without the password
+        let encrypted = manifest(&["content.xml", "styles.xml"], true);
+        assert_eq!(verify_entries(&[
+            mimetype,
+            ("content.xml", b"\x8F\x02binary garbage"),
+            styles,
+            ("META-INF/manifest.xml", &encrypted),
+        ]).map_err(|err| err.kind()), Err(io::ErrorKind::Unsupported));
+    }
+}
@@ -0,0 +1,417 @@
+//! A checker for Opus audio in an Ogg container (RFC 7845)
+//!
+//! (This is layered on the page walker in [`ogg`](super::ogg), so every page's CRC gets checked
+//! too. On top of that, each Opus stream must begin with a valid `OpusHead` packet on a page of
+//! its own, followed by an `OpusTags` packet, and its granule positions must never go backwards or
+//! end before the pre-skip has been played. The audio packets themselves aren't decoded.)
+
+// Standard library imports
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::{self, Read};
+
+// Local Imports
+use super::ogg::{self, Page};
+use super::{corrupt, unsupported};
+
+/// The magic signature at the start of the identification header
+const HEAD_MAGIC: &[u8] = b"OpusHead";
+
+/// The magic signature at the start of the comment header
+const TAGS_MAGIC: &[u8] = b"OpusTags";
+
+/// The length of an identification header without a channel mapping table
+const HEAD_LEN: usize = 19;
+
+/// The largest comment header which will be read into memory to check it
+///
+/// (Cover art can make these fairly large, but anything beyond this is far more likely to be
+/// corruption.)
+const MAX_TAGS_LEN: usize = 64 * 1024 * 1024;
+
+/// The sample rate which granule positions count in, regardless of the input sample rate
+pub const GRANULE_RATE: u64 = 48_000;
+
+/// What was found in an Opus file
+#[derive(Debug, Default)]
+pub struct Summary {
+    /// How many Opus streams there were, counting every link of a chained file
+    pub streams: usize,
+    /// How many samples (at [`GRANULE_RATE`]) there are in all of them, after the pre-skip
+    pub samples: u64,
+}
+
+/// The state of an Opus stream which is in progress
+#[derive(Default)]
+struct Stream {
+    /// How many packets have been completed (so the next is `OpusTags` at 1, and audio from 2)
+    packets: u64,
+    /// Whether the last page ended partway through a packet
+    continuing: bool,
+    /// The `OpusTags` packet, as much as has been read so far
+    tags: Vec<u8>,
+    /// How many samples to discard from the start of the decoded audio
+    pre_skip: u64,
+    /// The most recent granule position
+    granule: Option<u64>,
+}
+
+/// Check the `OpusHead` packet of the stream with `serial`, returning its pre-skip
+fn check_head(head: &[u8], serial: u32) -> io::Result<u64> {
+    let fail = |problem: &str| corrupt(&format!("Opus stream {:08X}'s OpusHead packet {}",
+                                                serial, problem));
+    if head.len() < HEAD_LEN || !head.starts_with(HEAD_MAGIC) {
+        return Err(fail("is invalid"));
+    }
+    let version = head[8];
+    if version >> 4 != 0 {
+        return Err(unsupported(&format!("Opus stream {:08X} is version {}, which isn't supported",
+                                        serial, version)));
+    }
+    let channels = usize::from(head[9]);
+    let pre_skip = u64::from(u16::from_le_bytes([head[10], head[11]]));
+    let family = head[18];
+    if channels == 0 {
+        return Err(fail("says there are no channels"));
+    }
+
+    // Mapping family 0 is mono or stereo without a table, while the others map every output
+    // channel to a decoded one (or to silence, as 255) through a table
+    if family == 0 {
+        return if channels > 2 {
+            Err(fail(&format!("has {} channels, but mapping family 0 only allows 1 or 2",
+                              channels)))
+        } else {
+            Ok(pre_skip)
+        };
+    } else if family == 1 && channels > 8 {
+        return Err(fail(&format!("has {} channels, but mapping family 1 only allows up to 8",
+                                 channels)));
+    }
+    let table = head.get(HEAD_LEN..HEAD_LEN + 2 + channels)
+        .ok_or_else(|| fail("is too short for its channel mapping table"))?;
+    let (streams, coupled) = (usize::from(table[0]), usize::from(table[1]));
+    if streams == 0 || coupled > streams || streams + coupled > 255 {
+        return Err(fail(&format!("has an invalid stream count ({} with {} coupled)", streams,
+                                 coupled)));
+    }
+    match table[2..].iter().find(|&&index| index != 255 && usize::from(index) >= streams + coupled)
+    {
+        Some(index) => Err(fail(&format!("maps a channel to decoded channel {}, but there are \
+                                          only {}", index, streams + coupled))),
+        None => Ok(pre_skip),
+    }
+}
+
+/// Split the first `len` bytes off of `rest`, if it has that many
+fn take<'a>(rest: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if rest.len() < len {
+        return None;
+    }
+    let (field, remainder) = rest.split_at(len);
+    *rest = remainder;
+    Some(field)
+}
+
+/// Split a little-endian 32-bit length off of `rest`, if it has one
+fn length(rest: &mut &[u8]) -> Option<usize> {
+    let bytes = take(rest, 4)?;
+    usize::try_from(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])).ok()
+}
+
+/// Check the `OpusTags` packet of the stream with `serial`
+fn check_tags(tags: &[u8], serial: u32) -> io::Result<()> {
+    if !tags.starts_with(TAGS_MAGIC) {
+        return Err(corrupt(&format!(
+            "Opus stream {:08X}'s second packet isn't an OpusTags packet", serial)));
+    }
+
+    // The vendor string, then each of the comments, all of them length-prefixed
+    let mut rest = &tags[TAGS_MAGIC.len()..];
+    let mut parse = || -> Option<()> {
+        let vendor_len = length(&mut rest)?;
+        take(&mut rest, vendor_len)?;
+        for _ in 0..length(&mut rest)? {
+            let comment_len = length(&mut rest)?;
+            take(&mut rest, comment_len)?;
+        }
+        Some(())
+    };
+    parse().ok_or_else(|| corrupt(&format!(
+        "Opus stream {:08X}'s OpusTags packet is cut short by its lengths", serial)))
+}
+
+/// Check a page's packets against the state of the Opus stream it belongs to
+fn check_page(stream: &mut Stream, page: &Page<'_>) -> io::Result<()> {
+    let serial = page.serial;
+    if page.continued != stream.continuing {
+        return Err(corrupt(&format!(
+            "Page {} of Opus stream {:08X} {} a packet from the previous page", page.sequence,
+            serial, if page.continued { "claims to continue" } else { "doesn't continue" })));
+    }
+
+    let packets = page.packets();
+    let ends = packets.iter().filter(|&&(_, complete)| complete).count();
+    for (index, &(data, complete)) in packets.iter().enumerate() {
+        if stream.packets == 1 {
+            if stream.tags.len() + data.len() > MAX_TAGS_LEN {
+                return Err(unsupported(&format!(
+                    "Opus stream {:08X}'s OpusTags packet is too large to check", serial)));
+            }
+            stream.tags.extend_from_slice(data);
+            if complete {
+                check_tags(&stream.tags, serial)?;
+                stream.tags = Vec::new();
+                // Audio data must begin on a fresh page
+                if index + 1 != packets.len() {
+                    return Err(corrupt(&format!(
+                        "Opus stream {:08X}'s OpusTags packet doesn't finish its page", serial)));
+                }
+            }
+        }
+        stream.packets += u64::from(complete);
+    }
+    stream.continuing = packets.last().map_or(false, |&(_, complete)| !complete);
+
+    // Granule positions are signed, with -1 marking pages where no packet ends
+    if page.granule != u64::MAX && ends > 0 {
+        if page.granule >= 1 << 63 {
+            return Err(corrupt(&format!(
+                "Page {} of Opus stream {:08X} has a negative granule position", page.sequence,
+                serial)));
+        } else if let Some(previous) = stream.granule.filter(|&previous| page.granule < previous) {
+            return Err(corrupt(&format!(
+                "Page {} of Opus stream {:08X} has a granule position ({}) before the previous \
+                 page's ({})", page.sequence, serial, page.granule, previous)));
+        }
+        stream.granule = Some(page.granule);
+    }
+    Ok(())
+}
+
+/// Check a stream's state once its last page has been read, returning how many samples it holds
+fn finish_stream(stream: &Stream, serial: u32) -> io::Result<u64> {
+    if stream.packets < 2 {
+        return Err(corrupt(&format!("Opus stream {:08X} ends before its OpusTags packet",
+                                    serial)));
+    } else if stream.continuing {
+        return Err(corrupt(&format!("Opus stream {:08X} ends partway through a packet",
+                                    serial)));
+    }
+    match stream.granule {
+        Some(granule) if granule < stream.pre_skip => Err(corrupt(&format!(
+            "Opus stream {:08X} ends at granule position {}, before its pre-skip of {} has been \
+             played", serial, granule, stream.pre_skip))),
+        Some(granule) => Ok(granule - stream.pre_skip),
+        None => Ok(0),
+    }
+}
+
+/// Walk the Ogg Opus file read from `input`
+///
+/// Chained files are accepted, as long as each link's Opus stream is valid. Streams of other
+/// codecs which begin after the first page are ignored, beyond the Ogg walker's checks.
+pub fn verify(input: impl Read) -> io::Result<Summary> {
+    let mut streams: HashMap<u32, Stream> = HashMap::new();
+    let (mut summary, mut pages) = (Summary::default(), 0_u64);
+    ogg::walk(input, |page| {
+        pages += 1;
+        if page.first {
+            let packets = page.packets();
+            let is_opus = packets.first().map_or(false, |&(data, _)| data.starts_with(HEAD_MAGIC));
+            if !is_opus {
+                return if pages == 1 {
+                    Err(corrupt("Ogg file's first stream isn't Opus (no OpusHead packet)"))
+                } else {
+                    Ok(())
+                };
+            }
+            let head = match packets[..] {
+                [(head, true)] if page.granule == 0 => head,
+                _ => return Err(corrupt(&format!(
+                    "Opus stream {:08X}'s OpusHead packet isn't alone on a page with a granule \
+                     position of 0", page.serial))),
+            };
+            let pre_skip = check_head(head, page.serial)?;
+            streams.insert(page.serial, Stream { packets: 1, pre_skip, ..Stream::default() });
+        } else if let Some(stream) = streams.get_mut(&page.serial) {
+            check_page(stream, page)?;
+        }
+
+        if page.last {
+            if let Some(stream) = streams.remove(&page.serial) {
+                summary.samples += finish_stream(&stream, page.serial)?;
+                summary.streams += 1;
+            }
+        }
+        Ok(())
+    })?;
+    Ok(summary)
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::super::checksums::OggCrc;
+    use super::*;
+
+    /// An identification header for stereo audio with a pre-skip of 312
+    const HEAD: &[u8] = b"OpusHead\x01\x02\x38\x01\x44\xAC\0\0\0\0\0";
+
+    /// A comment header with a vendor string and one comment
+    const TAGS: &[u8] = b"OpusTags\x04\0\0\0test\x01\0\0\0\x05\0\0\0A=foo";
+
+    /// Build a page holding `packets`, the last of which doesn't end on this page if `partial`
+    fn page(flags: u8, granule: u64, serial: u32, sequence: u32, packets: &[&[u8]],
+            partial: bool) -> Vec<u8> {
+        let mut lacing = Vec::new();
+        for packet in packets {
+            lacing.extend(vec![255; packet.len() / 255]);
+            lacing.push((packet.len() % 255) as u8);
+        }
+        if partial {
+            assert_eq!(lacing.pop(), Some(0), "partial packets must fill their lacing values");
+        }
+        let mut out = b"OggS\0".to_vec();
+        out.push(flags);
+        out.extend(&granule.to_le_bytes());
+        out.extend(&serial.to_le_bytes());
+        out.extend(&sequence.to_le_bytes());
+        out.extend(&[0; 4]);
+        out.push(lacing.len() as u8);
+        out.extend(lacing);
+        out.extend(packets.concat());
+        let mut crc = OggCrc::new();
+        crc.update(&out);
+        out[22..26].copy_from_slice(&crc.finish().to_le_bytes());
+        out
+    }
+
+    /// Build the two header pages of a stream
+    fn headers(serial: u32, head: &[u8]) -> Vec<u8> {
+        [page(0x02, 0, serial, 0, &[head], false), page(0, 0, serial, 1, &[TAGS], false)].concat()
+    }
+
+    /// Check `data`, expecting a failure whose message contains `message`
+    fn check_err(data: &[u8], message: &str) {
+        let err = verify(data).expect_err(message);
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", err);
+        assert!(err.to_string().contains(message), "{}", err);
+    }
+
+    #[test]
+    fn test_fixtures() {
+        for (name, data, streams, samples) in &[
+            ("single", &include_bytes!("../../../test_data/good/testfile.opus")[..], 1, 47_688),
+            ("chained", &include_bytes!("../../../test_data/good/testfile.chained.opus")[..], 2,
+             56_976),
+        ] {
+            let summary = verify(*data).unwrap_or_else(|err| panic!("{}: {}", name, err));
+            assert_eq!(summary.streams, *streams, "{}", name);
+            assert_eq!(summary.samples, *samples, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_bad_fixtures() {
+        let single = include_bytes!("../../../test_data/bad/testfile.opus");
+        let chained = include_bytes!("../../../test_data/bad/testfile.chained.opus");
+        check_err(single, "Opus stream 0EEDFACE's OpusHead packet has 3 channels, but mapping \
+                           family 0 only allows 1 or 2");
+        check_err(chained, "Page 4 of Opus stream 5678EF01 has a granule position (0) before the \
+                            previous page's (19200)");
+
+        // Both of them are fine as far as the Ogg container is concerned
+        ogg::verify(&single[..]).expect("single");
+        ogg::verify(&chained[..]).expect("chained");
+    }
+
+    #[test]
+    fn test_head() {
+        assert_eq!(check_head(HEAD, 1).expect("stereo"), 312);
+        let mut surround = HEAD.to_vec();
+        surround[9] = 6;
+        surround[18] = 1;
+        surround.extend(&[4, 2, 0, 4, 1, 2, 3, 255]);
+        check_head(&surround, 1).expect("5.1 surround");
+
+        let mut future = HEAD.to_vec();
+        future[8] = 0x10;
+        let err = check_head(&future, 1).expect_err("version 16");
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported, "{}", err);
+
+        for (offset, value, message) in &[
+            (9, 0, "says there are no channels"),
+            (9, 9, "has 9 channels, but mapping family 1 only allows up to 8"),
+            (19, 0, "has an invalid stream count (0 with 2 coupled)"),
+            (20, 5, "has an invalid stream count (4 with 5 coupled)"),
+            (23, 6, "maps a channel to decoded channel 6, but there are only 6"),
+        ] {
+            let mut head = surround.clone();
+            head[*offset] = *value;
+            let err = check_head(&head, 1).expect_err(message);
+            assert!(err.to_string().contains(message), "{}", err);
+        }
+        let err = check_head(&surround[..26], 1).expect_err("cut short");
+        assert!(err.to_string().contains("is too short for its channel mapping table"), "{}", err);
+        let err = check_head(&HEAD[..18], 1).expect_err("cut short");
+        assert!(err.to_string().contains("OpusHead packet is invalid"), "{}", err);
+    }
+
+    #[test]
+    fn test_tags() {
+        check_tags(TAGS, 1).expect("valid");
+        check_tags(b"OpusTags\0\0\0\0\0\0\0\0", 1).expect("empty");
+        for tags in &[&TAGS[..TAGS.len() - 1], b"OpusTags\x04\0\0\0test\x02\0\0\0\x01\0\0\0A"] {
+            let err = check_tags(tags, 1).expect_err("cut short");
+            assert!(err.to_string().contains("is cut short by its lengths"), "{}", err);
+        }
+        let err = check_tags(b"OpusHead", 1).expect_err("wrong magic");
+        assert!(err.to_string().contains("second packet isn't an OpusTags packet"), "{}", err);
+    }
+
+    #[test]
+    fn test_streams() {
+        let audio = |flags, granule, sequence| page(flags, granule, 7, sequence, &[b"\xF8"], false);
+        let good = [headers(7, HEAD), audio(0, 960, 2), audio(0x04, 1920, 3)].concat();
+        assert_eq!(verify(&good[..]).expect("valid").samples, 1920 - 312);
+
+        // Pages where no packet ends have a granule position of -1
+        let split = [headers(7, HEAD), page(0, u64::MAX, 7, 2, &[&[0; 255]], true),
+                     page(0x05, 960, 7, 3, &[b""], false)].concat();
+        verify(&split[..]).expect("split packet");
+
+        // Streams of other codecs may be multiplexed in, as long as they don't come first
+        let other = [page(0x06, 0, 9, 0, &[b"\x7FFLAC"], false)].concat();
+        verify(&[headers(7, HEAD), other.clone(), audio(0x04, 960, 2)].concat()[..])
+            .expect("multiplexed");
+        check_err(&[other, headers(7, HEAD), audio(0x04, 960, 2)].concat(),
+                  "Ogg file's first stream isn't Opus (no OpusHead packet)");
+
+        for (data, message) in &[
+            ([page(0x02, 5, 7, 0, &[HEAD], false), audio(0x04, 960, 1)].concat(),
+             "OpusHead packet isn't alone on a page with a granule position of 0"),
+            ([page(0x02, 0, 7, 0, &[HEAD, TAGS], false), audio(0x04, 960, 1)].concat(),
+             "OpusHead packet isn't alone on a page"),
+            ([page(0x02, 0, 7, 0, &[HEAD], false), page(0x04, 0, 7, 1, &[b"\xF8"], false)]
+                .concat(), "Opus stream 00000007's second packet isn't an OpusTags packet"),
+            (page(0x06, 0, 7, 0, &[HEAD], false), "ends before its OpusTags packet"),
+            ([page(0x02, 0, 7, 0, &[HEAD], false), page(0x04, 0, 7, 1, &[TAGS, b"\xF8"], false)]
+                .concat(), "OpusTags packet doesn't finish its page"),
+            ([headers(7, HEAD), page(0x05, 960, 7, 2, &[b"\xF8"], false)].concat(),
+             "Page 2 of Opus stream 00000007 claims to continue a packet from the previous page"),
+            ([headers(7, HEAD), page(0, u64::MAX, 7, 2, &[&[0; 255]], true),
+              audio(0x04, 960, 3)].concat(), "doesn't continue a packet from the previous page"),
+            ([headers(7, HEAD), page(0x04, u64::MAX, 7, 2, &[&[0; 255]], true)].concat(),
+             "Opus stream 00000007 ends partway through a packet"),
+            ([headers(7, HEAD), audio(0x04, 1 << 63, 2)].concat(),
+             "Page 2 of Opus stream 00000007 has a negative granule position"),
+            ([headers(7, HEAD), audio(0x04, 300, 2)].concat(),
+             "Opus stream 00000007 ends at granule position 300, before its pre-skip of 312 has \
+              been played"),
+        ] {
+            check_err(data, message);
+        }
+    }
+}
@@ -0,0 +1,312 @@
+//! A checker for Windows PE (Portable Executable) images, such as `.exe` and `.dll` files
+//!
+//! (PE images only carry a checksum if the linker was asked for one, which is mostly done for
+//! drivers and system DLLs, so most of the time all that can be checked is that the headers are
+//! sane and that the raw data of every section is actually present in the file.)
+
+// Standard library imports
+use std::convert::TryFrom;
+use std::io::{self, Read, Seek, SeekFrom};
+
+// Local Imports
+use super::bytes::{check_bounds, le16, le32, read_at};
+use super::{corrupt, unsupported};
+
+/// The magic number at the start of the DOS header every PE image begins with
+const DOS_MAGIC: &[u8; 2] = b"MZ";
+
+/// The size of the DOS header
+const DOS_HEADER_SIZE: u64 = 64;
+
+/// The offset of `e_lfanew` (the offset of the PE signature) in the DOS header
+const LFANEW_OFFSET: usize = 0x3C;
+
+/// The signature which precedes the COFF file header
+const PE_SIGNATURE: &[u8; 4] = b"PE\0\0";
+
+/// The size of the COFF file header
+const COFF_HEADER_SIZE: u64 = 20;
+
+/// The optional header magic number for 32-bit images
+const PE32_MAGIC: u16 = 0x10B;
+
+/// The optional header magic number for 64-bit images
+const PE32_PLUS_MAGIC: u16 = 0x20B;
+
+/// The optional header magic number for ROM images, which have a different layout
+const ROM_MAGIC: u16 = 0x107;
+
+/// The offset of `CheckSum` in the optional header (which is the same for PE32 and PE32+)
+const CHECKSUM_OFFSET: usize = 64;
+
+/// The size of a section table entry
+const SECTION_HEADER_SIZE: u64 = 40;
+
+/// The index of the certificate table in the data directories, which (unlike the others) gives
+/// a file offset rather than an address in memory
+const CERTIFICATE_TABLE: usize = 4;
+
+/// Compute the PE checksum of the whole file, treating the `CheckSum` field at
+/// `checksum_offset` as zero
+///
+/// (The ones' complement sum of the file as 16-bit little-endian words, plus the file length.)
+fn checksum<R: Read + Seek>(input: &mut R, file_len: u64, checksum_offset: u64)
+        -> io::Result<u32> {
+    input.seek(SeekFrom::Start(0))?;
+    let mut sum = 0_u64;
+    let mut buf = vec![0; 64 * 1024];
+    let mut position = 0;
+    while position < file_len {
+        let chunk = usize::try_from(file_len - position).map_or(buf.len(), |x| x.min(buf.len()));
+        input.read_exact(&mut buf[..chunk])?;
+        for offset in checksum_offset..checksum_offset + 4 {
+            if let Some(byte) = offset.checked_sub(position).and_then(|x| usize::try_from(x).ok())
+                    .and_then(|x| buf[..chunk].get_mut(x)) {
+                *byte = 0;
+            }
+        }
+
+        // Every chunk but the last is a whole number of words, so a trailing odd byte gets padded
+        for word in buf[..chunk].chunks(2) {
+            sum += u64::from(u16::from_le_bytes([word[0], *word.get(1).unwrap_or(&0)]));
+        }
+        position += chunk as u64;
+    }
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    Ok((sum as u32).wrapping_add(file_len as u32))
+}
+
+/// Check the PE image in `input` (which is `file_len` bytes long)
+///
+/// The DOS header, PE signature, COFF file header, and optional header are checked for sanity,
+/// and the section table, the raw data of every section, and the certificate table (if any) are
+/// checked to lie within the file.
+///
+/// If the optional header's `CheckSum` field is set, it's also checked. However, tools which
+/// append data to an image (eg. self-extractors and installers) commonly leave the checksum as it
+/// was, so a mismatch is only treated as corruption if there's nothing after the image. When
+/// `require_checksum` is set, images without a checksum and mismatches which can't be trusted are
+/// reported as unsupported instead of passing, so this can sit before a plain structural check in
+/// a fallback chain.
+pub fn verify<R: Read + Seek>(mut input: R, file_len: u64, require_checksum: bool)
+        -> io::Result<()> {
+    if file_len < DOS_HEADER_SIZE {
+        return Err(corrupt("MZ executable is truncated (too short for a DOS header)"));
+    }
+    let mut dos_header = [0; DOS_HEADER_SIZE as usize];
+    read_at(&mut input, 0, &mut dos_header)?;
+    if !dos_header.starts_with(DOS_MAGIC) {
+        return Err(corrupt("Not an MZ executable (bad magic number)"));
+    }
+
+    // DOS, NE, and LE/LX executables have an MZ header too, but e_lfanew means nothing to DOS
+    let lfanew = u64::from(le32(&dos_header[LFANEW_OFFSET..]));
+    let mut signature = [0; 4];
+    if lfanew + 4 <= file_len {
+        read_at(&mut input, lfanew, &mut signature)?;
+    }
+    if &signature != PE_SIGNATURE {
+        return Err(unsupported("Not a PE image (probably a DOS, NE, or LE executable)"));
+    }
+
+    let coff_offset = lfanew + 4;
+    check_bounds("PE COFF file header", coff_offset, COFF_HEADER_SIZE, file_len)?;
+    let mut coff = [0; COFF_HEADER_SIZE as usize];
+    read_at(&mut input, coff_offset, &mut coff)?;
+    let num_sections = u64::from(le16(&coff[2..]));
+    let optional_size = le16(&coff[16..]);
+
+    let optional_offset = coff_offset + COFF_HEADER_SIZE;
+    check_bounds("PE optional header", optional_offset, u64::from(optional_size), file_len)?;
+    if optional_size < 2 {
+        return Err(corrupt("PE image has no optional header"));
+    }
+    let mut optional = vec![0; usize::from(optional_size)];
+    read_at(&mut input, optional_offset, &mut optional)?;
+    let fixed_size = match le16(&optional) {
+        PE32_MAGIC => 96,
+        PE32_PLUS_MAGIC => 112,
+        ROM_MAGIC => return Err(unsupported("PE ROM images aren't supported")),
+        magic => return Err(corrupt(&format!(
+            "PE optional header has an unknown magic number: {:#06x}", magic))),
+    };
+    if optional.len() < fixed_size {
+        return Err(corrupt(&format!("PE optional header is too small: {} < {} bytes",
+            optional.len(), fixed_size)));
+    }
+    let num_directories = le32(&optional[fixed_size - 4..]) as usize;
+    let directories = &optional[fixed_size..];
+    if num_directories > directories.len() / 8 {
+        return Err(corrupt(&format!(
+            "PE optional header is too small for its {} data directories", num_directories)));
+    }
+
+    let (section_alignment, file_alignment) = (le32(&optional[32..]), le32(&optional[36..]));
+    if !file_alignment.is_power_of_two() {
+        return Err(corrupt(&format!("PE FileAlignment isn't a power of two: {:#x}",
+            file_alignment)));
+    }
+    if section_alignment < file_alignment {
+        return Err(corrupt(&format!("PE SectionAlignment is less than FileAlignment: {:#x} < {:#x}",
+            section_alignment, file_alignment)));
+    }
+    let headers_size = u64::from(le32(&optional[60..]));
+    check_bounds("PE headers (SizeOfHeaders)", 0, headers_size, file_len)?;
+    let mut image_end = headers_size;
+
+    let table_offset = optional_offset + u64::from(optional_size);
+    check_bounds("PE section table", table_offset, num_sections * SECTION_HEADER_SIZE, file_len)?;
+    let mut table = vec![0; usize::try_from(num_sections * SECTION_HEADER_SIZE).expect("small")];
+    read_at(&mut input, table_offset, &mut table)?;
+    for (index, section) in table.chunks(SECTION_HEADER_SIZE as usize).enumerate() {
+        let name = String::from_utf8_lossy(&section[..8]);
+        let name = name.trim_end_matches('\0');
+        let (size, offset) = (u64::from(le32(&section[16..])), u64::from(le32(&section[20..])));
+        if size > 0 {
+            check_bounds(&format!("PE section {} ('{}')", index, name), offset, size, file_len)?;
+            image_end = image_end.max(offset + size);
+        }
+    }
+
+    if num_directories > CERTIFICATE_TABLE {
+        let entry = &directories[CERTIFICATE_TABLE * 8..];
+        let (offset, size) = (u64::from(le32(entry)), u64::from(le32(&entry[4..])));
+        if size > 0 {
+            check_bounds("PE certificate table", offset, size, file_len)?;
+            image_end = image_end.max(offset + size);
+        }
+    }
+
+    let stored = le32(&optional[CHECKSUM_OFFSET..]);
+    if stored == 0 {
+        return if require_checksum {
+            Err(unsupported("PE image has no checksum"))
+        } else {
+            Ok(())
+        };
+    }
+    let checksum_offset = optional_offset + CHECKSUM_OFFSET as u64;
+    let actual = checksum(&mut input, file_len, checksum_offset)?;
+    match (actual == stored, image_end < file_len) {
+        (true, _) => Ok(()),
+        (false, true) if require_checksum => Err(unsupported(
+            "PE checksum doesn't match, but the file has data appended after the image which it \
+             may predate")),
+        (false, true) => Ok(()),
+        (false, false) => Err(corrupt(&format!(
+            "PE checksum doesn't match: stored {:#010x}, calculated {:#010x}", stored, actual))),
+    }
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// A minimal checksummed PE32+ DLL, built by `test_data/sources/make_test_pe.py`
+    const DLL: &[u8] = include_bytes!("../../../test_data/good/testfile.dll");
+
+    /// A PE32 NSIS installer, whose stub's checksum predates the data appended to it
+    const NSIS: &[u8] = include_bytes!("../../../test_data/good/testfile.nsis.exe");
+
+    /// A PE32 RAR self-extractor with no checksum
+    const RAR5_SFX: &[u8] = include_bytes!("../../../test_data/good/testfile.rar5.wincon.sfx.exe");
+
+    /// The offset of the optional header in `DLL`
+    const OPTIONAL: usize = 0x58;
+
+    /// The offset of the section table in `DLL`
+    const SECTIONS: usize = OPTIONAL + 240;
+
+    /// Check `data` the way the handler would
+    fn check(data: &[u8], require_checksum: bool) -> io::Result<()> {
+        verify(Cursor::new(data), data.len() as u64, require_checksum)
+    }
+
+    /// Copy `DLL` with `value` written over the bytes at `offset`, and the checksum cleared so
+    /// it can't be what catches the damage
+    fn patched(offset: usize, value: &[u8]) -> Vec<u8> {
+        let mut data = DLL.to_vec();
+        data[OPTIONAL + CHECKSUM_OFFSET..OPTIONAL + CHECKSUM_OFFSET + 4].copy_from_slice(&[0; 4]);
+        data[offset..offset + value.len()].copy_from_slice(value);
+        data
+    }
+
+    #[test]
+    fn test_good_files() {
+        for (name, data) in &[("DLL", DLL), ("NSIS", NSIS), ("RAR5 SFX", RAR5_SFX)] {
+            assert_eq!(check(data, false).map_err(|err| err.to_string()), Ok(()), "{}", name);
+        }
+        assert_eq!(check(DLL, true).map_err(|err| err.to_string()), Ok(()));
+
+        // Odd lengths get padded, the CheckSum field needn't be aligned, and carries wrap around
+        let unaligned = [1, 0, 2, 0xAA, 0xBB, 0xCC, 0xDD, 0, 3];
+        assert_eq!(checksum(&mut Cursor::new(&unaligned), 9, 3).expect("in memory"), 6 + 9);
+        let carries = [0xFF; 4];
+        assert_eq!(checksum(&mut Cursor::new(&carries), 4, 100).expect("in memory"), 0xFFFF + 4);
+    }
+
+    #[test]
+    fn test_missing_checksums() {
+        for (name, data) in &[("stale checksum", NSIS), ("no checksum", RAR5_SFX)] {
+            let err = check(data, true).expect_err(name);
+            assert_eq!(err.kind(), io::ErrorKind::Unsupported, "{}: {}", name, err);
+        }
+
+        // Appending data is only a problem if the checksum is required
+        let mut appended = DLL.to_vec();
+        appended.extend_from_slice(b"Appended data");
+        assert_eq!(check(&appended, false).map_err(|err| err.to_string()), Ok(()));
+        let err = check(&appended, true).expect_err("appended data");
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported, "{}", err);
+
+        // Executables for DOS and older versions of Windows are something for another handler
+        let dos = include_bytes!("../../../test_data/good/testfile.rar3.dos_sfx.exe");
+        let err = check(dos, false).expect_err("DOS executable");
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported, "{}", err);
+        let err = check(&patched(OPTIONAL, &ROM_MAGIC.to_le_bytes()), false)
+            .expect_err("ROM image");
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported, "{}", err);
+    }
+
+    #[test]
+    fn test_bad_files() {
+        let bad_dll = include_bytes!("../../../test_data/bad/testfile.dll");
+        for require_checksum in &[false, true] {
+            let err = check(bad_dll, *require_checksum).expect_err("flipped bit in .text");
+            assert!(err.to_string().starts_with("PE checksum doesn't match"), "{}", err);
+        }
+
+        let err = check(&patched(SECTIONS + 16, &0x400_u32.to_le_bytes()), false)
+            .expect_err("oversized .text");
+        assert!(err.to_string().starts_with("PE section 0 ('.text') runs past the end"),
+            "{}", err);
+
+        for (name, data) in &[
+            ("empty", &b""[..]),
+            ("truncated DOS header", &DLL[..0x30]),
+            ("truncated COFF header", &DLL[..0x50]),
+            ("truncated optional header", &DLL[..0x100]),
+            ("truncated section table", &DLL[..SECTIONS + 20]),
+            ("truncated .text", &DLL[..DLL.len() - 1]),
+            ("bad magic", &patched(0, b"ZM")),
+            ("no optional header", &patched(0x54, &[0, 0])),
+            ("short optional header", &patched(0x54, &[100, 0])),
+            ("bad optional header magic", &patched(OPTIONAL, &[0x0B, 0x03])),
+            ("too many data directories", &patched(OPTIONAL + 108, &[17, 0, 0, 0])),
+            ("bad FileAlignment", &patched(OPTIONAL + 36, &[0x01, 0x03, 0, 0])),
+            ("FileAlignment > SectionAlignment", &patched(OPTIONAL + 36, &[0, 0x20, 0, 0])),
+            ("bad SizeOfHeaders", &patched(OPTIONAL + 60, &[0, 0, 1, 0])),
+            ("too many sections", &patched(0x46, &[0x20, 0])),
+            ("bad certificate table", &patched(OPTIONAL + 112 + 32, &[0, 4, 0, 0, 8, 0, 0, 0])),
+        ] {
+            let err = check(data, false).expect_err(name);
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}: {}", name, err);
+        }
+    }
+}
@@ -0,0 +1,549 @@
+//! A parser for Apple property lists in their binary (`bplist00`) and XML forms
+//!
+//! (Binary property lists are read into memory, since their objects are found through an offset
+//! table at the end, and every object in that table is decoded and its references followed. XML
+//! property lists are streamed through the well-formedness checker in [`xml`](super::xml) and
+//! each value is checked against the property list DTD's rules, without reading the DTD itself.
+//! Old-style OpenStep text property lists aren't supported.)
+
+// Standard library imports
+use std::char;
+use std::convert::TryFrom;
+use std::io::{self, Read};
+
+// Local Imports
+use super::{corrupt, unsupported, xml};
+
+/// The signature at the start of a binary property list, minus the version
+const BINARY_MAGIC: &[u8] = b"bplist";
+
+/// The length of a binary property list's signature and version
+const HEADER_SIZE: usize = 8;
+
+/// The length of the trailer at the end of a binary property list
+const TRAILER_SIZE: usize = 32;
+
+/// The largest binary property list which will be read into memory to check it
+const MAX_BINARY_SIZE: u64 = 64 * 1024 * 1024;
+
+/// The longest text which is accepted in an `<integer>`, `<real>`, or `<date>` element
+const MAX_SCALAR_TEXT: usize = 256;
+
+/// What was found in a property list
+#[derive(Debug, Default)]
+pub struct Summary {
+    /// Whether it was a binary property list, rather than an XML one
+    pub binary: bool,
+    /// How many objects (for binary) or values (for XML) it holds
+    pub objects: u64,
+}
+
+/// Read a big-endian unsigned integer of up to 8 bytes
+fn uint(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |acc, &byte| (acc << 8) | u64::from(byte))
+}
+
+/// The parts of a binary property list's trailer which are needed to find its objects
+struct Trailer {
+    /// How many bytes each entry in the offset table takes
+    offset_size: usize,
+    /// How many bytes each reference to an object takes
+    ref_size: usize,
+    /// How many objects there are
+    objects: u64,
+    /// The index of the root object
+    top: u64,
+    /// Where the offset table starts, which is also where the objects must end
+    table_start: usize,
+}
+
+/// Read and check the trailer of the binary property list in `data`
+fn trailer(data: &[u8]) -> io::Result<Trailer> {
+    if data.len() < HEADER_SIZE + 2 + TRAILER_SIZE {
+        return Err(corrupt("Binary property list is too short to have a trailer (truncated?)"));
+    }
+    let fields = &data[data.len() - TRAILER_SIZE..];
+    let (offset_size, ref_size) = (usize::from(fields[6]), usize::from(fields[7]));
+    let (objects, top) = (uint(&fields[8..16]), uint(&fields[16..24]));
+    let table_start = uint(&fields[24..32]);
+    if !(1..=8).contains(&offset_size) || !(1..=8).contains(&ref_size) {
+        return Err(corrupt("Binary property list trailer has an invalid integer size"));
+    }
+
+    let trailer_start = (data.len() - TRAILER_SIZE) as u64;
+    let table_end = objects.checked_mul(offset_size as u64)
+        .and_then(|len| len.checked_add(table_start));
+    if objects == 0 || top >= objects {
+        return Err(corrupt("Binary property list trailer has an invalid object count"));
+    } else if table_start <= HEADER_SIZE as u64
+            || table_end.map_or(true, |end| end > trailer_start) {
+        return Err(corrupt("Binary property list trailer places the offset table out of bounds"));
+    } else if ref_size < 8 && objects > 1 << (8 * ref_size) {
+        return Err(corrupt(
+            "Binary property list trailer's object reference size is too small to cover it"));
+    }
+    Ok(Trailer { offset_size, ref_size, objects, top, table_start: table_start as usize })
+}
+
+/// Decode the binary property list object at `offset`, returning the references it holds and
+/// whether it's a string (since a dictionary's keys must be)
+///
+/// `data` is everything before the offset table, where all of the objects must be.
+fn object(data: &[u8], offset: usize, trailer: &Trailer) -> Result<(Vec<u64>, bool), String> {
+    let past_end = || "runs into the offset table".to_owned();
+    let marker = data[offset];
+    let (kind, info) = (marker >> 4, marker & 0xF);
+    let mut pos = offset + 1;
+
+    // The element or byte count of a data, string, or collection object
+    let mut count = || -> Result<usize, String> {
+        if info != 0xF {
+            return Ok(usize::from(info));
+        }
+        let int_marker = *data.get(pos).ok_or_else(past_end)?;
+        if int_marker >> 4 != 0x1 || int_marker & 0xF > 3 {
+            return Err(format!("has an invalid length marker byte 0x{:02X}", int_marker));
+        }
+        let len = 1 << (int_marker & 0xF);
+        let bytes = data.get(pos + 1..pos + 1 + len).ok_or_else(past_end)?;
+        pos += 1 + len;
+        usize::try_from(uint(bytes)).map_err(|_| past_end())
+    };
+    let (body_len, refs, is_string) = match (kind, info) {
+        (0x0, 0x0) | (0x0, 0x8) | (0x0, 0x9) | (0x0, 0xF) => (0, 0, false),
+        (0x1, 0..=4) | (0x2, 2..=3) => (1 << info, 0, false),
+        (0x3, 0x3) => (8, 0, false),
+        (0x4, _) => (count()?, 0, false),
+        (0x5, _) => (count()?, 0, true),
+        (0x6, _) => (count()?.checked_mul(2).ok_or_else(past_end)?, 0, true),
+        (0x8, _) => (usize::from(info) + 1, 0, false),
+        (0xA, _) | (0xC, _) => (0, count()?, false),
+        (0xD, _) => (0, count()?.checked_mul(2).ok_or_else(past_end)?, false),
+        _ => return Err(format!("has an invalid marker byte 0x{:02X}", marker)),
+    };
+    let body_len = refs.checked_mul(trailer.ref_size).ok_or_else(past_end)?.max(body_len);
+    let body = data.get(pos..).and_then(|rest| rest.get(..body_len)).ok_or_else(past_end)?;
+
+    if kind == 0x5 && !body.is_ascii() {
+        return Err("is an ASCII string with non-ASCII characters in it".to_owned());
+    } else if kind == 0x6 && char::decode_utf16(body.chunks(2).map(|x| (u16::from(x[0]) << 8)
+            | u16::from(x[1]))).any(|x| x.is_err()) {
+        return Err("is a UTF-16 string with an unpaired surrogate in it".to_owned());
+    }
+
+    let refs: Vec<u64> = body.chunks(trailer.ref_size).take(refs).map(uint).collect();
+    if let Some(target) = refs.iter().find(|&&target| target >= trailer.objects) {
+        return Err(format!("refers to object {}, but there are only {}", target,
+                           trailer.objects));
+    }
+    Ok((refs, is_string))
+}
+
+/// Check the binary property list in `data`
+fn verify_binary(data: &[u8]) -> io::Result<Summary> {
+    let version = &data[BINARY_MAGIC.len()..HEADER_SIZE];
+    if version != b"00" {
+        return Err(unsupported(&format!("Binary property list version {} isn't supported",
+                                        String::from_utf8_lossy(version))));
+    }
+    let trailer = trailer(data)?;
+    let objects = &data[..trailer.table_start];
+    let table = &data[trailer.table_start..];
+
+    let mut children = Vec::new();
+    let mut strings = Vec::new();
+    let mut dicts = Vec::new();
+    for (index, entry) in table.chunks(trailer.offset_size).take(trailer.objects as usize)
+            .enumerate() {
+        let offset = uint(entry);
+        if offset < HEADER_SIZE as u64 || offset >= trailer.table_start as u64 {
+            return Err(corrupt(&format!(
+                "Binary property list object {} has an offset ({}) outside of the object data",
+                index, offset)));
+        }
+        let (refs, is_string) = object(objects, offset as usize, &trailer).map_err(|problem|
+            corrupt(&format!("Binary property list object {} at byte {} {}", index, offset,
+                             problem)))?;
+        if objects[offset as usize] >> 4 == 0xD {
+            dicts.push((index, offset));
+        }
+        children.push(refs);
+        strings.push(is_string);
+    }
+
+    // Dictionary keys come first, followed by the values in the same order
+    for &(index, offset) in &dicts {
+        let refs = &children[index];
+        if let Some(key) = refs[..refs.len() / 2].iter().find(|&&key| !strings[key as usize]) {
+            return Err(corrupt(&format!(
+                "Binary property list object {} at byte {} is a dictionary with a key which \
+                 isn't a string (object {})", index, offset, key)));
+        }
+    }
+
+    // A container which contains itself would make readers loop forever, so they reject them
+    let (mut state, mut stack) = (vec![0_u8; children.len()], vec![(trailer.top as usize, 0)]);
+    while let Some(&(index, next)) = stack.last() {
+        state[index] = 1;
+        match children[index].get(next) {
+            Some(&child) => {
+                stack.last_mut().expect("just looked at it").1 += 1;
+                match state[child as usize] {
+                    0 => stack.push((child as usize, 0)),
+                    1 => return Err(corrupt(&format!(
+                        "Binary property list object {} contains itself", child))),
+                    _ => {},
+                }
+            },
+            None => {
+                state[index] = 2;
+                stack.pop();
+            },
+        }
+    }
+    Ok(Summary { binary: true, objects: trailer.objects })
+}
+
+/// The kinds of XML property list element which hold text rather than other elements
+enum Leaf {
+    /// `<key>`, `<string>`, or `<true/>`/`<false/>` (which must be empty)
+    Text { empty: bool },
+    /// `<data>`, with how many Base64 characters and how much padding have been seen so far
+    Data { chars: usize, padding: usize },
+    /// `<integer>`, `<real>`, or `<date>`, with the text collected so far
+    Scalar(String),
+}
+
+/// What's open at each level of an XML property list
+enum Frame {
+    /// The root `<plist>` element, and whether it has its value yet
+    Plist(bool),
+    /// An `<array>`
+    Array,
+    /// A `<dict>`, and whether it has a `<key>` which is waiting for its value
+    Dict(bool),
+    /// An element which holds text
+    Leaf(Leaf),
+}
+
+/// Check the text of an `<integer>`, `<real>`, or `<date>` element
+fn check_scalar(name: &str, text: &str) -> Result<(), String> {
+    let text = text.trim();
+    let valid = match name {
+        "integer" => {
+            let (negative, digits) = match text.as_bytes().first() {
+                Some(b'-') => (true, &text[1..]),
+                Some(b'+') => (false, &text[1..]),
+                _ => (false, text),
+            };
+            let value = match digits.get(..2) {
+                Some("0x") | Some("0X") => u64::from_str_radix(&digits[2..], 16),
+                _ => digits.parse::<u64>(),
+            };
+            value.map_or(false, |value| !negative || value <= 1 << 63)
+                && digits.bytes().all(|x| x.is_ascii_alphanumeric())
+        },
+        "real" => text.parse::<f64>().is_ok(),
+        _ => {
+            let bytes = text.as_bytes();
+            let field = |range: std::ops::Range<usize>, max: u32| text.get(range)
+                .filter(|x| x.bytes().all(|x| x.is_ascii_digit()))
+                .and_then(|x| x.parse::<u32>().ok()).map_or(false, |x| x <= max);
+            bytes.len() == 20 && field(0..4, 9999) && bytes[4] == b'-' && field(5..7, 12)
+                && bytes[7] == b'-' && field(8..10, 31) && bytes[10] == b'T' && field(11..13, 23)
+                && bytes[13] == b':' && field(14..16, 59) && bytes[16] == b':'
+                && field(17..19, 60) && bytes[19] == b'Z'
+        },
+    };
+    if valid { Ok(()) } else { Err(format!("<{}> holds {:?}, which isn't valid", name, text)) }
+}
+
+/// Handle the start of an element in an XML property list
+fn start_element(stack: &mut Vec<Frame>, name: &str) -> Result<(), String> {
+    match stack.last_mut() {
+        None if name != "plist" => return Err(format!("The root element is <{}>, not <plist>",
+                                                      name)),
+        None => {
+            stack.push(Frame::Plist(false));
+            return Ok(());
+        },
+        Some(Frame::Leaf(_)) => return Err(format!("<{}> is inside an element which holds text",
+                                                   name)),
+        Some(Frame::Plist(true)) => return Err("<plist> has more than one value".to_owned()),
+        Some(Frame::Plist(full)) => *full = true,
+        Some(Frame::Dict(false)) if name != "key" => {
+            return Err(format!("<dict> has a <{}> where a <key> should be", name));
+        },
+        Some(Frame::Dict(waiting)) if name == "key" && *waiting => {
+            return Err("<dict> has a <key> with no value".to_owned());
+        },
+        Some(Frame::Dict(waiting)) => *waiting = !*waiting,
+        Some(Frame::Array) if name == "key" => {
+            return Err("<key> is only allowed inside a <dict>".to_owned());
+        },
+        Some(Frame::Array) => {},
+    }
+    stack.push(match name {
+        "array" => Frame::Array,
+        "dict" => Frame::Dict(false),
+        "key" | "string" => Frame::Leaf(Leaf::Text { empty: false }),
+        "true" | "false" => Frame::Leaf(Leaf::Text { empty: true }),
+        "data" => Frame::Leaf(Leaf::Data { chars: 0, padding: 0 }),
+        "integer" | "real" | "date" => Frame::Leaf(Leaf::Scalar(String::new())),
+        "plist" => return Err("<plist> is only allowed as the root element".to_owned()),
+        _ => return Err(format!("<{}> isn't a property list element", name)),
+    });
+    Ok(())
+}
+
+/// Handle some text in an XML property list
+fn text(stack: &mut Vec<Frame>, text: &str) -> Result<(), String> {
+    match stack.last_mut() {
+        Some(Frame::Leaf(Leaf::Text { empty: false })) => {},
+        Some(Frame::Leaf(Leaf::Data { chars, padding })) => {
+            for ch in text.chars().filter(|ch| !ch.is_ascii_whitespace()) {
+                if ch == '=' && *padding < 2 {
+                    *padding += 1;
+                } else if (ch.is_ascii_alphanumeric() || ch == '+' || ch == '/') && *padding == 0 {
+                    *chars += 1;
+                } else {
+                    return Err("<data> isn't valid Base64".to_owned());
+                }
+            }
+        },
+        Some(Frame::Leaf(Leaf::Scalar(collected))) => {
+            if collected.len() + text.len() > MAX_SCALAR_TEXT {
+                return Err("A number or date is too long".to_owned());
+            }
+            collected.push_str(text);
+        },
+        _ if text.trim().is_empty() => {},
+        Some(Frame::Leaf(Leaf::Text { .. })) => {
+            return Err("<true> and <false> must be empty".to_owned());
+        },
+        _ => return Err("There's text outside of any value".to_owned()),
+    }
+    Ok(())
+}
+
+/// Handle the end of an element in an XML property list
+fn end_element(stack: &mut Vec<Frame>, name: &str) -> Result<(), String> {
+    match stack.pop() {
+        Some(Frame::Plist(false)) => Err("<plist> is empty".to_owned()),
+        Some(Frame::Dict(true)) => Err("<dict> ends with a <key> which has no value".to_owned()),
+        Some(Frame::Leaf(Leaf::Data { chars, padding })) if (chars + padding) % 4 != 0 => {
+            Err("<data> isn't valid Base64".to_owned())
+        },
+        Some(Frame::Leaf(Leaf::Scalar(collected))) => check_scalar(name, &collected),
+        _ => Ok(()),
+    }
+}
+
+/// Check the XML property list read from `input`
+fn verify_xml(input: impl Read) -> io::Result<Summary> {
+    let mut reader = xml::Reader::new(input);
+    let (mut stack, mut objects) = (Vec::new(), 0);
+    while let Some(event) = reader.next_event()? {
+        match &event {
+            xml::Event::Start(name, _) => {
+                objects += u64::from(name != "plist" && name != "key");
+                start_element(&mut stack, name)
+            },
+            xml::Event::End(name) => end_element(&mut stack, name),
+            xml::Event::Text(content) => text(&mut stack, content),
+        }.map_err(|problem| corrupt(&format!("Property list error at line {}: {}",
+                                     reader.line(), problem)))?;
+    }
+    Ok(Summary { binary: false, objects })
+}
+
+/// Check the property list read from `input`, telling the binary and XML forms apart by their
+/// first few bytes
+pub fn verify(mut input: impl Read) -> io::Result<Summary> {
+    let mut head = [0; 16];
+    let mut len = 0;
+    while len < head.len() {
+        match input.read(&mut head[len..])? {
+            0 => break,
+            count => len += count,
+        }
+    }
+    let head = &head[..len];
+
+    if head.starts_with(BINARY_MAGIC) {
+        let mut data = head.to_vec();
+        input.take(MAX_BINARY_SIZE).read_to_end(&mut data)?;
+        if data.len() as u64 > MAX_BINARY_SIZE {
+            return Err(unsupported("Binary property list is too large to check"));
+        } else if data.len() < HEADER_SIZE {
+            return Err(corrupt("Binary property list is truncated"));
+        }
+        return verify_binary(&data);
+    }
+
+    // Look past any byte order mark (and, for UTF-16, the zero bytes) for the first character
+    let text = head.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(head);
+    let utf16 = text.starts_with(b"\xFE\xFF") || text.starts_with(b"\xFF\xFE");
+    let first = text.iter().skip(if utf16 { 2 } else { 0 })
+        .find(|&&byte| !byte.is_ascii_whitespace() && !(utf16 && byte == 0));
+    match first {
+        Some(b'<') => verify_xml(io::Cursor::new(head.to_vec()).chain(input)),
+        Some(b'{') | Some(b'(') | Some(b'"') | Some(b'/') => {
+            Err(unsupported("Old-style OpenStep property lists aren't supported"))
+        },
+        Some(byte) if byte.is_ascii_alphanumeric() => {
+            Err(unsupported("Old-style OpenStep property lists aren't supported"))
+        },
+        _ => Err(corrupt("Not a property list")),
+    }
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The binary property list fixture
+    const BINARY: &[u8] = include_bytes!("../../../test_data/good/testfile.binary.plist");
+
+    /// Check `data` as an in-memory stream
+    fn check(data: &[u8]) -> io::Result<Summary> {
+        verify(io::Cursor::new(data))
+    }
+
+    /// Check `data`, expecting a failure of `kind` whose message contains `message`
+    fn check_err(data: &[u8], kind: io::ErrorKind, message: &str) {
+        let err = check(data).expect_err(message);
+        assert_eq!(err.kind(), kind, "{}", err);
+        assert!(err.to_string().contains(message), "{}", err);
+    }
+
+    /// Assemble a binary property list with one-byte offsets and references, with the first
+    /// object as its root
+    fn binary(objects: &[&[u8]]) -> Vec<u8> {
+        let mut data = b"bplist00".to_vec();
+        let mut offsets = Vec::new();
+        for object in objects {
+            offsets.push(data.len() as u8);
+            data.extend_from_slice(object);
+        }
+        let table_start = data.len() as u64;
+        data.extend_from_slice(&offsets);
+        data.extend_from_slice(&[0, 0, 0, 0, 0, 0, 1, 1]);
+        data.extend_from_slice(&(objects.len() as u64).to_be_bytes());
+        data.extend_from_slice(&0_u64.to_be_bytes());
+        data.extend_from_slice(&table_start.to_be_bytes());
+        data
+    }
+
+    /// Wrap `body` in the `<plist>` element of an XML property list
+    fn xml(body: &str) -> Vec<u8> {
+        format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<plist version=\"1.0\">{}</plist>\n",
+                body).into_bytes()
+    }
+
+    #[test]
+    fn test_fixtures() {
+        let summary = check(BINARY).expect("binary fixture");
+        assert!(summary.binary);
+        assert_eq!(summary.objects, 27);
+
+        let summary = check(include_bytes!("../../../test_data/good/testfile.plist"))
+            .expect("XML fixture");
+        assert!(!summary.binary);
+        assert_eq!(summary.objects, 15);
+    }
+
+    #[test]
+    fn test_bad_fixtures() {
+        check_err(include_bytes!("../../../test_data/bad/testfile.binary.plist"),
+            io::ErrorKind::InvalidData,
+            "Binary property list object 0 at byte 8 refers to object 129, but there are only 27");
+        check_err(include_bytes!("../../../test_data/bad/testfile.plist"),
+            io::ErrorKind::InvalidData,
+            "Property list error at line 6: <integer> holds \"4r\", which isn't valid");
+
+        for cut in &[7, 20, BINARY.len() - 33, BINARY.len() - 1] {
+            check_err(&BINARY[..*cut], io::ErrorKind::InvalidData, "");
+        }
+    }
+
+    #[test]
+    fn test_sniffing() {
+        check_err(b"bplist15\0\0\0\0", io::ErrorKind::Unsupported,
+                  "Binary property list version 15 isn't supported");
+        check_err(b"{ Answer = 42; }", io::ErrorKind::Unsupported,
+                  "Old-style OpenStep property lists aren't supported");
+        check_err(b"\x89PNG\r\n\x1a\n", io::ErrorKind::InvalidData, "Not a property list");
+        check_err(b"", io::ErrorKind::InvalidData, "Not a property list");
+    }
+
+    #[test]
+    fn test_binary_objects() {
+        // A dictionary mapping "a" to 5, an array of both, and a UTF-16 string
+        let summary = check(&binary(&[b"\xA3\x01\x02\x03", b"\xD1\x04\x05",
+            b"\x63\0a\xD8\x3D\xDC\xBE", b"\x08", b"\x51a", b"\x10\x05"])).expect("valid");
+        assert_eq!(summary.objects, 6);
+
+        check_err(&binary(&[b"\xA1\x00"]), io::ErrorKind::InvalidData,
+                  "Binary property list object 0 contains itself");
+        check_err(&binary(&[b"\xA1\x01", b"\xA1\x00"]), io::ErrorKind::InvalidData,
+                  "contains itself");
+        check_err(&binary(&[b"\xD1\x01\x02", b"\x10\x05", b"\x08"]), io::ErrorKind::InvalidData,
+                  "is a dictionary with a key which isn't a string (object 1)");
+        check_err(&binary(&[b"\x62\0a\xD8\x3D"]), io::ErrorKind::InvalidData,
+                  "is a UTF-16 string with an unpaired surrogate in it");
+        check_err(&binary(&[b"\x52a\xE9"]), io::ErrorKind::InvalidData,
+                  "is an ASCII string with non-ASCII characters in it");
+        check_err(&binary(&[b"\x74"]), io::ErrorKind::InvalidData,
+                  "has an invalid marker byte 0x74");
+        check_err(&binary(&[b"\x4F\x10\x09abc"]), io::ErrorKind::InvalidData,
+                  "runs into the offset table");
+
+        // Two objects which reach the same child are fine, as long as it doesn't loop
+        check(&binary(&[b"\xA2\x01\x01", b"\xA1\x02", b"\x09"])).expect("shared child");
+    }
+
+    #[test]
+    fn test_xml_structure() {
+        check(&xml("<dict><key>a</key><array><true/><string/></array></dict>")).expect("valid");
+        for (body, message) in &[
+            ("", "<plist> is empty"),
+            ("<true/><false/>", "<plist> has more than one value"),
+            ("<dict><key>a</key></dict>", "<dict> ends with a <key> which has no value"),
+            ("<dict><key>a</key><key>b</key></dict>", "<dict> has a <key> with no value"),
+            ("<dict><string>a</string></dict>", "<dict> has a <string> where a <key> should be"),
+            ("<array><key>a</key></array>", "<key> is only allowed inside a <dict>"),
+            ("<array><plist/></array>", "<plist> is only allowed as the root element"),
+            ("<set/>", "<set> isn't a property list element"),
+            ("<string><true/></string>", "<true> is inside an element which holds text"),
+            ("<true>yes</true>", "<true> and <false> must be empty"),
+            ("stray<array/>", "There's text outside of any value"),
+        ] {
+            check_err(&xml(body), io::ErrorKind::InvalidData, message);
+        }
+        check_err(b"<dict/>", io::ErrorKind::InvalidData,
+                  "The root element is <dict>, not <plist>");
+    }
+
+    #[test]
+    fn test_xml_values() {
+        for body in &["<integer>-9223372036854775808</integer>", "<integer> 0x1F </integer>",
+                      "<integer>18446744073709551615</integer>", "<real>-1.5e3</real>",
+                      "<date>2024-02-29T12:34:56Z</date>", "<data>\n  QUJD\n  RA==\n</data>"] {
+            check(&xml(body)).unwrap_or_else(|err| panic!("{}: {}", body, err));
+        }
+        for body in &["<integer>-9223372036854775809</integer>", "<integer>1_000</integer>",
+                      "<integer></integer>", "<real>one</real>",
+                      "<date>2024-13-01T00:00:00Z</date>", "<date>2024-02-29 12:34:56</date>"] {
+            check_err(&xml(body), io::ErrorKind::InvalidData, "which isn't valid");
+        }
+        for body in &["<data>QUJ</data>", "<data>QQ==QQ==</data>", "<data>Q===</data>",
+                      "<data>QU*D</data>"] {
+            check_err(&xml(body), io::ErrorKind::InvalidData, "<data> isn't valid Base64");
+        }
+        check_err(&xml(&format!("<integer>{}</integer>", "1".repeat(300))),
+                  io::ErrorKind::InvalidData, "A number or date is too long");
+    }
+}
@@ -0,0 +1,158 @@
+//! A walker for PNG files which verifies every chunk's CRC without decoding the image
+//!
+//! (Unlike a decoder, this also checks ancillary chunks like `tEXt`, `iTXt`, and `eXIf`, which
+//! decoders are free to skip.)
+
+// Standard library imports
+use std::io::{self, Read};
+
+// Local Imports
+use super::corrupt;
+
+/// The signature at the start of every PNG file
+const SIGNATURE: [u8; 8] = [137, b'P', b'N', b'G', b'\r', b'\n', 26, b'\n'];
+
+/// The largest chunk length the PNG specification allows
+const MAX_CHUNK_LEN: u32 = 0x7FFF_FFFF;
+
+/// The length of the `IHDR` chunk's data
+const IHDR_LEN: u32 = 13;
+
+/// Read exactly enough to fill `buf`, reporting a short read as truncation
+fn read_chunk_part(input: &mut impl Read, buf: &mut [u8]) -> io::Result<()> {
+    input.read_exact(buf).map_err(|err| match err.kind() {
+        io::ErrorKind::UnexpectedEof => corrupt("PNG file is truncated"),
+        _ => err,
+    })
+}
+
+/// Walk every chunk of the PNG file read from `input`
+///
+/// Besides each chunk's CRC, this checks that `IHDR` comes first, that the file ends with `IEND`
+/// (which catches truncation at a chunk boundary), and that nothing follows `IEND`.
+pub fn verify(mut input: impl Read) -> io::Result<()> {
+    let mut signature = [0; SIGNATURE.len()];
+    read_chunk_part(&mut input, &mut signature)?;
+    if signature != SIGNATURE {
+        return Err(corrupt("Not a PNG file"));
+    }
+
+    let mut header = [0; 8];
+    for index in 0_u64.. {
+        read_chunk_part(&mut input, &mut header)?;
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        let kind = &header[4..];
+        let name = String::from_utf8_lossy(kind).into_owned();
+        if len > MAX_CHUNK_LEN || !kind.iter().all(u8::is_ascii_alphabetic) {
+            return Err(corrupt(&format!("Invalid PNG chunk header at chunk {}", index)));
+        }
+        if (index == 0) != (kind == b"IHDR") || (kind == b"IHDR" && len != IHDR_LEN) {
+            return Err(corrupt(&format!("PNG file has a misplaced or invalid {} chunk", name)));
+        }
+
+        let mut crc = crc32fast::Hasher::new();
+        crc.update(kind);
+        let copied = io::copy(&mut (&mut input).take(u64::from(len)), &mut HashWriter(&mut crc))?;
+        if copied != u64::from(len) {
+            return Err(corrupt("PNG file is truncated"));
+        }
+        let mut stored_crc = [0; 4];
+        read_chunk_part(&mut input, &mut stored_crc)?;
+        if crc.finalize() != u32::from_be_bytes(stored_crc) {
+            return Err(corrupt(&format!("CRC mismatch for PNG chunk {} ({})", index, name)));
+        }
+
+        if kind == b"IEND" {
+            break;
+        }
+    }
+
+    if input.read(&mut [0])? != 0 {
+        return Err(corrupt("PNG file has data after the IEND chunk"));
+    }
+    Ok(())
+}
+
+/// Adapter to feed data written by [`io::copy`] into a CRC32 hasher
+struct HashWriter<'a>(&'a mut crc32fast::Hasher);
+
+impl io::Write for HashWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a chunk with a correct CRC
+    fn chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut out = (data.len() as u32).to_be_bytes().to_vec();
+        out.extend(kind);
+        out.extend(data);
+        out.extend(&crc32fast::hash(&out[4..]).to_be_bytes());
+        out
+    }
+
+    /// Build a PNG file out of `chunks`
+    fn png(chunks: &[Vec<u8>]) -> Vec<u8> {
+        [&SIGNATURE[..], &chunks.concat()].concat()
+    }
+
+    /// Verify `input`, returning the error kind on failure
+    fn verify_bytes(input: &[u8]) -> Result<(), io::ErrorKind> {
+        verify(input).map_err(|err| err.kind())
+    }
+
+    #[test]
+    fn test_chunk_structure() {
+        let (ihdr, iend) = (chunk(b"IHDR", &[0; 13]), chunk(b"IEND", b""));
+        let text = chunk(b"tEXt", b"Comment\0Hello");
+        let good = png(&[ihdr.clone(), text.clone(), iend.clone()]);
+        assert_eq!(verify_bytes(&good), Ok(()));
+
+        for offset in &[0, 12, 20, 35, 40, good.len() - 1] {
+            let mut corrupted = good.clone();
+            corrupted[*offset] ^= 0x01;
+            assert_eq!(verify_bytes(&corrupted), Err(io::ErrorKind::InvalidData));
+        }
+        for cut in &[0, 4, 8, 14, 33, 50, good.len() - 12, good.len() - 1] {
+            assert_eq!(verify_bytes(&good[..*cut]), Err(io::ErrorKind::InvalidData));
+        }
+        let mut trailing = good.clone();
+        trailing.push(0);
+        assert_eq!(verify_bytes(&trailing), Err(io::ErrorKind::InvalidData));
+
+        for bad in &[
+            png(&[text.clone(), ihdr.clone(), iend.clone()]),
+            png(&[ihdr.clone(), ihdr.clone(), iend.clone()]),
+            png(&[chunk(b"IHDR", &[0; 12]), iend.clone()]),
+            png(&[ihdr.clone(), chunk(b"tE1t", b""), iend.clone()]),
+            png(&[iend.clone()]),
+        ] {
+            assert_eq!(verify_bytes(bad), Err(io::ErrorKind::InvalidData));
+        }
+        let mut oversized = png(&[ihdr, text, iend]);
+        oversized[33..37].copy_from_slice(&0x8000_0000_u32.to_be_bytes());
+        assert_eq!(verify_bytes(&oversized), Err(io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn test_png_fixtures() {
+        assert_eq!(verify_bytes(include_bytes!("../../../test_data/good/testfile.png")), Ok(()));
+        assert_eq!(verify_bytes(include_bytes!("../../../test_data/good/testfile.text.png")),
+                   Ok(()));
+        assert_eq!(verify_bytes(include_bytes!("../../../test_data/bad/testfile.png")),
+                   Err(io::ErrorKind::InvalidData));
+        assert_eq!(verify_bytes(include_bytes!("../../../test_data/bad/testfile.text.png")),
+                   Err(io::ErrorKind::InvalidData));
+    }
+}
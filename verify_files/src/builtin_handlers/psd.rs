@@ -0,0 +1,419 @@
+//! A checker for Adobe Photoshop documents (PSD) and large documents (PSB)
+//!
+//! (The header's fields are checked against the limits in Adobe's file format specification, then
+//! the four length-prefixed sections which follow it are walked, making sure each one fits in the
+//! file. Along the way, the image resource blocks are parsed and, when the merged image is stored
+//! uncompressed or RLE-compressed, the amount of image data is checked against the dimensions.
+//! Layers and pixels aren't decoded.)
+
+// Standard library imports
+use std::io::{self, Read, Seek, SeekFrom};
+
+// Local Imports
+use super::{corrupt, unsupported};
+
+/// The signature at the start of every Photoshop document
+const SIGNATURE: &[u8] = b"8BPS";
+
+/// The length of the fixed-size header
+const HEADER_LEN: usize = 26;
+
+/// Signatures which image resource blocks may have (Photoshop's own, then ones which other Adobe
+/// applications, like ImageReady and PhotoDeluxe, have written)
+const RESOURCE_SIGNATURES: [&[u8; 4]; 5] = [b"8BIM", b"MeSa", b"AgHg", b"PHUT", b"DCSR"];
+
+/// The most channels a document may have
+const MAX_CHANNELS: u16 = 56;
+
+/// The largest width or height a PSD file may have
+const MAX_PSD_DIMENSION: u32 = 30_000;
+
+/// The largest width or height a PSB file may have
+const MAX_PSB_DIMENSION: u32 = 300_000;
+
+/// The bit depths a channel may have
+const DEPTHS: [u16; 4] = [1, 8, 16, 32];
+
+/// The Bitmap color mode, which is the only one allowed (and required) at a depth of 1
+const MODE_BITMAP: u16 = 0;
+/// The Indexed color mode, which stores its palette in the color mode data section
+const MODE_INDEXED: u16 = 2;
+/// Every color mode defined by the specification
+const MODES: [u16; 8] = [MODE_BITMAP, 1, MODE_INDEXED, 3, 4, 7, 8, 9];
+
+/// The length of an Indexed color image's palette (256 RGB triples, stored as three planes)
+const PALETTE_LEN: u64 = 768;
+
+/// How [`section_len`] describes the end of the file when a top-level section runs past it
+const FILE: &str = "the file (truncated?)";
+
+/// How [`section_len`] describes the end of the layer and mask information section
+const SECTION: &str = "the layer and mask information section";
+
+/// How many RLE byte counts to read from the image data section at a time
+const COUNTS_PER_READ: usize = 4096;
+
+/// What was found in a Photoshop document
+#[derive(Debug, Default)]
+pub struct Summary {
+    /// Whether it's a PSB (large document format) file, rather than a PSD file
+    pub large: bool,
+    /// The width of the image, in pixels
+    pub width: u32,
+    /// The height of the image, in pixels
+    pub height: u32,
+    /// How many channels the image has, including any alpha channels
+    pub channels: u16,
+    /// How many blocks the image resources section holds
+    pub resources: usize,
+}
+
+/// Read a big-endian unsigned integer of up to 8 bytes
+fn uint(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |acc, &byte| (acc << 8) | u64::from(byte))
+}
+
+/// Read exactly `buf.len()` bytes, reporting an early EOF as truncation of `what`
+fn read_record<R: Read>(input: &mut R, buf: &mut [u8], what: &str) -> io::Result<()> {
+    #[allow(clippy::wildcard_enum_match_arm)]
+    input.read_exact(buf).map_err(|err| match err.kind() {
+        io::ErrorKind::UnexpectedEof =>
+            corrupt(&format!("PSD {} runs past the end of the file (truncated?)", what)),
+        _ => err,
+    })
+}
+
+/// Read the length of the section starting at `*pos`, which takes `size` bytes, and make sure
+/// the section ends by `end` (the end of `within`), advancing `*pos` past the length
+fn section_len<R: Read>(input: &mut R, pos: &mut u64, size: usize, end: u64, what: &str,
+                        within: &str) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    read_record(input, &mut buf[..size], what)?;
+    let len = uint(&buf[..size]);
+    *pos += size as u64;
+    if len > end - *pos {
+        return Err(corrupt(&format!("PSD {} is {} bytes long, which runs past the end of {}",
+                                    what, len, within)));
+    }
+    Ok(len)
+}
+
+/// Check the header fields at the start of `header`, returning whether it's a PSB file
+fn check_header(header: &[u8]) -> io::Result<bool> {
+    if &header[..4] != SIGNATURE {
+        return Err(corrupt("Not a Photoshop document (no 8BPS signature)"));
+    }
+    let large = match uint(&header[4..6]) {
+        1 => false,
+        2 => true,
+        version => return Err(unsupported(&format!("PSD version {} isn't supported", version))),
+    };
+    let channels = uint(&header[12..14]) as u16;
+    let (height, width) = (uint(&header[14..18]) as u32, uint(&header[18..22]) as u32);
+    let (depth, mode) = (uint(&header[22..24]) as u16, uint(&header[24..26]) as u16);
+    let max_dimension = if large { MAX_PSB_DIMENSION } else { MAX_PSD_DIMENSION };
+
+    if header[6..12].iter().any(|&byte| byte != 0) {
+        Err(corrupt("PSD header's reserved bytes aren't zero"))
+    } else if !(1..=MAX_CHANNELS).contains(&channels) {
+        Err(corrupt(&format!("PSD header has {} channels, which is outside the allowed 1 to {}",
+                             channels, MAX_CHANNELS)))
+    } else if !(1..=max_dimension).contains(&width) || !(1..=max_dimension).contains(&height) {
+        Err(corrupt(&format!("PSD header's dimensions ({}x{}) are outside the allowed 1 to {}",
+                             width, height, max_dimension)))
+    } else if !DEPTHS.contains(&depth) {
+        Err(corrupt(&format!("PSD header has an invalid bit depth ({})", depth)))
+    } else if !MODES.contains(&mode) {
+        Err(corrupt(&format!("PSD header has an invalid color mode ({})", mode)))
+    } else if (depth == 1) != (mode == MODE_BITMAP) {
+        Err(corrupt(&format!("PSD header's bit depth ({}) doesn't match its color mode ({})",
+                             depth, mode)))
+    } else {
+        Ok(large)
+    }
+}
+
+/// Parse the `len` bytes of image resource blocks starting at `*pos`, returning how many there
+/// were and advancing `*pos` past them
+fn check_resources<R: Read + Seek>(input: &mut R, pos: &mut u64, len: u64) -> io::Result<usize> {
+    let end = *pos + len;
+    let mut count = 0;
+    while *pos < end {
+        let what = format!("image resource block {}", count);
+        // The signature, ID, name length byte, and at least a padding byte for the name
+        let mut head = [0; 8];
+        if end - *pos < head.len() as u64 + 4 {
+            return Err(corrupt(&format!("PSD {} is cut off by the end of its section", what)));
+        }
+        read_record(input, &mut head, &what)?;
+        if !RESOURCE_SIGNATURES.iter().any(|&signature| head[..4] == signature[..]) {
+            return Err(corrupt(&format!("PSD {} has an invalid signature ({:?})", what,
+                                        String::from_utf8_lossy(&head[..4]))));
+        }
+
+        // The name is a Pascal string, padded to make its length (with its length byte) even,
+        // and one byte of that has already been read along with the length byte
+        let name_len = u64::from(head[6]) | 1;
+        let mut size = [0; 4];
+        if end - *pos < 7 + name_len + 4 {
+            return Err(corrupt(&format!("PSD {} is cut off by the end of its section", what)));
+        }
+        input.seek(SeekFrom::Current(name_len as i64 - 1))?;
+        read_record(input, &mut size, &what)?;
+        *pos += 7 + name_len + 4;
+
+        // The data is padded to an even length, but the padding after the last block is left
+        // out by some writers
+        let size = uint(&size);
+        let padded = size + (size & 1);
+        if size > end - *pos {
+            return Err(corrupt(&format!(
+                "PSD {} (ID {}) is {} bytes long, which runs past the end of its section", what,
+                uint(&head[4..6]), size)));
+        }
+        let skip = padded.min(end - *pos);
+        input.seek(SeekFrom::Current(skip as i64))?;
+        *pos += skip;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Check that the layer and mask information section starting at `*pos`, which is `len` bytes
+/// long, has room for the subsections it declares, advancing `*pos` past it
+fn check_layers<R: Read + Seek>(input: &mut R, pos: &mut u64, len: u64, large: bool)
+        -> io::Result<()> {
+    let end = *pos + len;
+    let size = if large { 8 } else { 4 };
+    if len >= size as u64 {
+        let layers_len = section_len(input, pos, size, end, "layer info subsection", SECTION)?;
+        input.seek(SeekFrom::Current(layers_len as i64))?;
+        *pos += layers_len;
+        if end - *pos >= 4 {
+            section_len(input, pos, 4, end, "global layer mask info subsection", SECTION)?;
+        }
+    }
+    // Any additional layer information blocks run to the end of the section
+    input.seek(SeekFrom::Start(end))?;
+    *pos = end;
+    Ok(())
+}
+
+/// Check that the image data at `*pos`, which runs to `file_len`, is long enough to hold the
+/// merged image when it's uncompressed or RLE-compressed
+fn check_image_data<R: Read>(input: &mut R, pos: u64, file_len: u64, header: &[u8], large: bool)
+        -> io::Result<()> {
+    let mut compression = [0; 2];
+    read_record(input, &mut compression, "image data section")?;
+    let available = file_len - pos - 2;
+    let channels = uint(&header[12..14]);
+    let (height, width, depth) = (uint(&header[14..18]), uint(&header[18..22]),
+                                  uint(&header[22..24]));
+    let row_len = (width * depth + 7) / 8;
+    let rows = channels * height;
+
+    let needed = match uint(&compression) {
+        0 => rows * row_len,
+        1 => {
+            // A byte count for each row of each channel, then the PackBits data, where each row
+            // may grow by at most one header byte for every 128 bytes
+            let size = if large { 4 } else { 2 };
+            let max_row_len = row_len + (row_len + 127) / 128;
+            let mut total = rows * size as u64;
+            if total > available {
+                return Err(corrupt(&format!(
+                    "PSD image data is {} bytes short of its RLE byte counts (truncated?)",
+                    total - available)));
+            }
+            let mut buf = vec![0; COUNTS_PER_READ * size];
+            let mut left = rows as usize;
+            while left > 0 {
+                let chunk = left.min(COUNTS_PER_READ);
+                read_record(input, &mut buf[..chunk * size], "image data section")?;
+                for count in buf[..chunk * size].chunks(size).map(uint) {
+                    if count > max_row_len {
+                        return Err(corrupt(&format!(
+                            "PSD image data has an RLE-compressed row of {} bytes, which is \
+                             more than a {}-byte row can take", count, row_len)));
+                    }
+                    total += count;
+                }
+                left -= chunk;
+            }
+            total
+        },
+        2 | 3 => 1,
+        method => return Err(corrupt(&format!(
+            "PSD image data has an invalid compression method ({})", method))),
+    };
+    if needed > available {
+        return Err(corrupt(&format!("PSD image data is {} bytes short (truncated?)",
+                                    needed - available)));
+    }
+    Ok(())
+}
+
+/// Walk the sections of the Photoshop document read from `input`, which is `file_len` bytes long
+pub fn verify<R: Read + Seek>(mut input: R, file_len: u64) -> io::Result<Summary> {
+    let mut header = [0; HEADER_LEN];
+    read_record(&mut input, &mut header, "header")?;
+    let large = check_header(&header)?;
+    let mut pos = HEADER_LEN as u64;
+
+    let color_len = section_len(&mut input, &mut pos, 4, file_len, "color mode data section",
+                                FILE)?;
+    if uint(&header[24..26]) as u16 == MODE_INDEXED && color_len != PALETTE_LEN {
+        return Err(corrupt(&format!(
+            "PSD color mode data section is {} bytes long, but an Indexed color image's palette \
+             takes {}", color_len, PALETTE_LEN)));
+    }
+    input.seek(SeekFrom::Current(color_len as i64))?;
+    pos += color_len;
+
+    let resources_len = section_len(&mut input, &mut pos, 4, file_len,
+                                    "image resources section", FILE)?;
+    let resources = check_resources(&mut input, &mut pos, resources_len)?;
+
+    let size = if large { 8 } else { 4 };
+    let layers_len = section_len(&mut input, &mut pos, size, file_len,
+                                 "layer and mask information section", FILE)?;
+    check_layers(&mut input, &mut pos, layers_len, large)?;
+
+    check_image_data(&mut input, pos, file_len, &header, large)?;
+    Ok(Summary {
+        large,
+        width: uint(&header[18..22]) as u32,
+        height: uint(&header[14..18]) as u32,
+        channels: uint(&header[12..14]) as u16,
+        resources,
+    })
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The PSD fixture, with RLE-compressed image data and two image resources
+    const PSD: &[u8] = include_bytes!("../../../test_data/good/testfile.psd");
+
+    /// Where the PSD fixture's section lengths, second image resource, layer info, and image
+    /// data compression method are
+    const RESOURCES: usize = 30;
+    const SECOND_RESOURCE: usize = 62;
+    const LAYERS: usize = 86;
+    const COMPRESSION: usize = 98;
+
+    /// Walk `data` as an in-memory file
+    fn check(data: &[u8]) -> io::Result<Summary> {
+        verify(io::Cursor::new(data), data.len() as u64)
+    }
+
+    /// Walk `data`, expecting a failure whose message contains `message`
+    fn check_err(data: &[u8], message: &str) {
+        let err = check(data).expect_err(message);
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", err);
+        assert!(err.to_string().contains(message), "{}", err);
+    }
+
+    /// Walk a copy of the PSD fixture with `patch` written at `offset`
+    fn check_patched(offset: usize, patch: &[u8]) -> io::Result<Summary> {
+        let mut data = PSD.to_vec();
+        data[offset..offset + patch.len()].copy_from_slice(patch);
+        check(&data)
+    }
+
+    #[test]
+    fn test_fixtures() {
+        for (name, data, large) in &[
+            ("psd", PSD, false),
+            ("psb", &include_bytes!("../../../test_data/good/testfile.psb")[..], true),
+        ] {
+            let summary = check(data).unwrap_or_else(|err| panic!("{}: {}", name, err));
+            assert_eq!(summary.large, *large, "{}", name);
+            assert_eq!((summary.width, summary.height), (24, 16), "{}", name);
+            assert_eq!(summary.channels, 3, "{}", name);
+            assert_eq!(summary.resources, 2, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_bad_fixtures() {
+        check_err(include_bytes!("../../../test_data/bad/testfile.psd"),
+            "PSD image resource block 1 has an invalid signature (\"8BIN\")");
+        check_err(include_bytes!("../../../test_data/bad/testfile.psb"),
+            "PSD image data is 10 bytes short (truncated?)");
+
+        for cut in &[0, 20, RESOURCES + 10, LAYERS + 2, COMPRESSION + 1, PSD.len() - 1] {
+            check_err(&PSD[..*cut], "(truncated?)");
+        }
+    }
+
+    #[test]
+    fn test_header() {
+        let err = check_patched(4, &[0, 3]).expect_err("version 3");
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported, "{}", err);
+
+        for (offset, patch, message) in &[
+            (0, &b"8BPX"[..], "Not a Photoshop document (no 8BPS signature)"),
+            (8, b"\x01", "PSD header's reserved bytes aren't zero"),
+            (12, b"\0\0", "PSD header has 0 channels, which is outside the allowed 1 to 56"),
+            (12, b"\0\x39", "PSD header has 57 channels"),
+            (18, b"\0\0\x75\x31", "dimensions (30001x16) are outside the allowed 1 to 30000"),
+            (14, b"\0\0\0\0", "dimensions (24x0) are outside"),
+            (22, b"\0\x07", "PSD header has an invalid bit depth (7)"),
+            (24, b"\0\x05", "PSD header has an invalid color mode (5)"),
+            (22, b"\0\x01", "PSD header's bit depth (1) doesn't match its color mode (3)"),
+            (24, b"\0\x00", "PSD header's bit depth (8) doesn't match its color mode (0)"),
+            (24, b"\0\x02", "an Indexed color image's palette takes 768"),
+        ] {
+            let err = check_patched(*offset, patch).expect_err(message);
+            assert!(err.to_string().contains(message), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_resources() {
+        check(PSD).expect("fixture");
+
+        // The padding after the last block's data may be left out
+        let mut data = PSD.to_vec();
+        data.remove(LAYERS - 1);
+        data[RESOURCES + 3] -= 1;
+        check(&data).expect("unpadded last resource");
+
+        let err = check_patched(SECOND_RESOURCE + 12, &[0, 0, 0, 9]).expect_err("size");
+        assert!(err.to_string().contains(
+            "PSD image resource block 1 (ID 1028) is 9 bytes long, which runs past the end of its \
+             section"), "{}", err);
+        let err = check_patched(RESOURCES, &[0, 0, 0, 60]).expect_err("section");
+        assert!(err.to_string().contains("PSD image resource block 2 is cut off by the end of its \
+                                          section"), "{}", err);
+        check_patched(SECOND_RESOURCE, b"MeSa").expect("ImageReady signature");
+    }
+
+    #[test]
+    fn test_layers_and_image_data() {
+        let err = check_patched(LAYERS + 4, &[0, 0, 0, 9]).expect_err("layer info");
+        assert!(err.to_string().contains(
+            "PSD layer info subsection is 9 bytes long, which runs past the end of the layer and \
+             mask information section"), "{}", err);
+        let err = check_patched(LAYERS, &[0, 0, 0x10, 0]).expect_err("layers");
+        assert!(err.to_string().contains("PSD layer and mask information section is 4096 bytes \
+                                          long, which runs past the end of the file"), "{}", err);
+
+        let err = check_patched(COMPRESSION, &[0, 4]).expect_err("method");
+        assert!(err.to_string().contains("PSD image data has an invalid compression method (4)"),
+                "{}", err);
+        let err = check_patched(COMPRESSION + 2, &[0, 200]).expect_err("row");
+        assert!(err.to_string().contains(
+            "PSD image data has an RLE-compressed row of 200 bytes, which is more than a 24-byte \
+             row can take"), "{}", err);
+
+        // ZIP-compressed data is left alone, but uncompressed data must fill the whole image
+        check_patched(COMPRESSION, &[0, 2]).expect("ZIP");
+        let err = check_patched(COMPRESSION, &[0, 0]).expect_err("raw");
+        assert!(err.to_string().contains("bytes short (truncated?)"), "{}", err);
+    }
+}
@@ -0,0 +1,346 @@
+//! A walker for the block structure of RAR archives (both RAR 1.5-4.x and RAR 5.0)
+//!
+//! (Both formats protect every header with a CRC and declare the size of the data which follows
+//! it, so damaged headers and truncation can be caught without decompressing anything. The file
+//! data itself is left to an external tool, since RAR's compression is proprietary.)
+
+// Standard library imports
+use std::convert::TryFrom;
+use std::io::{self, Read, Seek, SeekFrom};
+
+// 3rd-party imports
+use crc32fast::Hasher as Crc32;
+
+// Local Imports
+use super::bytes::{le16, le32};
+use super::{corrupt, unsupported};
+
+/// The signature of a RAR 1.5-4.x archive (which doubles as its "marker block")
+const RAR4_SIGNATURE: &[u8] = b"Rar!\x1A\x07\x00";
+
+/// The signature of a RAR 5.0 archive
+const RAR5_SIGNATURE: &[u8] = b"Rar!\x1A\x07\x01\x00";
+
+/// The signature of a RAR 1.4 archive, which uses a different and undocumented block format
+const RAR14_SIGNATURE: &[u8] = b"RE~^";
+
+/// The largest header RAR 5.0 allows
+const MAX_RAR5_HEADER_SIZE: u64 = 2 * 1024 * 1024;
+
+/// RAR 1.5-4.x block type: Main archive header
+const RAR4_MAIN: u8 = 0x73;
+/// RAR 1.5-4.x block type: File header
+const RAR4_FILE: u8 = 0x74;
+/// RAR 1.5-4.x block type: Service header (comments, recovery records, etc.)
+const RAR4_SERVICE: u8 = 0x7A;
+/// RAR 1.5-4.x block type: End of archive
+const RAR4_END: u8 = 0x7B;
+/// RAR 1.5-4.x main header flag: Has a RAR 2.x-style comment embedded in it
+const RAR4_MAIN_COMMENT: u16 = 0x0002;
+/// RAR 1.5-4.x main header flag: Headers are encrypted
+const RAR4_MAIN_ENCRYPTED: u16 = 0x0080;
+/// RAR 1.5-4.x main header flag: Has an `ENCRYPT_VER` field
+const RAR4_MAIN_ENCRYPT_VERSION: u16 = 0x0200;
+/// RAR 1.5-4.x file header flag: Has a RAR 2.x-style comment embedded in it
+const RAR4_FILE_COMMENT: u16 = 0x0008;
+/// RAR 1.5-4.x file header flag: Has `HIGH_PACK_SIZE` and `HIGH_UNP_SIZE` fields
+const RAR4_FILE_LARGE: u16 = 0x0100;
+/// RAR 1.5-4.x block flag: Followed by `ADD_SIZE` bytes of data
+const RAR4_HAS_DATA: u16 = 0x8000;
+
+/// The size of a RAR 1.5-4.x file header, up to the end of its `HIGH_PACK_SIZE` field
+const RAR4_LARGE_FILE_HEADER_SIZE: usize = 36;
+
+/// RAR 5.0 header type: Main archive header
+const RAR5_MAIN: u64 = 1;
+/// RAR 5.0 header type: File header
+const RAR5_FILE: u64 = 2;
+/// RAR 5.0 header type: Archive encryption header
+const RAR5_ENCRYPTION: u64 = 4;
+/// RAR 5.0 header type: End of archive
+const RAR5_END: u64 = 5;
+/// RAR 5.0 header flag: Has an extra area
+const RAR5_HAS_EXTRA: u64 = 0x0001;
+/// RAR 5.0 header flag: Followed by a data area
+const RAR5_HAS_DATA: u64 = 0x0002;
+
+/// Read a RAR 5.0 variable-length integer from the start of `bytes`, returning it and its length
+fn vint(bytes: &[u8]) -> Option<(u64, usize)> {
+    let mut value = 0;
+    for (i, byte) in bytes.iter().take(10).enumerate() {
+        value |= u64::from(byte & 0x7F) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+/// Shorthand for reporting a header which runs past the end of the file
+fn truncated(offset: u64) -> io::Error {
+    corrupt(&format!("RAR header at byte {} runs past the end of the file (truncated?)", offset))
+}
+
+/// Read exactly `buf.len()` bytes, reporting an early EOF as truncation of the header at `offset`
+fn read_header<R: Read>(input: &mut R, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    #[allow(clippy::wildcard_enum_match_arm)]
+    input.read_exact(buf).map_err(|err| match err.kind() {
+        io::ErrorKind::UnexpectedEof => truncated(offset),
+        _ => err,
+    })
+}
+
+/// Skip the `size` bytes of data following the header at `offset`, which must fit in the file
+fn skip_data<R: Seek>(input: &mut R, pos: u64, size: u64, file_len: u64, offset: u64)
+        -> io::Result<u64> {
+    let end = pos.checked_add(size).filter(|end| *end <= file_len).ok_or_else(|| corrupt(
+        &format!("Data for RAR header at byte {} runs past the end of the file (truncated?)",
+            offset)))?;
+    input.seek(SeekFrom::Start(end))?;
+    Ok(end)
+}
+
+/// Walk the blocks of a RAR 1.5-4.x archive, returning whether any file has data to decompress
+///
+/// (The end-of-archive block is optional in this version of the format, so reaching EOF at a
+/// block boundary is accepted.)
+fn verify_rar4<R: Read + Seek>(mut input: R, file_len: u64) -> io::Result<bool> {
+    let mut pos = RAR4_SIGNATURE.len() as u64;
+    let mut has_data = false;
+    if pos >= file_len {
+        return Err(truncated(pos));
+    }
+    while pos < file_len {
+        let mut header = vec![0; 7];
+        read_header(&mut input, &mut header, pos)?;
+        let (crc, kind, flags, size) =
+            (le16(&header), header[2], le16(&header[3..]), usize::from(le16(&header[5..])));
+        if size < header.len() {
+            return Err(corrupt(&format!("RAR header at byte {} has an invalid size", pos)));
+        }
+        header.resize(size, 0);
+        read_header(&mut input, &mut header[7..], pos)?;
+
+        // Archives with RAR 2.x-style comments only checksum the fields unrar parses
+        let checked = match kind {
+            RAR4_MAIN if flags & RAR4_MAIN_COMMENT != 0 =>
+                if flags & RAR4_MAIN_ENCRYPT_VERSION == 0 { 13 } else { 14 },
+            _ => size,
+        };
+        if header.len() < checked
+                || crc32fast::hash(&header[2..checked]) & 0xFFFF != u32::from(crc) {
+            if kind == RAR4_FILE && flags & RAR4_FILE_COMMENT != 0 {
+                return Err(unsupported("RAR files with RAR 2.x file comments aren't supported"));
+            }
+            return Err(corrupt(&format!("RAR header at byte {} has a bad CRC", pos)));
+        }
+
+        if pos == RAR4_SIGNATURE.len() as u64 && kind != RAR4_MAIN {
+            return Err(corrupt("RAR archive doesn't start with a main archive header"));
+        }
+        if kind == RAR4_MAIN && flags & RAR4_MAIN_ENCRYPTED != 0 {
+            return Err(unsupported("RAR archives with encrypted headers aren't supported"));
+        }
+
+        let mut data_size = 0;
+        if flags & RAR4_HAS_DATA != 0 {
+            data_size = u64::from(le32(header.get(7..11).ok_or_else(|| corrupt(&format!(
+                "RAR header at byte {} is too short for its data size", pos)))?));
+        }
+        if (kind == RAR4_FILE || kind == RAR4_SERVICE) && flags & RAR4_FILE_LARGE != 0 {
+            let high = header.get(32..RAR4_LARGE_FILE_HEADER_SIZE).ok_or_else(|| corrupt(
+                &format!("RAR header at byte {} is too short for its data size", pos)))?;
+            data_size |= u64::from(le32(high)) << 32;
+        }
+        has_data |= kind == RAR4_FILE && data_size > 0;
+
+        let offset = pos;
+        pos = skip_data(&mut input, pos + size as u64, data_size, file_len, offset)?;
+        if kind == RAR4_END {
+            break;
+        }
+    }
+    Ok(has_data)
+}
+
+/// Walk the headers of a RAR 5.0 archive, returning whether any file has data to decompress
+///
+/// (Anything after the end-of-archive header is ignored, since it may be a recovery volume or
+/// other appended data.)
+fn verify_rar5<R: Read + Seek>(mut input: R, file_len: u64) -> io::Result<bool> {
+    let mut pos = RAR5_SIGNATURE.len() as u64;
+    let mut has_data = false;
+    loop {
+        if pos >= file_len {
+            return Err(corrupt("RAR archive has no end of archive header (truncated?)"));
+        }
+
+        // The CRC covers the size field, which is itself variable-length
+        let mut prefix = [0; 4];
+        read_header(&mut input, &mut prefix, pos)?;
+        let mut size_field = Vec::new();
+        while size_field.last().map_or(true, |byte| byte & 0x80 != 0) {
+            if size_field.len() == 3 {
+                return Err(corrupt(&format!("RAR header at byte {} has an invalid size", pos)));
+            }
+            let mut byte = [0];
+            read_header(&mut input, &mut byte, pos)?;
+            size_field.push(byte[0]);
+        }
+        let size = vint(&size_field).map_or(0, |(size, _)| size);
+        if size == 0 || size > MAX_RAR5_HEADER_SIZE {
+            return Err(corrupt(&format!("RAR header at byte {} has an invalid size", pos)));
+        }
+        let mut header = vec![0; usize::try_from(size).expect("bounded by MAX_RAR5_HEADER_SIZE")];
+        read_header(&mut input, &mut header, pos)?;
+
+        let mut crc = Crc32::new();
+        crc.update(&size_field);
+        crc.update(&header);
+        if crc.finalize() != le32(&prefix) {
+            return Err(corrupt(&format!("RAR header at byte {} has a bad CRC", pos)));
+        }
+
+        // Type, flags, and then the extra area and data sizes if the flags say they're present
+        let malformed = || corrupt(&format!("RAR header at byte {} is malformed", pos));
+        let mut fields = &header[..];
+        let mut next_field = || -> io::Result<u64> {
+            let (value, len) = vint(fields).ok_or_else(malformed)?;
+            fields = &fields[len..];
+            Ok(value)
+        };
+        let kind = next_field()?;
+        let flags = next_field()?;
+        let extra_size = if flags & RAR5_HAS_EXTRA != 0 { next_field()? } else { 0 };
+        let data_size = if flags & RAR5_HAS_DATA != 0 { next_field()? } else { 0 };
+        if extra_size > size {
+            return Err(malformed());
+        }
+
+        if pos == RAR5_SIGNATURE.len() as u64 && kind != RAR5_MAIN && kind != RAR5_ENCRYPTION {
+            return Err(corrupt("RAR archive doesn't start with a main archive header"));
+        }
+        if kind == RAR5_ENCRYPTION {
+            return Err(unsupported("RAR archives with encrypted headers aren't supported"));
+        }
+        has_data |= kind == RAR5_FILE && data_size > 0;
+
+        let offset = pos;
+        let header_end = pos + (prefix.len() + size_field.len()) as u64 + size;
+        pos = skip_data(&mut input, header_end, data_size, file_len, offset)?;
+        if kind == RAR5_END {
+            return Ok(has_data);
+        }
+    }
+}
+
+/// Walk the headers of the RAR archive in `input` (which is `file_len` bytes long), verifying
+/// their CRCs and that the data they declare is all present
+///
+/// Since the file data can't be decompressed to check its CRCs, an archive with intact headers is
+/// still reported as unsupported unless it contains nothing but empty files and directories. (A
+/// volume of a multi-volume archive is checked on its own, without looking for the others.)
+pub fn verify<R: Read + Seek>(mut input: R, file_len: u64) -> io::Result<()> {
+    let mut signature = [0; 8];
+    let len = usize::try_from(file_len.min(signature.len() as u64)).expect("at most 8");
+    input.read_exact(&mut signature[..len])?;
+
+    let has_data = if signature.starts_with(RAR5_SIGNATURE) {
+        verify_rar5(input, file_len)?
+    } else if signature.starts_with(RAR4_SIGNATURE) {
+        input.seek(SeekFrom::Start(RAR4_SIGNATURE.len() as u64))?;
+        verify_rar4(input, file_len)?
+    } else if signature.starts_with(RAR14_SIGNATURE) {
+        return Err(unsupported("RAR 1.4 archives aren't supported"));
+    } else {
+        return Err(corrupt("Not a RAR archive (bad signature)"));
+    };
+
+    if has_data {
+        return Err(unsupported(
+            "RAR headers are intact, but file data must be decompressed to check its CRCs"));
+    }
+    Ok(())
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// Well-formed archives in both formats, with solid compression, recovery records, etc.
+    const GOOD: [(&str, &[u8]); 9] = [
+        ("rar3.av", include_bytes!("../../../test_data/good/testfile.rar3.av.rar")),
+        ("rar3.locked", include_bytes!("../../../test_data/good/testfile.rar3.locked.rar")),
+        ("rar3", include_bytes!("../../../test_data/good/testfile.rar3.rar")),
+        ("rar3.rr", include_bytes!("../../../test_data/good/testfile.rar3.rr.rar")),
+        ("rar3.solid", include_bytes!("../../../test_data/good/testfile.rar3.solid.rar")),
+        ("rar5.locked", include_bytes!("../../../test_data/good/testfile.rar5.locked.rar")),
+        ("rar5", include_bytes!("../../../test_data/good/testfile.rar5.rar")),
+        ("rar5.rr", include_bytes!("../../../test_data/good/testfile.rar5.rr.rar")),
+        ("rar5.solid", include_bytes!("../../../test_data/good/testfile.rar5.solid.rar")),
+    ];
+
+    /// Check `data` the way the handler would
+    fn check(data: &[u8]) -> io::Result<()> {
+        verify(Cursor::new(data), data.len() as u64)
+    }
+
+    #[test]
+    fn test_good_archives() {
+        for (name, data) in &GOOD {
+            let err = check(data).expect_err(name);
+            assert_eq!(err.kind(), io::ErrorKind::Unsupported, "{}: {}", name, err);
+            assert!(err.to_string().contains("headers are intact"), "{}: {}", name, err);
+        }
+
+        // An archive with nothing to decompress can be fully checked
+        // (Built by hand: a RAR 5.0 main header and end of archive header)
+        let mut empty = RAR5_SIGNATURE.to_vec();
+        for header in &[&[0x03, 0x01, 0x00, 0x00][..], &[0x03, 0x05, 0x04, 0x00]] {
+            let mut crc = Crc32::new();
+            crc.update(header);
+            empty.extend(&crc.finalize().to_le_bytes());
+            empty.extend(*header);
+        }
+        assert_eq!(check(&empty).map_err(|err| err.to_string()), Ok(()));
+    }
+
+    #[test]
+    fn test_bad_archives() {
+        for (name, data) in &[
+            ("rar3", &include_bytes!("../../../test_data/bad/testfile.rar3.rar")[..]),
+            ("rar3.solid", include_bytes!("../../../test_data/bad/testfile.rar3.solid.rar")),
+            ("rar5", include_bytes!("../../../test_data/bad/testfile.rar5.rar")),
+            ("rar5.rr", include_bytes!("../../../test_data/bad/testfile.rar5.rr.rar")),
+            ("not RAR", b"PK\x03\x04 not a RAR file at all"),
+            ("empty", b""),
+        ] {
+            let err = check(data).expect_err(name);
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}: {}", name, err);
+        }
+    }
+
+    #[test]
+    fn test_truncated() {
+        for (name, data) in &GOOD {
+            // RAR 1.5-4.x archives may legitimately end without an end of archive block, so only
+            // cut into it, except for one archive where the block boundaries are known
+            let lengths: Vec<usize> = if *name == "rar3" {
+                (7..data.len()).filter(|len| *len != 20 && *len != 91).collect()
+            } else if name.starts_with("rar3") {
+                (data.len() - 6..data.len()).collect()
+            } else {
+                (0..data.len()).collect()
+            };
+            for len in lengths {
+                let err = check(&data[..len]).expect_err(name);
+                assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{} cut to {}: {}", name, len,
+                    err);
+            }
+        }
+    }
+}
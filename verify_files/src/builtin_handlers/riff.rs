@@ -0,0 +1,336 @@
+//! A walker for RIFF files (WAV, AVI, WebP, etc.) which checks the chunk structure
+//!
+//! (RIFF has no checksums, but every chunk declares its size, so truncation and damaged chunk
+//! headers show up as chunks which don't fit inside their parents. `WAVE` files also get their
+//! `fmt ` and `data` chunks checked against each other.)
+
+// Standard library imports
+use std::io::{self, Read};
+
+// Local Imports
+use super::{corrupt, unsupported};
+
+/// The largest `fmt ` chunk which will be read into memory
+const MAX_FMT_SIZE: u32 = 64 * 1024;
+
+/// The size of a chunk header
+const CHUNK_HEADER_SIZE: u64 = 8;
+
+/// A `LIST` (or `RIFF`) chunk which is still being walked
+struct Container {
+    /// The offset where its data ends
+    end: u64,
+    /// Whether it's the outermost `RIFF` chunk
+    outermost: bool,
+}
+
+/// What's been seen in a `WAVE` file so far
+#[derive(Default)]
+struct WaveState {
+    /// The block alignment from the `fmt ` chunk, if one has been seen
+    block_align: Option<u16>,
+    /// Whether a `data` chunk has been seen
+    has_data: bool,
+}
+
+/// A reader which keeps track of how far into the file it is
+struct Tracked<R> {
+    /// The file
+    input: R,
+    /// How many bytes have been read so far
+    pos: u64,
+    /// Whether chunk sizes are big-endian (`RIFX` rather than `RIFF`)
+    big_endian: bool,
+}
+
+impl<R: Read> Tracked<R> {
+    /// Fill `buf`, reporting a short read as truncation
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.input.read_exact(buf).map_err(|err| match err.kind() {
+            io::ErrorKind::UnexpectedEof => corrupt("RIFF file is truncated"),
+            _ => err,
+        })?;
+        self.pos += buf.len() as u64;
+        Ok(())
+    }
+
+    /// Read as much of `buf` as possible before the file ends, returning how much was read
+    fn read_up_to(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = (&mut self.input).take(buf.len() as u64).read(buf)?;
+        let len = if len > 0 && len < buf.len() {
+            len + (&mut self.input).take((buf.len() - len) as u64).read(&mut buf[len..])?
+        } else {
+            len
+        };
+        self.pos += len as u64;
+        Ok(len)
+    }
+
+    /// Skip `len` bytes, reporting it as truncation if the file ends first
+    fn skip(&mut self, len: u64) -> io::Result<()> {
+        let skipped = io::copy(&mut (&mut self.input).take(len), &mut io::sink())?;
+        self.pos += skipped;
+        if skipped == len { Ok(()) } else { Err(corrupt("RIFF file is truncated")) }
+    }
+
+    /// Decode a chunk size
+    fn size(&self, bytes: &[u8]) -> u32 {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) }
+    }
+}
+
+/// Check the `fmt ` chunk of a `WAVE` file, returning its block alignment
+fn parse_fmt(fmt: &[u8]) -> io::Result<u16> {
+    if fmt.len() < 16 {
+        return Err(corrupt("WAVE fmt chunk is too short"));
+    }
+    let channels = u16::from_le_bytes([fmt[2], fmt[3]]);
+    let block_align = u16::from_le_bytes([fmt[12], fmt[13]]);
+    if channels == 0 || block_align == 0 {
+        return Err(corrupt("WAVE fmt chunk has zero channels or block alignment"));
+    }
+    Ok(block_align)
+}
+
+/// Check the structure of the RIFF file read from `input`
+///
+/// The outermost chunk's size must match the file's length (give or take a padding byte), every
+/// chunk must fit inside its parent, and `LIST` chunks are walked recursively. AVI files may
+/// continue with OpenDML `AVIX` chunks, and `WAVE` files must have a `fmt ` chunk before a `data`
+/// chunk whose size is a multiple of the block alignment.
+pub fn verify(input: impl Read) -> io::Result<()> {
+    let mut input = Tracked { input, pos: 0, big_endian: false };
+    let mut header = [0; 12];
+    input.read_exact(&mut header)?;
+    input.big_endian = match &header[..4] {
+        b"RIFF" => false,
+        b"RIFX" => true,
+        b"RF64" => return Err(unsupported("RF64 files aren't supported")),
+        _ => return Err(corrupt("Not a RIFF file")),
+    };
+    let form = [header[8], header[9], header[10], header[11]];
+    let mut wave = if &form == b"WAVE" { Some(WaveState::default()) } else { None };
+
+    let mut riff_size = input.size(&header[4..]);
+    let mut containers = vec![Container { end: 8 + u64::from(riff_size), outermost: true }];
+    loop {
+        let (end, outermost) = match containers.last() {
+            Some(container) => (container.end, container.outermost),
+            None => {
+                // The file may only continue with a padding byte or (for AVI) another RIFF chunk
+                let mut next = [0; 12];
+                let mut len = input.read_up_to(&mut next)?;
+                if len > 0 && riff_size % 2 == 1 {
+                    next.copy_within(1..len, 0);
+                    len -= 1;
+                    len += input.read_up_to(&mut next[len..])?;
+                }
+                if len == 0 {
+                    break;
+                }
+                if len < next.len() || &form != b"AVI " || &next[..4] != b"RIFF"
+                        || &next[8..] != b"AVIX" {
+                    return Err(corrupt("RIFF file has data after its RIFF chunk"));
+                }
+                riff_size = input.size(&next[4..]);
+                let end = input.pos - 4 + u64::from(riff_size);
+                containers.push(Container { end, outermost: true });
+                continue;
+            },
+        };
+
+        // Chunks are word-aligned, so odd-sized ones are followed by a padding byte (though the
+        // last one in the file may omit it, whether or not the RIFF size counts it)
+        if input.pos % 2 == 1 && input.pos < end && input.read_up_to(&mut [0])? == 0 {
+            if input.pos + 1 == containers[0].end {
+                break;
+            }
+            return Err(corrupt("RIFF file is truncated"));
+        }
+        if input.pos >= end {
+            containers.pop();
+            continue;
+        }
+
+        let start = input.pos;
+        if end - start < CHUNK_HEADER_SIZE {
+            return Err(corrupt(&format!("RIFF chunk header at byte {} is cut off", start)));
+        }
+        let mut chunk_header = [0; 8];
+        input.read_exact(&mut chunk_header)?;
+        let id = [chunk_header[0], chunk_header[1], chunk_header[2], chunk_header[3]];
+        let name = String::from_utf8_lossy(&id).into_owned();
+        if !id.iter().all(|&byte| (0x20..0x7F).contains(&byte)) {
+            return Err(corrupt(&format!("Invalid RIFF chunk ID at byte {}", start)));
+        }
+        let size = input.size(&chunk_header[4..]);
+        let chunk_end = input.pos + u64::from(size);
+        if chunk_end > end {
+            return Err(corrupt(&format!(
+                "RIFF {} chunk at byte {} runs past the end of its parent", name, start)));
+        }
+
+        let top_level = outermost && containers.len() == 1;
+        match (&id, wave.as_mut()) {
+            (b"LIST", _) if size >= 4 => {
+                input.skip(4)?;
+                containers.push(Container { end: chunk_end, outermost: false });
+            },
+            (b"fmt ", Some(wave)) if top_level => {
+                if size > MAX_FMT_SIZE {
+                    return Err(corrupt("WAVE fmt chunk is implausibly large"));
+                }
+                let mut fmt = vec![0; size as usize];
+                input.read_exact(&mut fmt)?;
+                wave.block_align = Some(parse_fmt(&fmt)?);
+            },
+            (b"data", Some(wave)) if top_level => {
+                match wave.block_align {
+                    None => return Err(corrupt("WAVE data chunk comes before the fmt chunk")),
+                    Some(align) if size % u32::from(align) != 0 => return Err(corrupt(&format!(
+                        "WAVE data chunk size {} isn't a multiple of the block alignment ({})",
+                        size, align))),
+                    Some(_) => {},
+                }
+                wave.has_data = true;
+                input.skip(u64::from(size))?;
+            },
+            _ => input.skip(u64::from(size))?,
+        }
+    }
+
+    match wave {
+        Some(WaveState { block_align: None, .. }) =>
+            Err(corrupt("WAVE file has no fmt chunk")),
+        Some(WaveState { has_data: false, .. }) => Err(corrupt("WAVE file has no data chunk")),
+        _ => Ok(()),
+    }
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a chunk, with padding if needed
+    fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut out = id.to_vec();
+        out.extend(&(data.len() as u32).to_le_bytes());
+        out.extend(data);
+        if data.len() % 2 == 1 {
+            out.push(0);
+        }
+        out
+    }
+
+    /// Build a RIFF file of the given form out of `chunks`
+    fn riff(form: &[u8; 4], chunks: &[Vec<u8>]) -> Vec<u8> {
+        chunk(b"RIFF", &[&form[..], &chunks.concat()].concat())
+    }
+
+    /// A `fmt ` chunk for 16-bit stereo PCM at 44.1kHz
+    fn fmt() -> Vec<u8> {
+        let mut data = vec![1, 0, 2, 0];
+        data.extend(&44100_u32.to_le_bytes());
+        data.extend(&(44100_u32 * 4).to_le_bytes());
+        data.extend(&[4, 0, 16, 0]);
+        chunk(b"fmt ", &data)
+    }
+
+    /// Verify `input`, returning the error kind on failure
+    fn verify_bytes(input: &[u8]) -> Result<(), io::ErrorKind> {
+        verify(input).map_err(|err| err.kind())
+    }
+
+    #[test]
+    fn test_wave() {
+        let info = chunk(b"LIST", &[&b"INFO"[..], &chunk(b"INAM", b"Test\0")].concat());
+        let good = riff(b"WAVE", &[fmt(), chunk(b"data", &[0; 400]), info.clone()]);
+        assert_eq!(verify_bytes(&good), Ok(()));
+        assert_eq!(verify_bytes(&riff(b"WAVE", &[fmt(), chunk(b"fact", &[0; 4]),
+                                                 chunk(b"data", &[0; 40])])), Ok(()));
+
+        for cut in &[4, 12, 20, 100, good.len() - info.len(), good.len() - 2] {
+            assert_eq!(verify_bytes(&good[..*cut]), Err(io::ErrorKind::InvalidData));
+        }
+        let mut trailing = good.clone();
+        trailing.extend(b"junk");
+        assert_eq!(verify_bytes(&trailing), Err(io::ErrorKind::InvalidData));
+
+        for bad in &[
+            riff(b"WAVE", &[chunk(b"data", &[0; 400]), fmt()]),
+            riff(b"WAVE", &[fmt(), chunk(b"data", &[0; 402])]),
+            riff(b"WAVE", &[fmt()]),
+            riff(b"WAVE", &[chunk(b"fmt ", &[0; 16]), chunk(b"data", &[0; 4])]),
+            riff(b"WAVE", &[fmt(), chunk(b"da\0a", &[0; 4])]),
+        ] {
+            assert_eq!(verify_bytes(bad), Err(io::ErrorKind::InvalidData));
+        }
+
+        // A chunk whose declared size runs past the end of its parent
+        let mut overlong = good.clone();
+        overlong[good.len() - info.len() + 4] += 2;
+        assert_eq!(verify_bytes(&overlong), Err(io::ErrorKind::InvalidData));
+
+        let mut rf64 = good;
+        rf64[..4].copy_from_slice(b"RF64");
+        assert_eq!(verify_bytes(&rf64), Err(io::ErrorKind::Unsupported));
+    }
+
+    #[test]
+    fn test_padding() {
+        // An odd-sized final chunk may or may not have its padding byte, with the RIFF size
+        // counting it or not
+        let padded = riff(b"WEBP", &[chunk(b"VP8L", &[0; 5])]);
+        assert_eq!(verify_bytes(&padded), Ok(()));
+        let mut unpadded = padded[..padded.len() - 1].to_vec();
+        assert_eq!(verify_bytes(&unpadded), Ok(()));
+        unpadded[4] -= 1;
+        assert_eq!(verify_bytes(&unpadded), Ok(()));
+        let mut size_unpadded = padded.clone();
+        size_unpadded[4] -= 1;
+        assert_eq!(verify_bytes(&size_unpadded), Ok(()));
+
+        // ...but padding must still be present between chunks
+        let mut missing = chunk(b"VP8X", &[0; 5]);
+        missing.pop();
+        let bad = riff(b"WEBP", &[missing, chunk(b"VP8L", &[0; 6])]);
+        assert_eq!(verify_bytes(&bad), Err(io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn test_avi() {
+        let hdrl = chunk(b"LIST", &[&b"hdrl"[..], &chunk(b"avih", &[0; 56])].concat());
+        let movi = chunk(b"LIST", &[&b"movi"[..], &chunk(b"00dc", &[0; 99]),
+                                    &chunk(b"LIST", &[&b"rec "[..], &chunk(b"01wb", &[1; 8])]
+                                           .concat())].concat());
+        let good = riff(b"AVI ", &[hdrl.clone(), movi.clone(), chunk(b"idx1", &[0; 16])]);
+        assert_eq!(verify_bytes(&good), Ok(()));
+
+        // OpenDML files continue with more RIFF chunks
+        let extended = [good.clone(), riff(b"AVIX", &[movi.clone()])].concat();
+        assert_eq!(verify_bytes(&extended), Ok(()));
+        assert_eq!(verify_bytes(&extended[..extended.len() - 2]), Err(io::ErrorKind::InvalidData));
+        let wrong_form = [good.clone(), riff(b"WAVE", &[movi])].concat();
+        assert_eq!(verify_bytes(&wrong_form), Err(io::ErrorKind::InvalidData));
+
+        let mut nested_overrun = good;
+        nested_overrun[12 + 4] += 2;
+        assert_eq!(verify_bytes(&nested_overrun), Err(io::ErrorKind::InvalidData));
+        assert_eq!(verify_bytes(b"RIFX\0\0\0\x04AVI "), Ok(()));
+    }
+
+    #[test]
+    fn test_riff_fixtures() {
+        assert_eq!(verify_bytes(include_bytes!("../../../test_data/good/testfile.wav")), Ok(()));
+        assert_eq!(verify_bytes(include_bytes!("../../../test_data/good/testfile.info.wav")),
+                   Ok(()));
+        assert_eq!(verify_bytes(include_bytes!("../../../test_data/good/testfile.webp")), Ok(()));
+        assert_eq!(verify_bytes(include_bytes!("../../../test_data/bad/testfile.wav")),
+                   Err(io::ErrorKind::InvalidData));
+        assert_eq!(verify_bytes(include_bytes!("../../../test_data/bad/testfile.webp")),
+                   Err(io::ErrorKind::InvalidData));
+    }
+}
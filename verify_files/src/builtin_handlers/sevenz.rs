@@ -0,0 +1,735 @@
+//! A minimal `.7z` reader, following `DOC/7zFormat.txt` from the 7-Zip source
+//!
+//! (Just enough to verify the CRCs of archives which use the Copy, LZMA, LZMA2, or Deflate codecs.
+//! Anything else, including encryption, is reported as `ErrorKind::Unsupported` so an external
+//! tool can take over.)
+
+// Standard library imports
+use std::convert::TryFrom;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+
+// 3rd-party crate imports
+use crc32fast::Hasher as Crc32;
+use flate2::bufread::DeflateDecoder;
+
+// Local Imports
+use super::lzma::{lzma2_dict_size, Lzma2Reader, LzmaReader};
+use super::{corrupt, eof_is_corrupt, unsupported};
+
+/// The magic number at the start of every `.7z` file
+const SIGNATURE: [u8; 6] = [b'7', b'z', 0xBC, 0xAF, 0x27, 0x1C];
+
+/// The size of the fixed header at the start of the file, which points to the real one at the end
+const START_HEADER_SIZE: u64 = 32;
+
+/// The property IDs which make up the header's structure (named as in `7zFormat.txt`)
+mod prop {
+    /// `kEnd`: Terminates a structure or list of properties
+    pub const END: u8 = 0x00;
+    /// `kHeader`: An unencoded header
+    pub const HEADER: u8 = 0x01;
+    /// `kArchiveProperties`: Properties of the archive as a whole
+    pub const ARCHIVE_PROPERTIES: u8 = 0x02;
+    /// `kAdditionalStreamsInfo`: Streams which hold parts of the header
+    pub const ADDITIONAL_STREAMS_INFO: u8 = 0x03;
+    /// `kMainStreamsInfo`: The streams which hold the files
+    pub const MAIN_STREAMS_INFO: u8 = 0x04;
+    /// `kFilesInfo`: The list of files
+    pub const FILES_INFO: u8 = 0x05;
+    /// `kPackInfo`: Where the packed streams are
+    pub const PACK_INFO: u8 = 0x06;
+    /// `kUnPackInfo`: How the packed streams are decoded
+    pub const UNPACK_INFO: u8 = 0x07;
+    /// `kSubStreamsInfo`: How each folder's output divides into files
+    pub const SUBSTREAMS_INFO: u8 = 0x08;
+    /// `kSize`: A list of sizes
+    pub const SIZE: u8 = 0x09;
+    /// `kCRC`: A list of CRCs
+    pub const CRC: u8 = 0x0A;
+    /// `kFolder`: The list of folders
+    pub const FOLDER: u8 = 0x0B;
+    /// `kCodersUnPackSize`: The size of every coder's output
+    pub const CODERS_UNPACK_SIZE: u8 = 0x0C;
+    /// `kNumUnPackStream`: How many files are in each folder
+    pub const NUM_UNPACK_STREAM: u8 = 0x0D;
+    /// `kEmptyStream`: Which files have no data
+    pub const EMPTY_STREAM: u8 = 0x0E;
+    /// `kName`: The file names
+    pub const NAME: u8 = 0x11;
+    /// `kEncodedHeader`: A header which must be decoded like a folder
+    pub const ENCODED_HEADER: u8 = 0x17;
+}
+
+/// The codec IDs which can be decoded, or which get a more specific error message
+mod codec {
+    /// Stored without compression
+    pub const COPY: &[u8] = &[0x00];
+    /// LZMA, with the properties from an `.lzma` header
+    pub const LZMA: &[u8] = &[0x03, 0x01, 0x01];
+    /// LZMA2, with its dictionary size byte
+    pub const LZMA2: &[u8] = &[0x21];
+    /// Deflate, as in Zip and GZip
+    pub const DEFLATE: &[u8] = &[0x04, 0x01, 0x08];
+    /// AES-256 with a SHA-256 key derivation function
+    pub const AES: &[u8] = &[0x06, 0xF1, 0x07, 0x01];
+}
+
+/// Read a little-endian integer from a slice of up to eight bytes
+fn le_uint(bytes: &[u8]) -> u64 {
+    bytes.iter().rev().fold(0, |acc, &byte| (acc << 8) | u64::from(byte))
+}
+
+/// Calculate the CRC32 of a slice
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finalize()
+}
+
+/// A position within a header
+struct Cursor<'a> {
+    /// The header
+    data: &'a [u8],
+    /// The offset of the next unread byte
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    /// Start reading from the beginning of `data`
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    /// Read `len` bytes
+    fn bytes(&mut self, len: u64) -> io::Result<&'a [u8]> {
+        let end = usize::try_from(len).ok().and_then(|len| self.pos.checked_add(len));
+        let bytes = end.and_then(|end| self.data.get(self.pos..end))
+            .ok_or_else(|| corrupt("7z header is truncated"))?;
+        self.pos += bytes.len();
+        Ok(bytes)
+    }
+
+    /// Read a single byte
+    fn byte(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    /// Read a byte which must match `expected`
+    fn expect(&mut self, expected: u8) -> io::Result<()> {
+        if self.byte()? == expected {
+            Ok(())
+        } else {
+            Err(corrupt("Unexpected property in 7z header"))
+        }
+    }
+
+    /// Read a variable-length `NUMBER`, whose first byte says how many more bytes follow
+    fn number(&mut self) -> io::Result<u64> {
+        let first = self.byte()?;
+        let extra = first.leading_ones();
+        let low = le_uint(self.bytes(u64::from(extra))?);
+        Ok(if extra < 8 { low | (u64::from(first & (0x7F >> extra)) << (8 * extra)) } else { low })
+    }
+
+    /// Read a `NUMBER` which counts items that each take at least one more byte of header
+    ///
+    /// (So that a corrupted count can't trigger an enormous allocation.)
+    fn count(&mut self) -> io::Result<usize> {
+        let count = self.number()?;
+        usize::try_from(count).ok().filter(|&count| count <= self.data.len() - self.pos)
+            .ok_or_else(|| corrupt("Implausible item count in 7z header"))
+    }
+
+    /// Read a bit field of `count` flags, most significant bit first
+    fn bits(&mut self, count: usize) -> io::Result<Vec<bool>> {
+        let bytes = self.bytes((count as u64 + 7) / 8)?;
+        Ok((0..count).map(|i| bytes[i / 8] & (0x80 >> (i % 8)) != 0).collect())
+    }
+
+    /// Read `count` CRCs, some of which may be left undefined
+    fn digests(&mut self, count: usize) -> io::Result<Vec<Option<u32>>> {
+        let defined = if self.byte()? == 0 { self.bits(count)? } else { vec![true; count] };
+        defined.into_iter().map(|defined| {
+            Ok(if defined { Some(le_uint(self.bytes(4)?) as u32) } else { None })
+        }).collect()
+    }
+}
+
+/// One step in the chain of decoders which unpacks a folder
+struct Coder {
+    /// The codec's ID
+    id: Vec<u8>,
+    /// The codec's properties (eg. the dictionary size)
+    props: Vec<u8>,
+}
+
+/// A set of coders which unpack one or more packed streams into one or more files, concatenated
+struct Folder {
+    /// The coders, in the order they were declared in
+    coders: Vec<Coder>,
+    /// The order the coders must be applied in, or `None` if they're not a simple chain (eg. BCJ2)
+    chain: Option<Vec<usize>>,
+    /// Which output stream is the folder's final output
+    main_out: usize,
+    /// How many packed streams the folder reads
+    packed_streams: usize,
+    /// How many output streams the coders have in total
+    out_streams: usize,
+    /// The size of each output stream
+    unpack_sizes: Vec<u64>,
+    /// The CRC of the folder's final output, if stored
+    crc: Option<u32>,
+    /// The size and CRC of each file within the folder's output
+    substreams: Vec<(u64, Option<u32>)>,
+}
+
+impl Folder {
+    /// Read a folder's coders and how they connect to each other
+    fn read(cur: &mut Cursor<'_>) -> io::Result<Self> {
+        let count = cur.count()?;
+        let (mut coders, mut in_streams, mut out_streams) = (Vec::new(), 0_u64, 0_u64);
+        let mut simple = true;
+        for _ in 0..count {
+            let flags = cur.byte()?;
+            if flags & 0xC0 != 0 {
+                return Err(unsupported("7z folder uses alternative coder methods"));
+            }
+            let id = cur.bytes(u64::from(flags & 0x0F))?.to_vec();
+            let (ins, outs) = if flags & 0x10 == 0 {
+                (1, 1)
+            } else {
+                (cur.number()?, cur.number()?)
+            };
+            simple &= ins == 1 && outs == 1;
+            in_streams = in_streams.saturating_add(ins);
+            out_streams = out_streams.saturating_add(outs);
+            let props = if flags & 0x20 == 0 {
+                Vec::new()
+            } else {
+                let len = cur.number()?;
+                cur.bytes(len)?.to_vec()
+            };
+            coders.push(Coder { id, props });
+        }
+
+        let bind_pair_count = out_streams.checked_sub(1)
+            .ok_or_else(|| corrupt("7z folder has no coders"))?;
+        let bind_pairs = (0..bind_pair_count).map(|_| Ok((cur.number()?, cur.number()?)))
+            .collect::<io::Result<Vec<_>>>()?;
+        let packed_count = in_streams.checked_sub(bind_pair_count)
+            .and_then(|count| usize::try_from(count).ok())
+            .ok_or_else(|| corrupt("7z folder has more bind pairs than inputs"))?;
+        let packed = if packed_count == 1 {
+            (0..in_streams).find(|&idx| !bind_pairs.iter().any(|&(input, _)| input == idx))
+                .ok_or_else(|| corrupt("7z folder doesn't read any packed stream"))?
+        } else {
+            for _ in 0..packed_count {
+                cur.number()?;
+            }
+            0
+        };
+
+        let main_out = (0..out_streams)
+            .find(|&idx| !bind_pairs.iter().any(|&(_, output)| output == idx))
+            .and_then(|idx| usize::try_from(idx).ok())
+            .ok_or_else(|| corrupt("7z folder has no final output"))?;
+
+        Ok(Self {
+            chain: if simple && packed_count == 1 {
+                Some(Self::chain(&bind_pairs, packed, coders.len())?)
+            } else {
+                None
+            },
+            main_out,
+            out_streams: usize::try_from(out_streams)
+                .map_err(|_| corrupt("Implausible stream count in 7z header"))?,
+            coders,
+            packed_streams: packed_count,
+            unpack_sizes: Vec::new(),
+            crc: None,
+            substreams: Vec::new(),
+        })
+    }
+
+    /// Work out the order to apply a chain of single-input, single-output coders in, starting
+    /// from the one which reads `packed`
+    ///
+    /// (For such coders, the stream indexes in the bind pairs are also coder indexes.)
+    fn chain(bind_pairs: &[(u64, u64)], packed: u64, count: usize) -> io::Result<Vec<usize>> {
+        let mut chain = vec![packed];
+        while let Some(&(next, _)) = bind_pairs.iter()
+                .find(|&&(_, output)| Some(&output) == chain.last()) {
+            if chain.contains(&next) {
+                return Err(corrupt("7z folder's coders form a loop"));
+            }
+            chain.push(next);
+        }
+        if chain.len() != bind_pairs.len() + 1 {
+            return Err(corrupt("7z folder's coders aren't all connected"));
+        }
+        chain.into_iter().map(|idx| usize::try_from(idx).ok().filter(|&idx| idx < count)
+            .ok_or_else(|| corrupt("Invalid coder index in 7z folder"))).collect()
+    }
+
+    /// The size of the folder's final output
+    fn unpack_size(&self) -> u64 {
+        self.unpack_sizes.get(self.main_out).copied().unwrap_or(0)
+    }
+
+    /// Wrap `packed` in the decoders needed to produce the folder's output
+    fn reader<'a>(&self, packed: impl Read + 'a) -> io::Result<Box<dyn Read + 'a>> {
+        let chain = self.chain.as_ref().ok_or_else(|| {
+            unsupported("7z folder uses coders with multiple inputs or outputs (eg. BCJ2)")
+        })?;
+        let mut reader: Box<dyn Read + 'a> = Box::new(packed);
+        for &idx in chain {
+            let (coder, size) = (&self.coders[idx], self.unpack_sizes[idx]);
+            reader = match &coder.id[..] {
+                codec::COPY => reader,
+                codec::LZMA => Box::new(LzmaReader::new(reader, &coder.props, size)?),
+                codec::LZMA2 => {
+                    let props = coder.props.first()
+                        .ok_or_else(|| corrupt("Missing LZMA2 properties"))?;
+                    Box::new(Lzma2Reader::new(reader, lzma2_dict_size(*props)?))
+                },
+                codec::DEFLATE => Box::new(Inflate(DeflateDecoder::new(BufReader::new(reader)))),
+                codec::AES => return Err(unsupported("7z archive is encrypted")),
+                id => return Err(unsupported(&format!("7z archive uses an unsupported codec ({})",
+                    id.iter().map(|byte| format!("{:02X}", byte)).collect::<String>()))),
+            };
+            reader = Box::new(reader.take(size));
+        }
+        Ok(reader)
+    }
+}
+
+/// Adapter to report corrupt Deflate data the same way as the other codecs do
+///
+/// (`flate2` uses `ErrorKind::InvalidInput`.)
+struct Inflate<R: Read>(DeflateDecoder<BufReader<R>>);
+
+impl<R: Read> Read for Inflate<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        #[allow(clippy::wildcard_enum_match_arm)]
+        self.0.read(buf).map_err(|err| match err.kind() {
+            io::ErrorKind::InvalidInput => corrupt(&err.to_string()),
+            _ => err,
+        })
+    }
+}
+
+/// The location and layout of a set of packed streams
+#[derive(Default)]
+struct StreamsInfo {
+    /// Where the first packed stream starts, relative to the end of the start header
+    pack_pos: u64,
+    /// The size of each packed stream
+    pack_sizes: Vec<u64>,
+    /// How the packed streams unpack into files
+    folders: Vec<Folder>,
+}
+
+impl StreamsInfo {
+    /// Read a `StreamsInfo` structure, up to and including its `kEnd`
+    fn read(cur: &mut Cursor<'_>) -> io::Result<Self> {
+        let mut info = Self::default();
+        let mut id = cur.byte()?;
+        if id == prop::PACK_INFO {
+            info.pack_pos = cur.number()?;
+            let count = cur.count()?;
+            cur.expect(prop::SIZE)?;
+            info.pack_sizes = (0..count).map(|_| cur.number()).collect::<io::Result<_>>()?;
+            id = cur.byte()?;
+            if id == prop::CRC {
+                // The packed streams' CRCs would be redundant with those of the unpacked data
+                cur.digests(count)?;
+                id = cur.byte()?;
+            }
+            if id != prop::END {
+                return Err(corrupt("Unexpected property in 7z header"));
+            }
+            id = cur.byte()?;
+        }
+
+        if id == prop::UNPACK_INFO {
+            cur.expect(prop::FOLDER)?;
+            let count = cur.count()?;
+            if cur.byte()? != 0 {
+                return Err(unsupported("7z header stores its folders elsewhere"));
+            }
+            info.folders = (0..count).map(|_| Folder::read(cur)).collect::<io::Result<_>>()?;
+            cur.expect(prop::CODERS_UNPACK_SIZE)?;
+            for folder in &mut info.folders {
+                folder.unpack_sizes = (0..folder.out_streams).map(|_| cur.number())
+                    .collect::<io::Result<_>>()?;
+            }
+            id = cur.byte()?;
+            if id == prop::CRC {
+                for (folder, crc) in info.folders.iter_mut().zip(cur.digests(count)?) {
+                    folder.crc = crc;
+                }
+                id = cur.byte()?;
+            }
+            if id != prop::END {
+                return Err(corrupt("Unexpected property in 7z header"));
+            }
+            id = cur.byte()?;
+        }
+
+        // Without a SubStreamsInfo, each folder holds one file
+        let mut counts = vec![1; info.folders.len()];
+        let has_substreams = id == prop::SUBSTREAMS_INFO;
+        if has_substreams {
+            id = cur.byte()?;
+            if id == prop::NUM_UNPACK_STREAM {
+                for count in &mut counts {
+                    *count = cur.count()?;
+                }
+                id = cur.byte()?;
+            }
+        }
+        let has_sizes = has_substreams && id == prop::SIZE;
+        for (folder, &count) in info.folders.iter_mut().zip(&counts) {
+            let mut sizes = Vec::new();
+            if has_sizes {
+                for _ in 1..count {
+                    sizes.push(cur.number()?);
+                }
+            } else if count > 1 {
+                return Err(corrupt("7z header is missing the sizes of files in a folder"));
+            }
+            if count > 0 {
+                let listed = sizes.iter().try_fold(0_u64, |acc, &size| acc.checked_add(size));
+                sizes.push(listed.and_then(|listed| folder.unpack_size().checked_sub(listed))
+                    .ok_or_else(|| corrupt("7z folder's files are larger than the folder"))?);
+            }
+            let inherited = if count == 1 { folder.crc } else { None };
+            folder.substreams = sizes.into_iter().map(|size| (size, inherited)).collect();
+        }
+        if has_sizes {
+            id = cur.byte()?;
+        }
+
+        if has_substreams && id == prop::CRC {
+            let unknown = info.folders.iter()
+                .filter(|folder| folder.substreams.len() != 1 || folder.crc.is_none())
+                .map(|folder| folder.substreams.len())
+                .sum();
+            let mut digests = cur.digests(unknown)?.into_iter();
+            for folder in &mut info.folders {
+                if folder.substreams.len() != 1 || folder.crc.is_none() {
+                    for substream in &mut folder.substreams {
+                        substream.1 = digests.next().flatten();
+                    }
+                }
+            }
+            id = cur.byte()?;
+        }
+        if has_substreams {
+            if id != prop::END {
+                return Err(corrupt("Unexpected property in 7z header"));
+            }
+            id = cur.byte()?;
+        }
+        if id != prop::END {
+            return Err(corrupt("Unexpected property in 7z header"));
+        }
+        Ok(info)
+    }
+}
+
+/// Read the `FilesInfo` structure, returning the names of the files which have data
+fn files_info(cur: &mut Cursor<'_>) -> io::Result<Vec<String>> {
+    let count = cur.count()?;
+    let (mut has_data, mut names) = (vec![true; count], None);
+    loop {
+        let id = cur.byte()?;
+        if id == prop::END {
+            break;
+        }
+        let len = cur.number()?;
+        let mut data = Cursor::new(cur.bytes(len)?);
+        match id {
+            prop::EMPTY_STREAM => {
+                has_data = data.bits(count)?.into_iter().map(|empty| !empty).collect();
+            },
+            prop::NAME => {
+                if data.byte()? != 0 {
+                    return Err(unsupported("7z header stores its file names elsewhere"));
+                }
+                let utf16: Vec<u16> = data.data[1..].chunks_exact(2)
+                    .map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect();
+                names = Some(utf16.split(|&unit| unit == 0).take(count)
+                    .map(String::from_utf16_lossy).collect::<Vec<_>>());
+            },
+            _ => {},
+        }
+    }
+
+    let names = names.unwrap_or_default();
+    Ok(has_data.into_iter().enumerate().filter(|&(_, has_data)| has_data)
+        .map(|(idx, _)| names.get(idx).cloned().unwrap_or_else(|| format!("file #{}", idx + 1)))
+        .collect())
+}
+
+/// Read the CRC32 of the next `len` bytes of `reader`
+fn crc32_of(reader: &mut dyn Read, len: u64) -> io::Result<u32> {
+    let (mut crc, mut buffer, mut remaining) = (Crc32::new(), vec![0; 0x10000], len);
+    while remaining > 0 {
+        let chunk = usize::try_from(remaining).unwrap_or(usize::MAX).min(buffer.len());
+        let read = reader.read(&mut buffer[..chunk])?;
+        if read == 0 {
+            return Err(corrupt("7z folder is shorter than its header says"));
+        }
+        crc.update(&buffer[..read]);
+        remaining -= read as u64;
+    }
+    Ok(crc.finalize())
+}
+
+/// Verify the CRCs of every file in `.7z` archive read from `input`
+///
+/// (Reads each folder's packed streams sequentially, so even solid archives are verified without
+/// holding more than a dictionary's worth of their contents in memory.)
+pub fn verify<R: Read + Seek>(mut input: R) -> io::Result<()> {
+    eof_is_corrupt(verify_inner(&mut input))
+}
+
+/// Helper to map `UnexpectedEof` from anywhere in the decoding process to corruption
+fn verify_inner<R: Read + Seek>(input: &mut R) -> io::Result<()> {
+    let mut start = [0; START_HEADER_SIZE as usize];
+    input.read_exact(&mut start)?;
+    if start[..6] != SIGNATURE {
+        return Err(corrupt("Not a 7z archive"));
+    }
+    if start[6] != 0 {
+        return Err(unsupported(&format!("Unsupported 7z format version {}.{}",
+                                        start[6], start[7])));
+    }
+    if crc32(&start[12..]) != le_uint(&start[8..12]) as u32 {
+        return Err(corrupt("7z start header CRC mismatch"));
+    }
+    let (offset, len) = (le_uint(&start[12..20]), le_uint(&start[20..28]));
+    if len == 0 {
+        // An empty archive
+        return Ok(());
+    }
+
+    let file_len = input.seek(SeekFrom::End(0))?;
+    let header_pos = START_HEADER_SIZE.checked_add(offset)
+        .filter(|pos| pos.checked_add(len).map_or(false, |end| end <= file_len))
+        .ok_or_else(|| corrupt("7z header is beyond the end of the file"))?;
+    input.seek(SeekFrom::Start(header_pos))?;
+    let mut header = vec![0; len as usize];
+    input.read_exact(&mut header)?;
+    if crc32(&header) != le_uint(&start[28..32]) as u32 {
+        return Err(corrupt("7z header CRC mismatch"));
+    }
+
+    if header.first() == Some(&prop::ENCODED_HEADER) {
+        let info = StreamsInfo::read(&mut Cursor::new(&header[1..]))?;
+        let folder = match &info.folders[..] {
+            [folder] if folder.substreams.len() == 1 => folder,
+            _ => return Err(corrupt("7z encoded header isn't a single stream")),
+        };
+        let mut decoded = Vec::new();
+        read_folders(input, &info, |_, size, reader| {
+            reader.take(size).read_to_end(&mut decoded)?;
+            Ok(())
+        })?;
+        if decoded.len() as u64 != folder.unpack_size() {
+            return Err(corrupt("7z folder is shorter than its header says"));
+        }
+        if folder.crc.map_or(false, |crc| crc != crc32(&decoded)) {
+            return Err(corrupt("7z header CRC mismatch"));
+        }
+        header = decoded;
+    }
+
+    let mut cur = Cursor::new(&header);
+    cur.expect(prop::HEADER)?;
+    let mut id = cur.byte()?;
+    if id == prop::ARCHIVE_PROPERTIES {
+        while cur.byte()? != prop::END {
+            let len = cur.number()?;
+            cur.bytes(len)?;
+        }
+        id = cur.byte()?;
+    }
+    if id == prop::ADDITIONAL_STREAMS_INFO {
+        return Err(unsupported("7z header has additional streams"));
+    }
+    let info = if id == prop::MAIN_STREAMS_INFO {
+        let info = StreamsInfo::read(&mut cur)?;
+        id = cur.byte()?;
+        info
+    } else {
+        StreamsInfo::default()
+    };
+    let names = if id == prop::FILES_INFO {
+        let names = files_info(&mut cur)?;
+        id = cur.byte()?;
+        let substreams: usize = info.folders.iter().map(|folder| folder.substreams.len()).sum();
+        if names.len() != substreams {
+            return Err(corrupt("7z file list doesn't match its packed data"));
+        }
+        names
+    } else {
+        Vec::new()
+    };
+    if id != prop::END {
+        return Err(corrupt("Unexpected property in 7z header"));
+    }
+
+    read_folders(input, &info, |idx, size, reader| {
+        let crc = crc32_of(reader, size)?;
+        match info.folders.iter().flat_map(|folder| &folder.substreams).nth(idx) {
+            Some(&(_, Some(expected))) if expected != crc => Err(corrupt(&format!(
+                "CRC mismatch for {}",
+                names.get(idx).map_or("file in 7z archive", |name| name)))),
+            _ => Ok(()),
+        }
+    })
+}
+
+/// Decode every folder in `info`, calling `visit` with the index, size, and a reader positioned
+/// at the start of each file in turn
+fn read_folders<R: Read + Seek>(
+    input: &mut R,
+    info: &StreamsInfo,
+    mut visit: impl FnMut(usize, u64, &mut dyn Read) -> io::Result<()>,
+) -> io::Result<()> {
+    let (mut pack_pos, mut pack_idx, mut file_idx) = (info.pack_pos, 0_usize, 0);
+    for folder in &info.folders {
+        let sizes = pack_idx.checked_add(folder.packed_streams)
+            .and_then(|end| info.pack_sizes.get(pack_idx..end))
+            .ok_or_else(|| corrupt("7z folder refers to missing packed streams"))?;
+        pack_idx += folder.packed_streams;
+
+        if !folder.substreams.is_empty() {
+            let start = START_HEADER_SIZE.checked_add(pack_pos)
+                .ok_or_else(|| corrupt("7z packed stream is beyond the end of the file"))?;
+            input.seek(SeekFrom::Start(start))?;
+            let packed = BufReader::new(&mut *input).take(sizes.first().copied().unwrap_or(0));
+            let mut reader = folder.reader(packed)?;
+            for &(size, _) in &folder.substreams {
+                visit(file_idx, size, &mut reader)?;
+                file_idx += 1;
+            }
+        }
+        pack_pos = sizes.iter().try_fold(pack_pos, |acc, &size| acc.checked_add(size))
+            .ok_or_else(|| corrupt("7z packed stream is beyond the end of the file"))?;
+    }
+    Ok(())
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Assemble an archive from its packed streams and (possibly encoded) header
+    fn archive(packed: &[u8], header: &[u8]) -> Vec<u8> {
+        let mut tail = (packed.len() as u64).to_le_bytes().to_vec();
+        tail.extend(&(header.len() as u64).to_le_bytes());
+        tail.extend(&crc32(header).to_le_bytes());
+        let mut out = SIGNATURE.to_vec();
+        out.extend(&[0, 4]);
+        out.extend(&crc32(&tail).to_le_bytes());
+        out.extend(&tail);
+        out.extend(packed);
+        out.extend(header);
+        out
+    }
+
+    /// Build a `StreamsInfo` for one folder which packs `files` with a single coder
+    fn streams_info(pack_pos: usize, codec: &[u8], files: &[&[u8]]) -> Vec<u8> {
+        let total = files.iter().map(|file| file.len()).sum::<usize>() as u8;
+        let mut out = vec![prop::PACK_INFO, pack_pos as u8, 1, prop::SIZE, total, prop::END,
+                           prop::UNPACK_INFO, prop::FOLDER, 1, 0, 1, codec.len() as u8];
+        out.extend(codec);
+        out.extend(&[prop::CODERS_UNPACK_SIZE, total, prop::END, prop::SUBSTREAMS_INFO,
+                     prop::NUM_UNPACK_STREAM, files.len() as u8, prop::SIZE]);
+        out.extend(files[..files.len() - 1].iter().map(|file| file.len() as u8));
+        out.extend(&[prop::CRC, 1]);
+        for file in files {
+            out.extend(&crc32(file).to_le_bytes());
+        }
+        out.extend(&[prop::END, prop::END]);
+        out
+    }
+
+    /// Build an unencoded header for `file_count` files packed into one folder
+    fn header(codec: &[u8], files: &[&[u8]], file_count: u8) -> Vec<u8> {
+        let mut out = vec![prop::HEADER, prop::MAIN_STREAMS_INFO];
+        out.extend(streams_info(0, codec, files));
+        out.extend(&[prop::FILES_INFO, file_count, prop::END, prop::END]);
+        out
+    }
+
+    /// Verify `input`, returning the error kind on failure
+    fn verify_bytes(input: &[u8]) -> Result<(), io::ErrorKind> {
+        verify(io::Cursor::new(input)).map_err(|err| err.kind())
+    }
+
+    #[test]
+    fn test_number() {
+        let number = |bytes: &[u8]| Cursor::new(bytes).number().unwrap();
+        assert_eq!(number(&[0x7F]), 0x7F);
+        assert_eq!(number(&[0x80, 0x80]), 0x80);
+        assert_eq!(number(&[0xC1, 0x23, 0x45]), 0x1_4523);
+        assert_eq!(number(&[0xFF, 1, 2, 3, 4, 5, 6, 7, 8]), 0x0807_0605_0403_0201);
+        assert!(Cursor::new(&[0xC1, 0x23]).number().is_err());
+    }
+
+    #[test]
+    fn test_archive_structure() {
+        let files: &[&[u8]] = &[b"Hello", b", 7z"];
+        let good = archive(b"Hello, 7z", &header(codec::COPY, files, 2));
+        assert_eq!(verify_bytes(&good), Ok(()));
+        assert_eq!(verify_bytes(&archive(b"", b"")), Ok(()));
+
+        let mut corrupted = good.clone();
+        corrupted[38] ^= 0x01;
+        assert_eq!(verify_bytes(&corrupted), Err(io::ErrorKind::InvalidData));
+        for cut in &[0, 20, 34, good.len() - 1] {
+            assert_eq!(verify_bytes(&good[..*cut]), Err(io::ErrorKind::InvalidData));
+        }
+        let miscounted = archive(b"Hello, 7z", &header(codec::COPY, files, 3));
+        assert_eq!(verify_bytes(&miscounted), Err(io::ErrorKind::InvalidData));
+
+        let plain_header = header(codec::COPY, files, 2);
+        let mut packed = b"Hello, 7z".to_vec();
+        packed.extend(&plain_header);
+        let mut encoded = vec![prop::ENCODED_HEADER];
+        encoded.extend(streams_info(9, codec::COPY, &[&plain_header]));
+        assert_eq!(verify_bytes(&archive(&packed, &encoded)), Ok(()));
+    }
+
+    #[test]
+    fn test_unsupported() {
+        let files: &[&[u8]] = &[b"Hello"];
+        for &codec in &[codec::AES, &[0x03, 0x04, 0x01]] {
+            let unknown = archive(b"Hello", &header(codec, files, 1));
+            assert_eq!(verify_bytes(&unknown), Err(io::ErrorKind::Unsupported));
+        }
+
+        let plain_header = header(codec::COPY, files, 1);
+        let mut packed = b"Hello".to_vec();
+        packed.extend(&plain_header);
+        let mut encrypted = vec![prop::ENCODED_HEADER];
+        encrypted.extend(streams_info(5, codec::AES, &[&plain_header]));
+        assert_eq!(verify_bytes(&archive(&packed, &encrypted)), Err(io::ErrorKind::Unsupported));
+    }
+
+    #[test]
+    fn test_lzma_fixtures() {
+        assert_eq!(verify_bytes(include_bytes!("../../../test_data/good/testfile.7z")), Ok(()));
+        assert_eq!(verify_bytes(include_bytes!("../../../test_data/good/testfile.cb7")), Ok(()));
+        assert_eq!(verify_bytes(include_bytes!("../../../test_data/bad/testfile.7z")),
+                   Err(io::ErrorKind::InvalidData));
+        assert_eq!(verify_bytes(include_bytes!("../../../test_data/bad/testfile.cb7")),
+                   Err(io::ErrorKind::InvalidData));
+    }
+}
@@ -0,0 +1,644 @@
+//! A checker for SQLite 3 database files, following the "Database File Format" document
+//!
+//! (The quick check only looks at the 100-byte header and the file's length. The full check also
+//! walks every b-tree, overflow chain, and freelist page, requiring that each page is used exactly
+//! once and checking rowid order, free space accounting, record headers, and pointer maps. That
+//! covers the structural half of what `PRAGMA integrity_check` does without needing to link SQLite
+//! itself, but it doesn't compare indexes against their tables.)
+
+// Standard library imports
+use std::convert::TryFrom;
+use std::io::{self, Read, Seek, SeekFrom};
+
+// Local Imports
+use super::bytes::{be16, be32};
+use super::{corrupt, unsupported};
+
+/// The magic number at the start of every SQLite 3 database
+const MAGIC: &[u8; 16] = b"SQLite format 3\0";
+
+/// The size of the database header at the start of page 1
+const HEADER_SIZE: usize = 100;
+
+/// The offset of the page containing the bytes SQLite uses for file locking, which is never used
+const LOCK_BYTE_OFFSET: u64 = 0x4000_0000;
+
+/// B-tree page type: Interior page of an index b-tree
+const INDEX_INTERIOR: u8 = 2;
+/// B-tree page type: Interior page of a table b-tree
+const TABLE_INTERIOR: u8 = 5;
+/// B-tree page type: Leaf page of an index b-tree
+const INDEX_LEAF: u8 = 10;
+/// B-tree page type: Leaf page of a table b-tree
+const TABLE_LEAF: u8 = 13;
+
+/// Read a variable-length integer from the start of `bytes`, returning it and its length
+///
+/// Returns `None` if `bytes` ends in the middle of it.
+fn varint(bytes: &[u8]) -> Option<(u64, usize)> {
+    let mut value = 0_u64;
+    for (idx, &byte) in bytes.iter().enumerate().take(9) {
+        if idx == 8 {
+            return Some(((value << 8) | u64::from(byte), 9));
+        }
+        value = (value << 7) | u64::from(byte & 0x7F);
+        if byte & 0x80 == 0 {
+            return Some((value, idx + 1));
+        }
+    }
+    None
+}
+
+/// The fields of the database header which the checks need
+#[derive(Debug)]
+pub struct Header {
+    /// The size of a page in bytes
+    page_size: u32,
+    /// The number of usable bytes in each page (ie. minus the reserved space at the end)
+    usable_size: u32,
+    /// The number of pages, according to the header (if it's known to be valid)
+    page_count: Option<u32>,
+    /// The first freelist trunk page (or 0)
+    freelist_trunk: u32,
+    /// The number of pages on the freelist
+    freelist_count: u32,
+    /// Whether the database has pointer map pages (ie. uses auto-vacuum)
+    has_ptrmap: bool,
+}
+
+impl Header {
+    /// Parse and sanity-check the database header
+    fn parse(header: &[u8; HEADER_SIZE]) -> io::Result<Self> {
+        if &header[..MAGIC.len()] != MAGIC {
+            return Err(corrupt("Not an SQLite 3 database"));
+        }
+        let page_size = match be16(&header[16..]) {
+            1 => 65536,
+            size if size.is_power_of_two() && size >= 512 => u32::from(size),
+            size => return Err(corrupt(&format!("Invalid SQLite page size {}", size))),
+        };
+        match (header[18], header[19]) {
+            (1..=2, 1..=2) => {},
+            (write, read) if write > 2 || read > 2 => return Err(unsupported(&format!(
+                "Unsupported SQLite file format version {}/{}", write, read))),
+            _ => return Err(corrupt("Invalid SQLite file format version")),
+        }
+        let usable_size = page_size - u32::from(header[20]);
+        if usable_size < 480 {
+            return Err(corrupt("Too much reserved space at the end of each SQLite page"));
+        }
+        if header[21..24] != [64, 32, 32] {
+            return Err(corrupt("Invalid SQLite payload fractions"));
+        }
+
+        let schema_format = be32(&header[44..]);
+        let encoding = be32(&header[56..]);
+        if schema_format > 4 || encoding > 3 {
+            return Err(corrupt("Invalid SQLite schema format or text encoding"));
+        }
+
+        // The page count is only trustworthy if it was written by a version of SQLite which knew
+        // to keep it updated, as shown by it matching the change counter
+        let page_count = Some(be32(&header[28..]))
+            .filter(|&count| count > 0 && header[24..28] == header[92..96]);
+        Ok(Self {
+            page_size,
+            usable_size,
+            page_count,
+            freelist_trunk: be32(&header[32..]),
+            freelist_count: be32(&header[36..]),
+            has_ptrmap: be32(&header[52..]) != 0,
+        })
+    }
+
+    /// Check the header's page count against the length of the file, returning the page count
+    fn check_length(&self, file_len: u64) -> io::Result<u32> {
+        let page_size = u64::from(self.page_size);
+        match self.page_count {
+            Some(count) if u64::from(count) * page_size > file_len => Err(corrupt(&format!(
+                "SQLite database is truncated ({} of {} pages present)",
+                file_len / page_size, count))),
+            Some(count) => Ok(count),
+            None if file_len % page_size != 0 => Err(corrupt(
+                "SQLite database isn't a whole number of pages long (truncated?)")),
+            None => u32::try_from(file_len / page_size)
+                .map_err(|_| corrupt("SQLite database has too many pages")),
+        }
+    }
+}
+
+/// Pointer map entry type: The root page of a b-tree
+const PTRMAP_ROOT: u8 = 1;
+/// Pointer map entry type: A page on the freelist
+const PTRMAP_FREE: u8 = 2;
+/// Pointer map entry type: The first page of an overflow chain
+const PTRMAP_OVERFLOW1: u8 = 3;
+/// Pointer map entry type: A later page of an overflow chain
+const PTRMAP_OVERFLOW2: u8 = 4;
+/// Pointer map entry type: A non-root b-tree page
+const PTRMAP_BTREE: u8 = 5;
+
+/// A b-tree page which has been found but not visited yet
+struct PendingPage {
+    /// The page number
+    number: u32,
+    /// The page number of its parent (or 0 for the root)
+    parent: u32,
+    /// How many levels below the root it is
+    depth: u32,
+    /// The range its rowids must fall within, if it's part of a table b-tree
+    ///
+    /// (Exclusive lower bound and inclusive upper bound, as in the interior pages' keys.)
+    bounds: (Option<i64>, Option<i64>),
+}
+
+/// A walker which visits every page of the database, checking that each is used exactly once
+struct Walker<R> {
+    /// The database file
+    input: R,
+    /// The header's fields
+    header: Header,
+    /// Which pages have been used by something, indexed by page number
+    used: Vec<bool>,
+    /// What each page's pointer map entry should say, if the database has pointer map pages
+    ptrmap: Vec<Option<(u8, u32)>>,
+}
+
+impl<R: Read + Seek> Walker<R> {
+    /// Read page `number`
+    fn read_page(&mut self, number: u32) -> io::Result<Vec<u8>> {
+        let mut page = vec![0; self.header.page_size as usize];
+        self.input.seek(SeekFrom::Start(u64::from(number - 1) * page.len() as u64))?;
+        self.input.read_exact(&mut page)?;
+        page.truncate(self.header.usable_size as usize);
+        Ok(page)
+    }
+
+    /// Record that page `number` is used by `what`, checking that nothing else already uses it
+    ///
+    /// `ptrmap` is the pointer map entry it implies (ignored if there are no pointer maps).
+    fn claim(&mut self, number: u32, what: &str, ptrmap: (u8, u32)) -> io::Result<()> {
+        match self.used.get_mut(number as usize) {
+            Some(used) if number > 0 && !*used => {
+                *used = true;
+                if let Some(entry) = self.ptrmap.get_mut(number as usize) {
+                    *entry = Some(ptrmap);
+                }
+                Ok(())
+            },
+            Some(_) if number > 0 => Err(corrupt(&format!(
+                "SQLite page {} is used twice (second time by {})", number, what))),
+            _ => Err(corrupt(&format!(
+                "SQLite {} refers to page {}, which doesn't exist", what, number))),
+        }
+    }
+
+    /// The page number of the lock-byte page
+    fn lock_page(&self) -> u64 {
+        LOCK_BYTE_OFFSET / u64::from(self.header.page_size) + 1
+    }
+
+    /// The page number of the pointer map page which holds the entry for page `number`
+    ///
+    /// (A pointer map page which would land on the lock-byte page is moved to the page after
+    /// it, without shifting the ones which follow.)
+    fn ptrmap_page(&self, number: u64) -> u64 {
+        let stride = u64::from(self.header.usable_size / 5) + 1;
+        let page = (number - 2) / stride * stride + 2;
+        if page == self.lock_page() { page + 1 } else { page }
+    }
+
+    /// Claim the lock-byte page and any pointer map pages, which aren't referenced by anything
+    fn claim_reserved_pages(&mut self) -> io::Result<()> {
+        let page_count = self.used.len() as u64 - 1;
+        let lock_page = self.lock_page() as usize;
+        if let Some(used) = self.used.get_mut(lock_page) {
+            *used = true;
+        }
+        if self.header.has_ptrmap {
+            for page in 2..=page_count {
+                if self.ptrmap_page(page) == page {
+                    self.claim(page as u32, "pointer map", (0, 0))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that every pointer map entry matches how the page turned out to be used
+    fn check_ptrmap(&mut self) -> io::Result<()> {
+        let mut map_page = (0, Vec::new());
+        for number in 2..self.ptrmap.len() {
+            let expected = match self.ptrmap[number] {
+                Some(entry) if entry.0 != 0 => entry,
+                _ => continue,
+            };
+            let map_number = self.ptrmap_page(number as u64);
+            if map_page.0 != map_number {
+                map_page = (map_number, self.read_page(map_number as u32)?);
+            }
+            let offset = (number - map_number as usize - 1) * 5;
+            let entry = &map_page.1[offset..offset + 5];
+            if (entry[0], be32(&entry[1..])) != expected {
+                return Err(corrupt(&format!(
+                    "SQLite pointer map entry for page {} is wrong", number)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Walk the freelist, checking that it holds as many pages as the header says
+    fn walk_freelist(&mut self) -> io::Result<()> {
+        let (mut trunk, mut count) = (self.header.freelist_trunk, 0_u64);
+        while trunk != 0 {
+            self.claim(trunk, "freelist", (PTRMAP_FREE, 0))?;
+            let page = self.read_page(trunk)?;
+            let leaves = be32(&page[4..]) as usize;
+            if leaves > page.len() / 4 - 2 {
+                return Err(corrupt(&format!("SQLite freelist page {} is overfull", trunk)));
+            }
+            for leaf in page[8..8 + leaves * 4].chunks(4) {
+                self.claim(be32(leaf), "freelist", (PTRMAP_FREE, 0))?;
+            }
+            count += 1 + leaves as u64;
+            trunk = be32(&page);
+        }
+        if count != u64::from(self.header.freelist_count) {
+            return Err(corrupt(&format!(
+                "SQLite freelist holds {} pages, but the header says {}",
+                count, self.header.freelist_count)));
+        }
+        Ok(())
+    }
+
+    /// Follow the chain of `count` overflow pages which starts at `first` and belongs to a cell
+    /// on page `owner`, returning their content if `keep` is set
+    fn walk_overflow(&mut self, first: u32, owner: u32, count: u64, keep: bool)
+            -> io::Result<Vec<u8>> {
+        let (mut next, mut ptrmap, mut content) = (first, (PTRMAP_OVERFLOW1, owner), Vec::new());
+        for _ in 0..count {
+            if next == 0 {
+                return Err(corrupt("SQLite overflow chain ends early"));
+            }
+            self.claim(next, "overflow chain", ptrmap)?;
+            let page = self.read_page(next)?;
+            if keep {
+                content.extend(&page[4..]);
+            }
+            ptrmap = (PTRMAP_OVERFLOW2, next);
+            next = be32(&page);
+        }
+        if next != 0 {
+            return Err(corrupt("SQLite overflow chain is longer than its payload"));
+        }
+        Ok(content)
+    }
+
+    /// Walk the b-tree rooted at `root`, returning the payloads of its cells if `keep` is set
+    ///
+    /// Besides following every child and overflow pointer, this checks that the cells and
+    /// freeblocks on each page don't overlap, that the page's fragmentation count is right, that
+    /// all leaves are at the same depth, and, for table b-trees, that the rowids are in order.
+    ///
+    /// (Only used with `keep` for the schema table, whose records say where the other b-trees
+    /// are.)
+    fn walk_btree(&mut self, root: u32, keep: bool) -> io::Result<Vec<Vec<u8>>> {
+        let what = format!("b-tree rooted at page {}", root);
+        let mut pending =
+            vec![PendingPage { number: root, parent: 0, depth: 0, bounds: (None, None) }];
+        let (mut kind, mut leaf_depth, mut payloads) = (None, None, Vec::new());
+        while let Some(PendingPage { number, parent, depth, bounds }) = pending.pop() {
+            let ptrmap = if parent == 0 { (PTRMAP_ROOT, 0) } else { (PTRMAP_BTREE, parent) };
+            self.claim(number, &what, ptrmap)?;
+            let page = self.read_page(number)?;
+            let start = if number == 1 { HEADER_SIZE } else { 0 };
+            let invalid = || corrupt(&format!("SQLite b-tree page {} is malformed", number));
+
+            let page_type = page[start];
+            if ![INDEX_INTERIOR, TABLE_INTERIOR, INDEX_LEAF, TABLE_LEAF].contains(&page_type) {
+                return Err(invalid());
+            }
+            let is_table = page_type == TABLE_INTERIOR || page_type == TABLE_LEAF;
+            if *kind.get_or_insert(is_table) != is_table {
+                return Err(corrupt(&format!("SQLite {} mixes table and index pages", what)));
+            }
+            let is_leaf = page_type == INDEX_LEAF || page_type == TABLE_LEAF;
+            if is_leaf && *leaf_depth.get_or_insert(depth) != depth {
+                return Err(corrupt(&format!("SQLite {} has leaves at different depths", what)));
+            }
+            let header_len = if is_leaf { 8 } else { 12 };
+
+            let cell_count = usize::from(be16(&page[start + 3..]));
+            let pointers_end = start + header_len + cell_count * 2;
+            if pointers_end > page.len() {
+                return Err(invalid());
+            }
+            let (mut spans, mut lower) = (Vec::with_capacity(cell_count), bounds.0);
+            for pointer in page[start + header_len..pointers_end].chunks(2) {
+                let offset = usize::from(be16(pointer));
+                if offset < pointers_end || offset >= page.len() {
+                    return Err(invalid());
+                }
+                let cell = &page[offset..];
+                let mut pos = 0;
+                let mut child = None;
+                if !is_leaf {
+                    child = Some(be32(cell.get(..4).ok_or_else(invalid)?));
+                    pos = 4;
+                }
+                let mut payload_len = 0;
+                if !is_table || is_leaf {
+                    let (len, varint_len) = varint(&cell[pos..]).ok_or_else(invalid)?;
+                    payload_len = len;
+                    pos += varint_len;
+                }
+                if is_table {
+                    let (rowid, varint_len) = varint(&cell[pos..]).ok_or_else(invalid)?;
+                    pos += varint_len;
+                    let rowid = rowid as i64;
+                    if lower.map_or(false, |lower| rowid <= lower)
+                            || bounds.1.map_or(false, |upper| rowid > upper) {
+                        return Err(corrupt(&format!(
+                            "SQLite b-tree page {} has rowid {} out of order", number, rowid)));
+                    }
+                    if let Some(child) = child {
+                        pending.push(PendingPage {
+                            number: child, parent: number, depth: depth + 1,
+                            bounds: (lower, Some(rowid)) });
+                    }
+                    lower = Some(rowid);
+                } else if let Some(child) = child {
+                    pending.push(PendingPage {
+                        number: child, parent: number, depth: depth + 1, bounds: (None, None) });
+                }
+
+                let local = if is_table && !is_leaf {
+                    0
+                } else {
+                    self.local_payload(payload_len, is_table)
+                };
+                let mut payload = cell.get(pos..pos + local).ok_or_else(invalid)?.to_vec();
+                pos += local;
+                if !(is_table && !is_leaf) {
+                    match record_len(&payload, payload_len) {
+                        Ok(Some(len)) if len > payload_len => return Err(invalid()),
+                        Ok(_) => {},
+                        Err(()) => return Err(invalid()),
+                    }
+                }
+                if (local as u64) < payload_len {
+                    let first = be32(cell.get(pos..pos + 4).ok_or_else(invalid)?);
+                    pos += 4;
+                    let page_content = u64::from(self.header.usable_size) - 4;
+                    let overflow_len = payload_len - local as u64;
+                    let pages = (overflow_len + page_content - 1) / page_content;
+                    payload.extend(self.walk_overflow(first, number, pages, keep)?);
+                    payload.truncate(payload_len as usize);
+                }
+                spans.push((offset, pos.max(4)));
+                if keep {
+                    payloads.push(payload);
+                }
+            }
+            if !is_leaf {
+                pending.push(PendingPage {
+                    number: be32(&page[start + 8..]), parent: number, depth: depth + 1,
+                    bounds: (if is_table { lower } else { None }, bounds.1) });
+            }
+            check_free_space(&page, start, pointers_end, spans).map_err(|()| corrupt(&format!(
+                "SQLite b-tree page {} has corrupted free space accounting", number)))?;
+        }
+        Ok(payloads)
+    }
+
+    /// How many bytes of a `payload_len`-byte payload are stored in the b-tree page itself
+    fn local_payload(&self, payload_len: u64, is_table: bool) -> usize {
+        let usable = u64::from(self.header.usable_size);
+        let max_local = if is_table { usable - 35 } else { (usable - 12) * 64 / 255 - 23 };
+        if payload_len <= max_local {
+            return payload_len as usize;
+        }
+        let min_local = (usable - 12) * 32 / 255 - 23;
+        let local = min_local + (payload_len - min_local) % (usable - 4);
+        (if local <= max_local { local } else { min_local }) as usize
+    }
+}
+
+/// Check that the cells (given as offset and length) and freeblocks on a b-tree page don't
+/// overlap, and that the gaps between them add up to the page's fragmentation count
+///
+/// `start` is where the page header begins and `pointers_end` is where the cell pointers end.
+fn check_free_space(page: &[u8], start: usize, pointers_end: usize,
+                    mut spans: Vec<(usize, usize)>) -> Result<(), ()> {
+    let content_start = match usize::from(be16(&page[start + 5..])) {
+        0 => 65536,
+        offset => offset,
+    };
+    if content_start < pointers_end || content_start > page.len() {
+        return Err(());
+    }
+
+    let mut freeblock = usize::from(be16(&page[start + 1..]));
+    while freeblock != 0 {
+        let header = page.get(freeblock..freeblock + 4).ok_or(())?;
+        let (next, size) = (usize::from(be16(header)), usize::from(be16(&header[2..])));
+        if freeblock < content_start || size < 4
+                || (next != 0 && next <= freeblock + size) {
+            return Err(());
+        }
+        spans.push((freeblock, size));
+        freeblock = next;
+    }
+
+    spans.sort_unstable();
+    let (mut pos, mut fragmented) = (content_start, 0);
+    for (offset, len) in spans {
+        if offset < pos {
+            return Err(());
+        }
+        fragmented += offset - pos;
+        pos = offset + len;
+    }
+    if pos > page.len() || fragmented + page.len() - pos != usize::from(page[start + 7]) {
+        return Err(());
+    }
+    Ok(())
+}
+
+/// The size of a record column's value, given its serial type (or `None` for reserved types)
+fn serial_type_size(serial_type: u64) -> Option<u64> {
+    match serial_type {
+        0 | 8 | 9 => Some(0),
+        1..=4 => Some(serial_type),
+        5 => Some(6),
+        6 | 7 => Some(8),
+        10 | 11 => None,
+        _ => Some((serial_type - 12) / 2),
+    }
+}
+
+/// Work out the total length of a record from its header, given the part of it stored locally
+///
+/// (SQLite tolerates payloads which are longer than their records, so only shorter ones count as
+/// corruption.)
+///
+/// Returns `Ok(None)` if the header continues onto an overflow page and `Err(())` if it's
+/// malformed.
+fn record_len(local: &[u8], payload_len: u64) -> Result<Option<u64>, ()> {
+    let (header_len, mut pos) = varint(local).ok_or(())?;
+    if header_len < pos as u64 || header_len > payload_len {
+        return Err(());
+    }
+    let header = match local.get(..header_len as usize) {
+        Some(header) => header,
+        None => return Ok(None),
+    };
+    let mut total = header_len;
+    while pos < header.len() {
+        let (serial_type, len) = varint(&header[pos..]).ok_or(())?;
+        pos += len;
+        total += serial_type_size(serial_type).ok_or(())?;
+    }
+    if pos == header.len() { Ok(Some(total)) } else { Err(()) }
+}
+
+/// Extract the `rootpage` column from a record in the schema table
+///
+/// Returns `None` if the record is malformed and `Some(0)` for entries without a b-tree (eg. views
+/// and triggers).
+fn schema_root_page(record: &[u8]) -> Option<u32> {
+    let (header_len, mut pos) = varint(record)?;
+    let mut body = usize::try_from(header_len).ok()?;
+    for column in 0..4 {
+        let (serial_type, len) = varint(record.get(pos..)?)?;
+        pos += len;
+        let size = serial_type_size(serial_type)? as usize;
+        if column == 3 {
+            let value = record.get(body..body + size)?;
+            return match serial_type {
+                0 | 8 => Some(0),
+                9 => Some(1),
+                1..=6 => u32::try_from(value.iter().fold(0_u64, |acc, &byte|
+                    (acc << 8) | u64::from(byte))).ok(),
+                _ => None,
+            };
+        }
+        body += size;
+    }
+    None
+}
+
+/// Read and sanity-check the header of the SQLite database read from `input`
+pub fn read_header(input: &mut impl Read) -> io::Result<Header> {
+    let mut raw_header = [0; HEADER_SIZE];
+    input.read_exact(&mut raw_header).map_err(|err| match err.kind() {
+        io::ErrorKind::UnexpectedEof => corrupt("SQLite database header is truncated"),
+        _ => err,
+    })?;
+    Header::parse(&raw_header)
+}
+
+/// Check the header of the SQLite database read from `input`, and that the file is long enough
+///
+/// If `full` is set, also walk the whole database to check that every page is used exactly once.
+pub fn verify<R: Read + Seek>(mut input: R, file_len: u64, full: bool) -> io::Result<()> {
+    let header = read_header(&mut input)?;
+    let page_count = header.check_length(file_len)?;
+    if !full {
+        return Ok(());
+    }
+
+    let ptrmap = if header.has_ptrmap { vec![None; page_count as usize + 1] } else { Vec::new() };
+    let used = vec![false; page_count as usize + 1];
+    let mut walker = Walker { input, header, used, ptrmap };
+    walker.used[0] = true;
+    walker.claim_reserved_pages()?;
+    walker.walk_freelist()?;
+    for record in walker.walk_btree(1, true)? {
+        match schema_root_page(&record) {
+            Some(0) => {},
+            Some(root) => { walker.walk_btree(root, false)?; },
+            None => return Err(corrupt("SQLite schema table holds a malformed record")),
+        }
+    }
+    if let Some(page) = walker.used.iter().position(|&used| !used) {
+        return Err(corrupt(&format!("SQLite page {} is never used", page)));
+    }
+    walker.check_ptrmap()
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Verify `input`, returning the error kind on failure
+    fn verify_bytes(input: &[u8], full: bool) -> Result<(), io::ErrorKind> {
+        verify(Cursor::new(input), input.len() as u64, full).map_err(|err| err.kind())
+    }
+
+    #[test]
+    fn test_varint() {
+        assert_eq!(varint(&[0x00]), Some((0, 1)));
+        assert_eq!(varint(&[0x7F, 0xFF]), Some((0x7F, 1)));
+        assert_eq!(varint(&[0x81, 0x00]), Some((0x80, 2)));
+        assert_eq!(varint(&[0xFF; 9]), Some((u64::MAX, 9)));
+        assert_eq!(varint(&[0x81]), None);
+    }
+
+    #[test]
+    fn test_header() {
+        let good = include_bytes!("../../../test_data/good/testfile.sqlite3");
+        assert_eq!(verify_bytes(good, false), Ok(()));
+        for cut in &[0, 50, 100, 1024, good.len() - 1] {
+            assert_eq!(verify_bytes(&good[..*cut], false), Err(io::ErrorKind::InvalidData));
+        }
+        for (offset, value) in &[(0, b's'), (16, 0x03), (21, 63), (56, 7), (18, 0)] {
+            let mut bad = good.to_vec();
+            bad[*offset] = *value;
+            assert_eq!(verify_bytes(&bad, false), Err(io::ErrorKind::InvalidData));
+        }
+        let mut future = good.to_vec();
+        future[19] = 3;
+        assert_eq!(verify_bytes(&future, false), Err(io::ErrorKind::Unsupported));
+
+        // A stale page count means the length is checked against the page size instead
+        let mut stale = good.to_vec();
+        stale[95] ^= 1;
+        stale.extend(&[0; 1024]);
+        assert_eq!(verify_bytes(&stale, false), Ok(()));
+        stale.push(0);
+        assert_eq!(verify_bytes(&stale, false), Err(io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn test_structure() {
+        for good in &[&include_bytes!("../../../test_data/good/testfile.sqlite3")[..],
+                      include_bytes!("../../../test_data/good/testfile_foreign.sqlite3")] {
+            assert_eq!(verify_bytes(good, true), Ok(()));
+
+            // An extra page which nothing refers to
+            let mut orphan = good.to_vec();
+            orphan[28..32].copy_from_slice(&(good.len() as u32 / 1024 + 1).to_be_bytes());
+            orphan.extend(&[0; 1024]);
+            assert_eq!(verify_bytes(&orphan, false), Ok(()));
+            assert_eq!(verify_bytes(&orphan, true), Err(io::ErrorKind::InvalidData));
+
+            // A table's root page which has been overwritten
+            let mut wiped = good.to_vec();
+            wiped[1024..2048].copy_from_slice(&[0; 1024]);
+            assert_eq!(verify_bytes(&wiped, true), Err(io::ErrorKind::InvalidData));
+
+            // A fragmentation count which doesn't match the gaps between the cells
+            let mut fragmented = good.to_vec();
+            fragmented[1024 + 7] = 1;
+            assert_eq!(verify_bytes(&fragmented, true), Err(io::ErrorKind::InvalidData));
+        }
+
+        let bad = include_bytes!("../../../test_data/bad/testfile.sqlite3");
+        assert_eq!(verify_bytes(bad, false), Ok(()));
+        assert_eq!(verify_bytes(bad, true), Err(io::ErrorKind::InvalidData));
+    }
+}
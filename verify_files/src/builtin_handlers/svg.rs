@@ -0,0 +1,109 @@
+//! A check that a document is well-formed XML with an `<svg>` root element in the SVG namespace
+//!
+//! (The XML is checked by the streaming [`xml`](super::xml) parser, which never expands or fetches
+//! external entities, so documents which refer to remote DTDs are still checked offline.
+//! Gzip-compressed `.svgz` files are recognized by their header and decompressed on the fly.)
+
+// Standard library imports
+use std::io::{self, BufReader, Read};
+
+// Local Imports
+use super::tar::Labelled;
+use super::{corrupt, exhaust_reader, xml, GzipDecoder};
+
+/// The namespace an SVG document's root element must be in
+const SVG_NAMESPACE: &str = "http://www.w3.org/2000/svg";
+
+/// The magic number at the start of a gzip stream
+const GZIP_MAGIC: &[u8] = &[0x1F, 0x8B];
+
+/// Check the XML, then whether its root element was an `<svg>` in the SVG namespace
+///
+/// (Well-formedness comes first, so a malformed document is reported as such even if its root
+/// element is wrong too.)
+fn check_document(input: impl Read) -> io::Result<()> {
+    let mut reader = xml::Reader::new(input);
+    let mut is_svg = None;
+    while let Some(event) = reader.next_event()? {
+        if let (None, xml::Event::Start(name, attributes)) = (is_svg, &event) {
+            let namespace_attribute = match name.split_once(':') {
+                Some((prefix, _)) => format!("xmlns:{}", prefix),
+                None => "xmlns".to_owned(),
+            };
+            is_svg = Some(xml::local_name(name) == "svg" && attributes.iter()
+                .any(|(key, value)| *key == namespace_attribute && value == SVG_NAMESPACE));
+        }
+    }
+    if is_svg == Some(true) {
+        Ok(())
+    } else {
+        Err(corrupt("not an SVG document"))
+    }
+}
+
+/// Check that the SVG image read from `input`, which may be gzip-compressed, is well-formed XML
+/// with an `<svg>` root element in the SVG namespace
+///
+/// Damage to the gzip layer takes precedence over problems with the document inside, since it
+/// would explain them.
+pub fn verify(mut input: impl Read) -> io::Result<()> {
+    let mut magic = Vec::with_capacity(GZIP_MAGIC.len());
+    (&mut input).take(GZIP_MAGIC.len() as u64).read_to_end(&mut magic)?;
+    let input = io::Cursor::new(magic.clone()).chain(input);
+    if magic != GZIP_MAGIC {
+        return check_document(input);
+    }
+
+    let mut decoder = Labelled::new(GzipDecoder::new(BufReader::new(input)), "gzip");
+    let checked = check_document(&mut decoder);
+    if !decoder.failed() {
+        exhaust_reader(&mut decoder)?;
+    }
+    checked
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_good_images() {
+        for (name, data) in &[
+            ("svg", &include_bytes!("../../../test_data/good/testfile.svg")[..]),
+            ("svgz", include_bytes!("../../../test_data/good/testfile.svgz")),
+            ("prologue", b"<?xml version='1.0'?>\n<!-- by hand -->\n<!DOCTYPE svg PUBLIC \
+                \"-//W3C//DTD SVG 1.1//EN\" \"http://www.w3.org/Graphics/SVG/1.1/DTD/svg11.dtd\">\
+                <svg xmlns='http://www.w3.org/2000/svg'><title>&undeclared;</title></svg>"),
+            ("prefixed", b"<s:svg xmlns:s='http://www.w3.org/2000/svg'><s:g/></s:svg>"),
+        ] {
+            assert_eq!(verify(*data).map_err(|err| err.to_string()), Ok(()), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_bad_images() {
+        // A well-formed document in a damaged gzip stream
+        let mut bad_crc = include_bytes!("../../../test_data/good/testfile.svgz").to_vec();
+        let crc = bad_crc.len() - 8;
+        bad_crc[crc] ^= 1;
+
+        for (name, data, message) in &[
+            ("svg", &include_bytes!("../../../test_data/bad/testfile.svg")[..], "XML error at"),
+            ("svgz", include_bytes!("../../../test_data/bad/testfile.svgz"), "XML error at"),
+            ("bad CRC", &bad_crc, "Compressed stream (gzip): "),
+            ("other root", b"<html xmlns='http://www.w3.org/1999/xhtml'/>",
+                "not an SVG document"),
+            ("no namespace", b"<svg><g/></svg>", "not an SVG document"),
+            ("wrong prefix", b"<s:svg xmlns='http://www.w3.org/2000/svg' xmlns:s='urn:x'/>",
+                "not an SVG document"),
+            ("malformed other root", b"<html><body></html>", "XML error at"),
+            ("empty", b"", "XML error at"),
+        ] {
+            let err = verify(*data).expect_err(name);
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}: {}", name, err);
+            assert!(err.to_string().contains(message), "{}: {}", name, err);
+        }
+    }
+}
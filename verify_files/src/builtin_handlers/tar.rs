@@ -0,0 +1,362 @@
+//! A streaming walk of a tar archive's headers, checking each header's checksum and that the
+//! archive isn't truncated
+//!
+//! (Tar has no checksums over the data itself, so this can only catch damage to the headers and
+//! the structure. It's meant to be layered on top of a decompressor whose checksums cover the
+//! rest.)
+
+// Standard library imports
+use std::convert::TryFrom;
+use std::io::{self, Read};
+
+// Local Imports
+use super::{corrupt, unsupported};
+
+/// The size of a header and the unit the data following it is padded to
+const BLOCK_LEN: usize = 512;
+
+/// The largest pax extended header or GNU long name which will be read to learn about the next
+/// entry
+const MAX_META_LEN: u64 = 1 << 20;
+
+/// The range of the checksum field within a header
+const CHECKSUM_FIELD: std::ops::Range<usize> = 148..156;
+
+/// Read a whole block, returning how many bytes were available if `input` ended first
+fn read_block(input: &mut impl Read, block: &mut [u8; BLOCK_LEN])
+        -> io::Result<Result<(), usize>> {
+    let mut filled = 0;
+    while filled < BLOCK_LEN {
+        match input.read(&mut block[filled..]) {
+            Ok(0) => return Ok(Err(filled)),
+            Ok(len) => filled += len,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {},
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(Ok(()))
+}
+
+/// Parse a numeric header field, which is either octal text or, as a GNU extension, a base-256
+/// value flagged by the high bit of its first byte
+fn parse_number(field: &[u8]) -> Option<u64> {
+    if field[0] & 0x80 != 0 {
+        // Negative values (flagged by the next bit) make no sense for anything checked here
+        if field[0] & 0x40 != 0 {
+            return None;
+        }
+        return field[1..].iter().try_fold(u64::from(field[0] & 0x3F), |acc, &x| {
+            acc.checked_mul(256).map(|acc| acc | u64::from(x))
+        });
+    }
+
+    let text = &field[field.iter().position(|&x| x != b' ').unwrap_or(field.len())..];
+    let digits = text.iter().position(|&x| x == b' ' || x == 0).unwrap_or(text.len());
+    if text[digits..].iter().any(|&x| x != b' ' && x != 0) {
+        return None;
+    }
+    text[..digits].iter().try_fold(0_u64, |acc, &x| match x {
+        b'0'..=b'7' => acc.checked_mul(8).map(|acc| acc + u64::from(x - b'0')),
+        _ => None,
+    })
+}
+
+/// Check a header's checksum, which is the sum of its bytes with the checksum field counted as
+/// spaces (some old implementations summed them as signed bytes, so that's accepted too)
+fn checksum_matches(block: &[u8; BLOCK_LEN]) -> bool {
+    let expected = match parse_number(&block[CHECKSUM_FIELD]) {
+        Some(value) => value,
+        None => return false,
+    };
+    let (mut unsigned, mut signed) = (0_u64, 0_i64);
+    for (i, &byte) in block.iter().enumerate() {
+        let byte = if CHECKSUM_FIELD.contains(&i) { b' ' } else { byte };
+        unsigned += u64::from(byte);
+        signed += i64::from(i8::from_ne_bytes([byte]));
+    }
+    expected == unsigned || i64::try_from(expected).map_or(false, |x| x == signed)
+}
+
+/// Get the text of a NUL-terminated header field
+fn field_text(field: &[u8]) -> &[u8] {
+    &field[..field.iter().position(|&x| x == 0).unwrap_or(field.len())]
+}
+
+/// Read the data of a pax extended header or GNU long name into memory
+fn read_meta(input: &mut impl Read, size: u64, name: &str) -> io::Result<Vec<u8>> {
+    if size > MAX_META_LEN {
+        return Err(unsupported(&format!("Tar {} larger than {} bytes", name, MAX_META_LEN)));
+    }
+    let mut data = Vec::with_capacity(usize::try_from(size).expect("no more than 1MiB"));
+    input.take(size).read_to_end(&mut data)?;
+    if data.len() as u64 != size {
+        return Err(corrupt(&format!("Tar archive truncated partway through a {}", name)));
+    }
+    Ok(data)
+}
+
+/// Parse the records of a pax extended header (`"<length> <key>=<value>\n"`), returning the
+/// `size` and `path` values, if present
+fn parse_pax(mut data: &[u8]) -> io::Result<(Option<u64>, Option<Vec<u8>>)> {
+    let malformed = || corrupt("Malformed pax extended header in tar archive");
+    let (mut size, mut path) = (None, None);
+    while !data.is_empty() {
+        let space = data.iter().position(|&x| x == b' ').ok_or_else(malformed)?;
+        let len = std::str::from_utf8(&data[..space]).ok()
+            .and_then(|x| x.parse::<usize>().ok())
+            .filter(|&x| x > space + 1 && x <= data.len() && data[x - 1] == b'\n')
+            .ok_or_else(malformed)?;
+        let record = &data[space + 1..len - 1];
+        let equals = record.iter().position(|&x| x == b'=').ok_or_else(malformed)?;
+        let (key, value) = (&record[..equals], &record[equals + 1..]);
+        match key {
+            b"size" => size = Some(std::str::from_utf8(value).ok()
+                .and_then(|x| x.parse().ok())
+                .ok_or_else(malformed)?),
+            b"path" => path = Some(value.to_vec()),
+            _ => {},
+        }
+        data = &data[len..];
+    }
+    Ok((size, path))
+}
+
+/// A reader which labels the errors of the decompressor it wraps with its format, so they can't be
+/// mistaken for problems with the archive inside
+pub struct Labelled<R> {
+    /// The decompressor being wrapped
+    inner: R,
+    /// The name of the compression format
+    format: &'static str,
+    /// Whether `inner` has returned an error
+    failed: bool,
+}
+
+impl<R: Read> Labelled<R> {
+    /// Wrap the decompressor for the given format
+    pub fn new(inner: R, format: &'static str) -> Self {
+        Self { inner, format, failed: false }
+    }
+
+    /// Whether the decompressor has returned an error
+    pub fn failed(&self) -> bool {
+        self.failed
+    }
+}
+
+impl<R: Read> Read for Labelled<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let format = self.format;
+        let failed = &mut self.failed;
+        self.inner.read(buf).map_err(|err| {
+            *failed = true;
+            io::Error::new(err.kind(), format!("Compressed stream ({}): {}", format, err))
+        })
+    }
+}
+
+/// Walk the headers of a tar archive, returning how many entries it has
+///
+/// Errors from `input` are passed through unchanged, so a decompressor's complaints can be told
+/// apart from the archive being cut short. Anything after the end-of-archive marker (usually
+/// padding out to the record size) is left unread.
+pub fn verify(mut input: impl Read) -> io::Result<u64> {
+    let mut block = [0; BLOCK_LEN];
+    let mut entries = 0;
+    let (mut next_size, mut next_name) = (None, None);
+    loop {
+        match read_block(&mut input, &mut block)? {
+            Ok(()) => {},
+            Err(0) => return Err(corrupt(&format!(
+                "Tar archive truncated: no end-of-archive marker after {} entries", entries))),
+            Err(_) => return Err(corrupt("Tar archive truncated partway through a header")),
+        }
+
+        // The end of the archive is marked by two zeroed blocks, though a lone one before EOF is
+        // tolerated, as GNU tar does
+        if block.iter().all(|&x| x == 0) {
+            return match read_block(&mut input, &mut block)? {
+                Ok(()) if block.iter().all(|&x| x == 0) => Ok(entries),
+                Err(0) => Ok(entries),
+                _ => Err(corrupt(&format!("Zeroed block where tar header {} should be",
+                                          entries + 1))),
+            };
+        }
+
+        // (GNU tar uses the POSIX prefix field for other things, so only trust it with POSIX magic)
+        let mut name = field_text(&block[..100]).to_vec();
+        if &block[257..263] == b"ustar\0" && !field_text(&block[345..500]).is_empty() {
+            let mut prefixed = field_text(&block[345..500]).to_vec();
+            prefixed.push(b'/');
+            prefixed.extend(&name);
+            name = prefixed;
+        }
+        let name = next_name.take().unwrap_or(name);
+        let name = String::from_utf8_lossy(&name).into_owned();
+        if !checksum_matches(&block) {
+            return Err(corrupt(&format!("Tar header checksum mismatch for entry {:?}", name)));
+        }
+        let size = parse_number(&block[124..136])
+            .ok_or_else(|| corrupt(&format!("Invalid size in tar header for entry {:?}", name)))?;
+        let type_flag = block[156];
+        let size = if type_flag == b'x' || type_flag == b'L' {
+            size
+        } else {
+            next_size.take().unwrap_or(size)
+        };
+
+        match type_flag {
+            b'x' => {
+                let data = read_meta(&mut input, size, "pax extended header")?;
+                let (size, path) = parse_pax(&data)?;
+                next_size = size;
+                next_name = path;
+            },
+            b'L' => {
+                next_name = Some(field_text(&read_meta(&mut input, size, "GNU long name")?)
+                                 .to_vec());
+            },
+            _ => {
+                if io::copy(&mut (&mut input).take(size), &mut io::sink())? != size {
+                    return Err(corrupt(&format!("Tar archive truncated in the data of entry {:?}",
+                                                name)));
+                }
+
+                // Global pax headers and GNU long link names describe other entries
+                if type_flag != b'g' && type_flag != b'K' {
+                    entries += 1;
+                }
+            },
+        }
+
+        // Skip the padding to the next block boundary
+        let padding = (BLOCK_LEN as u64 - size % BLOCK_LEN as u64) % BLOCK_LEN as u64;
+        if io::copy(&mut (&mut input).take(padding), &mut io::sink())? != padding {
+            return Err(corrupt(&format!("Tar archive truncated in the padding after entry {:?}",
+                                        name)));
+        }
+    }
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtin_handlers::GzipDecoder;
+
+    /// A single-entry archive written by GNU tar
+    const GOOD: &[u8] = include_bytes!("../../../test_data/good/testfile.tar");
+
+    /// Build a POSIX ustar header with a valid checksum
+    fn header(name: &str, size: usize, type_flag: u8) -> Vec<u8> {
+        let mut block = vec![0; BLOCK_LEN];
+        block[..name.len()].copy_from_slice(name.as_bytes());
+        block[124..136].copy_from_slice(format!("{:011o}\0", size).as_bytes());
+        block[156] = type_flag;
+        block[257..263].copy_from_slice(b"ustar\0");
+        block[263..265].copy_from_slice(b"00");
+        block[CHECKSUM_FIELD].copy_from_slice(b"        ");
+        let sum: u32 = block.iter().map(|&x| u32::from(x)).sum();
+        block[CHECKSUM_FIELD].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+        block
+    }
+
+    /// Append an entry with the given data, padded to a whole number of blocks
+    fn entry(archive: &mut Vec<u8>, name: &str, type_flag: u8, data: &[u8]) {
+        archive.extend(header(name, data.len(), type_flag));
+        archive.extend(data);
+        archive.resize((archive.len() + BLOCK_LEN - 1) / BLOCK_LEN * BLOCK_LEN, 0);
+    }
+
+    /// Walk `input`, returning the error message on failure
+    fn walk(input: &[u8]) -> Result<u64, String> {
+        verify(input).map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn test_numbers() {
+        assert_eq!(parse_number(b"00000001750\0"), Some(1000));
+        assert_eq!(parse_number(b"  1750 \0\0\0\0\0"), Some(1000));
+        assert_eq!(parse_number(b"\0\0\0\0\0\0\0\0"), Some(0));
+        assert_eq!(parse_number(b"00001750 1\0\0"), None);
+        assert_eq!(parse_number(b"00000001780\0"), None);
+        assert_eq!(parse_number(b"\x80\0\0\0\0\0\0\0\0\0\x03\xE8"), Some(1000));
+        assert_eq!(parse_number(b"\xFF\xFF\xFF\xFF\xFF\xFF\xFF\xFF\xFF\xFF\xFC\x18"), None);
+        assert_eq!(parse_number(b"\x80\x01\0\0\0\0\0\0\0\0\0\0"), None);
+    }
+
+    #[test]
+    fn test_fixtures() {
+        assert_eq!(walk(GOOD), Ok(1));
+        assert_eq!(walk(include_bytes!("../../../test_data/bad/testfile.tar")).unwrap_err(),
+                   "Tar header checksum mismatch for entry \"tdstfile.txt\"");
+    }
+
+    #[test]
+    fn test_truncation() {
+        assert_eq!(walk(&GOOD[..0]).unwrap_err(),
+                   "Tar archive truncated: no end-of-archive marker after 0 entries");
+        assert_eq!(walk(&GOOD[..100]).unwrap_err(),
+                   "Tar archive truncated partway through a header");
+        assert_eq!(walk(&GOOD[..520]).unwrap_err(),
+                   "Tar archive truncated in the data of entry \"testfile.txt\"");
+        assert_eq!(walk(&GOOD[..600]).unwrap_err(),
+                   "Tar archive truncated in the padding after entry \"testfile.txt\"");
+        assert_eq!(walk(&GOOD[..1024]).unwrap_err(),
+                   "Tar archive truncated: no end-of-archive marker after 1 entries");
+
+        // A lone zeroed block is tolerated at the end, but not before another header
+        assert_eq!(walk(&GOOD[..1536]), Ok(1));
+        let mut gap = GOOD[..1536].to_vec();
+        gap.extend(&GOOD[..1024]);
+        assert_eq!(walk(&gap).unwrap_err(), "Zeroed block where tar header 2 should be");
+    }
+
+    #[test]
+    fn test_extensions() {
+        let long_name = "long/".repeat(40);
+        let mut archive = Vec::new();
+        entry(&mut archive, "././@LongLink", b'L', format!("{}\0", long_name).as_bytes());
+        entry(&mut archive, "truncated", b'0', b"GNU");
+        entry(&mut archive, "pax", b'x', b"29 path=pax/with/a/long/name\n12 size=700\n");
+
+        // The pax size has to override the header's for the data not to be read as a header
+        let overridden = archive.len();
+        archive.extend(header("small", 0, b'0'));
+        entry(&mut archive, "", 0, &[b'x'; 700]);
+        archive.drain(overridden + BLOCK_LEN..overridden + 2 * BLOCK_LEN);
+
+        entry(&mut archive, "global", b'g', b"19 comment=ignored\n");
+        entry(&mut archive, "dir/", b'5', b"");
+        archive.resize(archive.len() + 2 * BLOCK_LEN, 0);
+        assert_eq!(walk(&archive), Ok(3));
+
+        assert_eq!(walk(&archive[..3 * BLOCK_LEN + 3]).unwrap_err(),
+                   format!("Tar archive truncated in the padding after entry {:?}", long_name));
+        assert_eq!(walk(&archive[..overridden + BLOCK_LEN + 600]).unwrap_err(),
+                   "Tar archive truncated in the data of entry \"pax/with/a/long/name\"");
+
+        let mut malformed = Vec::new();
+        entry(&mut malformed, "pax", b'x', b"99 path=wrong length\n");
+        assert_eq!(walk(&malformed).unwrap_err(), "Malformed pax extended header in tar archive");
+    }
+
+    #[test]
+    fn test_labelled() {
+        // A complete gzip stream holding a truncated archive
+        let truncated = include_bytes!("../../../test_data/bad/testfile.truncated.tgz");
+        let mut decoder = Labelled::new(GzipDecoder::new(&truncated[..]), "gzip");
+        assert_eq!(verify(&mut decoder).unwrap_err().to_string(),
+                   "Tar archive truncated in the data of entry \"testfile.txt\"");
+        assert!(!decoder.failed());
+
+        // A truncated gzip stream holding a complete archive
+        let good = include_bytes!("../../../test_data/good/testfile.tgz");
+        let mut decoder = Labelled::new(GzipDecoder::new(&good[..good.len() - 4]), "gzip");
+        assert_eq!(verify(&mut decoder).unwrap(), 1);
+        assert_eq!(io::copy(&mut decoder, &mut io::sink()).unwrap_err().to_string(),
+                   "Compressed stream (gzip): Unexpected end of file");
+        assert!(decoder.failed());
+    }
+}
@@ -0,0 +1,294 @@
+//! A checker for plaintext which catches invalid encoding and binary garbage spliced into text
+//!
+//! (UTF-8 is the main target, but UTF-16 and UTF-32 are also recognized, either by their BOMs or by
+//! the zero bytes an ASCII character leaves in the first code unit, so they don't look like text
+//! full of NULs. The price is that UTF-8 with a NUL as its second byte gets misread as UTF-16.)
+
+// Standard library imports
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Read};
+
+// Local Imports
+use super::corrupt;
+
+/// How many bytes to read at a time
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// How many characters the control-character density is measured over
+const CONTROL_WINDOW: u64 = 64;
+
+/// How many C0 control characters (other than tab, newline, and carriage return) to tolerate in
+/// any [`CONTROL_WINDOW`] characters
+///
+/// (High enough for things like form feeds between sections or ANSI colour codes in logs.)
+const CONTROL_LIMIT: usize = 16;
+
+/// The text encodings which can be checked
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Encoding {
+    /// UTF-8 (with or without a BOM)
+    Utf8,
+    /// UTF-16, with the flag set for big-endian
+    Utf16(bool),
+    /// UTF-32, with the flag set for big-endian
+    Utf32(bool),
+}
+
+impl Encoding {
+    /// Guess the encoding from the start of the file, returning it and the length of its BOM
+    fn detect(start: &[u8]) -> (Self, usize) {
+        match start {
+            [0, 0, 0xFE, 0xFF, ..] => (Self::Utf32(true), 4),
+            [0xFF, 0xFE, 0, 0, ..] => (Self::Utf32(false), 4),
+            [0xFE, 0xFF, ..] => (Self::Utf16(true), 2),
+            [0xFF, 0xFE, ..] => (Self::Utf16(false), 2),
+            [0xEF, 0xBB, 0xBF, ..] => (Self::Utf8, 3),
+            [0, 0, 0, c, ..] if *c != 0 => (Self::Utf32(true), 0),
+            [c, 0, 0, 0, ..] if *c != 0 => (Self::Utf32(false), 0),
+            [0, c, ..] if *c != 0 => (Self::Utf16(true), 0),
+            [c, 0, ..] if *c != 0 => (Self::Utf16(false), 0),
+            _ => (Self::Utf8, 0),
+        }
+    }
+
+    /// The size of a code unit in bytes
+    fn unit_size(self) -> usize {
+        match self {
+            Self::Utf8 => 1,
+            Self::Utf16(_) => 2,
+            Self::Utf32(_) => 4,
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Utf8 => "UTF-8",
+            Self::Utf16(false) => "UTF-16LE",
+            Self::Utf16(true) => "UTF-16BE",
+            Self::Utf32(false) => "UTF-32LE",
+            Self::Utf32(true) => "UTF-32BE",
+        })
+    }
+}
+
+/// The heuristic for recognizing binary data spliced into text
+#[derive(Default)]
+struct ControlCheck {
+    /// How many characters have been seen so far
+    chars: u64,
+    /// The character indexes and byte offsets of recent suspicious control characters
+    recent: VecDeque<(u64, u64)>,
+}
+
+impl ControlCheck {
+    /// Account for a character at byte offset `offset`
+    fn check(&mut self, offset: u64, ch: u32) -> io::Result<()> {
+        self.chars += 1;
+        if ch >= 0x20 || ch == u32::from(b'\t') || ch == u32::from(b'\n')
+                || ch == u32::from(b'\r') {
+            return Ok(());
+        }
+        if ch == 0 {
+            return Err(corrupt(&format!("NUL character in text at byte {}", offset)));
+        }
+
+        while self.recent.front().map_or(false, |&(idx, _)| idx + CONTROL_WINDOW < self.chars) {
+            self.recent.pop_front();
+        }
+        self.recent.push_back((self.chars, offset));
+        match self.recent.front() {
+            Some(&(_, first)) if self.recent.len() > CONTROL_LIMIT => Err(corrupt(&format!(
+                "Dense run of control characters in text starting at byte {}", first))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Read into `buf` until it's full or the input ends, returning how much was read
+fn fill(input: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match input.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(read) => len += read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {},
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(len)
+}
+
+/// Check that the text read from `input` is validly encoded and doesn't contain binary garbage
+///
+/// NUL characters, or more than [`CONTROL_LIMIT`] other C0 control characters (besides tab,
+/// newline, and carriage return) in any [`CONTROL_WINDOW`] characters, are reported as corruption
+/// along with the byte offset where they start.
+pub fn verify(mut input: impl Read) -> io::Result<()> {
+    let mut buf = vec![0; BUFFER_SIZE];
+    let mut len = fill(&mut input, &mut buf)?;
+    let (encoding, bom_len) = Encoding::detect(&buf[..len]);
+    let unit_size = encoding.unit_size();
+
+    let (mut controls, mut high_surrogate) = (ControlCheck::default(), None);
+    let (mut start, mut base) = (bom_len, 0_u64);
+    loop {
+        let at_eof = len < buf.len();
+        let mut end = len - (len - start) % unit_size;
+        if let Encoding::Utf8 = encoding {
+            match std::str::from_utf8(&buf[start..end]) {
+                Ok(_) => {},
+                Err(err) if err.error_len().is_none() => end = start + err.valid_up_to(),
+                Err(err) => {
+                    let valid_end = start + err.valid_up_to();
+                    for (idx, &byte) in buf[start..valid_end].iter().enumerate() {
+                        controls.check(base + (start + idx) as u64, u32::from(byte))?;
+                    }
+                    return Err(corrupt(&format!(
+                        "Invalid UTF-8 in text at byte {}", base + valid_end as u64)));
+                },
+            }
+            // Checking the bytes one by one is enough, since every control character is ASCII
+            for (idx, &byte) in buf[start..end].iter().enumerate() {
+                controls.check(base + (start + idx) as u64, u32::from(byte))?;
+            }
+        } else {
+            for (idx, unit) in buf[start..end].chunks(unit_size).enumerate() {
+                let offset = base + (start + idx * unit_size) as u64;
+                let value = match encoding {
+                    Encoding::Utf16(true) | Encoding::Utf32(true) =>
+                        unit.iter().fold(0, |acc, &byte| (acc << 8) | u32::from(byte)),
+                    _ => unit.iter().rev().fold(0, |acc, &byte| (acc << 8) | u32::from(byte)),
+                };
+                let ch = match (encoding, high_surrogate.take()) {
+                    (Encoding::Utf16(_), None) if (0xD800..0xDC00).contains(&value) => {
+                        high_surrogate = Some(value);
+                        continue;
+                    },
+                    (Encoding::Utf16(_), Some(high)) if (0xDC00..0xE000).contains(&value) =>
+                        0x10000 + ((high - 0xD800) << 10) + (value - 0xDC00),
+                    (_, None) if value < 0xD800 || (0xE000..0x11_0000).contains(&value) => value,
+                    _ => return Err(corrupt(&format!(
+                        "Invalid {} in text at byte {}", encoding, offset))),
+                };
+                controls.check(offset, ch)?;
+            }
+        }
+
+        if at_eof {
+            if end < len || high_surrogate.is_some() {
+                return Err(corrupt(&format!("Text ends in the middle of a {} character",
+                                            encoding)));
+            }
+            return Ok(());
+        }
+        buf.copy_within(end..len, 0);
+        base += end as u64;
+        len -= end;
+        start = 0;
+        len += fill(&mut input, &mut buf[len..])?;
+    }
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Verify `input`, returning the error message on failure
+    fn verify_bytes(input: &[u8]) -> Result<(), String> {
+        verify(input).map_err(|err| {
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            err.to_string()
+        })
+    }
+
+    /// A reader which returns at most one byte per call, to exercise the buffer boundaries
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(self.0.len()).min(1);
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn test_utf8() {
+        for good in &["", "Hello", "\u{FEFF}Hello", "tab\there\r\n", "\u{C}Page\u{C}",
+                      "Ünïcødé 💾"] {
+            assert_eq!(verify_bytes(good.as_bytes()), Ok(()), "{:?}", good);
+        }
+        assert_eq!(verify_bytes(b"Testing\x00123").unwrap_err(), "NUL character in text at byte 7");
+        assert_eq!(verify_bytes(b"Caf\xC3").unwrap_err(),
+                   "Text ends in the middle of a UTF-8 character");
+        assert_eq!(verify_bytes(b"Caf\xE9 au lait").unwrap_err(),
+                   "Invalid UTF-8 in text at byte 3");
+
+        // A multi-byte character split across the internal buffer boundary
+        let mut split = vec![b'a'; BUFFER_SIZE - 1];
+        split.extend("💾 and more".as_bytes());
+        assert_eq!(verify_bytes(&split), Ok(()));
+        split[BUFFER_SIZE + 1] = b'!';
+        assert_eq!(verify_bytes(&split).unwrap_err(),
+                   format!("Invalid UTF-8 in text at byte {}", BUFFER_SIZE - 1));
+        assert!(verify(Trickle("Ünïcødé 💾".as_bytes())).is_ok());
+    }
+
+    #[test]
+    fn test_control_density() {
+        let mut ansi = Vec::new();
+        for _ in 0..100 {
+            ansi.extend(b"\x1b[1;32mINFO\x1b[0m Something happened\n");
+        }
+        assert_eq!(verify_bytes(&ansi), Ok(()));
+
+        let mut spliced = b"Some text, then ".to_vec();
+        spliced.extend((1..=24_u8).cycle().take(200));
+        spliced.extend(b" and more text");
+        assert_eq!(verify_bytes(&spliced).unwrap_err(),
+                   "Dense run of control characters in text starting at byte 16");
+    }
+
+    #[test]
+    fn test_utf16_and_utf32() {
+        let text = "Testing 💾";
+        let utf16le: Vec<u8> = text.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        let utf16be: Vec<u8> = text.encode_utf16().flat_map(u16::to_be_bytes).collect();
+        let utf32le: Vec<u8> = text.chars().flat_map(|ch| u32::from(ch).to_le_bytes()).collect();
+        for good in &[&utf16le, &utf16be, &utf32le] {
+            assert_eq!(verify_bytes(good), Ok(()));
+            assert!(verify_bytes(&good[..good.len() - 1]).is_err());
+        }
+        assert_eq!(verify_bytes(&utf16le[..utf16le.len() - 2]).unwrap_err(),
+                   "Text ends in the middle of a UTF-16LE character");
+        assert!(verify_bytes(&[0xFF, 0xFE, b'a', 0, 0x00, 0xDC]).is_err());
+        assert!(verify_bytes(&[b'a', 0, 0, 0, 0, 0, 0x11, 0]).is_err());
+    }
+
+    #[test]
+    fn test_text_fixtures() {
+        for (good, bad) in &[
+            (&include_bytes!("../../../test_data/good/testfile.utf8.txt")[..],
+             &include_bytes!("../../../test_data/bad/testfile.utf8.txt")[..]),
+            (include_bytes!("../../../test_data/good/testfile.utf8-bom.txt"),
+             include_bytes!("../../../test_data/bad/testfile.utf8-bom.txt")),
+            (include_bytes!("../../../test_data/good/testfile.utf-16-le.txt"),
+             include_bytes!("../../../test_data/bad/testfile.utf-16-le.txt")),
+            (include_bytes!("../../../test_data/good/testfile.utf-16-be-bom.txt"),
+             include_bytes!("../../../test_data/bad/testfile.utf-16-be-bom.txt")),
+            (include_bytes!("../../../test_data/good/testfile.utf-32-be.txt"),
+             include_bytes!("../../../test_data/bad/testfile.utf-32-be.txt")),
+            (include_bytes!("../../../test_data/good/testfile.utf-32-le-bom.txt"),
+             include_bytes!("../../../test_data/bad/testfile.utf-32-le-bom.txt")),
+        ] {
+            assert_eq!(verify_bytes(good), Ok(()));
+            assert!(verify_bytes(bad).unwrap_err().starts_with("NUL character"));
+        }
+    }
+}
@@ -0,0 +1,128 @@
+//! A well-formedness check for whole TOML documents
+//!
+//! (The file is parsed as a complete document by `toml_edit`, so problems which only show up at
+//! the document level, like duplicate keys or a table defined twice, are caught too.)
+
+// Standard library imports
+use std::io::{self, Read};
+use std::str;
+
+// Local Imports
+use super::{corrupt, unsupported};
+
+/// The largest document which will be read into memory for parsing
+///
+/// (`toml_edit` needs the whole document as a `&str`. Anything bigger than this is far more likely
+/// to be something else with the wrong extension than a real TOML file.)
+const MAX_SIZE: u64 = 64 * 1024 * 1024;
+
+/// How much to read at a time, so binary files fail the UTF-8 check without being read in full
+const CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Turn a byte offset into `text` into 1-based line and column numbers, counting characters
+fn position(text: &str, offset: usize) -> (usize, usize) {
+    let before = text.get(..offset).unwrap_or(text);
+    let line_start = before.rfind('\n').map_or(0, |idx| idx + 1);
+    let column = before.get(line_start..).map_or(0, |line| line.chars().count());
+    (before.matches('\n').count() + 1, column + 1)
+}
+
+/// Read all of `input` as UTF-8, checking each chunk as it arrives and refusing to read more than
+/// [`MAX_SIZE`] bytes
+fn read_limited(mut input: impl Read) -> io::Result<String> {
+    let mut data = Vec::new();
+    let mut checked = 0;
+    loop {
+        let read = (&mut input).take(CHUNK_SIZE).read_to_end(&mut data)?;
+        match str::from_utf8(data.get(checked..).unwrap_or_default()) {
+            Ok(_) => checked = data.len(),
+            // An incomplete sequence at the end of a chunk may be finished by the next one
+            Err(err) if err.error_len().is_none() && read > 0 => checked += err.valid_up_to(),
+            Err(err) => {
+                let offset = checked + err.valid_up_to();
+                let valid = str::from_utf8(data.get(..offset).unwrap_or_default())
+                    .map_err(|_| corrupt("invalid UTF-8"))?;
+                let (line, column) = position(valid, offset);
+                return Err(corrupt(&format!(
+                    "TOML error at line {} column {}: invalid UTF-8", line, column)));
+            },
+        }
+        if read == 0 {
+            break;
+        }
+        if data.len() as u64 > MAX_SIZE {
+            return Err(unsupported(&format!(
+                "Refusing to parse a TOML document larger than {} MiB", MAX_SIZE / 1024 / 1024)));
+        }
+    }
+    String::from_utf8(data).map_err(|_| corrupt("invalid UTF-8"))
+}
+
+/// Check that `input` is a well-formed TOML document, reporting the position of any error
+pub fn verify(input: impl Read) -> io::Result<()> {
+    let text = read_limited(input)?;
+    text.parse::<toml_edit::DocumentMut>().map_err(|err| {
+        let (line, column) = position(&text, err.span().map_or(0, |span| span.start));
+        corrupt(&format!("TOML error at line {} column {}: {}",
+                         line, column, err.message().trim_end()))
+    })?;
+    Ok(())
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::*;
+
+    #[test]
+    fn test_position() {
+        assert_eq!(position("", 0), (1, 1));
+        assert_eq!(position("a = 1\nb = 2", 6), (2, 1));
+        assert_eq!(position("a = 1\nb = 2", 10), (2, 5));
+        assert_eq!(position("\u{e9} = 1", 3), (1, 3));
+    }
+
+    #[test]
+    fn test_good_documents() {
+        for (name, data) in &[
+            ("empty", &b""[..]),
+            ("comment", b"# Nothing but a comment\n"),
+            ("tables", b"title = \"x\"\n\n[a]\nb = 1\nc.d = [1, 2]\n\n[[e]]\nf = true\n[[e]]\n"),
+            ("non-ASCII", "name = \"\u{e9}t\u{e9}\"\n".as_bytes()),
+        ] {
+            assert_eq!(verify(*data).map_err(|err| err.to_string()), Ok(()), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_bad_documents() {
+        for (name, data, message) in &[
+            ("duplicate key", &b"a = 1\nb = 2\na = 3\n"[..], "at line 3 column 1: duplicate key"),
+            ("redefined table", b"[a]\nb = 1\n\n[a]\nc = 2\n", "at line 4 column 1: invalid table"),
+            ("bare value", b"\"just a string\"\n", "at line 1 column 1"),
+            ("unterminated", b"a = [1, 2\n", "at line 2 column 1"),
+            ("bad UTF-8", b"a = 1\nb = \"\xC3\x28\"\n", "at line 2 column 6: invalid UTF-8"),
+            ("truncated UTF-8", b"a = \"\xC3", "at line 1 column 6: invalid UTF-8"),
+        ] {
+            let err = verify(*data).expect_err(name);
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}: {}", name, err);
+            assert!(err.to_string().contains(message), "{}: {}", name, err);
+        }
+    }
+
+    #[test]
+    fn test_size_limit() {
+        let mut chunk_edge = vec![b'#'; usize::try_from(CHUNK_SIZE).unwrap() - 1];
+        chunk_edge.extend_from_slice("\u{e9}\n".as_bytes());
+        assert_eq!(verify(&chunk_edge[..]).map_err(|err| err.to_string()), Ok(()));
+
+        let err = verify(io::repeat(b'#')).expect_err("endless input");
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported, "{}", err);
+
+        let err = verify(b"\xFF".chain(io::repeat(b'#'))).expect_err("endless binary");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", err);
+    }
+}
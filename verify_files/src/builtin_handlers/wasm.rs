@@ -0,0 +1,1587 @@
+//! A validator for WebAssembly modules
+//!
+//! (WebAssembly has no checksums, so this decodes the whole module and checks it the way a
+//! validator would: sections must come in the right order and be exactly as long as they claim,
+//! every index must refer to something which exists, and every function body and constant
+//! expression must type-check, following the validation algorithm in the spec's appendix.
+//! Nothing is ever compiled or instantiated.)
+
+// Standard library imports
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, BufReader, Read};
+
+// Local Imports
+use super::{corrupt, unsupported};
+
+/// The magic number at the start of every WebAssembly binary
+const MAGIC: &[u8; 4] = b"\0asm";
+
+/// The version and layer fields of a core module
+const MODULE_VERSION: [u8; 4] = [1, 0, 0, 0];
+
+/// The layer field which marks a binary as a component rather than a core module
+const COMPONENT_LAYER: [u8; 2] = [1, 0];
+
+/// The position of each non-custom section in the order they must appear in, indexed by ID
+///
+/// (The tag section from the exception handling proposal would go between memory and global.)
+const SECTION_ORDER: [u8; 13] = [0, 1, 2, 3, 4, 5, 7, 8, 9, 10, 12, 13, 11];
+
+/// The name of each section, indexed by ID, for error messages
+const SECTION_NAMES: [&str; 13] = ["custom", "type", "import", "function", "table", "memory",
+    "global", "export", "start", "element", "code", "data", "data count"];
+
+/// The largest number of 64KiB pages a 32-bit memory can have
+const MAX_PAGES: u64 = 65536;
+
+/// The most parameters or results a function type can have
+///
+/// (The spec has no limit, but this is the one V8 and `wasmparser` enforce, and it bounds how
+/// much work checking each block or call can take.)
+const MAX_TYPE_LEN: u32 = 1000;
+
+/// The types of value which can be operands, locals, globals, or table elements
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ValType {
+    /// A 32-bit integer
+    I32,
+    /// A 64-bit integer
+    I64,
+    /// A 32-bit float
+    F32,
+    /// A 64-bit float
+    F64,
+    /// A 128-bit SIMD vector
+    V128,
+    /// A reference to a function
+    FuncRef,
+    /// A reference to something provided by the host
+    ExternRef,
+}
+
+impl ValType {
+    /// Whether this is a reference type
+    fn is_ref(self) -> bool {
+        matches!(self, Self::FuncRef | Self::ExternRef)
+    }
+}
+
+impl fmt::Display for ValType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::I32 => "i32",
+            Self::I64 => "i64",
+            Self::F32 => "f32",
+            Self::F64 => "f64",
+            Self::V128 => "v128",
+            Self::FuncRef => "funcref",
+            Self::ExternRef => "externref",
+        })
+    }
+}
+
+/// The parameter and result types of a function type
+struct FuncType {
+    /// The types of the parameters
+    params: Vec<ValType>,
+    /// The types of the results
+    results: Vec<ValType>,
+}
+
+/// The type of a block
+#[derive(Clone, Copy)]
+enum BlockType {
+    /// No parameters or results
+    Empty,
+    /// No parameters and one result
+    Value(ValType),
+    /// The function type with this index
+    Func(u32),
+}
+
+impl BlockType {
+    /// The types of the block's parameters
+    fn params<'a>(&'a self, module: &'a Module) -> &'a [ValType] {
+        match self {
+            Self::Empty | Self::Value(_) => &[],
+            Self::Func(index) => &module.types[*index as usize].params,
+        }
+    }
+
+    /// The types of the block's results
+    fn results<'a>(&'a self, module: &'a Module) -> &'a [ValType] {
+        match self {
+            Self::Empty => &[],
+            Self::Value(ty) => std::slice::from_ref(ty),
+            Self::Func(index) => &module.types[*index as usize].results,
+        }
+    }
+}
+
+/// The kinds of block an instruction sequence can be nested in
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum BlockKind {
+    /// A function body or `block`
+    Block,
+    /// A `loop`, which branches go to the start of
+    Loop,
+    /// An `if` which hasn't had its `else` yet
+    If,
+    /// The `else` branch of an `if`
+    Else,
+}
+
+/// A block on the control stack
+#[derive(Clone, Copy)]
+struct Frame {
+    /// What kind of block this is
+    kind: BlockKind,
+    /// The block's type
+    ty: BlockType,
+    /// How many operands were on the stack below the block's parameters
+    height: usize,
+    /// Whether the rest of the block is unreachable, so operands can be popped which aren't there
+    unreachable: bool,
+}
+
+impl Frame {
+    /// The types of the operands which a branch to this block passes along
+    fn label_types<'a>(&'a self, module: &'a Module) -> &'a [ValType] {
+        if self.kind == BlockKind::Loop { self.ty.params(module) } else { self.ty.results(module) }
+    }
+}
+
+/// The types of a function's locals
+struct Locals<'a> {
+    /// The types of the function's parameters, which are its first locals
+    params: &'a [ValType],
+    /// The rest of the locals, as runs of `(index after the run's last local, type)`
+    runs: Vec<(u64, ValType)>,
+}
+
+impl Locals<'_> {
+    /// The type of local `index`, if there is one
+    fn get(&self, index: u32) -> Option<ValType> {
+        if let Some(ty) = self.params.get(index as usize) {
+            return Some(*ty);
+        }
+        let run = self.runs.partition_point(|&(end, _)| end <= u64::from(index));
+        self.runs.get(run).map(|&(_, ty)| ty)
+    }
+}
+
+/// The operand and control stacks used to check the types in a function body
+struct Stack {
+    /// The operands' types, where `None` is one of unknown type popped in unreachable code
+    operands: Vec<Option<ValType>>,
+    /// The blocks the current instruction is nested in, outermost first
+    frames: Vec<Frame>,
+}
+
+impl Stack {
+    /// Push an operand of type `ty`
+    fn push(&mut self, ty: ValType) {
+        self.operands.push(Some(ty));
+    }
+
+    /// Push operands of types `types`
+    fn push_all(&mut self, types: &[ValType]) {
+        self.operands.extend(types.iter().copied().map(Some));
+    }
+
+    /// Pop an operand of any type
+    fn pop_any<R: Read>(&mut self, reader: &Reader<R>) -> io::Result<Option<ValType>> {
+        let frame = self.frames.last().expect("only called inside a block");
+        if self.operands.len() > frame.height {
+            Ok(self.operands.pop().expect("the stack is above the block's height"))
+        } else if frame.unreachable {
+            Ok(None)
+        } else {
+            Err(reader.corrupt("function has a type mismatch (an instruction is missing an \
+                operand)"))
+        }
+    }
+
+    /// Pop an operand of type `expected`
+    fn pop<R: Read>(&mut self, reader: &Reader<R>, expected: ValType)
+            -> io::Result<Option<ValType>> {
+        match self.pop_any(reader)? {
+            Some(actual) if actual != expected => Err(reader.mismatch(expected, actual)),
+            actual => Ok(actual),
+        }
+    }
+
+    /// Pop operands of types `types`, the last of which is on top of the stack
+    fn pop_all<R: Read>(&mut self, reader: &Reader<R>, types: &[ValType]) -> io::Result<()> {
+        for ty in types.iter().rev() {
+            self.pop(reader, *ty)?;
+        }
+        Ok(())
+    }
+
+    /// Pop operands of types `params` and push results of types `results`
+    fn apply<R: Read>(&mut self, reader: &Reader<R>, params: &[ValType], results: &[ValType])
+            -> io::Result<()> {
+        self.pop_all(reader, params)?;
+        self.push_all(results);
+        Ok(())
+    }
+
+    /// Mark the rest of the innermost block as unreachable, discarding its operands
+    fn unreachable(&mut self) {
+        let frame = self.frames.last_mut().expect("only called inside a block");
+        self.operands.truncate(frame.height);
+        frame.unreachable = true;
+    }
+
+    /// Start a block of type `ty`, whose parameters have already been popped
+    fn push_frame(&mut self, module: &Module, kind: BlockKind, ty: BlockType) {
+        self.frames.push(Frame { kind, ty, height: self.operands.len(), unreachable: false });
+        self.push_all(ty.params(module));
+    }
+
+    /// End the innermost block, popping its results
+    fn pop_frame<R: Read>(&mut self, reader: &Reader<R>, module: &Module) -> io::Result<Frame> {
+        let frame = *self.frames.last().expect("only called inside a block");
+        self.pop_all(reader, frame.ty.results(module))?;
+        if self.operands.len() != frame.height {
+            return Err(reader.corrupt("function has a type mismatch (a block leaves extra \
+                operands on the stack)"));
+        }
+        self.frames.pop();
+        Ok(frame)
+    }
+}
+
+/// What the module defines, as needed to check references into it and the types they imply
+#[derive(Default)]
+struct Module {
+    /// The function types
+    types: Vec<FuncType>,
+    /// The type index of each function, imported functions first
+    functions: Vec<u32>,
+    /// How many of `functions` are imported
+    imported_functions: usize,
+    /// How many function bodies the code section has held
+    bodies: usize,
+    /// The element type of each table
+    tables: Vec<ValType>,
+    /// The number of memories
+    memories: u32,
+    /// The type of each global and whether it's mutable, imported globals first
+    globals: Vec<(ValType, bool)>,
+    /// How many of `globals` are imported
+    imported_globals: usize,
+    /// The type of each element segment
+    elements: Vec<ValType>,
+    /// The functions which function bodies may use `ref.func` on, because something outside a
+    /// function body refers to them
+    refs: HashSet<u32>,
+    /// The number of data segments promised by the data count section, if there is one
+    data_count: Option<u32>,
+    /// Whether the data section was present
+    has_data: bool,
+}
+
+/// A reader which tracks its offset in the module (for error messages) and the end of the
+/// section (or function body) being read
+struct Reader<R> {
+    /// The module being read
+    input: R,
+    /// How many bytes of the module have been read
+    pos: u64,
+    /// The offset at which the section being read ends
+    end: u64,
+}
+
+impl<R: Read> Reader<R> {
+    /// Build an error saying the module is corrupted, giving the current offset
+    fn corrupt(&self, message: &str) -> io::Error {
+        corrupt(&format!("WebAssembly {} (at offset {:#x})", message, self.pos))
+    }
+
+    /// Build an error saying the module uses something unsupported, giving the current offset
+    fn unsupported(&self, message: &str) -> io::Error {
+        unsupported(&format!("WebAssembly module uses {} (at offset {:#x})", message, self.pos))
+    }
+
+    /// Build an error saying an instruction found an operand of the wrong type
+    fn mismatch(&self, expected: ValType, actual: ValType) -> io::Error {
+        self.corrupt(&format!("function has a type mismatch (expected {expected} but found \
+            {actual})"))
+    }
+
+    /// Read one byte, or return `None` at the end of the module
+    fn try_byte(&mut self) -> io::Result<Option<u8>> {
+        if self.pos >= self.end {
+            return Err(self.corrupt("section contents run past the end of the section"));
+        }
+        let mut byte = [0];
+        loop {
+            match self.input.read(&mut byte) {
+                Ok(0) => return Ok(None),
+                Ok(_) => break,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {},
+                Err(err) => return Err(err),
+            }
+        }
+        self.pos += 1;
+        Ok(Some(byte[0]))
+    }
+
+    /// Read one byte
+    fn byte(&mut self) -> io::Result<u8> {
+        self.try_byte()?.ok_or_else(|| self.corrupt("module is truncated"))
+    }
+
+    /// Skip `len` bytes
+    fn skip(&mut self, len: u64) -> io::Result<()> {
+        if self.end - self.pos < len {
+            return Err(self.corrupt("section contents run past the end of the section"));
+        }
+        let skipped = io::copy(&mut (&mut self.input).take(len), &mut io::sink())?;
+        self.pos += skipped;
+        if skipped < len {
+            return Err(self.corrupt("module is truncated"));
+        }
+        Ok(())
+    }
+
+    /// Read an unsigned LEB128 integer of at most `bits` bits
+    fn unsigned(&mut self, bits: u32) -> io::Result<u64> {
+        let (mut value, mut shift) = (0, 0);
+        loop {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7F) << shift;
+            if byte & 0x80 == 0 {
+                if shift + 7 > bits && u32::from(byte) >> (bits - shift) != 0 {
+                    return Err(self.corrupt("integer is too large"));
+                }
+                return Ok(value);
+            }
+            shift += 7;
+            if shift >= bits {
+                return Err(self.corrupt("integer representation is too long"));
+            }
+        }
+    }
+
+    /// Read a signed LEB128 integer of at most `bits` bits, the first byte of which is `first`
+    fn signed_from(&mut self, bits: u32, first: u8) -> io::Result<i64> {
+        let (mut value, mut shift, mut byte) = (0, 0, first);
+        loop {
+            value |= i64::from(byte & 0x7F) << shift;
+            if byte & 0x80 == 0 {
+                // Any bits past `bits` must just be copies of the sign bit
+                let used = bits.saturating_sub(shift).min(7);
+                let payload = i64::from(i8::from_ne_bytes([byte << 1]) >> 1);
+                if used < 7 && payload >> (used - 1) != 0 && payload >> (used - 1) != -1 {
+                    return Err(self.corrupt("integer is too large"));
+                }
+                shift += 7;
+                if shift < 64 && byte & 0x40 != 0 {
+                    value |= -1 << shift;
+                }
+                return Ok(value);
+            }
+            shift += 7;
+            if shift >= bits {
+                return Err(self.corrupt("integer representation is too long"));
+            }
+            byte = self.byte()?;
+        }
+    }
+
+    /// Read a signed LEB128 integer of at most `bits` bits
+    fn signed(&mut self, bits: u32) -> io::Result<i64> {
+        let first = self.byte()?;
+        self.signed_from(bits, first)
+    }
+
+    /// Read a `u32`, as used for counts, sizes, and indices
+    fn u32(&mut self) -> io::Result<u32> {
+        self.unsigned(32).map(|x| u32::try_from(x).expect("32 bits"))
+    }
+
+    /// Read an index and check that it's less than `count`, calling it `what` in messages
+    fn index(&mut self, count: usize, what: &str) -> io::Result<u32> {
+        let index = self.u32()?;
+        if index as usize >= count {
+            return Err(self.corrupt(&format!(
+                "module refers to {what} {index}, which doesn't exist")));
+        }
+        Ok(index)
+    }
+
+    /// Read a function index, returning the function's type
+    fn function<'a>(&mut self, module: &'a Module) -> io::Result<&'a FuncType> {
+        let index = self.index(module.functions.len(), "function")?;
+        Ok(&module.types[module.functions[index as usize] as usize])
+    }
+
+    /// Read a type index, returning the type
+    fn func_type<'a>(&mut self, module: &'a Module) -> io::Result<&'a FuncType> {
+        let index = self.index(module.types.len(), "type")?;
+        Ok(&module.types[index as usize])
+    }
+
+    /// Read a table index, returning the table's element type
+    fn table(&mut self, module: &Module) -> io::Result<ValType> {
+        let index = self.index(module.tables.len(), "table")?;
+        Ok(module.tables[index as usize])
+    }
+
+    /// Read an element segment index, returning the segment's type
+    fn element(&mut self, module: &Module) -> io::Result<ValType> {
+        let index = self.index(module.elements.len(), "element segment")?;
+        Ok(module.elements[index as usize])
+    }
+
+    /// Read a global index, returning the global's type and whether it's mutable
+    fn global(&mut self, module: &Module) -> io::Result<(ValType, bool)> {
+        let index = self.index(module.globals.len(), "global")?;
+        Ok(module.globals[index as usize])
+    }
+
+    /// Read a local index, returning the local's type
+    fn local(&mut self, locals: &Locals<'_>) -> io::Result<ValType> {
+        let index = self.u32()?;
+        locals.get(index).ok_or_else(|| self.corrupt(&format!(
+            "module refers to local {index}, which doesn't exist")))
+    }
+
+    /// Read a name, checking that it's valid UTF-8
+    fn name(&mut self) -> io::Result<String> {
+        let len = u64::from(self.u32()?);
+        if self.end - self.pos < len {
+            return Err(self.corrupt("name runs past the end of the section"));
+        }
+        let mut name = vec![0; usize::try_from(len).expect("no longer than the section")];
+        self.input.read_exact(&mut name).map_err(|err| match err.kind() {
+            io::ErrorKind::UnexpectedEof => self.corrupt("module is truncated"),
+            _ => err,
+        })?;
+        self.pos += len;
+        String::from_utf8(name).map_err(|_| self.corrupt("name isn't valid UTF-8"))
+    }
+
+    /// Read a value type, given its first byte
+    fn value_type(&self, byte: u8) -> io::Result<ValType> {
+        match byte {
+            0x7F => Ok(ValType::I32),
+            0x7E => Ok(ValType::I64),
+            0x7D => Ok(ValType::F32),
+            0x7C => Ok(ValType::F64),
+            0x7B => Ok(ValType::V128),
+            0x70 => Ok(ValType::FuncRef),
+            0x6F => Ok(ValType::ExternRef),
+            0x63 | 0x64 | 0x69..=0x6E | 0x71..=0x74 => Err(self.unsupported(
+                "a reference type from the typed function references or GC proposals")),
+            _ => Err(self.corrupt(&format!("module has an invalid value type: {byte:#04x}"))),
+        }
+    }
+
+    /// Read the parameter or result types of a function type
+    fn value_types(&mut self) -> io::Result<Vec<ValType>> {
+        let count = self.u32()?;
+        if count > MAX_TYPE_LEN {
+            return Err(self.unsupported(&format!(
+                "a function type with more than {MAX_TYPE_LEN} parameters or results")));
+        }
+        (0..count).map(|_| {
+            let byte = self.byte()?;
+            self.value_type(byte)
+        }).collect()
+    }
+
+    /// Read a reference type, given its first byte
+    fn ref_type_from(&self, byte: u8) -> io::Result<ValType> {
+        match byte {
+            0x7B..=0x7F => Err(self.corrupt("module uses a number type as a reference type")),
+            _ => self.value_type(byte),
+        }
+    }
+
+    /// Read a reference type
+    fn ref_type(&mut self) -> io::Result<ValType> {
+        let byte = self.byte()?;
+        self.ref_type_from(byte)
+    }
+
+    /// Read the limits of a table or memory
+    fn limits(&mut self, is_memory: bool) -> io::Result<()> {
+        let flags = self.byte()?;
+        let has_max = match flags {
+            0 | 1 => flags == 1,
+            2 | 3 if is_memory => {
+                return Err(self.unsupported("a shared memory (threads proposal)"));
+            },
+            4..=7 => return Err(self.unsupported("64-bit limits (memory64 proposal)")),
+            _ => {
+                return Err(self.corrupt(&format!("module has invalid limits flags: {flags:#04x}")));
+            },
+        };
+        let min = u64::from(self.u32()?);
+        let max = if has_max { Some(u64::from(self.u32()?)) } else { None };
+        if is_memory && max.unwrap_or(min).max(min) > MAX_PAGES {
+            return Err(self.corrupt("memory is larger than 4GiB"));
+        }
+        if max.is_some_and(|max| max < min) {
+            return Err(self.corrupt("limits have a maximum below their minimum"));
+        }
+        Ok(())
+    }
+
+    /// Read the type of a global, returning its value type and whether it's mutable
+    fn global_type(&mut self) -> io::Result<(ValType, bool)> {
+        let byte = self.byte()?;
+        let ty = self.value_type(byte)?;
+        match self.byte()? {
+            mutability @ (0 | 1) => Ok((ty, mutability == 1)),
+            _ => Err(self.corrupt("global has invalid mutability")),
+        }
+    }
+
+    /// Read the type of a block, which is empty, a value type, or a type index
+    fn block_type(&mut self, module: &Module) -> io::Result<BlockType> {
+        match self.byte()? {
+            0x40 => Ok(BlockType::Empty),
+            byte if byte & 0xC0 == 0x40 => self.value_type(byte).map(BlockType::Value),
+            byte => {
+                let index = self.signed_from(33, byte)?;
+                match u32::try_from(index) {
+                    Ok(index) if (index as usize) < module.types.len() => {
+                        Ok(BlockType::Func(index))
+                    },
+                    _ => Err(self.corrupt(&format!(
+                        "module refers to type {index}, which doesn't exist"))),
+                }
+            },
+        }
+    }
+
+    /// Read the immediates of a load or store whose natural alignment is `2^natural` bytes
+    fn mem_arg(&mut self, module: &Module, natural: u32) -> io::Result<()> {
+        let mut align = self.u32()?;
+        let memory = if align & 0x40 == 0 { 0 } else { self.u32()? };
+        align &= !0x40;
+        if memory >= module.memories {
+            return Err(self.corrupt(&format!(
+                "module refers to memory {memory}, which doesn't exist")));
+        }
+        if align > natural {
+            return Err(self.corrupt("memory access is aligned more than its natural alignment"));
+        }
+        self.u32().map(|_| ())
+    }
+
+    /// Read a lane index for a vector with `lanes` lanes
+    fn lane(&mut self, lanes: u8) -> io::Result<()> {
+        if self.byte()? >= lanes {
+            return Err(self.corrupt("function has a lane index which is out of range"));
+        }
+        Ok(())
+    }
+
+    /// Read a branch target, returning the block it refers to
+    fn label(&mut self, stack: &Stack) -> io::Result<Frame> {
+        let depth = self.index(stack.frames.len(), "branch label")?;
+        Ok(stack.frames[stack.frames.len() - 1 - depth as usize])
+    }
+
+    /// Read a constant expression which must produce a value of type `expected`, returning the
+    /// function it refers to with `ref.func`, if any
+    ///
+    /// (No constant instruction consumes a reference, so a valid constant expression can't refer
+    /// to more than one function.)
+    fn const_expr(&mut self, module: &Module, expected: ValType) -> io::Result<Option<u32>> {
+        use ValType::{F32, F64, I32, I64, V128};
+        let mut stack = Vec::new();
+        let mut function = None;
+        loop {
+            let opcode = self.byte()?;
+            let ty = match opcode {
+                0x0B => break,
+                0x23 => {
+                    let index = self.index(module.globals.len(), "global")?;
+                    match module.globals[index as usize] {
+                        (ty, false) if (index as usize) < module.imported_globals => ty,
+                        _ => return Err(self.corrupt("constant expression refers to a global \
+                            which isn't an immutable import")),
+                    }
+                },
+                0x41 => {
+                    self.signed(32)?;
+                    I32
+                },
+                0x42 => {
+                    self.signed(64)?;
+                    I64
+                },
+                0x43 => {
+                    self.skip(4)?;
+                    F32
+                },
+                0x44 => {
+                    self.skip(8)?;
+                    F64
+                },
+                // `add`, `sub`, and `mul`, from the extended constant expressions proposal
+                0x6A..=0x6C | 0x7C..=0x7E => {
+                    let ty = if opcode < 0x7C { I32 } else { I64 };
+                    if stack.pop() != Some(ty) || stack.pop() != Some(ty) {
+                        return Err(self.corrupt(&format!(
+                            "constant expression has a type mismatch (expected {ty} operands)")));
+                    }
+                    ty
+                },
+                0xD0 => self.ref_type()?,
+                0xD2 => {
+                    function = Some(self.index(module.functions.len(), "function")?);
+                    ValType::FuncRef
+                },
+                0xFD => match self.u32()? {
+                    12 => {
+                        self.skip(16)?;
+                        V128
+                    },
+                    _ => return Err(self.corrupt("constant expression contains a non-constant \
+                        SIMD instruction")),
+                },
+                _ => return Err(self.corrupt(&format!(
+                    "constant expression contains a non-constant instruction: {opcode:#04x}"))),
+            };
+            stack.push(ty);
+        }
+        if stack != [expected] {
+            return Err(self.corrupt(&format!(
+                "constant expression doesn't produce exactly one {expected}")));
+        }
+        Ok(function)
+    }
+
+    /// Read a function body's instructions up to its final `end`, checking the operand types
+    ///
+    /// `type_index` is the function's type and `locals` holds the types of its locals.
+    fn expr(&mut self, module: &Module, type_index: u32, locals: &Locals<'_>) -> io::Result<()> {
+        let results = &module.types[type_index as usize].results;
+        let mut stack = Stack { operands: Vec::new(), frames: vec![Frame {
+            kind: BlockKind::Block, ty: BlockType::Func(type_index), height: 0,
+            unreachable: false }] };
+        let mut popped = Vec::new();
+        while !stack.frames.is_empty() {
+            let opcode = self.byte()?;
+            match opcode {
+                0x00 => stack.unreachable(),
+                0x01 => {},
+                0x02..=0x04 => {
+                    let ty = self.block_type(module)?;
+                    let kind = match opcode {
+                        0x02 => BlockKind::Block,
+                        0x03 => BlockKind::Loop,
+                        _ => {
+                            stack.pop(self, ValType::I32)?;
+                            BlockKind::If
+                        },
+                    };
+                    stack.pop_all(self, ty.params(module))?;
+                    stack.push_frame(module, kind, ty);
+                },
+                0x05 => {
+                    if stack.frames.last().map(|x| x.kind) != Some(BlockKind::If) {
+                        return Err(self.corrupt("function has an 'else' outside of an 'if'"));
+                    }
+                    let frame = stack.pop_frame(self, module)?;
+                    stack.push_frame(module, BlockKind::Else, frame.ty);
+                },
+                0x0B => {
+                    let frame = stack.pop_frame(self, module)?;
+                    let results = frame.ty.results(module);
+                    if frame.kind == BlockKind::If && frame.ty.params(module) != results {
+                        return Err(self.corrupt("function has an 'if' with no 'else' whose \
+                            results aren't its parameters"));
+                    }
+                    stack.push_all(results);
+                },
+                0x0C => {
+                    let frame = self.label(&stack)?;
+                    stack.pop_all(self, frame.label_types(module))?;
+                    stack.unreachable();
+                },
+                0x0D => {
+                    let frame = self.label(&stack)?;
+                    stack.pop(self, ValType::I32)?;
+                    stack.apply(self, frame.label_types(module), frame.label_types(module))?;
+                },
+                0x0E => {
+                    let count = self.u32()?;
+                    stack.pop(self, ValType::I32)?;
+                    let mut arity = None;
+                    for _ in 0..=count {
+                        let frame = self.label(&stack)?;
+                        let types = frame.label_types(module);
+                        if *arity.get_or_insert(types.len()) != types.len() {
+                            return Err(self.corrupt("function has a 'br_table' whose targets \
+                                take different numbers of operands"));
+                        }
+                        // Every target is checked against the same operands, which only differ
+                        // from `types` if their types are unknown because this is unreachable
+                        for ty in types.iter().rev() {
+                            popped.push(stack.pop(self, *ty)?);
+                        }
+                        stack.operands.extend(popped.drain(..).rev());
+                    }
+                    stack.unreachable();
+                },
+                0x0F => {
+                    stack.pop_all(self, results)?;
+                    stack.unreachable();
+                },
+                _ => self.instruction(module, results, locals, &mut stack, opcode)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Check an instruction which isn't for control flow, in a function whose result types are
+    /// `results`
+    fn instruction(&mut self, module: &Module, results: &[ValType], locals: &Locals<'_>,
+                   stack: &mut Stack, opcode: u8) -> io::Result<()> {
+        use ValType::{F32, F64, I32, I64};
+        let memories = module.memories as usize;
+        match opcode {
+            // `call`, `call_indirect`, `return_call`, and `return_call_indirect`
+            0x10..=0x13 => {
+                let ty = if opcode & 1 == 0 {
+                    self.function(module)?
+                } else {
+                    let ty = self.func_type(module)?;
+                    if self.table(module)? != ValType::FuncRef {
+                        return Err(self.corrupt("function makes an indirect call through a \
+                            table which doesn't hold functions"));
+                    }
+                    stack.pop(self, I32)?;
+                    ty
+                };
+                stack.pop_all(self, &ty.params)?;
+                if opcode < 0x12 {
+                    stack.push_all(&ty.results);
+                } else if ty.results == results {
+                    stack.unreachable();
+                } else {
+                    return Err(self.corrupt("function makes a tail call to a function with \
+                        different results"));
+                }
+            },
+            0x1A => {
+                stack.pop_any(self)?;
+            },
+            0x1B => {
+                stack.pop(self, I32)?;
+                let (first, second) = (stack.pop_any(self)?, stack.pop_any(self)?);
+                if first.is_some_and(ValType::is_ref) || second.is_some_and(ValType::is_ref) {
+                    return Err(self.corrupt("function has a 'select' of references without a \
+                        type annotation"));
+                }
+                if let (Some(first), Some(second)) = (first, second) {
+                    if first != second {
+                        return Err(self.mismatch(first, second));
+                    }
+                }
+                stack.operands.push(first.or(second));
+            },
+            0x1C => {
+                if self.u32()? != 1 {
+                    return Err(self.corrupt("typed 'select' doesn't have exactly one type"));
+                }
+                let byte = self.byte()?;
+                let ty = self.value_type(byte)?;
+                stack.apply(self, &[ty, ty, I32], &[ty])?;
+            },
+            0x20 => {
+                let ty = self.local(locals)?;
+                stack.push(ty);
+            },
+            0x21 => {
+                let ty = self.local(locals)?;
+                stack.pop(self, ty)?;
+            },
+            0x22 => {
+                let ty = self.local(locals)?;
+                stack.apply(self, &[ty], &[ty])?;
+            },
+            0x23 => {
+                let (ty, _) = self.global(module)?;
+                stack.push(ty);
+            },
+            0x24 => {
+                let (ty, mutable) = self.global(module)?;
+                if !mutable {
+                    return Err(self.corrupt("function sets an immutable global"));
+                }
+                stack.pop(self, ty)?;
+            },
+            0x25 => {
+                let ty = self.table(module)?;
+                stack.apply(self, &[I32], &[ty])?;
+            },
+            0x26 => {
+                let ty = self.table(module)?;
+                stack.apply(self, &[I32, ty], &[])?;
+            },
+            0x28..=0x3E => {
+                let (natural, ty) = memory_access(opcode);
+                self.mem_arg(module, natural)?;
+                if opcode < 0x36 {
+                    stack.apply(self, &[I32], &[ty])?;
+                } else {
+                    stack.apply(self, &[I32, ty], &[])?;
+                }
+            },
+            0x3F => {
+                self.index(memories, "memory")?;
+                stack.push(I32);
+            },
+            0x40 => {
+                self.index(memories, "memory")?;
+                stack.apply(self, &[I32], &[I32])?;
+            },
+            0x41 => {
+                self.signed(32)?;
+                stack.push(I32);
+            },
+            0x42 => {
+                self.signed(64)?;
+                stack.push(I64);
+            },
+            0x43 => {
+                self.skip(4)?;
+                stack.push(F32);
+            },
+            0x44 => {
+                self.skip(8)?;
+                stack.push(F64);
+            },
+            0x45..=0xC4 => {
+                let (operand, count, result) = numeric_signature(opcode);
+                for _ in 0..count {
+                    stack.pop(self, operand)?;
+                }
+                stack.push(result);
+            },
+            0xD0 => {
+                let ty = self.ref_type()?;
+                stack.push(ty);
+            },
+            0xD1 => {
+                if stack.pop_any(self)?.is_some_and(|ty| !ty.is_ref()) {
+                    return Err(self.corrupt("function has a 'ref.is_null' of something which \
+                        isn't a reference"));
+                }
+                stack.push(I32);
+            },
+            0xD2 => {
+                let index = self.index(module.functions.len(), "function")?;
+                if !module.refs.contains(&index) {
+                    return Err(self.corrupt(&format!("function has a 'ref.func' for function \
+                        {index}, which isn't declared by an element segment, export, or global")));
+                }
+                stack.push(ValType::FuncRef);
+            },
+            0xFC => self.misc_instruction(module, stack)?,
+            0xFD => self.simd_instruction(module, stack)?,
+            0x06..=0x09 | 0x18 | 0x19 | 0x1F => {
+                return Err(self.unsupported("the exception handling proposal"));
+            },
+            0x14 | 0x15 | 0xD3..=0xD6 => {
+                return Err(self.unsupported("the typed function references proposal"));
+            },
+            0xFB => return Err(self.unsupported("the GC proposal")),
+            0xFE => return Err(self.unsupported("the threads proposal")),
+            _ => {
+                return Err(self.corrupt(&format!("function has an unknown opcode: {opcode:#04x}")));
+            },
+        }
+        Ok(())
+    }
+
+    /// Check the rest of an instruction with the `0xFC` prefix (saturating truncation and bulk
+    /// memory/table operations)
+    fn misc_instruction(&mut self, module: &Module, stack: &mut Stack) -> io::Result<()> {
+        use ValType::{F32, F64, I32, I64};
+        let memories = module.memories as usize;
+        let data = |reader: &mut Self| match module.data_count {
+            Some(count) => reader.index(count as usize, "data segment").map(|_| ()),
+            None => Err(reader.corrupt("module uses data segment indices without a data count \
+                section")),
+        };
+        // The destination, source or value, and length of an initialization, copy, or fill
+        let bulk = [I32, I32, I32];
+        match self.u32()? {
+            // The saturating truncations, from f32 or f64 (bit 1) to i32 or i64 (bit 2)
+            opcode @ 0..=7 => {
+                let operand = if opcode & 2 == 0 { F32 } else { F64 };
+                let result = if opcode & 4 == 0 { I32 } else { I64 };
+                stack.apply(self, &[operand], &[result])
+            },
+            8 => {
+                data(self)?;
+                self.index(memories, "memory")?;
+                stack.apply(self, &bulk, &[])
+            },
+            9 => data(self),
+            10 => {
+                self.index(memories, "memory")?;
+                self.index(memories, "memory")?;
+                stack.apply(self, &bulk, &[])
+            },
+            11 => {
+                self.index(memories, "memory")?;
+                stack.apply(self, &bulk, &[])
+            },
+            12 => {
+                if self.element(module)? != self.table(module)? {
+                    return Err(self.corrupt("function initializes a table from an element \
+                        segment of a different type"));
+                }
+                stack.apply(self, &bulk, &[])
+            },
+            13 => self.element(module).map(|_| ()),
+            14 => {
+                if self.table(module)? != self.table(module)? {
+                    return Err(self.corrupt("function copies between tables of different types"));
+                }
+                stack.apply(self, &bulk, &[])
+            },
+            15 => {
+                let ty = self.table(module)?;
+                stack.apply(self, &[ty, I32], &[I32])
+            },
+            16 => {
+                self.table(module)?;
+                stack.apply(self, &[], &[I32])
+            },
+            17 => {
+                let ty = self.table(module)?;
+                stack.apply(self, &[I32, ty, I32], &[])
+            },
+            opcode => Err(self.unsupported(&format!(
+                "an unknown 0xFC-prefixed instruction ({opcode}) from a newer proposal"))),
+        }
+    }
+
+    /// Check the rest of an instruction with the `0xFD` prefix (fixed-width SIMD)
+    fn simd_instruction(&mut self, module: &Module, stack: &mut Stack) -> io::Result<()> {
+        use ValType::{F32, F64, I32, I64, V128};
+        let opcode = self.u32()?;
+        match opcode {
+            // `v128.load` and the extending and splatting loads
+            0x00..=0x0A => {
+                let natural = match opcode {
+                    0x00 => 4,
+                    0x07..=0x0A => opcode - 0x07,
+                    _ => 3,
+                };
+                self.mem_arg(module, natural)?;
+                stack.apply(self, &[I32], &[V128])
+            },
+            0x0B => {
+                self.mem_arg(module, 4)?;
+                stack.apply(self, &[I32, V128], &[])
+            },
+            0x0C => {
+                self.skip(16)?;
+                stack.apply(self, &[], &[V128])
+            },
+            0x0D => {
+                for _ in 0..16 {
+                    self.lane(32)?;
+                }
+                stack.apply(self, &[V128, V128], &[V128])
+            },
+            0x0F..=0x14 => {
+                let scalar = match opcode {
+                    0x0F..=0x11 => I32,
+                    0x12 => I64,
+                    0x13 => F32,
+                    _ => F64,
+                };
+                stack.apply(self, &[scalar], &[V128])
+            },
+            // The `extract_lane`s and `replace_lane`s
+            0x15..=0x22 => {
+                let (lanes, scalar) = match opcode {
+                    0x15..=0x17 => (16, I32),
+                    0x18..=0x1A => (8, I32),
+                    0x1B | 0x1C => (4, I32),
+                    0x1D | 0x1E => (2, I64),
+                    0x1F | 0x20 => (4, F32),
+                    _ => (2, F64),
+                };
+                self.lane(lanes)?;
+                if matches!(opcode, 0x17 | 0x1A | 0x1C | 0x1E | 0x20 | 0x22) {
+                    stack.apply(self, &[V128, scalar], &[V128])
+                } else {
+                    stack.apply(self, &[V128], &[scalar])
+                }
+            },
+            0x52 => stack.apply(self, &[V128, V128, V128], &[V128]),
+            // `v128.any_true`, the `all_true`s, and the `bitmask`s
+            0x53 | 0x63 | 0x64 | 0x83 | 0x84 | 0xA3 | 0xA4 | 0xC3 | 0xC4 => {
+                stack.apply(self, &[V128], &[I32])
+            },
+            // The loads and stores of single 8, 16, 32, or 64-bit lanes
+            0x54..=0x5B => {
+                let size = opcode & 3;
+                self.mem_arg(module, size)?;
+                self.lane(16 >> size)?;
+                let results: &[ValType] = if opcode < 0x58 { &[V128] } else { &[] };
+                stack.apply(self, &[I32, V128], results)
+            },
+            0x5C | 0x5D => {
+                self.mem_arg(module, opcode - 0x5A)?;
+                stack.apply(self, &[I32], &[V128])
+            },
+            // The shifts
+            0x6B..=0x6D | 0x8B..=0x8D | 0xAB..=0xAD | 0xCB..=0xCD => {
+                stack.apply(self, &[V128, I32], &[V128])
+            },
+            0x4D | 0x5E..=0x62 | 0x67..=0x6A | 0x74 | 0x75 | 0x7A | 0x7C..=0x81 | 0x87..=0x8A
+                | 0x94 | 0xA0 | 0xA1 | 0xA7..=0xAA | 0xC0 | 0xC1 | 0xC7..=0xCA | 0xE0 | 0xE1
+                | 0xE3 | 0xEC | 0xED | 0xEF | 0xF8..=0xFF => {
+                stack.apply(self, &[V128], &[V128])
+            },
+            0x0E | 0x23..=0x4C | 0x4E..=0x51 | 0x65 | 0x66 | 0x6E..=0x73 | 0x76..=0x79 | 0x7B
+                | 0x82 | 0x85 | 0x86 | 0x8E..=0x93 | 0x95..=0x99 | 0x9B..=0x9F | 0xAE | 0xB1
+                | 0xB5..=0xBA | 0xBC..=0xBF | 0xCE | 0xD1 | 0xD5..=0xDF | 0xE4..=0xEB
+                | 0xF0..=0xF7 => {
+                stack.apply(self, &[V128, V128], &[V128])
+            },
+            0x100.. => Err(self.unsupported("the relaxed SIMD proposal")),
+            _ => Err(self.corrupt(&format!("function has an unknown SIMD opcode: {opcode:#04x}"))),
+        }
+    }
+}
+
+/// The natural alignment (as a power of two) and value type of the load or store `opcode`
+fn memory_access(opcode: u8) -> (u32, ValType) {
+    use ValType::{F32, F64, I32, I64};
+    match opcode {
+        0x28 | 0x36 => (2, I32),
+        0x29 | 0x37 => (3, I64),
+        0x2A | 0x38 => (2, F32),
+        0x2B | 0x39 => (3, F64),
+        0x2C | 0x2D | 0x3A => (0, I32),
+        0x2E | 0x2F | 0x3B => (1, I32),
+        0x30 | 0x31 | 0x3C => (0, I64),
+        0x32 | 0x33 | 0x3D => (1, I64),
+        0x34 | 0x35 | 0x3E => (2, I64),
+        _ => unreachable!("only called for loads and stores"),
+    }
+}
+
+/// The operand type, number of operands, and result type of the numeric instruction `opcode`
+fn numeric_signature(opcode: u8) -> (ValType, usize, ValType) {
+    use ValType::{F32, F64, I32, I64};
+    match opcode {
+        0x45 | 0x67..=0x69 | 0xC0 | 0xC1 => (I32, 1, I32),
+        0x46..=0x4F | 0x6A..=0x78 => (I32, 2, I32),
+        0x50 | 0xA7 => (I64, 1, I32),
+        0x51..=0x5A => (I64, 2, I32),
+        0x5B..=0x60 => (F32, 2, I32),
+        0x61..=0x66 => (F64, 2, I32),
+        0x79..=0x7B | 0xC2..=0xC4 => (I64, 1, I64),
+        0x7C..=0x8A => (I64, 2, I64),
+        0x8B..=0x91 => (F32, 1, F32),
+        0x92..=0x98 => (F32, 2, F32),
+        0x99..=0x9F => (F64, 1, F64),
+        0xA0..=0xA6 => (F64, 2, F64),
+        0xA8 | 0xA9 | 0xBC => (F32, 1, I32),
+        0xAA | 0xAB => (F64, 1, I32),
+        0xAC | 0xAD => (I32, 1, I64),
+        0xAE | 0xAF => (F32, 1, I64),
+        0xB0 | 0xB1 | 0xBD => (F64, 1, I64),
+        0xB2 | 0xB3 | 0xBE => (I32, 1, F32),
+        0xB4 | 0xB5 => (I64, 1, F32),
+        0xB6 => (F64, 1, F32),
+        0xB7 | 0xB8 => (I32, 1, F64),
+        0xB9 | 0xBA | 0xBF => (I64, 1, F64),
+        0xBB => (F32, 1, F64),
+        _ => unreachable!("only called for numeric instructions"),
+    }
+}
+
+/// Check the contents of the non-custom section `id`, adding what it defines to `module`
+fn read_section<R: Read>(reader: &mut Reader<R>, id: u8, module: &mut Module) -> io::Result<()> {
+    match id {
+        1 => for _ in 0..reader.u32()? {
+            match reader.byte()? {
+                0x60 => {},
+                0x4E..=0x50 => return Err(reader.unsupported("the GC proposal")),
+                form => return Err(reader.corrupt(&format!("module has an invalid type form: \
+                    {form:#04x}"))),
+            }
+            let params = reader.value_types()?;
+            let results = reader.value_types()?;
+            module.types.push(FuncType { params, results });
+        },
+        2 => for _ in 0..reader.u32()? {
+            reader.name()?;
+            reader.name()?;
+            match reader.byte()? {
+                0 => {
+                    let index = reader.index(module.types.len(), "type")?;
+                    module.functions.push(index);
+                    module.imported_functions += 1;
+                },
+                1 => {
+                    let ty = reader.ref_type()?;
+                    reader.limits(false)?;
+                    module.tables.push(ty);
+                },
+                2 => {
+                    reader.limits(true)?;
+                    module.memories += 1;
+                },
+                3 => {
+                    let global = reader.global_type()?;
+                    module.globals.push(global);
+                    module.imported_globals += 1;
+                },
+                4 => return Err(reader.unsupported("the exception handling proposal")),
+                kind => return Err(reader.corrupt(&format!("module has an invalid import kind: \
+                    {kind}"))),
+            }
+        },
+        3 => for _ in 0..reader.u32()? {
+            let index = reader.index(module.types.len(), "type")?;
+            module.functions.push(index);
+        },
+        4 => for _ in 0..reader.u32()? {
+            // (0x40 introduces a table with an initializer expression)
+            let ty = match reader.byte()? {
+                0x40 => return Err(reader.unsupported("the typed function references proposal")),
+                byte => reader.ref_type_from(byte)?,
+            };
+            reader.limits(false)?;
+            module.tables.push(ty);
+        },
+        5 => for _ in 0..reader.u32()? {
+            reader.limits(true)?;
+            module.memories += 1;
+        },
+        6 => for _ in 0..reader.u32()? {
+            let (ty, mutable) = reader.global_type()?;
+            let function = reader.const_expr(module, ty)?;
+            module.refs.extend(function);
+            module.globals.push((ty, mutable));
+        },
+        7 => {
+            let mut names = HashSet::new();
+            for _ in 0..reader.u32()? {
+                let name = reader.name()?;
+                if !names.insert(name) {
+                    return Err(reader.corrupt("module has two exports with the same name"));
+                }
+                let kind = reader.byte()?;
+                let count = match kind {
+                    0 => module.functions.len(),
+                    1 => module.tables.len(),
+                    2 => module.memories as usize,
+                    3 => module.globals.len(),
+                    4 => return Err(reader.unsupported("the exception handling proposal")),
+                    kind => return Err(reader.corrupt(&format!(
+                        "module has an invalid export kind: {kind}"))),
+                };
+                let index = reader.index(count, "export target")?;
+                if kind == 0 {
+                    module.refs.insert(index);
+                }
+            }
+        },
+        8 => {
+            let ty = reader.function(module)?;
+            if !ty.params.is_empty() || !ty.results.is_empty() {
+                return Err(reader.corrupt("start function has parameters or results"));
+            }
+        },
+        9 => for _ in 0..reader.u32()? {
+            read_element(reader, module)?;
+        },
+        10 => {
+            let count = reader.u32()? as usize;
+            if count != module.functions.len() - module.imported_functions {
+                return Err(reader.corrupt("code section doesn't have a body for every function"));
+            }
+            for index in module.imported_functions..module.functions.len() {
+                let size = u64::from(reader.u32()?);
+                if reader.end - reader.pos < size {
+                    return Err(reader.corrupt("function body runs past the end of the section"));
+                }
+                let section_end = reader.end;
+                reader.end = reader.pos + size;
+
+                let type_index = module.functions[index];
+                let params = &module.types[type_index as usize].params;
+                let mut locals = Locals { params, runs: Vec::new() };
+                let mut count = params.len() as u64;
+                for _ in 0..reader.u32()? {
+                    count += u64::from(reader.u32()?);
+                    let byte = reader.byte()?;
+                    locals.runs.push((count, reader.value_type(byte)?));
+                }
+                if count > u64::from(u32::MAX) {
+                    return Err(reader.corrupt("function has too many locals"));
+                }
+                reader.expr(module, type_index, &locals)?;
+                if reader.pos != reader.end {
+                    return Err(reader.corrupt("function body continues after its final 'end'"));
+                }
+                reader.end = section_end;
+            }
+            module.bodies = count;
+        },
+        11 => {
+            let count = reader.u32()?;
+            if module.data_count.is_some_and(|x| x != count) {
+                return Err(reader.corrupt("data section and data count section disagree"));
+            }
+            for _ in 0..count {
+                match reader.u32()? {
+                    0 => {
+                        if module.memories == 0 {
+                            return Err(reader.corrupt("module refers to memory 0, which doesn't \
+                                exist"));
+                        }
+                        reader.const_expr(module, ValType::I32)?;
+                    },
+                    1 => {},
+                    2 => {
+                        reader.index(module.memories as usize, "memory")?;
+                        reader.const_expr(module, ValType::I32)?;
+                    },
+                    _ => return Err(reader.corrupt("module has invalid data segment flags")),
+                }
+                let len = reader.u32()?;
+                reader.skip(u64::from(len))?;
+            }
+            module.has_data = true;
+        },
+        12 => module.data_count = Some(reader.u32()?),
+        _ => unreachable!("only called for known section IDs"),
+    }
+    Ok(())
+}
+
+/// Read an element segment, recording its type and the functions it refers to
+fn read_element<R: Read>(reader: &mut Reader<R>, module: &mut Module) -> io::Result<()> {
+    let flags = reader.u32()?;
+    if flags > 7 {
+        return Err(reader.corrupt("module has invalid element segment flags"));
+    }
+    let (is_passive, has_table, uses_exprs) = (flags & 1 != 0, flags & 2 != 0, flags & 4 != 0);
+    let table = if is_passive {
+        None
+    } else {
+        let table = if has_table { reader.u32()? } else { 0 };
+        let Some(&table) = module.tables.get(table as usize) else {
+            return Err(reader.corrupt(&format!("module refers to table {table}, which doesn't \
+                exist")));
+        };
+        reader.const_expr(module, ValType::I32)?;
+        Some(table)
+    };
+
+    // The element kind or type is implied for the original encoding (flags 0)
+    let ty = if !is_passive && !has_table {
+        ValType::FuncRef
+    } else if uses_exprs {
+        reader.ref_type()?
+    } else if reader.byte()? == 0 {
+        ValType::FuncRef
+    } else {
+        return Err(reader.corrupt("module has an invalid element kind"));
+    };
+    if table.is_some_and(|table| table != ty) {
+        return Err(reader.corrupt("element segment doesn't match the type of its table"));
+    }
+    for _ in 0..reader.u32()? {
+        let function = if uses_exprs {
+            reader.const_expr(module, ty)?
+        } else {
+            Some(reader.index(module.functions.len(), "function")?)
+        };
+        module.refs.extend(function);
+    }
+    module.elements.push(ty);
+    Ok(())
+}
+
+/// Check the WebAssembly module in `input`
+///
+/// Every section is decoded without buffering, so memory use doesn't depend on the size of the
+/// module. Components and modules which use proposals beyond those in the WebAssembly 2.0 spec
+/// (plus the extended constant expressions, tail call, and multi-memory proposals) are reported as
+/// unsupported.
+pub fn verify(input: impl Read) -> io::Result<()> {
+    let mut reader = Reader { input: BufReader::new(input), pos: 0, end: u64::MAX };
+    let mut header = [0; 8];
+    reader.input.read_exact(&mut header).map_err(|err| match err.kind() {
+        io::ErrorKind::UnexpectedEof => corrupt("WebAssembly module is truncated (too short for \
+            a header)"),
+        _ => err,
+    })?;
+    reader.pos = header.len() as u64;
+    if !header.starts_with(MAGIC) {
+        return Err(corrupt("Not a WebAssembly module (bad magic number)"));
+    }
+    if header[6..] == COMPONENT_LAYER {
+        return Err(unsupported("WebAssembly components aren't supported"));
+    }
+    if header[4..] != MODULE_VERSION {
+        return Err(unsupported(&format!("Unknown WebAssembly version: {:02X?}", &header[4..])));
+    }
+
+    let mut module = Module::default();
+    let mut last_order = 0;
+    while let Some(id) = reader.try_byte()? {
+        let size = u64::from(reader.u32()?);
+        reader.end = reader.pos + size;
+        match id {
+            0 => {
+                reader.name()?;
+                reader.skip(reader.end - reader.pos)?;
+            },
+            1..=12 => {
+                let order = SECTION_ORDER[usize::from(id)];
+                if order <= last_order {
+                    return Err(reader.corrupt(&format!("{} section is out of order or repeated",
+                        SECTION_NAMES[usize::from(id)])));
+                }
+                last_order = order;
+                read_section(&mut reader, id, &mut module)?;
+            },
+            13 => return Err(reader.unsupported("the exception handling proposal")),
+            _ => return Err(reader.corrupt(&format!("module has an unknown section ID: {id}"))),
+        }
+        if reader.pos != reader.end {
+            return Err(reader.corrupt(&format!("{} section is longer than its contents",
+                SECTION_NAMES.get(usize::from(id)).unwrap_or(&"unknown"))));
+        }
+        reader.end = u64::MAX;
+    }
+
+    if module.bodies != module.functions.len() - module.imported_functions {
+        return Err(reader.corrupt("module has functions with no code section"));
+    }
+    if module.data_count.is_some_and(|x| x > 0) && !module.has_data {
+        return Err(reader.corrupt("module has a data count section but no data section"));
+    }
+    Ok(())
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A small module using every non-core section, built by `test_data/sources/make_test_wasm.py`
+    const MODULE: &[u8] = include_bytes!("../../../test_data/good/testfile.wasm");
+
+    /// Verify `input`, returning the error kind on failure
+    fn verify_bytes(input: &[u8]) -> Result<(), io::ErrorKind> {
+        verify(input).map_err(|err| err.kind())
+    }
+
+    /// Build a module from `(id, contents)` pairs
+    fn module(sections: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut module = b"\0asm\x01\0\0\0".to_vec();
+        for (id, contents) in sections {
+            module.push(*id);
+            module.push(u8::try_from(contents.len()).unwrap());
+            module.extend(*contents);
+        }
+        module
+    }
+
+    /// Sections declaring a `() -> ()` type, one function of that type, and a memory
+    const BARE: &[(u8, &[u8])] = &[(1, &[1, 0x60, 0, 0]), (3, &[1, 0]), (5, &[1, 0, 1])];
+
+    /// Sections for a richer context: types `() -> ()` and `(i32) -> (i32, i64)`, an immutable
+    /// imported `i32` global, one `() -> ()` function, `funcref` and `externref` tables, a memory,
+    /// a mutable `i64` global, an immutable `i32` global, and a declarative segment for function 0
+    const CONTEXT: &[(u8, &[u8])] = &[
+        (1, &[2, 0x60, 0, 0, 0x60, 1, 0x7F, 2, 0x7F, 0x7E]),
+        (2, &[1, 1, b'm', 1, b'g', 0x03, 0x7F, 0]),
+        (3, &[1, 0]),
+        (4, &[2, 0x70, 0, 1, 0x6F, 0, 1]),
+        (5, &[1, 0, 1]),
+        (6, &[2, 0x7E, 1, 0x42, 2, 0x42, 3, 0x7E, 0x0B, 0x7F, 0, 0x23, 0, 0x0B]),
+        (9, &[1, 3, 0, 1, 0]),
+    ];
+
+    /// Build a module from `sections` plus a code section holding one `() -> ()` function whose
+    /// body is `code` (without the final `end`)
+    fn function_in(sections: &[(u8, &[u8])], code: &[u8]) -> Vec<u8> {
+        let mut code_section = vec![1, u8::try_from(code.len() + 2).unwrap(), 0];
+        code_section.extend(code);
+        code_section.push(0x0B);
+        let mut sections = sections.to_vec();
+        sections.push((10, &code_section));
+        module(&sections)
+    }
+
+    /// Build a module with one `() -> ()` function whose body is `code` (without the final `end`)
+    fn function(code: &[u8]) -> Vec<u8> {
+        function_in(BARE, code)
+    }
+
+    /// Read a LEB128 integer from `bytes` with `read`, also returning how many bytes were used
+    fn leb<T>(bytes: &[u8], read: impl Fn(&mut Reader<&[u8]>) -> io::Result<T>)
+            -> Result<(T, u64), io::ErrorKind> {
+        let mut reader = Reader { input: bytes, pos: 0, end: u64::MAX };
+        read(&mut reader).map(|x| (x, reader.pos)).map_err(|err| err.kind())
+    }
+
+    #[test]
+    fn test_leb128() {
+        let unsigned = |bytes: &[u8]| leb(bytes, |r| r.unsigned(32));
+        assert_eq!(unsigned(&[0x00]), Ok((0, 1)));
+        assert_eq!(unsigned(&[0xE5, 0x8E, 0x26]), Ok((624_485, 3)));
+        assert_eq!(unsigned(&[0x80, 0x00]), Ok((0, 2)));
+        assert_eq!(unsigned(&[0xFF, 0xFF, 0xFF, 0xFF, 0x0F]), Ok((0xFFFF_FFFF, 5)));
+        assert_eq!(unsigned(&[0xFF, 0xFF, 0xFF, 0xFF, 0x1F]), Err(io::ErrorKind::InvalidData));
+        assert_eq!(unsigned(&[0x80, 0x80, 0x80, 0x80, 0x80, 0x00]),
+                   Err(io::ErrorKind::InvalidData));
+        assert_eq!(unsigned(&[0x80]), Err(io::ErrorKind::InvalidData));
+
+        let signed = |bits, bytes: &[u8]| leb(bytes, |r| r.signed(bits));
+        assert_eq!(signed(32, &[0x7F]), Ok((-1, 1)));
+        assert_eq!(signed(32, &[0x3F]), Ok((63, 1)));
+        assert_eq!(signed(32, &[0xC0, 0xBB, 0x78]), Ok((-123_456, 3)));
+        assert_eq!(signed(32, &[0x80, 0x80, 0x80, 0x80, 0x78]), Ok((i64::from(i32::MIN), 5)));
+        assert_eq!(signed(32, &[0xFF, 0xFF, 0xFF, 0xFF, 0x07]), Ok((i64::from(i32::MAX), 5)));
+        assert_eq!(signed(32, &[0xFF, 0xFF, 0xFF, 0xFF, 0x0F]), Err(io::ErrorKind::InvalidData));
+        assert_eq!(signed(32, &[0x80, 0x80, 0x80, 0x80, 0x70]), Err(io::ErrorKind::InvalidData));
+        assert_eq!(signed(33, &[0xFF, 0xFF, 0xFF, 0xFF, 0x0F]), Ok((0xFFFF_FFFF, 5)));
+        assert_eq!(signed(64, &[0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x7F]),
+                   Ok((i64::MIN, 10)));
+        assert_eq!(signed(64, &[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00]),
+                   Ok((i64::MAX, 10)));
+        assert_eq!(signed(64, &[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01]),
+                   Err(io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn test_good_modules() {
+        assert_eq!(verify_bytes(MODULE), Ok(()));
+        assert_eq!(verify_bytes(&module(&[])), Ok(()));
+        assert_eq!(verify_bytes(&module(&[(0, b"\x04meta1234"), (1, &[0]), (0, b"\0")])), Ok(()));
+
+        // Nested blocks with branches out of each level and a typed `select`
+        assert_eq!(verify_bytes(&function(&[0x02, 0x40, 0x03, 0x40, 0x0C, 0x02, 0x0B, 0x0B,
+            0x41, 0, 0x41, 1, 0x41, 1, 0x1C, 1, 0x7F, 0x1A])), Ok(()));
+
+        // Well-typed bodies: operands of any type after `unreachable`, a multi-value block, an
+        // `if` with a result, a `br_table` in dead code whose labels have different types, a
+        // loop with a parameter, SIMD lanes, a declared `ref.func`, a tail call through a
+        // table, and a mutable `global.set`
+        for code in [&[0x00, 0x6A, 0x1A][..], &[0x00, 0x1B, 0x1A],
+                &[0x41, 1, 0x02, 0x01, 0x42, 0, 0x0B, 0x1A, 0x1A],
+                &[0x41, 0, 0x04, 0x7F, 0x41, 1, 0x05, 0x41, 2, 0x0B, 0x1A],
+                &[0x02, 0x7E, 0x02, 0x7F, 0x00, 0x0E, 1, 0, 1, 0x0B, 0x1A, 0x42, 0, 0x0B, 0x1A],
+                &[0x41, 0, 0x03, 0x01, 0x41, 1, 0x0D, 0, 0x42, 0, 0x0B, 0x1A, 0x1A],
+                &[0x41, 1, 0xFD, 17, 0xFD, 27, 3, 0x1A], &[0xD2, 0, 0x1A], &[0x41, 0, 0x13, 0, 0],
+                &[0x42, 5, 0x24, 1]] {
+            assert_eq!(verify_bytes(&function_in(CONTEXT, code)), Ok(()), "{code:02X?}");
+        }
+    }
+
+    #[test]
+    fn test_bad_modules() {
+        let damaged = include_bytes!("../../../test_data/bad/testfile.wasm");
+        assert_eq!(verify_bytes(damaged), Err(io::ErrorKind::InvalidData));
+
+        for len in [0, 7, 8 + 1, 40, MODULE.len() - 1] {
+            assert_eq!(verify_bytes(&MODULE[..len]), Err(io::ErrorKind::InvalidData), "{len}");
+        }
+        assert_eq!(verify_bytes(b"\0ASM\x01\0\0\0"), Err(io::ErrorKind::InvalidData));
+
+        // Sections out of order, repeated, unknown, or with trailing bytes
+        assert_eq!(verify_bytes(&module(&[(3, &[0]), (1, &[0])])),
+                   Err(io::ErrorKind::InvalidData));
+        assert_eq!(verify_bytes(&module(&[(1, &[0]), (1, &[0])])),
+                   Err(io::ErrorKind::InvalidData));
+        assert_eq!(verify_bytes(&module(&[(12, &[0]), (9, &[0])])),
+                   Err(io::ErrorKind::InvalidData));
+        assert_eq!(verify_bytes(&module(&[(14, &[0])])), Err(io::ErrorKind::InvalidData));
+        assert_eq!(verify_bytes(&module(&[(1, &[0, 0])])), Err(io::ErrorKind::InvalidData));
+        assert_eq!(verify_bytes(&module(&[(1, &[1, 0x60, 0, 0, 0])])),
+                   Err(io::ErrorKind::InvalidData));
+        assert_eq!(verify_bytes(&module(&[(0, b"\x02\xC3\x28")])), Err(io::ErrorKind::InvalidData));
+
+        // Functions without bodies, duplicate exports, and bad limits
+        assert_eq!(verify_bytes(&module(&[(1, &[1, 0x60, 0, 0]), (3, &[1, 0])])),
+                   Err(io::ErrorKind::InvalidData));
+        assert_eq!(verify_bytes(&module(&[(5, &[1, 0, 1]), (7, b"\x02\x01m\x02\0\x01m\x02\0")])),
+                   Err(io::ErrorKind::InvalidData));
+        assert_eq!(verify_bytes(&module(&[(5, &[1, 1, 2, 1])])), Err(io::ErrorKind::InvalidData));
+        assert_eq!(verify_bytes(&module(&[(5, &[1, 0, 0x81, 0x80, 0x04])])),
+                   Err(io::ErrorKind::InvalidData));
+        assert_eq!(verify_bytes(&module(&[(12, &[1])])), Err(io::ErrorKind::InvalidData));
+
+        // Instruction sequences with broken nesting or references to things which don't exist
+        for code in [&[0x05][..], &[0x02, 0x40], &[0x0C, 0x01], &[0x10, 0x01], &[0x20, 0x00],
+                &[0x23, 0x00], &[0x41, 0, 0x28, 5, 0, 0x1A], &[0x3F, 1, 0x1A], &[0xFC, 0x09, 0],
+                &[0x02, 0x05, 0x0B], &[0x27], &[0x0B, 0x0B]] {
+            assert_eq!(verify_bytes(&function(code)), Err(io::ErrorKind::InvalidData),
+                       "{code:02X?}");
+        }
+        assert_eq!(verify_bytes(&function(&[0xD2, 0, 0x1A])), Err(io::ErrorKind::InvalidData));
+
+        // Ill-typed bodies: mixed operands, a leftover value, an empty stack, an over-aligned
+        // load, an `if` without `else` which changes the stack, an untyped `select` of
+        // references, `br_table` labels of different arities, a tail call with other results,
+        // `call_indirect` through an `externref` table, and a `global.set` of an immutable global
+        for code in [&[0x41, 0, 0x42, 0, 0x6A, 0x1A][..], &[0x41, 0], &[0x1A],
+                &[0x41, 0, 0x28, 3, 0, 0x1A], &[0x41, 0, 0x04, 0x7F, 0x41, 1, 0x0B, 0x1A],
+                &[0xD0, 0x70, 0xD0, 0x70, 0x41, 0, 0x1B, 0x1A],
+                &[0x02, 0x7F, 0x02, 0x40, 0x41, 0, 0x0E, 1, 0, 1, 0x0B, 0x41, 0, 0x0B, 0x1A],
+                &[0x41, 0, 0x41, 0, 0x13, 1, 0], &[0x41, 0, 0x11, 0, 1], &[0x41, 0, 0x24, 2]] {
+            assert_eq!(verify_bytes(&function_in(CONTEXT, code)), Err(io::ErrorKind::InvalidData),
+                       "{code:02X?}");
+        }
+
+        // Constant expressions of the wrong type or reading a defined global, a start function
+        // with parameters, and a `funcref` segment for an `externref` table
+        assert_eq!(verify_bytes(&module(&[(6, &[1, 0x7F, 0, 0x42, 0, 0x0B])])),
+                   Err(io::ErrorKind::InvalidData));
+        assert_eq!(verify_bytes(&module(&[(6, &[2, 0x7F, 0, 0x41, 0, 0x0B,
+                                                0x7F, 0, 0x23, 0, 0x0B])])),
+                   Err(io::ErrorKind::InvalidData));
+        assert_eq!(verify_bytes(&module(&[(1, &[1, 0x60, 1, 0x7F, 0]), (3, &[1, 0]), (8, &[0]),
+                                          (10, &[1, 2, 0, 0x0B])])),
+                   Err(io::ErrorKind::InvalidData));
+        assert_eq!(verify_bytes(&module(&[(1, &[1, 0x60, 0, 0]), (3, &[1, 0]),
+                                          (4, &[1, 0x6F, 0, 1]), (9, &[1, 0, 0x41, 0, 0x0B, 1, 0]),
+                                          (10, &[1, 2, 0, 0x0B])])),
+                   Err(io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn test_unsupported() {
+        assert_eq!(verify_bytes(b"\0asm\x0D\0\x01\0"), Err(io::ErrorKind::Unsupported));
+        assert_eq!(verify_bytes(b"\0asm\x02\0\0\0"), Err(io::ErrorKind::Unsupported));
+        assert_eq!(verify_bytes(&module(&[(13, &[0])])), Err(io::ErrorKind::Unsupported));
+        assert_eq!(verify_bytes(&module(&[(1, &[1, 0x4E, 0])])), Err(io::ErrorKind::Unsupported));
+        assert_eq!(verify_bytes(&module(&[(5, &[1, 3, 1, 1])])), Err(io::ErrorKind::Unsupported));
+        assert_eq!(verify_bytes(&module(&[(5, &[1, 4, 1])])), Err(io::ErrorKind::Unsupported));
+        for code in [&[0x06, 0x40, 0x0B][..], &[0xFE, 0x03, 0], &[0xFD, 0x80, 0x02]] {
+            assert_eq!(verify_bytes(&function(code)), Err(io::ErrorKind::Unsupported),
+                       "{code:02X?}");
+        }
+    }
+}
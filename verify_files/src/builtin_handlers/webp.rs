@@ -0,0 +1,962 @@
+//! A structural check for WebP images which also decodes the entropy coding of lossless bitstreams
+//!
+//! (WebP has no checksums. The RIFF container is walked with WebP's own rules about which chunks
+//! may appear where, and the dimensions declared by the `VP8X` and `ANMF` chunks are checked
+//! against those of the image bitstreams they hold. Lossy (VP8) bitstreams only have their frame
+//! headers checked, but lossless (VP8L) bitstreams, including losslessly compressed `ALPH` chunks,
+//! have their transforms and prefix codes read and every pixel decoded as far as the LZ77 stage.
+//! The transforms aren't applied, since that can't fail.)
+
+// Standard library imports
+use std::convert::TryFrom;
+use std::io::{self, BufReader, Read};
+
+// Local Imports
+use super::bytes::low32;
+use super::{corrupt, unsupported};
+
+/// The size of a chunk header
+const CHUNK_HEADER_SIZE: u64 = 8;
+
+/// `VP8X` flag: The file has an `ICCP` chunk
+const FLAG_ICC: u8 = 0x20;
+/// `VP8X` flag: Some of the image has transparency
+const FLAG_ALPHA: u8 = 0x10;
+/// `VP8X` flag: The file has an `EXIF` chunk
+const FLAG_EXIF: u8 = 0x08;
+/// `VP8X` flag: The file has an `XMP ` chunk
+const FLAG_XMP: u8 = 0x04;
+/// `VP8X` flag: The file is animated
+const FLAG_ANIMATION: u8 = 0x02;
+
+/// Canvases and frames must have fewer pixels than this
+const MAX_AREA: u64 = 1 << 32;
+
+/// The largest width or height a VP8 or VP8L bitstream can have
+const MAX_BITSTREAM_DIMENSION: u32 = 1 << 14;
+
+/// How long prefix codes in VP8L bitstreams may be
+const MAX_CODE_LENGTH: usize = 15;
+
+/// The order in which the code lengths of the code length code are stored
+const CODE_LENGTH_ORDER: [usize; 19] =
+    [17, 18, 0, 1, 2, 3, 4, 5, 16, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+
+/// The number of length prefix codes at the end of the green alphabet
+const LENGTH_PREFIXES: u16 = 24;
+
+/// The largest number of color cache bits
+const MAX_CACHE_BITS: u32 = 11;
+
+/// Nearby pixels which the first 120 distance codes refer to, as `(left, up)` offsets
+const DISTANCE_MAP: [(i8, u8); 120] = [
+    (0, 1), (1, 0), (1, 1), (-1, 1), (0, 2), (2, 0), (1, 2), (-1, 2), (2, 1), (-2, 1), (2, 2),
+    (-2, 2), (0, 3), (3, 0), (1, 3), (-1, 3), (3, 1), (-3, 1), (2, 3), (-2, 3), (3, 2), (-3, 2),
+    (0, 4), (4, 0), (1, 4), (-1, 4), (4, 1), (-4, 1), (3, 3), (-3, 3), (2, 4), (-2, 4), (4, 2),
+    (-4, 2), (0, 5), (3, 4), (-3, 4), (4, 3), (-4, 3), (5, 0), (1, 5), (-1, 5), (5, 1), (-5, 1),
+    (2, 5), (-2, 5), (5, 2), (-5, 2), (4, 4), (-4, 4), (3, 5), (-3, 5), (5, 3), (-5, 3), (0, 6),
+    (6, 0), (1, 6), (-1, 6), (6, 1), (-6, 1), (2, 6), (-2, 6), (6, 2), (-6, 2), (4, 5), (-4, 5),
+    (5, 4), (-5, 4), (3, 6), (-3, 6), (6, 3), (-6, 3), (0, 7), (7, 0), (1, 7), (-1, 7), (5, 5),
+    (-5, 5), (7, 1), (-7, 1), (4, 6), (-4, 6), (6, 4), (-6, 4), (2, 7), (-2, 7), (7, 2), (-7, 2),
+    (3, 7), (-3, 7), (7, 3), (-7, 3), (5, 6), (-5, 6), (6, 5), (-6, 5), (8, 0), (4, 7), (-4, 7),
+    (7, 4), (-7, 4), (8, 1), (8, 2), (6, 6), (-6, 6), (8, 3), (5, 7), (-5, 7), (7, 5), (-7, 5),
+    (8, 4), (6, 7), (-6, 7), (7, 6), (-7, 6), (8, 5), (7, 7), (-7, 7), (8, 6), (8, 7),
+];
+
+/// What was found in a WebP file
+#[derive(Debug, Default)]
+pub struct Summary {
+    /// The width of the canvas
+    pub width: u32,
+    /// The height of the canvas
+    pub height: u32,
+    /// How many frames there are (1 for still images)
+    pub frames: u32,
+    /// How many lossless bitstreams had their entropy coding decoded
+    pub lossless: u32,
+}
+
+/// A chunk whose header has just been read
+struct Chunk {
+    /// The chunk ID
+    id: [u8; 4],
+    /// The size of the chunk's payload, not counting any padding
+    size: u32,
+    /// Where the chunk header is
+    start: u64,
+}
+
+impl Chunk {
+    /// Describe the chunk for error messages
+    fn describe(&self) -> String {
+        format!("WebP {} chunk at byte {}", String::from_utf8_lossy(&self.id).trim_end(),
+                self.start)
+    }
+}
+
+/// A reader which keeps track of how far into the file it is
+struct Counted<R> {
+    /// The file
+    input: R,
+    /// How many bytes have been read so far
+    pos: u64,
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.input.read(buf)?;
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+/// Fill `buf`, reporting a short read as truncation
+fn read_exact<R: Read + ?Sized>(input: &mut R, buf: &mut [u8]) -> io::Result<()> {
+    input.read_exact(buf).map_err(|err| match err.kind() {
+        io::ErrorKind::UnexpectedEof => corrupt("WebP file is truncated"),
+        _ => err,
+    })
+}
+
+/// Read the chunk header at `start`
+fn read_chunk<R: Read + ?Sized>(input: &mut R, start: u64) -> io::Result<Chunk> {
+    let mut header = [0; 8];
+    read_exact(input, &mut header)?;
+    let id = [header[0], header[1], header[2], header[3]];
+    if !id.iter().all(|&byte| (0x20..0x7F).contains(&byte)) {
+        return Err(corrupt(&format!("Invalid WebP chunk ID at byte {start}")));
+    }
+    let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    Ok(Chunk { id, size, start })
+}
+
+/// Skip whatever is left of a chunk's payload, reporting it as truncation if the file ends first
+fn finish_chunk<R: Read>(mut payload: io::Take<R>) -> io::Result<()> {
+    io::copy(&mut payload, &mut io::sink())?;
+    if payload.limit() == 0 { Ok(()) } else { Err(corrupt("WebP file is truncated")) }
+}
+
+/// Decode a little-endian 24-bit integer
+fn u24(bytes: &[u8]) -> u32 {
+    u32::from(bytes[0]) | u32::from(bytes[1]) << 8 | u32::from(bytes[2]) << 16
+}
+
+/// Check the frame header of a VP8 (lossy) bitstream, returning its dimensions
+fn vp8_header(chunk: &Chunk, payload: &mut dyn Read) -> io::Result<(u32, u32)> {
+    if chunk.size < 10 {
+        return Err(corrupt(&format!("{} is too short", chunk.describe())));
+    }
+    let mut header = [0; 10];
+    read_exact(payload, &mut header)?;
+    let tag = u24(&header);
+    if tag & 0x01 != 0 {
+        return Err(corrupt(&format!("{} doesn't hold a key frame", chunk.describe())));
+    } else if (tag >> 1) & 0x07 > 3 || (tag >> 4) & 0x01 == 0 || header[3..6] != [0x9D, 0x01, 0x2A]
+    {
+        return Err(corrupt(&format!("{} has an invalid frame header", chunk.describe())));
+    } else if tag >> 5 >= chunk.size {
+        return Err(corrupt(&format!("{} has a first partition which runs past its end",
+                                    chunk.describe())));
+    }
+    let width = u32::from(u16::from_le_bytes([header[6], header[7]]) & 0x3FFF);
+    let height = u32::from(u16::from_le_bytes([header[8], header[9]]) & 0x3FFF);
+    if width == 0 || height == 0 {
+        return Err(corrupt(&format!("{} has a zero width or height", chunk.describe())));
+    }
+    Ok((width, height))
+}
+
+/// Reads a VP8L bitstream, least significant bit first
+struct BitReader<'a> {
+    /// The bitstream
+    input: BufReader<&'a mut dyn Read>,
+    /// Bits which have been read from `input` but not consumed
+    bits: u64,
+    /// How many bits are in `bits`
+    count: u32,
+    /// What the bitstream is, for error messages
+    context: String,
+}
+
+impl<'a> BitReader<'a> {
+    /// Start reading the bitstream in the payload of `chunk`
+    fn new(chunk: &Chunk, payload: &'a mut dyn Read) -> Self {
+        Self { input: BufReader::new(payload), bits: 0, count: 0,
+               context: format!("Lossless bitstream in {}", chunk.describe()) }
+    }
+
+    /// Describe a problem with the bitstream
+    fn error(&self, problem: &str) -> io::Error {
+        corrupt(&format!("{} {}", self.context, problem))
+    }
+
+    /// Read `count` (at most 32) bits
+    fn read(&mut self, count: u32) -> io::Result<u32> {
+        while self.count < count {
+            let mut byte = [0];
+            if self.input.read(&mut byte)? == 0 {
+                return Err(self.error("is truncated"));
+            }
+            self.bits |= u64::from(byte[0]) << self.count;
+            self.count += 8;
+        }
+        let value = low32(self.bits & ((1 << count) - 1));
+        self.bits >>= count;
+        self.count -= count;
+        Ok(value)
+    }
+
+    /// Read a single bit as a flag
+    fn flag(&mut self) -> io::Result<bool> {
+        Ok(self.read(1)? == 1)
+    }
+}
+
+/// A canonical prefix code, decoded one bit at a time
+struct PrefixCode {
+    /// How many codes there are of each length
+    counts: [u16; MAX_CODE_LENGTH + 1],
+    /// The symbols, in order of their codes
+    symbols: Vec<u16>,
+}
+
+impl PrefixCode {
+    /// Build the code for the given code lengths, if they describe a complete code
+    ///
+    /// (A code with a single symbol is allowed, and decodes without reading any bits.)
+    fn new(lengths: &[u8]) -> Option<Self> {
+        let mut counts = [0; MAX_CODE_LENGTH + 1];
+        for &len in lengths {
+            counts[usize::from(len)] += 1;
+        }
+        let mut symbols = Vec::new();
+        for len in 1..=MAX_CODE_LENGTH {
+            symbols.extend((0..).zip(lengths).filter(|x| usize::from(*x.1) == len).map(|x| x.0));
+        }
+        counts[0] = 0;
+
+        if symbols.len() == 1 {
+            return Some(Self { counts: [0; MAX_CODE_LENGTH + 1], symbols });
+        }
+        let mut left: i32 = 1;
+        for &count in &counts[1..] {
+            left = (left << 1) - i32::from(count);
+            if left < 0 {
+                return None;
+            }
+        }
+        if left == 0 { Some(Self { counts, symbols }) } else { None }
+    }
+
+    /// Read a symbol
+    fn decode(&self, reader: &mut BitReader<'_>) -> io::Result<u16> {
+        if let [symbol] = self.symbols[..] {
+            return Ok(symbol);
+        }
+        let (mut code, mut first, mut index) = (0, 0, 0);
+        for &count in &self.counts[1..] {
+            code |= reader.read(1)?;
+            let count = u32::from(count);
+            if code < first + count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(reader.error("has a code which isn't in its prefix code"))
+    }
+}
+
+/// Read a prefix code for an alphabet of `alphabet_size` symbols
+fn read_prefix_code(reader: &mut BitReader<'_>, alphabet_size: usize) -> io::Result<PrefixCode> {
+    let mut lengths = vec![0; alphabet_size];
+    if reader.flag()? {
+        // A "simple" code of one or two symbols (which are ignored if outside the alphabet)
+        let two_symbols = reader.flag()?;
+        let first_bits = if reader.flag()? { 8 } else { 1 };
+        let mut symbols = vec![reader.read(first_bits)?];
+        if two_symbols {
+            symbols.push(reader.read(8)?);
+        }
+        for symbol in symbols {
+            if let Some(len) = lengths.get_mut(symbol as usize) {
+                *len = 1;
+            }
+        }
+    } else {
+        let mut length_lengths = [0; 19];
+        let count = 4 + reader.read(4)? as usize;
+        for &symbol in &CODE_LENGTH_ORDER[..count] {
+            length_lengths[symbol] = u8::try_from(reader.read(3)?)
+                .map_err(|_| reader.error("has an invalid code length code"))?;
+        }
+        let length_code = PrefixCode::new(&length_lengths)
+            .ok_or_else(|| reader.error("has an invalid code length code"))?;
+
+        let mut max_symbol = alphabet_size;
+        if reader.flag()? {
+            let bits = 2 + 2 * reader.read(3)?;
+            max_symbol = 2 + reader.read(bits)? as usize;
+            if max_symbol > alphabet_size {
+                return Err(reader.error("has more code lengths than symbols"));
+            }
+        }
+
+        let (mut symbol, mut previous) = (0, 8);
+        while symbol < alphabet_size && max_symbol > 0 {
+            max_symbol -= 1;
+            let len = length_code.decode(reader)?;
+            if let Ok(code_len @ 0..=15) = u8::try_from(len) {
+                lengths[symbol] = code_len;
+                symbol += 1;
+                if code_len != 0 {
+                    previous = code_len;
+                }
+                continue;
+            }
+            let (extra_bits, offset, value) = match len {
+                16 => (2, 3, previous),
+                17 => (3, 3, 0),
+                _ => (7, 11, 0),
+            };
+            let repeat = offset + reader.read(extra_bits)? as usize;
+            let run = lengths.get_mut(symbol..symbol + repeat)
+                .ok_or_else(|| reader.error("has more code lengths than symbols"))?;
+            run.fill(value);
+            symbol += repeat;
+        }
+    }
+    PrefixCode::new(&lengths).ok_or_else(|| reader.error("has an invalid prefix code"))
+}
+
+/// Read the five prefix codes (green, red, blue, alpha, and distance) which make up a group
+fn read_group(reader: &mut BitReader<'_>, cache_size: usize) -> io::Result<[PrefixCode; 5]> {
+    Ok([
+        read_prefix_code(reader, 256 + usize::from(LENGTH_PREFIXES) + cache_size)?,
+        read_prefix_code(reader, 256)?,
+        read_prefix_code(reader, 256)?,
+        read_prefix_code(reader, 256)?,
+        read_prefix_code(reader, 40)?,
+    ])
+}
+
+/// Divide `size` by `1 << bits`, rounding up
+fn subsample(size: u32, bits: u32) -> u32 {
+    (size + (1 << bits) - 1) >> bits
+}
+
+/// Read the extra bits for a length or distance prefix code, returning the length or distance
+fn prefix_value(reader: &mut BitReader<'_>, prefix: u16) -> io::Result<u64> {
+    if prefix < 4 {
+        return Ok(u64::from(prefix) + 1);
+    }
+    let extra_bits = u32::from(prefix - 2) >> 1;
+    let offset = (2 + u64::from(prefix & 1)) << extra_bits;
+    Ok(offset + u64::from(reader.read(extra_bits)?) + 1)
+}
+
+/// How an image's pixels select the group of prefix codes to decode them with
+struct EntropyImage {
+    /// The index of the group for each block
+    groups: Vec<usize>,
+    /// The log2 of the block size
+    bits: u32,
+    /// How many blocks wide the image is
+    width: u32,
+}
+
+/// Read the transforms which precede the main image of a bitstream, returning the width of the
+/// image once any color indexing transform has packed several pixels into one
+fn read_transforms(reader: &mut BitReader<'_>, mut width: u32, height: u32) -> io::Result<u32> {
+    let mut seen = [false; 4];
+    while reader.flag()? {
+        let kind = reader.read(2)? as usize;
+        if seen[kind] {
+            return Err(reader.error("applies the same transform twice"));
+        }
+        seen[kind] = true;
+        match kind {
+            // Predictor and color transforms
+            0 | 1 => {
+                let bits = reader.read(3)? + 2;
+                read_image(reader, subsample(width, bits), subsample(height, bits), false, false)?;
+            },
+            // Color indexing transform
+            3 => {
+                let colors = reader.read(8)? + 1;
+                read_image(reader, colors, 1, false, false)?;
+                width = subsample(width, match colors {
+                    0..=2 => 3,
+                    3..=4 => 2,
+                    5..=16 => 1,
+                    _ => 0,
+                });
+            },
+            // Subtract green transform
+            _ => {},
+        }
+    }
+    Ok(width)
+}
+
+/// Read an entropy-coded image which is `width` by `height`, returning its pixels if `keep` is set
+///
+/// The main image of a bitstream (`main` set) is preceded by its transforms and may have an entropy
+/// image choosing between several groups of prefix codes. Subimages (the transforms' data and the
+/// entropy image itself) may not.
+fn read_image(reader: &mut BitReader<'_>, mut width: u32, height: u32, main: bool, keep: bool)
+              -> io::Result<Vec<u32>> {
+    if main {
+        width = read_transforms(reader, width, height)?;
+    }
+
+    let mut cache_bits = 0;
+    if reader.flag()? {
+        cache_bits = reader.read(4)?;
+        if !(1..=MAX_CACHE_BITS).contains(&cache_bits) {
+            return Err(reader.error(&format!(
+                "has an invalid color cache size ({cache_bits} bits)")));
+        }
+    }
+    let cache_size = if cache_bits == 0 { 0 } else { 1 << cache_bits };
+
+    let mut entropy = None;
+    let mut group_count = 1;
+    if main && reader.flag()? {
+        let bits = reader.read(3)? + 2;
+        let blocks_wide = subsample(width, bits);
+        let blocks = read_image(reader, blocks_wide, subsample(height, bits), false, true)?;
+        let groups: Vec<_> = blocks.iter().map(|x| ((x >> 8) & 0xFFFF) as usize).collect();
+        group_count = groups.iter().max().map_or(1, |x| x + 1);
+        entropy = Some(EntropyImage { groups, bits, width: blocks_wide });
+    }
+
+    // Only keep the groups which some block of the image actually uses
+    let mut used = vec![entropy.is_none(); group_count];
+    for &group in entropy.iter().flat_map(|x| &x.groups) {
+        used[group] = true;
+    }
+    let mut groups = Vec::with_capacity(group_count);
+    for is_used in used {
+        let group = read_group(reader, cache_size)?;
+        groups.push(if is_used { Some(group) } else { None });
+    }
+
+    let total = u64::from(width) * u64::from(height);
+    let mut pixels = Vec::new();
+    let mut cache = vec![0; if keep { cache_size } else { 0 }];
+    let mut pos = 0;
+    while pos < total {
+        let group = match &entropy {
+            Some(entropy) => {
+                let (x, y) = (pos % u64::from(width), pos / u64::from(width));
+                let block = (y >> entropy.bits) * u64::from(entropy.width) + (x >> entropy.bits);
+                usize::try_from(block).ok().and_then(|x| entropy.groups.get(x)).copied()
+                    .ok_or_else(|| reader.error("has an entropy image which doesn't cover it"))?
+            },
+            None => 0,
+        };
+        let codes = groups[group].as_ref().ok_or_else(|| reader.error("uses an unread group"))?;
+
+        let green = codes[0].decode(reader)?;
+        let mut copy = None;
+        if green < 256 {
+            let red = codes[1].decode(reader)?;
+            let blue = codes[2].decode(reader)?;
+            let alpha = codes[3].decode(reader)?;
+            if keep {
+                pixels.push(u32::from(alpha) << 24 | u32::from(red) << 16 | u32::from(green) << 8
+                            | u32::from(blue));
+            }
+            pos += 1;
+        } else if green < 256 + LENGTH_PREFIXES {
+            let len = prefix_value(reader, green - 256)?;
+            let distance_prefix = codes[4].decode(reader)?;
+            let distance_code = prefix_value(reader, distance_prefix)?;
+            let mapped = usize::try_from(distance_code - 1).ok().and_then(|x| DISTANCE_MAP.get(x));
+            let distance = match mapped {
+                // Distances to nearby pixels are coded specially, and the closest is one
+                Some(&(left, up)) => {
+                    let distance = i64::from(left) + i64::from(up) * i64::from(width);
+                    u64::try_from(distance).unwrap_or(0).max(1)
+                },
+                None => distance_code - DISTANCE_MAP.len() as u64,
+            };
+            if distance > pos || len > total - pos {
+                return Err(reader.error(&format!(
+                    "has a backward reference outside the image at pixel {pos}")));
+            }
+            copy = Some((len, distance));
+            pos += len;
+        } else {
+            if keep {
+                pixels.push(cache[usize::from(green - 256 - LENGTH_PREFIXES)]);
+            }
+            pos += 1;
+        }
+
+        if keep {
+            let too_large = |_| unsupported("WebP image is too large to decode on this platform");
+            let copied = copy.map_or(Ok(1), |x| usize::try_from(x.0)).map_err(too_large)?;
+            if let Some((_, distance)) = copy {
+                let distance = usize::try_from(distance).map_err(too_large)?;
+                for _ in 0..copied {
+                    pixels.push(pixels[pixels.len() - distance]);
+                }
+            }
+            if cache_bits > 0 {
+                let start = pixels.len() - copied;
+                for &pixel in &pixels[start..] {
+                    let index = 0x1E35_A7BD_u32.wrapping_mul(pixel) >> (32 - cache_bits);
+                    cache[index as usize] = pixel;
+                }
+            }
+        }
+    }
+    Ok(pixels)
+}
+
+/// Decode the entropy coding of a VP8L (lossless) bitstream, returning its dimensions
+fn vp8l(chunk: &Chunk, payload: &mut dyn Read) -> io::Result<(u32, u32)> {
+    let mut reader = BitReader::new(chunk, payload);
+    if reader.read(8)? != 0x2F {
+        return Err(reader.error("doesn't start with the VP8L signature"));
+    }
+    let width = reader.read(14)? + 1;
+    let height = reader.read(14)? + 1;
+    reader.read(1)?;
+    if reader.read(3)? != 0 {
+        return Err(reader.error("has an unknown version"));
+    }
+    read_image(&mut reader, width, height, true, false)?;
+    Ok((width, height))
+}
+
+/// What's been seen in a WebP file so far
+#[allow(clippy::struct_excessive_bools)] // One for each kind of chunk which may only appear once
+#[derive(Default)]
+struct Walker {
+    /// How many top-level chunks have been walked
+    chunks: u32,
+    /// The flags from the `VP8X` chunk, if the file is in the extended format
+    flags: Option<u8>,
+    /// Whether an `ICCP` chunk has been seen
+    iccp: bool,
+    /// Whether an `ANIM` chunk has been seen
+    anim: bool,
+    /// Whether an `EXIF` chunk has been seen
+    exif: bool,
+    /// Whether an `XMP ` chunk has been seen
+    xmp: bool,
+    /// Whether an `ALPH` chunk is waiting for the bitstream it belongs to
+    alpha: bool,
+    /// Whether the bitstream of a still image has been seen
+    image: bool,
+    /// What has been found so far
+    summary: Summary,
+}
+
+impl Walker {
+    /// Check a top-level chunk
+    fn chunk(&mut self, chunk: &Chunk, payload: &mut dyn Read) -> io::Result<()> {
+        self.chunks += 1;
+        let flags = match (self.flags, &chunk.id) {
+            (Some(flags), _) => flags,
+            (None, b"VP8X") if self.chunks == 1 => return self.vp8x(chunk, payload),
+            (None, b"VP8 " | b"VP8L") if self.chunks == 1 => {
+                let (width, height) = self.bitstream(chunk, payload)?;
+                self.summary.width = width;
+                self.summary.height = height;
+                self.summary.frames = 1;
+                self.image = true;
+                return Ok(());
+            },
+            (None, _) if self.chunks == 1 => {
+                return Err(corrupt("WebP file doesn't start with a VP8, VP8L, or VP8X chunk"));
+            },
+            (None, b"VP8X" | b"VP8 " | b"VP8L" | b"ALPH" | b"ANIM" | b"ANMF") => {
+                return Err(corrupt(&format!("{} comes after the image data",
+                                            chunk.describe())));
+            },
+            (None, _) => return Ok(()),
+        };
+
+        let animated = flags & FLAG_ANIMATION != 0;
+        let started = self.anim || self.alpha || self.image || self.summary.frames > 0;
+        let duplicate = match &chunk.id {
+            b"ALPH" => self.alpha,
+            b"EXIF" => self.exif,
+            b"XMP " => self.xmp,
+            _ => false,
+        };
+        match &chunk.id {
+            b"VP8X" => Err(corrupt(&format!("{} isn't the first chunk", chunk.describe()))),
+            b"ICCP" if self.iccp || started => Err(corrupt(&format!(
+                "{} isn't the only ICCP chunk before the image data", chunk.describe()))),
+            b"ANIM" if !animated || self.anim || self.summary.frames > 0 => Err(corrupt(&format!(
+                "{} isn't the only ANIM chunk before the frames of an animated file",
+                chunk.describe()))),
+            b"ANIM" if chunk.size < 6 => {
+                Err(corrupt(&format!("{} is too short", chunk.describe())))
+            },
+            b"ANMF" if !self.anim => Err(corrupt(&format!(
+                "{} doesn't come after an ANIM chunk in an animated file", chunk.describe()))),
+            b"ALPH" | b"VP8 " | b"VP8L" if animated => Err(corrupt(&format!(
+                "{} is outside of any ANMF chunk in an animated file", chunk.describe()))),
+            b"ALPH" | b"VP8 " | b"VP8L" if self.image => Err(corrupt(&format!(
+                "{} comes after the image data", chunk.describe()))),
+            _ if duplicate => Err(corrupt(&format!("{} is a duplicate", chunk.describe()))),
+            b"ANMF" => self.frame(chunk, payload),
+            b"ALPH" => {
+                self.alpha = true;
+                self.alph(chunk, payload, self.summary.width, self.summary.height)
+            },
+            b"VP8 " | b"VP8L" => {
+                let size = self.bitstream(chunk, payload)?;
+                if size != (self.summary.width, self.summary.height) {
+                    return Err(corrupt(&format!(
+                        "WebP canvas is {}x{}, but the image in the {} is {}x{}",
+                        self.summary.width, self.summary.height, chunk.describe(), size.0,
+                        size.1)));
+                }
+                self.summary.frames = 1;
+                self.image = true;
+                self.alpha = false;
+                Ok(())
+            },
+            b"ICCP" => {
+                self.iccp = true;
+                Ok(())
+            },
+            b"ANIM" => {
+                self.anim = true;
+                Ok(())
+            },
+            b"EXIF" => {
+                self.exif = true;
+                Ok(())
+            },
+            b"XMP " => {
+                self.xmp = true;
+                Ok(())
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Read the `VP8X` chunk of an extended format file
+    fn vp8x(&mut self, chunk: &Chunk, payload: &mut dyn Read) -> io::Result<()> {
+        if chunk.size < 10 {
+            return Err(corrupt(&format!("{} is too short", chunk.describe())));
+        }
+        let mut data = [0; 10];
+        read_exact(payload, &mut data)?;
+        if data[0] & !(FLAG_ICC | FLAG_ALPHA | FLAG_EXIF | FLAG_XMP | FLAG_ANIMATION) != 0 {
+            return Err(corrupt(&format!("{} has reserved flags set", chunk.describe())));
+        }
+        let (width, height) = (u24(&data[4..]) + 1, u24(&data[7..]) + 1);
+        if u64::from(width) * u64::from(height) >= MAX_AREA {
+            return Err(corrupt(&format!("WebP canvas is too large ({width}x{height})")));
+        }
+        self.flags = Some(data[0]);
+        self.summary.width = width;
+        self.summary.height = height;
+        Ok(())
+    }
+
+    /// Check an image bitstream, returning its dimensions
+    fn bitstream(&mut self, chunk: &Chunk, payload: &mut dyn Read) -> io::Result<(u32, u32)> {
+        if &chunk.id == b"VP8 " {
+            vp8_header(chunk, payload)
+        } else {
+            self.summary.lossless += 1;
+            vp8l(chunk, payload)
+        }
+    }
+
+    /// Check the `ALPH` chunk for an image which is `width` by `height`
+    fn alph(&mut self, chunk: &Chunk, payload: &mut dyn Read, width: u32, height: u32)
+            -> io::Result<()> {
+        let mut header = [0];
+        if chunk.size < 1 {
+            return Err(corrupt(&format!("{} is too short", chunk.describe())));
+        }
+        read_exact(payload, &mut header)?;
+        let (compression, preprocessing, reserved) =
+            (header[0] & 0x03, (header[0] >> 4) & 0x03, header[0] >> 6);
+        if compression > 1 || preprocessing > 1 || reserved != 0 {
+            return Err(corrupt(&format!("{} has an invalid header", chunk.describe())));
+        } else if width > MAX_BITSTREAM_DIMENSION || height > MAX_BITSTREAM_DIMENSION {
+            return Err(corrupt(&format!("{} is for an image larger than a VP8 bitstream can be",
+                                        chunk.describe())));
+        }
+
+        if compression == 0 {
+            if u64::from(chunk.size - 1) < u64::from(width) * u64::from(height) {
+                return Err(corrupt(&format!("{} is too short for a {}x{} image",
+                                            chunk.describe(), width, height)));
+            }
+        } else {
+            // Compressed alpha is a VP8L bitstream without the header
+            self.summary.lossless += 1;
+            read_image(&mut BitReader::new(chunk, payload), width, height, true, false)?;
+        }
+        Ok(())
+    }
+
+    /// Check an `ANMF` chunk and the frame it holds
+    fn frame(&mut self, chunk: &Chunk, payload: &mut dyn Read) -> io::Result<()> {
+        if chunk.size < 16 {
+            return Err(corrupt(&format!("{} is too short", chunk.describe())));
+        }
+        let mut header = [0; 16];
+        read_exact(payload, &mut header)?;
+        let (left, top) = (u24(&header[0..]) * 2, u24(&header[3..]) * 2);
+        let (width, height) = (u24(&header[6..]) + 1, u24(&header[9..]) + 1);
+        if left + width > self.summary.width || top + height > self.summary.height {
+            return Err(corrupt(&format!("The frame in the {} doesn't fit within the {}x{} canvas",
+                                        chunk.describe(), self.summary.width,
+                                        self.summary.height)));
+        }
+
+        // An optional ALPH chunk, the image bitstream, and possibly some unknown chunks
+        let (mut alpha, mut image) = (false, false);
+        let mut remaining = u64::from(chunk.size) - 16;
+        let mut pos = chunk.start + CHUNK_HEADER_SIZE + 16;
+        while remaining > 0 {
+            if remaining < CHUNK_HEADER_SIZE {
+                return Err(corrupt(&format!("WebP chunk header at byte {pos} is cut off")));
+            }
+            let sub = read_chunk(payload, pos)?;
+            remaining -= CHUNK_HEADER_SIZE;
+            if u64::from(sub.size) > remaining {
+                return Err(corrupt(&format!("{} runs past the end of its ANMF chunk",
+                                            sub.describe())));
+            }
+            let mut sub_payload = (&mut *payload).take(u64::from(sub.size));
+            match &sub.id {
+                b"ALPH" if !alpha && !image => {
+                    alpha = true;
+                    self.alph(&sub, &mut sub_payload, width, height)?;
+                },
+                b"VP8 " | b"VP8L" if !image => {
+                    image = true;
+                    let size = self.bitstream(&sub, &mut sub_payload)?;
+                    if size != (width, height) {
+                        return Err(corrupt(&format!(
+                            "The frame in the {} is {}x{}, but its image is {}x{}",
+                            chunk.describe(), width, height, size.0, size.1)));
+                    }
+                },
+                b"ALPH" | b"VP8 " | b"VP8L" => return Err(corrupt(&format!(
+                    "{} has more than one image in its frame", chunk.describe()))),
+                _ => {},
+            }
+            finish_chunk(sub_payload)?;
+
+            // The padding may be left off the last chunk
+            let padded = (u64::from(sub.size) + u64::from(sub.size % 2)).min(remaining);
+            if padded > u64::from(sub.size) {
+                read_exact(payload, &mut [0])?;
+            }
+            remaining -= padded;
+            pos += CHUNK_HEADER_SIZE + padded;
+        }
+        if !image {
+            return Err(corrupt(&format!("{} has no image in its frame", chunk.describe())));
+        }
+        self.summary.frames += 1;
+        Ok(())
+    }
+
+    /// Check that nothing was missing once all of the chunks have been walked
+    fn finish(self) -> io::Result<Summary> {
+        let flags = self.flags.unwrap_or(0);
+        if flags & FLAG_ANIMATION != 0 && self.summary.frames == 0 {
+            return Err(corrupt("Animated WebP file has no frames"));
+        } else if flags & FLAG_ANIMATION == 0 && !self.image {
+            return Err(corrupt("WebP file has no image data"));
+        }
+        if self.flags.is_some() {
+            for &(flag, present, name) in
+                    &[(FLAG_ICC, self.iccp, "ICCP"), (FLAG_EXIF, self.exif, "EXIF"),
+                      (FLAG_XMP, self.xmp, "XMP")] {
+                if (flags & flag != 0) != present {
+                    return Err(corrupt(&format!(
+                        "WebP VP8X flags disagree with whether there's an {name} chunk")));
+                }
+            }
+        }
+        Ok(self.summary)
+    }
+}
+
+/// Check the structure of the WebP file read from `input`
+///
+/// The `RIFF` chunk's size must match the file's length (give or take a padding byte) and every
+/// chunk must fit inside it. Extended format files must have their chunks in the order the
+/// specification requires, with the flags in the `VP8X` chunk agreeing with which optional chunks
+/// are present and the canvas agreeing with the size of the image or frames.
+pub fn verify(input: impl Read) -> io::Result<Summary> {
+    let mut input = Counted { input, pos: 0 };
+    let mut header = [0; 12];
+    read_exact(&mut input, &mut header)?;
+    if &header[..4] != b"RIFF" || &header[8..] != b"WEBP" {
+        return Err(corrupt("Not a WebP file"));
+    }
+    let riff_end = 8 + u64::from(u32::from_le_bytes([header[4], header[5], header[6],
+                                                      header[7]]));
+    if riff_end < 12 {
+        return Err(corrupt("WebP RIFF chunk is too short"));
+    }
+
+    let mut walker = Walker::default();
+    loop {
+        // Chunks are word-aligned, but the last one in the file may omit its padding byte,
+        // whether or not the RIFF size counts it
+        if input.pos % 2 == 1 && input.pos < riff_end && input.read(&mut [0])? == 0 {
+            if input.pos + 1 == riff_end {
+                break;
+            }
+            return Err(corrupt("WebP file is truncated"));
+        }
+        if input.pos >= riff_end {
+            break;
+        }
+
+        let start = input.pos;
+        if riff_end - start < CHUNK_HEADER_SIZE {
+            return Err(corrupt(&format!("WebP chunk header at byte {start} is cut off")));
+        }
+        let chunk = read_chunk(&mut input, start)?;
+        if input.pos + u64::from(chunk.size) > riff_end {
+            return Err(corrupt(&format!("{} runs past the end of the RIFF chunk",
+                                        chunk.describe())));
+        }
+        let mut payload = (&mut input).take(u64::from(chunk.size));
+        walker.chunk(&chunk, &mut payload)?;
+        finish_chunk(payload)?;
+    }
+
+    // The file may only continue with a padding byte which the RIFF size doesn't count
+    let allowed = u64::from(input.pos == riff_end && riff_end % 2 == 1);
+    if io::copy(&mut input.take(2), &mut io::sink())? > allowed {
+        return Err(corrupt("WebP file has data after its RIFF chunk"));
+    }
+    walker.finish()
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::*;
+
+    /// The simple format lossy fixture
+    const LOSSY: &[u8] = include_bytes!("../../../test_data/good/testfile.webp");
+
+    /// The extended format fixture with `VP8X`, `ALPH`, `VP8 `, and `XMP ` chunks
+    const ALPHA: &[u8] = include_bytes!("../../../test_data/good/testfile.alpha.webp");
+
+    /// Walk `data`, expecting a failure whose message contains `message`
+    fn check_err(data: &[u8], message: &str) {
+        let err = verify(data).expect_err(message);
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{err}");
+        assert!(err.to_string().contains(message), "{}", err);
+    }
+
+    /// Wrap `chunks` in a `RIFF` chunk, padding them as needed
+    fn riff(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+        let mut body = b"WEBP".to_vec();
+        for (id, payload) in chunks {
+            body.extend_from_slice(&id[..]);
+            body.extend_from_slice(&u32::try_from(payload.len()).unwrap().to_le_bytes());
+            body.extend_from_slice(payload);
+            if payload.len() % 2 == 1 {
+                body.push(0);
+            }
+        }
+        let mut data = b"RIFF".to_vec();
+        data.extend_from_slice(&u32::try_from(body.len()).unwrap().to_le_bytes());
+        data.extend(body);
+        data
+    }
+
+    /// A `VP8X` payload with the given flags for a canvas the size of the lossy fixture
+    fn vp8x(flags: u8) -> [u8; 10] {
+        [flags, 0, 0, 0, 1, 0, 0, 1, 0, 0]
+    }
+
+    #[test]
+    fn test_fixtures() {
+        for (name, data, width, frames, lossless) in &[
+            ("lossy", LOSSY, 2, 1, 0),
+            ("alpha", ALPHA, 2, 1, 1),
+            ("lossless", &include_bytes!("../../../test_data/good/testfile.lossless.webp")[..],
+                16, 1, 1),
+            ("animated", &include_bytes!("../../../test_data/good/testfile.animated.webp")[..],
+                16, 2, 1),
+        ] {
+            let summary = verify(*data).unwrap_or_else(|err| panic!("{}: {}", name, err));
+            assert_eq!((summary.width, summary.height), (*width, *width), "{name}");
+            assert_eq!(summary.frames, *frames, "{name}");
+            assert_eq!(summary.lossless, *lossless, "{name}");
+        }
+    }
+
+    #[test]
+    fn test_bad_fixtures() {
+        check_err(include_bytes!("../../../test_data/bad/testfile.webp"),
+            "WebP VP8 chunk at byte 12 runs past the end of the RIFF chunk");
+        check_err(include_bytes!("../../../test_data/bad/testfile.lossless.webp"),
+            "Lossless bitstream in WebP VP8L chunk at byte 12 is truncated");
+        check_err(include_bytes!("../../../test_data/bad/testfile.animated.webp"),
+            "The frame in the WebP ANMF chunk at byte 160 doesn't fit within the 16x16 canvas");
+    }
+
+    #[test]
+    fn test_chunk_order() {
+        let image = &LOSSY[20..];
+        verify(&riff(&[(b"VP8X", &vp8x(FLAG_ICC)), (b"ICCP", b"profile"), (b"VP8 ", image)])[..])
+            .expect("ICCP before the image");
+
+        check_err(&riff(&[(b"VP8X", &vp8x(FLAG_ICC)), (b"VP8 ", image), (b"ICCP", b"profile")]),
+            "WebP ICCP chunk at byte 96 isn't the only ICCP chunk before the image data");
+        check_err(&riff(&[(b"VP8 ", image), (b"VP8X", &vp8x(0))]),
+            "WebP VP8X chunk at byte 78 comes after the image data");
+        check_err(&riff(&[(b"VP8X", &vp8x(0)), (b"VP8 ", image), (b"VP8 ", image)]),
+            "WebP VP8 chunk at byte 96 comes after the image data");
+        check_err(&riff(&[(b"VP8X", &vp8x(FLAG_ANIMATION)), (b"VP8 ", image)]),
+            "WebP VP8 chunk at byte 30 is outside of any ANMF chunk in an animated file");
+        check_err(&riff(&[(b"VP8X", &vp8x(0)), (b"XMP ", b"<x/>"), (b"XMP ", b"<x/>"),
+                          (b"VP8 ", image)]),
+            "WebP XMP chunk at byte 42 is a duplicate");
+    }
+
+    #[test]
+    fn test_flags_and_trailing_data() {
+        let mut data = ALPHA.to_vec();
+        data[20] &= !FLAG_XMP;
+        check_err(&data, "WebP VP8X flags disagree with whether there's an XMP chunk");
+
+        data[20] |= FLAG_XMP | FLAG_EXIF;
+        check_err(&data, "WebP VP8X flags disagree with whether there's an EXIF chunk");
+
+        let mut data = LOSSY.to_vec();
+        data.push(0);
+        check_err(&data, "WebP file has data after its RIFF chunk");
+    }
+
+    #[test]
+    fn test_prefix_code() {
+        assert!(PrefixCode::new(&[1, 1]).is_some());
+        assert!(PrefixCode::new(&[2, 1, 0, 2]).is_some());
+        assert!(PrefixCode::new(&[0, 0, 7, 0]).is_some(), "single symbol");
+        assert!(PrefixCode::new(&[1, 2]).is_none(), "incomplete");
+        assert!(PrefixCode::new(&[1, 1, 1]).is_none(), "oversubscribed");
+        assert!(PrefixCode::new(&[0, 0]).is_none(), "empty");
+    }
+}
@@ -0,0 +1,715 @@
+//! A checker for WOFF and WOFF2 web fonts
+//!
+//! (WOFF compresses each table of a TrueType/OpenType font separately with zlib and keeps their
+//! original checksums, so those can be checked after decompression. WOFF2 compresses all of the
+//! tables as a single Brotli stream and drops the checksums, so all that can be checked there is
+//! that the stream decompresses and that the tables in it agree with each other.)
+
+// Standard library imports
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::convert::{TryFrom, TryInto};
+use std::io::{self, Read};
+
+// 3rd-party imports
+use flate2::read::ZlibDecoder;
+
+// Local Imports
+use super::brotli::BrotliDecoder;
+use super::bytes::{be16, be32};
+use super::checksums::SfntChecksum;
+use super::{corrupt, unsupported};
+
+/// The signature at the start of a WOFF file
+const WOFF_SIGNATURE: &[u8; 4] = b"wOFF";
+
+/// The signature at the start of a WOFF2 file
+const WOFF2_SIGNATURE: &[u8; 4] = b"wOF2";
+
+/// The size of the WOFF header
+const WOFF_HEADER_SIZE: usize = 44;
+
+/// The size of the WOFF2 header
+const WOFF2_HEADER_SIZE: usize = 48;
+
+/// The `flavor` of a font collection
+const COLLECTION_FLAVOR: &[u8; 4] = b"ttcf";
+
+/// The magic number in every `head` table
+const HEAD_MAGIC: u32 = 0x5F0F_3CF5;
+
+/// The size of version 1.0 of the `head` table
+const HEAD_SIZE: usize = 54;
+
+/// The offset of `checkSumAdjustment` within the `head` table
+const ADJUSTMENT_OFFSET: usize = 8;
+
+/// The offset of `indexToLocFormat` within the `head` table
+const INDEX_FORMAT_OFFSET: usize = 50;
+
+/// The tags WOFF2 can refer to by index in its table directory, in index order
+const KNOWN_TAGS: [&[u8; 4]; 63] = [
+    b"cmap", b"head", b"hhea", b"hmtx", b"maxp", b"name", b"OS/2", b"post", b"cvt ", b"fpgm",
+    b"glyf", b"loca", b"prep", b"CFF ", b"VORG", b"EBDT", b"EBLC", b"gasp", b"hdmx", b"kern",
+    b"LTSH", b"PCLT", b"VDMX", b"vhea", b"vmtx", b"BASE", b"GDEF", b"GPOS", b"GSUB", b"EBSC",
+    b"JSTF", b"MATH", b"CBDT", b"CBLC", b"COLR", b"CPAL", b"SVG ", b"sbix", b"acnt", b"avar",
+    b"bdat", b"bloc", b"bsln", b"cvar", b"fdsc", b"feat", b"fmtx", b"fvar", b"gvar", b"hsty",
+    b"just", b"lcar", b"mort", b"morx", b"opbd", b"prop", b"trak", b"Zapf", b"Silf", b"Glat",
+    b"Gloc", b"Feat", b"Sill",
+];
+
+/// Render a table tag for use in error messages
+fn name(tag: &[u8]) -> String {
+    String::from_utf8_lossy(tag).trim_end().to_owned()
+}
+
+/// Round `len` up to the four-byte boundary tables are padded to in an sfnt file
+fn padded(len: u32) -> u64 {
+    (u64::from(len) + 3) & !3
+}
+
+/// Get the `len` bytes at `offset` in `data`, or report `what` as running past the end
+fn block<'a>(data: &'a [u8], offset: u32, len: u32, what: &str) -> io::Result<&'a [u8]> {
+    let start = offset as usize;
+    start.checked_add(len as usize).and_then(|end| data.get(start..end)).ok_or_else(|| {
+        corrupt(&format!("{} runs past the end of the file (truncated?)", what))
+    })
+}
+
+/// Decompress a block which should come to `len` bytes
+fn decompress(reader: impl Read, len: u32, what: &str) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    reader.take(u64::from(len) + 1).read_to_end(&mut out)
+        .map_err(|err| corrupt(&format!("{} failed to decompress: {}", what, err)))?;
+    if out.len() as u64 != u64::from(len) {
+        return Err(corrupt(&format!("{} decompresses to {} bytes instead of {}",
+            what, out.len(), len)));
+    }
+    Ok(out)
+}
+
+/// A reader for the fields of a header, directory, or data stream, which reports running out of
+/// data as truncation of `what`
+struct Fields<'a> {
+    /// The data being read
+    data: &'a [u8],
+    /// How much of `data` has been read
+    pos: usize,
+    /// What `data` is, for error messages
+    what: &'static str,
+}
+
+impl<'a> Fields<'a> {
+    /// Start reading `data`, which holds `what`
+    fn new(data: &'a [u8], what: &'static str) -> Self {
+        Self { data, pos: 0, what }
+    }
+
+    /// Read the next `len` bytes
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos.saturating_add(len))
+            .ok_or_else(|| corrupt(&format!("{} is truncated", self.what)))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    /// Read a `u8`
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// Read a big-endian `u16`
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(be16(self.take(2)?))
+    }
+
+    /// Read a big-endian `u32`
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(be32(self.take(4)?))
+    }
+
+    /// Read a WOFF2 `UIntBase128`
+    fn base128(&mut self) -> io::Result<u32> {
+        let mut value: u32 = 0;
+        for index in 0..5 {
+            let byte = self.u8()?;
+            if (index == 0 && byte == 0x80) || value.leading_zeros() < 7 {
+                return Err(corrupt(&format!("{} contains an invalid UIntBase128", self.what)));
+            }
+            value = (value << 7) | u32::from(byte & 0x7F);
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(corrupt(&format!("{} contains an invalid UIntBase128", self.what)))
+    }
+
+    /// Read a WOFF2 `255UInt16`
+    fn u255(&mut self) -> io::Result<u16> {
+        Ok(match self.u8()? {
+            253 => self.u16()?,
+            254 => 506 + u16::from(self.u8()?),
+            255 => 253 + u16::from(self.u8()?),
+            byte => u16::from(byte),
+        })
+    }
+
+    /// Check that everything has been read
+    fn finish(&self) -> io::Result<()> {
+        if self.pos != self.data.len() {
+            return Err(corrupt(&format!("{} has {} bytes of unused data",
+                self.what, self.data.len() - self.pos)));
+        }
+        Ok(())
+    }
+}
+
+/// The fields of a WOFF or WOFF2 header
+struct Header {
+    /// The sfnt version of the font inside
+    flavor: [u8; 4],
+    /// How many tables the font has
+    num_tables: u16,
+    /// How large the font would be as a TrueType/OpenType file
+    total_sfnt_size: u32,
+    /// The length of the compressed font data (WOFF2 only)
+    total_compressed_size: u32,
+    /// The offset, compressed length, and decompressed length of the metadata block
+    metadata: (u32, u32, u32),
+    /// The offset and length of the private data block
+    private: (u32, u32),
+}
+
+impl Header {
+    /// Read and check the header at the start of `data`, which is named `format` in messages
+    fn read(data: &[u8], format: &'static str) -> io::Result<Self> {
+        let mut fields = Fields::new(data, "Font header");
+        fields.take(4)?;
+        let flavor = fields.take(4)?.try_into().expect("4 bytes");
+        let length = fields.u32()?;
+        let num_tables = fields.u16()?;
+        let reserved = fields.u16()?;
+        let total_sfnt_size = fields.u32()?;
+        let total_compressed_size = if format == "WOFF2" { fields.u32()? } else { 0 };
+        fields.take(4)?;
+        let metadata = (fields.u32()?, fields.u32()?, fields.u32()?);
+        let private = (fields.u32()?, fields.u32()?);
+
+        if length as usize != data.len() {
+            return Err(corrupt(&format!("{} header gives the file length as {} bytes, but it's {}",
+                format, length, data.len())));
+        }
+        if reserved != 0 {
+            return Err(corrupt(&format!("Reserved field in {} header isn't zero", format)));
+        }
+        if num_tables == 0 {
+            return Err(corrupt(&format!("{} font has no tables", format)));
+        }
+        Ok(Self { flavor, num_tables, total_sfnt_size, total_compressed_size, metadata, private })
+    }
+
+    /// Check `total_sfnt_size` against the uncompressed lengths of the tables
+    fn check_sfnt_size(&self, lengths: impl Iterator<Item = u32>, format: &str) -> io::Result<()> {
+        let expected = 12 + 16 * u64::from(self.num_tables) + lengths.map(padded).sum::<u64>();
+        if u64::from(self.total_sfnt_size) != expected {
+            return Err(corrupt(&format!(
+                "{} header gives the uncompressed size as {} bytes, but the tables need {}",
+                format, self.total_sfnt_size, expected)));
+        }
+        Ok(())
+    }
+
+    /// Check the extended metadata and private data blocks, which follow the font data (ending
+    /// at `font_end`), decompressing the former with `decompressor`
+    fn check_extra_blocks<'a, D: Read>(&self, data: &'a [u8], font_end: usize, format: &str,
+            decompressor: impl FnOnce(&'a [u8]) -> D) -> io::Result<()> {
+        let (offset, len, orig_len) = self.metadata;
+        let mut end = font_end;
+        if offset != 0 || len != 0 {
+            let what = format!("{} metadata block", format);
+            let compressed = block(data, offset, len, &what)?;
+            if (offset as usize) < end {
+                return Err(corrupt(&format!("{} overlaps the font data", what)));
+            }
+            decompress(decompressor(compressed), orig_len, &what)?;
+            end = offset as usize + len as usize;
+        }
+
+        let (offset, len) = self.private;
+        if offset != 0 || len != 0 {
+            let what = format!("{} private data block", format);
+            block(data, offset, len, &what)?;
+            if (offset as usize) < end {
+                return Err(corrupt(&format!("{} overlaps the data before it", what)));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check a WOFF file, decompressing every table and checking it against its checksum
+fn verify_woff(data: &[u8]) -> io::Result<()> {
+    let header = Header::read(data, "WOFF")?;
+    if &header.flavor == COLLECTION_FLAVOR {
+        return Err(corrupt("WOFF files can't contain font collections"));
+    }
+
+    let mut directory = Fields::new(&data[WOFF_HEADER_SIZE..], "WOFF table directory");
+    let (mut lengths, mut font_end) = (Vec::new(), 0);
+    for _ in 0..header.num_tables {
+        let tag = directory.take(4)?;
+        let (offset, comp_length) = (directory.u32()?, directory.u32()?);
+        let (orig_length, orig_checksum) = (directory.u32()?, directory.u32()?);
+        let what = format!("WOFF table '{}'", name(tag));
+        let compressed = block(data, offset, comp_length, &what)?;
+        if offset % 4 != 0 {
+            return Err(corrupt(&format!("{} isn't aligned to a four-byte boundary", what)));
+        }
+
+        let table = if comp_length == orig_length {
+            Cow::Borrowed(compressed)
+        } else if comp_length < orig_length {
+            Cow::Owned(decompress(ZlibDecoder::new(compressed), orig_length, &what)?)
+        } else {
+            return Err(corrupt(&format!("{} is larger compressed than uncompressed", what)));
+        };
+
+        // The checksum of `head` is calculated as if `checkSumAdjustment` were zero
+        let mut sum = SfntChecksum::new();
+        sum.update(&table);
+        let mut actual = sum.finish();
+        if tag == b"head" {
+            if table.len() < HEAD_SIZE || be32(&table[12..]) != HEAD_MAGIC {
+                return Err(corrupt("WOFF table 'head' is invalid"));
+            }
+            actual = actual.wrapping_sub(be32(&table[ADJUSTMENT_OFFSET..]));
+        }
+        if actual != orig_checksum {
+            return Err(corrupt(&format!("{} has a bad checksum", what)));
+        }
+        lengths.push(orig_length);
+        font_end = font_end.max(offset as usize + comp_length as usize);
+    }
+
+    header.check_sfnt_size(lengths.into_iter(), "WOFF")?;
+    header.check_extra_blocks(data, font_end, "WOFF", ZlibDecoder::new)
+}
+
+/// One entry in a WOFF2 table directory
+struct Table {
+    /// The table's tag
+    tag: [u8; 4],
+    /// The table's length once fully decoded
+    orig_length: u32,
+    /// The table's length in the decompressed data
+    length: u32,
+    /// Whether the table is stored in a transformed form
+    transformed: bool,
+    /// Where the table starts in the decompressed data
+    offset: usize,
+}
+
+/// Read a WOFF2 table directory entry
+fn read_table(directory: &mut Fields<'_>) -> io::Result<Table> {
+    let flags = directory.u8()?;
+    let tag: [u8; 4] = match KNOWN_TAGS.get(usize::from(flags & 0x3F)) {
+        Some(tag) => **tag,
+        None => directory.take(4)?.try_into().expect("4 bytes"),
+    };
+    let orig_length = directory.base128()?;
+
+    // Version 0 is the null transform for everything but `glyf` and `loca`, where it's version 3
+    let version = flags >> 6;
+    let transformed = match (&tag, version) {
+        (b"glyf", 0) | (b"loca", 0) | (b"hmtx", 1) => true,
+        (b"glyf", 3) | (b"loca", 3) => false,
+        (tag, 0) if tag != b"glyf" && tag != b"loca" => false,
+        _ => {
+            return Err(unsupported(&format!(
+                "WOFF2 table '{}' uses unknown transform version {}", name(&tag), version)));
+        },
+    };
+    let length = if transformed { directory.base128()? } else { orig_length };
+    if &tag == b"loca" && transformed && length != 0 {
+        return Err(corrupt("WOFF2 table 'loca' is transformed but not empty"));
+    }
+    Ok(Table { tag, orig_length, length, transformed, offset: 0 })
+}
+
+/// Read the WOFF2 collection directory, returning the table indexes of each font
+fn read_collection(directory: &mut Fields<'_>, num_tables: usize)
+        -> io::Result<Vec<Vec<usize>>> {
+    let version = directory.u32()?;
+    if version != 0x0001_0000 && version != 0x0002_0000 {
+        return Err(corrupt(&format!("WOFF2 font collection has an unknown version: {:#010X}",
+            version)));
+    }
+    let num_fonts = directory.u255()?;
+    if num_fonts == 0 {
+        return Err(corrupt("WOFF2 font collection contains no fonts"));
+    }
+    (0..num_fonts).map(|_| {
+        let font_tables = directory.u255()?;
+        directory.take(4)?;
+        if font_tables == 0 {
+            return Err(corrupt("WOFF2 font collection contains a font with no tables"));
+        }
+        (0..font_tables).map(|_| match usize::from(directory.u16()?) {
+            index if index < num_tables => Ok(index),
+            _ => Err(corrupt("WOFF2 font collection refers to a table which doesn't exist")),
+        }).collect()
+    }).collect()
+}
+
+/// Check the transformed `glyf` table in `data` by walking its streams the way reconstruction
+/// would, given `num_glyphs` from `maxp` (if present) and the `loca` table's directory entry
+fn verify_glyf(data: &[u8], num_glyphs: Option<u16>, index_format: u16, loca: &Table)
+        -> io::Result<()> {
+    let mut header = Fields::new(data, "WOFF2 transformed 'glyf' table");
+    header.take(2)?;
+    let option_flags = header.u16()?;
+    let glyphs = header.u16()?;
+    if header.u16()? != index_format || index_format > 1 {
+        return Err(corrupt("WOFF2 transformed 'glyf' table has the wrong indexToLocFormat"));
+    }
+    if num_glyphs.map_or(false, |x| x != glyphs) {
+        return Err(corrupt("WOFF2 transformed 'glyf' table and 'maxp' disagree on glyph count"));
+    }
+    if u64::from(loca.orig_length) != (u64::from(glyphs) + 1) * (2 << index_format) {
+        return Err(corrupt("WOFF2 table 'loca' has the wrong length for the glyph count"));
+    }
+
+    let mut sizes = [0; 7];
+    for size in &mut sizes {
+        *size = header.u32()? as usize;
+    }
+    let mut contours = Fields::new(header.take(sizes[0])?, "WOFF2 glyph contour count stream");
+    let mut points = Fields::new(header.take(sizes[1])?, "WOFF2 glyph point count stream");
+    let mut flags = Fields::new(header.take(sizes[2])?, "WOFF2 glyph flag stream");
+    let mut glyph = Fields::new(header.take(sizes[3])?, "WOFF2 glyph stream");
+    let mut composite = Fields::new(header.take(sizes[4])?, "WOFF2 composite glyph stream");
+    let mut bbox = Fields::new(header.take(sizes[5])?, "WOFF2 glyph bounding box stream");
+    let mut instructions = Fields::new(header.take(sizes[6])?, "WOFF2 glyph instruction stream");
+    let bitmap_len = (usize::from(glyphs) + 31) / 32 * 4;
+    if option_flags & 1 != 0 {
+        header.take(bitmap_len)?;
+    }
+    header.finish()?;
+
+    let bbox_bitmap = bbox.take(bitmap_len)?;
+    for index in 0..usize::from(glyphs) {
+        let has_bbox = bbox_bitmap[index >> 3] & (0x80 >> (index & 7)) != 0;
+        let mut has_instructions = true;
+        match contours.u16()? as i16 {
+            0 if has_bbox => return Err(corrupt("WOFF2 empty glyph has a bounding box")),
+            0 => has_instructions = false,
+            -1 if !has_bbox => return Err(corrupt("WOFF2 composite glyph has no bounding box")),
+            -1 => {
+                has_instructions = false;
+                loop {
+                    let component_flags = composite.u16()?;
+                    let args = if component_flags & 0x0001 == 0 { 2 } else { 4 };
+                    let scale = match component_flags {
+                        x if x & 0x0008 != 0 => 2,
+                        x if x & 0x0040 != 0 => 4,
+                        x if x & 0x0080 != 0 => 8,
+                        _ => 0,
+                    };
+                    composite.take(2 + args + scale)?;
+                    has_instructions |= component_flags & 0x0100 != 0;
+                    if component_flags & 0x0020 == 0 {
+                        break;
+                    }
+                }
+            },
+            count if count > 0 => {
+                let mut total = 0;
+                for _ in 0..count {
+                    total += usize::from(points.u255()?);
+                }
+                for &flag in flags.take(total)? {
+                    glyph.take(match flag & 0x7F {
+                        0..=83 => 1,
+                        84..=119 => 2,
+                        120..=123 => 3,
+                        _ => 4,
+                    })?;
+                }
+            },
+            _ => return Err(corrupt("WOFF2 glyph has an invalid contour count")),
+        }
+        if has_instructions {
+            instructions.take(usize::from(glyph.u255()?))?;
+        }
+        if has_bbox {
+            bbox.take(8)?;
+        }
+    }
+
+    for stream in &[contours, points, flags, glyph, composite, bbox, instructions] {
+        stream.finish()?;
+    }
+    Ok(())
+}
+
+/// Check the transformed `hmtx` table in `data` against the glyph and metric counts
+fn verify_hmtx(data: &[u8], num_glyphs: u16, num_metrics: u16) -> io::Result<()> {
+    let flags = *data.first().ok_or_else(|| corrupt("WOFF2 transformed 'hmtx' table is empty"))?;
+    if flags & 0xFC != 0 || flags & 0x03 == 0 {
+        return Err(corrupt("WOFF2 transformed 'hmtx' table has invalid flags"));
+    }
+    if num_metrics == 0 || num_metrics > num_glyphs {
+        return Err(corrupt("WOFF2 font has an invalid numberOfHMetrics"));
+    }
+    let (glyphs, metrics) = (usize::from(num_glyphs), usize::from(num_metrics));
+    let mut expected = 1 + 2 * metrics;
+    if flags & 0x01 == 0 {
+        expected += 2 * metrics;
+    }
+    if flags & 0x02 == 0 {
+        expected += 2 * (glyphs - metrics);
+    }
+    if data.len() != expected {
+        return Err(corrupt("WOFF2 transformed 'hmtx' table has the wrong length"));
+    }
+    Ok(())
+}
+
+/// Check that the tables making up one font in a WOFF2 file agree with each other, skipping
+/// transformed `glyf` tables which are in `checked`
+fn verify_woff2_font(font: &[usize], tables: &[Table], data: &[u8],
+        checked: &mut HashSet<usize>) -> io::Result<()> {
+    let mut tags = HashSet::new();
+    for &index in font {
+        if !tags.insert(tables[index].tag) {
+            return Err(corrupt(&format!("WOFF2 font has more than one '{}' table",
+                name(&tables[index].tag))));
+        }
+    }
+    let find = |tag: &[u8; 4]| font.iter().copied().find(|&x| &tables[x].tag == tag);
+    let contents = |index: usize| {
+        let table = &tables[index];
+        &data[table.offset..table.offset + table.length as usize]
+    };
+
+    let head = contents(find(b"head").ok_or_else(|| corrupt("WOFF2 font has no 'head' table"))?);
+    if head.len() < HEAD_SIZE || be32(&head[12..]) != HEAD_MAGIC {
+        return Err(corrupt("WOFF2 table 'head' is invalid"));
+    }
+    let num_glyphs = find(b"maxp").map(contents).filter(|x| x.len() >= 6).map(|x| be16(&x[4..]));
+
+    let glyf = match (find(b"glyf"), find(b"loca")) {
+        (None, None) => None,
+        (Some(glyf), Some(loca)) if tables[glyf].transformed == tables[loca].transformed => {
+            if tables[glyf].transformed && checked.insert(glyf) {
+                let index_format = be16(&head[INDEX_FORMAT_OFFSET..]);
+                verify_glyf(contents(glyf), num_glyphs, index_format, &tables[loca])?;
+            }
+            Some(glyf)
+        },
+        (Some(_), Some(_)) => return Err(corrupt(
+            "WOFF2 tables 'glyf' and 'loca' must both be transformed or neither")),
+        _ => return Err(corrupt("WOFF2 font has only one of the 'glyf' and 'loca' tables")),
+    };
+
+    if let Some(hmtx) = find(b"hmtx").filter(|&x| tables[x].transformed) {
+        if !glyf.map_or(false, |x| tables[x].transformed) {
+            return Err(corrupt("WOFF2 table 'hmtx' is transformed without the 'glyf' table"));
+        }
+        let hhea = find(b"hhea").map(contents).filter(|x| x.len() >= 36)
+            .ok_or_else(|| corrupt("WOFF2 font with a transformed 'hmtx' has no valid 'hhea'"))?;
+        let num_glyphs = num_glyphs
+            .ok_or_else(|| corrupt("WOFF2 font with a transformed 'hmtx' has no valid 'maxp'"))?;
+        verify_hmtx(contents(hmtx), num_glyphs, be16(&hhea[34..]))?;
+    }
+    Ok(())
+}
+
+/// Check a WOFF2 file, decompressing the font data and checking how the tables fit together
+fn verify_woff2(data: &[u8]) -> io::Result<()> {
+    let header = Header::read(data, "WOFF2")?;
+    let mut directory = Fields::new(&data[WOFF2_HEADER_SIZE..], "WOFF2 table directory");
+    let mut tables = (0..header.num_tables).map(|_| read_table(&mut directory))
+        .collect::<io::Result<Vec<_>>>()?;
+    let fonts = if &header.flavor == COLLECTION_FLAVOR {
+        read_collection(&mut directory, tables.len())?
+    } else {
+        header.check_sfnt_size(tables.iter().map(|x| x.orig_length), "WOFF2")?;
+        vec![(0..tables.len()).collect()]
+    };
+
+    let data_start = WOFF2_HEADER_SIZE + directory.pos;
+    let compressed = block(data, data_start as u32, header.total_compressed_size,
+        "WOFF2 compressed font data")?;
+    let mut offset = 0;
+    for table in &mut tables {
+        table.offset = offset;
+        offset += table.length as usize;
+    }
+    let what = "WOFF2 compressed font data";
+    let mut decoder = BrotliDecoder::new(compressed);
+    let decompressed = decompress(&mut decoder, u32::try_from(offset).unwrap_or(u32::MAX), what)?;
+    if !decoder.into_inner().is_empty() {
+        return Err(corrupt("WOFF2 compressed font data has trailing garbage"));
+    }
+
+    let mut checked = HashSet::new();
+    for font in &fonts {
+        verify_woff2_font(font, &tables, &decompressed, &mut checked)?;
+    }
+    header.check_extra_blocks(data, data_start + compressed.len(), "WOFF2", BrotliDecoder::new)
+}
+
+/// Check the WOFF or WOFF2 font in `data`
+///
+/// WOFF tables are decompressed and checked against the checksums and lengths in the table
+/// directory. WOFF2 has neither, so its font data is decompressed and the transformed tables are
+/// walked as if reconstructing them, to check that they agree with the rest of the font.
+pub fn verify(data: &[u8]) -> io::Result<()> {
+    match data.get(..4) {
+        Some(signature) if signature == WOFF_SIGNATURE => verify_woff(data),
+        Some(signature) if signature == WOFF2_SIGNATURE => verify_woff2(data),
+        _ => Err(corrupt("Not a WOFF or WOFF2 file (unrecognized signature)")),
+    }
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `test_data/good/testfile.ttf` wrapped by `test_data/sources/make_test_woff.py`
+    const WOFF: &[u8] = include_bytes!("../../../test_data/good/testfile.woff");
+
+    /// The same font as WOFF2, with the `glyf` and `loca` tables transformed
+    const WOFF2: &[u8] = include_bytes!("../../../test_data/good/testfile.woff2");
+
+    /// Wrap `data` in a Brotli stream made of a single uncompressed meta-block
+    fn stored_brotli(data: &[u8]) -> Vec<u8> {
+        let header = ((data.len() as u32 - 1) << 4) | (1 << 20);
+        let mut stream = header.to_le_bytes()[..3].to_vec();
+        stream.extend_from_slice(data);
+        stream.push(0x03);
+        stream
+    }
+
+    /// Decompress the font data in `WOFF2`, let `edit` change it, and reassemble the file with
+    /// the result stored uncompressed
+    fn edit_woff2(edit: impl FnOnce(&mut Vec<u8>, &[Table])) -> Vec<u8> {
+        let header = Header::read(WOFF2, "WOFF2").expect("valid header");
+        let mut directory = Fields::new(&WOFF2[WOFF2_HEADER_SIZE..], "directory");
+        let mut tables = (0..header.num_tables).map(|_| read_table(&mut directory))
+            .collect::<io::Result<Vec<_>>>().expect("valid directory");
+        let mut offset = 0;
+        for table in &mut tables {
+            table.offset = offset;
+            offset += table.length as usize;
+        }
+
+        let data_start = WOFF2_HEADER_SIZE + directory.pos;
+        let compressed = &WOFF2[data_start..data_start + header.total_compressed_size as usize];
+        let mut decompressed = Vec::new();
+        BrotliDecoder::new(compressed).read_to_end(&mut decompressed).expect("valid stream");
+        edit(&mut decompressed, &tables);
+
+        let stored = stored_brotli(&decompressed);
+        let (meta_offset, meta_len, _) = header.metadata;
+        let mut data = WOFF2[..data_start].to_vec();
+        data.extend_from_slice(&stored);
+        data.resize(data_start + padded(stored.len() as u32) as usize, 0);
+        let new_meta_offset = data.len() as u32;
+        data.extend_from_slice(block(WOFF2, meta_offset, meta_len, "metadata").expect("valid"));
+
+        let data_len = data.len() as u32;
+        data[8..12].copy_from_slice(&data_len.to_be_bytes());
+        data[20..24].copy_from_slice(&(stored.len() as u32).to_be_bytes());
+        data[28..32].copy_from_slice(&new_meta_offset.to_be_bytes());
+        data
+    }
+
+    /// Find the table tagged `tag` in `tables`
+    fn find<'a>(tables: &'a [Table], tag: &[u8]) -> &'a Table {
+        tables.iter().find(|x| x.tag == tag).expect("table present")
+    }
+
+    #[test]
+    fn test_good_fonts() {
+        assert_eq!(verify(WOFF).map_err(|err| err.to_string()), Ok(()));
+        assert_eq!(verify(WOFF2).map_err(|err| err.to_string()), Ok(()));
+        assert_eq!(verify(&edit_woff2(|_, _| ())).map_err(|err| err.to_string()), Ok(()));
+    }
+
+    #[test]
+    fn test_bad_fonts() {
+        let bad_woff = include_bytes!("../../../test_data/bad/testfile.woff");
+        let err = verify(bad_woff).expect_err("flipped bit in compressed glyf");
+        assert!(err.to_string().starts_with("WOFF table 'glyf' failed to decompress"), "{}", err);
+
+        let bad_woff2 = include_bytes!("../../../test_data/bad/testfile.woff2");
+        let err = verify(bad_woff2).expect_err("flipped bit in the Brotli stream");
+        assert!(err.to_string().starts_with("WOFF2 compressed font data failed"), "{}", err);
+
+        let mut bad_checksum = WOFF.to_vec();
+        bad_checksum[WOFF_HEADER_SIZE + 16] ^= 1;
+        let err = verify(&bad_checksum).expect_err("flipped bit in origChecksum");
+        assert!(err.to_string().ends_with("has a bad checksum"), "{}", err);
+
+        // Damage inside the WOFF2 font data, which Brotli has no checksum to catch
+        let extra_glyph = edit_woff2(|data, tables| data[find(tables, b"glyf").offset + 5] += 1);
+        let err = verify(&extra_glyph).expect_err("wrong numGlyphs in transformed glyf");
+        assert!(err.to_string().contains("disagree on glyph count"), "{}", err);
+        let no_bbox = edit_woff2(|data, tables| {
+            let glyf = find(tables, b"glyf").offset;
+            let bitmap = glyf + 36 + (0..5).map(|x| be32(&data[glyf + 8 + x * 4..]) as usize)
+                .sum::<usize>();
+            data[bitmap] &= 0x7F;
+        });
+        let err = verify(&no_bbox).expect_err("unused bounding box");
+        assert!(err.to_string().contains("unused data"), "{}", err);
+        let bad_head = edit_woff2(|data, tables| data[find(tables, b"head").offset + 12] ^= 1);
+        let err = verify(&bad_head).expect_err("flipped bit in head magic");
+        assert_eq!(err.to_string(), "WOFF2 table 'head' is invalid");
+        let too_long = edit_woff2(|data, _| data.push(0));
+        let err = verify(&too_long).expect_err("extra byte of font data");
+        assert!(err.to_string().contains("decompresses to"), "{}", err);
+
+        let mut bad_length = WOFF.to_vec();
+        bad_length[11] ^= 1;
+        let mut bad_reserved = WOFF2.to_vec();
+        bad_reserved[15] = 1;
+        let mut bad_sfnt_size = WOFF.to_vec();
+        bad_sfnt_size[19] ^= 4;
+        let mut bad_woff2_sfnt_size = WOFF2.to_vec();
+        bad_woff2_sfnt_size[19] ^= 4;
+        let mut no_tables = WOFF2.to_vec();
+        no_tables[12..14].copy_from_slice(&[0, 0]);
+        let mut bad_metadata = WOFF.to_vec();
+        let last = bad_metadata.len() - 1;
+        bad_metadata[last] ^= 1;
+
+        for (name, data) in &[
+            ("empty", &b""[..]),
+            ("bad signature", &b"wOF3"[..]),
+            ("truncated WOFF header", &WOFF[..20]),
+            ("truncated WOFF2 header", &WOFF2[..20]),
+            ("truncated WOFF", &WOFF[..WOFF.len() - 1]),
+            ("truncated WOFF2", &WOFF2[..WOFF2.len() - 1]),
+            ("bad length", &bad_length),
+            ("bad reserved field", &bad_reserved),
+            ("bad WOFF totalSfntSize", &bad_sfnt_size),
+            ("bad WOFF2 totalSfntSize", &bad_woff2_sfnt_size),
+            ("no tables", &no_tables),
+            ("bad metadata", &bad_metadata),
+        ] {
+            let err = verify(data).expect_err(name);
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}: {}", name, err);
+        }
+
+        // Transforms newer than this checker are reported as such rather than as corruption
+        let mut unknown_transform = WOFF2.to_vec();
+        unknown_transform[WOFF2_HEADER_SIZE] |= 0x40;
+        let err = verify(&unknown_transform).expect_err("unknown transform version");
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported, "{}", err);
+    }
+}
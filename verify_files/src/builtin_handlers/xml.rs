@@ -0,0 +1,833 @@
+//! A streaming XML 1.0 well-formedness checker which doubles as a minimal pull parser
+//!
+//! (Documents are decoded one character at a time, so they're never held in memory whole, and
+//! handlers for XML-based container formats can look at the elements as they go by. Entity
+//! references are checked against the internal DTD subset but never expanded, so "billion laughs"
+//! documents cost nothing. Validity against the DTD, and namespaces, aren't checked.)
+
+// Standard library imports
+use std::collections::HashSet;
+use std::io::{self, BufRead, BufReader, Read};
+use std::mem;
+
+// Local Imports
+use super::{corrupt, unsupported};
+
+/// The most text which will be collected before it's handed over as an [`Event::Text`]
+const MAX_TEXT_CHUNK: usize = 64 * 1024;
+
+/// The entities which are available without being declared
+const PREDEFINED_ENTITIES: [(&str, char); 5] =
+    [("amp", '&'), ("apos", '\''), ("gt", '>'), ("lt", '<'), ("quot", '"')];
+
+/// Something the parser found in the document
+#[derive(Debug, PartialEq, Eq)]
+pub enum Event {
+    /// A start tag (or empty-element tag), with its name and its attributes in document order
+    Start(String, Vec<(String, String)>),
+    /// An end tag (also produced right after the [`Start`](Self::Start) of an empty-element tag)
+    End(String),
+    /// Character data, with references expanded and line endings normalized
+    ///
+    /// (A long run of text may be split over more than one of these.)
+    Text(String),
+}
+
+/// The encodings which can be decoded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Encoding {
+    /// UTF-8 (the default, and also used for documents declared as US-ASCII)
+    Utf8,
+    /// UTF-16, with the flag set for big-endian
+    Utf16(bool),
+    /// ISO-8859-1
+    Latin1,
+}
+
+/// Turns the raw bytes of a document into characters
+struct Decoder<R> {
+    /// The document
+    input: BufReader<R>,
+    /// The encoding, once it's been guessed from the first few bytes
+    encoding: Option<Encoding>,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Read one byte
+    fn byte(&mut self) -> io::Result<Option<u8>> {
+        let byte = self.input.fill_buf()?.first().copied();
+        if byte.is_some() {
+            self.input.consume(1);
+        }
+        Ok(byte)
+    }
+
+    /// Read one UTF-16 code unit, given its first byte
+    fn unit(&mut self, first: u8, big_endian: bool) -> io::Result<u16> {
+        let second = self.byte()?
+            .ok_or_else(|| corrupt("Document ends partway through a character"))?;
+        let bytes = [first, second];
+        Ok(if big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+    }
+
+    /// Guess the encoding from the byte order mark or, failing that, the zero bytes in a
+    /// UTF-16 `<?`
+    fn detect(&mut self) -> io::Result<Encoding> {
+        let (encoding, bom_len) = match self.input.fill_buf()? {
+            [0xEF, 0xBB, 0xBF, ..] => (Encoding::Utf8, 3),
+            [0xFE, 0xFF, ..] => (Encoding::Utf16(true), 2),
+            [0xFF, 0xFE, ..] => (Encoding::Utf16(false), 2),
+            [0, b'<', 0, b'?', ..] => (Encoding::Utf16(true), 0),
+            [b'<', 0, b'?', 0, ..] => (Encoding::Utf16(false), 0),
+            _ => (Encoding::Utf8, 0),
+        };
+        self.input.consume(bom_len);
+        Ok(encoding)
+    }
+
+    /// Read one character
+    fn next_char(&mut self) -> io::Result<Option<char>> {
+        let encoding = match self.encoding {
+            Some(encoding) => encoding,
+            None => {
+                let encoding = self.detect()?;
+                *self.encoding.insert(encoding)
+            },
+        };
+        let first = match self.byte()? {
+            Some(byte) => byte,
+            None => return Ok(None),
+        };
+
+        match encoding {
+            Encoding::Latin1 => Ok(Some(char::from(first))),
+            Encoding::Utf8 => {
+                let len = match first {
+                    0..=0x7F => return Ok(Some(char::from(first))),
+                    0xC0..=0xDF => 2,
+                    0xE0..=0xEF => 3,
+                    0xF0..=0xF7 => 4,
+                    _ => return Err(corrupt("Invalid UTF-8")),
+                };
+                let mut buf = [first, 0, 0, 0];
+                for byte in &mut buf[1..len] {
+                    *byte = self.byte()?
+                        .ok_or_else(|| corrupt("Document ends partway through a character"))?;
+                }
+                std::str::from_utf8(&buf[..len]).ok().and_then(|x| x.chars().next())
+                    .map(Some).ok_or_else(|| corrupt("Invalid UTF-8"))
+            },
+            Encoding::Utf16(big_endian) => {
+                let mut units = vec![self.unit(first, big_endian)?];
+                if (0xD800..0xDC00).contains(&units[0]) {
+                    let second = self.byte()?
+                        .ok_or_else(|| corrupt("Document ends partway through a character"))?;
+                    units.push(self.unit(second, big_endian)?);
+                }
+                match char::decode_utf16(units).next() {
+                    Some(Ok(ch)) => Ok(Some(ch)),
+                    _ => Err(corrupt("Invalid UTF-16")),
+                }
+            },
+        }
+    }
+}
+
+/// Whether `ch` may appear in an XML 1.0 document at all
+fn is_xml_char(ch: char) -> bool {
+    matches!(ch, '\t' | '\n' | '\r' | '\u{20}'..='\u{D7FF}' | '\u{E000}'..='\u{FFFD}'
+                 | '\u{10000}'..='\u{10FFFF}')
+}
+
+/// Whether `ch` counts as whitespace between markup
+fn is_space(ch: char) -> bool {
+    matches!(ch, ' ' | '\t' | '\n' | '\r')
+}
+
+/// Whether `ch` may start a name
+fn is_name_start(ch: char) -> bool {
+    matches!(ch, ':' | 'A'..='Z' | '_' | 'a'..='z' | '\u{C0}'..='\u{D6}' | '\u{D8}'..='\u{F6}'
+                 | '\u{F8}'..='\u{2FF}' | '\u{370}'..='\u{37D}' | '\u{37F}'..='\u{1FFF}'
+                 | '\u{200C}'..='\u{200D}' | '\u{2070}'..='\u{218F}' | '\u{2C00}'..='\u{2FEF}'
+                 | '\u{3001}'..='\u{D7FF}' | '\u{F900}'..='\u{FDCF}' | '\u{FDF0}'..='\u{FFFD}'
+                 | '\u{10000}'..='\u{EFFFF}')
+}
+
+/// Whether `ch` may appear in a name after the first character
+fn is_name_char(ch: char) -> bool {
+    is_name_start(ch) || matches!(ch, '-' | '.' | '0'..='9' | '\u{B7}' | '\u{300}'..='\u{36F}'
+                                      | '\u{203F}'..='\u{2040}')
+}
+
+/// A pull parser which checks the well-formedness of everything it reads
+pub struct Reader<R> {
+    /// The document
+    decoder: Decoder<R>,
+    /// A character which has been decoded but not consumed yet
+    peeked: Option<char>,
+    /// The line of the next character (counting from 1)
+    line: u64,
+    /// How many characters of the current line have been consumed
+    column: u64,
+    /// The names of the elements which are currently open
+    open: Vec<String>,
+    /// Whether the root element has been started
+    seen_root: bool,
+    /// Whether a `DOCTYPE` declaration has been seen
+    seen_doctype: bool,
+    /// The general entities declared in the internal DTD subset
+    entities: HashSet<String>,
+    /// Whether there may be declarations which couldn't be read (ie. in an external DTD), making
+    /// references to undeclared entities a validity problem rather than a well-formedness one
+    external_decls: bool,
+    /// How many `]` characters in a row have just been seen in character data (to catch `]]>`)
+    brackets: usize,
+    /// Whether the parser is partway through a CDATA section
+    in_cdata: bool,
+    /// Character data collected so far
+    text: String,
+    /// An event to return before reading any further (for the end of an empty-element tag)
+    pending: Option<Event>,
+}
+
+impl<R: Read> Reader<R> {
+    /// Start parsing the document read from `input`
+    pub fn new(input: R) -> Self {
+        Self {
+            decoder: Decoder { input: BufReader::new(input), encoding: None },
+            peeked: None,
+            line: 1,
+            column: 0,
+            open: Vec::new(),
+            seen_root: false,
+            seen_doctype: false,
+            entities: HashSet::new(),
+            external_decls: false,
+            brackets: 0,
+            in_cdata: false,
+            text: String::new(),
+            pending: None,
+        }
+    }
+
+    /// The line the parser has reached (counting from 1), for reporting problems with the events
+    pub fn line(&self) -> u64 {
+        self.line
+    }
+
+    /// Build an error for a well-formedness problem at the current position
+    fn error(&self, message: &str) -> io::Error {
+        corrupt(&format!("XML error at line {}, column {}: {}", self.line, self.column, message))
+    }
+
+    /// Look at the next character without consuming it
+    fn peek(&mut self) -> io::Result<Option<char>> {
+        if self.peeked.is_none() {
+            self.peeked = self.decoder.next_char().map_err(|err| match err.kind() {
+                io::ErrorKind::InvalidData => self.error(&err.to_string()),
+                _ => err,
+            })?;
+        }
+        Ok(self.peeked)
+    }
+
+    /// Consume the next character, normalizing line endings to `\n`
+    fn bump(&mut self) -> io::Result<Option<char>> {
+        self.peek()?;
+        let ch = match self.peeked.take() {
+            Some(ch) => ch,
+            None => return Ok(None),
+        };
+        if !is_xml_char(ch) {
+            return Err(self.error(&format!("Character U+{:04X} isn't allowed", u32::from(ch))));
+        }
+        if ch == '\r' || ch == '\n' {
+            self.line += 1;
+            self.column = 0;
+        } else {
+            self.column += 1;
+        }
+        if ch == '\r' {
+            if self.peek()? == Some('\n') {
+                self.peeked = None;
+            }
+            return Ok(Some('\n'));
+        }
+        Ok(Some(ch))
+    }
+
+    /// Consume the next character, treating the end of the document as an error
+    fn require(&mut self, context: &str) -> io::Result<char> {
+        match self.bump()? {
+            Some(ch) => Ok(ch),
+            None => Err(self.error(&format!("Document ends inside {}", context))),
+        }
+    }
+
+    /// Consume the next character if it's `expected`
+    fn eat(&mut self, expected: char) -> io::Result<bool> {
+        let found = self.peek()? == Some(expected);
+        if found {
+            self.bump()?;
+        }
+        Ok(found)
+    }
+
+    /// Consume `expected`, which must come next
+    fn expect(&mut self, expected: &str, context: &str) -> io::Result<()> {
+        for ch in expected.chars() {
+            if self.bump()? != Some(ch) {
+                return Err(self.error(&format!("Expected {:?} in {}", expected, context)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Consume any whitespace, returning whether there was any
+    fn skip_space(&mut self) -> io::Result<bool> {
+        let mut found = false;
+        while self.peek()?.map_or(false, is_space) {
+            self.bump()?;
+            found = true;
+        }
+        Ok(found)
+    }
+
+    /// Consume a name
+    fn name(&mut self, context: &str) -> io::Result<String> {
+        let mut name = String::new();
+        match self.bump()? {
+            Some(ch) if is_name_start(ch) => name.push(ch),
+            _ => return Err(self.error(&format!("Expected a name in {}", context))),
+        }
+        while let Some(ch) = self.peek()?.filter(|&x| is_name_char(x)) {
+            self.bump()?;
+            name.push(ch);
+        }
+        Ok(name)
+    }
+
+    /// Consume a quoted literal which can't contain references (eg. in the XML declaration)
+    fn literal(&mut self, context: &str) -> io::Result<String> {
+        let quote = self.require(context)?;
+        if quote != '"' && quote != '\'' {
+            return Err(self.error(&format!("Expected a quoted value in {}", context)));
+        }
+        let mut value = String::new();
+        loop {
+            match self.require(context)? {
+                ch if ch == quote => return Ok(value),
+                ch => value.push(ch),
+            }
+        }
+    }
+
+    /// Consume a reference (after its `&`), returning the character it stands for unless it's a
+    /// reference to a declared entity
+    fn reference(&mut self) -> io::Result<Option<char>> {
+        if self.eat('#')? {
+            let radix = if self.eat('x')? { 16 } else { 10 };
+            let mut digits = String::new();
+            loop {
+                match self.require("a character reference")? {
+                    ';' => break,
+                    ch if ch.is_digit(radix) && digits.len() < 8 => digits.push(ch),
+                    _ => return Err(self.error("Malformed character reference")),
+                }
+            }
+            return match u32::from_str_radix(&digits, radix).ok().and_then(char::from_u32) {
+                Some(ch) if is_xml_char(ch) => Ok(Some(ch)),
+                _ => Err(self.error(&format!("Character reference &#{}; isn't allowed",
+                    if radix == 16 { format!("x{}", digits) } else { digits }))),
+            };
+        }
+
+        let name = self.name("an entity reference")?;
+        self.expect(";", "an entity reference")?;
+        if let Some(&(_, ch)) = PREDEFINED_ENTITIES.iter().find(|(x, _)| *x == name) {
+            Ok(Some(ch))
+        } else if self.external_decls || self.entities.contains(&name) {
+            Ok(None)
+        } else {
+            Err(self.error(&format!("Reference to undeclared entity &{};", name)))
+        }
+    }
+
+    /// Consume a quoted attribute value, expanding references and normalizing whitespace
+    fn attribute_value(&mut self) -> io::Result<String> {
+        let quote = self.require("an attribute value")?;
+        if quote != '"' && quote != '\'' {
+            return Err(self.error("Attribute values must be quoted"));
+        }
+        let mut value = String::new();
+        loop {
+            match self.require("an attribute value")? {
+                ch if ch == quote => return Ok(value),
+                '<' => return Err(self.error("'<' isn't allowed in attribute values")),
+                '&' => value.extend(self.reference()?),
+                ch if is_space(ch) => value.push(' '),
+                ch => value.push(ch),
+            }
+        }
+    }
+
+    /// Consume a start tag or empty-element tag (after its `<`)
+    fn start_tag(&mut self) -> io::Result<Event> {
+        if self.seen_root && self.open.is_empty() {
+            return Err(self.error("More than one root element"));
+        }
+        let name = self.name("a start tag")?;
+        let mut attributes: Vec<(String, String)> = Vec::new();
+        let empty = loop {
+            let spaced = self.skip_space()?;
+            if self.eat('>')? {
+                break false;
+            } else if self.eat('/')? {
+                self.expect(">", "an empty-element tag")?;
+                break true;
+            } else if !spaced {
+                return Err(self.error(&format!("Malformed start tag <{}>", name)));
+            }
+            let attribute = self.name("a start tag")?;
+            self.skip_space()?;
+            self.expect("=", "an attribute")?;
+            self.skip_space()?;
+            let value = self.attribute_value()?;
+            if attributes.iter().any(|(x, _)| *x == attribute) {
+                return Err(self.error(&format!("Duplicate attribute {} on <{}>", attribute, name)));
+            }
+            attributes.push((attribute, value));
+        };
+
+        self.seen_root = true;
+        if empty {
+            self.pending = Some(Event::End(name.clone()));
+        } else {
+            self.open.push(name.clone());
+        }
+        Ok(Event::Start(name, attributes))
+    }
+
+    /// Consume an end tag (after its `</`)
+    fn end_tag(&mut self) -> io::Result<Event> {
+        let name = self.name("an end tag")?;
+        self.skip_space()?;
+        self.expect(">", "an end tag")?;
+        match self.open.pop() {
+            Some(open) if open == name => Ok(Event::End(name)),
+            Some(open) => Err(self.error(&format!(
+                "End tag </{}> doesn't match start tag <{}>", name, open))),
+            None => Err(self.error(&format!("End tag </{}> has no start tag", name))),
+        }
+    }
+
+
+    /// Consume the XML declaration (after its `<?xml`)
+    fn declaration(&mut self) -> io::Result<()> {
+        // Each field may only appear once, in this order, and `version` is mandatory
+        let mut fields = ["version", "encoding", "standalone"].iter();
+        loop {
+            let spaced = self.skip_space()?;
+            if self.eat('?')? {
+                return self.expect(">", "the XML declaration");
+            } else if !spaced {
+                return Err(self.error("Malformed XML declaration"));
+            }
+            let name = self.name("the XML declaration")?;
+            let first = fields.len() == 3;
+            if !fields.any(|x| *x == name) || (first && name != "version") {
+                return Err(self.error(&format!("Unexpected {} in the XML declaration", name)));
+            }
+            self.skip_space()?;
+            self.expect("=", "the XML declaration")?;
+            self.skip_space()?;
+            let value = self.literal("the XML declaration")?;
+            match name.as_str() {
+                "version" => if !value.strip_prefix("1.")
+                        .map_or(false, |x| !x.is_empty() && x.chars().all(|c| c.is_ascii_digit())) {
+                    return Err(self.error(&format!("Unsupported XML version {:?}", value)));
+                },
+                "encoding" => self.set_encoding(&value)?,
+                _ if value == "yes" || value == "no" => {},
+                _ => return Err(self.error(&format!("Invalid standalone value {:?}", value))),
+            }
+        }
+    }
+
+    /// Switch to the encoding named in the XML declaration, if it can be decoded
+    fn set_encoding(&mut self, name: &str) -> io::Result<()> {
+        match name.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" | "us-ascii" | "ascii" | "utf-16" | "utf-16le" | "utf-16be" => Ok(()),
+            "iso-8859-1" | "iso_8859-1" | "latin1" | "l1" => {
+                // (A document which starts with a UTF-8 BOM is decoded as UTF-8 regardless)
+                if self.decoder.encoding == Some(Encoding::Utf8) {
+                    self.decoder.encoding = Some(Encoding::Latin1);
+                }
+                Ok(())
+            },
+            _ => Err(unsupported(&format!("XML documents encoded as {} aren't supported", name))),
+        }
+    }
+
+    /// Consume a processing instruction or the XML declaration (after its `<?`)
+    fn processing_instruction(&mut self, at_start: bool) -> io::Result<()> {
+        let target = self.name("a processing instruction")?;
+        if target.eq_ignore_ascii_case("xml") {
+            return if target == "xml" && at_start {
+                self.declaration()
+            } else {
+                Err(self.error("The XML declaration must come at the very start"))
+            };
+        }
+        if self.eat('?')? {
+            return self.expect(">", "a processing instruction");
+        } else if !self.skip_space()? {
+            return Err(self.error("Malformed processing instruction"));
+        }
+        loop {
+            if self.require("a processing instruction")? == '?' && self.eat('>')? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Consume a comment (after its `<!-`)
+    fn comment(&mut self) -> io::Result<()> {
+        self.expect("-", "a comment")?;
+        loop {
+            if self.require("a comment")? == '-' && self.eat('-')? {
+                return if self.eat('>')? {
+                    Ok(())
+                } else {
+                    Err(self.error("\"--\" isn't allowed in comments"))
+                };
+            }
+        }
+    }
+
+    /// Collect the contents of a CDATA section as text, returning whether its end was reached
+    /// (rather than [`MAX_TEXT_CHUNK`])
+    fn cdata(&mut self) -> io::Result<bool> {
+        // `]` characters are held back until it's clear whether they're part of the `]]>`
+        while self.text.len() < MAX_TEXT_CHUNK {
+            match self.require("a CDATA section")? {
+                ']' => self.brackets += 1,
+                '>' if self.brackets >= 2 => {
+                    self.text.extend(std::iter::repeat(']').take(self.brackets - 2));
+                    self.brackets = 0;
+                    self.in_cdata = false;
+                    return Ok(true);
+                },
+                ch => {
+                    self.text.extend(std::iter::repeat(']').take(self.brackets));
+                    self.brackets = 0;
+                    self.text.push(ch);
+                },
+            }
+        }
+        Ok(false)
+    }
+
+    /// Consume everything up to the `>` which ends a markup declaration in the DTD, skipping over
+    /// quoted strings
+    fn skip_declaration(&mut self) -> io::Result<()> {
+        let mut quote = None;
+        loop {
+            let ch = self.require("the DTD")?;
+            match quote {
+                Some(open) if ch == open => quote = None,
+                Some(_) => {},
+                None if ch == '"' || ch == '\'' => quote = Some(ch),
+                None if ch == '>' => return Ok(()),
+                None => {},
+            }
+        }
+    }
+
+    /// Consume the internal DTD subset (after its `[`), recording which entities it declares
+    fn internal_subset(&mut self) -> io::Result<()> {
+        loop {
+            self.skip_space()?;
+            match self.require("the DTD")? {
+                ']' => return Ok(()),
+                '%' => {
+                    // Parameter entities may pull in declarations from elsewhere
+                    self.name("a parameter entity reference")?;
+                    self.expect(";", "a parameter entity reference")?;
+                    self.external_decls = true;
+                },
+                '<' if self.eat('?')? => self.processing_instruction(false)?,
+                '<' if self.eat('!')? => if self.eat('-')? {
+                    self.comment()?;
+                } else {
+                    match self.name("a markup declaration")?.as_str() {
+                        "ENTITY" => if self.skip_space()? && !self.eat('%')? {
+                            let name = self.name("an entity declaration")?;
+                            self.entities.insert(name);
+                        },
+                        "ATTLIST" | "ELEMENT" | "NOTATION" => {},
+                        _ => return Err(self.error("Malformed markup declaration in the DTD")),
+                    }
+                    self.skip_declaration()?;
+                },
+                _ => return Err(self.error("Unexpected content in the DTD")),
+            }
+        }
+    }
+
+    /// Consume a `DOCTYPE` declaration (after its `<!D`)
+    fn doctype(&mut self) -> io::Result<()> {
+        self.expect("OCTYPE", "a DOCTYPE declaration")?;
+        if self.seen_doctype || self.seen_root {
+            return Err(self.error("The DOCTYPE declaration must come before the root element"));
+        }
+        self.seen_doctype = true;
+        if !self.skip_space()? {
+            return Err(self.error("Malformed DOCTYPE declaration"));
+        }
+        self.name("a DOCTYPE declaration")?;
+
+        if self.skip_space()? && matches!(self.peek()?, Some('P' | 'S')) {
+            let literals = match self.name("a DOCTYPE declaration")?.as_str() {
+                "PUBLIC" => 2,
+                "SYSTEM" => 1,
+                _ => return Err(self.error("Malformed DOCTYPE declaration")),
+            };
+            for _ in 0..literals {
+                if !self.skip_space()? {
+                    return Err(self.error("Malformed DOCTYPE declaration"));
+                }
+                self.literal("a DOCTYPE declaration")?;
+            }
+            self.external_decls = true;
+            self.skip_space()?;
+        }
+        if self.eat('[')? {
+            self.internal_subset()?;
+            self.skip_space()?;
+        }
+        self.expect(">", "a DOCTYPE declaration")
+    }
+
+    /// Parse up to the next start tag, end tag, or run of text, returning `None` once the
+    /// document has ended properly
+    pub fn next_event(&mut self) -> io::Result<Option<Event>> {
+        if let Some(event) = self.pending.take() {
+            return Ok(Some(event));
+        }
+        loop {
+            if (self.in_cdata && !self.cdata()?) || self.text.len() >= MAX_TEXT_CHUNK {
+                return Ok(Some(Event::Text(mem::take(&mut self.text))));
+            }
+
+            let at_start = self.line == 1 && self.column == 0;
+            let ch = match self.peek()? {
+                Some(ch) => ch,
+                None => return match self.open.last() {
+                    Some(name) => Err(self.error(&format!("Document ends inside <{}>", name))),
+                    None if !self.seen_root => Err(self.error("Document has no root element")),
+                    None => Ok(None),
+                },
+            };
+
+            if ch == '<' {
+                if !self.text.is_empty() {
+                    return Ok(Some(Event::Text(mem::take(&mut self.text))));
+                }
+                self.bump()?;
+                self.brackets = 0;
+                if self.eat('/')? {
+                    return self.end_tag().map(Some);
+                } else if self.eat('?')? {
+                    self.processing_instruction(at_start)?;
+                } else if !self.eat('!')? {
+                    return self.start_tag().map(Some);
+                } else if self.eat('-')? {
+                    self.comment()?;
+                } else if self.eat('D')? {
+                    self.doctype()?;
+                } else if self.eat('[')? {
+                    self.expect("CDATA[", "a CDATA section")?;
+                    if self.open.is_empty() {
+                        return Err(self.error("CDATA sections must be inside the root element"));
+                    }
+                    self.in_cdata = true;
+                } else {
+                    return Err(self.error("Malformed markup"));
+                }
+            } else if self.open.is_empty() {
+                self.bump()?;
+                if !is_space(ch) {
+                    return Err(self.error(if self.seen_root {
+                        "Content after the root element"
+                    } else {
+                        "Content before the root element"
+                    }));
+                }
+            } else if ch == '&' {
+                self.bump()?;
+                self.brackets = 0;
+                let expanded = self.reference()?;
+                self.text.extend(expanded);
+            } else {
+                let ch = self.require("character data")?;
+                if ch == '>' && self.brackets >= 2 {
+                    return Err(self.error("\"]]>\" isn't allowed in character data"));
+                }
+                self.brackets = if ch == ']' { self.brackets + 1 } else { 0 };
+                self.text.push(ch);
+            }
+        }
+    }
+}
+
+/// Strip the namespace prefix (if any) from an element or attribute name
+pub fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+/// Check that the document read from `input` is well-formed XML
+pub fn verify(input: impl Read) -> io::Result<()> {
+    let mut reader = Reader::new(input);
+    while reader.next_event()?.is_some() {}
+    Ok(())
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Verify `input`, returning the error kind on failure
+    fn verify_bytes(input: &[u8]) -> Result<(), io::ErrorKind> {
+        verify(input).map_err(|err| err.kind())
+    }
+
+    /// Parse `input`, returning all of its events
+    fn events(input: &[u8]) -> Vec<Event> {
+        let mut reader = Reader::new(input);
+        let mut events = Vec::new();
+        while let Some(event) = reader.next_event().expect("well-formed test input") {
+            events.push(event);
+        }
+        events
+    }
+
+    #[test]
+    fn test_well_formed() {
+        for input in &[
+            &b"<a/>"[..],
+            b"<?xml version=\"1.0\"?>\n<a>text</a>\n",
+            b"\xEF\xBB\xBF<?xml version='1.1' encoding='UTF-8' standalone='yes' ?><a/>",
+            b"<!-- comment --><?pi data?><a b='1' c=\"&lt;&#x41;&#66;\"><b/><![CDATA[<&]]></a>",
+            b"<!DOCTYPE a [<!ENTITY e 'x'> <!ATTLIST a b CDATA '>'> <!-- c -->]><a>&e;</a>",
+            b"<!DOCTYPE html PUBLIC \"-//W3C//DTD XHTML 1.1//EN\" \"xhtml11.dtd\">
+              <html>&nbsp;</html>",
+            b"<a>]]</a>",
+            b"<\xC3\xA9l\xC3\xA9ment attr=\"\xE2\x9C\x93\"/>",
+        ] {
+            assert_eq!(verify_bytes(input), Ok(()), "{}", String::from_utf8_lossy(input));
+        }
+    }
+
+    #[test]
+    fn test_malformed() {
+        for input in &[
+            &b""[..],
+            b"text",
+            b"<a>",
+            b"<a></b>",
+            b"</a>",
+            b"<a/><b/>",
+            b"<a/>text",
+            b"<a b=1/>",
+            b"<a b='1' b='2'/>",
+            b"<a b='<'/>",
+            b"<ab='1'/>",
+            b"<a>&bogus;</a>",
+            b"<a>&#0;</a>",
+            b"<a>&#xD800;</a>",
+            b"<a>&amp</a>",
+            b"<a>]]></a>",
+            b"<a>\x00</a>",
+            b"<a>\xFF</a>",
+            b"<a>\xC3</a>",
+            b"<!-- a -- b --><a/>",
+            b"<a><!-- a ---></a>",
+            b" <?xml version='1.0'?><a/>",
+            b"<?xml encoding='UTF-8'?><a/>",
+            b"<?xml version='2.0'?><a/>",
+            b"<a/><!DOCTYPE a>",
+            b"<![CDATA[x]]><a/>",
+            b"<a><![CDATA[x</a>",
+            b"<a><!bogus></a>",
+            b"<1/>",
+        ] {
+            assert_eq!(verify_bytes(input), Err(io::ErrorKind::InvalidData), "{}",
+                       String::from_utf8_lossy(input));
+        }
+    }
+
+    #[test]
+    fn test_events() {
+        fn owned(name: &str, value: &str) -> (String, String) {
+            (name.to_owned(), value.to_owned())
+        }
+        let input = b"<?xml version='1.0'?>\r\n<a x='1 &amp;\t2'>b\r\nc<d/>&#x263A;</a>";
+        assert_eq!(events(input), vec![
+            Event::Start("a".to_owned(), vec![owned("x", "1 & 2")]),
+            Event::Text("b\nc".to_owned()),
+            Event::Start("d".to_owned(), vec![]),
+            Event::End("d".to_owned()),
+            Event::Text("\u{263A}".to_owned()),
+            Event::End("a".to_owned()),
+        ]);
+
+        // Long runs of text are split up, but nothing goes missing
+        let mut long = b"<a><![CDATA[".to_vec();
+        long.extend(std::iter::repeat(b']').take(MAX_TEXT_CHUNK * 2));
+        long.extend(b"]]>x</a>");
+        let text: String = events(&long).into_iter().filter_map(|x| match x {
+            Event::Text(text) => Some(text),
+            _ => None,
+        }).collect();
+        assert_eq!(text.len(), MAX_TEXT_CHUNK * 2 + 1);
+    }
+
+    #[test]
+    fn test_encodings() {
+        let utf16: Vec<u8> = "\u{FEFF}<a>\u{1F600}</a>".encode_utf16()
+            .flat_map(u16::to_le_bytes).collect();
+        assert_eq!(verify_bytes(&utf16), Ok(()));
+        let utf16: Vec<u8> = "<?xml version='1.0' encoding='UTF-16'?><a/>".encode_utf16()
+            .flat_map(u16::to_be_bytes).collect();
+        assert_eq!(verify_bytes(&utf16), Ok(()));
+        assert_eq!(verify_bytes(&utf16[..utf16.len() - 1]), Err(io::ErrorKind::InvalidData));
+
+        assert_eq!(events(b"<?xml version='1.0' encoding='ISO-8859-1'?><a>\xE9</a>")[1],
+                   Event::Text("\u{E9}".to_owned()));
+        assert_eq!(verify_bytes(b"<?xml version='1.0' encoding='Shift_JIS'?><a/>"),
+                   Err(io::ErrorKind::Unsupported));
+    }
+
+    #[test]
+    fn test_xml_fixtures() {
+        for (path, data) in &[
+            ("good/testfile.xml", &include_bytes!("../../../test_data/good/testfile.xml")[..]),
+            ("good/testfile.mml", include_bytes!("../../../test_data/good/testfile.mml")),
+            ("good/testfile.rss", include_bytes!("../../../test_data/good/testfile.rss")),
+            ("good/testfile.svg", include_bytes!("../../../test_data/good/testfile.svg")),
+        ] {
+            assert_eq!(verify_bytes(data), Ok(()), "{}", path);
+        }
+        for (path, data) in &[
+            ("bad/testfile.xml", &include_bytes!("../../../test_data/bad/testfile.xml")[..]),
+            ("bad/testfile.mml", include_bytes!("../../../test_data/bad/testfile.mml")),
+            ("bad/testfile.rss", include_bytes!("../../../test_data/bad/testfile.rss")),
+            ("bad/testfile.svg", include_bytes!("../../../test_data/bad/testfile.svg")),
+        ] {
+            assert_eq!(verify_bytes(data), Err(io::ErrorKind::InvalidData), "{}", path);
+        }
+    }
+}
@@ -0,0 +1,513 @@
+//! A reader for the `.xz` container which verifies every check, index, and padding field
+//!
+//! (Only the LZMA2 filter is supported, since that's what virtually every `.xz` file uses.
+//! Anything else is reported as `ErrorKind::Unsupported` so an external tool can take over.)
+
+// Standard library imports
+use std::io::{self, BufRead, Read};
+
+// 3rd-party crate imports
+use crc32fast::Hasher as Crc32;
+
+// Local Imports
+use super::checksums::{Crc64, Sha256};
+use super::lzma::{lzma2_dict_size, Lzma2Decoder};
+use super::{corrupt, eof_is_corrupt, unsupported};
+
+/// The magic number at the start of every stream header
+const HEADER_MAGIC: [u8; 6] = [0xFD, b'7', b'z', b'X', b'Z', 0x00];
+
+/// The magic number at the end of every stream footer
+const FOOTER_MAGIC: [u8; 2] = [b'Y', b'Z'];
+
+/// The filter ID for LZMA2
+const FILTER_LZMA2: u64 = 0x21;
+
+/// A reader which counts the bytes consumed from the reader it wraps
+struct Counted<R> {
+    /// The reader being wrapped
+    inner: R,
+    /// How many bytes have been consumed so far
+    count: u64,
+}
+
+impl<R: BufRead> Counted<R> {
+    /// Read a single byte, also feeding it into `crc`
+    fn byte(&mut self, crc: &mut Crc32) -> io::Result<u8> {
+        let mut byte = [0];
+        self.read_exact(&mut byte)?;
+        crc.update(&byte);
+        Ok(byte[0])
+    }
+
+    /// Read a multibyte integer, also feeding it into `crc`
+    fn varint(&mut self, crc: &mut Crc32) -> io::Result<u64> {
+        let mut value = 0;
+        for idx in 0..9 {
+            let byte = self.byte(crc)?;
+            value |= u64::from(byte & 0x7F) << (idx * 7);
+            if byte & 0x80 == 0 {
+                if byte == 0 && idx > 0 {
+                    return Err(corrupt("Non-minimal integer encoding in xz headers"));
+                }
+                return Ok(value);
+            }
+        }
+        Err(corrupt("Overlong integer in xz headers"))
+    }
+
+    /// Read a little-endian CRC32 and compare it against `crc`
+    fn crc32(&mut self, crc: Crc32, what: &str) -> io::Result<()> {
+        let mut stored = [0; 4];
+        self.read_exact(&mut stored)?;
+        if u32::from_le_bytes(stored) == crc.finalize() {
+            Ok(())
+        } else {
+            Err(corrupt(&format!("CRC32 mismatch in xz {}", what)))
+        }
+    }
+
+    /// Consume null bytes until the count is a multiple of four
+    fn padding(&mut self, what: &str) -> io::Result<()> {
+        while self.count % 4 != 0 {
+            let mut byte = [0];
+            self.read_exact(&mut byte)?;
+            if byte[0] != 0 {
+                return Err(corrupt(&format!("Non-null {} padding in xz stream", what)));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.count += len as u64;
+        Ok(len)
+    }
+}
+
+/// The integrity check a stream applies to each block
+enum Check {
+    /// No check
+    None,
+    /// CRC32
+    Crc32(Crc32),
+    /// CRC64
+    Crc64(Crc64),
+    /// SHA-256
+    Sha256(Box<Sha256>),
+}
+
+impl Check {
+    /// Start a new check of the given type
+    fn new(check_id: u8) -> io::Result<Self> {
+        match check_id {
+            0x00 => Ok(Self::None),
+            0x01 => Ok(Self::Crc32(Crc32::new())),
+            0x04 => Ok(Self::Crc64(Crc64::new())),
+            0x0A => Ok(Self::Sha256(Box::new(Sha256::new()))),
+            0x02..=0x0F => Err(unsupported(&format!("Unsupported xz check type {}", check_id))),
+            _ => Err(corrupt("Invalid xz stream flags")),
+        }
+    }
+
+    /// Add `data` to the check
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::None => {},
+            Self::Crc32(crc) => crc.update(data),
+            Self::Crc64(crc) => crc.update(data),
+            Self::Sha256(sha) => sha.update(data),
+        }
+    }
+
+    /// The check value, in the byte order it's stored in
+    fn finish(self) -> Vec<u8> {
+        match self {
+            Self::None => Vec::new(),
+            Self::Crc32(crc) => crc.finalize().to_le_bytes().to_vec(),
+            Self::Crc64(crc) => crc.finish().to_le_bytes().to_vec(),
+            Self::Sha256(sha) => sha.finish().to_vec(),
+        }
+    }
+}
+
+/// A block which is currently being decompressed
+struct Block {
+    /// The LZMA2 decoder for the block's data
+    decoder: Lzma2Decoder,
+    /// The check of the block's uncompressed data
+    check: Check,
+    /// The size of the block header
+    header_size: u64,
+    /// Where the compressed data starts in the input
+    start: u64,
+    /// The uncompressed size so far
+    uncompressed: u64,
+    /// The compressed size declared in the block header, if any
+    expected_compressed: Option<u64>,
+    /// The uncompressed size declared in the block header, if any
+    expected_uncompressed: Option<u64>,
+}
+
+/// Where the reader is in the file's structure
+enum State {
+    /// Expecting a stream header (and, unless it's the first, not expecting EOF before it)
+    StreamHeader { first: bool },
+    /// Expecting a block header or the index
+    Blocks,
+    /// Decompressing a block
+    Block(Box<Block>),
+    /// Expecting stream padding, another stream, or EOF
+    Padding,
+    /// Finished
+    Done,
+}
+
+/// A streaming decompressor for `.xz` files
+pub struct XzDecoder<R> {
+    /// The compressed data
+    input: Counted<R>,
+    /// Where the reader is in the file's structure
+    state: State,
+    /// The check ID from the current stream's flags
+    check_id: u8,
+    /// The current stream's flags, for comparison with its footer
+    flags: [u8; 2],
+    /// The unpadded and uncompressed size of each block so far in the current stream
+    records: Vec<(u64, u64)>,
+    /// Decompressed data which hasn't been read yet
+    buffer: Vec<u8>,
+    /// How much of `buffer` has been read
+    buffer_pos: usize,
+}
+
+impl<R: BufRead> XzDecoder<R> {
+    /// Prepare to decompress `input`
+    pub fn new(input: R) -> Self {
+        Self {
+            input: Counted { inner: input, count: 0 },
+            state: State::StreamHeader { first: true },
+            check_id: 0,
+            flags: [0; 2],
+            records: Vec::new(),
+            buffer: Vec::new(),
+            buffer_pos: 0,
+        }
+    }
+
+    /// Read and verify a stream header
+    fn stream_header(&mut self) -> io::Result<()> {
+        let mut header = [0; 12];
+        self.input.read_exact(&mut header)?;
+        if header[..6] != HEADER_MAGIC {
+            return Err(corrupt("Not an xz stream header"));
+        }
+        let mut crc = Crc32::new();
+        crc.update(&header[6..8]);
+        if u32::from_le_bytes([header[8], header[9], header[10], header[11]]) != crc.finalize() {
+            return Err(corrupt("CRC32 mismatch in xz stream header"));
+        }
+        if header[6] != 0 || header[7] & 0xF0 != 0 {
+            return Err(unsupported("Unsupported xz stream flags"));
+        }
+        Check::new(header[7])?;
+        self.flags = [header[6], header[7]];
+        self.check_id = header[7];
+        self.records.clear();
+        Ok(())
+    }
+
+    /// Read and verify a block header, given its already-read size byte
+    fn block_header(&mut self, size_byte: u8) -> io::Result<Block> {
+        let start = self.input.count - 1;
+        let header_size = (u64::from(size_byte) + 1) * 4;
+        let mut crc = Crc32::new();
+        crc.update(&[size_byte]);
+
+        let flags = self.input.byte(&mut crc)?;
+        if flags & 0x3C != 0 {
+            return Err(unsupported("Unsupported xz block flags"));
+        }
+        let expected_compressed =
+            if flags & 0x40 == 0 { None } else { Some(self.input.varint(&mut crc)?) };
+        let expected_uncompressed =
+            if flags & 0x80 == 0 { None } else { Some(self.input.varint(&mut crc)?) };
+
+        let filter_count = (flags & 0x03) + 1;
+        let mut dict_size = None;
+        for _ in 0..filter_count {
+            let filter_id = self.input.varint(&mut crc)?;
+            let props_size = self.input.varint(&mut crc)?;
+            if filter_id != FILTER_LZMA2 {
+                return Err(unsupported(&format!("Unsupported xz filter 0x{:02X}", filter_id)));
+            }
+            if props_size != 1 || filter_count != 1 {
+                return Err(corrupt("Invalid LZMA2 filter properties in xz block header"));
+            }
+            dict_size = Some(lzma2_dict_size(self.input.byte(&mut crc)?)?);
+        }
+
+        if self.input.count - start > header_size - 4 {
+            return Err(corrupt("xz block header is larger than its declared size"));
+        }
+        while self.input.count - start < header_size - 4 {
+            if self.input.byte(&mut crc)? != 0 {
+                return Err(corrupt("Non-null xz block header padding"));
+            }
+        }
+        self.input.crc32(crc, "block header")?;
+
+        Ok(Block {
+            decoder: Lzma2Decoder::new(dict_size.unwrap_or(0)),
+            check: Check::new(self.check_id)?,
+            header_size,
+            start: self.input.count,
+            uncompressed: 0,
+            expected_compressed,
+            expected_uncompressed,
+        })
+    }
+
+    /// Verify the end of a block once its last chunk has been decoded
+    fn finish_block(&mut self, block: Block) -> io::Result<()> {
+        let compressed = self.input.count - block.start;
+        if block.expected_compressed.map_or(false, |x| x != compressed)
+            || block.expected_uncompressed.map_or(false, |x| x != block.uncompressed)
+        {
+            return Err(corrupt("xz block sizes don't match its header"));
+        }
+        self.input.padding("block")?;
+
+        let expected = block.check.finish();
+        let mut stored = vec![0; expected.len()];
+        self.input.read_exact(&mut stored)?;
+        if stored != expected {
+            return Err(corrupt("Check value mismatch in xz block"));
+        }
+
+        let unpadded = block.header_size + compressed + expected.len() as u64;
+        self.records.push((unpadded, block.uncompressed));
+        Ok(())
+    }
+
+    /// Read and verify the index and stream footer, given the already-read index indicator byte
+    fn index_and_footer(&mut self) -> io::Result<()> {
+        let start = self.input.count - 1;
+        let mut crc = Crc32::new();
+        crc.update(&[0]);
+
+        let count = self.input.varint(&mut crc)?;
+        if count != self.records.len() as u64 {
+            return Err(corrupt("xz index doesn't match the number of blocks"));
+        }
+        for idx in 0..self.records.len() {
+            let unpadded = self.input.varint(&mut crc)?;
+            let uncompressed = self.input.varint(&mut crc)?;
+            if self.records[idx] != (unpadded, uncompressed) {
+                return Err(corrupt("xz index doesn't match the block sizes"));
+            }
+        }
+        while self.input.count % 4 != 0 {
+            if self.input.byte(&mut crc)? != 0 {
+                return Err(corrupt("Non-null xz index padding"));
+            }
+        }
+        self.input.crc32(crc, "index")?;
+        let index_size = self.input.count - start;
+
+        let mut footer = [0; 12];
+        self.input.read_exact(&mut footer)?;
+        let mut crc = Crc32::new();
+        crc.update(&footer[4..10]);
+        if u32::from_le_bytes([footer[0], footer[1], footer[2], footer[3]]) != crc.finalize() {
+            return Err(corrupt("CRC32 mismatch in xz stream footer"));
+        }
+        let backward_size =
+            (u64::from(u32::from_le_bytes([footer[4], footer[5], footer[6], footer[7]])) + 1) * 4;
+        if backward_size != index_size
+            || footer[8..10] != self.flags
+            || footer[10..] != FOOTER_MAGIC
+        {
+            return Err(corrupt("xz stream footer doesn't match the rest of the stream"));
+        }
+        Ok(())
+    }
+
+    /// Decode more data into `buffer`, returning `false` at the end of the file
+    fn fill(&mut self) -> io::Result<bool> {
+        loop {
+            match std::mem::replace(&mut self.state, State::Done) {
+                State::StreamHeader { first } => {
+                    if first && self.input.inner.fill_buf()?.is_empty() {
+                        return Err(corrupt("Empty file"));
+                    }
+                    self.stream_header()?;
+                    self.state = State::Blocks;
+                },
+                State::Blocks => {
+                    let mut byte = [0];
+                    self.input.read_exact(&mut byte)?;
+                    if byte[0] == 0 {
+                        self.index_and_footer()?;
+                        self.state = State::Padding;
+                    } else {
+                        self.state = State::Block(Box::new(self.block_header(byte[0])?));
+                    }
+                },
+                State::Block(mut block) => {
+                    self.buffer.clear();
+                    self.buffer_pos = 0;
+                    if block.decoder.decode_chunk(&mut self.input, &mut self.buffer)? {
+                        block.check.update(&self.buffer);
+                        block.uncompressed += self.buffer.len() as u64;
+                        self.state = State::Block(block);
+                    } else {
+                        self.finish_block(*block)?;
+                        self.state = State::Blocks;
+                    }
+                    if !self.buffer.is_empty() {
+                        return Ok(true);
+                    }
+                },
+                State::Padding => {
+                    let next = self.input.inner.fill_buf()?;
+                    if next.is_empty() {
+                        return Ok(false);
+                    } else if next[0] == 0 {
+                        let mut padding = [0; 4];
+                        self.input.read_exact(&mut padding)?;
+                        if padding != [0; 4] {
+                            return Err(corrupt("Invalid xz stream padding"));
+                        }
+                        self.state = State::Padding;
+                    } else {
+                        self.state = State::StreamHeader { first: false };
+                    }
+                },
+                State::Done => return Ok(false),
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Read for XzDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.buffer_pos == self.buffer.len() {
+            let more = eof_is_corrupt(self.fill())?;
+            if !more {
+                return Ok(0);
+            }
+        }
+        let len = buf.len().min(self.buffer.len() - self.buffer_pos);
+        buf[..len].copy_from_slice(&self.buffer[self.buffer_pos..self.buffer_pos + len]);
+        self.buffer_pos += len;
+        Ok(len)
+    }
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a single-block stream which stores `data` in an uncompressed LZMA2 chunk
+    fn stream(check_id: u8, filter_id: u8, data: &[u8]) -> Vec<u8> {
+        let crc32 = |bytes: &[u8]| {
+            let mut crc = Crc32::new();
+            crc.update(bytes);
+            crc.finalize().to_le_bytes()
+        };
+        let flags = [0, check_id];
+        let mut out = HEADER_MAGIC.to_vec();
+        out.extend(&flags);
+        out.extend(&crc32(&flags));
+
+        let block_header = [2, 0, filter_id, 1, 0, 0, 0, 0];
+        out.extend(&block_header);
+        out.extend(&crc32(&block_header));
+        out.push(0x01);
+        out.extend(&(data.len() as u16 - 1).to_be_bytes());
+        out.extend(data);
+        out.push(0x00);
+        let unpadded = block_header.len() + 4 + data.len() + 4;
+        while out.len() % 4 != 0 {
+            out.push(0);
+        }
+        let check = Check::new(check_id).map_or(vec![0; 4], |mut check| {
+            check.update(data);
+            check.finish()
+        });
+        out.extend(&check);
+
+        let mut index = vec![0, 1, (unpadded + check.len()) as u8, data.len() as u8];
+        while index.len() % 4 != 0 {
+            index.push(0);
+        }
+        let index_crc = crc32(&index);
+        out.extend(&index);
+        out.extend(&index_crc);
+
+        let mut footer = (((index.len() + 4) / 4 - 1) as u32).to_le_bytes().to_vec();
+        footer.extend(&flags);
+        out.extend(&crc32(&footer));
+        out.extend(&footer);
+        out.extend(&FOOTER_MAGIC);
+        out
+    }
+
+    /// Decompress `input`, returning the error kind on failure
+    fn decode(input: &[u8]) -> Result<Vec<u8>, io::ErrorKind> {
+        let mut output = Vec::new();
+        XzDecoder::new(input).read_to_end(&mut output).map_err(|err| err.kind())?;
+        Ok(output)
+    }
+
+    #[test]
+    fn test_checks() {
+        for &check_id in &[0x00, 0x01, 0x04, 0x0A] {
+            assert_eq!(decode(&stream(check_id, 0x21, b"Hello, xz!\n")).unwrap(), b"Hello, xz!\n");
+
+            let mut corrupted = stream(check_id, 0x21, b"Hello, xz!\n");
+            corrupted[35] ^= 0x03;
+            let expected = if check_id == 0 { Ok(b"Hello, xy!\n".to_vec()) }
+                           else { Err(io::ErrorKind::InvalidData) };
+            assert_eq!(decode(&corrupted), expected);
+        }
+        assert_eq!(decode(&stream(0x02, 0x21, b"Hello")), Err(io::ErrorKind::Unsupported));
+        assert_eq!(decode(&stream(0x04, 0x04, b"Hello")), Err(io::ErrorKind::Unsupported));
+    }
+
+    #[test]
+    fn test_stream_structure() {
+        let single = stream(0x04, 0x21, b"Hello");
+        for cut in &[0, 11, 30, single.len() - 1] {
+            assert_eq!(decode(&single[..*cut]), Err(io::ErrorKind::InvalidData));
+        }
+
+        let mut multi = single.clone();
+        multi.extend(&[0; 8]);
+        multi.extend(&stream(0x0A, 0x21, b", world"));
+        multi.extend(&[0; 4]);
+        assert_eq!(decode(&multi).unwrap(), b"Hello, world");
+
+        for trailer in &[&[0, 0][..], &[0, 0, 0, 1], b"junk"] {
+            let mut bad = single.clone();
+            bad.extend(*trailer);
+            assert_eq!(decode(&bad), Err(io::ErrorKind::InvalidData));
+        }
+    }
+
+    #[test]
+    fn test_lzma_fixtures() {
+        assert!(decode(include_bytes!("../../../test_data/good/testfile.txz")).is_ok());
+        assert!(decode(include_bytes!("../../../test_data/good/testfile.txt.xz")).is_ok());
+        assert_eq!(decode(include_bytes!("../../../test_data/bad/testfile.txz")),
+                   Err(io::ErrorKind::InvalidData));
+        assert_eq!(decode(include_bytes!("../../../test_data/bad/testfile.txt.xz")),
+                   Err(io::ErrorKind::InvalidData));
+    }
+}
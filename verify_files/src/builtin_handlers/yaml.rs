@@ -0,0 +1,1391 @@
+//! A well-formedness checker for YAML streams, modelled on libyaml's scanner and parser
+//!
+//! (No values are constructed. The parser only tracks how many nodes each anchor stands for, so
+//! that aliases which would expand into an unreasonable amount of data can be refused rather than
+//! passed as valid.)
+
+// Standard library imports
+use std::collections::{HashMap, VecDeque};
+use std::io::Read;
+
+// Local Imports
+use super::FailureType;
+
+/// How many nodes aliases may expand into, per document, before the file is refused
+///
+/// (High enough for any legitimate use of anchors, but low enough to catch "billion laughs"
+/// documents long before they'd exhaust the memory of something which actually loads them.)
+const ALIAS_EXPANSION_LIMIT: u64 = 1_000_000;
+
+/// The largest stream which will be read into memory for parsing
+///
+/// (The parser needs the whole stream as a `&str`. Anything bigger than this is far more likely to
+/// be something else with the wrong extension than a real YAML file.)
+const MAX_SIZE: u64 = 64 * 1024 * 1024;
+
+/// How far a simple key may be from its `:` before it stops being a candidate
+const MAX_SIMPLE_KEY_LEN: usize = 1024;
+
+/// The characters which start or end flow collections and separate their entries
+const FLOW_INDICATORS: &[char] = &[',', '[', ']', '{', '}'];
+
+/// Report a syntax error at `mark`
+fn syntax_error(message: &str, mark: Mark) -> FailureType {
+    FailureType::InvalidContent(format!("YAML error at line {} column {}: {}",
+                                        mark.line + 1, mark.col + 1, message))
+}
+
+/// Whether `chr` is allowed anywhere in a YAML stream (the `c-printable` production)
+fn is_printable(chr: char) -> bool {
+    matches!(chr, '\t' | '\n' | '\r' | ' '..='~' | '\u{85}' | '\u{A0}'..='\u{D7FF}'
+             | '\u{E000}'..='\u{FFFD}' | '\u{10000}'..='\u{10FFFF}')
+}
+
+/// Whether `chr` may appear in a tag (the `ns-uri-char` production, plus `!` for tag handles)
+fn is_uri_char(chr: char) -> bool {
+    chr.is_ascii_alphanumeric() || "-#;/?:@&=+$,_.!~*'()[]%".contains(chr)
+}
+
+/// A position in the input, counted in characters from zero
+#[derive(Clone, Copy, Debug, Default)]
+struct Mark {
+    /// Index into the input
+    index: usize,
+    /// Line number
+    line: usize,
+    /// Column number
+    col: usize,
+}
+
+/// The kinds of token produced by the [`Scanner`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum TokenKind {
+    /// The start of the input
+    StreamStart,
+    /// The end of the input
+    StreamEnd,
+    /// A `%YAML`, `%TAG`, or reserved directive, and the handle a `%TAG` directive declares
+    Directive(Option<String>),
+    /// `---`
+    DocumentStart,
+    /// `...`
+    DocumentEnd,
+    /// An (implicit) increase in indentation which starts a block sequence
+    BlockSequenceStart,
+    /// An (implicit) increase in indentation which starts a block mapping
+    BlockMappingStart,
+    /// An (implicit) decrease in indentation which ends a block collection
+    BlockEnd,
+    /// `[`
+    FlowSequenceStart,
+    /// `]`
+    FlowSequenceEnd,
+    /// `{`
+    FlowMappingStart,
+    /// `}`
+    FlowMappingEnd,
+    /// `-` in a block sequence
+    BlockEntry,
+    /// `,`
+    FlowEntry,
+    /// `?` (or inserted before a simple key)
+    Key,
+    /// `:`
+    Value,
+    /// `*name`
+    Alias(String),
+    /// `&name`
+    Anchor(String),
+    /// `!tag`, and its named handle (eg. `!e!`), if it has one
+    Tag(Option<String>),
+    /// A plain, quoted, or block scalar
+    Scalar,
+}
+
+/// A token and where it started
+#[derive(Debug)]
+struct Token(TokenKind, Mark);
+
+/// A place in the token stream where a `:` would make the token that starts there a mapping key
+#[derive(Clone, Copy, Default)]
+struct SimpleKey {
+    /// Whether this is still a candidate
+    possible: bool,
+    /// Whether failing to find the `:` is an error (ie. the token began a line of a block mapping)
+    required: bool,
+    /// How many tokens came before the key's first token
+    token_number: usize,
+    /// Where the key's first token started
+    mark: Mark,
+}
+
+/// A tokenizer for YAML which resolves indentation into explicit start/end tokens
+struct Scanner {
+    /// The input, minus any leading byte order mark
+    chars: Vec<char>,
+    /// The current position
+    mark: Mark,
+    /// Tokens which have been scanned but not yet handed to the parser
+    tokens: VecDeque<Token>,
+    /// How many tokens have been handed to the parser
+    tokens_parsed: usize,
+    /// Whether the `StreamStart` token has been produced
+    stream_start_produced: bool,
+    /// Whether the `StreamEnd` token has been produced
+    stream_end_produced: bool,
+    /// The indentation of the innermost block collection (-1 at the top level)
+    indent: isize,
+    /// The indentation of the enclosing block collections
+    indents: Vec<isize>,
+    /// Whether a simple key may start at the current position
+    simple_key_allowed: bool,
+    /// The candidate simple key for each level of flow collection nesting (plus the block level)
+    simple_keys: Vec<SimpleKey>,
+}
+
+impl Scanner {
+    /// Prepare to scan `input`
+    fn new(input: &str) -> Self {
+        let input = input.strip_prefix('\u{FEFF}').unwrap_or(input);
+        Self {
+            chars: input.chars().collect(),
+            mark: Mark::default(),
+            tokens: VecDeque::new(),
+            tokens_parsed: 0,
+            stream_start_produced: false,
+            stream_end_produced: false,
+            indent: -1,
+            indents: Vec::new(),
+            simple_key_allowed: false,
+            simple_keys: vec![SimpleKey::default()],
+        }
+    }
+
+    /// The character `offset` characters ahead of the current position (`'\0'` past the end)
+    fn peek_at(&self, offset: usize) -> char {
+        self.chars.get(self.mark.index + offset).copied().unwrap_or('\0')
+    }
+
+    /// The character at the current position (`'\0'` past the end)
+    fn peek(&self) -> char {
+        self.peek_at(0)
+    }
+
+    /// Whether the current position is the end of the input
+    fn at_end(&self) -> bool {
+        self.mark.index >= self.chars.len()
+    }
+
+    /// Whether the character `offset` characters ahead is a space or tab
+    fn is_blank_at(&self, offset: usize) -> bool {
+        matches!(self.peek_at(offset), ' ' | '\t')
+    }
+
+    /// Whether the character `offset` characters ahead is a line break
+    fn is_break_at(&self, offset: usize) -> bool {
+        matches!(self.peek_at(offset), '\r' | '\n')
+    }
+
+    /// Whether the character `offset` characters ahead is whitespace or the end of the input
+    fn is_blankz_at(&self, offset: usize) -> bool {
+        self.mark.index + offset >= self.chars.len() || self.is_blank_at(offset)
+            || self.is_break_at(offset)
+    }
+
+    /// Whether the current position is a `---` or `...` marker at the start of a line
+    fn at_document_indicator(&self) -> bool {
+        self.mark.col == 0 && self.is_blankz_at(3)
+            && ['-', '.'].iter().any(|&chr| (0..3).all(|offset| self.peek_at(offset) == chr))
+    }
+
+    /// Step past one character which isn't a line break
+    fn skip(&mut self) {
+        self.mark.index += 1;
+        self.mark.col += 1;
+    }
+
+    /// Step past a line break
+    fn skip_break(&mut self) {
+        if self.peek() == '\r' && self.peek_at(1) == '\n' {
+            self.mark.index += 1;
+        }
+        self.mark.index += 1;
+        self.mark.line += 1;
+        self.mark.col = 0;
+    }
+
+    /// Step past everything up to the next line break
+    fn skip_to_break(&mut self) {
+        while !self.at_end() && !self.is_break_at(0) {
+            self.skip();
+        }
+    }
+
+    /// Get the next token, scanning more input if necessary
+    fn next_token(&mut self) -> Result<Token, FailureType> {
+        self.peek_token()?;
+        self.tokens_parsed += 1;
+        Ok(self.tokens.pop_front().expect("peek_token ensures a token is queued"))
+    }
+
+    /// Look at the next token without consuming it
+    fn peek_token(&mut self) -> Result<&Token, FailureType> {
+        loop {
+            let need_more = if self.tokens.is_empty() {
+                true
+            } else {
+                self.stale_simple_keys()?;
+                let parsed = self.tokens_parsed;
+                self.simple_keys.iter().any(|key| key.possible && key.token_number == parsed)
+            };
+            if !need_more {
+                break;
+            }
+            if self.stream_end_produced {
+                return Err(syntax_error("Unexpected end of stream", self.mark));
+            }
+            self.fetch_next_token()?;
+        }
+        Ok(self.tokens.front().expect("loop only exits once a token is queued"))
+    }
+
+    /// Queue a token which starts at `mark`
+    fn push(&mut self, kind: TokenKind, mark: Mark) {
+        self.tokens.push_back(Token(kind, mark));
+    }
+
+    /// Scan the next token (or tokens, if indentation changes) into the queue
+    fn fetch_next_token(&mut self) -> Result<(), FailureType> {
+        if !self.stream_start_produced {
+            self.stream_start_produced = true;
+            self.simple_key_allowed = true;
+            self.push(TokenKind::StreamStart, self.mark);
+            return Ok(());
+        }
+
+        self.scan_to_next_token()?;
+        self.stale_simple_keys()?;
+        self.unroll_indent(self.mark.col as isize);
+
+        let (chr, next_is_blankz) = (self.peek(), self.is_blankz_at(1));
+        let in_flow = self.simple_keys.len() > 1;
+        if self.at_end() {
+            self.unroll_indent(-1);
+            self.remove_simple_key()?;
+            self.simple_key_allowed = false;
+            self.stream_end_produced = true;
+            self.push(TokenKind::StreamEnd, self.mark);
+            return Ok(());
+        }
+        if self.mark.col == 0 && chr == '%' {
+            return self.fetch_directive();
+        }
+        if self.at_document_indicator() {
+            let kind = if chr == '-' { TokenKind::DocumentStart } else { TokenKind::DocumentEnd };
+            return self.fetch_document_indicator(kind);
+        }
+        match chr {
+            '[' => self.fetch_flow_collection_start(TokenKind::FlowSequenceStart),
+            '{' => self.fetch_flow_collection_start(TokenKind::FlowMappingStart),
+            ']' => self.fetch_flow_collection_end(TokenKind::FlowSequenceEnd),
+            '}' => self.fetch_flow_collection_end(TokenKind::FlowMappingEnd),
+            ',' => self.fetch_flow_entry(),
+            '-' if next_is_blankz => self.fetch_block_entry(),
+            '?' if in_flow || next_is_blankz => self.fetch_key(),
+            ':' if in_flow || next_is_blankz => self.fetch_value(),
+            '*' | '&' => self.fetch_anchor(chr == '*'),
+            '!' => self.fetch_tag(),
+            '|' | '>' if !in_flow => self.fetch_block_scalar(),
+            '\'' | '"' => self.fetch_flow_scalar(chr == '\''),
+            '-' | '?' | ':' => self.fetch_plain_scalar(),
+            '#' | '|' | '>' | '%' | '@' | '`' | '\t' | ' ' | '\r' | '\n' => Err(syntax_error(
+                &format!("Found {:?}, which cannot start any token", chr), self.mark)),
+            _ => self.fetch_plain_scalar(),
+        }
+    }
+
+    /// Skip whitespace, comments, and line breaks up to the start of the next token
+    fn scan_to_next_token(&mut self) -> Result<(), FailureType> {
+        loop {
+            if self.mark.col == 0 && self.peek() == '\u{FEFF}' {
+                self.skip();
+            }
+            while self.peek() == ' ' || (self.peek() == '\t'
+                    && (self.simple_keys.len() > 1 || !self.simple_key_allowed)) {
+                self.skip();
+            }
+            if self.peek() == '#' {
+                self.skip_to_break();
+            }
+            if !self.is_break_at(0) {
+                return Ok(());
+            }
+            self.skip_break();
+            if self.simple_keys.len() == 1 {
+                self.simple_key_allowed = true;
+            }
+        }
+    }
+
+    /// Drop simple key candidates which can no longer be followed by their `:`
+    fn stale_simple_keys(&mut self) -> Result<(), FailureType> {
+        let mark = self.mark;
+        for key in &mut self.simple_keys {
+            if key.possible && (key.mark.line < mark.line
+                                || key.mark.index + MAX_SIMPLE_KEY_LEN < mark.index) {
+                if key.required {
+                    return Err(syntax_error("Could not find expected ':'", key.mark));
+                }
+                key.possible = false;
+            }
+        }
+        Ok(())
+    }
+
+    /// Record that the token about to be queued could be a simple key
+    fn save_simple_key(&mut self) -> Result<(), FailureType> {
+        if self.simple_key_allowed {
+            self.remove_simple_key()?;
+            let key = SimpleKey {
+                possible: true,
+                required: self.simple_keys.len() == 1 && self.indent == self.mark.col as isize,
+                token_number: self.tokens_parsed + self.tokens.len(),
+                mark: self.mark,
+            };
+            *self.simple_keys.last_mut().expect("the block level is never popped") = key;
+        }
+        Ok(())
+    }
+
+    /// Drop the current simple key candidate, if it's allowed to not have a `:`
+    fn remove_simple_key(&mut self) -> Result<(), FailureType> {
+        let key = self.simple_keys.last_mut().expect("the block level is never popped");
+        if key.possible && key.required {
+            return Err(syntax_error("Could not find expected ':'", key.mark));
+        }
+        key.possible = false;
+        Ok(())
+    }
+
+    /// Start a new block collection if `col` is further indented than the current one
+    ///
+    /// The start token is inserted before the token numbered `token_number` if one is given.
+    fn roll_indent(&mut self, col: usize, token_number: Option<usize>, kind: TokenKind,
+                   mark: Mark) {
+        if self.simple_keys.len() > 1 || self.indent >= col as isize {
+            return;
+        }
+        self.indents.push(self.indent);
+        self.indent = col as isize;
+        match token_number {
+            Some(number) => self.tokens.insert(number - self.tokens_parsed, Token(kind, mark)),
+            None => self.push(kind, mark),
+        }
+    }
+
+    /// End every block collection which is indented further than `col`
+    fn unroll_indent(&mut self, col: isize) {
+        if self.simple_keys.len() > 1 {
+            return;
+        }
+        while self.indent > col {
+            self.push(TokenKind::BlockEnd, self.mark);
+            self.indent = self.indents.pop().unwrap_or(-1);
+        }
+    }
+
+    /// Scan a `%YAML`, `%TAG`, or reserved directive
+    fn fetch_directive(&mut self) -> Result<(), FailureType> {
+        self.unroll_indent(-1);
+        self.remove_simple_key()?;
+        self.simple_key_allowed = false;
+
+        let start = self.mark;
+        self.skip();
+        let name_start = self.mark.index;
+        while self.peek().is_ascii_alphanumeric() || matches!(self.peek(), '-' | '_') {
+            self.skip();
+        }
+        let name: String = self.chars[name_start..self.mark.index].iter().collect();
+        if name.is_empty() || !self.is_blankz_at(0) {
+            return Err(syntax_error("Malformed directive name", start));
+        }
+        let mut params = Vec::new();
+        loop {
+            while self.is_blank_at(0) {
+                self.skip();
+            }
+            if self.is_blankz_at(0) || self.peek() == '#' {
+                break;
+            }
+            let param_start = self.mark.index;
+            while !self.is_blankz_at(0) {
+                self.skip();
+            }
+            params.push(self.chars[param_start..self.mark.index].iter().collect::<String>());
+        }
+        let valid = match name.as_str() {
+            "YAML" => params.len() == 1 && params[0].split_once('.').map_or(false, |(major, minor)|
+                [major, minor].iter().all(|part|
+                    !part.is_empty() && part.chars().all(|chr| chr.is_ascii_digit()))),
+            "TAG" => params.len() == 2 && params[0].starts_with('!') && params[0].ends_with('!'),
+            _ => true,
+        };
+        if !valid {
+            return Err(syntax_error(&format!("Malformed %{} directive", name), start));
+        }
+        if name == "YAML" && params.first().map_or(false, |version| !version.starts_with("1.")) {
+            return Err(FailureType::UnsupportedFormat(format!(
+                "Unsupported YAML version {}", params[0])));
+        }
+        self.skip_comment_to_break(start)?;
+        let handle = if name == "TAG" { params.into_iter().next() } else { None };
+        self.push(TokenKind::Directive(handle), start);
+        Ok(())
+    }
+
+    /// Skip trailing blanks and an optional comment, requiring the line to end there
+    fn skip_comment_to_break(&mut self, start: Mark) -> Result<(), FailureType> {
+        let blanks_start = self.mark.index;
+        while self.is_blank_at(0) {
+            self.skip();
+        }
+        if self.peek() == '#' && self.mark.index > blanks_start {
+            self.skip_to_break();
+        }
+        if self.at_end() || self.is_break_at(0) {
+            Ok(())
+        } else {
+            Err(syntax_error("Did not find expected comment or line break", start))
+        }
+    }
+
+    /// Scan a `---` or `...` marker
+    fn fetch_document_indicator(&mut self, kind: TokenKind) -> Result<(), FailureType> {
+        self.unroll_indent(-1);
+        self.remove_simple_key()?;
+        self.simple_key_allowed = false;
+        let start = self.mark;
+        for _ in 0..3 {
+            self.skip();
+        }
+        self.push(kind, start);
+        Ok(())
+    }
+
+    /// Scan a `[` or `{`
+    fn fetch_flow_collection_start(&mut self, kind: TokenKind) -> Result<(), FailureType> {
+        self.save_simple_key()?;
+        self.simple_keys.push(SimpleKey::default());
+        self.simple_key_allowed = true;
+        let start = self.mark;
+        self.skip();
+        self.push(kind, start);
+        Ok(())
+    }
+
+    /// Scan a `]` or `}`
+    fn fetch_flow_collection_end(&mut self, kind: TokenKind) -> Result<(), FailureType> {
+        self.remove_simple_key()?;
+        if self.simple_keys.len() > 1 {
+            self.simple_keys.pop();
+        }
+        self.simple_key_allowed = false;
+        let start = self.mark;
+        self.skip();
+        self.push(kind, start);
+        Ok(())
+    }
+
+    /// Scan a `,`
+    fn fetch_flow_entry(&mut self) -> Result<(), FailureType> {
+        self.remove_simple_key()?;
+        self.simple_key_allowed = true;
+        let start = self.mark;
+        self.skip();
+        self.push(TokenKind::FlowEntry, start);
+        Ok(())
+    }
+
+    /// Scan a `-` which introduces a block sequence entry
+    fn fetch_block_entry(&mut self) -> Result<(), FailureType> {
+        let start = self.mark;
+        if self.simple_keys.len() == 1 {
+            if !self.simple_key_allowed {
+                return Err(syntax_error(
+                    "Block sequence entries are not allowed in this context", start));
+            }
+            self.roll_indent(start.col, None, TokenKind::BlockSequenceStart, start);
+        }
+        self.remove_simple_key()?;
+        self.simple_key_allowed = true;
+        self.skip();
+        self.push(TokenKind::BlockEntry, start);
+        Ok(())
+    }
+
+    /// Scan a `?` which introduces a complex mapping key
+    fn fetch_key(&mut self) -> Result<(), FailureType> {
+        let start = self.mark;
+        if self.simple_keys.len() == 1 {
+            if !self.simple_key_allowed {
+                return Err(syntax_error("Mapping keys are not allowed in this context", start));
+            }
+            self.roll_indent(start.col, None, TokenKind::BlockMappingStart, start);
+        }
+        self.remove_simple_key()?;
+        self.simple_key_allowed = self.simple_keys.len() == 1;
+        self.skip();
+        self.push(TokenKind::Key, start);
+        Ok(())
+    }
+
+    /// Scan a `:`, retroactively marking the preceding simple key if there is one
+    fn fetch_value(&mut self) -> Result<(), FailureType> {
+        let start = self.mark;
+        let key = *self.simple_keys.last().expect("the block level is never popped");
+        if key.possible {
+            self.tokens.insert(key.token_number - self.tokens_parsed,
+                               Token(TokenKind::Key, key.mark));
+            self.roll_indent(key.mark.col, Some(key.token_number),
+                             TokenKind::BlockMappingStart, key.mark);
+            self.simple_keys.last_mut().expect("the block level is never popped").possible = false;
+            self.simple_key_allowed = false;
+        } else {
+            if self.simple_keys.len() == 1 {
+                if !self.simple_key_allowed {
+                    return Err(syntax_error(
+                        "Mapping values are not allowed in this context", start));
+                }
+                self.roll_indent(start.col, None, TokenKind::BlockMappingStart, start);
+            }
+            self.simple_key_allowed = self.simple_keys.len() == 1;
+        }
+        self.skip();
+        self.push(TokenKind::Value, start);
+        Ok(())
+    }
+
+    /// Scan an `*alias` or an `&anchor`
+    fn fetch_anchor(&mut self, is_alias: bool) -> Result<(), FailureType> {
+        self.save_simple_key()?;
+        self.simple_key_allowed = false;
+        let start = self.mark;
+        self.skip();
+        let name_start = self.mark.index;
+        // (Stopping at `: ` too, as libyaml does, since `&anchor: value` is common in the wild)
+        while !self.is_blankz_at(0) && !FLOW_INDICATORS.contains(&self.peek())
+                && !(self.peek() == ':' && self.is_blankz_at(1)) {
+            self.skip();
+        }
+        if self.mark.index == name_start {
+            return Err(syntax_error("Anchor or alias has no name", start));
+        }
+        let name = self.chars[name_start..self.mark.index].iter().collect();
+        self.push(if is_alias { TokenKind::Alias(name) } else { TokenKind::Anchor(name) }, start);
+        Ok(())
+    }
+
+    /// Scan a `!tag`
+    fn fetch_tag(&mut self) -> Result<(), FailureType> {
+        self.save_simple_key()?;
+        self.simple_key_allowed = false;
+        let start = self.mark;
+        self.skip();
+        let mut handle = None;
+        if self.peek() == '<' {
+            while self.peek() != '>' {
+                if self.is_blankz_at(0) {
+                    return Err(syntax_error("Verbatim tag is missing its closing '>'", start));
+                }
+                self.skip();
+            }
+            self.skip();
+        } else {
+            let in_flow = self.simple_keys.len() > 1;
+            let name_start = self.mark.index;
+            while !self.is_blankz_at(0) && !(in_flow && FLOW_INDICATORS.contains(&self.peek())) {
+                let chr = self.peek();
+                let escaped = chr != '%'
+                    || (1..3).all(|offset| self.peek_at(offset).is_ascii_hexdigit());
+                if !is_uri_char(chr) || !escaped {
+                    return Err(syntax_error("Found a character which isn't allowed in tags",
+                                            self.mark));
+                }
+                let name = &self.chars[name_start..self.mark.index];
+                if chr == '!' && handle.is_none()
+                        && name.iter().all(|chr| chr.is_ascii_alphanumeric() || *chr == '-') {
+                    handle = Some(format!("!{}!", name.iter().collect::<String>()));
+                }
+                self.skip();
+            }
+        }
+        if !self.is_blankz_at(0) && !(self.simple_keys.len() > 1
+                                      && FLOW_INDICATORS.contains(&self.peek())) {
+            return Err(syntax_error("Did not find expected whitespace after tag", start));
+        }
+        self.push(TokenKind::Tag(handle), start);
+        Ok(())
+    }
+
+    /// Scan a `|` literal or `>` folded scalar
+    fn fetch_block_scalar(&mut self) -> Result<(), FailureType> {
+        self.remove_simple_key()?;
+        self.simple_key_allowed = true;
+        let start = self.mark;
+        self.skip();
+
+        let (mut chomping, mut increment) = (false, None);
+        for _ in 0..2 {
+            match self.peek() {
+                '+' | '-' if !chomping => chomping = true,
+                '0' if increment.is_none() => return Err(syntax_error(
+                    "Block scalar indentation indicator can't be 0", self.mark)),
+                digit @ '1'..='9' if increment.is_none() =>
+                    increment = digit.to_digit(10).map(|digit| digit as usize),
+                _ => break,
+            }
+            self.skip();
+        }
+        self.skip_comment_to_break(start)?;
+        if !self.at_end() {
+            self.skip_break();
+        }
+
+        let parent_indent = (self.indent + 1).max(0) as usize;
+        let mut indent = increment.map_or(0, |increment| self.indent.max(0) as usize + increment);
+        let mut max_indent = 0;
+        self.skip_block_scalar_breaks(&mut indent, &mut max_indent, start)?;
+        if indent == 0 {
+            indent = max_indent.max(parent_indent).max(1);
+        }
+        while self.mark.col == indent && !self.at_end() && !self.at_document_indicator() {
+            self.skip_to_break();
+            if self.at_end() {
+                break;
+            }
+            self.skip_break();
+            self.skip_block_scalar_breaks(&mut indent, &mut max_indent, start)?;
+        }
+        self.push(TokenKind::Scalar, start);
+        Ok(())
+    }
+
+    /// Skip the indentation and any empty lines before a line of a block scalar's content
+    ///
+    /// If `indent` is still 0 (auto-detect), `max_indent` is updated with the deepest indentation
+    /// which was seen.
+    fn skip_block_scalar_breaks(&mut self, indent: &mut usize, max_indent: &mut usize,
+                                start: Mark) -> Result<(), FailureType> {
+        loop {
+            while (*indent == 0 || self.mark.col < *indent) && self.peek() == ' ' {
+                self.skip();
+            }
+            *max_indent = (*max_indent).max(self.mark.col);
+            if (*indent == 0 || self.mark.col < *indent) && self.peek() == '\t' {
+                return Err(syntax_error(
+                    "Found a tab where block scalar indentation was expected", start));
+            }
+            if !self.is_break_at(0) {
+                return Ok(());
+            }
+            self.skip_break();
+        }
+    }
+
+    /// Scan a `'single-quoted'` or `"double-quoted"` scalar
+    fn fetch_flow_scalar(&mut self, single: bool) -> Result<(), FailureType> {
+        self.save_simple_key()?;
+        self.simple_key_allowed = false;
+        let (start, quote) = (self.mark, self.peek());
+        self.skip();
+        loop {
+            if self.at_document_indicator() {
+                return Err(syntax_error("Found a document marker inside a quoted scalar", start));
+            }
+            if self.at_end() {
+                return Err(syntax_error("Quoted scalar is missing its closing quote", start));
+            }
+            while !self.is_blankz_at(0) {
+                match self.peek() {
+                    '\'' if single && self.peek_at(1) == '\'' => {
+                        self.skip();
+                        self.skip();
+                    },
+                    chr if chr == quote => break,
+                    '\\' if !single && self.is_break_at(1) => {
+                        self.skip();
+                        break;
+                    },
+                    '\\' if !single => self.skip_escape()?,
+                    _ => self.skip(),
+                }
+            }
+            if self.peek() == quote {
+                self.skip();
+                break;
+            }
+            while self.is_blank_at(0) || self.is_break_at(0) {
+                if self.is_blank_at(0) { self.skip() } else { self.skip_break() }
+            }
+        }
+        self.push(TokenKind::Scalar, start);
+        Ok(())
+    }
+
+    /// Step past an escape sequence in a double-quoted scalar, checking that it's valid
+    fn skip_escape(&mut self) -> Result<(), FailureType> {
+        let start = self.mark;
+        let len = match self.peek_at(1) {
+            '0' | 'a' | 'b' | 't' | '\t' | 'n' | 'v' | 'f' | 'r' | 'e' | ' ' | '"' | '/' | '\\'
+                | 'N' | '_' | 'L' | 'P' => 0,
+            'x' => 2,
+            'u' => 4,
+            'U' => 8,
+            chr => return Err(syntax_error(&format!("Unknown escape sequence \\{}", chr), start)),
+        };
+        self.skip();
+        self.skip();
+        let digits: String = (0..len).map(|offset| self.peek_at(offset)).collect();
+        let code = u32::from_str_radix(&digits, 16).ok()
+            .filter(|_| digits.chars().all(|chr| chr.is_ascii_hexdigit()));
+        if len > 0 && code.and_then(char::from_u32).is_none() {
+            return Err(syntax_error("Invalid hexadecimal escape sequence", start));
+        }
+        for _ in 0..len {
+            self.skip();
+        }
+        Ok(())
+    }
+
+    /// Scan an unquoted scalar, which may span multiple lines
+    fn fetch_plain_scalar(&mut self) -> Result<(), FailureType> {
+        self.save_simple_key()?;
+        self.simple_key_allowed = false;
+        let start = self.mark;
+        let in_flow = self.simple_keys.len() > 1;
+        let indent = self.indent + 1;
+        let mut leading_blanks = false;
+        loop {
+            if self.at_document_indicator() || self.peek() == '#' {
+                break;
+            }
+            while !self.is_blankz_at(0) {
+                let chr = self.peek();
+                if (chr == ':' && (self.is_blankz_at(1) || (in_flow
+                                   && FLOW_INDICATORS.contains(&self.peek_at(1)))))
+                        || (in_flow && FLOW_INDICATORS.contains(&chr)) {
+                    break;
+                }
+                self.skip();
+            }
+            if !(self.is_blank_at(0) || self.is_break_at(0)) {
+                break;
+            }
+            while self.is_blank_at(0) || self.is_break_at(0) {
+                if self.is_blank_at(0) {
+                    if leading_blanks && (self.mark.col as isize) < indent && self.peek() == '\t' {
+                        return Err(syntax_error("Found a tab which violates indentation",
+                                                self.mark));
+                    }
+                    self.skip();
+                } else {
+                    self.skip_break();
+                    leading_blanks = true;
+                }
+            }
+            if !in_flow && (self.mark.col as isize) < indent {
+                break;
+            }
+        }
+        if leading_blanks {
+            self.simple_key_allowed = true;
+        }
+        self.push(TokenKind::Scalar, start);
+        Ok(())
+    }
+}
+
+/// The parser's position in the grammar
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    /// Expecting the start of the input
+    StreamStart,
+    /// Expecting a document, which may omit its `---`
+    ImplicitDocumentStart,
+    /// Expecting a document, which must begin with `---`
+    DocumentStart,
+    /// Expecting the root node of an explicit document, which may be empty
+    DocumentContent,
+    /// Expecting a document to end, optionally with `...`
+    DocumentEnd,
+    /// Expecting the root node of an implicit document
+    BlockNode,
+    /// Expecting the first entry of a block sequence
+    BlockSequenceFirstEntry,
+    /// Expecting another entry of a block sequence or its end
+    BlockSequenceEntry,
+    /// Expecting another entry of a sequence at the same indentation as its parent mapping's keys
+    IndentlessSequenceEntry,
+    /// Expecting the first key of a block mapping
+    BlockMappingFirstKey,
+    /// Expecting another key of a block mapping or its end
+    BlockMappingKey,
+    /// Expecting the value for a block mapping key
+    BlockMappingValue,
+    /// Expecting the first entry of a flow sequence or its end
+    FlowSequenceFirstEntry,
+    /// Expecting `,` and another entry of a flow sequence, or its end
+    FlowSequenceEntry,
+    /// Expecting the key of a single-pair mapping in a flow sequence
+    FlowSequenceEntryMappingKey,
+    /// Expecting the value of a single-pair mapping in a flow sequence
+    FlowSequenceEntryMappingValue,
+    /// Expecting the end of a single-pair mapping in a flow sequence
+    FlowSequenceEntryMappingEnd,
+    /// Expecting the first key of a flow mapping or its end
+    FlowMappingFirstKey,
+    /// Expecting `,` and another key of a flow mapping, or its end
+    FlowMappingKey,
+    /// Expecting the value for a flow mapping key
+    FlowMappingValue,
+    /// Expecting nothing, for a flow mapping key with no `:`
+    FlowMappingEmptyValue,
+    /// Finished
+    End,
+}
+
+/// The parts of the node graph which matter for checking aliases
+#[derive(Debug)]
+enum Event {
+    /// The end of the input
+    StreamEnd,
+    /// The start of a document
+    DocumentStart,
+    /// An alias, and where it was
+    Alias(String, Mark),
+    /// A scalar (or an empty node), and its anchor
+    Scalar(Option<String>),
+    /// The start of a sequence or mapping, and its anchor
+    CollectionStart(Option<String>),
+    /// The end of a sequence or mapping
+    CollectionEnd,
+    /// Anything else (eg. document ends)
+    Other,
+}
+
+/// A checker for the YAML grammar, run over the tokens from a [`Scanner`]
+struct Parser {
+    /// The token source
+    scanner: Scanner,
+    /// What to expect next
+    state: State,
+    /// What to go back to expecting once the current node is finished
+    states: Vec<State>,
+    /// The tag handles declared by the current document's `%TAG` directives
+    tag_handles: Vec<String>,
+}
+
+impl Parser {
+    /// Prepare to parse `input`
+    fn new(input: &str) -> Self {
+        Self {
+            scanner: Scanner::new(input),
+            state: State::StreamStart,
+            states: Vec::new(),
+            tag_handles: Vec::new(),
+        }
+    }
+
+    /// The kind of the next token
+    fn peek_kind(&mut self) -> Result<TokenKind, FailureType> {
+        self.scanner.peek_token().map(|token| token.0.clone())
+    }
+
+    /// Consume the next token if it's of the given kind
+    fn skip_if(&mut self, kind: &TokenKind) -> Result<bool, FailureType> {
+        let matched = &self.peek_kind()? == kind;
+        if matched {
+            self.scanner.next_token()?;
+        }
+        Ok(matched)
+    }
+
+    /// An error about the next token
+    fn error(&mut self, message: &str) -> FailureType {
+        match self.scanner.peek_token() {
+            Ok(&Token(_, mark)) => syntax_error(message, mark),
+            Err(err) => err,
+        }
+    }
+
+    /// Return to the state saved before the current node began
+    fn pop_state(&mut self) {
+        self.state = self.states.pop().unwrap_or(State::End);
+    }
+
+    /// Parse up to the next event
+    fn next_event(&mut self) -> Result<Event, FailureType> {
+        use TokenKind as T;
+        match self.state {
+            State::StreamStart => {
+                self.scanner.next_token()?;
+                self.state = State::ImplicitDocumentStart;
+                Ok(Event::Other)
+            },
+            State::ImplicitDocumentStart => self.document_start(true),
+            State::DocumentStart => self.document_start(false),
+            State::DocumentContent => match self.peek_kind()? {
+                T::Directive(_) | T::DocumentStart | T::DocumentEnd | T::StreamEnd => {
+                    self.pop_state();
+                    Ok(Event::Scalar(None))
+                },
+                _ => self.node(true, false),
+            },
+            State::DocumentEnd => {
+                let explicit = self.skip_if(&T::DocumentEnd)?;
+                self.state = if explicit { State::ImplicitDocumentStart }
+                             else { State::DocumentStart };
+                Ok(Event::Other)
+            },
+            State::BlockNode => self.node(true, false),
+            State::End => Ok(Event::StreamEnd),
+
+            State::BlockSequenceFirstEntry | State::BlockSequenceEntry => {
+                if self.state == State::BlockSequenceFirstEntry {
+                    self.scanner.next_token()?;
+                }
+                if self.skip_if(&T::BlockEntry)? {
+                    self.state = State::BlockSequenceEntry;
+                    return self.node_unless(&[T::BlockEntry, T::BlockEnd], true, false);
+                }
+                if self.skip_if(&T::BlockEnd)? {
+                    self.pop_state();
+                    return Ok(Event::CollectionEnd);
+                }
+                Err(self.error("Did not find expected '-' indicator in block sequence"))
+            },
+            State::IndentlessSequenceEntry => {
+                if self.skip_if(&T::BlockEntry)? {
+                    return self.node_unless(&[T::BlockEntry, T::Key, T::Value, T::BlockEnd],
+                                            true, false);
+                }
+                self.pop_state();
+                Ok(Event::CollectionEnd)
+            },
+            State::BlockMappingFirstKey | State::BlockMappingKey => {
+                if self.state == State::BlockMappingFirstKey {
+                    self.scanner.next_token()?;
+                }
+                self.state = State::BlockMappingValue;
+                if self.skip_if(&T::Key)? {
+                    return self.node_unless(&[T::Key, T::Value, T::BlockEnd], true, true);
+                }
+                if self.peek_kind()? == T::Value {
+                    return Ok(Event::Scalar(None));
+                }
+                if self.skip_if(&T::BlockEnd)? {
+                    self.pop_state();
+                    return Ok(Event::CollectionEnd);
+                }
+                Err(self.error("Did not find expected key in block mapping"))
+            },
+            State::BlockMappingValue => {
+                self.state = State::BlockMappingKey;
+                if self.skip_if(&T::Value)? {
+                    return self.node_unless(&[T::Key, T::Value, T::BlockEnd], true, true);
+                }
+                Ok(Event::Scalar(None))
+            },
+
+            State::FlowSequenceFirstEntry | State::FlowSequenceEntry => {
+                if self.state == State::FlowSequenceFirstEntry {
+                    self.scanner.next_token()?;
+                }
+                if self.skip_if(&T::FlowSequenceEnd)? {
+                    self.pop_state();
+                    return Ok(Event::CollectionEnd);
+                }
+                if self.state == State::FlowSequenceEntry {
+                    if !self.skip_if(&T::FlowEntry)? {
+                        return Err(self.error("Did not find expected ',' or ']'"));
+                    }
+                    if self.skip_if(&T::FlowSequenceEnd)? {
+                        self.pop_state();
+                        return Ok(Event::CollectionEnd);
+                    }
+                }
+                self.state = State::FlowSequenceEntry;
+                if self.skip_if(&T::Key)? {
+                    self.state = State::FlowSequenceEntryMappingKey;
+                    return Ok(Event::CollectionStart(None));
+                }
+                self.states.push(State::FlowSequenceEntry);
+                self.node(false, false)
+            },
+            State::FlowSequenceEntryMappingKey => {
+                self.state = State::FlowSequenceEntryMappingValue;
+                self.node_unless(&[T::Value, T::FlowEntry, T::FlowSequenceEnd], false, false)
+            },
+            State::FlowSequenceEntryMappingValue => {
+                self.state = State::FlowSequenceEntryMappingEnd;
+                if self.skip_if(&T::Value)? {
+                    return self.node_unless(&[T::FlowEntry, T::FlowSequenceEnd], false, false);
+                }
+                Ok(Event::Scalar(None))
+            },
+            State::FlowSequenceEntryMappingEnd => {
+                self.state = State::FlowSequenceEntry;
+                Ok(Event::CollectionEnd)
+            },
+
+            State::FlowMappingFirstKey | State::FlowMappingKey => {
+                if self.state == State::FlowMappingFirstKey {
+                    self.scanner.next_token()?;
+                }
+                if self.skip_if(&T::FlowMappingEnd)? {
+                    self.pop_state();
+                    return Ok(Event::CollectionEnd);
+                }
+                if self.state == State::FlowMappingKey {
+                    if !self.skip_if(&T::FlowEntry)? {
+                        return Err(self.error("Did not find expected ',' or '}'"));
+                    }
+                    if self.skip_if(&T::FlowMappingEnd)? {
+                        self.pop_state();
+                        return Ok(Event::CollectionEnd);
+                    }
+                }
+                self.state = State::FlowMappingValue;
+                if self.skip_if(&T::Key)? {
+                    return self.node_unless(&[T::Value, T::FlowEntry, T::FlowMappingEnd],
+                                            false, false);
+                }
+                if self.peek_kind()? == T::Value {
+                    return Ok(Event::Scalar(None));
+                }
+                self.state = State::FlowMappingEmptyValue;
+                self.states.push(State::FlowMappingEmptyValue);
+                self.node(false, false)
+            },
+            State::FlowMappingValue => {
+                self.state = State::FlowMappingKey;
+                if self.skip_if(&T::Value)? {
+                    return self.node_unless(&[T::FlowEntry, T::FlowMappingEnd], false, false);
+                }
+                Ok(Event::Scalar(None))
+            },
+            State::FlowMappingEmptyValue => {
+                self.state = State::FlowMappingKey;
+                Ok(Event::Scalar(None))
+            },
+        }
+    }
+
+    /// Start the next document, or end the stream
+    fn document_start(&mut self, implicit: bool) -> Result<Event, FailureType> {
+        use TokenKind as T;
+        if !implicit {
+            while self.skip_if(&T::DocumentEnd)? {}
+        }
+        self.tag_handles.clear();
+        match self.peek_kind()? {
+            T::StreamEnd => {
+                self.scanner.next_token()?;
+                self.state = State::End;
+                Ok(Event::StreamEnd)
+            },
+            T::Directive(_) | T::DocumentStart => {
+                while let T::Directive(handle) = self.peek_kind()? {
+                    if let Some(handle) = handle {
+                        if self.tag_handles.contains(&handle) {
+                            return Err(self.error("Found a duplicate %TAG directive"));
+                        }
+                        self.tag_handles.push(handle);
+                    }
+                    self.scanner.next_token()?;
+                }
+                if !self.skip_if(&T::DocumentStart)? {
+                    return Err(self.error("Did not find expected '---' after directives"));
+                }
+                self.states.push(State::DocumentEnd);
+                self.state = State::DocumentContent;
+                Ok(Event::DocumentStart)
+            },
+            T::DocumentEnd => {
+                self.scanner.next_token()?;
+                Ok(Event::Other)
+            },
+            _ if implicit => {
+                self.states.push(State::DocumentEnd);
+                self.state = State::BlockNode;
+                Ok(Event::DocumentStart)
+            },
+            _ => Err(self.error("Did not find expected '---' before document")),
+        }
+    }
+
+    /// Parse a node unless the next token is one of `kinds`, in which case the node is empty
+    ///
+    /// The current state is where to go after the node.
+    fn node_unless(&mut self, kinds: &[TokenKind], block: bool, indentless_sequence: bool)
+            -> Result<Event, FailureType> {
+        if kinds.contains(&self.peek_kind()?) {
+            return Ok(Event::Scalar(None));
+        }
+        self.states.push(self.state);
+        self.node(block, indentless_sequence)
+    }
+
+    /// Parse the start of a node, then return to the state on top of the stack once it's done
+    fn node(&mut self, block: bool, indentless_sequence: bool) -> Result<Event, FailureType> {
+        use TokenKind as T;
+        if let Token(T::Alias(name), mark) = self.scanner.peek_token()? {
+            let event = Event::Alias(name.clone(), *mark);
+            self.scanner.next_token()?;
+            self.pop_state();
+            return Ok(event);
+        }
+
+        let (mut anchor, mut tagged) = (None, false);
+        loop {
+            match self.peek_kind()? {
+                T::Anchor(name) if anchor.is_none() => anchor = Some(name),
+                T::Tag(handle) if !tagged => {
+                    if handle.map_or(false, |handle|
+                            handle != "!!" && !self.tag_handles.contains(&handle)) {
+                        return Err(self.error("Tag uses a handle with no %TAG directive"));
+                    }
+                    tagged = true;
+                },
+                _ => break,
+            }
+            self.scanner.next_token()?;
+        }
+
+        self.state = match self.peek_kind()? {
+            T::BlockEntry if indentless_sequence => State::IndentlessSequenceEntry,
+            T::Scalar => {
+                self.scanner.next_token()?;
+                self.pop_state();
+                return Ok(Event::Scalar(anchor));
+            },
+            T::FlowSequenceStart => State::FlowSequenceFirstEntry,
+            T::FlowMappingStart => State::FlowMappingFirstKey,
+            T::BlockSequenceStart if block => State::BlockSequenceFirstEntry,
+            T::BlockMappingStart if block => State::BlockMappingFirstKey,
+            _ if anchor.is_some() || tagged => {
+                self.pop_state();
+                return Ok(Event::Scalar(anchor));
+            },
+            _ => return Err(self.error("Did not find expected node content")),
+        };
+        Ok(Event::CollectionStart(anchor))
+    }
+}
+
+/// Tallies how many nodes each anchor stands for, to catch aliases which expand too far
+#[derive(Default)]
+struct AliasCounter {
+    /// How many nodes each anchor in the current document expands to
+    anchors: HashMap<String, u64>,
+    /// The anchor and running node count of each collection which hasn't ended yet
+    open: Vec<(Option<String>, u64)>,
+    /// How many nodes the aliases in the current document have expanded to so far
+    expanded: u64,
+}
+
+impl AliasCounter {
+    /// Record a node of `size` nodes, and the anchor it was given, if any
+    fn add_node(&mut self, anchor: Option<String>, size: u64) {
+        if let Some(name) = anchor {
+            self.anchors.insert(name, size);
+        }
+        if let Some((_, parent_size)) = self.open.last_mut() {
+            *parent_size = parent_size.saturating_add(size);
+        }
+    }
+
+    /// Account for an event from the [`Parser`]
+    fn event(&mut self, event: Event) -> Result<(), FailureType> {
+        match event {
+            Event::DocumentStart => *self = Self::default(),
+            Event::Scalar(anchor) => self.add_node(anchor, 1),
+            Event::CollectionStart(anchor) => {
+                // Register the anchor immediately so recursive aliases resolve
+                if let Some(name) = &anchor {
+                    self.anchors.insert(name.clone(), 1);
+                }
+                self.open.push((anchor, 1));
+            },
+            Event::CollectionEnd => {
+                if let Some((anchor, size)) = self.open.pop() {
+                    self.add_node(anchor, size);
+                }
+            },
+            Event::Alias(name, mark) => {
+                let size = *self.anchors.get(&name).ok_or_else(|| syntax_error(
+                    &format!("Alias refers to undefined anchor {:?}", name), mark))?;
+                self.expanded = self.expanded.saturating_add(size);
+                if self.expanded > ALIAS_EXPANSION_LIMIT {
+                    return Err(FailureType::InternalError(format!(
+                        "YAML aliases expand to more than {} nodes", ALIAS_EXPANSION_LIMIT)));
+                }
+                self.add_node(None, size);
+            },
+            Event::StreamEnd | Event::Other => {},
+        }
+        Ok(())
+    }
+}
+
+/// Read all of `input` as UTF-8, refusing to read more than [`MAX_SIZE`] bytes
+pub fn read_limited(input: impl Read) -> Result<String, FailureType> {
+    let mut data = Vec::new();
+    input.take(MAX_SIZE + 1).read_to_end(&mut data)
+        .map_err(|err| FailureType::IoError(err.to_string()))?;
+    if data.len() as u64 > MAX_SIZE {
+        return Err(FailureType::UnsupportedFormat(format!(
+            "Refusing to parse a YAML stream larger than {} MiB", MAX_SIZE / 1024 / 1024)));
+    }
+    String::from_utf8(data).map_err(|err| FailureType::InvalidContent(err.to_string()))
+}
+
+/// Check that `input` is a well-formed stream of zero or more YAML documents
+///
+/// Syntax errors (including aliases to undefined anchors) are reported as
+/// [`FailureType::InvalidContent`], while aliases which expand past [`ALIAS_EXPANSION_LIMIT`]
+/// nodes are reported as [`FailureType::InternalError`] since they say nothing about corruption.
+pub fn verify(input: &str) -> Result<(), FailureType> {
+    for (line, text) in input.split('\n').enumerate() {
+        if let Some((col, chr)) = text.chars().enumerate().find(|&(_, chr)| !is_printable(chr)) {
+            let message = format!("Found non-printable character U+{:04X}", u32::from(chr));
+            return Err(syntax_error(&message, Mark { index: 0, line, col }));
+        }
+    }
+
+    let mut parser = Parser::new(input);
+    let mut counter = AliasCounter::default();
+    loop {
+        match parser.next_event()? {
+            Event::StreamEnd => return Ok(()),
+            event => counter.event(event)?,
+        }
+    }
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Verify `input`, returning a simplified result
+    fn check(input: &str) -> Result<(), &'static str> {
+        verify(input).map_err(|err| match err {
+            FailureType::InvalidContent(_) => "invalid",
+            FailureType::InternalError(_) => "internal",
+            _ => "other",
+        })
+    }
+
+    #[test]
+    fn test_valid_documents() {
+        for input in &[
+            "",
+            "# Just a comment\n",
+            "scalar",
+            "key: value\nlist:\n  - a\n  - b: c\n    d: e\nindentless:\n- x\n- y\n",
+            "a: {b: [1, 2, {c: d}], 'e': \"f\", g: }\nh: [i, j: k, ? l : m]\n",
+            "{\"json\":1,\"style\":[true,null]}",
+            "literal: |\n  line 1\n\n  line 2\nfolded: >-\n  text\n  more\nnext: 1\n",
+            "keep: |+2\n    indented\n\nafter: x\n",
+            "? complex key\n: complex value\n? [a, b]\n: c\n",
+            "base: &base {a: 1}\nderived:\n  <<: *base\n  b: !!str 2\n",
+            "%YAML 1.2\n%TAG !e! tag:example.com,2000:\n---\n!e!foo bar\n...\n",
+            "--- first\n--- second\n...\n---\nthird: 3\n...\n",
+            "plain scalar\n  over several\n  lines\n",
+            "'single '' quoted\n  on lines'\n",
+            "\"escapes \\x41 \\u00e9 \\U0001F600 \\\n  continued\"\n",
+            "- - nested\n  - seq\n- &anchor value\n- *anchor\n",
+            "\u{FEFF}key: value # with BOM\n",
+            "url: http://example.com:8080/path\ntime: 12:30:00\n",
+            "empty:\nalso empty:\n",
+            "- !!map {}\n- !<tag:yaml.org,2002:str> x\n- &a\n- ! y\n- !local%20tag z\n",
+            "&anchor: value\n",
+        ] {
+            assert_eq!(check(input), Ok(()), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_invalid_documents() {
+        for input in &[
+            "key: value: other\n",
+            "a: 1\n b: 2\n",
+            "list:\n  - a\n  b: c\n",
+            "[unterminated, sequence\n",
+            "{unterminated: mapping\n",
+            "[a, b]]\n",
+            "\"unterminated\n",
+            "'unterminated",
+            "\"bad \\q escape\"\n",
+            "\"bad \\xZZ escape\"\n",
+            "key:\n\t- tab indented\n",
+            "@reserved\n",
+            "- a\nb: c\n",
+            "*undefined\n",
+            "---\nkey: value\n%YAML 1.2\n",
+            "first\nsecond: doc\n",
+            "text \u{0} with NUL\n",
+            "key: value\n\"quoted\n---\nx\"\n",
+            "%YAML one\n--- x\n",
+            "literal: |0\n  x\n",
+            "--- a: b\n",
+            "literal: |# comment\n  x\n",
+            "!e!foo bar\n",
+            "%TAG !e! tag:a,2000:\n--- !e!x a\n--- !e!x b\n",
+            "%TAG !e! tag:a,2000:\n%TAG !e! tag:b,2000:\n--- x\n",
+            "!bad\"tag x\n",
+            "!bad%zzescape x\n",
+        ] {
+            assert_eq!(check(input), Err("invalid"), "{:?}", input);
+        }
+        assert_eq!(check("%YAML 2.0\n--- x\n"), Err("other"));
+    }
+
+    #[test]
+    fn test_alias_expansion() {
+        let mut laughs = "a0: &a0 [lol, lol, lol, lol, lol, lol, lol, lol, lol, lol]\n".to_owned();
+        for level in 1..5 {
+            let refs = vec![format!("*a{}", level - 1); 10].join(", ");
+            laughs.push_str(&format!("a{}: &a{} [{}]\n", level, level, refs));
+        }
+        assert_eq!(check(&laughs), Ok(()));
+
+        for level in 5..10 {
+            let refs = vec![format!("*a{}", level - 1); 10].join(", ");
+            laughs.push_str(&format!("a{}: &a{} [{}]\n", level, level, refs));
+        }
+        assert_eq!(check(&laughs), Err("internal"));
+
+        // Anchors don't carry over between documents
+        assert_eq!(check("&a x\n--- *a\n"), Err("invalid"));
+    }
+
+    #[test]
+    fn test_yaml_fixtures() {
+        let good = include_str!("../../../test_data/good/testfile.yaml");
+        let bad = include_str!("../../../test_data/bad/testfile.yaml");
+        assert_eq!(check(good), Ok(()));
+        assert_eq!(check(bad), Err("invalid"));
+    }
+
+    #[test]
+    fn test_size_limit() {
+        assert_eq!(read_limited(&b"a: 1\n"[..]).unwrap(), "a: 1\n");
+        assert!(matches!(read_limited(&b"a: \xFF\n"[..]), Err(FailureType::InvalidContent(_))));
+        assert!(matches!(read_limited(std::io::repeat(b'#')),
+                         Err(FailureType::UnsupportedFormat(_))));
+    }
+}
@@ -0,0 +1,625 @@
+//! Checks of the Zip records which the `zip` crate reads but doesn't cross-check
+//!
+//! (So far, that's the end of central directory record and, for archives which have them, the
+//! Zip64 end of central directory record and its locator, the agreement between each central
+//! directory record and the local file header it points to, plus telling apart the kinds of
+//! encryption which keep entries from being checked.)
+
+// Standard library imports
+use std::convert::TryFrom;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+
+// Local Imports
+use super::{corrupt, unsupported};
+
+/// The signature of the end of central directory record
+const EOCD_SIGNATURE: [u8; 4] = *b"PK\x05\x06";
+
+/// The signature of the Zip64 end of central directory locator
+const ZIP64_LOCATOR_SIGNATURE: [u8; 4] = *b"PK\x06\x07";
+
+/// The signature of the Zip64 end of central directory record
+const ZIP64_EOCD_SIGNATURE: [u8; 4] = *b"PK\x06\x06";
+
+/// The signature of a central directory file header
+const CENTRAL_HEADER_SIGNATURE: [u8; 4] = *b"PK\x01\x02";
+
+/// The signature of a local file header
+const LOCAL_HEADER_SIGNATURE: [u8; 4] = *b"PK\x03\x04";
+
+/// The optional signature of a data descriptor
+const DATA_DESCRIPTOR_SIGNATURE: [u8; 4] = *b"PK\x07\x08";
+
+/// The length of the end of central directory record, not counting the comment
+const EOCD_LEN: u64 = 22;
+
+/// The length of the Zip64 end of central directory locator
+const ZIP64_LOCATOR_LEN: u64 = 20;
+
+/// The length of the Zip64 end of central directory record, not counting extensible data
+const ZIP64_EOCD_LEN: u64 = 56;
+
+/// What the Zip64 record's "size of record" field counts for a record with no extensible data
+///
+/// (It excludes the signature and the field itself.)
+const ZIP64_EOCD_MIN_SIZE: u64 = ZIP64_EOCD_LEN - 12;
+
+/// The length of a central directory file header, not counting its variable-length fields
+const CENTRAL_HEADER_LEN: usize = 46;
+
+/// The length of a local file header, not counting its variable-length fields
+const LOCAL_HEADER_LEN: usize = 30;
+
+/// The general purpose flag bit which means the CRC and sizes follow the data in a data descriptor
+const DATA_DESCRIPTOR_FLAG: u64 = 1 << 3;
+
+/// The placeholder a 32-bit size or offset holds when the real value is in the Zip64 extra field
+const ZIP64_PLACEHOLDER: u64 = 0xFFFF_FFFF;
+
+/// The ID of the extra field which holds an entry's Zip64 sizes and offset
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+
+/// The ID of the extra field which WinZip's AES encryption adds to each entry
+const AES_EXTRA_ID: u16 = 0x9901;
+
+/// Where the end of central directory record(s) say the central directory is
+#[derive(Debug, PartialEq, Eq)]
+pub struct CentralDirectory {
+    /// The number of entries in the archive
+    pub entries: u64,
+    /// The recorded offset of the central directory
+    pub offset: u64,
+    /// The recorded size of the central directory
+    pub size: u64,
+    /// Where the central directory actually ends (the start of the record which follows it)
+    pub end: u64,
+    /// Whether the values came from a Zip64 end of central directory record
+    pub is_zip64: bool,
+}
+
+/// Read a little-endian integer of up to eight bytes
+fn le_uint(bytes: &[u8]) -> u64 {
+    bytes.iter().rev().fold(0, |acc, &x| (acc << 8) | u64::from(x))
+}
+
+/// Read `N` bytes at `offset`
+fn read_at<R: Read + Seek, const N: usize>(input: &mut R, offset: u64) -> io::Result<[u8; N]> {
+    let mut data = [0; N];
+    input.seek(SeekFrom::Start(offset))?;
+    input.read_exact(&mut data)?;
+    Ok(data)
+}
+
+/// Check that a field of the end of central directory record either agrees with its Zip64
+/// counterpart or holds the all-ones placeholder which defers to it
+fn check_field(name: &str, short: u64, long: u64, placeholder: u64) -> io::Result<()> {
+    if short == placeholder || short == long {
+        Ok(())
+    } else {
+        Err(corrupt(&format!("Zip end of central directory record disagrees with its Zip64 \
+                              counterpart about the {}", name)))
+    }
+}
+
+/// Find the end of central directory record, returning its offset and contents
+///
+/// (Of the candidates whose comment fits in the file, the last is used, as the `zip` crate does.)
+fn find_eocd<R: Read + Seek>(input: &mut R, file_len: u64) -> io::Result<Option<(u64, Vec<u8>)>> {
+    let tail_len = file_len.min(EOCD_LEN + 0xFFFF);
+    let mut tail = vec![0; usize::try_from(tail_len).expect("no more than 64KiB")];
+    input.seek(SeekFrom::Start(file_len - tail_len))?;
+    input.read_exact(&mut tail)?;
+
+    let last_start = tail.len().saturating_sub(EOCD_LEN as usize - 1);
+    Ok((0..last_start).rev()
+        .find(|&pos| {
+            tail[pos..pos + 4] == EOCD_SIGNATURE
+                && pos as u64 + EOCD_LEN + le_uint(&tail[pos + 20..pos + 22]) <= tail_len
+        })
+        .map(|pos| (file_len - tail_len + pos as u64, tail[pos..pos + EOCD_LEN as usize].to_vec())))
+}
+
+/// Locate the central directory, checking the Zip64 records against the end of central directory
+/// record and the size of the file if there are any
+///
+/// Returns `None` if there's no recognizable end of central directory record, leaving it to the
+/// `zip` crate to report that the file isn't a Zip file.
+pub fn locate_central_directory<R: Read + Seek>(input: &mut R, file_len: u64)
+        -> io::Result<Option<CentralDirectory>> {
+    let (eocd_offset, eocd) = match find_eocd(input, file_len)? {
+        Some(found) => found,
+        None => return Ok(None),
+    };
+    let field = |start: usize, len: usize| le_uint(&eocd[start..start + len]);
+    let mut directory = CentralDirectory {
+        entries: field(10, 2),
+        offset: field(16, 4),
+        size: field(12, 4),
+        end: eocd_offset,
+        is_zip64: false,
+    };
+
+    // Without a locator right before the record, this isn't a Zip64 archive
+    let locator_offset = match eocd_offset.checked_sub(ZIP64_LOCATOR_LEN) {
+        Some(offset) => offset,
+        None => return Ok(Some(directory)),
+    };
+    let locator: [u8; ZIP64_LOCATOR_LEN as usize] = read_at(input, locator_offset)?;
+    if locator[..4] != ZIP64_LOCATOR_SIGNATURE {
+        return Ok(Some(directory));
+    }
+    if le_uint(&locator[4..8]) != 0 || le_uint(&locator[16..20]) > 1 {
+        return Err(unsupported("Multi-disk Zip64 archives aren't supported"));
+    }
+
+    let record_offset = le_uint(&locator[8..16]);
+    if record_offset.saturating_add(ZIP64_EOCD_LEN) > locator_offset {
+        return Err(corrupt("Zip64 end of central directory locator points past the end of the \
+                            space available for the record"));
+    }
+    let record: [u8; ZIP64_EOCD_LEN as usize] = read_at(input, record_offset)?;
+    if record[..4] != ZIP64_EOCD_SIGNATURE {
+        return Err(corrupt("No Zip64 end of central directory record where its locator points"));
+    }
+    let record_size = le_uint(&record[4..12]);
+    if record_size < ZIP64_EOCD_MIN_SIZE || record_offset + 12 + record_size != locator_offset {
+        return Err(corrupt("Zip64 end of central directory record's size doesn't match the space \
+                            between it and its locator"));
+    }
+    if le_uint(&record[16..20]) != 0 || le_uint(&record[20..24]) != 0 {
+        return Err(unsupported("Multi-disk Zip64 archives aren't supported"));
+    }
+    let entries_on_disk = le_uint(&record[24..32]);
+    directory = CentralDirectory {
+        entries: le_uint(&record[32..40]),
+        offset: le_uint(&record[48..56]),
+        size: le_uint(&record[40..48]),
+        end: record_offset,
+        is_zip64: true,
+    };
+    if entries_on_disk != directory.entries {
+        return Err(corrupt("Zip64 end of central directory record disagrees with itself about the \
+                            number of entries"));
+    }
+    if directory.offset.checked_add(directory.size).map_or(true, |end| end > record_offset) {
+        return Err(corrupt("Zip64 central directory overlaps or runs past the Zip64 end of \
+                            central directory record"));
+    }
+
+    check_field("number of entries on this disk", field(8, 2), entries_on_disk, 0xFFFF)?;
+    check_field("number of entries", field(10, 2), directory.entries, 0xFFFF)?;
+    check_field("central directory size", field(12, 4), directory.size, 0xFFFF_FFFF)?;
+    check_field("central directory offset", field(16, 4), directory.offset, 0xFFFF_FFFF)?;
+    Ok(Some(directory))
+}
+
+/// Find the contents of the extra field with the given ID, if present
+///
+/// (A field which claims to be longer than what's left is returned truncated.)
+fn extra_field(extra: &[u8], id: u16) -> Option<&[u8]> {
+    let mut fields = extra;
+    while fields.len() >= 4 {
+        let len = usize::try_from(le_uint(&fields[2..4])).expect("16 bits");
+        let (header, rest) = fields.split_at(4);
+        if le_uint(&header[..2]) == u64::from(id) {
+            return Some(&rest[..len.min(rest.len())]);
+        }
+        fields = rest.get(len..).unwrap_or_default();
+    }
+    None
+}
+
+/// The fields of a central directory record which its local file header should agree with
+struct Entry {
+    /// The file name, as stored
+    name: Vec<u8>,
+    /// The compression method
+    method: u64,
+    /// The CRC-32 of the uncompressed data
+    crc: u64,
+    /// The size of the data as stored
+    compressed_size: u64,
+    /// The size of the data once decompressed
+    size: u64,
+    /// The recorded offset of the local file header
+    offset: u64,
+}
+
+impl Entry {
+    /// Format an error about this entry for a report
+    fn error(&self, message: &str) -> io::Error {
+        corrupt(&format!("Zip entry {:?}: {}", String::from_utf8_lossy(&self.name), message))
+    }
+
+    /// Format an error about a `what` which disagrees with the central directory about `field`
+    fn mismatch(&self, what: &str, field: &str) -> io::Error {
+        self.error(&format!("{} disagrees with the central directory about the {}", what, field))
+    }
+}
+
+/// Replace each placeholder in `values` with the next value from a Zip64 extra field
+fn apply_zip64_extra(entry: &Entry, extra: &[u8], values: &mut [&mut u64]) -> io::Result<()> {
+    let mut data = extra_field(extra, ZIP64_EXTRA_ID).unwrap_or_default();
+    for value in values.iter_mut().filter(|x| ***x == ZIP64_PLACEHOLDER) {
+        if data.len() < 8 {
+            return Err(entry.error("Zip64 extra field is missing a value its header defers to"));
+        }
+        **value = le_uint(&data[..8]);
+        data = &data[8..];
+    }
+    Ok(())
+}
+
+/// Read the central directory records
+fn read_central_directory<R: Read + Seek>(input: &mut R, start: u64, directory: &CentralDirectory)
+        -> io::Result<Vec<Entry>> {
+    input.seek(SeekFrom::Start(start))?;
+    let mut records = BufReader::new(input.take(directory.size));
+    let mut entries = Vec::new();
+    for index in 0..directory.entries {
+        let mut header = [0; CENTRAL_HEADER_LEN];
+        let mut read = |buf: &mut [u8]| records.read_exact(buf).map_err(|err| {
+            if err.kind() == io::ErrorKind::UnexpectedEof {
+                corrupt("Zip central directory is too short to hold as many entries as the end \
+                         of central directory record says it has")
+            } else {
+                err
+            }
+        });
+        read(&mut header)?;
+        if header[..4] != CENTRAL_HEADER_SIGNATURE {
+            return Err(corrupt(&format!("Zip central directory record {} has no signature",
+                                        index)));
+        }
+        let field = |start: usize, len: usize| le_uint(&header[start..start + len]);
+        let mut name = vec![0; usize::try_from(field(28, 2)).expect("16 bits")];
+        let mut extra = vec![0; usize::try_from(field(30, 2)).expect("16 bits")];
+        let mut comment = vec![0; usize::try_from(field(32, 2)).expect("16 bits")];
+        read(&mut name)?;
+        read(&mut extra)?;
+        read(&mut comment)?;
+
+        let mut entry = Entry {
+            name,
+            method: field(10, 2),
+            crc: field(16, 4),
+            compressed_size: field(20, 4),
+            size: field(24, 4),
+            offset: field(42, 4),
+        };
+        let (mut size, mut compressed_size, mut offset) =
+            (entry.size, entry.compressed_size, entry.offset);
+        apply_zip64_extra(&entry, &extra, &mut [&mut size, &mut compressed_size, &mut offset])?;
+        entry.size = size;
+        entry.compressed_size = compressed_size;
+        entry.offset = offset;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Check a local file header against its central directory record, returning where the entry
+/// (including any data descriptor) ends
+///
+/// `start` is where the header should be and `limit` is where the central directory starts.
+fn check_local_header<R: Read + Seek>(input: &mut R, entry: &Entry, start: u64, limit: u64)
+        -> io::Result<u64> {
+    let no_header = || entry.error("no local file header at the offset the central directory \
+                                    records");
+    if start + LOCAL_HEADER_LEN as u64 > limit {
+        return Err(no_header());
+    }
+    let header: [u8; LOCAL_HEADER_LEN] = read_at(input, start)?;
+    if header[..4] != LOCAL_HEADER_SIGNATURE {
+        return Err(no_header());
+    }
+    let field = |start: usize, len: usize| le_uint(&header[start..start + len]);
+    let data_start = start + LOCAL_HEADER_LEN as u64 + field(26, 2) + field(28, 2);
+    let data_end = data_start.saturating_add(entry.compressed_size);
+    if data_end > limit {
+        return Err(entry.error("data runs into the central directory"));
+    }
+
+    let mut name = vec![0; usize::try_from(field(26, 2)).expect("16 bits")];
+    let mut extra = vec![0; usize::try_from(field(28, 2)).expect("16 bits")];
+    input.read_exact(&mut name)?;
+    input.read_exact(&mut extra)?;
+    if name != entry.name {
+        return Err(entry.mismatch("local file header", "file name"));
+    }
+    if field(8, 2) != entry.method {
+        return Err(entry.mismatch("local file header", "compression method"));
+    }
+
+    // The local header must hold both sizes in its Zip64 extra field if it has one, but some
+    // writers only saturate the one which needs it, so take the field's layout as authoritative
+    let (mut size, mut compressed_size) = (field(22, 4), field(18, 4));
+    let zip64 = extra_field(&extra, ZIP64_EXTRA_ID);
+    match zip64 {
+        Some(data) if data.len() >= 16 && (size == ZIP64_PLACEHOLDER
+                                           || compressed_size == ZIP64_PLACEHOLDER) => {
+            if size == ZIP64_PLACEHOLDER {
+                size = le_uint(&data[..8]);
+            }
+            if compressed_size == ZIP64_PLACEHOLDER {
+                compressed_size = le_uint(&data[8..16]);
+            }
+        },
+        _ => apply_zip64_extra(entry, &extra, &mut [&mut size, &mut compressed_size])?,
+    }
+
+    // With a data descriptor, the local header may leave the CRC and sizes zeroed
+    let deferred = field(6, 2) & DATA_DESCRIPTOR_FLAG != 0;
+    for &(name, local, central) in &[("CRC-32", field(14, 4), entry.crc),
+                                     ("compressed size", compressed_size, entry.compressed_size),
+                                     ("uncompressed size", size, entry.size)] {
+        if local != central && !(deferred && local == 0) {
+            return Err(entry.mismatch("local file header", name));
+        }
+    }
+    if !deferred {
+        return Ok(data_end);
+    }
+
+    // The descriptor's signature is optional and its sizes are 64-bit in Zip64 entries
+    let sizes_len = if zip64.is_some() { 16 } else { 8 };
+    let mut descriptor = [0; 4 + 4 + 16];
+    let available = usize::try_from((limit - data_end).min(descriptor.len() as u64))
+        .expect("no more than 24");
+    input.seek(SeekFrom::Start(data_end))?;
+    input.read_exact(&mut descriptor[..available])?;
+    let skip = if descriptor[..4] == DATA_DESCRIPTOR_SIGNATURE { 4 } else { 0 };
+    let descriptor_len = skip + 4 + sizes_len;
+    if descriptor_len > available {
+        return Err(entry.error("data descriptor runs into the central directory"));
+    }
+    let half = sizes_len / 2;
+    let field = |start: usize, len: usize| le_uint(&descriptor[skip + start..skip + start + len]);
+    for &(name, local, central) in &[("CRC-32", field(0, 4), entry.crc),
+                                     ("compressed size", field(4, half), entry.compressed_size),
+                                     ("uncompressed size", field(4 + half, half), entry.size)] {
+        if local != central {
+            return Err(entry.mismatch("data descriptor", name));
+        }
+    }
+    Ok(data_end + descriptor_len as u64)
+}
+
+/// Check each central directory record against the local file header it points to, and that the
+/// entries neither overlap nor leave unaccounted-for data before the central directory
+///
+/// (Any data before the first entry is allowed, as long as the central directory's recorded
+/// offset accounts for it, since that's how self-extracting archives are built.)
+pub fn check_local_headers<R: Read + Seek>(input: &mut R, directory: &CentralDirectory)
+        -> io::Result<()> {
+    let start = directory.end.checked_sub(directory.size)
+        .ok_or_else(|| corrupt("Zip central directory is larger than the space before the end \
+                                of central directory record"))?;
+    let prefix_len = start.checked_sub(directory.offset)
+        .ok_or_else(|| corrupt("Zip central directory starts before its recorded offset"))?;
+
+    let mut entries = read_central_directory(input, start, directory)?;
+    entries.sort_by_key(|entry| entry.offset);
+    let mut previous: Option<(&Entry, u64)> = None;
+    for entry in &entries {
+        let offset = entry.offset.saturating_add(prefix_len);
+        if let Some((other, end)) = previous {
+            if offset < end {
+                return Err(corrupt(&format!("Zip entries {:?} and {:?} overlap",
+                                            String::from_utf8_lossy(&other.name),
+                                            String::from_utf8_lossy(&entry.name))));
+            }
+        }
+        previous = Some((entry, check_local_header(input, entry, offset, start)?));
+    }
+
+    match previous {
+        Some((_, end)) if end < start => Err(corrupt(&format!(
+            "{} bytes of unaccounted-for data between the last Zip entry and the central \
+             directory", start - end))),
+        _ => Ok(()),
+    }
+}
+
+/// A tally of the entries which couldn't be checked because they're encrypted
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct EncryptedEntries {
+    /// Entries encrypted with the traditional PKWARE scheme
+    pub zip_crypto: usize,
+    /// Entries encrypted with WinZip's AES scheme
+    pub aes: usize,
+}
+
+impl EncryptedEntries {
+    /// Count an encrypted entry, given its central directory extra field
+    pub fn add(&mut self, extra: &[u8]) {
+        if extra_field(extra, AES_EXTRA_ID).is_some() {
+            self.aes += 1;
+        } else {
+            self.zip_crypto += 1;
+        }
+    }
+
+    /// How many entries have been counted
+    pub fn total(&self) -> usize {
+        self.zip_crypto + self.aes
+    }
+
+    /// Describe the tally for a report, given how many entries the archive has in all
+    pub fn describe(&self, entries: usize) -> String {
+        format!("{} of {} Zip entries are encrypted ({} with ZipCrypto, {} with AES) and \
+                 couldn't be checked", self.total(), entries, self.zip_crypto, self.aes)
+    }
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A two-entry archive with forced Zip64 records, built by `make_test_zip64.py`
+    const ZIP64: &[u8] = include_bytes!("../../../test_data/good/testfile.zip64.zip");
+
+    /// Locate the central directory in `data`, returning the error message on failure
+    fn locate(data: &[u8]) -> Result<Option<CentralDirectory>, String> {
+        let len = data.len() as u64;
+        locate_central_directory(&mut io::Cursor::new(data), len).map_err(|err| err.to_string())
+    }
+
+    /// Find where the Zip64 end of central directory record starts in `data`
+    fn zip64_start(data: &[u8]) -> usize {
+        data.windows(4).rposition(|x| x == ZIP64_EOCD_SIGNATURE).unwrap()
+    }
+
+    #[test]
+    fn test_plain_archives() {
+        assert_eq!(locate(b"Not a Zip file"), Ok(None));
+
+        let mut empty = EOCD_SIGNATURE.to_vec();
+        empty.resize(EOCD_LEN as usize, 0);
+        let expected = CentralDirectory { entries: 0, offset: 0, size: 0, end: 0, is_zip64: false };
+        assert_eq!(locate(&empty), Ok(Some(expected)));
+
+        // A comment containing something that looks like another record must not be mistaken for
+        // the real one
+        let mut commented = empty.clone();
+        commented[8] = 1;
+        commented[10] = 1;
+        commented[20] = 22;
+        let mut decoy = empty.clone();
+        decoy[20] = 5;
+        commented.extend(&decoy);
+        assert_eq!(locate(&commented).unwrap().unwrap().entries, 1);
+
+        let plain = include_bytes!("../../../test_data/good/testfile.zip");
+        let directory = locate(plain).unwrap().unwrap();
+        assert_eq!((directory.entries, directory.is_zip64), (1, false));
+    }
+
+    #[test]
+    fn test_zip64() {
+        let directory = locate(ZIP64).unwrap().unwrap();
+        assert_eq!((directory.entries, directory.is_zip64), (2, true));
+        assert_eq!(directory.offset + directory.size, zip64_start(ZIP64) as u64);
+
+        // The short record may defer to the Zip64 one with all-ones placeholders
+        let mut saturated = ZIP64.to_vec();
+        let eocd = saturated.len() - EOCD_LEN as usize;
+        saturated[eocd + 8..eocd + 20].copy_from_slice(&[0xFF; 12]);
+        assert_eq!(locate(&saturated).unwrap().unwrap(), directory);
+
+        let bad = include_bytes!("../../../test_data/bad/testfile.zip64.zip");
+        assert!(locate(bad).unwrap_err().contains("overlaps or runs past"));
+    }
+
+    #[test]
+    fn test_zip64_damage() {
+        let start = zip64_start(ZIP64);
+        let eocd = ZIP64.len() - EOCD_LEN as usize;
+        let locator = eocd - ZIP64_LOCATOR_LEN as usize;
+        let damaged = |offset: usize, value: u8| {
+            let mut data = ZIP64.to_vec();
+            data[offset] = value;
+            locate(&data).unwrap_err()
+        };
+
+        assert!(damaged(locator + 8, 0xFF).contains("points past"));
+        assert!(damaged(locator + 8, 1).starts_with("No Zip64 end of central directory record"));
+        assert!(damaged(start + 4, 0x2D).contains("size doesn't match"));
+        assert!(damaged(start + 24, 3).contains("disagrees with itself"));
+        assert!(damaged(eocd + 10, 3).ends_with("about the number of entries"));
+        assert!(damaged(eocd + 12, 0).ends_with("about the central directory size"));
+
+        let mut data = ZIP64.to_vec();
+        data[locator + 16] = 2;
+        let err = locate_central_directory(&mut io::Cursor::new(&data), data.len() as u64)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    }
+
+    /// Locate the central directory in `data` and check the local headers against it
+    fn check(data: &[u8]) -> Result<(), String> {
+        let mut input = io::Cursor::new(data);
+        let directory = locate_central_directory(&mut input, data.len() as u64)
+            .map_err(|err| err.to_string())?
+            .expect("a Zip file");
+        check_local_headers(&mut input, &directory).map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn test_local_headers() {
+        let plain = include_bytes!("../../../test_data/good/testfile.zip");
+        assert_eq!(check(plain), Ok(()));
+        assert_eq!(check(ZIP64), Ok(()));
+
+        // Data descriptors, with and without Zip64 sizes and signatures
+        assert_eq!(check(include_bytes!("../../../test_data/good/testfile.epub")), Ok(()));
+        assert_eq!(check(include_bytes!("../../../test_data/good/testfile.jar")), Ok(()));
+        assert_eq!(check(include_bytes!("../../../test_data/good/testfile.aes.zip")), Ok(()));
+
+        // Prepended data is fine as long as the central directory's recorded offset allows for it
+        let mut prefixed = b"#!/bin/sh\n".to_vec();
+        prefixed.extend(&plain[..]);
+        assert_eq!(check(&prefixed), Ok(()));
+
+        let bad = include_bytes!("../../../test_data/bad/testfile.mismatch.zip");
+        assert_eq!(check(bad).unwrap_err(), "Zip entry \"testfile.txt\": local file header \
+                                             disagrees with the central directory about the file \
+                                             name");
+    }
+
+    #[test]
+    fn test_local_header_damage() {
+        let plain = include_bytes!("../../../test_data/good/testfile.zip");
+        let damaged = |data: &[u8], offset: usize| {
+            let mut data = data.to_vec();
+            data[offset] ^= 1;
+            check(&data).unwrap_err()
+        };
+
+        assert!(damaged(plain, 0).ends_with("no local file header at the offset the central \
+                                              directory records"));
+        assert!(damaged(plain, 8).ends_with("about the compression method"));
+        assert!(damaged(plain, 14).ends_with("about the CRC-32"));
+        assert!(damaged(plain, 18).ends_with("about the compressed size"));
+        assert!(damaged(plain, 22).ends_with("about the uncompressed size"));
+        assert!(damaged(plain, 26).ends_with("data runs into the central directory"));
+
+        let epub = include_bytes!("../../../test_data/good/testfile.epub");
+        let descriptor = epub.windows(4).position(|x| x == DATA_DESCRIPTOR_SIGNATURE).unwrap();
+        assert!(damaged(epub, descriptor + 4).ends_with("data descriptor disagrees with the \
+                                                          central directory about the CRC-32"));
+
+        // Pointing the second entry at the first
+        let second = ZIP64.windows(4).rposition(|x| x == CENTRAL_HEADER_SIGNATURE).unwrap();
+        let mut overlapping = ZIP64.to_vec();
+        let extra_start = second + CENTRAL_HEADER_LEN + usize::from(ZIP64[second + 28]);
+        overlapping[extra_start + 4 + 16..extra_start + 4 + 24].copy_from_slice(&[0; 8]);
+        assert_eq!(check(&overlapping).unwrap_err(),
+                   "Zip entries \"testfile.txt\" and \"stored.txt\" overlap");
+
+        // Unaccounted-for data between the last entry and the central directory
+        let eocd = plain.len() - EOCD_LEN as usize;
+        let cd_offset = usize::try_from(le_uint(&plain[eocd + 16..eocd + 20])).unwrap();
+        let mut gap = plain[..cd_offset].to_vec();
+        gap.extend(b"junk");
+        gap.extend(&plain[cd_offset..]);
+        gap[eocd + 4 + 16] += 4;
+        assert_eq!(check(&gap).unwrap_err(), "4 bytes of unaccounted-for data between the last \
+                                              Zip entry and the central directory");
+    }
+
+    #[test]
+    fn test_encrypted_entries() {
+        let mut encrypted = EncryptedEntries::default();
+        encrypted.add(b"");
+        encrypted.add(b"UT\x05\0\x03\0\0\0\0");
+        encrypted.add(b"UT\x05\0\x03\0\0\0\0\x01\x99\x07\0\x02\0AE\x03\x08\0");
+        encrypted.add(b"\x01\x99\x07\0\x02\0AE\x03\x08\0");
+        encrypted.add(b"UT\xFF\0\x01\x99");
+        assert_eq!(encrypted, EncryptedEntries { zip_crypto: 3, aes: 2 });
+        assert_eq!(encrypted.describe(7), "5 of 7 Zip entries are encrypted (3 with ZipCrypto, \
+                                           2 with AES) and couldn't be checked");
+    }
+}
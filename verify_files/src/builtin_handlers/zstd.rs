@@ -0,0 +1,933 @@
+//! A minimal Zstandard decompressor, following RFC 8878
+//!
+//! (Just enough to verify `.zst` files without a C dependency. Frames which need a dictionary are
+//! reported as `ErrorKind::Unsupported` so an external tool which has it can take over.)
+
+// Standard library imports
+use std::convert::TryFrom;
+use std::io::{self, BufRead, Read};
+
+// Local Imports
+use super::bytes::low32;
+use super::checksums::Xxh64;
+use super::{corrupt, eof_is_corrupt, unsupported};
+
+/// The magic number at the start of every Zstandard frame
+const FRAME_MAGIC: u32 = 0xFD2F_B528;
+
+/// The magic number of skippable frames, ignoring the low four bits
+const SKIPPABLE_MAGIC: u32 = 0x184D_2A50;
+
+/// The most data a single block may decompress to
+const MAX_BLOCK_SIZE: usize = 128 << 10;
+
+/// The largest window this decoder is willing to allocate memory for
+///
+/// (The same as `zstd --long=31`, which needs an explicit `--memory` to decompress.)
+const MAX_WINDOW_SIZE: u64 = 1 << 31;
+
+/// The predefined probabilities for literal length codes
+const LL_DEFAULT: (&[i16], u32) = (&[
+    4, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 2, 1, 1, 1, 1, 1,
+    -1, -1, -1, -1,
+], 6);
+
+/// The predefined probabilities for match length codes
+const ML_DEFAULT: (&[i16], u32) = (&[
+    1, 4, 3, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1, -1, -1,
+], 6);
+
+/// The predefined probabilities for offset codes
+const OF_DEFAULT: (&[i16], u32) = (&[
+    1, 1, 1, 1, 1, 1, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1,
+], 5);
+
+/// The baseline and number of extra bits for each literal length code
+const LL_CODES: [(u32, u32); 36] = [
+    (0, 0), (1, 0), (2, 0), (3, 0), (4, 0), (5, 0), (6, 0), (7, 0), (8, 0), (9, 0), (10, 0),
+    (11, 0), (12, 0), (13, 0), (14, 0), (15, 0), (16, 1), (18, 1), (20, 1), (22, 1), (24, 2),
+    (28, 2), (32, 3), (40, 3), (48, 4), (64, 6), (128, 7), (256, 8), (512, 9), (1024, 10),
+    (2048, 11), (4096, 12), (8192, 13), (16384, 14), (32768, 15), (65536, 16),
+];
+
+/// The baseline and number of extra bits for each match length code
+const ML_CODES: [(u32, u32); 53] = [
+    (3, 0), (4, 0), (5, 0), (6, 0), (7, 0), (8, 0), (9, 0), (10, 0), (11, 0), (12, 0), (13, 0),
+    (14, 0), (15, 0), (16, 0), (17, 0), (18, 0), (19, 0), (20, 0), (21, 0), (22, 0), (23, 0),
+    (24, 0), (25, 0), (26, 0), (27, 0), (28, 0), (29, 0), (30, 0), (31, 0), (32, 0), (33, 0),
+    (34, 0), (35, 1), (37, 1), (39, 1), (41, 1), (43, 2), (47, 2), (51, 3), (59, 3), (67, 4),
+    (83, 4), (99, 5), (131, 7), (259, 8), (515, 9), (1027, 10), (2051, 11), (4099, 12),
+    (8195, 13), (16387, 14), (32771, 15), (65539, 16),
+];
+
+/// The largest offset code this decoder accepts (enough for any window it will allocate)
+const MAX_OFFSET_CODE: usize = 31;
+
+/// Read a little-endian integer of up to eight bytes
+fn le_uint(bytes: &[u8]) -> u64 {
+    bytes.iter().rev().fold(0, |acc, &byte| (acc << 8) | u64::from(byte))
+}
+
+/// Read a little-endian size of up to four bytes
+fn le_size(bytes: &[u8]) -> usize {
+    bytes.iter().rev().fold(0, |acc, &byte| (acc << 8) | usize::from(byte))
+}
+
+/// Get `len` bytes from `data` starting at `start`, or report what was being read as truncated
+fn slice<'a>(data: &'a [u8], start: usize, len: usize, what: &str) -> io::Result<&'a [u8]> {
+    data.get(start..start.saturating_add(len))
+        .ok_or_else(|| corrupt(&format!("Truncated zstd {what}")))
+}
+
+/// Get the byte at `pos` in `data`, or report what was being read as truncated
+fn byte(data: &[u8], pos: usize, what: &str) -> io::Result<u8> {
+    data.get(pos).copied().ok_or_else(|| corrupt(&format!("Truncated zstd {what}")))
+}
+
+/// A reader for the little-endian bitstream used by FSE table descriptions
+struct ForwardBits<'a> {
+    /// The bytes being read
+    data: &'a [u8],
+    /// The index of the next bit to be read
+    pos: usize,
+}
+
+impl ForwardBits<'_> {
+    /// Look at the next `count` bits (at most 24) without consuming them, treating any past the
+    /// end as zero
+    fn peek(&self, count: u32) -> u32 {
+        let mut bytes = [0; 4];
+        for (byte, &value) in bytes.iter_mut().zip(self.data.get(self.pos / 8..).unwrap_or(&[])) {
+            *byte = value;
+        }
+        (u32::from_le_bytes(bytes) >> (self.pos % 8)) & ((1 << count) - 1)
+    }
+
+    /// Consume `count` bits
+    fn consume(&mut self, count: u32) -> io::Result<()> {
+        self.pos += count as usize;
+        if self.pos > self.data.len() * 8 {
+            return Err(corrupt("Truncated zstd FSE table description"));
+        }
+        Ok(())
+    }
+
+    /// Consume and return the next `count` bits
+    fn read(&mut self, count: u32) -> io::Result<u32> {
+        let value = self.peek(count);
+        self.consume(count)?;
+        Ok(value)
+    }
+}
+
+/// A reader for the bitstreams Zstandard writes forward and decodes backward
+struct BackwardBits<'a> {
+    /// The bytes being read
+    data: &'a [u8],
+    /// How many bits are left to read
+    pos: usize,
+    /// Whether reading has gone past the start
+    overrun: bool,
+}
+
+impl<'a> BackwardBits<'a> {
+    /// Start reading `data` from the end, skipping the padding which marks where it starts
+    fn new(data: &'a [u8]) -> io::Result<Self> {
+        match data.last() {
+            Some(&last) if last != 0 => Ok(Self {
+                data,
+                pos: data.len() * 8 - last.leading_zeros() as usize - 1,
+                overrun: false,
+            }),
+            _ => Err(corrupt("Missing zstd bitstream padding")),
+        }
+    }
+
+    /// Get `count` bits (at most 32) starting at bit `start`
+    fn extract(&self, start: usize, count: u32) -> u32 {
+        let first = start / 8;
+        let end = (first + 8).min(self.data.len());
+        low32((le_uint(&self.data[first..end]) >> (start % 8)) & ((1 << count) - 1))
+    }
+
+    /// Look at the next `count` bits without consuming them, treating any past the start as zero
+    fn peek(&self, count: u32) -> u32 {
+        match self.pos.checked_sub(count as usize) {
+            Some(low) => self.extract(low, count),
+            None => (self.extract(0, count) & ((1 << self.pos) - 1)) << (count as usize - self.pos),
+        }
+    }
+
+    /// Consume `count` bits without looking at them
+    fn skip(&mut self, count: u32) {
+        if let Some(pos) = self.pos.checked_sub(count as usize) {
+            self.pos = pos;
+        } else {
+            self.pos = 0;
+            self.overrun = true;
+        }
+    }
+
+    /// Consume and return the next `count` bits
+    fn read(&mut self, count: u32) -> u32 {
+        let value = self.peek(count);
+        self.skip(count);
+        value
+    }
+
+    /// Whether every bit has been read, without going past the start
+    fn finished(&self) -> bool {
+        self.pos == 0 && !self.overrun
+    }
+}
+
+/// One entry in a finite state entropy decoding table
+#[derive(Clone, Copy, Default)]
+struct FseEntry {
+    /// The symbol this state decodes to
+    symbol: u8,
+    /// How many bits to read for the next state
+    bits: u32,
+    /// What to add those bits to for the next state
+    base: usize,
+}
+
+/// A finite state entropy decoding table
+#[derive(Clone)]
+struct FseTable {
+    /// How many bits the initial state takes
+    log: u32,
+    /// The table itself, indexed by state
+    entries: Vec<FseEntry>,
+}
+
+impl FseTable {
+    /// A table which decodes to `symbol` every time without reading any bits
+    fn rle(symbol: u8) -> Self {
+        Self { log: 0, entries: vec![FseEntry { symbol, bits: 0, base: 0 }] }
+    }
+
+    /// Build a table from normalized symbol counts which sum to `1 << log`
+    ///
+    /// (A count of -1 means "less than one" and gets a single state at the end of the table.)
+    fn from_counts(counts: &[i16], log: u32) -> io::Result<Self> {
+        if counts.len() > 256 {
+            return Err(corrupt("Too many symbols in zstd FSE table"));
+        }
+        let size = 1_usize << log;
+        let mut entries = vec![FseEntry::default(); size];
+        let mut next = vec![0; counts.len()];
+        let mut high = size;
+        for ((symbol, &count), next) in (0..=u8::MAX).zip(counts).zip(&mut next) {
+            if count == -1 {
+                high = high.checked_sub(1).ok_or_else(|| corrupt("Invalid zstd FSE table"))?;
+                entries[high].symbol = symbol;
+                *next = 1;
+            } else {
+                *next = usize::try_from(count).map_err(|_| corrupt("Invalid zstd FSE table"))?;
+            }
+        }
+
+        let step = (size >> 1) + (size >> 3) + 3;
+        let mut pos = 0;
+        for (symbol, &count) in (0..=u8::MAX).zip(counts) {
+            for _ in 0..count.max(0) {
+                entries[pos].symbol = symbol;
+                pos = (pos + step) & (size - 1);
+                while pos >= high {
+                    pos = (pos + step) & (size - 1);
+                }
+            }
+        }
+        if pos != 0 {
+            return Err(corrupt("Invalid zstd FSE table"));
+        }
+
+        for entry in &mut entries {
+            let state = next[usize::from(entry.symbol)];
+            next[usize::from(entry.symbol)] += 1;
+            let bits = state.checked_ilog2().and_then(|x| log.checked_sub(x))
+                .ok_or_else(|| corrupt("Invalid zstd FSE table"))?;
+            entry.bits = bits;
+            entry.base = (state << bits) - size;
+        }
+        Ok(Self { log, entries })
+    }
+
+    /// Read a table description from the start of `data`, returning the table and the number of
+    /// bytes it took up
+    fn read(data: &[u8], max_log: u32, max_symbol: usize) -> io::Result<(Self, usize)> {
+        let mut bits = ForwardBits { data, pos: 0 };
+        let log = bits.read(4)? + 5;
+        if log > max_log {
+            return Err(corrupt("zstd FSE table accuracy is too high"));
+        }
+
+        let mut counts = Vec::new();
+        let mut remaining = (1_u32 << log) + 1;
+        let mut threshold = 1_u32 << log;
+        let mut width = log + 1;
+        while remaining > 1 {
+            if counts.len() > max_symbol {
+                return Err(corrupt("Too many symbols in zstd FSE table"));
+            }
+            let max = 2 * threshold - 1 - remaining;
+            let low = bits.peek(width - 1);
+            let value = if low < max {
+                bits.consume(width - 1)?;
+                low
+            } else {
+                let value = bits.read(width)?;
+                if value >= threshold { value - max } else { value }
+            };
+
+            // Counts are stored one higher, so that -1 can be represented
+            let count = i16::try_from(value).map_err(|_| corrupt("Invalid zstd FSE table"))? - 1;
+            remaining = remaining.checked_sub(u32::from(count.unsigned_abs()))
+                .ok_or_else(|| corrupt("Invalid zstd FSE table description"))?;
+            counts.push(count);
+
+            if count == 0 {
+                loop {
+                    let repeat = bits.read(2)?;
+                    counts.extend((0..repeat).map(|_| 0));
+                    if repeat != 3 {
+                        break;
+                    }
+                }
+            }
+            if remaining < threshold {
+                if remaining <= 1 {
+                    break;
+                }
+                width = 32 - remaining.leading_zeros();
+                threshold = 1 << (width - 1);
+            }
+        }
+        if remaining != 1 || counts.len() > max_symbol + 1 {
+            return Err(corrupt("Invalid zstd FSE table description"));
+        }
+        Ok((Self::from_counts(&counts, log)?, bits.pos.div_ceil(8)))
+    }
+
+    /// Set up a decoding state by reading its initial value
+    fn start(&self, bits: &mut BackwardBits<'_>) -> FseState<'_> {
+        FseState { table: self, state: bits.read(self.log) as usize }
+    }
+}
+
+/// The state of a finite state entropy decoder
+struct FseState<'a> {
+    /// The table being decoded with
+    table: &'a FseTable,
+    /// The current state
+    state: usize,
+}
+
+impl FseState<'_> {
+    /// The symbol for the current state
+    fn symbol(&self) -> u8 {
+        self.table.entries[self.state].symbol
+    }
+
+    /// Move to the next state
+    fn update(&mut self, bits: &mut BackwardBits<'_>) {
+        let entry = self.table.entries[self.state];
+        self.state = entry.base + bits.read(entry.bits) as usize;
+    }
+}
+
+/// A Huffman decoding table for literals
+struct HuffmanTable {
+    /// The length of the longest code
+    max_bits: u32,
+    /// The symbol and code length for every `max_bits`-wide bit pattern
+    entries: Vec<(u8, u32)>,
+}
+
+impl HuffmanTable {
+    /// Read a Huffman tree description from the start of `data`, returning the table and the
+    /// number of bytes it took up
+    fn read(data: &[u8]) -> io::Result<(Self, usize)> {
+        let header = usize::from(*data.first().ok_or_else(|| corrupt("Missing Huffman tree"))?);
+        let mut weights = Vec::new();
+        let size = if header < 128 {
+            // FSE-compressed weights, decoded by two interleaved states
+            let body = slice(data, 1, header, "Huffman tree")?;
+            let (table, used) = FseTable::read(body, 6, 255)?;
+            let mut bits = BackwardBits::new(&body[used.min(body.len())..])?;
+            let mut states = [table.start(&mut bits), table.start(&mut bits)];
+            for idx in (0..2).cycle() {
+                weights.push(states[idx].symbol());
+                states[idx].update(&mut bits);
+                if bits.overrun {
+                    weights.push(states[1 - idx].symbol());
+                    break;
+                } else if weights.len() > 255 {
+                    return Err(corrupt("Too many Huffman weights"));
+                }
+            }
+            header + 1
+        } else {
+            let count = header - 127;
+            for &byte in slice(data, 1, count.div_ceil(2), "Huffman tree")? {
+                weights.extend(&[byte >> 4, byte & 0xF]);
+            }
+            weights.truncate(count);
+            count.div_ceil(2) + 1
+        };
+        if weights.len() > 255 {
+            return Err(corrupt("Too many Huffman weights"));
+        }
+
+        // The last weight is implied by the others summing to a power of two
+        let mut total = 0_u32;
+        for &weight in &weights {
+            if weight > 11 {
+                return Err(corrupt("Invalid Huffman weight"));
+            } else if weight > 0 {
+                total += 1 << (weight - 1);
+            }
+        }
+        let max_bits = 32 - total.leading_zeros();
+        let rest = (1_u32 << max_bits) - total;
+        if total == 0 || max_bits > 11 || !rest.is_power_of_two() {
+            return Err(corrupt("Invalid Huffman weights"));
+        }
+        weights.push(u8::try_from(rest.trailing_zeros() + 1)
+            .map_err(|_| corrupt("Invalid Huffman weights"))?);
+
+        let mut entries = Vec::with_capacity(1 << max_bits);
+        for weight in 1..=max_bits {
+            let symbols = (0..=u8::MAX).zip(&weights).filter(|(_, &x)| u32::from(x) == weight);
+            for (symbol, _) in symbols {
+                let entry = (symbol, max_bits + 1 - weight);
+                entries.extend((0..1 << (weight - 1)).map(|_| entry));
+            }
+        }
+        Ok((Self { max_bits, entries }, size))
+    }
+
+    /// Decode exactly `count` literals from one Huffman-coded stream into `out`
+    fn decode_stream(&self, data: &[u8], count: usize, out: &mut Vec<u8>) -> io::Result<()> {
+        let mut bits = BackwardBits::new(data)?;
+        for _ in 0..count {
+            let (symbol, len) = self.entries[bits.peek(self.max_bits) as usize];
+            bits.skip(len);
+            out.push(symbol);
+        }
+        if !bits.finished() {
+            return Err(corrupt("Huffman stream size doesn't match its contents"));
+        }
+        Ok(())
+    }
+}
+
+/// The state which carries over from one compressed block to the next within a frame
+struct BlockState {
+    /// The previous block's Huffman table, for reuse by treeless literals
+    huffman: Option<HuffmanTable>,
+    /// The previous literal length table, for reuse by repeat mode
+    ll: Option<FseTable>,
+    /// The previous offset table, for reuse by repeat mode
+    of: Option<FseTable>,
+    /// The previous match length table, for reuse by repeat mode
+    ml: Option<FseTable>,
+    /// The three most recently used offsets
+    reps: [usize; 3],
+}
+
+impl BlockState {
+    /// The state at the start of a frame
+    fn new() -> Self {
+        Self { huffman: None, ll: None, of: None, ml: None, reps: [1, 4, 8] }
+    }
+
+    /// Decode the literals section at the start of `data`, returning the literals and the number
+    /// of bytes the section took up
+    fn literals(&mut self, data: &[u8]) -> io::Result<(Vec<u8>, usize)> {
+        let first = *data.first().ok_or_else(|| corrupt("Missing zstd literals section"))?;
+        let (kind, size_format) = (first & 3, (first >> 2) & 3);
+        if kind < 2 {
+            // Raw or RLE literals
+            let header_len = match size_format { 1 => 2, 3 => 3, _ => 1 };
+            let header = le_size(slice(data, 0, header_len, "literals header")?);
+            let len = if header_len == 1 { header >> 3 } else { header >> 4 };
+            return if kind == 0 {
+                Ok((slice(data, header_len, len, "literals")?.to_vec(), header_len + len))
+            } else {
+                Ok((vec![byte(data, header_len, "literals")?; len], header_len + 1))
+            };
+        }
+
+        let (header_len, size_bits) = match size_format {
+            0 | 1 => (3, 10),
+            2 => (4, 14),
+            _ => (5, 18),
+        };
+        let header = le_uint(slice(data, 0, header_len, "literals header")?);
+        let mask = (1 << size_bits) - 1;
+        let len = usize::try_from((header >> 4) & mask).unwrap_or(usize::MAX);
+        let compressed_len =
+            usize::try_from((header >> (4 + size_bits)) & mask).unwrap_or(usize::MAX);
+        if len > MAX_BLOCK_SIZE {
+            return Err(corrupt("Too many literals in zstd block"));
+        }
+        let mut body = slice(data, header_len, compressed_len, "literals")?;
+        if kind == 2 {
+            let (table, used) = HuffmanTable::read(body)?;
+            self.huffman = Some(table);
+            body = &body[used..];
+        }
+        let table = self.huffman.as_ref()
+            .ok_or_else(|| corrupt("zstd block reuses a Huffman table which doesn't exist"))?;
+
+        let mut literals = Vec::with_capacity(len);
+        if size_format == 0 {
+            table.decode_stream(body, len, &mut literals)?;
+        } else {
+            let jump = slice(body, 0, 6, "Huffman jump table")?;
+            let mut sizes = [0; 4];
+            for (size, bytes) in sizes.iter_mut().zip(jump.chunks_exact(2)) {
+                *size = le_size(bytes);
+            }
+            sizes[3] = (body.len() - 6)
+                .checked_sub(sizes[..3].iter().sum())
+                .ok_or_else(|| corrupt("Invalid Huffman jump table"))?;
+            let segment = len.div_ceil(4);
+            let last = len.checked_sub(segment * 3)
+                .ok_or_else(|| corrupt("Too few literals for four Huffman streams"))?;
+            let mut start = 6;
+            for (idx, size) in sizes.iter().enumerate() {
+                let count = if idx == 3 { last } else { segment };
+                table.decode_stream(&body[start..start + size], count, &mut literals)?;
+                start += size;
+            }
+        }
+        Ok((literals, header_len + compressed_len))
+    }
+
+    /// Set up one of the sequence decoding tables according to its compression mode, returning
+    /// the number of bytes of `data` its description took up
+    fn table(slot: &mut Option<FseTable>, mode: u8, data: &[u8], default: (&[i16], u32),
+             max_log: u32, max_symbol: usize) -> io::Result<usize> {
+        match mode {
+            0 => {
+                *slot = Some(FseTable::from_counts(default.0, default.1)?);
+                Ok(0)
+            },
+            1 => {
+                let symbol = byte(data, 0, "sequences header")?;
+                if usize::from(symbol) > max_symbol {
+                    return Err(corrupt("Invalid zstd RLE sequence code"));
+                }
+                *slot = Some(FseTable::rle(symbol));
+                Ok(1)
+            },
+            2 => {
+                let (table, used) = FseTable::read(data, max_log, max_symbol)?;
+                *slot = Some(table);
+                Ok(used)
+            },
+            _ if slot.is_some() => Ok(0),
+            _ => Err(corrupt("zstd block reuses a sequence table which doesn't exist")),
+        }
+    }
+
+    /// Decode a compressed block, appending its contents to `history`
+    fn decode(&mut self, data: &[u8], history: &mut Vec<u8>, window_size: usize)
+            -> io::Result<()> {
+        let (literals, mut pos) = self.literals(data)?;
+
+        let first = usize::from(byte(data, pos, "sequences header")?);
+        let count = match first {
+            0..=127 => first,
+            128..=254 => {
+                ((first - 128) << 8) + usize::from(byte(data, pos + 1, "sequences header")?)
+            },
+            _ => 0x7F00 + le_size(slice(data, pos + 1, 2, "sequences header")?),
+        };
+        pos += match first { 0..=127 => 1, 128..=254 => 2, _ => 3 };
+        if count == 0 {
+            if pos != data.len() {
+                return Err(corrupt("zstd block size doesn't match its contents"));
+            }
+            history.extend(&literals);
+            return Ok(());
+        }
+
+        let modes = byte(data, pos, "sequences header")?;
+        if modes & 3 != 0 {
+            return Err(corrupt("Reserved bits set in zstd sequences header"));
+        }
+        pos += 1;
+        pos += Self::table(&mut self.ll, modes >> 6, &data[pos..], LL_DEFAULT, 9, 35)?;
+        pos += Self::table(&mut self.of, (modes >> 4) & 3, &data[pos..], OF_DEFAULT, 8,
+                           MAX_OFFSET_CODE)?;
+        pos += Self::table(&mut self.ml, (modes >> 2) & 3, &data[pos..], ML_DEFAULT, 9, 52)?;
+        let (Some(ll_table), Some(of_table), Some(ml_table)) =
+            (self.ll.as_ref(), self.of.as_ref(), self.ml.as_ref()) else {
+            return Err(corrupt("Missing zstd sequence table"));
+        };
+
+        let mut bits = BackwardBits::new(data.get(pos..).unwrap_or(&[]))?;
+        let mut ll_state = ll_table.start(&mut bits);
+        let mut of_state = of_table.start(&mut bits);
+        let mut ml_state = ml_table.start(&mut bits);
+        let mut literal_pos = 0;
+        for idx in 0..count {
+            let of_code = u32::from(of_state.symbol());
+            let (ml_base, ml_bits) = ML_CODES[usize::from(ml_state.symbol())];
+            let (ll_base, ll_bits) = LL_CODES[usize::from(ll_state.symbol())];
+            let offset_value = (1_usize << of_code) + bits.read(of_code) as usize;
+            let match_len = (ml_base + bits.read(ml_bits)) as usize;
+            let literal_len = (ll_base + bits.read(ll_bits)) as usize;
+            if idx + 1 < count {
+                ll_state.update(&mut bits);
+                ml_state.update(&mut bits);
+                of_state.update(&mut bits);
+            }
+
+            let [rep0, rep1, rep2] = self.reps;
+            let (offset, updated) = if offset_value > 3 {
+                (offset_value - 3, [offset_value - 3, rep0, rep1])
+            } else {
+                // A repeat offset, counted from the second one if there are no literals
+                match offset_value - 1 + usize::from(literal_len == 0) {
+                    0 => (rep0, self.reps),
+                    1 => (rep1, [rep1, rep0, rep2]),
+                    2 => (rep2, [rep2, rep0, rep1]),
+                    _ => {
+                        let offset = rep0.checked_sub(1).filter(|&x| x > 0)
+                            .ok_or_else(|| corrupt("Invalid zstd repeat offset"))?;
+                        (offset, [offset, rep0, rep1])
+                    },
+                }
+            };
+            self.reps = updated;
+
+            let literals = literals.get(literal_pos..literal_pos + literal_len)
+                .ok_or_else(|| corrupt("zstd sequences use more literals than the block has"))?;
+            history.extend(literals);
+            literal_pos += literal_len;
+            if offset > history.len() || offset > window_size {
+                return Err(corrupt("zstd match offset is beyond the start of the data"));
+            }
+            let start = history.len() - offset;
+            if offset >= match_len {
+                history.extend_from_within(start..start + match_len);
+            } else {
+                for idx in 0..match_len {
+                    history.push(history[start + idx]);
+                }
+            }
+        }
+        if !bits.finished() {
+            return Err(corrupt("zstd sequences size doesn't match its contents"));
+        }
+        history.extend(&literals[literal_pos..]);
+        Ok(())
+    }
+}
+
+/// The state of the frame currently being decompressed
+struct Frame {
+    /// How far back matches may reach
+    window_size: usize,
+    /// The content size declared in the frame header, if any
+    content_size: Option<u64>,
+    /// The content checksum being calculated, if the frame has one
+    checksum: Option<Xxh64>,
+    /// How much has been decompressed so far
+    decompressed: u64,
+    /// State carried over between compressed blocks
+    blocks: BlockState,
+    /// Whether the last block has been decoded
+    finished: bool,
+}
+
+/// A streaming decompressor for `.zst` files
+pub struct ZstdDecoder<R> {
+    /// The compressed data
+    input: R,
+    /// The frame currently being decompressed, if any
+    frame: Option<Frame>,
+    /// Recently decompressed data (which matches are copied from)
+    history: Vec<u8>,
+    /// How much of `history` has been read
+    history_pos: usize,
+    /// Whether any (non-skippable) frame has been seen yet
+    seen_frame: bool,
+    /// How many frames had no content checksum to verify
+    unchecked_frames: usize,
+}
+
+impl<R: BufRead> ZstdDecoder<R> {
+    /// Prepare to decompress `input`
+    pub fn new(input: R) -> Self {
+        Self {
+            input,
+            frame: None,
+            history: Vec::new(),
+            history_pos: 0,
+            seen_frame: false,
+            unchecked_frames: 0,
+        }
+    }
+
+    /// How many of the frames read so far had no content checksum
+    ///
+    /// (Their blocks decoded without errors, but there was nothing to compare the output to.)
+    pub fn unchecked_frames(&self) -> usize {
+        self.unchecked_frames
+    }
+
+    /// Read exactly `len` bytes from the input
+    fn read_vec(&mut self, len: usize) -> io::Result<Vec<u8>> {
+        let mut data = vec![0; len];
+        self.input.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    /// Read a frame header, given that its magic number has already been read
+    fn frame_header(&mut self) -> io::Result<Frame> {
+        let descriptor = self.read_vec(1)?[0];
+        let single_segment = descriptor & 0x20 != 0;
+        if descriptor & 0x08 != 0 {
+            return Err(corrupt("Reserved bit set in zstd frame header"));
+        }
+
+        let mut window_size = if single_segment {
+            None
+        } else {
+            let byte = self.read_vec(1)?[0];
+            let base = 1_u64 << (10 + (byte >> 3));
+            Some(base + (base / 8) * u64::from(byte & 7))
+        };
+
+        let dict_id_len = [0, 1, 2, 4][usize::from(descriptor & 3)];
+        if le_uint(&self.read_vec(dict_id_len)?) != 0 {
+            return Err(unsupported("zstd frame needs a dictionary"));
+        }
+
+        let content_size = match (descriptor >> 6, single_segment) {
+            (0, false) => None,
+            (0, true) => Some(le_uint(&self.read_vec(1)?)),
+            (1, _) => Some(le_uint(&self.read_vec(2)?) + 256),
+            (2, _) => Some(le_uint(&self.read_vec(4)?)),
+            _ => Some(le_uint(&self.read_vec(8)?)),
+        };
+        if single_segment {
+            window_size = content_size;
+        }
+
+        let window_size = window_size.unwrap_or(0);
+        if window_size > MAX_WINDOW_SIZE {
+            return Err(unsupported(&format!(
+                "zstd window size of {window_size} bytes is too large")));
+        }
+        Ok(Frame {
+            window_size: usize::try_from(window_size).unwrap_or(usize::MAX),
+            content_size,
+            checksum: if descriptor & 0x04 == 0 { None } else { Some(Xxh64::new()) },
+            decompressed: 0,
+            blocks: BlockState::new(),
+            finished: false,
+        })
+    }
+
+    /// Decode the next block of `frame` into `history`
+    fn block(&mut self, frame: &mut Frame) -> io::Result<()> {
+        let header = le_size(&self.read_vec(3)?);
+        let size = header >> 3;
+        let max_size = frame.window_size.min(MAX_BLOCK_SIZE);
+
+        // Discard history which matches can no longer reach, once there's a fair amount of it
+        let excess = self.history.len().saturating_sub(frame.window_size);
+        if excess > frame.window_size.max(MAX_BLOCK_SIZE) {
+            self.history.drain(..excess);
+        }
+        let start = self.history.len();
+
+        match (header >> 1) & 3 {
+            0 => {
+                let data = self.read_vec(size)?;
+                self.history.extend(data);
+            },
+            1 => {
+                let byte = self.read_vec(1)?[0];
+                self.history.resize(start + size, byte);
+            },
+            2 if size <= MAX_BLOCK_SIZE => {
+                let data = self.read_vec(size)?;
+                frame.blocks.decode(&data, &mut self.history, frame.window_size)?;
+            },
+            2 => return Err(corrupt("zstd block is larger than the maximum block size")),
+            _ => return Err(corrupt("Reserved zstd block type")),
+        }
+
+        let produced = &self.history[start..];
+        if produced.len() > max_size {
+            return Err(corrupt("zstd block is larger than the maximum block size"));
+        }
+        if let Some(checksum) = frame.checksum.as_mut() {
+            checksum.update(produced);
+        }
+        frame.decompressed += produced.len() as u64;
+        frame.finished = header & 1 == 1;
+        self.history_pos = start;
+        Ok(())
+    }
+
+    /// Check the end of a frame once its last block has been decoded
+    fn finish_frame(&mut self, frame: Frame) -> io::Result<()> {
+        if frame.content_size.is_some_and(|x| x != frame.decompressed) {
+            return Err(corrupt("zstd frame size doesn't match its header"));
+        }
+        match frame.checksum {
+            Some(checksum) => {
+                let stored = le_uint(&self.read_vec(4)?);
+                if stored != checksum.finish() & 0xFFFF_FFFF {
+                    return Err(corrupt("zstd content checksum mismatch"));
+                }
+            },
+            None => self.unchecked_frames += 1,
+        }
+        Ok(())
+    }
+
+    /// Decode more data into `history`, returning `false` at the end of the file
+    fn fill(&mut self) -> io::Result<bool> {
+        loop {
+            match self.frame.take() {
+                None => {
+                    if self.input.fill_buf()?.is_empty() {
+                        return if self.seen_frame { Ok(false) } else { Err(corrupt("Empty file")) };
+                    }
+                    let mut magic = [0; 4];
+                    self.input.read_exact(&mut magic)?;
+                    let magic = u32::from_le_bytes(magic);
+                    if magic & 0xFFFF_FFF0 == SKIPPABLE_MAGIC {
+                        let len = le_uint(&self.read_vec(4)?);
+                        let skipped = io::copy(&mut (&mut self.input).take(len), &mut io::sink())?;
+                        if skipped != len {
+                            return Err(corrupt("Unexpected end of file"));
+                        }
+                    } else if magic == FRAME_MAGIC {
+                        self.frame = Some(self.frame_header()?);
+                        self.seen_frame = true;
+                        self.history.clear();
+                        self.history_pos = 0;
+                    } else {
+                        return Err(corrupt("Not a zstd frame"));
+                    }
+                },
+                Some(frame) if frame.finished => self.finish_frame(frame)?,
+                Some(mut frame) => {
+                    self.block(&mut frame)?;
+                    self.frame = Some(frame);
+                    if self.history_pos < self.history.len() {
+                        return Ok(true);
+                    }
+                },
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Read for ZstdDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.history_pos == self.history.len() {
+            let more = eof_is_corrupt(self.fill())?;
+            if !more {
+                return Ok(0);
+            }
+        }
+        let available = &self.history[self.history_pos..];
+        let len = buf.len().min(available.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.history_pos += len;
+        Ok(len)
+    }
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a single-segment frame which stores `data` in one raw block
+    fn frame(data: &[u8], checksum: bool) -> Vec<u8> {
+        let mut out = FRAME_MAGIC.to_le_bytes().to_vec();
+        out.push(if checksum { 0x24 } else { 0x20 });
+        out.push(u8::try_from(data.len()).unwrap());
+        out.extend(&(u32::try_from(data.len()).unwrap() << 3 | 1).to_le_bytes()[..3]);
+        out.extend(data);
+        if checksum {
+            let mut hasher = Xxh64::new();
+            hasher.update(data);
+            out.extend(&hasher.finish().to_le_bytes()[..4]);
+        }
+        out
+    }
+
+    /// Decompress `input`, returning the error kind on failure
+    fn decode(input: &[u8]) -> Result<Vec<u8>, io::ErrorKind> {
+        let mut output = Vec::new();
+        ZstdDecoder::new(input).read_to_end(&mut output).map_err(|err| err.kind())?;
+        Ok(output)
+    }
+
+    #[test]
+    fn test_checksums() {
+        assert_eq!(decode(&frame(b"Hello, zstd!\n", true)).unwrap(), b"Hello, zstd!\n");
+
+        let mut corrupted = frame(b"Hello, zstd!\n", true);
+        corrupted[15] ^= 0x01;
+        assert_eq!(decode(&corrupted), Err(io::ErrorKind::InvalidData));
+
+        let unchecked = frame(b"Hello, zstd!\n", false);
+        let mut decoder = ZstdDecoder::new(&unchecked[..]);
+        assert_eq!(io::copy(&mut decoder, &mut io::sink()).unwrap(), 13);
+        assert_eq!(decoder.unchecked_frames(), 1);
+
+        let mut with_dict = frame(b"Hello", true);
+        with_dict[4] |= 0x01;
+        with_dict.insert(5, 0x42);
+        assert_eq!(decode(&with_dict), Err(io::ErrorKind::Unsupported));
+    }
+
+    #[test]
+    fn test_frame_structure() {
+        let single = frame(b"Hello", true);
+        for cut in &[0, 3, 7, single.len() - 1] {
+            assert_eq!(decode(&single[..*cut]), Err(io::ErrorKind::InvalidData));
+        }
+
+        let mut multi = single.clone();
+        multi.extend(&(SKIPPABLE_MAGIC | 0x0A).to_le_bytes());
+        multi.extend(&3_u32.to_le_bytes());
+        multi.extend(b"xyz");
+        multi.extend(&frame(b", world", false));
+        assert_eq!(decode(&multi).unwrap(), b"Hello, world");
+
+        for trailer in &[&[0x28, 0xB5][..], b"junk"] {
+            let mut bad = single.clone();
+            bad.extend(*trailer);
+            assert_eq!(decode(&bad), Err(io::ErrorKind::InvalidData));
+        }
+    }
+
+    #[test]
+    fn test_zstd_fixtures() {
+        assert!(decode(include_bytes!("../../../test_data/good/testfile.tzst")).is_ok());
+        assert_eq!(decode(include_bytes!("../../../test_data/good/testfile.txt.zst")).unwrap(),
+                   &include_bytes!("../../../test_data/sources/testfile.txt")[..]);
+        assert_eq!(decode(include_bytes!("../../../test_data/bad/testfile.tzst")),
+                   Err(io::ErrorKind::InvalidData));
+        assert_eq!(decode(include_bytes!("../../../test_data/bad/testfile.txt.zst")),
+                   Err(io::ErrorKind::InvalidData));
+    }
+}
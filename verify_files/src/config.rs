@@ -1,9 +1,10 @@
 //! Definitions for the `verifiers.toml` configuration file.
 //!
-//! Invoke this machinery via the [`parse`] function.
+//! Invoke this machinery via the [`parse`] function (or [`parse_overlay`] for a user's file which
+//! extends the defaults).
 //!
 //! **NOTE:** Uses `BTreeMap` instead of `HashMap` to ensure the data will serialize to TOML in
-//! sorted order.
+//! sorted order. (Except for overrides, where the order they were written in matters.)
 //!
 //! **TODO:** Consider using the [`types`
 //! module](https://docs.rs/ignore/0.4.17/ignore/types/index.html) from the `ignore` crate and
@@ -11,17 +12,28 @@
 //! name for various "no handler registered for this type" situations.
 
 // Standard library imports
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::ffi::OsString;
 use std::fmt::Write;
-use std::ops::Not;
+use std::fs;
+use std::ops::{Not, Range};
+use std::path::{Path, PathBuf};
 use std::result::Result as StdResult;
+use std::time::Duration;
 
 // 3rd-party crate imports
 use anyhow::{anyhow, Context, Result}; // It's an internal API, so no need for thiserror yet.
-use log::warn;
-use serde::{Deserialize, Serialize};
+use globset::{Glob, GlobBuilder};
+use indexmap::IndexMap;
+use log::{debug, warn};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use validator::{Validate, ValidationError, ValidationErrors};
 
+// Local Imports
+use crate::builtin_handlers::Confidence;
+use crate::subprocess::{self, PATH_TOKEN};
+
 // ----==== Helpers for Schema ====----
 
 /// Wrapper to compact the repeated boilerplate of attaching messages to a custom
@@ -35,12 +47,77 @@ macro_rules! fail_valid {
 }
 
 /// Helper for Serde's `skip_serializing_if`
+///
+/// (Also used for non-numeric fields to skip serializing them when they have their default value.)
 #[allow(clippy::trivially_copy_pass_by_ref)]
-fn is_zero(int: &usize) -> bool {
-    *int == 0
+fn is_zero<T: Default + PartialEq>(int: &T) -> bool {
+    *int == T::default()
+}
+
+/// The newest [`Root::config_version`] this build understands
+pub const CONFIG_VERSION: u32 = 2;
+
+/// Helper for Serde's `default` on [`Root::config_version`]
+fn current_config_version() -> u32 {
+    CONFIG_VERSION
+}
+
+/// Find everything in `arg` that looks like a `{token}`
+fn find_tokens(arg: &str) -> impl Iterator<Item = &str> {
+    arg.match_indices('{').filter_map(move |(start, _)| {
+        let len = arg[start + 1..]
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == ':'))
+            .filter(|&len| len > 0 && arg[start + 1 + len..].starts_with('}'))?;
+        Some(&arg[start..start + len + 2])
+    })
+}
+
+/// Validator: `arg` doesn't contain any unknown tokens which would be passed through literally
+fn validate_tokens(arg: &str) -> StdResult<(), ValidationError> {
+    let is_known = |token: &str| {
+        subprocess::TOKENS.contains(&token)
+            || subprocess::arg_token_key(token).map_or(false, |key| !key.contains(':'))
+    };
+    if let Some(token) = find_tokens(arg).find(|x| !is_known(x)) {
+        fail_valid!(
+            "unknown_subst",
+            format!("Unknown substitution token {} in argument: {}", token, arg)
+        );
+    }
+    Ok(())
+}
+
+/// Validator: `env` tables have sane variable names and no unknown tokens in their values
+fn validate_env(input: &BTreeMap<String, String>) -> StdResult<(), ValidationError> {
+    for (name, value) in input {
+        if name.is_empty() || name.contains('=') || name.contains('\0') {
+            fail_valid!(
+                "invalid_env_name",
+                format!("Environment variable names must be non-empty and contain no '=' or NUL \
+                         characters: {:?}", name)
+            );
+        }
+        validate_tokens(value)?;
+    }
+    Ok(())
+}
+
+/// Validator: `handler_args` keys can be used in `{arg:key}` substitution tokens
+fn validate_handler_args(input: &HandlerArgs) -> StdResult<(), ValidationError> {
+    for key in input.keys() {
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            fail_valid!(
+                "invalid_arg_name",
+                format!("handler_args keys must be non-empty and contain only ASCII letters, \
+                         digits, and underscores: {:?}", key)
+            );
+        }
+    }
+    Ok(())
 }
 
-/// Validator: `argv[0]` doesn't contain any substitution tokens (as a safety net)
+/// Validator: `argv[0]` doesn't contain any substitution tokens (as a safety net) and no unknown
+/// tokens would be passed through literally
 fn validate_argv(argv: &[String]) -> StdResult<(), ValidationError> {
     if let Some(argv0) = argv.get(0) {
         if argv0.contains('{') {
@@ -50,6 +127,43 @@ fn validate_argv(argv: &[String]) -> StdResult<(), ValidationError> {
             );
         }
     }
+    argv.iter().try_for_each(|x| validate_tokens(x))
+}
+
+/// Validator: handlers which read `stdin` don't also expect a path
+fn validate_handler(input: &Handler) -> StdResult<(), ValidationError> {
+    if input.input == InputKind::Stdin && input.argv.iter().any(|x| x.contains(PATH_TOKEN)) {
+        fail_valid!(
+            "stdin_and_path",
+            format!("Handlers with input = \"stdin\" can't use {}: {}", PATH_TOKEN,
+                    input.argv.join(" "))
+        );
+    }
+    Ok(())
+}
+
+/// Validator: no exit code is assigned to more than one category
+fn validate_exit_codes(input: &ExitCodes) -> StdResult<(), ValidationError> {
+    if input.success.is_empty() {
+        fail_valid!("no_success_codes", "exit_codes.success must not be empty");
+    }
+
+    let categories = [
+        ("success", &input.success),
+        ("unsupported", &input.unsupported),
+        ("internal", &input.internal),
+    ];
+    for (idx, (name, codes)) in categories.iter().enumerate() {
+        for (other_name, other_codes) in &categories[idx + 1..] {
+            if let Some(code) = codes.iter().find(|x| other_codes.contains(x)) {
+                fail_valid!(
+                    "overlapping_exit_codes",
+                    format!("Exit code {} is in both exit_codes.{} and exit_codes.{}",
+                            code, name, other_name)
+                );
+            }
+        }
+    }
     Ok(())
 }
 
@@ -67,6 +181,25 @@ fn validate_exts(input: &OneOrList<String>) -> StdResult<(), ValidationError> {
         );
     }
 
+    // Multi-part extensions like `tar.gz` are allowed, but not empty components
+    let exts: Vec<_> =
+        input.iter().map(String::as_str).filter(|x| x.ends_with('.') || x.contains("..")).collect();
+    if !exts.is_empty() {
+        fail_valid!(
+            "empty_ext_component",
+            format!("Extensions must not contain empty components: {}", exts.join(", "))
+        );
+    }
+
+    Ok(())
+}
+
+/// Validator: `container` fields aren't empty and don't contain empty strings
+fn validate_containers(input: &OneOrList<String>) -> StdResult<(), ValidationError> {
+    if input.is_empty() || input.iter().any(String::is_empty) {
+        fail_valid!("empty_container", "'container' must not be empty or contain empty strings");
+    }
+
     Ok(())
 }
 
@@ -79,24 +212,63 @@ fn validate_handlers(input: &OneOrList<String>) -> StdResult<(), ValidationError
     Ok(())
 }
 
-/// Validator: none of the `header` fields contain empty strings
-fn validate_headers(input: &OneOrList<Vec<u8>>) -> StdResult<(), ValidationError> {
-    if input.is_empty() || input.iter().any(Vec::is_empty) {
+/// Validator: the given string is a valid glob, as decided by [`build_glob`]
+fn validate_glob(input: &str) -> StdResult<(), ValidationError> {
+    if let Err(err) = build_glob(input) {
+        fail_valid!("invalid_glob", err);
+    }
+    Ok(())
+}
+
+/// Validator: `directory_name` fields are valid globs
+fn validate_dir_names(input: &OneOrList<String>) -> StdResult<(), ValidationError> {
+    if input.is_empty() {
+        fail_valid!("empty_dir_names", "directory_name must not be an empty list");
+    }
+    input.iter().try_for_each(|x| validate_glob(x))
+}
+
+/// Validator: none of the `header` fields contain empty strings or nothing but wildcards
+///
+/// (Invalid hex is left to [`validate_filetype`] so the error can say which filetype it's in.)
+fn validate_headers(input: &OneOrList<Header>) -> StdResult<(), ValidationError> {
+    let headers = || input.iter().filter(|x| x.invalid_hex.is_none());
+    if input.is_empty() || headers().any(|x| x.is_empty()) {
         fail_valid!("empty_header", "Header patterns must not be empty sequences");
     }
+    if headers().any(|x| x.iter().all(Option::is_none)) {
+        fail_valid!("wildcard_header", "Header patterns must not consist entirely of ?? wildcards");
+    }
 
     Ok(())
 }
 
 /// Validator: every filetype definition maps an autodetection method to a handler
 ///
-/// **XXX:** Have overrides map to filetypes instead of handlers and allow an exception to this if
-/// "overrides" contains a glob that matches it?
+/// **XXX:** Allow an exception to this for filetypes which an `[override.<glob>]` forces via
+/// `filetype`?
 fn validate_filetype(input: &Filetype) -> StdResult<(), ValidationError> {
-    if input.extension.is_none() && input.header.is_none() {
+    let headers = input.header.as_deref().unwrap_or(&[]);
+    if let Some(text) = headers.iter().find_map(|x| x.invalid_hex.as_ref()) {
+        let problem = parse_hex(text).err().unwrap_or_default();
+        fail_valid!(
+            "invalid_hex",
+            format!("{} in header {:?} for filetype: {}", problem, text, input.description)
+        );
+    }
+    let matches_files = input.extension.is_some() || input.header.is_some();
+    if !matches_files && input.directory_name.is_none() {
         fail_valid!(
             "no_autodetect",
-            format!("Neither extension nor header set for filetype: {}", input.description)
+            format!("None of extension, header, or directory_name set for filetype: {}",
+                    input.description)
+        );
+    }
+    if matches_files && input.directory_name.is_some() {
+        fail_valid!(
+            "file_and_directory",
+            format!("directory_name can't be combined with extension or header for filetype: {}",
+                    input.description)
         );
     }
     if input.handler.is_none() && input.container.is_none() {
@@ -108,52 +280,123 @@ fn validate_filetype(input: &Filetype) -> StdResult<(), ValidationError> {
     Ok(())
 }
 
-/// Validator: none of the overrides are no-ops
-fn validate_override(input: &Override) -> StdResult<(), ValidationError> {
-    // Ignoring is a non-default effect
-    if input.ignore {
-        return Ok(());
+/// Validator: every `[override.<glob>]` table is keyed by a valid glob and isn't a no-op
+fn validate_overrides(input: &IndexMap<String, Override>) -> StdResult<(), ValidationError> {
+    for (glob, override_) in input {
+        validate_path_glob(glob)?;
+        match (&override_.filetype, &override_.handler) {
+            (None, None) => {
+                fail_valid!("noop_override", format!("Override has no effect: {}", glob));
+            },
+            (Some(_), Some(_)) => {
+                fail_valid!(
+                    "filetype_and_handler",
+                    format!("Override sets both filetype and handler: {}", glob)
+                );
+            },
+            _ => {},
+        }
+    }
+    Ok(())
+}
+
+/// Validator: the globs in `ignore` are non-empty and valid
+fn validate_ignore(input: &[String]) -> StdResult<(), ValidationError> {
+    input.iter().try_for_each(|x| validate_path_glob(x))
+}
+
+/// Validator: a glob which `[override.<glob>]` or `ignore` matches against paths is non-empty and
+/// valid
+fn validate_path_glob(input: &str) -> StdResult<(), ValidationError> {
+    if input.is_empty() {
+        fail_valid!("empty_glob", "Globbing pattern must not be empty");
     }
+    validate_glob(input)
+}
 
-    // Forcing a handler is a non-default effect
-    if let Some(ref handler) = input.handler {
-        if !handler.is_empty() {
-            return Ok(());
+/// Follow every candidate in `filetype`'s `container` (recursively), failing on cycles and unknown
+/// IDs, where `dep_chain` is the path of IDs taken to reach `filetype`
+fn validate_container_branches<'a>(input: &'a Root, filetype: &'a Filetype,
+                                   dep_chain: &mut Vec<&'a str>)
+        -> StdResult<(), ValidationError> {
+    for container in filetype.container.iter().flatten() {
+        let referrer = dep_chain[dep_chain.len() - 1];
+        let cycle = dep_chain.contains(&container.as_str());
+        dep_chain.push(container);
+        if cycle {
+            fail_valid!(
+                "container_cycle",
+                format!("Cyclical 'container' dependency: {}", dep_chain.join(" -> "))
+            );
+        }
+        if let Some(container_filetype) = input.filetypes.get(container) {
+            validate_container_branches(input, container_filetype, dep_chain)?;
+        } else {
+            fail_valid!(
+                "container_not_found",
+                format!("'container' for filetype {} not found: {}", referrer, container)
+            );
         }
+        dep_chain.pop();
     }
-    fail_valid!("noop_override", format!("Override has no effect: {}", input.path));
+    Ok(())
 }
 
-/// Validator: all filetypes have sane `container` dependencies
+/// Validator: all filetypes have sane `container` dependencies (no cycles and no unknown IDs,
+/// even for filetypes which have a `handler` of their own), overrides only force filetypes which
+/// exist, and no two filetypes claim the same extension without some way to tell them apart
 fn validate_root(input: &Root) -> StdResult<(), ValidationError> {
-    for (id, mut filetype) in &input.filetypes {
+    for (id, filetype) in &input.filetypes {
         // Don't bother allocating the dep_chain vec for entries without `container`
         if filetype.container.is_none() {
             continue;
         }
 
-        // Pre-allocate for the typical case of only one iteration plus the push() for error join()
+        // Pre-allocate for the typical case of only one level plus the push() for error join()
         // (Something like "cbz -> zip -> cbz")
         let mut dep_chain = Vec::with_capacity(3);
         dep_chain.push(id.as_str());
-
-        while let Some(container) = filetype.container.as_deref() {
-            let cycle = dep_chain.contains(&container);
-            dep_chain.push(&container);
-            if cycle {
+        validate_container_branches(input, filetype, &mut dep_chain)?;
+    }
+    for (glob, override_) in &input.overrides {
+        if let Some(id) = override_.filetype.as_deref() {
+            if !input.filetypes.contains_key(id) {
                 fail_valid!(
-                    "container_cycle",
-                    format!("Cyclical 'container' dependency: {}", dep_chain.join(" -> "))
+                    "filetype_not_found",
+                    format!("'filetype' for override {:?} not found: {}", glob, id)
                 );
             }
-            if let Some(container_filetype) = input.filetypes.get(container) {
-                filetype = container_filetype
-            } else {
+        }
+    }
+
+    // Check for filetypes which share an extension with no way to tell them apart
+    let mut claims: BTreeMap<String, Vec<(&str, &Filetype, _)>> = BTreeMap::new();
+    for (id, filetype) in &input.filetypes {
+        let mut headers: Vec<&Header> = filetype.header.iter().flat_map(|x| x.iter()).collect();
+        headers.sort_unstable();
+        headers.dedup();
+        let headers = (headers, filetype.header_offset);
+
+        let mut exts: Vec<String> = filetype.extension.iter().flat_map(|x| x.iter())
+            .map(|x| if input.case_sensitive_extensions { x.clone() } else { x.to_lowercase() })
+            .collect();
+        exts.sort_unstable();
+        exts.dedup();
+
+        for ext in exts {
+            let claimants = claims.entry(ext.clone()).or_default();
+            let clash = claimants.iter().find(|(_, other, other_headers)| {
+                *other_headers == headers && !(filetype.allow_ambiguous || other.allow_ambiguous)
+            });
+            if let Some((other_id, ..)) = clash {
                 fail_valid!(
-                    "container_not_found",
-                    format!("'container' for {} not found: {}", id, container)
+                    "ambiguous_extension",
+                    format!("Filetypes {} and {} both claim extension {:?} with no way to tell \
+                             them apart (give them different headers or set \
+                             allow_ambiguous = true)", other_id, id, ext)
                 );
             }
+            claimants.push((id, filetype, headers.clone()));
         }
     }
     Ok(())
@@ -162,16 +405,26 @@ fn validate_root(input: &Root) -> StdResult<(), ValidationError> {
 /// Validator: If present, the `sources` field must contain valid URLs
 ///
 /// **TODO:** Look into how much weight it would add to validate the format of these further.
-fn validate_sources(input: &OneOrList<String>) -> StdResult<(), ValidationError> {
-    if input.is_empty() || input.iter().any(String::is_empty) {
+fn validate_sources(input: &OneOrList<Source>) -> StdResult<(), ValidationError> {
+    if input.is_empty() || input.iter().any(|x| x.url.is_empty()) {
         fail_valid!("empty_handler", "Source list must be absent or contain non-empty strings");
     }
 
-    // Ensure users who already need help don't have to deal with esoteric protocols
-    for url in input.iter() {
-        if !(url.starts_with("http://") || url.starts_with("https://")) {
+    for source in input.iter() {
+        let url = match url::Url::parse(&source.url) {
+            Ok(url) if url.host_str().map_or(false, |x| !x.is_empty()) => url,
+            Ok(_) => fail_valid!("invalid_url", format!("Source URL has no host: {}", source.url)),
+            Err(err) => {
+                fail_valid!("invalid_url", format!("Invalid source URL {:?}: {}", source.url, err))
+            },
+        };
+        // Ensure users who already need help don't have to deal with esoteric protocols
+        if !matches!(url.scheme(), "http" | "https") {
             fail_valid!("invalid_url", "Only HTTP and HTTPS URLs are supported as sources");
         }
+        if source.note.as_deref() == Some("") {
+            fail_valid!("empty_note", "If provided, a source's 'note' must not be empty");
+        }
     }
     Ok(())
 }
@@ -199,46 +452,412 @@ impl<K, V: Validate> ValidateExtensions for BTreeMap<K, V> {
 /// Helper for fields which can contain one entry or a list of entries to keep the configuration
 /// file clean and easy to edit.
 ///
-/// **TODO:** Custom ser/de impl to round-trip a bare `T` in TOML as `vec![T]` so both the file and
-/// the code which consumes the config can be clean.
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(untagged)]
-pub enum OneOrList<T> {
-    /// Allow `T` as shorthand for `[T]` in the TOML
-    One(T),
-    /// Allow more than one `T` in the TOML
-    List(Vec<T>),
+/// (A single entry is serialized as a bare `T` and anything else as a list, so the output of
+/// `--dump-config` looks the way the file would be written by hand.)
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OneOrList<T>(Vec<T>);
+
+impl<T> OneOrList<T> {
+    /// Wrap a single entry
+    pub fn one(entry: T) -> Self {
+        Self(vec![entry])
+    }
+
+    /// Wrap a list of entries
+    pub fn list(entries: Vec<T>) -> Self {
+        Self(entries)
+    }
+
+    /// Iterate over the entries
+    pub fn iter(&self) -> std::slice::Iter<'_, T> {
+        self.0.iter()
+    }
+}
+
+impl<T> From<Vec<T>> for OneOrList<T> {
+    fn from(entries: Vec<T>) -> Self {
+        Self(entries)
+    }
 }
-use OneOrList::{List, One};
 
 impl<T> ::std::ops::Deref for OneOrList<T> {
     type Target = [T];
 
     fn deref(&self) -> &[T] {
-        match *self {
-            One(ref x) => std::slice::from_ref(x),
-            List(ref x) => x.as_slice(),
+        &self.0
+    }
+}
+
+impl<T> IntoIterator for OneOrList<T> {
+    type Item = T;
+    type IntoIter = std::vec::IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a OneOrList<T> {
+    type Item = &'a T;
+    type IntoIter = std::slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+impl<T: Serialize> Serialize for OneOrList<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> StdResult<S::Ok, S::Error> {
+        match self.0.as_slice() {
+            [entry] => entry.serialize(serializer),
+            entries => entries.serialize(serializer),
+        }
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for OneOrList<T> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> StdResult<Self, D::Error> {
+        /// The forms which a [`OneOrList`] can take in the TOML
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr<T> {
+            /// Allow `T` as shorthand for `[T]`
+            One(T),
+            /// Allow more than one `T`
+            List(Vec<T>),
+        }
+
+        Ok(match Repr::deserialize(deserializer)? {
+            Repr::One(entry) => Self::one(entry),
+            Repr::List(entries) => Self::list(entries),
+        })
+    }
+}
+
+/// Parse a duration given as a number of seconds or a human-friendly string like `90s`, `1.5m`,
+/// or `1h 30m`
+///
+/// (Accepted units are `ms`, `s`, `m`, and `h`, plus common spellings like `sec` and `mins`.
+/// A bare number is taken to be seconds.)
+pub fn parse_duration(input: &str) -> StdResult<Duration, String> {
+    let invalid = || format!("Invalid duration {:?} (expected something like 30s, 5m, or 1h30m)",
+                             input);
+    let input_trimmed = input.trim();
+    if let Ok(secs) = input_trimmed.parse::<f64>() {
+        return seconds_to_duration(secs);
+    }
+
+    let mut total = 0.0;
+    let mut rest = input_trimmed;
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+    while !rest.is_empty() {
+        let num_len = rest.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(rest.len());
+        let value: f64 = rest[..num_len].parse().map_err(|_| invalid())?;
+        rest = rest[num_len..].trim_start();
+
+        let unit_len = rest.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(rest.len());
+        let scale = match &rest[..unit_len] {
+            "ms" | "msec" | "msecs" => 0.001,
+            "s" | "sec" | "secs" | "second" | "seconds" => 1.0,
+            "m" | "min" | "mins" | "minute" | "minutes" => 60.0,
+            "h" | "hr" | "hrs" | "hour" | "hours" => 3600.0,
+            _ => return Err(invalid()),
+        };
+        total += value * scale;
+        rest = rest[unit_len..].trim_start();
+    }
+    seconds_to_duration(total)
+}
+
+/// Convert a number of seconds to a `Duration`, rejecting values which make no sense as timeouts
+fn seconds_to_duration(secs: f64) -> StdResult<Duration, String> {
+    if secs.is_finite() && secs > 0.0 {
+        Duration::try_from_secs_f64(secs).map_err(|e| e.to_string())
+    } else {
+        Err(format!("Durations must be greater than zero: {}", secs))
+    }
+}
+
+/// A positive length of time, written in the TOML as a number of seconds or a string accepted by
+/// [`parse_duration`]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(try_from = "DurationRepr", into = "DurationRepr")]
+pub struct Timeout(pub Duration);
+
+/// The forms which a [`Timeout`] can take in the TOML
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum DurationRepr {
+    /// A whole number of seconds
+    Seconds(u64),
+    /// A fractional number of seconds
+    Fractional(f64),
+    /// A human-friendly string like `1h30m`
+    Text(String),
+}
+
+impl TryFrom<DurationRepr> for Timeout {
+    type Error = String;
+
+    fn try_from(value: DurationRepr) -> StdResult<Self, String> {
+        match value {
+            DurationRepr::Seconds(secs) => seconds_to_duration(secs as f64),
+            DurationRepr::Fractional(secs) => seconds_to_duration(secs),
+            DurationRepr::Text(text) => parse_duration(&text),
+        }
+        .map(Timeout)
+    }
+}
+
+impl From<Timeout> for DurationRepr {
+    fn from(value: Timeout) -> Self {
+        if value.0.subsec_nanos() == 0 {
+            DurationRepr::Seconds(value.0.as_secs())
+        } else {
+            DurationRepr::Fractional(value.0.as_secs_f64())
+        }
+    }
+}
+
+/// Parse a size given as a number of bytes or a human-friendly string like `500MB`, `2GiB`, or
+/// `1.5 TB`
+///
+/// (Units may be decimal (`KB`, `MB`, `GB`, `TB`) or binary (`KiB`, `MiB`, `GiB`, `TiB`) and
+/// aren't case-sensitive. A bare number is taken to be bytes.)
+pub fn parse_size(input: &str) -> StdResult<u64, String> {
+    let invalid = || format!("Invalid size {:?} (expected something like 500MB or 2GiB)", input);
+    let input_trimmed = input.trim();
+    let num_len = input_trimmed
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(input_trimmed.len());
+    let (number, unit) = input_trimmed.split_at(num_len);
+    let scale: u64 = match unit.trim_start().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        "tb" => 1_000_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        _ => return Err(invalid()),
+    };
+
+    // Only fall back to floating point for fractions, so large whole sizes stay exact
+    let bytes = match number.parse::<u64>() {
+        Ok(whole) => whole.checked_mul(scale),
+        Err(_) => {
+            let bytes = number.parse::<f64>().map_err(|_| invalid())? * scale as f64;
+            Some(bytes.round()).filter(|x| *x < u64::MAX as f64).map(|x| x as u64)
+        },
+    };
+    match bytes {
+        Some(0) => Err(format!("Sizes must be at least one byte: {}", input_trimmed)),
+        Some(bytes) => Ok(bytes),
+        None => Err(format!("Size is too large: {}", input_trimmed)),
+    }
+}
+
+/// A positive number of bytes, written in the TOML as a number or a string accepted by
+/// [`parse_size`]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(try_from = "SizeRepr", into = "SizeRepr")]
+pub struct SizeLimit(pub u64);
+
+/// The forms which a [`SizeLimit`] can take in the TOML
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum SizeRepr {
+    /// A number of bytes
+    Bytes(u64),
+    /// A human-friendly string like `2GiB`
+    Text(String),
+}
+
+impl TryFrom<SizeRepr> for SizeLimit {
+    type Error = String;
+
+    fn try_from(value: SizeRepr) -> StdResult<Self, String> {
+        match value {
+            SizeRepr::Bytes(0) => Err("Sizes must be at least one byte: 0".to_owned()),
+            SizeRepr::Bytes(bytes) => Ok(bytes),
+            SizeRepr::Text(text) => parse_size(&text),
+        }
+        .map(SizeLimit)
+    }
+}
+
+impl From<SizeLimit> for SizeRepr {
+    /// Use the largest binary unit which represents the size exactly, if any
+    fn from(value: SizeLimit) -> Self {
+        let units = [("TiB", 1_u64 << 40), ("GiB", 1 << 30), ("MiB", 1 << 20), ("KiB", 1 << 10)];
+        for (unit, scale) in &units {
+            if value.0 % scale == 0 {
+                return SizeRepr::Text(format!("{}{}", value.0 / scale, unit));
+            }
+        }
+        SizeRepr::Bytes(value.0)
+    }
+}
+
+/// Decode a string of hex digits like `89504E47` or `89 50 4e 47` into a header pattern, where
+/// `??` stands for a byte which may have any value
+pub fn parse_hex(input: &str) -> StdResult<Vec<Option<u8>>, String> {
+    let is_digit = |x: char| x.is_ascii_hexdigit() || x == '?';
+    if let Some(bad) = input.chars().find(|x| !is_digit(*x) && !x.is_whitespace()) {
+        return Err(format!("{:?} is not a hex digit", bad));
+    }
+    let digits: Vec<char> = input.chars().filter(|x| is_digit(*x)).collect();
+    if digits.len() % 2 != 0 {
+        return Err("Odd number of hex digits".to_owned());
+    }
+    digits
+        .chunks(2)
+        .map(|pair| match (pair[0].to_digit(16), pair[1].to_digit(16)) {
+            (Some(high), Some(low)) => Ok(Some((high << 4 | low) as u8)),
+            (None, None) => Ok(None),
+            _ => Err(format!("\"{}{}\" is neither a byte nor a ?? wildcard", pair[0], pair[1])),
+        })
+        .collect()
+}
+
+/// Compile a glob from the configuration file, so every part of the program that uses one
+/// agrees on what's valid
+///
+/// Alternations with nothing in them (eg. `{}` or `{,}`) are rejected, since they're almost
+/// certainly mistakes, even though `globset` accepts them.
+pub fn build_glob(pattern: &str) -> StdResult<Glob, String> {
+    let invalid = |reason: &dyn std::fmt::Display| {
+        format!("Invalid globbing pattern {:?}: {}", pattern, reason)
+    };
+    if has_empty_alternation(pattern) {
+        return Err(invalid(&"empty alternation"));
+    }
+    GlobBuilder::new(pattern).build().map_err(|err| invalid(err.kind()))
+}
+
+/// Check whether `pattern` contains a `{...}` alternation with no non-empty alternatives
+fn has_empty_alternation(pattern: &str) -> bool {
+    let mut chars = pattern.chars().peekable();
+    let (mut in_class, mut alternation_empty) = (false, None);
+    while let Some(chr) = chars.next() {
+        match chr {
+            _ if in_class => in_class = chr != ']',
+            '{' => alternation_empty = Some(true),
+            '}' if alternation_empty == Some(true) => return true,
+            '}' => alternation_empty = None,
+            ',' => {},
+            _ => {
+                if chr == '\\' {
+                    chars.next();
+                } else if chr == '[' {
+                    // A `]` right at the start (after any negation) is part of the class
+                    in_class = true;
+                    chars.next_if(|x| *x == '!' || *x == '^');
+                    chars.next_if_eq(&']');
+                }
+                alternation_empty = alternation_empty.map(|_| false);
+            },
+        }
+    }
+    false
+}
+
+/// A pattern to match against the start of a file, written in the TOML as either a list of byte
+/// values or a string accepted by [`parse_hex`]
+///
+/// (Hex which can't be decoded is kept rather than rejected during deserialization, since
+/// `OneOrList` would replace the error with an unhelpful one about matching no variant, and
+/// validation can say which filetype it's in.)
+#[derive(Clone, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(from = "HeaderRepr", into = "HeaderRepr")]
+pub struct Header {
+    /// The bytes to match, with `None` for wildcards
+    pattern: Vec<Option<u8>>,
+    /// The text of the header, if it was given as hex which couldn't be decoded
+    invalid_hex: Option<String>,
+}
+
+impl Header {
+    /// Check whether `data` starts with this pattern
+    pub fn matches(&self, data: &[u8]) -> bool {
+        data.len() >= self.pattern.len()
+            && self.pattern.iter().zip(data).all(|(x, y)| x.map_or(true, |x| x == *y))
+    }
+}
+
+/// The forms which a [`Header`] can take in the TOML
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum HeaderRepr {
+    /// A list of byte values
+    Bytes(Vec<u8>),
+    /// A string of hex digits
+    Hex(String),
+}
+
+impl From<HeaderRepr> for Header {
+    fn from(value: HeaderRepr) -> Self {
+        match value {
+            HeaderRepr::Bytes(bytes) => {
+                Self { pattern: bytes.into_iter().map(Some).collect(), invalid_hex: None }
+            },
+            HeaderRepr::Hex(text) => match parse_hex(&text) {
+                Ok(pattern) => Self { pattern, invalid_hex: None },
+                Err(_) => Self { pattern: Vec::new(), invalid_hex: Some(text) },
+            },
         }
     }
 }
 
+impl From<Header> for HeaderRepr {
+    fn from(value: Header) -> Self {
+        let Header { pattern, invalid_hex } = value;
+        HeaderRepr::Hex(invalid_hex.unwrap_or_else(|| {
+            pattern
+                .iter()
+                .map(|x| x.map_or_else(|| "??".to_owned(), |x| format!("{:02X}", x)))
+                .collect::<Vec<_>>()
+                .join(" ")
+        }))
+    }
+}
+
+impl ::std::ops::Deref for Header {
+    type Target = [Option<u8>];
+
+    fn deref(&self) -> &[Option<u8>] {
+        &self.pattern
+    }
+}
+
 // ----==== Configuration Schema ====----
 
 /// Definition of `[[filetype]]` tables.
 #[derive(Debug, Deserialize, Serialize, Validate)]
 #[validate(schema(function = "validate_filetype"))]
+#[serde(deny_unknown_fields)]
 pub struct Filetype {
     /// The id of another filetype that this is a specialization of.
     /// (eg. OpenDocument and CBZ are specialized forms of Zip files.)
+    ///
+    /// If more than one is given (eg. for formats which exist in both a TIFF-based and a
+    /// BigTIFF-based form), they're tried in order when this filetype has no `handler` of its
+    /// own, moving on to the next whenever one says the file isn't valid.
     #[serde(skip_serializing_if = "Option::is_none")]
-    #[validate(length(min = 1, message = "'container' must not be an empty string if present"))]
-    pub container: Option<String>,
+    #[validate(custom = "validate_containers")]
+    pub container: Option<OneOrList<String>>,
 
     /// A human-readable description for use in status messages
     #[validate(length(min = 1, message = "'description' must not be an empty string"))]
     pub description: String,
 
     /// One or more extensions to identify the file by
+    ///
+    /// Multi-part extensions like `tar.gz` are supported and the longest match wins.
     #[serde(skip_serializing_if = "Option::is_none")]
     #[validate(custom = "validate_exts")]
     pub extension: Option<OneOrList<String>>,
@@ -254,15 +873,16 @@ pub struct Filetype {
     /// If fallback is necessary to tell apart several formats which share the same extension
     /// and/or header (eg. `.exe` possibly being multiple different kinds of self-extracting
     /// archives), then specify multiple `[filetype.*]` sections with the same or overlapping
-    /// `extension` and `header` content.
+    /// `extension` and `header` content. (If they're identical, set `allow_ambiguous` too.)
     #[serde(skip_serializing_if = "Option::is_none")]
     #[validate(custom = "validate_handlers")]
     pub handler: Option<OneOrList<String>>,
 
-    /// One or more headers to identify the file type by
+    /// One or more headers to identify the file type by, each written as a list of byte values
+    /// or a string of hex digits (eg. `"89 50 4E 47"`) where `??` matches any byte
     #[serde(skip_serializing_if = "Option::is_none")]
     #[validate(custom = "validate_headers")]
-    pub header: Option<OneOrList<Vec<u8>>>,
+    pub header: Option<OneOrList<Header>>,
 
     /// The number of bytes to skip before attempting to match the header
     ///
@@ -270,41 +890,104 @@ pub struct Filetype {
     #[serde(default, skip_serializing_if = "is_zero")]
     pub header_offset: usize,
 
-    /// A special case for the image verifier
+    /// One or more globs to match against the names of directories which should be handed to
+    /// this filetype's handlers as a whole (eg. `VIDEO_TS` or `*.app`) instead of being walked
+    ///
+    /// Mutually exclusive with `extension` and `header`, and all handlers must have
+    /// `input = "directory"`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[validate(custom = "validate_dir_names")]
+    pub directory_name: Option<OneOrList<String>>,
+
+    /// If `true`, validate the members of this archive as if they were separate files after
+    /// the archive itself passes.
     ///
-    /// **TODO:** Refactor to either remove this or turn it into a BTreeMap for arbitrary keys
-    /// passed to builtin handlers and exposed to the argv string substitution.
+    /// Applies to any filetype which names this one as its `container`.
+    ///
+    /// For GZip, the payload's filetype is detected by stripping the `.gz` extension.
+    ///
+    /// **TODO:** Support archive formats other than Zip and GZip.
     #[serde(default, skip_serializing_if = "Not::not")]
-    pub multipage: bool,
-}
+    pub recurse: bool,
 
-/// Definition of `[[override]]` tables.
-#[derive(Debug, Deserialize, Serialize, Validate)]
-#[validate(schema(function = "validate_override"))]
-pub struct Override {
-    /// A globbing pattern for files this rule should match
-    #[validate(length(min = 1, message = "Globbing pattern must not be empty"))]
-    pub path: String,
+    /// The order in which to try filetypes which match the same file (eg. different kinds of
+    /// self-extracting `.exe`), with higher numbers tried first and ties broken by ID.
+    ///
+    /// All matching filetypes act as a fallback chain, so the file passes if any of them passes
+    /// it, and an `InvalidContent` failure only ends the chain if it came from the last one.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub priority: i32,
+
+    /// If `true`, sharing an extension with another filetype which has the same `header` and
+    /// `header_offset` (or also has no `header`) is intentional rather than a mistake.
+    ///
+    /// (Without this, such filetypes are an error, since one of them is probably never meant
+    /// to be tried first.)
+    #[serde(default, skip_serializing_if = "Not::not")]
+    pub allow_ambiguous: bool,
+
+    /// If `true`, zero-byte files of this type are reported as valid rather than as failures.
+    ///
+    /// (Empty files are never passed to handlers, since they're almost always the result of a
+    /// failed download or copy, so this is the only say a filetype gets in their verdict.)
+    #[serde(default, skip_serializing_if = "Not::not")]
+    pub valid_if_empty: bool,
+
+    /// If specified, files of this type which are larger than this (eg. `"2GiB"` or a number of
+    /// bytes) are reported as skipped rather than passed to a handler.
+    ///
+    /// (For things like multi-hundred-gigabyte disk images, where checking them in a routine scan
+    /// would take far too long. Takes precedence over `--max-size`.)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<SizeLimit>,
 
-    /// If specified, a file `handler` to apply to the path instead of relying on autodetection.
+    /// Arbitrary settings (eg. `multipage = "true"`) passed to built-in handlers and exposed to
+    /// external ones as `{arg:key}` substitution tokens
     ///
-    /// Has no effect when the glob matches a directory.
+    /// Built-in handlers ignore keys they don't recognize, and `{arg:key}` expands to an empty
+    /// string if this filetype doesn't set `key`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[validate(custom = "validate_handler_args")]
+    pub handler_args: HandlerArgs,
+}
+
+/// The type of [`Filetype::handler_args`]
+pub type HandlerArgs = BTreeMap<String, String>;
+
+/// An empty [`HandlerArgs`] for handlers which aren't being run on behalf of a filetype
+pub static NO_HANDLER_ARGS: HandlerArgs = BTreeMap::new();
+
+/// Definition of `[override.<glob>]` tables.
+///
+/// The glob is matched against the path relative to whichever root passed on the command line it
+/// was found under, and `*` may match across `/`.
+///
+/// If more than one override matches a file, the one which appears last in the configuration
+/// file wins, so general rules should come before specific exceptions.
+///
+/// (Before `ignore` became a list of its own, these were written as `[[override]]` tables with
+/// a `path` key. That form is still accepted, but rewritten with a deprecation warning.)
+#[derive(Debug, Deserialize, Eq, PartialEq, Serialize, Validate)]
+#[serde(deny_unknown_fields)]
+pub struct Override {
+    /// The file `handler` to apply to the path instead of relying on autodetection.
     ///
-    /// **NOTE:** At some point, I may need to extend the design to also support handlers that
-    /// take a *directory* path as input without risking feeding directories with file-like names
-    /// to handlers that only expect files.
+    /// If every handler in the chain has `input = "directory"`, the glob matches directories
+    /// instead of files, and the matching directories aren't descended into.
     #[serde(skip_serializing_if = "Option::is_none")]
     #[validate(custom = "validate_handlers")]
     pub handler: Option<OneOrList<String>>,
 
-    /// If `true`, don't process files or descend into directories matching the given glob.
+    /// The ID of a `[filetype.*]` to treat the path as, instead of relying on autodetection.
     ///
-    /// **TODO:** Disentangle `handler` and `ignore` overrides. Aside from "make invalid states
-    /// unrepresentable" (custom handler and ignore=true), using the `ignore` crate means that the
-    /// `message` field can't apply to overrides, so it makes more sense to do something like
-    /// having an ignores `Vec` and a handler overrides `BTreeMap` at the top level.
-    #[serde(default, skip_serializing_if = "Not::not")]
-    pub ignore: bool,
+    /// Unlike `handler`, this gets everything the filetype definition specifies (`container`,
+    /// `handler_args`, recursion into archive members, etc.) and results are reported under its
+    /// `description`. If it has a `directory_name`, the glob matches directories instead of
+    /// files.
+    ///
+    /// May not be combined with `handler`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filetype: Option<String>,
 
     /// The status message to display if this override matches a path.
     /// May be omitted to avoid displaying a message.
@@ -313,37 +996,265 @@ pub struct Override {
     pub message: Option<String>,
 }
 
-/// Definition of `[handler.*]` tables.
-#[derive(Debug, Deserialize, Serialize, Validate)]
-pub struct Handler {
-    /// A template for the command to invoke via `[std::process::Command]`.
-    ///
-    /// The following substitution tokens are available:
-    ///
-    /// * `{path}`: The path to the file to be validated.
-    /// * `{devnull}`: The path to `/dev/null` or equivalent, suitable for subprocesses which
-    ///    insist on producing an output file when used to check for errors.
-    ///
-    /// To simplify the common case, `{path}` will be appended to the end of the `Vec` if no
-    /// entries contain substitution tokens.
-    #[validate(length(min = 1, message = "'argv' must not be empty"), custom = "validate_argv")]
-    pub argv: Vec<String>,
+/// What kind of input a `[handler.*]` expects to be given
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InputKind {
+    /// A regular file
+    #[default]
+    File,
+    /// A directory, which won't be walked into
+    Directory,
+    /// A regular file, whose contents will be fed to the command's `stdin` instead of passing
+    /// its path (for tools like `xmllint --noout -` or `jq empty`)
+    Stdin,
+}
 
-    /// A human-readable description for use in status messages instead of the command name from
-    /// `argv[0]` when indicating what needs to be installed.
+/// How a `[handler.*]` command's exit codes should be interpreted
+///
+/// Codes not listed in any category mean the file is corrupt.
+#[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExitCodes {
+    /// Exit codes which mean the file passed (`[0]` if not specified)
     ///
-    /// Should include the definite article "the" if necessary to fit in a message like
-    /// "Could not find {description}. Please install it from one of the following URLs:" but,
-    /// by convention, it should also be capitalized suitably for display alone.
+    /// (Useful for tools like `7z` which return `1` for non-fatal warnings.)
+    #[serde(default = "ExitCodes::default_success")]
+    pub success: Vec<i32>,
+
+    /// Exit codes which mean the tool doesn't support this file, so the next handler in the
+    /// fallback chain should be tried instead
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unsupported: Vec<i32>,
+
+    /// Exit codes which mean something went wrong with the tool itself (eg. a usage error), so
+    /// the file couldn't be verified
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub internal: Vec<i32>,
+}
+
+impl ExitCodes {
+    /// The default value for [`success`](Self::success)
+    fn default_success() -> Vec<i32> {
+        vec![0]
+    }
+}
+
+impl Default for ExitCodes {
+    fn default() -> Self {
+        Self { success: Self::default_success(), unsupported: Vec::new(), internal: Vec::new() }
+    }
+}
+
+/// Resource limits for a `[handler.*]` command, so a tool sent into a runaway state by a corrupt
+/// file can't take the whole system down with it
+#[derive(Debug, Default, Deserialize, Eq, PartialEq, Serialize, Validate)]
+#[serde(deny_unknown_fields)]
+pub struct Limits {
+    /// The maximum amount of (virtual) memory the command may use, in MiB
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[validate(range(min = 1, message = "limits.memory_mb must be at least 1"))]
+    pub memory_mb: Option<u64>,
+
+    /// The maximum amount of CPU time the command may use, in seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[validate(range(min = 1, message = "limits.cpu_seconds must be at least 1"))]
+    pub cpu_seconds: Option<u64>,
+}
+
+impl Limits {
+    /// Whether any limits are set
+    pub fn is_empty(&self) -> bool {
+        self.memory_mb.is_none() && self.cpu_seconds.is_none()
+    }
+}
+
+/// The I/O scheduling classes a `[handler.*]` command can be demoted to on Linux
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IoClass {
+    /// Share disk bandwidth with everything else, but don't outrank it
+    BestEffort,
+    /// Only get disk time when nothing else wants it
+    Idle,
+}
+
+impl IoClass {
+    /// The class number `ionice -c` expects
+    pub fn number(self) -> u8 {
+        match self {
+            Self::BestEffort => 2,
+            Self::Idle => 3,
+        }
+    }
+}
+
+/// The operating systems a `[handler.*]` entry can be restricted to
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Platform {
+    /// Linux
+    Linux,
+    /// macOS
+    Macos,
+    /// Microsoft Windows
+    Windows,
+}
+
+impl Platform {
+    /// The platform this binary was built for, or `None` if it's not one which can be named in
+    /// `platforms`
+    pub fn current() -> Option<Self> {
+        if cfg!(target_os = "linux") {
+            Some(Self::Linux)
+        } else if cfg!(target_os = "macos") {
+            Some(Self::Macos)
+        } else if cfg!(windows) {
+            Some(Self::Windows)
+        } else {
+            None
+        }
+    }
+}
+
+/// A place a `[handler.*]` command can be installed from, written in the TOML as either a bare
+/// URL or a table like `{ url = "...", platform = "windows", note = "official build" }`
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(from = "SourceRepr", into = "SourceRepr")]
+pub struct Source {
+    /// Where to go
+    pub url: String,
+    /// The platform the link is for, if it isn't for all of them
+    pub platform: Option<Platform>,
+    /// Anything else worth knowing about the link (eg. `"official build"`)
+    pub note: Option<String>,
+}
+
+/// The forms which a [`Source`] can take in the TOML
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum SourceRepr {
+    /// Just the URL
+    Url(String),
+    /// The URL with details about it
+    Table {
+        /// See [`Source::url`]
+        url: String,
+        /// See [`Source::platform`]
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        platform: Option<Platform>,
+        /// See [`Source::note`]
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        note: Option<String>,
+    },
+}
+
+impl From<SourceRepr> for Source {
+    fn from(value: SourceRepr) -> Self {
+        match value {
+            SourceRepr::Url(url) => Self { url, platform: None, note: None },
+            SourceRepr::Table { url, platform, note } => Self { url, platform, note },
+        }
+    }
+}
+
+impl From<Source> for SourceRepr {
+    fn from(value: Source) -> Self {
+        match value {
+            Source { url, platform: None, note: None } => SourceRepr::Url(url),
+            Source { url, platform, note } => SourceRepr::Table { url, platform, note },
+        }
+    }
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.url)?;
+        match self.note {
+            Some(ref note) => write!(f, " ({})", note),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Definition of `[handler.*]` tables.
+#[derive(Debug, Deserialize, Serialize, Validate)]
+#[validate(schema(function = "validate_handler"))]
+#[serde(deny_unknown_fields)]
+pub struct Handler {
+    /// A template for the command to invoke via `[std::process::Command]`.
+    ///
+    /// The following substitution tokens are available:
+    ///
+    /// * `{path}`: The path to the file to be validated.
+    /// * `{devnull}`: The path to `/dev/null` or equivalent, suitable for subprocesses which
+    ///    insist on producing an output file when used to check for errors.
+    /// * `{basename}`: The file name from `{path}`, including its extension.
+    /// * `{ext}`: The part of `{basename}` after the last period (eg. `gz` for `foo.tar.gz`), or
+    ///    an empty string if it has no extension.
+    /// * `{dir}`: The directory containing `{path}`, or `.` if it's a bare filename.
+    /// * `{tmpdir}`: A freshly-created scratch directory, for tools which insist on writing
+    ///    output somewhere. It's deleted once the command exits, whatever the outcome.
+    ///
+    /// To simplify the common case, `{path}` will be appended to the end of the `Vec` if no
+    /// entries contain it.
+    ///
+    /// Any other `{...}` token is rejected, rather than silently being passed through literally.
+    #[validate(length(min = 1, message = "'argv' must not be empty"), custom = "validate_argv")]
+    pub argv: Vec<String>,
+
+    /// If specified, the working directory to run the command in.
+    ///
+    /// Accepts the same substitution tokens as `argv` (eg. `cwd = "{dir}"` for tools which must
+    /// be run from the directory containing the file).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[validate(custom = "validate_tokens")]
+    pub cwd: Option<String>,
+
+    /// Environment variables to set for the command (eg. `LANG = "C"` so messages matched by
+    /// `fail_if_stderr` aren't localized).
+    ///
+    /// Values accept the same substitution tokens as `argv` (eg. `TMPDIR = "{tmpdir}"`).
+    ///
+    /// **NOTE:** Overriding `PATH` also changes how `argv[0]` is looked up.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[validate(custom = "validate_env")]
+    pub env: BTreeMap<String, String>,
+
+    /// If `true`, start the command with an empty environment (aside from `env`) rather than
+    /// inheriting ours, for reproducibility.
+    #[serde(default, skip_serializing_if = "Not::not")]
+    pub env_clear: bool,
+
+    /// A human-readable description for use in status messages instead of the command name from
+    /// `argv[0]` when indicating what needs to be installed.
+    ///
+    /// Should include the definite article "the" if necessary to fit in a message like
+    /// "Could not find {description}. Please install it from one of the following URLs:" but,
+    /// by convention, it should also be capitalized suitably for display alone.
     #[serde(skip_serializing_if = "Option::is_none")]
     #[validate(length(min = 1, message = "If provided, 'description' must not be empty"))]
     pub description: Option<String>,
 
-    /// If present and non-empty, the command will be considered to have failed if its output to
+    /// If specified, how much the command passing a file says about it (eg. `"well-formed"` for
+    /// a parser which doesn't check any checksums, or `"data-hash"` for one which does), so
+    /// reports can say how well each file is protected
+    ///
+    /// See [`Confidence`] for the possible values.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<Confidence>,
+
+    /// Whether `{path}` will be a file or a directory (eg. a `VIDEO_TS` folder or a Git
+    /// repository), or whether the file will be fed to `stdin` instead
+    ///
+    /// Directory handlers are only ever given directories matched by `directory_name` or by an
+    /// `[override.<glob>]`, so they can't be fed directories with file-like names by accident.
     ///
-    /// **NOTE:** At some point, I may need to extend the design to also support handlers that
-    /// take a *directory* path as input without risking feeding directories with file-like names
-    /// to handlers that only expect files.
+    /// With `input = "stdin"`, `{path}` may not be used and isn't appended to `argv`.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub input: InputKind,
+
+    /// If present and non-empty, the command will be considered to have failed if its output to
     /// `stderr` contains the given string, even if it returns an exit code that indicates success.
     ///
     /// Being present but empty is considered an error to avoid allowing an "unintended state that
@@ -352,7 +1263,68 @@ pub struct Handler {
     #[validate(length(min = 1, message = "If provided, 'fail_if_stderr' must not be empty"))]
     pub fail_if_stderr: Option<String>,
 
-    /// If specified, one or more URLs from which the handler can be installed.
+    /// How to interpret the command's exit codes, for tools which use different codes to
+    /// distinguish a damaged file from one they don't support.
+    ///
+    /// For example, `exit_codes.success = [0, 1]` and `exit_codes.unsupported = [2]`.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    #[validate(custom = "validate_exit_codes")]
+    pub exit_codes: ExitCodes,
+
+    /// Resource limits to apply to the command (eg. `limits.memory_mb = 2048`).
+    ///
+    /// A command which hits a limit is reported as having been unable to verify the file rather
+    /// than as having found it corrupt.
+    ///
+    /// **NOTE:** Only supported on Unix-like platforms. Elsewhere, a warning is logged and the
+    /// command is run without limits.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    #[validate]
+    pub limits: Limits,
+
+    /// If specified, the maximum number of instances of this command to run at once (eg. `1`
+    /// for a virus scanner which already uses all available cores), in addition to the limit
+    /// set by `--subprocess-jobs`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[validate(range(min = 1, message = "If provided, 'max_concurrency' must be at least 1"))]
+    pub max_concurrency: Option<usize>,
+
+    /// If specified, how much to lower the command's CPU priority (from 1 to 19, like `nice -n`)
+    /// so heavy tools don't make the machine unusable for interactive work.
+    ///
+    /// This is added to any lowering requested by `--nice`. On Windows, any value just runs the
+    /// command at below-normal priority.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[validate(range(min = 1, max = 19, message = "If provided, 'nice' must be from 1 to 19"))]
+    pub nice: Option<u8>,
+
+    /// If specified, the I/O scheduling class (`"best-effort"` or `"idle"`) to run the command
+    /// in, for disk-heavy tools.
+    ///
+    /// **NOTE:** Only supported on Linux. Elsewhere, a warning is logged and it's ignored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ionice_class: Option<IoClass>,
+
+    /// If specified, how long to let the command run before killing it.
+    ///
+    /// May be given as a number of seconds or a string like `"90s"` or `"1h30m"`. If omitted,
+    /// the `--handler-timeout` command-line option applies.
+    ///
+    /// A handler which times out is reported as having been unable to verify the file rather than
+    /// as having found it corrupt, since hanging isn't evidence of corruption.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<Timeout>,
+
+    /// If specified, the platforms (`"linux"`, `"macos"`, or `"windows"`) the command exists on.
+    ///
+    /// Elsewhere, the handler is treated as unavailable (so fallback chains move on to the next
+    /// handler) and `--check-handlers` doesn't ask for it to be installed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[validate(length(min = 1, message = "If provided, 'platforms' must not be empty"))]
+    pub platforms: Option<Vec<Platform>>,
+
+    /// If specified, one or more URLs from which the handler can be installed, each of which may
+    /// instead be a table which also says which `platform` it's for and/or gives a `note`.
     ///
     /// By convention:
     ///
@@ -368,16 +1340,75 @@ pub struct Handler {
     ///
     /// It is acceptable to link to the website for Cygwin if the only suitable Windows port is
     /// provided as part of Cygwin.
+    ///
+    /// (When asking for the handler to be installed, links labelled with the current `platform`
+    /// are shown first and ones labelled with other platforms are left out.)
     #[serde(skip_serializing_if = "Option::is_none")]
     #[validate(custom = "validate_sources")]
-    pub sources: Option<OneOrList<String>>,
+    pub sources: Option<OneOrList<Source>>,
+}
+
+impl Handler {
+    /// Whether the command is meant to be used on the given platform
+    ///
+    /// (Usually given [`Platform::current`], but a parameter so tests don't depend on the
+    /// platform they're run on.)
+    pub fn runs_on(&self, platform: Option<Platform>) -> bool {
+        self.platforms.as_ref().map_or(true, |x| platform.map_or(false, |y| x.contains(&y)))
+    }
+
+    /// The `sources` to suggest for installing the command on the given platform, with links
+    /// labelled for it first, then unlabelled ones
+    ///
+    /// (If every link is for some other platform, they're all returned, since that's still
+    /// better than nothing.)
+    pub fn sources_for(&self, platform: Option<Platform>) -> Vec<&Source> {
+        let sources = self.sources.as_deref().unwrap_or(&[]);
+        let mut matching: Vec<_> =
+            sources.iter().filter(|x| x.platform.is_some() && x.platform == platform).collect();
+        matching.extend(sources.iter().filter(|x| x.platform.is_none()));
+        if matching.is_empty() {
+            sources.iter().collect()
+        } else {
+            matching
+        }
+    }
 }
 
 /// Root of the configuration schema
 ///
 #[derive(Debug, Deserialize, Serialize, Validate)]
 #[validate(schema(function = "validate_root"))]
+#[serde(deny_unknown_fields)]
 pub struct Root {
+    /// The version of this schema the file was written for, so files written for older versions
+    /// can be migrated (as described on [`migrate`]) and ones for newer versions rejected
+    ///
+    /// Files which don't specify one are assumed to be version 1, from before it existed, and
+    /// it's always [`CONFIG_VERSION`] once parsing is done.
+    #[serde(default = "current_config_version")]
+    pub config_version: u32,
+
+    /// If `true`, match `extension` fields against filenames case-sensitively.
+    ///
+    /// Defaults to `false` so that, for example, `IMG_0001.JPG` is matched by `extension = "jpg"`.
+    #[serde(default, skip_serializing_if = "Not::not")]
+    pub case_sensitive_extensions: bool,
+
+    /// If `true`, keys which don't mean anything (eg. typos like `extenson`) and likely mistakes
+    /// found by [`lint`] (eg. unrecognized handler IDs) are errors rather than warnings. (Also
+    /// enabled by `--strict-config`.)
+    #[serde(default, skip_serializing_if = "Not::not")]
+    pub strict: bool,
+
+    /// Globs for files not to process and directories not to descend into.
+    ///
+    /// They're matched the same way as the globs for `[override.<glob>]` tables, and paths
+    /// they match are only mentioned in the debug output.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[validate(custom = "validate_ignore")]
+    pub ignore: Vec<String>,
+
     /// A list of filetype definitions, including mappings to handlers.
     ///
     /// It is represented as a hashmap to ensure that each filetype has a unique identifer. This
@@ -395,10 +1426,14 @@ pub struct Root {
     #[serde(rename = "filetype", default)]
     pub filetypes: BTreeMap<String, Filetype>,
 
-    /// A list of rules for overriding `filetypes` or excluding folder for specific globs.
+    /// Rules for forcing a handler for paths matching specific globs, keyed by the glob.
+    ///
+    /// (An `IndexMap` rather than a `BTreeMap` because the order they were written in decides
+    /// which wins when more than one matches.)
     #[validate]
+    #[validate(custom = "validate_overrides")]
     #[serde(rename = "override", default)]
-    pub overrides: Vec<Override>,
+    pub overrides: IndexMap<String, Override>,
 
     /// A list of *external* handler definitions to be used by `filetypes` and `overrides`.
     /// (This list includes only subprocesses, not built-in handlers)
@@ -407,6 +1442,190 @@ pub struct Root {
     pub handlers: BTreeMap<String, Handler>,
 }
 
+/// A user configuration file, which is applied on top of the default configuration
+///
+/// Filetypes and handlers replace any default entries with the same ID (or are added if there
+/// are none), overrides replace any default ones for the same glob and take precedence over the
+/// rest, `ignore` globs are added to the default ones, and anything listed in
+/// [`disabled`](Self::disabled) is removed from the defaults.
+///
+/// Files named in [`include`](Self::include) are merged in before that happens.
+///
+/// (Repeating a default entry has no effect, so the output of `--dump-config` can be used as an
+/// overlay.)
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Overlay {
+    /// The same as [`Root::config_version`]
+    ///
+    /// (Only needed by [`migrate`], which reads it before deserialization, since the overlay can
+    /// be older or newer than the defaults.)
+    #[allow(dead_code)]
+    pub config_version: Option<u32>,
+
+    /// If specified, replaces [`Root::case_sensitive_extensions`]
+    pub case_sensitive_extensions: Option<bool>,
+
+    /// If specified, replaces [`Root::strict`]
+    pub strict: Option<bool>,
+
+    /// Other overlay files to merge into this one, as paths (or globs, matched like a shell
+    /// would) relative to the directory of the file that includes them
+    ///
+    /// Each is applied on top of the including file in the order listed (with the files a glob
+    /// matches in sorted order) the same way the merged result is applied on top of the defaults,
+    /// except that defining a filetype or handler ID in more than one file is an error.
+    #[serde(default)]
+    pub include: Vec<String>,
+
+    /// Filetype definitions to add or replace
+    #[serde(rename = "filetype", default)]
+    pub filetypes: BTreeMap<String, Filetype>,
+
+    /// Globs to ignore in addition to the default ones
+    #[serde(default)]
+    pub ignore: Vec<String>,
+
+    /// Override rules to add or replace
+    #[serde(rename = "override", default)]
+    pub overrides: IndexMap<String, Override>,
+
+    /// External handler definitions to add or replace
+    #[serde(rename = "handler", default)]
+    pub handlers: BTreeMap<String, Handler>,
+
+    /// IDs of default entries to remove
+    #[serde(default)]
+    pub disabled: Disabled,
+}
+
+/// Definition of the `[disabled]` table in an [`Overlay`]
+///
+/// (A `[filetype.<id>]` or `[handler.<id>]` table containing only `disabled = true` is accepted
+/// as another way to write an entry here, as described on [`deserialize_overlay`].)
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Disabled {
+    /// IDs of default `[filetype.*]` entries to remove
+    #[serde(default)]
+    pub filetypes: Vec<String>,
+
+    /// IDs of default `[handler.*]` entries to remove
+    #[serde(default)]
+    pub handlers: Vec<String>,
+}
+
+impl Overlay {
+    /// Merge an [included](Self::include) file into this one
+    ///
+    /// (The caller is responsible for rejecting filetypes and handlers defined in both.)
+    fn merge(&mut self, other: Overlay) {
+        self.case_sensitive_extensions =
+            other.case_sensitive_extensions.or(self.case_sensitive_extensions);
+        self.strict = other.strict.or(self.strict);
+        self.filetypes.extend(other.filetypes);
+        self.handlers.extend(other.handlers);
+        for glob in other.ignore {
+            if !self.ignore.contains(&glob) {
+                self.ignore.push(glob);
+            }
+        }
+        for (glob, override_) in other.overrides {
+            self.overrides.shift_remove(&glob);
+            self.overrides.insert(glob, override_);
+        }
+        self.disabled.filetypes.extend(other.disabled.filetypes);
+        self.disabled.handlers.extend(other.disabled.handlers);
+    }
+}
+
+impl Root {
+    /// Apply a user's [`Overlay`] on top of this configuration
+    ///
+    /// **NOTE:** The result must be validated afterward, since an overlay may legally refer to
+    /// entries from the defaults (or break references to ones it disables).
+    pub fn apply(&mut self, overlay: Overlay) -> Result<()> {
+        for id in &overlay.disabled.filetypes {
+            if overlay.filetypes.contains_key(id) {
+                return Err(anyhow!("Filetype {} is both defined and disabled", id));
+            }
+            if self.filetypes.remove(id).is_none() {
+                warn!("Disabled filetype not found in the defaults: {}", id);
+            }
+        }
+        for id in &overlay.disabled.handlers {
+            if overlay.handlers.contains_key(id) {
+                return Err(anyhow!("Handler {} is both defined and disabled", id));
+            }
+            if self.handlers.remove(id).is_none() {
+                warn!("Disabled handler not found in the defaults: {}", id);
+            }
+        }
+        let disabled_handlers = overlay.disabled.handlers;
+
+        if let Some(case_sensitive) = overlay.case_sensitive_extensions {
+            self.case_sensitive_extensions = case_sensitive;
+        }
+        if let Some(strict) = overlay.strict {
+            self.strict = strict;
+        }
+        self.filetypes.extend(overlay.filetypes);
+        self.handlers.extend(overlay.handlers);
+        for glob in overlay.ignore {
+            if !self.ignore.contains(&glob) {
+                self.ignore.push(glob);
+            }
+        }
+        for (glob, override_) in overlay.overrides {
+            // Move replaced rules to the end, so they still take precedence over the defaults
+            self.overrides.shift_remove(&glob);
+            self.overrides.insert(glob, override_);
+        }
+
+        // Catch chains which disabling left with nothing to fall back to (Ones which still have
+        // something left are only linted like any other unrecognized handler ID.)
+        let is_dangling = |chain: Option<&OneOrList<String>>| {
+            chain.map_or(false, |x| x.iter().all(|id| disabled_handlers.contains(id)))
+        };
+        if let Some(id) = self.filetypes.iter().find(|x| is_dangling(x.1.handler.as_ref())) {
+            return Err(anyhow!("Every handler for filetype {} is disabled", id.0));
+        }
+        if let Some(glob) = self.overrides.iter().find(|x| is_dangling(x.1.handler.as_ref())) {
+            return Err(anyhow!("Every handler for override {:?} is disabled", glob.0));
+        }
+        Ok(())
+    }
+
+    /// Serialize this configuration back into `verifiers.toml` form
+    ///
+    /// (`toml_edit` serializes everything as inline tables, so the top-level sections are
+    /// converted back into regular tables to match how the file is written by hand.)
+    pub fn to_toml(&self) -> Result<String> {
+        use toml_edit::Item;
+
+        let mut doc = toml_edit::ser::to_document(self)
+            .with_context(|| "Error serializing configuration")?;
+        for section in &["filetype", "override", "handler"] {
+            if let Some(item) = doc.get_mut(section) {
+                if let Ok(mut table) = std::mem::take(item).into_table() {
+                    table.set_implicit(true);
+                    for (_, entry) in table.iter_mut() {
+                        *entry = match std::mem::take(entry).into_table() {
+                            Ok(mut inner) => {
+                                inner.decor_mut().set_prefix("\n");
+                                Item::Table(inner)
+                            },
+                            Err(original) => original,
+                        };
+                    }
+                    *item = Item::Table(table);
+                }
+            }
+        }
+        Ok(doc.to_string().trim_start().to_owned())
+    }
+}
+
 // ----==== Parsing Functions ====----
 
 /// Reformat [`ValidationErrors`] for display to the user
@@ -475,87 +1694,925 @@ pub fn format_validation_errors(errors: ValidationErrors) -> anyhow::Error {
 
 /// Parse and validate the given `verifiers.toml` text
 ///
+/// Anything found by [`lint`] is logged as a warning or, if the text says `strict = true`,
+/// reported as a single error listing all of it.
+///
 /// TODO: Better design for integrating the builtin handler check.
 pub fn parse(toml_str: &str, is_builtin_handler: &dyn Fn(&str) -> bool) -> Result<Root> {
-    // Parse and perform all validation where the outcome couldn't change as a result of a fallback
-    // chain injecting new values.
-    let parsed: Root =
-        toml_edit::de::from_str(toml_str).with_context(|| "Error parsing configuration file")?;
+    let parsed = assemble(None, toml_str, None, false)?;
+    report_findings(&parsed, is_builtin_handler, parsed.strict, &[])?;
+    Ok(parsed)
+}
+
+/// Parse the `verifiers.toml` text in `base_str`, apply the [`Overlay`] in `overlay_str` on top
+/// of it, and validate the result
+///
+/// Anything found by [`lint`] is logged as a warning or, in strict mode, reported as a single
+/// error listing all of it. (Findings which `base_str` has on its own are still only warned
+/// about, since they aren't the overlay's fault.)
+///
+/// If `strict` is set, strict mode applies even if the file doesn't say `strict = true`, and
+/// `path` is where `overlay_str` was read from, if anywhere, for resolving its
+/// [`include`](Overlay::include) paths.
+pub fn parse_overlay(base_str: &str, overlay_str: &str, path: Option<&Path>,
+                     is_builtin_handler: &dyn Fn(&str) -> bool, strict: bool) -> Result<Root> {
+    let merged = assemble(Some(base_str), overlay_str, path, strict)?;
+    let strict = strict || merged.strict;
+    let inherited =
+        if strict { inherited_findings(base_str, is_builtin_handler)? } else { Vec::new() };
+    report_findings(&merged, is_builtin_handler, strict, &inherited)?;
+    Ok(merged)
+}
+
+/// Parse and validate `toml_str` like [`parse`] (or [`parse_overlay`] if `base_str` is given)
+/// but return what [`lint`] found instead of logging it, along with the line and column in
+/// `toml_str` of the entry each finding is about (if it's there)
+///
+/// (Findings which `base_str` has on its own are left out, since they aren't the overlay's
+/// fault, and everything else is an error in strict mode. Findings about entries from
+/// [included](Overlay::include) files have no position.)
+pub fn check_text(base_str: Option<&str>, toml_str: &str, path: Option<&Path>,
+                  is_builtin_handler: &dyn Fn(&str) -> bool, strict: bool)
+        -> Result<Vec<(Finding, Option<(usize, usize)>)>> {
+    let inherited = match base_str {
+        Some(base_str) => inherited_findings(base_str, is_builtin_handler)?,
+        None => Vec::new(),
+    };
+    let parsed = assemble(base_str, toml_str, path, strict)?;
+    let strict = strict || parsed.strict;
+    let doc = toml_edit::ImDocument::parse(toml_str)
+        .with_context(|| "Error parsing configuration file")?;
+    Ok(lint(&parsed, is_builtin_handler)
+        .into_iter()
+        .filter(|finding| !inherited.contains(&finding.message))
+        .map(|mut finding| {
+            if strict {
+                finding.severity = Severity::Error;
+            }
+            let position = finding.location.find_in(&doc).map(|offset| {
+                let before = &toml_str[..offset];
+                let line_start = before.rfind('\n').map_or(0, |x| x + 1);
+                (before.matches('\n').count() + 1, before[line_start..].chars().count() + 1)
+            });
+            (finding, position)
+        })
+        .collect())
+}
+
+/// Parse `toml_str` (applying it on top of `base_str` as an [`Overlay`] if given) and perform
+/// all validation which can fail
+///
+/// (Unknown keys in `toml_str` and any files it includes are handled as described on
+/// [`deserialize`], while `base_str` is always held to the strict standard, since it's expected
+/// to be the built-in default.)
+fn assemble(base_str: Option<&str>, toml_str: &str, path: Option<&Path>, strict: bool)
+        -> Result<Root> {
+    let mut parsed = match base_str {
+        Some(base_str) => {
+            let mut merged: Root = deserialize(base_str, true)
+                .with_context(|| "Error parsing default configuration")?;
+            let mut overlay = deserialize_overlay(toml_str, strict)
+                .with_context(|| "Error parsing configuration file")?;
+            if !overlay.include.is_empty() {
+                let path = path.ok_or_else(|| {
+                    anyhow!("'include' can only be used in a configuration file read from disk")
+                })?;
+                resolve_includes(&mut overlay, path, strict)?;
+            }
+            merged.apply(overlay)?;
+            merged
+        },
+        None => deserialize(toml_str, strict)
+            .with_context(|| "Error parsing configuration file")?,
+    };
+    parsed.config_version = CONFIG_VERSION;
+
+    // Perform all validation where the outcome couldn't change as a result of a fallback chain
+    // injecting new values.
     parsed.validate().map_err(format_validation_errors)?;
-    // TODO: Use a Result for all other failures too, instead of `warn!`.
 
-    // Check for `container` values that don't match any filetype IDs
+    // Check that directory handlers are only used for directories and vice versa
+    let is_dir_handler = |id: &String| {
+        parsed.handlers.get(id).map_or(false, |x| x.input == InputKind::Directory)
+    };
     for (id, filetype) in &parsed.filetypes {
-        if let Some(ref container) = filetype.container {
-            if !parsed.filetypes.contains_key(container.as_str()) {
-                warn!("Invalid container ID for filetype {}: {}", id, container);
+        let chain = filetype.handler.as_deref().unwrap_or(&[]);
+        if filetype.directory_name.is_some() {
+            if let Some(handler) = chain.iter().find(|x| !is_dir_handler(x)) {
+                return Err(anyhow!("Filetype {} has directory_name, but handler {} doesn't \
+                                    have input = \"directory\"", id, handler));
             }
+        } else if let Some(handler) = chain.iter().find(|x| is_dir_handler(x)) {
+            return Err(anyhow!("Filetype {} matches files, but handler {} has \
+                                input = \"directory\"", id, handler));
         }
     }
-
-    // Check for typos in filetype handler fields
-    for (id, filetype) in &parsed.filetypes {
-        if let Some(ref handler) = filetype.handler {
-            for handler in handler
-                .iter()
-                .filter(|y| !(parsed.handlers.contains_key(*y) || is_builtin_handler(y.as_str())))
-            {
-                warn!("Unrecognized handler for filetype {}: {}", id, handler);
-            }
+    for (glob, override_) in &parsed.overrides {
+        let chain = override_.handler.as_deref().unwrap_or(&[]);
+        if chain.iter().any(is_dir_handler) && !chain.iter().all(is_dir_handler) {
+            return Err(anyhow!("Override {:?} mixes file and directory handlers", glob));
         }
     }
+    Ok(parsed)
+}
 
-    // Check for typos in override handler fields
-    for override_ in &parsed.overrides {
-        // Check for typos in handler fields
-        if let Some(handler) = override_.handler.as_deref() {
-            for handler in handler.iter().filter(|y| !parsed.handlers.contains_key(*y)) {
-                warn!("Unrecognized handler for override {:#?}: {}", override_.path, handler);
+/// Deserialize an [`Overlay`] as described on [`deserialize`], after moving the IDs of any
+/// `[filetype.<id>]` and `[handler.<id>]` tables which only say `disabled = true` into its
+/// [`disabled`](Overlay::disabled) table
+///
+/// (Those tables otherwise have to be complete definitions, so this is easier than making
+/// every field optional.)
+fn deserialize_overlay(toml_str: &str, strict: bool) -> Result<Overlay> {
+    use toml_edit::{Array, DocumentMut, Item, Table, Value};
+
+    let mut doc: DocumentMut = match toml_str.parse() {
+        Ok(doc) => doc,
+        Err(_) => return deserialize(toml_str, strict),
+    };
+    let mut changed = false;
+    for (section, kind) in &[("filetype", "filetypes"), ("handler", "handlers")] {
+        let mut ids = Vec::new();
+        if let Some(table) = doc.get_mut(section).and_then(Item::as_table_like_mut) {
+            for (id, entry) in table.iter_mut() {
+                let entry = match entry.as_table_like_mut() {
+                    Some(entry) => entry,
+                    None => continue,
+                };
+                match entry.remove("disabled").map(|x| x.as_bool()) {
+                    None => continue,
+                    Some(Some(true)) if entry.is_empty() => ids.push(id.get().to_owned()),
+                    Some(Some(true)) => {
+                        return Err(anyhow!("[{}.{}] has disabled = true, so it can't set \
+                                            anything else", section, id.get()));
+                    },
+                    Some(Some(false)) => {},
+                    Some(None) => {
+                        return Err(anyhow!("'disabled' in [{}.{}] must be true or false",
+                                           section, id.get()));
+                    },
+                }
+                changed = true;
+            }
+            for id in &ids {
+                table.remove(id);
             }
         }
-
-        match override_.path.as_str() {
-            "*" | "*.*" => warn!("Override with too-broad `path` glob: {}", override_.path),
-            _ => {},
+        if ids.is_empty() {
+            continue;
         }
+        let disabled = doc.entry("disabled").or_insert_with(|| Item::Table(Table::new()));
+        let list = disabled
+            .as_table_like_mut()
+            .ok_or_else(|| anyhow!("'disabled' must be a table"))?
+            .entry(kind)
+            .or_insert(Item::Value(Value::Array(Array::new())));
+        let list =
+            list.as_array_mut().ok_or_else(|| anyhow!("'disabled.{}' must be a list", kind))?;
+        ids.into_iter().for_each(|id| list.push(id));
     }
+    if changed {
+        deserialize(&doc.to_string(), strict)
+    } else {
+        deserialize(toml_str, strict)
+    }
+}
 
-    // TODO: At a higher level (not config file parsing), decide how to implement checking for
-    //       nonexistent argv0 in handlers without annoying people who don't need support for all
-    //       formats installed. (Maybe a config key that you set after installation to silence
-    //       pre-flight checks for formats you only want to be warned about when it encounters one
-    //       it can't check? ...or maybe a command-line argument which causes it to output a report
-    //       on what formats are supported but not possible and what to install to enable them.)
+/// How deeply [`Overlay::include`] may nest before it's assumed to be a mistake
+const MAX_INCLUDE_DEPTH: usize = 8;
 
-    Ok(parsed)
+/// Merge everything the overlay read from `path` includes into it, as described on
+/// [`Overlay::include`]
+fn resolve_includes(overlay: &mut Overlay, path: &Path, strict: bool) -> Result<()> {
+    let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+    let mut includes = Includes {
+        strict,
+        stack: vec![canonical.clone()],
+        loaded: std::iter::once(canonical).collect(),
+        origins: BTreeMap::new(),
+    };
+    includes.record(overlay, path)?;
+    includes.resolve(overlay, path)
 }
 
-// ----==== Tests ====----
+/// State for resolving an [`Overlay`]'s [`include`](Overlay::include) list
+struct Includes {
+    /// Whether to parse included files in strict mode
+    strict: bool,
+    /// The canonicalized paths of the files currently being included, outermost first, for
+    /// detecting cycles
+    stack: Vec<PathBuf>,
+    /// The canonicalized paths of every file loaded so far, so files listed more than once
+    /// (eg. by overlapping globs) are only merged once
+    loaded: BTreeSet<PathBuf>,
+    /// The file each filetype and handler was defined in, for reporting duplicates
+    origins: BTreeMap<(&'static str, String), PathBuf>,
+}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+impl Includes {
+    /// Note which file the entries in `overlay` came from, failing if any were already defined
+    /// in another one
+    fn record(&mut self, overlay: &Overlay, path: &Path) -> Result<()> {
+        let ids = overlay.filetypes.keys().map(|id| ("Filetype", id))
+            .chain(overlay.handlers.keys().map(|id| ("Handler", id)));
+        for (kind, id) in ids {
+            if let Some(first) = self.origins.insert((kind, id.clone()), path.to_owned()) {
+                return Err(anyhow!("{} {} is defined in both {} and {}",
+                                   kind, id, first.display(), path.display()));
+            }
+        }
+        Ok(())
+    }
 
-    /// Quick helper to deduplicate basic assertions
-    ///
-    /// **TODO:** Assert more
-    fn assert_validation_result(toml_str: &str, section: &str) {
-        let result = do_validate(toml_str).expect_err("Validation should error out").into_errors();
-        result.get(section).expect(&format!("Get section {} from error response", section));
+    /// Merge everything `overlay` (read from `path`) includes into it, recursively
+    fn resolve(&mut self, overlay: &mut Overlay, path: &Path) -> Result<()> {
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        for pattern in std::mem::take(&mut overlay.include) {
+            for file in expand_include(dir, &pattern)? {
+                let canonical = fs::canonicalize(&file).with_context(|| {
+                    format!("Could not read included file {}", file.display())
+                })?;
+                if let Some(start) = self.stack.iter().position(|x| *x == canonical) {
+                    let cycle: Vec<_> = self.stack[start..].iter().chain(Some(&canonical))
+                        .map(|x| x.display().to_string())
+                        .collect();
+                    return Err(anyhow!("Include cycle: {}", cycle.join(" -> ")));
+                }
+                if !self.loaded.insert(canonical.clone()) {
+                    debug!("Skipping already-included file {}", file.display());
+                    continue;
+                }
+                if self.stack.len() > MAX_INCLUDE_DEPTH {
+                    return Err(anyhow!("Includes are nested more than {} deep at {}",
+                                       MAX_INCLUDE_DEPTH, file.display()));
+                }
+
+                let toml_str = fs::read_to_string(&file).with_context(|| {
+                    format!("Could not read included file {}", file.display())
+                })?;
+                let mut included = deserialize_overlay(&toml_str, self.strict)
+                    .with_context(|| format!("Error parsing included file {}", file.display()))?;
+                self.record(&included, &file)?;
+                self.stack.push(canonical);
+                self.resolve(&mut included, &file)?;
+                self.stack.pop();
+                overlay.merge(included);
+            }
+        }
+        Ok(())
     }
+}
 
-    fn do_validate(toml_str: &str) -> std::result::Result<(), ValidationErrors> {
-        let parsed: Root = toml_edit::de::from_str(toml_str).unwrap();
-        parsed.validate()
+/// Expand one entry from an [`include`](Overlay::include) list into the paths it refers to
+///
+/// (Unlike override globs, `*` doesn't match across directories, since that's what people will
+/// expect of a glob for filenames. Globs which match nothing are only warned about, so a directory
+/// of optional snippets can be empty.)
+fn expand_include(dir: &Path, pattern: &str) -> Result<Vec<PathBuf>> {
+    let is_glob = |x: &str| x.contains(|c| matches!(c, '*' | '?' | '[' | '{'));
+    if !is_glob(pattern) {
+        return Ok(vec![dir.join(pattern)]);
     }
 
-    // TODO: Unit tests for the checks that currently `warn!`
+    // Only walk the part of the tree the glob could match
+    let components: Vec<_> = pattern.split('/').collect();
+    let literal_len = components.iter().take_while(|x| !is_glob(x)).count();
+    let base = dir.join(components[..literal_len].join("/"));
+    let rest = components[literal_len..].join("/");
+    let matcher = GlobBuilder::new(&rest)
+        .literal_separator(true)
+        .build()
+        .map_err(|err| anyhow!("Invalid globbing pattern {:?} in 'include': {}",
+                               pattern, err.kind()))?
+        .compile_matcher();
 
-    /// Verify that nested validation is occurring
-    ///
-    /// (Because it's so easy to accidentally drop a `#[validate]` and not notice)
-    #[test]
-    #[rustfmt::skip]
+    let mut walker = ignore::WalkBuilder::new(&base);
+    walker.standard_filters(false);
+    if !rest.contains("**") {
+        walker.max_depth(Some(components.len() - literal_len));
+    }
+    let mut matches = Vec::new();
+    for entry in walker.build() {
+        let entry = entry.with_context(|| format!("Could not expand include {:?}", pattern))?;
+        let relative = entry.path().strip_prefix(&base).unwrap_or_else(|_| entry.path());
+        if entry.file_type().map_or(false, |x| !x.is_dir()) && matcher.is_match(relative) {
+            matches.push(entry.into_path());
+        }
+    }
+    if matches.is_empty() {
+        warn!("No files matched include {:?} in {}", pattern, dir.display());
+    }
+    matches.sort();
+    Ok(matches)
+}
+
+/// A step along the path from the top of a TOML document to one of its keys
+enum Step {
+    /// An entry in a table
+    Key(String),
+    /// An entry in an array of tables
+    Index(usize),
+}
+
+/// Find the path to the key at `span` in `table`, if it's there
+fn find_key(table: &dyn toml_edit::TableLike, span: &Range<usize>) -> Option<Vec<Step>> {
+    use toml_edit::{Item, Value};
+
+    for (name, item) in table.iter() {
+        let mut found = if table.key(name).and_then(toml_edit::Key::span).as_ref() == Some(span) {
+            Some(Vec::new())
+        } else {
+            let in_array = |(idx, entry): (usize, &dyn toml_edit::TableLike)| {
+                find_key(entry, span).map(|mut path| {
+                    path.insert(0, Step::Index(idx));
+                    path
+                })
+            };
+            match item {
+                Item::ArrayOfTables(array) => array.iter()
+                    .enumerate()
+                    .find_map(|(idx, x)| in_array((idx, x))),
+                Item::Value(Value::Array(array)) => array.iter()
+                    .enumerate()
+                    .filter_map(|(idx, x)| x.as_inline_table().map(|x| (idx, x)))
+                    .find_map(|(idx, x)| in_array((idx, x))),
+                _ => item.as_table_like().and_then(|x| find_key(x, span)),
+            }
+        };
+        if let Some(ref mut path) = found {
+            path.insert(0, Step::Key(name.to_owned()));
+            return found;
+        }
+    }
+    None
+}
+
+/// Remove the key at `path` from `table`, returning whether it was found
+fn remove_key(table: &mut dyn toml_edit::TableLike, path: &[Step]) -> bool {
+    use toml_edit::{Item, Value};
+
+    match path {
+        [Step::Key(name)] => table.remove(name).is_some(),
+        [Step::Key(name), Step::Index(idx), rest @ ..] => match table.get_mut(name) {
+            Some(Item::ArrayOfTables(array)) => {
+                array.get_mut(*idx).map_or(false, |x| remove_key(x, rest))
+            },
+            Some(Item::Value(Value::Array(array))) => array.get_mut(*idx)
+                .and_then(Value::as_inline_table_mut)
+                .map_or(false, |x| remove_key(x, rest)),
+            _ => false,
+        },
+        [Step::Key(name), rest @ ..] => table.get_mut(name)
+            .and_then(Item::as_table_like_mut)
+            .map_or(false, |x| remove_key(x, rest)),
+        _ => false,
+    }
+}
+
+/// Describe where the key at `path` is for use in messages (eg. `[handler.flac]`)
+fn describe_parent(path: &[Step]) -> String {
+    let parent = &path[..path.len().saturating_sub(1)];
+    if parent.is_empty() {
+        return "the top level".to_owned();
+    }
+    // Quote keys like override globs the way they'd have to be written in the file
+    let is_bare = |name: &str| name.chars().all(|c| c.is_ascii_alphanumeric() || "_-".contains(c));
+    let mut out = String::new();
+    for step in parent {
+        match step {
+            Step::Key(name) => {
+                if !out.is_empty() {
+                    out.push('.');
+                }
+                if is_bare(name) {
+                    out.push_str(name);
+                } else {
+                    let _ = write!(&mut out, "{:?}", name);
+                }
+            },
+            Step::Index(idx) => {
+                let _ = write!(&mut out, " #{}", idx + 1);
+            },
+        }
+    }
+    format!("[{}]", out)
+}
+
+/// Extract the key and the names it could have been from a Serde "unknown field" message
+fn parse_unknown_field(message: &str) -> Option<(&str, Vec<&str>)> {
+    let (name, rest) = message.strip_prefix("unknown field `")?.split_once('`')?;
+    Some((name, rest.split('`').skip(1).step_by(2).collect()))
+}
+
+/// Pick whichever of `candidates` a mistyped `name` was most likely meant to be, if any
+fn closest_match<'a>(name: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    candidates
+        .iter()
+        .map(|x| (strsim::levenshtein(name, x), *x))
+        .filter(|&(distance, x)| distance <= x.len() / 3 + 1)
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, x)| x)
+}
+
+/// A function which rewrites something deprecated in a parsed configuration file into its
+/// replacement, returning a warning explaining what it changed (or `None` if it changed nothing)
+type Migration = fn(&mut toml_edit::DocumentMut) -> Result<Option<String>>;
+
+/// The migrations to apply to files with each `config_version` older than [`CONFIG_VERSION`],
+/// in the order they should be applied
+const MIGRATIONS: &[(u32, Migration)] =
+    &[(1, migrate_legacy_overrides), (1, migrate_multipage)];
+
+/// Bring `toml_str` up to date with the current [`CONFIG_VERSION`] by applying every migration
+/// meant for older versions, or return `None` if none of them changed anything
+///
+/// Each change is logged as a warning, followed by a note suggesting `--dump-config` to get a
+/// copy in the current form. A version newer than [`CONFIG_VERSION`] is an error, since it
+/// would otherwise show up as a confusing pile of unknown keys.
+///
+/// (Malformed TOML is left alone, so deserializing it can report the problem.)
+fn migrate(toml_str: &str) -> Result<Option<String>> {
+    use toml_edit::DocumentMut;
+
+    let mut doc: DocumentMut = match toml_str.parse() {
+        Ok(doc) => doc,
+        Err(_) => return Ok(None),
+    };
+    let version = match doc.get("config_version") {
+        None => 1,
+        Some(item) => item
+            .as_integer()
+            .and_then(|x| u32::try_from(x).ok())
+            .filter(|x| *x >= 1)
+            .ok_or_else(|| anyhow!("'config_version' must be a whole number of at least 1"))?,
+    };
+    if version > CONFIG_VERSION {
+        return Err(anyhow!("This file is for config_version {}, but this version of \
+                            verify_files only understands up to {}. Please upgrade verify_files.",
+                           version, CONFIG_VERSION));
+    }
+
+    let mut changed = false;
+    for (_, migration) in MIGRATIONS.iter().filter(|(from, _)| *from >= version) {
+        if let Some(message) = migration(&mut doc)? {
+            warn!("{}", message);
+            changed = true;
+        }
+    }
+    if !changed {
+        return Ok(None);
+    }
+    warn!("This file was migrated from config_version {} to {} as described above. Use \
+           --dump-config to get a copy in the current form.", version, CONFIG_VERSION);
+    Ok(Some(doc.to_string()))
+}
+
+/// Rewrite any `[[override]]` tables (from before `config_version` 2) into `ignore` globs and
+/// `[override.<glob>]` tables, with a warning showing how to do the same to the file
+fn migrate_legacy_overrides(doc: &mut toml_edit::DocumentMut) -> Result<Option<String>> {
+    use toml_edit::{Array, DocumentMut, Item, Table};
+
+    let is_legacy = doc.get("override").map_or(false, Item::is_array_of_tables);
+    if !is_legacy || doc.get("ignore").map_or(false, |x| !x.is_array()) {
+        return Ok(None);
+    }
+    let legacy = doc.remove("override").and_then(|x| x.into_array_of_tables().ok());
+
+    // Build the replacements from scratch so the warning isn't cluttered by leftover formatting
+    let (mut ignore, mut overrides) = (Array::new(), Table::new());
+    let mut dropped = Vec::new();
+    overrides.set_implicit(true);
+    for (idx, mut table) in legacy.into_iter().flatten().enumerate() {
+        let glob = match table.remove("path").as_ref().and_then(Item::as_str) {
+            Some(glob) => glob.to_owned(),
+            None => return Err(anyhow!("[[override]] #{} has no `path` glob", idx + 1)),
+        };
+        match table.get("ignore").and_then(Item::as_bool) {
+            Some(true) => {
+                if table.len() > 1 {
+                    dropped.push(glob.clone());
+                }
+                if !ignore.iter().any(|x| x.as_str() == Some(glob.as_str())) {
+                    ignore.push(glob);
+                }
+                continue;
+            },
+            Some(false) => {
+                table.remove("ignore");
+            },
+            // Leave anything else for deserialization to complain about
+            None => {},
+        }
+
+        let mut rewritten = Table::new();
+        for (key, value) in table.iter() {
+            rewritten.insert(key, value.clone());
+        }
+        // Remove first so later rules still come later and take precedence
+        overrides.remove(&glob);
+        overrides.insert(&glob, Item::Table(rewritten));
+    }
+
+    let mut replacement = DocumentMut::new();
+    if !ignore.is_empty() {
+        replacement.insert("ignore", toml_edit::value(ignore.clone()));
+    }
+    if !overrides.is_empty() {
+        replacement.insert("override", Item::Table(overrides.clone()));
+    }
+    let mut message = format!("[[override]] tables are deprecated. They were read as if written \
+                               like this:\n\n{}", replacement.to_string().trim());
+    if !dropped.is_empty() {
+        let _ = write!(&mut message, "\n\n(Nothing but `ignore = true` has an effect on an \
+                                      ignored path, so the rest was left out for: {})",
+                       dropped.join(", "));
+    }
+
+    if let Some(existing) = doc.get_mut("ignore").and_then(Item::as_array_mut) {
+        existing.extend(ignore.iter().cloned());
+    } else if !ignore.is_empty() {
+        doc.insert("ignore", toml_edit::value(ignore));
+    }
+    doc.insert("override", Item::Table(overrides));
+    Ok(Some(message))
+}
+
+/// Move any `multipage = true` in filetypes (from before `config_version` 2) to
+/// `handler_args.multipage = "true"`, unless `handler_args` already sets it
+fn migrate_multipage(doc: &mut toml_edit::DocumentMut) -> Result<Option<String>> {
+    use toml_edit::{InlineTable, Item};
+
+    let (mut moved, mut dropped) = (Vec::new(), Vec::new());
+    let filetypes = doc.get_mut("filetype").and_then(Item::as_table_like_mut);
+    for (id, filetype) in filetypes.into_iter().flat_map(|x| x.iter_mut()) {
+        let filetype = match filetype.as_table_like_mut() {
+            Some(filetype) => filetype,
+            None => continue,
+        };
+        // Leave anything but a boolean for deserialization to complain about
+        let enabled = match filetype.get("multipage").and_then(Item::as_bool) {
+            Some(enabled) => enabled,
+            None => continue,
+        };
+        filetype.remove("multipage");
+        if enabled {
+            let args = filetype
+                .entry("handler_args")
+                .or_insert_with(|| toml_edit::value(InlineTable::new()));
+            if let Some(args) = args.as_table_like_mut() {
+                if !args.contains_key("multipage") {
+                    args.insert("multipage", toml_edit::value("true"));
+                }
+            }
+            moved.push(id.get().to_owned());
+        } else {
+            dropped.push(id.get().to_owned());
+        }
+    }
+
+    if moved.is_empty() && dropped.is_empty() {
+        return Ok(None);
+    }
+    let mut message = "`multipage` is deprecated in favour of `handler_args.multipage`.".to_owned();
+    if !moved.is_empty() {
+        let _ = write!(&mut message, " `multipage = true` was read as `handler_args.multipage = \
+                                      \"true\"` for: {}.", moved.join(", "));
+    }
+    if !dropped.is_empty() {
+        let _ = write!(&mut message, " `multipage = false` was left out for: {}.",
+                       dropped.join(", "));
+    }
+    Ok(Some(message))
+}
+
+/// Deserialize `toml_str`, warning about and then ignoring any keys which don't mean anything
+///
+/// If `strict` is set (or `toml_str` says `strict = true`), such keys are errors instead. Either
+/// way, the message names the key, where it is, and the likely intended key if it looks like a
+/// typo.
+///
+/// Files written for an older `config_version` are brought up to date as described on
+/// [`migrate`] first.
+fn deserialize<T: DeserializeOwned>(toml_str: &str, strict: bool) -> Result<T> {
+    use toml_edit::{ImDocument, Item};
+
+    let mut text = migrate(toml_str)?.unwrap_or_else(|| toml_str.to_owned());
+    loop {
+        let err = match toml_edit::de::from_str(&text) {
+            Ok(parsed) => return Ok(parsed),
+            Err(err) => err,
+        };
+        let (doc, span) = match (ImDocument::parse(text.as_str()), err.span()) {
+            (Ok(doc), Some(span)) => (doc, span),
+            _ => return Err(err.into()),
+        };
+        let (path, (name, expected)) =
+            match (find_key(doc.as_table(), &span), parse_unknown_field(err.message())) {
+                (Some(path), Some(unknown)) => (path, unknown),
+                _ => return Err(err.into()),
+            };
+
+        let mut message = format!("Unknown key {:?} in {}", name, describe_parent(&path));
+        if let Some(suggestion) = closest_match(name, &expected) {
+            let _ = write!(&mut message, " (did you mean {:?}?)", suggestion);
+        }
+        if strict || doc.get("strict").and_then(Item::as_bool).unwrap_or(false) {
+            return Err(anyhow::Error::new(err).context(message));
+        }
+        warn!("{}. Ignoring it.", message);
+
+        let mut doc = doc.into_mut();
+        if !remove_key(doc.as_table_mut(), &path) {
+            return Err(err.into());
+        }
+        text = doc.to_string();
+    }
+}
+
+/// The messages for everything [`lint`] finds in `base_str` on its own
+fn inherited_findings(base_str: &str, is_builtin_handler: &dyn Fn(&str) -> bool)
+        -> Result<Vec<String>> {
+    let base = assemble(None, base_str, None, true)?;
+    Ok(lint(&base, is_builtin_handler).into_iter().map(|x| x.message).collect())
+}
+
+/// Log everything [`lint`] finds as warnings or, if `strict`, fail with an error which lists all
+/// of it (except anything in `inherited`, which is still only warned about)
+fn report_findings(parsed: &Root, is_builtin_handler: &dyn Fn(&str) -> bool, strict: bool,
+                   inherited: &[String]) -> Result<()> {
+    let mut report = String::new();
+    for finding in lint(parsed, is_builtin_handler) {
+        if strict && !inherited.contains(&finding.message) {
+            let _ = write!(&mut report, "\n  {}", finding.message);
+        } else {
+            warn!("{}", finding.message);
+        }
+    }
+    if report.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!("Problems found in the configuration file (strict mode):{}", report))
+    }
+}
+
+/// How serious a [`Finding`] is
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Severity {
+    /// Probably unintended, but it won't keep anything from being verified
+    Warning,
+    /// Almost certainly a mistake (eg. a typo in a handler ID) which will keep some files from
+    /// being verified
+    Error,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Warning => "warning",
+            Self::Error => "error",
+        })
+    }
+}
+
+/// The entry in the configuration a [`Finding`] is about
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Location {
+    /// The `[filetype.*]` with the given ID
+    Filetype(String),
+    /// The `[handler.*]` with the given ID
+    Handler(String),
+    /// The `[override.<glob>]` with the given glob
+    Override(String),
+    /// The given glob in the `ignore` list
+    Ignore(String),
+}
+
+impl Location {
+    /// Find the byte offset of this entry in a parsed TOML document, if it's present
+    fn find_in(&self, doc: &toml_edit::ImDocument<&str>) -> Option<usize> {
+        let in_table = |section: &str, id: &str| {
+            let table = doc.get(section)?.as_table_like()?;
+            table.get(id)?.span().or_else(|| table.key(id)?.span())
+        };
+        // Files still using the deprecated `[[override]]` form have both kinds of entry there
+        let legacy = |glob: &str| {
+            doc.get("override")?
+                .as_array_of_tables()?
+                .iter()
+                .find(|x| x.get("path").and_then(toml_edit::Item::as_str) == Some(glob))?
+                .span()
+        };
+        let span = match self {
+            Self::Filetype(id) => in_table("filetype", id),
+            Self::Handler(id) => in_table("handler", id),
+            Self::Override(glob) => in_table("override", glob).or_else(|| legacy(glob)),
+            Self::Ignore(glob) => doc
+                .get("ignore")
+                .and_then(toml_edit::Item::as_array)
+                .and_then(|x| x.iter().find(|y| y.as_str() == Some(glob)))
+                .and_then(toml_edit::Value::span)
+                .or_else(|| legacy(glob)),
+        };
+        span.map(|x| x.start)
+    }
+}
+
+/// A likely mistake in a configuration which doesn't prevent it from being used
+#[derive(Clone, Debug)]
+pub struct Finding {
+    /// How serious it is
+    pub severity: Severity,
+    /// What it's about
+    pub location: Location,
+    /// A description of the problem
+    pub message: String,
+}
+
+/// Check whether `glob` matches (nearly) everything, like `*`, `*.*`, or `**/*`
+fn is_too_broad(glob: &str) -> bool {
+    let mut rest = glob;
+    while let Some(stripped) = rest.strip_prefix("**/") {
+        rest = stripped;
+    }
+    matches!(rest, "*" | "*.*" | "**")
+}
+
+/// Check a valid configuration for likely mistakes which aren't serious enough to refuse it
+pub fn lint(parsed: &Root, is_builtin_handler: &dyn Fn(&str) -> bool) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let mut found = |severity, location, message| {
+        findings.push(Finding { severity, location, message });
+    };
+
+    // NOTE: Unknown `container` IDs are left to `validate_root`, since a filetype can't be
+    //       resolved at all without its container.
+
+    // Check for environment overrides which will change how argv[0] gets resolved
+    for (id, handler) in &parsed.handlers {
+        if handler.env.keys().any(|x| x.eq_ignore_ascii_case("PATH")) {
+            found(Severity::Warning, Location::Handler(id.clone()),
+                  format!("Handler {} overrides PATH, which also affects where {:?} is looked up",
+                          id, handler.argv[0]));
+        }
+    }
+
+    // Check for typos in filetype handler fields
+    let is_known = |y: &&String| parsed.handlers.contains_key(*y) || is_builtin_handler(y);
+    for (id, filetype) in &parsed.filetypes {
+        if let Some(ref handler) = filetype.handler {
+            for handler in handler.iter().filter(|y| !is_known(y)) {
+                found(Severity::Error, Location::Filetype(id.clone()),
+                      format!("Unrecognized handler for filetype {}: {}", id, handler));
+            }
+        }
+    }
+
+    // Check for typos in override handler fields
+    for (glob, override_) in &parsed.overrides {
+        // Check for typos in handler fields
+        if let Some(handler) = override_.handler.as_deref() {
+            for handler in handler.iter().filter(|y| !is_known(y)) {
+                found(Severity::Error, Location::Override(glob.clone()),
+                      format!("Unrecognized handler for override {:#?}: {}", glob, handler));
+            }
+        }
+
+        if is_too_broad(glob) {
+            found(Severity::Warning, Location::Override(glob.clone()),
+                  format!("Override with too-broad glob: {}", glob));
+        }
+    }
+    for glob in parsed.ignore.iter().filter(|x| is_too_broad(x)) {
+        found(Severity::Warning, Location::Ignore(glob.clone()),
+              format!("Too-broad `ignore` glob: {}", glob));
+    }
+
+    // Check for filetypes which can only be told apart by the alphabetical order of their IDs
+    let mut seen = BTreeMap::new();
+    for (id, filetype) in &parsed.filetypes {
+        if filetype.extension.is_none() && filetype.header.is_none() {
+            continue;
+        }
+        let mut exts: Vec<String> = filetype.extension.iter().flat_map(|x| x.iter())
+            .map(|x| if parsed.case_sensitive_extensions { x.clone() } else { x.to_lowercase() })
+            .collect();
+        let mut headers: Vec<&[Option<u8>]> =
+            filetype.header.iter().flat_map(|x| x.iter()).map(|x| &**x).collect();
+        exts.sort_unstable();
+        exts.dedup();
+        headers.sort_unstable();
+        headers.dedup();
+
+        let key = (exts, headers, filetype.header_offset, filetype.priority);
+        let first = *seen.entry(key).or_insert(id);
+        if first != id {
+            found(Severity::Warning, Location::Filetype(id.clone()),
+                  format!("Filetypes {} and {} match the same files with the same priority, so \
+                           {} will always be tried first", first, id, first));
+        }
+    }
+
+    // NOTE: Checking for nonexistent argv0 in handlers is left to `--check-handlers` so people
+    //       who don't need support for all formats installed aren't nagged on every run.
+
+    findings
+}
+
+/// The places a user's configuration file may be found, in order of preference
+///
+/// That's `$XDG_CONFIG_HOME/verify_files/verifiers.toml`, then `~/.config/verify_files/...`, then
+/// (on Windows) `%APPDATA%\verify_files\...`, with environment variables looked up via `get_env`.
+pub fn user_config_paths(get_env: &dyn Fn(&str) -> Option<OsString>) -> Vec<PathBuf> {
+    // The XDG Base Directory spec says relative paths are invalid and should be ignored
+    let absolute = |name: &str| get_env(name).map(PathBuf::from).filter(|x| x.is_absolute());
+
+    let mut dirs = Vec::new();
+    dirs.extend(absolute("XDG_CONFIG_HOME"));
+    dirs.extend(absolute("HOME").map(|x| x.join(".config")));
+    if cfg!(windows) {
+        dirs.extend(absolute("APPDATA"));
+    }
+    dirs.dedup();
+    dirs.into_iter().map(|x| x.join("verify_files").join("verifiers.toml")).collect()
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Quick helper to deduplicate basic assertions
+    ///
+    /// **TODO:** Assert more
+    fn assert_validation_result(toml_str: &str, section: &str) {
+        let result = do_validate(toml_str).expect_err("Validation should error out").into_errors();
+        result.get(section).expect(&format!("Get section {} from error response", section));
+    }
+
+    fn do_validate(toml_str: &str) -> std::result::Result<(), ValidationErrors> {
+        let parsed: Root = toml_edit::de::from_str(toml_str).unwrap();
+        parsed.validate()
+    }
+
+    // TODO: Unit tests for the checks that currently `warn!`
+
+    /// Verify that directory handlers can't be mixed up with file handlers
+    #[test]
+    #[rustfmt::skip]
+    fn test_directory_handlers() {
+        let handlers = r#"
+            [handler.dvd]
+            argv = ["dvdisaster"]
+            input = "directory"
+
+            [handler.file]
+            argv = ["file"]
+        "#;
+        let check = |filetype: &str| parse(&format!("{}\n{}", handlers, filetype), &|_| false);
+
+        check(r#"
+            [filetype.video_ts]
+            description = "DVD-Video"
+            directory_name = "VIDEO_TS"
+            handler = "dvd"
+        "#).expect("Directory handler for directory_name should be accepted");
+        check(r#"
+            [filetype.iso]
+            description = "ISO image"
+            extension = "iso"
+            handler = ["file", "dvd"]
+        "#).expect_err("Directory handler for extension-matched filetype should be rejected");
+        check(r#"
+            [filetype.video_ts]
+            description = "DVD-Video"
+            directory_name = "VIDEO_TS"
+            handler = "file"
+        "#).expect_err("File handler for directory_name should be rejected");
+        check(r#"
+            [override."*/VIDEO_TS"]
+            handler = ["dvd", "file"]
+        "#).expect_err("Override mixing file and directory handlers should be rejected");
+
+        assert_validation_result(r#"
+                [filetype.video_ts]
+                description = "DVD-Video"
+                directory_name = "VIDEO_TS"
+                extension = "ts"
+                handler = "dvd"
+            "#, "filetype");
+        assert_validation_result(r#"
+                [filetype.video_ts]
+                description = "DVD-Video"
+                directory_name = "VIDEO_[TS"
+                handler = "dvd"
+            "#, "filetype");
+    }
+
+    /// Verify that nested validation is occurring
+    ///
+    /// (Because it's so easy to accidentally drop a `#[validate]` and not notice)
+    #[test]
+    #[rustfmt::skip]
     fn test_nested_validation() {
         // Filetype with an invalid description to trigger nested validation failure
         assert_validation_result(r#"
@@ -565,10 +2622,10 @@ mod tests {
                 extension = "foo"
             "#, "filetype");
 
-        // Override with an invalid description to trigger nested validation failure
+        // Override with an invalid handler to trigger nested validation failure
         assert_validation_result(r#"
-                [[override]]
-                path = ""
+                [override."*.bak"]
+                handler = []
             "#, "override");
 
         // Handler with an invalid argv to trigger nested validation failure
@@ -591,9 +2648,86 @@ mod tests {
 
         // Override that does nothing
         assert_validation_result(r#"
-                [[override]]
-                path = "quux"
+                [override.quux]
+            "#, "override");
+    }
+
+    /// Verify that override and ignore globs are checked to be valid globs
+    #[test]
+    #[rustfmt::skip]
+    fn test_override_glob_validation() {
+        assert_validation_result(r#"
+                [override."foo/[bar"]
+                handler = "zip"
+            "#, "override");
+        assert_validation_result(r#"
+                [override.""]
+                handler = "zip"
             "#, "override");
+        assert_validation_result(r#"ignore = ["*.bak", "foo/[bar"]"#, "ignore");
+        assert_validation_result(r#"ignore = [""]"#, "ignore");
+        do_validate(r#"
+                ignore = ["backups/**/*.{bak,old}"]
+
+                [override."backups/**/*.{bak,old}"]
+                handler = "zip"
+            "#).expect("Valid glob should be accepted");
+
+        for (glob, reason) in &[
+            ("foo/[bar", "unclosed character class"),
+            ("*.{}", "empty alternation"),
+            ("*.{,}", "empty alternation"),
+            ("{a,b", "unclosed alternate group"),
+        ] {
+            assert_validation_result(&format!("[override.{:?}]\nhandler = \"zip\"\n", glob),
+                                     "override");
+            let err = format!("{:#}", parse(&format!("[override.{:?}]\nhandler = \"zip\"\n",
+                                                      glob), &|_| true).unwrap_err());
+            assert!(err.contains(&format!("Invalid globbing pattern {:?}: {}", glob, reason)),
+                    "{}", err);
+        }
+        for glob in &["*.tar{.gz,}", "[{}]", "\\{\\}", "[]{}]"] {
+            assert!(build_glob(glob).is_ok(), "{}", glob);
+        }
+    }
+
+    /// Globs which match (nearly) everything should be caught however they're written
+    #[test]
+    fn test_too_broad_globs() {
+        for glob in &["*", "*.*", "**", "**/*", "**/*.*", "**/**/*"] {
+            assert!(is_too_broad(glob), "{}", glob);
+        }
+        for glob in &["*.bak", "**/*.bak", "foo/**", "*/*", "**/.git"] {
+            assert!(!is_too_broad(glob), "{}", glob);
+        }
+
+        let findings = check_text(None, "ignore = [\"**/*\"]\n\n[override.\"**\"]\n\
+                                         handler = \"zip\"\n", None, &|_| true, false).unwrap();
+        let messages: Vec<_> = findings.into_iter().map(|(x, _)| x.message).collect();
+        assert_eq!(messages, vec!["Override with too-broad glob: **",
+                                  "Too-broad `ignore` glob: **/*"]);
+    }
+
+    /// Verify that overrides can only force filetypes which exist, and not a handler as well
+    #[test]
+    #[rustfmt::skip]
+    fn test_override_filetype_validation() {
+        let filetypes = r#"
+            [filetype.zip]
+            description = "Zip archive"
+            extension = "zip"
+            handler = "zip"
+        "#;
+        do_validate(&format!("{}[override.\"*.dat\"]\nfiletype = \"zip\"\n", filetypes))
+            .expect("Existing filetype should be accepted");
+        assert_validation_result(&format!("{}[override.\"*.dat\"]\nfiletype = \"jar\"\n",
+                                          filetypes), "__all__");
+        assert_validation_result(&format!("{}[override.\"*.dat\"]\nfiletype = \"zip\"\n\
+                                          handler = \"zip\"\n", filetypes), "override");
+
+        let err = format!("{:#}", parse(&format!("{}[override.\"*.dat\"]\nfiletype = \"jar\"\n",
+                                                 filetypes), &|_| true).unwrap_err());
+        assert!(err.contains("'filetype' for override \"*.dat\" not found: jar"), "{}", err);
     }
 
     /// Verify that sources are checked to be superficially valid URLs
@@ -644,6 +2778,58 @@ mod tests {
                 argv = ["foo"]
                 sources = ["http://www.example.com/", "ftp://example.com/"]
             "#, "handler");
+        for bad in &["http://", "http:// garbage", "https://exa mple.com/", "https://[::1/"] {
+            assert_validation_result(&format!("[handler.foo]\nargv = [\"foo\"]\n\
+                                               sources = {:?}\n", bad), "handler");
+        }
+    }
+
+    /// `sources` entries may be tables, and should be chosen according to their `platform`
+    #[test]
+    #[rustfmt::skip]
+    fn test_source_tables() {
+        let parsed = parse(r#"
+            [handler.rpm]
+            argv = ["rpm", "-K"]
+            sources = [
+                "https://rpm.org/",
+                { url = "https://cygwin.com/", platform = "windows", note = "via Cygwin" },
+                { url = "https://rpm.org/download.html", platform = "linux" },
+            ]
+
+            [handler.sfc]
+            argv = ["sfc"]
+            sources = { url = "https://example.com/sfc", platform = "windows" }
+        "#, &|_| true).unwrap();
+        let urls = |id: &str, platform| parsed.handlers[id].sources_for(platform).iter()
+            .map(|x| x.to_string()).collect::<Vec<_>>();
+        assert_eq!(urls("rpm", Some(Platform::Linux)),
+                   vec!["https://rpm.org/download.html", "https://rpm.org/"]);
+        assert_eq!(urls("rpm", Some(Platform::Windows)),
+                   vec!["https://cygwin.com/ (via Cygwin)", "https://rpm.org/"]);
+        assert_eq!(urls("rpm", None), vec!["https://rpm.org/"]);
+        assert_eq!(urls("sfc", Some(Platform::Linux)), vec!["https://example.com/sfc"]);
+
+        // Plain URLs should stay plain strings when dumped
+        let dumped = parsed.to_toml().unwrap();
+        let expected = r#"sources = ["https://rpm.org/", { url = "https://cygwin.com/", "#;
+        assert!(dumped.contains(expected), "{}", dumped);
+        assert!(dumped.contains(r#"platform = "windows", note = "via Cygwin" }"#), "{}", dumped);
+        assert_eq!(parse(&dumped, &|_| true).unwrap().handlers["rpm"].sources,
+                   parsed.handlers["rpm"].sources);
+
+        for bad in &[
+            r#"sources = { url = "http://" }"#,
+            r#"sources = ["https://example.com/", { url = "not a url", platform = "linux" }]"#,
+            r#"sources = { url = "https://example.com/", note = "" }"#,
+        ] {
+            let err = format!("{:#}", parse(&format!("[handler.foo]\nargv = [\"foo\"]\n{}\n", bad),
+                                            &|_| true).unwrap_err());
+            assert!(err.contains("source"), "{}", err);
+        }
+        assert!(parse("[handler.foo]\nargv = [\"foo\"]\n\
+                       sources = { url = \"https://example.com/\", platform = \"amiga\" }\n",
+                      &|_| true).is_err());
     }
 
     /// Verify that all sections are optional
@@ -662,9 +2848,8 @@ mod tests {
             "#).expect("The parser should accept a filetype that may rely only on builtins");
 
         do_validate(r#"
-                [[override]]
-                path = "bar"
-                ignore = true
+                [override.bar]
+                handler = "zip"
             "#).expect("The parser should assume a lone override relies on a fallback chain");
 
         do_validate(r#"
@@ -705,41 +2890,1224 @@ mod tests {
             "#, "handler");
     }
 
-    /// Make sure the validation catches 'container' cycles
     #[test]
-    fn test_rejects_container_cycle() {
-        assert_validation_result(
-            r#"
-            [filetype.foo]
-            description = "Foo"
-            extension = "foo"
-            container = "bar"
+    fn test_parse_duration() {
+        let secs = Duration::from_secs;
+        assert_eq!(parse_duration("90"), Ok(secs(90)));
+        assert_eq!(parse_duration("1.5"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("90s"), Ok(secs(90)));
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("5m"), Ok(secs(300)));
+        assert_eq!(parse_duration("1h30m"), Ok(secs(5400)));
+        assert_eq!(parse_duration("1 hour 2 mins"), Ok(secs(3720)));
 
-            [filetype.bar]
-            description = "Bar"
-            extension = "bar"
-            container = "baz"
+        for bad in &["", "0", "-5", "0s", "5 parsecs", "m", "1h30", "inf"] {
+            assert!(parse_duration(bad).is_err(), "Should have rejected {:?}", bad);
+        }
+    }
 
-            [filetype.baz]
-            description = "Baz"
-            extension = "baz"
-            container = "foo"
-        "#,
-            "__all__",
-        );
+    /// Timeouts should accept both numbers of seconds and duration strings
+    #[test]
+    #[rustfmt::skip]
+    fn test_handler_timeouts() {
+        let timeout = |value: &str| toml_edit::de::from_str::<Handler>(
+            &format!("argv = [\"foo\"]\ntimeout = {}", value)).map(|x| x.timeout);
+        assert_eq!(timeout("30").unwrap(), Some(Timeout(Duration::from_secs(30))));
+        assert_eq!(timeout("2.5").unwrap(), Some(Timeout(Duration::from_millis(2500))));
+        assert_eq!(timeout(r#""2m""#).unwrap(), Some(Timeout(Duration::from_secs(120))));
+        assert!(timeout("0").is_err());
+        assert!(timeout("-1").is_err());
+        assert!(timeout(r#""soon""#).is_err());
     }
 
-    /// Make sure the validation catches unknown 'container' values
     #[test]
-    fn test_rejects_unknown_container() {
-        assert_validation_result(
-            r#"
-            [filetype.foo]
-            description = "Foo"
-            extension = "foo"
-            container = "bar"
-        "#,
-            "__all__",
-        );
+    fn test_parse_size() {
+        assert_eq!(parse_size("1024"), Ok(1024));
+        assert_eq!(parse_size("1B"), Ok(1));
+        assert_eq!(parse_size("2GiB"), Ok(2 << 30));
+        assert_eq!(parse_size("2 gib"), Ok(2 << 30));
+        assert_eq!(parse_size("500MB"), Ok(500_000_000));
+        assert_eq!(parse_size("1.5 KiB"), Ok(1536));
+        assert_eq!(parse_size("9007199254740993"), Ok(9_007_199_254_740_993), "Should be exact");
+
+        for bad in &["", "0", "0GiB", "-5", "5 parsecs", "GiB", "1.2.3MB", "99999999TiB"] {
+            assert!(parse_size(bad).is_err(), "Should have rejected {:?}", bad);
+        }
     }
-}
+
+    /// Size limits should accept both numbers of bytes and size strings, and be written back
+    /// in the most readable exact form
+    #[test]
+    fn test_max_size() {
+        let max_size = |value: &str| toml_edit::de::from_str::<Filetype>(
+            &format!("description = \"Foo\"\nextension = \"foo\"\nmax_size = {}", value))
+            .map(|x| x.max_size);
+        assert_eq!(max_size("1000").unwrap(), Some(SizeLimit(1000)));
+        assert_eq!(max_size(r#""2GiB""#).unwrap(), Some(SizeLimit(2 << 30)));
+        assert!(max_size("0").is_err());
+        assert!(max_size("-1").is_err());
+        assert!(max_size(r#""huge""#).is_err());
+
+        let repr = |bytes| {
+            SizeLimit(bytes).serialize(toml_edit::ser::ValueSerializer::new()).unwrap().to_string()
+        };
+        assert_eq!(repr(2 << 30), r#""2GiB""#);
+        assert_eq!(repr(3 << 20), r#""3MiB""#);
+        assert_eq!(repr(1536), "1536");
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_env_validation() {
+        let parsed = parse(r#"
+                [handler.gs]
+                argv = ["gs"]
+                env_clear = true
+                env = { LANG = "C", TMPDIR = "{tmpdir}" }
+            "#, &|_| false).expect("Valid env tables should be accepted");
+        assert_eq!(parsed.handlers["gs"].env["TMPDIR"], "{tmpdir}");
+
+        for env in &[r#""" = "x""#, r#""A=B" = "x""#, r#"TMPDIR = "{tmp}""#] {
+            assert_validation_result(&format!("[handler.foo]\nargv = [\"foo\"]\nenv = {{ {} }}",
+                                              env), "handler");
+        }
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_limits_validation() {
+        let parsed: Handler = toml_edit::de::from_str(r#"
+                argv = ["ffmpeg"]
+                limits = { memory_mb = 2048, cpu_seconds = 600 }
+            "#).unwrap();
+        assert_eq!(parsed.limits, Limits { memory_mb: Some(2048), cpu_seconds: Some(600) });
+        assert!(parsed.validate().is_ok());
+
+        assert_validation_result(r#"
+                [handler.foo]
+                argv = ["foo"]
+                limits.memory_mb = 0
+            "#, "handler");
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_priority_validation() {
+        let parsed: Handler = toml_edit::de::from_str(r#"
+                argv = ["7z", "t"]
+                nice = 10
+                ionice_class = "idle"
+            "#).unwrap();
+        assert_eq!((parsed.nice, parsed.ionice_class), (Some(10), Some(IoClass::Idle)));
+        assert!(parsed.validate().is_ok());
+
+        for bad in &["nice = 0", "nice = 20"] {
+            assert_validation_result(&format!(r#"
+                    [handler.foo]
+                    argv = ["foo"]
+                    {}
+                "#, bad), "handler");
+        }
+        assert!(toml_edit::de::from_str::<Handler>(r#"
+                argv = ["foo"]
+                ionice_class = "realtime"
+            "#).is_err(), "Classes needing root shouldn't be accepted");
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_platforms() {
+        let parsed: Handler = toml_edit::de::from_str(r#"
+                argv = ["sfc"]
+                platforms = ["windows", "macos"]
+            "#).unwrap();
+        assert!(parsed.validate().is_ok());
+        assert!(parsed.runs_on(Some(Platform::Windows)));
+        assert!(!parsed.runs_on(Some(Platform::Linux)));
+        assert!(!parsed.runs_on(None));
+
+        let unrestricted: Handler = toml_edit::de::from_str(r#"argv = ["7z"]"#).unwrap();
+        assert!(unrestricted.runs_on(None));
+
+        assert_validation_result(r#"
+                [handler.foo]
+                argv = ["foo"]
+                platforms = []
+            "#, "handler");
+        assert!(toml_edit::de::from_str::<Handler>(r#"
+                argv = ["foo"]
+                platforms = ["amiga"]
+            "#).is_err(), "Unknown platforms shouldn't be accepted");
+
+        // Handlers for other platforms still exist as far as typo-checking is concerned
+        let toml_str = r#"
+            [handler.rpm]
+            argv = ["rpm", "-K"]
+            platforms = ["linux"]
+
+            [handler.sfc]
+            argv = ["sfc"]
+            platforms = ["windows"]
+
+            [filetype.rpm]
+            description = "RPM Package"
+            extension = "rpm"
+            handler = ["rpm", "sfc"]
+        "#;
+        assert!(check_text(None, toml_str, None, &|_| false, true).unwrap().is_empty());
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_confidence() {
+        let parsed: Handler = toml_edit::de::from_str(r#"
+                argv = ["par2", "verify"]
+                confidence = "data-parity"
+            "#).unwrap();
+        assert_eq!(parsed.confidence, Some(Confidence::DataParity));
+
+        let err = toml_edit::de::from_str::<Handler>(r#"
+                argv = ["foo"]
+                confidence = "very"
+            "#).unwrap_err().to_string();
+        assert!(err.contains("well-formed"), "Valid levels should be listed: {}", err);
+    }
+
+    /// (Unix-only since the test paths aren't absolute on Windows)
+    #[cfg(unix)]
+    #[test]
+    fn test_user_config_paths() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| vars.iter().find(|x| x.0 == name).map(|x| OsString::from(x.1))
+        };
+        let expected = |dirs: &[&str]| -> Vec<PathBuf> {
+            dirs.iter().map(|x| PathBuf::from(x).join("verify_files/verifiers.toml")).collect()
+        };
+
+        assert_eq!(user_config_paths(&env(&[("XDG_CONFIG_HOME", "/xdg"), ("HOME", "/home/me")])),
+                   expected(&["/xdg", "/home/me/.config"]));
+        assert_eq!(user_config_paths(&env(&[("XDG_CONFIG_HOME", "/home/me/.config"),
+                                            ("HOME", "/home/me")])),
+                   expected(&["/home/me/.config"]));
+        assert_eq!(user_config_paths(&env(&[("XDG_CONFIG_HOME", "relative"), ("HOME", "/h")])),
+                   expected(&["/h/.config"]));
+        assert_eq!(user_config_paths(&env(&[])), expected(&[]));
+    }
+
+    /// Filetypes and handlers in a user's file should replace, add to, or remove defaults
+    #[test]
+    #[rustfmt::skip]
+    fn test_disabling() {
+        let base = r#"
+            [handler.unzip]
+            argv = ["unzip", "-t"]
+
+            [handler.7z]
+            argv = ["7z", "t"]
+
+            [handler.unrar]
+            argv = ["unrar", "t"]
+
+            [filetype.zip]
+            description = "Zip archive"
+            extension = "zip"
+            handler = ["unzip", "7z"]
+
+            [filetype.rar]
+            description = "RAR archive"
+            extension = "rar"
+            handler = "unrar"
+        "#;
+
+        // Both ways of disabling things should be usable together
+        let merged = parse_overlay(base, r#"
+            [filetype.rar]
+            disabled = true
+
+            [handler.unrar]
+            disabled = true
+
+            [disabled]
+            handlers = ["unzip"]
+        "#, None, &|_| false, false).unwrap();
+        assert_eq!(merged.filetypes.keys().collect::<Vec<_>>(), vec!["zip"]);
+        assert_eq!(merged.handlers.keys().collect::<Vec<_>>(), vec!["7z"]);
+
+        let merged = parse_overlay(base, r#"
+            [handler.7z]
+            disabled = false
+            argv = ["7za", "t"]
+        "#, None, &|_| false, true).unwrap();
+        assert_eq!(merged.handlers["7z"].argv[0], "7za");
+
+        // Filetypes left with no handler to fall back to are errors, even in non-strict mode
+        for overlay in &[
+            "[handler.unrar]\ndisabled = true\n",
+            "[disabled]\nhandlers = [\"unzip\", \"7z\"]\n",
+        ] {
+            let err = format!("{:#}", parse_overlay(base, overlay, None, &|_| false, false)
+                .unwrap_err());
+            assert!(err.contains("Every handler for filetype"), "{}", err);
+        }
+
+        for (overlay, expected) in &[
+            ("[filetype.rar]\ndisabled = true\nextension = \"cbr\"\n", "can't set anything else"),
+            ("[filetype.rar]\ndisabled = \"yes\"\n", "must be true or false"),
+        ] {
+            let err = format!("{:#}", parse_overlay(base, overlay, None, &|_| false, false)
+                .unwrap_err());
+            assert!(err.contains(expected), "{}", err);
+        }
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_includes() {
+        let tempdir = crate::tempdir::TempDir::new().unwrap();
+        let dir = tempdir.path();
+        let write = |name: &str, toml_str: &str| {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, toml_str).unwrap();
+            path
+        };
+        let base = r#"
+            [handler.unzip]
+            argv = ["unzip", "-t"]
+        "#;
+        let root = write("verifiers.toml", r#"
+            include = ["media/*.toml", "archives.toml"]
+            ignore = ["*.tmp"]
+        "#);
+        write("archives.toml", r#"
+            include = ["parts/*.toml"]
+            ignore = ["*.partial"]
+
+            [filetype.zip]
+            description = "Zip archive"
+            extension = "zip"
+            handler = "unzip"
+        "#);
+        write("parts/rar.toml", r#"
+            [handler.unrar]
+            argv = ["unrar", "t"]
+
+            [filetype.rar]
+            description = "RAR archive"
+            extension = "rar"
+            handler = "unrar"
+        "#);
+        write("media/png.toml", r#"
+            [filetype.png]
+            description = "PNG image"
+            extension = "png"
+            handler = "unzip"
+        "#);
+        write("media/nested/ignored.toml", "this isn't TOML");
+
+        let read = |path: &Path| fs::read_to_string(path).unwrap();
+        let merged = parse_overlay(base, &read(&root), Some(&root), &|_| false, true).unwrap();
+        assert_eq!(merged.filetypes.keys().collect::<Vec<_>>(), vec!["png", "rar", "zip"]);
+        assert!(merged.handlers.contains_key("unrar"));
+        assert_eq!(merged.ignore, vec!["*.tmp", "*.partial"]);
+        assert!(merged.to_toml().unwrap().contains("[filetype.rar]"),
+                "--dump-config should show the flattened result");
+
+        assert!(parse_overlay(base, &read(&root), None, &|_| false, true).is_err(),
+                "Includes can't be resolved without knowing where the file is");
+
+        // Duplicate IDs should name both files
+        let dupe = write("dupe.toml", r#"
+            include = ["media/png.toml"]
+
+            [filetype.png]
+            description = "Also PNG"
+            extension = "png"
+        "#);
+        let err = format!("{:#}",
+            parse_overlay(base, &read(&dupe), Some(&dupe), &|_| false, true).unwrap_err());
+        assert!(err.contains("Filetype png is defined in both") &&
+                err.contains("dupe.toml") && err.contains("png.toml"), "{}", err);
+
+        // Cycles and runaway nesting should be caught
+        let cycle = write("cycle_a.toml", r#"include = ["cycle_b.toml"]"#);
+        write("cycle_b.toml", r#"include = ["cycle_a.toml"]"#);
+        let err = format!("{:#}",
+            parse_overlay(base, &read(&cycle), Some(&cycle), &|_| false, true).unwrap_err());
+        assert!(err.contains("Include cycle") && err.contains("cycle_b.toml"), "{}", err);
+
+        for idx in 0..=MAX_INCLUDE_DEPTH {
+            write(&format!("deep{}.toml", idx), &format!("include = [\"deep{}.toml\"]", idx + 1));
+        }
+        let deep = write(&format!("deep{}.toml", MAX_INCLUDE_DEPTH + 1), "");
+        let deep = deep.with_file_name("deep0.toml");
+        let err = format!("{:#}",
+            parse_overlay(base, &read(&deep), Some(&deep), &|_| false, true).unwrap_err());
+        assert!(err.contains("nested more than"), "{}", err);
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_overlay() {
+        let base = r#"
+            ignore = ["*.partial"]
+
+            [handler.unzip]
+            argv = ["unzip", "-t"]
+
+            [handler.unrar]
+            argv = ["unrar", "t"]
+
+            [filetype.zip]
+            description = "Zip archive"
+            extension = "zip"
+            handler = "unzip"
+
+            [filetype.rar]
+            description = "RAR archive"
+            extension = "rar"
+            handler = "unrar"
+
+            [override."*.bak"]
+            handler = "zip"
+
+            [override."*.old"]
+            handler = "zip"
+        "#;
+        let merged = parse_overlay(base, r#"
+            ignore = ["*.tmp"]
+
+            [handler.unzip]
+            argv = ["/opt/bin/unzip", "-t"]
+
+            [filetype.jar]
+            container = "zip"
+            description = "Java archive"
+            extension = "jar"
+            handler = "unzip"
+
+            [override."*.bak"]
+            handler = "unzip"
+
+            [disabled]
+            filetypes = ["rar"]
+            handlers = ["unrar"]
+        "#, None, &|_| false, false).unwrap();
+
+        assert_eq!(merged.handlers["unzip"].argv[0], "/opt/bin/unzip", "Should replace");
+        assert_eq!(merged.filetypes.keys().collect::<Vec<_>>(), vec!["jar", "zip"]);
+        assert_eq!(merged.handlers.keys().collect::<Vec<_>>(), vec!["unzip"]);
+        assert_eq!(merged.ignore, vec!["*.partial", "*.tmp"]);
+        assert_eq!(merged.overrides.keys().collect::<Vec<_>>(), vec!["*.old", "*.bak"],
+                   "Replaced overrides should take precedence over the defaults");
+        assert_eq!(merged.overrides["*.bak"].handler, Some(vec!["unzip".to_owned()].into()));
+        assert!(!merged.case_sensitive_extensions);
+
+        // Validation must see the merged result
+        assert!(parse_overlay(base, r#"
+            [disabled]
+            filetypes = ["zip"]
+
+            [filetype.jar]
+            container = "zip"
+            description = "Java archive"
+            extension = "jar"
+        "#, None, &|_| false, false).is_err(),
+            "Containers removed by the overlay should be caught");
+        assert!(parse_overlay(base, r#"
+            [handler.unrar]
+            argv = ["unrar"]
+
+            [disabled]
+            handlers = ["unrar"]
+        "#, None, &|_| false, false).is_err(),
+            "Defining and disabling the same ID is contradictory");
+    }
+
+    /// The deprecated `[[override]]` form should be read as the equivalent `ignore` globs and
+    /// `[override.<glob>]` tables
+    #[test]
+    #[rustfmt::skip]
+    fn test_legacy_overrides() {
+        let current = parse(r#"
+            ignore = ["*.tmp", "*.partial"]
+
+            [override."*.bak"]
+            handler = "zip"
+            message = "Backup"
+
+            [override."old/*.bak"]
+            handler = ["unzip", "zip"]
+        "#, &|_| true).unwrap();
+        let legacy = parse(r#"
+            ignore = ["*.tmp"]
+
+            [[override]]
+            path = "old/*.bak"
+            handler = "unzip"
+
+            [[override]]
+            path = "*.partial"
+            ignore = true
+            message = "Skipping partial download"
+
+            [[override]]
+            path = "*.bak"
+            handler = "zip"
+            message = "Backup"
+            ignore = false
+
+            [[override]]
+            path = "old/*.bak"
+            handler = ["unzip", "zip"]
+        "#, &|_| true).unwrap();
+        assert_eq!(legacy.ignore, current.ignore);
+        assert_eq!(legacy.overrides.keys().collect::<Vec<_>>(), vec!["*.bak", "old/*.bak"],
+                   "Later rules should still take precedence");
+        assert_eq!(legacy.overrides, current.overrides);
+        assert_eq!(legacy.to_toml().unwrap(), current.to_toml().unwrap());
+
+        let merged = parse_overlay("ignore = [\"*.tmp\"]\n",
+                                   "[[override]]\npath = \"*.partial\"\nignore = true\n", None,
+                                   &|_| true, false).unwrap();
+        assert_eq!(merged.ignore, current.ignore);
+
+        let err = format!("{:#}", parse("[[override]]\nhandler = \"zip\"\n", &|_| true)
+            .unwrap_err());
+        assert!(err.contains("[[override]] #1 has no `path` glob"), "{}", err);
+    }
+
+    /// Each migration should rewrite the old form and leave everything else alone
+    #[test]
+    #[rustfmt::skip]
+    fn test_migrations() {
+        let run = |migration: Migration, toml_str: &str| {
+            let mut doc: toml_edit::DocumentMut = toml_str.parse().unwrap();
+            let message = migration(&mut doc).unwrap();
+            (doc, message)
+        };
+
+        let (doc, message) = run(migrate_legacy_overrides, r#"
+            ignore = ["*.tmp"]
+
+            [[override]]
+            path = "*.part"
+            ignore = true
+
+            [[override]]
+            path = "*.bak"
+            handler = "zip"
+            ignore = false
+        "#);
+        let overrides: Overlay = toml_edit::de::from_str(&doc.to_string()).unwrap();
+        assert_eq!(overrides.ignore, vec!["*.tmp", "*.part"]);
+        assert_eq!(overrides.overrides["*.bak"].handler.as_deref(), Some(&["zip".to_owned()][..]));
+        assert!(message.unwrap().contains("[override.\"*.bak\"]\nhandler = \"zip\""));
+
+        let (doc, message) = run(migrate_multipage, r#"
+            [filetype.tiff]
+            multipage = true
+
+            [filetype.dcx]
+            multipage = true
+            handler_args = { multipage = "yes" }
+
+            [filetype.png]
+            multipage = false
+
+            [filetype.gif.handler_args]
+            format = "gif"
+        "#);
+        let filetypes = doc["filetype"].as_table().unwrap();
+        assert_eq!(filetypes["tiff"]["handler_args"]["multipage"].as_str(), Some("true"));
+        assert_eq!(filetypes["dcx"]["handler_args"]["multipage"].as_str(), Some("yes"));
+        assert!(filetypes.iter().all(|(_, x)| !x.as_table().unwrap().contains_key("multipage")));
+        assert_eq!(message.unwrap(), "`multipage` is deprecated in favour of \
+            `handler_args.multipage`. `multipage = true` was read as `handler_args.multipage = \
+            \"true\"` for: tiff, dcx. `multipage = false` was left out for: png.");
+
+        for migration in MIGRATIONS.iter().map(|(_, x)| *x) {
+            let (doc, message) = run(migration, crate::app::DEFAULT_CONFIG);
+            assert_eq!((doc.to_string().as_str(), message), (crate::app::DEFAULT_CONFIG, None));
+        }
+    }
+
+    /// Migrations should only be applied to older files, and newer ones should be rejected
+    #[test]
+    fn test_config_version() {
+        let old = "[filetype.tiff]\nmultipage = true\n";
+        assert!(migrate(old).unwrap().unwrap().contains("handler_args = { multipage = \"true\" }"));
+        assert!(migrate(&format!("config_version = 1\n{}", old)).unwrap().is_some());
+        assert_eq!(migrate(&format!("config_version = {}\n{}", CONFIG_VERSION, old)).unwrap(),
+                   None);
+        assert_eq!(migrate("[filetype.tiff]\n").unwrap(), None);
+
+        let err = format!("{:#}", parse(&format!("config_version = {}\n", CONFIG_VERSION + 1),
+                                        &|_| true).unwrap_err());
+        assert!(err.contains("Please upgrade verify_files"), "{}", err);
+        for bad in &["0", "-1", "\"2\"", "1.5"] {
+            let err = format!("{:#}", parse(&format!("config_version = {}\n", bad), &|_| true)
+                .unwrap_err());
+            assert!(err.contains("'config_version' must be"), "{}", err);
+        }
+
+        // Whatever the file said, the result is in the current form
+        let parsed = parse(&format!("config_version = 1\n{}description = \"TIFF\"\n\
+                                     extension = \"tif\"\nhandler = \"image\"\n", old),
+                           &|_| true).unwrap();
+        assert_eq!(parsed.config_version, CONFIG_VERSION);
+        let merged = parse_overlay(crate::app::DEFAULT_CONFIG, "config_version = 1\n", None,
+                                   &|_| true, true).unwrap();
+        assert_eq!(merged.config_version, CONFIG_VERSION);
+    }
+
+    /// The dumped configuration must parse back to the same thing, even when used as an overlay
+    #[test]
+    fn test_to_toml_round_trip() {
+        let default = crate::app::DEFAULT_CONFIG;
+        let dumped = parse(default, &|_| true).unwrap().to_toml().unwrap();
+        assert!(dumped.contains("\n[filetype.zip]\n"), "Should use regular tables");
+        assert!(dumped.starts_with(&format!("config_version = {}\nignore = [", CONFIG_VERSION)),
+                "Top-level keys should come first");
+
+        assert_eq!(parse(&dumped, &|_| true).unwrap().to_toml().unwrap(), dumped);
+        let merged = parse_overlay(default, &dumped, None, &|_| true, false).unwrap();
+        assert_eq!(merged.to_toml().unwrap(), dumped);
+
+        // Single-element lists should come out as bare values and longer ones as lists
+        let toml_str = r#"
+            [filetype.foo]
+            description = "Foo"
+            extension = ["foo"]
+            header = [[70, 79, 79]]
+            handler = "json"
+
+            [filetype.bar]
+            description = "Bar"
+            extension = ["bar", "baz"]
+            header = ["42 41 52", [66, 65, 90]]
+            handler = ["json", "zip"]
+
+            [handler.foo]
+            argv = ["foo", "{path}"]
+            sources = ["https://example.com/foo"]
+
+            [handler.bar]
+            argv = ["bar", "{path}"]
+            sources = ["https://example.com/bar", "https://example.org/bar"]
+        "#;
+        let parsed = parse(toml_str, &|_| true).unwrap();
+        let dumped = parsed.to_toml().unwrap();
+        for expected in &[
+            r#"extension = "foo""#,
+            r#"header = "46 4F 4F""#,
+            r#"handler = "json""#,
+            r#"sources = "https://example.com/foo""#,
+            r#"extension = ["bar", "baz"]"#,
+            r#"header = ["42 41 52", "42 41 5A"]"#,
+            r#"handler = ["json", "zip"]"#,
+            r#"sources = ["https://example.com/bar", "https://example.org/bar"]"#,
+        ] {
+            assert!(dumped.contains(expected), "{}", dumped);
+        }
+
+        let reparsed = parse(&dumped, &|_| true).unwrap();
+        assert_eq!(reparsed.to_toml().unwrap(), dumped);
+        for id in &["foo", "bar"] {
+            let (before, after) = (&parsed.filetypes[*id], &reparsed.filetypes[*id]);
+            assert_eq!(before.extension, after.extension);
+            assert_eq!(before.header, after.header);
+            assert_eq!(before.handler, after.handler);
+            assert_eq!(parsed.handlers[*id].sources, reparsed.handlers[*id].sources);
+        }
+    }
+
+    #[test]
+    fn test_one_or_list() {
+        assert_eq!(OneOrList::one(1), OneOrList::list(vec![1]));
+        assert_eq!(OneOrList::from(vec![1, 2]).iter().sum::<i32>(), 3);
+        assert_eq!(OneOrList::list(vec![1, 2]).into_iter().collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!((&OneOrList::one(3)).into_iter().next(), Some(&3));
+    }
+
+    #[test]
+    fn test_parse_hex() {
+        let png = vec![Some(0x89), Some(0x50), Some(0x4E), Some(0x47)];
+        assert_eq!(parse_hex("89504E47").unwrap(), png);
+        assert_eq!(parse_hex(" 89 50\t4e 47 ").unwrap(), png);
+        assert_eq!(parse_hex("").unwrap(), vec![]);
+        assert_eq!(parse_hex("52 ?? 45").unwrap(), vec![Some(0x52), None, Some(0x45)]);
+        assert!(parse_hex("895").unwrap_err().contains("Odd number"));
+        assert!(parse_hex("89 5 0").is_ok(), "Whitespace between digits of a pair is ignored");
+        assert!(parse_hex("0x89").unwrap_err().contains("'x' is not a hex digit"));
+        assert!(parse_hex("8?").unwrap_err().contains("\"8?\" is neither a byte nor"));
+        assert!(parse_hex("+8").is_err());
+    }
+
+    #[test]
+    fn test_header_matching() {
+        let header = Header::from(HeaderRepr::Hex("52494646 ???????? 57415645".to_owned()));
+        assert!(header.matches(b"RIFF\x24\x08\x00\x00WAVEfmt "));
+        assert!(header.matches(b"RIFF\xff\xff\xff\xffWAVE"));
+        assert!(!header.matches(b"RIFF\x24\x08\x00\x00AVI LIST"));
+        assert!(!header.matches(b"RIFF\x24\x08\x00\x00WAV"), "Too short to match");
+        assert!(Header::from(HeaderRepr::Bytes(vec![])).matches(b""));
+    }
+
+    /// Headers should be accepted as hex strings, byte lists, or a mix of the two
+    #[test]
+    #[rustfmt::skip]
+    fn test_hex_headers() {
+        let parsed = parse(r#"
+            [filetype.png]
+            description = "PNG"
+            header = "89 50 4e 47 0D 0A 1A 0A"
+            handler = "png"
+
+            [filetype.iff]
+            description = "IFF"
+            header = ["464F524D", [65, 73, 70, 70]]
+            handler = "iff"
+
+            [filetype.mp4]
+            description = "MPEG-4 Part 14"
+            header = "????????66747970"
+            handler = "ffmpeg"
+        "#, &|_| true).unwrap();
+        let headers = |id: &str| parsed.filetypes[id].header.as_deref().unwrap();
+        assert!(headers("png")[0].matches(&[137, 80, 78, 71, 13, 10, 26, 10]));
+        assert!(headers("iff")[0].matches(b"FORM") && headers("iff")[1].matches(b"AIFF"));
+
+        let dumped = parsed.to_toml().unwrap();
+        assert!(dumped.contains(r#"header = "89 50 4E 47 0D 0A 1A 0A""#), "{}", dumped);
+        assert!(dumped.contains(r#"header = ["46 4F 52 4D", "41 49 46 46"]"#), "{}", dumped);
+        assert!(dumped.contains(r#"header = "?? ?? ?? ?? 66 74 79 70""#), "{}", dumped);
+
+        for (header, expected) in &[
+            (r#""895""#, r#"Odd number of hex digits in header "895" for filetype: Bad"#),
+            (r#"["89", "PNG"]"#, r#"'P' is not a hex digit in header "PNG" for filetype: Bad"#),
+            (r#""""#, "Header patterns must not be empty sequences"),
+            (r#"["89", "?? ??"]"#, "Header patterns must not consist entirely of ?? wildcards"),
+        ] {
+            let err = format!("{:#}", parse(&format!(
+                "[filetype.bad]\ndescription = \"Bad\"\nheader = {}\nhandler = \"x\"\n",
+                header), &|_| true).unwrap_err());
+            assert!(err.contains(expected), "{}", err);
+        }
+    }
+
+    /// Unknown keys should be ignored unless strict, and named along with a likely correction
+    #[test]
+    #[rustfmt::skip]
+    fn test_unknown_keys() {
+        let toml_str = r#"
+            comment = "This key is left over from an old version"
+
+            [filetype.flac]
+            description = "FLAC"
+            extenson = "flac"
+            header = [102, 76, 97, 67]
+            handler = "flac"
+
+            [handler.flac]
+            argv = ["flac", "-t", "{path}"]
+            limits.memroy_mb = 100
+            fail_if_sterr = "ERROR"
+
+            [override."*.bak"]
+            handler = "flac"
+            mesage = "Backup"
+        "#;
+        let parsed = parse(toml_str, &|_| false).unwrap();
+        assert_eq!(parsed.filetypes["flac"].extension, None);
+        assert!(parsed.handlers["flac"].limits.is_empty());
+        assert_eq!(parsed.overrides["*.bak"].message, None);
+
+        for strict_str in &[format!("strict = true\n{}", toml_str), toml_str.to_owned()] {
+            let err = format!("{:#}", assemble(None, strict_str, None, true).unwrap_err());
+            assert!(err.contains(r#"Unknown key "comment" in the top level"#), "{}", err);
+        }
+        let err = format!("{:#}", assemble(None, r#"
+            [filetype.flac]
+            description = "FLAC"
+            extenson = "flac"
+            handler = "flac"
+        "#, None, true).unwrap_err());
+        assert!(err.contains(
+            r#"Unknown key "extenson" in [filetype.flac] (did you mean "extension"?)"#), "{}", err);
+        assert!(err.contains("line 4"), "{}", err);
+
+        for (toml_str, expected) in &[
+            ("[handler.flac]\nargv = [\"flac\"]\nlimits.memroy_mb = 100\n",
+             r#"in [handler.flac.limits] (did you mean "memory_mb"?)"#),
+            ("[handler.flac]\nargv = [\"flac\"]\nfail_if_sterr = \"ERROR\"\n",
+             r#"(did you mean "fail_if_stderr"?)"#),
+            ("[override.a]\nhandler = \"x\"\n[override.\"*.b\"]\nhandlr = \"x\"\n",
+             r#"in [override."*.b"] (did you mean "handler"?)"#),
+            ("[[override]]\npath = \"a\"\nignore = true\n\
+              [[override]]\npath = \"b\"\nhandlr = \"x\"\n",
+             r#"in [override.b] (did you mean "handler"?)"#),
+            ("[disabled]\nfiletype = [\"flac\"]\n",
+             r#"in [disabled] (did you mean "filetypes"?)"#),
+            ("frobnicate = true\n", r#""frobnicate" in the top level"#),
+        ] {
+            let err = parse_overlay("", toml_str, None, &|_| false, true).unwrap_err();
+            let err = format!("{:#}", err);
+            assert!(err.contains(expected), "{}", err);
+        }
+        let err = format!("{:#}", parse_overlay("", "frobnicate = true\n", None, &|_| false, true)
+            .unwrap_err());
+        assert!(!err.contains("did you mean"), "{}", err);
+    }
+
+    /// Strict mode should report every finding at once, but not ones inherited from the base
+    #[test]
+    #[rustfmt::skip]
+    fn test_strict_findings() {
+        let toml_str = r#"
+            [filetype.foo]
+            description = "Foo"
+            extension = "foo"
+            handler = ["json", "frobnicate"]
+
+            [override."*"]
+            handler = "frobnicate"
+        "#;
+        let is_builtin = |x: &str| x == "json";
+        assert!(parse(toml_str, &is_builtin).is_ok());
+        let err = format!("{:#}", parse(&format!("strict = true\n{}", toml_str), &is_builtin)
+            .unwrap_err());
+        for expected in &[
+            "Unrecognized handler for filetype foo: frobnicate",
+            "Unrecognized handler for override \"*\": frobnicate",
+            "Override with too-broad glob: *",
+        ] {
+            assert!(err.contains(expected), "{}", err);
+        }
+
+        let base = r#"
+            [filetype.bar]
+            description = "Bar"
+            extension = "bar"
+            handler = "missing"
+        "#;
+        assert!(parse_overlay(base, "", None, &|_| false, true).is_ok());
+        assert!(parse_overlay(base, "[disabled]\nfiletypes = [\"bar\"]\n", None, &|_| false, true)
+            .is_ok());
+        let err = format!("{:#}", parse_overlay(base, toml_str, None, &is_builtin, true)
+            .unwrap_err());
+        assert!(err.contains("filetype foo: frobnicate"), "{}", err);
+        assert!(!err.contains("filetype bar"), "{}", err);
+        assert!(parse_overlay(base, "strict = true\n", None, &|_| false, false).is_ok());
+
+        let findings = check_text(None, toml_str, None, &is_builtin, true).unwrap();
+        assert_eq!(findings.len(), 3);
+        assert!(findings.iter().all(|(x, _)| x.severity == Severity::Error));
+    }
+
+    /// Filetypes sharing an extension need different headers unless that's marked as intentional
+    #[test]
+    #[rustfmt::skip]
+    fn test_ambiguous_extensions() {
+        let make = |first: &str, second: &str| format!(r#"
+            [filetype.foo]
+            description = "Foo"
+            extension = ["foo", "FOOBAR"]
+            handler = "json"
+            {}
+
+            [filetype.bar]
+            description = "Bar"
+            extension = ["bar", "foobar"]
+            handler = "json"
+            {}
+        "#, first, second);
+
+        for (first, second) in &[
+            ("", ""),
+            ("header = \"01 02\"", "header = [1, 2]"),
+            ("header = [\"01\", \"02\"]", "header = [\"02\", \"01\", \"01\"]"),
+            ("header = \"01\"\nheader_offset = 0", "header = \"01\""),
+        ] {
+            let toml_str = make(first, second);
+            assert_validation_result(&toml_str, "__all__");
+            let err = format!("{:#}", parse(&toml_str, &|_| true).unwrap_err());
+            assert!(err.contains("Filetypes bar and foo both claim extension \"foobar\""),
+                    "{}", err);
+
+            do_validate(&format!("case_sensitive_extensions = true\n{}", toml_str))
+                .expect("Extensions which only match case-insensitively should be accepted");
+            do_validate(&make(&format!("{}\nallow_ambiguous = true", first), second))
+                .expect("allow_ambiguous should suppress the error");
+        }
+
+        for (first, second) in &[
+            ("header = \"01\"", ""),
+            ("header = \"01\"", "header = \"02\""),
+            ("header = \"01\"", "header = \"01\"\nheader_offset = 4"),
+            ("header = \"01\"", "header = [\"01\", \"02\"]"),
+        ] {
+            do_validate(&make(first, second))
+                .expect("Filetypes with different headers should be accepted");
+        }
+    }
+
+    /// Filetypes which are indistinguishable except by ID should be warned about
+    #[test]
+    #[rustfmt::skip]
+    fn test_ambiguous_priority() {
+        let toml_str = |priority: i32| format!(r#"
+            [filetype.sfx_rar]
+            description = "Self-extracting RAR"
+            extension = ["exe", "EXE"]
+            header = ["4D 5A", [77, 90]]
+            handler = "json"
+
+            [filetype.sfx_zip]
+            description = "Self-extracting Zip"
+            extension = "exe"
+            header = "4D 5A"
+            handler = "json"
+            priority = {}
+            allow_ambiguous = true
+
+            [filetype.win_pe]
+            description = "Windows executable"
+            extension = "exe"
+            handler = "json"
+        "#, priority);
+        let messages = |toml: &str| check_text(None, toml, None, &|x| x == "json", false).unwrap()
+            .into_iter().map(|(x, _)| (x.severity, x.message)).collect::<Vec<_>>();
+
+        assert_eq!(messages(&toml_str(0)), vec![(Severity::Warning,
+            "Filetypes sfx_rar and sfx_zip match the same files with the same priority, so \
+             sfx_rar will always be tried first".to_owned())]);
+        assert!(messages(&toml_str(1)).is_empty());
+        assert!(messages(&format!("case_sensitive_extensions = true\n{}", toml_str(0)))
+            .is_empty());
+    }
+
+    /// Findings should carry severities and point at the entry they're about
+    #[test]
+    #[rustfmt::skip]
+    fn test_check_text() {
+        let findings = check_text(None, r#"
+ignore = ["*.bak", "*"]
+
+[handler.env_path]
+argv = ["foo"]
+env = { PATH = "/opt/bin" }
+
+[filetype.foo]
+description = "Foo"
+extension = "foo"
+handler = "typo"
+
+[override."*.*"]
+handler = "zip"
+"#, None, &|_| false, false).unwrap();
+        let summary: Vec<_> = findings.iter()
+            .map(|(x, pos)| (x.severity, x.location.clone(), *pos))
+            .collect();
+        assert_eq!(summary, vec![
+            (Severity::Warning, Location::Handler("env_path".into()), Some((4, 1))),
+            (Severity::Error, Location::Filetype("foo".into()), Some((8, 1))),
+            (Severity::Error, Location::Override("*.*".into()), Some((13, 1))),
+            (Severity::Warning, Location::Override("*.*".into()), Some((13, 1))),
+            (Severity::Warning, Location::Ignore("*".into()), Some((2, 20))),
+        ]);
+
+        // Entries in the deprecated `[[override]]` form should still be found
+        let findings = check_text(None, "\n[[override]]\npath = \"*\"\nignore = true\n", None,
+                                  &|_| false, false).unwrap();
+        assert_eq!(findings.iter().map(|(_, pos)| *pos).collect::<Vec<_>>(), vec![Some((2, 1))]);
+
+        // Problems the base already had aren't the overlay's fault, but new ones are, even if
+        // they involve entries which only exist in the base
+        let base = r#"
+            [handler.bar]
+            argv = ["bar"]
+
+            [handler.qux]
+            argv = ["qux"]
+
+            [filetype.foo]
+            description = "Foo"
+            extension = "foo"
+            handler = "typo"
+
+            [filetype.bar]
+            description = "Bar"
+            extension = "bar"
+            handler = ["bar", "qux"]
+        "#;
+        assert!(check_text(Some(base), "", None, &|_| false, false).unwrap().is_empty());
+        let findings = check_text(Some(base), "[disabled]\nhandlers = [\"bar\"]\n", None,
+                                  &|_| false, false).unwrap();
+        assert_eq!(findings.len(), 1);
+        assert_eq!((&findings[0].0.location, findings[0].1),
+                   (&Location::Filetype("bar".into()), None));
+
+        // Syntax errors should give their position
+        let err = check_text(None, "[handler.foo\n", None, &|_| false, false).unwrap_err();
+        assert!(format!("{:#}", err).contains("line 1"), "{:#}", err);
+    }
+
+    /// Handlers fed via `stdin` can't also be given the path
+    #[test]
+    #[rustfmt::skip]
+    fn test_stdin_handlers() {
+        do_validate(r#"
+                [handler.xmllint]
+                argv = ["xmllint", "--noout", "-"]
+                input = "stdin"
+            "#).expect("stdin handlers without {path} should be accepted");
+        assert_validation_result(r#"
+                [handler.xmllint]
+                argv = ["xmllint", "--noout", "{path}"]
+                input = "stdin"
+            "#, "handler");
+    }
+
+    /// Exit codes may only be assigned to one category, including the default success code
+    #[test]
+    #[rustfmt::skip]
+    fn test_exit_code_validation() {
+        do_validate(r#"
+                [handler.sevenzip]
+                argv = ["7z", "t"]
+                exit_codes.success = [0, 1]
+                exit_codes.unsupported = [2]
+                exit_codes.internal = [7]
+            "#).expect("Distinct exit codes should be accepted");
+
+        for codes in &["exit_codes.success = [0, 1]\nexit_codes.unsupported = [1]",
+                       "exit_codes.unsupported = [2]\nexit_codes.internal = [2, 7]",
+                       "exit_codes.unsupported = [0]",
+                       "exit_codes.success = []"] {
+            assert_validation_result(&format!("[handler.foo]\nargv = [\"foo\"]\n{}", codes),
+                                     "handler");
+        }
+
+        let parsed: Handler = toml_edit::de::from_str(r#"
+                argv = ["foo"]
+                exit_codes.unsupported = [2]
+            "#).unwrap();
+        assert_eq!(parsed.exit_codes.success, vec![0], "success should default to [0]");
+    }
+
+    /// Ensure typos in substitution tokens are caught rather than passed through literally
+    #[test]
+    #[rustfmt::skip]
+    fn test_rejects_unknown_substitutions() {
+        assert_validation_result(r#"
+                [handler.foobar]
+                argv = [ "foo", "{pth}" ]
+            "#, "handler");
+        assert_validation_result(r#"
+                [handler.foobar]
+                argv = [ "foo", "--out={dev_null}", "{path}" ]
+            "#, "handler");
+
+        let err = do_validate(r#"
+                [handler.foobar]
+                argv = [ "foo", "{pth}" ]
+            "#).unwrap_err();
+        assert!(err.to_string().contains("{pth}"), "Message should name the token: {}", err);
+
+        assert_validation_result(r#"
+                [handler.foobar]
+                argv = [ "foo" ]
+                cwd = "{directory}"
+            "#, "handler");
+
+        assert_validation_result(r#"
+                [handler.foobar]
+                argv = [ "foo", "{args:format}" ]
+            "#, "handler");
+        assert_validation_result(r#"
+                [handler.foobar]
+                argv = [ "foo", "{arg:a:b}" ]
+            "#, "handler");
+
+        do_validate(r#"
+                [handler.foobar]
+                argv = [ "foo", "--in={path}", "-o", "{devnull}", "{not a token}", "{}",
+                         "--format={ext}", "{dir}/{basename}", "--pages={arg:multipage}" ]
+                cwd = "{dir}"
+            "#).expect("Known tokens and non-token braces should be accepted");
+    }
+
+    /// `handler_args` keys must be usable in tokens, and `multipage` must still be accepted
+    #[test]
+    #[rustfmt::skip]
+    fn test_handler_args() {
+        let parsed = parse(r#"
+            [filetype.tiff]
+            description = "TIFF"
+            extension = "tif"
+            handler = "image"
+            multipage = true
+
+            [filetype.dcx]
+            description = "DCX"
+            extension = "dcx"
+            handler = "image"
+            handler_args = { multipage = "yes", format = "dcx" }
+            multipage = true
+        "#, &|_| true).unwrap();
+        assert_eq!(parsed.filetypes["tiff"].handler_args["multipage"], "true");
+        assert_eq!(parsed.filetypes["dcx"].handler_args["multipage"], "yes",
+                   "An explicit handler_args entry should win over the legacy key");
+
+        let dumped = parsed.to_toml().unwrap();
+        assert!(!dumped.contains("\nmultipage"), "{}", dumped);
+        assert!(dumped.contains(r#"handler_args = { multipage = "true" }"#), "{}", dumped);
+
+        for key in &["", "has space", "has-dash", "a:b"] {
+            let err = format!("{:#}", parse(&format!(r#"
+                [filetype.foo]
+                description = "Foo"
+                extension = "foo"
+                handler = "image"
+                handler_args = {{ "{}" = "x" }}
+            "#, key), &|_| true).unwrap_err());
+            assert!(err.contains("handler_args keys must be non-empty"), "{}", err);
+        }
+    }
+
+    /// Make sure malformed extensions are rejected but multi-part extensions are accepted
+    #[test]
+    #[rustfmt::skip]
+    fn test_extension_validation() {
+        for ext in &["\"\"", "\".gz\"", "\"tar.\"", "\"tar..gz\"", "[\"gz\", \"\"]"] {
+            assert_validation_result(&format!(r#"
+                [filetype.foo]
+                description = "Foo"
+                handler = "foo"
+                extension = {}
+            "#, ext), "filetype");
+        }
+        do_validate(r#"
+                [filetype.foo]
+                description = "Foo"
+                handler = "foo"
+                extension = ["tar.gz", "tgz"]
+            "#).expect("Multi-part extensions should be accepted");
+    }
+
+    /// Make sure the validation catches 'container' cycles
+    #[test]
+    fn test_rejects_container_cycle() {
+        assert_validation_result(
+            r#"
+            [filetype.foo]
+            description = "Foo"
+            extension = "foo"
+            container = "bar"
+
+            [filetype.bar]
+            description = "Bar"
+            extension = "bar"
+            container = "baz"
+
+            [filetype.baz]
+            description = "Baz"
+            extension = "baz"
+            container = "foo"
+        "#,
+            "__all__",
+        );
+    }
+
+    /// Make sure the validation catches unknown 'container' values
+    #[test]
+    fn test_rejects_unknown_container() {
+        assert_validation_result(
+            r#"
+            [filetype.foo]
+            description = "Foo"
+            extension = "foo"
+            container = "bar"
+        "#,
+            "__all__",
+        );
+    }
+
+    /// Unknown 'container' values should be errors naming the filetype which has them, even if
+    /// it has a handler of its own or is only reached through another filetype's 'container'
+    #[test]
+    #[rustfmt::skip]
+    fn test_unknown_container_messages() {
+        for (toml_str, expected) in &[
+            (r#"
+                [filetype.foo]
+                description = "Foo"
+                extension = "foo"
+                handler = "json"
+                container = "bar"
+            "#, "'container' for filetype foo not found: bar"),
+            (r#"
+                [filetype.foo]
+                description = "Foo"
+                extension = "foo"
+                container = "baz"
+
+                [filetype.baz]
+                description = "Baz"
+                extension = "baz"
+                handler = "json"
+                container = "quux"
+            "#, "'container' for filetype baz not found: quux"),
+        ] {
+            assert_validation_result(toml_str, "__all__");
+            let err = format!("{:#}", parse(toml_str, &|_| true).unwrap_err());
+            assert!(err.contains(expected), "{}", err);
+            assert!(check_text(None, toml_str, None, &|_| true, false).is_err());
+        }
+    }
+
+    /// 'container' may list several candidates, and every one must be checked
+    #[test]
+    #[rustfmt::skip]
+    fn test_container_candidates() {
+        let candidates = r#"
+            [filetype.tiff]
+            description = "TIFF"
+            extension = "tif"
+            handler = "image"
+
+            [filetype.bigtiff]
+            description = "BigTIFF"
+            extension = "btf"
+            handler = "image"
+
+            [filetype.dng]
+            description = "Digital Negative"
+            extension = "dng"
+            container = ["tiff", "bigtiff"]
+        "#;
+        let parsed = parse(candidates, &|_| true).unwrap();
+        assert_eq!(parsed.filetypes["dng"].container,
+                   Some(vec!["tiff".to_owned(), "bigtiff".to_owned()].into()));
+        assert!(parsed.to_toml().unwrap().contains(r#"container = ["tiff", "bigtiff"]"#));
+
+        for (extra, expected) in &[
+            ("container = \"quux\"\n", "'container' for filetype bigtiff not found: quux"),
+            ("container = [\"dng\"]\n", "Cyclical 'container' dependency"),
+        ] {
+            // Put the extra key in the second candidate to make sure it isn't skipped
+            let toml_str = candidates.replace("extension = \"btf\"\n",
+                                              &format!("extension = \"btf\"\n{}", extra));
+            let err = format!("{:#}", parse(&toml_str, &|_| true).unwrap_err());
+            assert!(err.contains(expected), "{}", err);
+        }
+        assert_validation_result(r#"
+                [filetype.foo]
+                description = "Foo"
+                extension = "foo"
+                container = []
+            "#, "filetype");
+    }
+}
+
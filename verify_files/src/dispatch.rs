@@ -0,0 +1,2255 @@
+//! Machinery for matching files against `[filetype.*]` definitions and running their handlers
+//!
+//! **NOTE:** The detection indexes use `HashMap` rather than the `BTreeMap` used in
+//! [`config`](crate::config) because they're only ever used for lookups and never serialized,
+//! and they get hit once for every file in trees that may contain hundreds of thousands of them.
+
+// Standard library imports
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+// 3rd-party crate imports
+use globset::{GlobSet, GlobSetBuilder};
+use log::{debug, info};
+use zip::read::ZipArchive;
+
+// Local Imports
+use crate::builtin_handlers::{Builtin, Confidence, FailureType, GzipDecoder, StreamHandlerFn};
+use crate::config::{
+    build_glob, Filetype, Handler, HandlerArgs, InputKind, Override, Platform, Root,
+    NO_HANDLER_ARGS,
+};
+use crate::subprocess::{self, HandlerOutput};
+use crate::tempdir::TempDir;
+use crate::throttle::Throttle;
+
+/// The type of the registry exposed as [`builtin_handlers::ALL`](crate::builtin_handlers::ALL)
+///
+/// (Taken as an argument rather than used directly so tests can substitute fake handlers.)
+pub type BuiltinRegistry = BTreeMap<&'static str, Builtin>;
+
+/// A handler ID from the configuration file, resolved to something that can be invoked
+pub enum ResolvedHandler<'cfg> {
+    /// One of the handlers compiled into the binary
+    Builtin(&'cfg Builtin),
+    /// A subprocess defined in a `[handler.*]` table
+    External(&'cfg Handler),
+}
+
+/// The result of attempting to verify a single file
+#[derive(Clone, Debug)]
+pub enum Outcome<'cfg> {
+    /// A handler ran and reported no problems
+    Passed {
+        /// The `description` of the filetype the file was detected as
+        filetype: &'cfg str,
+        /// The ID of the handler which checked it
+        handler: &'cfg str,
+        /// How much the handler passing it says about it, if known
+        confidence: Option<Confidence>,
+    },
+    /// A handler ran and reported a problem which ends the fallback chain
+    ///
+    /// (This includes failures to read the file, not just corruption.)
+    Failed {
+        /// The `description` of the filetype the file was detected as
+        filetype: &'cfg str,
+        /// The ID of the handler which reported the failure
+        handler: &'cfg str,
+        /// What the handler reported
+        reason: FailureType,
+        /// What the handler printed, if it was an external command
+        output: Option<HandlerOutput>,
+    },
+    /// The file's header identifies it as a different filetype than its extension does
+    ///
+    /// (Reported in addition to the verdict from the header-selected filetype, since it usually
+    /// indicates a botched rename even if the content itself is fine.)
+    Mismatched {
+        /// The `description` of the filetype the extension suggests
+        by_extension: &'cfg str,
+        /// The `description` of the filetype the header suggests, which was used to verify it
+        by_header: &'cfg str,
+    },
+    /// The file is larger than its filetype's `max_size` (or `--max-size`), so no handler was
+    /// run on it
+    TooLarge {
+        /// The `description` of the filetype the file was detected as
+        filetype: &'cfg str,
+    },
+    /// The file is zero bytes long, so no handler was run on it
+    Empty {
+        /// The `description` of the filetype the file was detected as
+        filetype: &'cfg str,
+        /// Whether the filetype's `valid_if_empty` declares this acceptable
+        valid: bool,
+    },
+    /// The file was recognized, but no available handler could reach a verdict on it
+    Unverified {
+        /// The `description` of the filetype the file was detected as
+        filetype: &'cfg str,
+        /// Why each handler in the fallback chain couldn't be used
+        reasons: Vec<String>,
+    },
+    /// No `[filetype.*]` definition claims this file
+    Unrecognized,
+    /// The file couldn't be read to perform detection
+    Unreadable(/** Stringified form of the internal error message */ String),
+    /// The path is a FIFO, socket, or device node, so reading it could block forever or
+    /// produce nonsense, and it was never handed to a handler
+    ///
+    /// (Produced while walking the filesystem, since such paths never reach the dispatcher.)
+    Special {
+        /// A human-readable name for the kind of special file
+        kind: &'static str,
+        /// Whether `--include-special` asked for this to count as a pass (at the lowest confidence)
+        included: bool,
+    },
+    /// The path is a symbolic link whose target doesn't exist
+    ///
+    /// (Produced while walking the filesystem, since such paths never reach the dispatcher.)
+    BrokenSymlink(/** Where the link points, if it could be read */ Option<PathBuf>),
+}
+
+/// Settings which control how files are processed, as opposed to how they're detected
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Validate the members of archives as if they were separate files even if their filetype
+    /// doesn't set `recurse`
+    pub recurse: bool,
+    /// How many levels of archives within archives to recurse into
+    pub max_depth: usize,
+    /// Report [`Outcome::Mismatched`] when a file's header contradicts its extension
+    pub warn_mismatched: bool,
+    /// How long to let external handlers which don't set their own `timeout` run
+    pub handler_timeout: Option<Duration>,
+    /// Where to create temporary directories instead of the system's temporary directory
+    pub scratch_dir: Option<PathBuf>,
+    /// How many external handlers may run at once, separately from any other parallelism
+    pub subprocess_jobs: usize,
+    /// The size in bytes above which files are skipped, for filetypes which don't set their own
+    /// `max_size`
+    pub max_size: Option<u64>,
+    /// How much the process's priority was lowered by (as with `nice -n`)
+    ///
+    /// (On Unix, subprocesses inherit this automatically, but Windows needs to be told.)
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub nice: Option<u8>,
+    /// The platform to treat as current when deciding which handlers' `platforms` allow them
+    /// to be used
+    pub platform: Option<Platform>,
+}
+
+impl Options {
+    /// Create a temporary directory inside [`scratch_dir`](Self::scratch_dir)
+    pub fn tempdir(&self) -> io::Result<TempDir> {
+        match self.scratch_dir {
+            Some(ref parent) => TempDir::new_in(parent),
+            None => TempDir::new(),
+        }
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            recurse: false,
+            max_depth: 5,
+            warn_mismatched: true,
+            handler_timeout: None,
+            scratch_dir: None,
+            subprocess_jobs: thread::available_parallelism().map_or(1, |x| x.get()),
+            max_size: None,
+            nice: None,
+            platform: Platform::current(),
+        }
+    }
+}
+
+/// The callback type used to report each [`Outcome`] along with the path to display for it
+///
+/// (More than one outcome may be reported per file when recursing into archives.)
+pub type ReportFn<'a, 'cfg> = dyn FnMut(&Path, Outcome<'cfg>) + 'a;
+
+/// Wrapper which remembers the first error returned by the reader it wraps
+///
+/// (Used to tell a failure in a decompressor apart from a handler rejecting the decompressed
+/// data it was fed.)
+struct ErrorTap<R> {
+    /// The reader being wrapped
+    inner: R,
+    /// The stringified form of the first error `inner` returned, if any
+    error: Option<String>,
+}
+
+impl<R: Read> Read for ErrorTap<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let result = self.inner.read(buf);
+        if let Err(ref err) = result {
+            self.error.get_or_insert_with(|| err.to_string());
+        }
+        result
+    }
+}
+
+/// Build the path used to report an outcome for something found inside an archive
+/// (eg. `archive.zip!path/inside/archive`)
+fn member_display(display: &Path, name: impl AsRef<std::ffi::OsStr>) -> PathBuf {
+    let mut member_display: OsString = display.as_os_str().to_owned();
+    member_display.push("!");
+    member_display.push(name);
+    member_display.into()
+}
+
+/// Check whether any of the given filetype's headers match the start of the file
+///
+/// `prefix` may be shorter than the header (eg. for a truncated file), in which case it is
+/// treated as a non-match.
+pub fn header_matches(filetype: &Filetype, prefix: &[u8]) -> bool {
+    matched_header_len(filetype, prefix).is_some()
+}
+
+/// Return the length of the longest of the filetype's headers which matches the given prefix
+///
+/// (Used to pick the most specific filetype when several headers match.)
+fn matched_header_len(filetype: &Filetype, prefix: &[u8]) -> Option<usize> {
+    let body = prefix.get(filetype.header_offset..)?;
+    filetype
+        .header
+        .iter()
+        .flatten()
+        .filter(|header| header.matches(body))
+        .map(|x| x.len())
+        .max()
+}
+
+/// Build the outcome for a zero-byte file of the given type
+fn empty_outcome(filetype: &Filetype) -> Outcome<'_> {
+    Outcome::Empty { filetype: &filetype.description, valid: filetype.valid_if_empty }
+}
+
+/// Read up to `len` bytes from the start of the given file
+///
+/// (Returns fewer bytes without complaint if the file is shorter than that.)
+fn read_prefix(path: &Path, len: usize) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(len);
+    File::open(path)?.take(len as u64).read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// Check whether a handler chain is made up of `[handler.*]` entries which take directories
+///
+/// (`config::parse` has already rejected chains which mix file and directory handlers.)
+fn is_directory_chain(config: &Root, chain: &[String]) -> bool {
+    chain.first().and_then(|id| config.handlers.get(id)).map_or(false, |x| {
+        x.input == InputKind::Directory
+    })
+}
+
+/// The subset of a [`Dispatcher`]'s rules needed to decide whether the walker should hand a
+/// directory to a directory handler instead of descending into it
+///
+/// (Split out so it can be owned by the walker's filter callback.)
+#[derive(Clone, Debug)]
+pub struct DirectoryClaims {
+    /// The globs of every `[override.<glob>]` which forces a directory handler or filetype
+    overrides: GlobSet,
+    /// The `directory_name` globs of every filetype, matched against the final path component
+    names: GlobSet,
+}
+
+impl DirectoryClaims {
+    /// Check whether a directory, given relative to the root it was found under, should be
+    /// handed to a directory handler
+    pub fn claims(&self, rel_path: &Path) -> bool {
+        self.overrides.is_match(rel_path)
+            || rel_path.file_name().map_or(false, |x| self.names.is_match(x))
+    }
+}
+
+/// Pre-indexed view of a parsed configuration, ready to be matched against files
+///
+/// Build one of these once at startup and then call [`Dispatcher::process`] for each file.
+pub struct Dispatcher<'cfg> {
+    /// The configuration the indexes were built from
+    config: &'cfg Root,
+    /// The built-in handlers available for `handler` fields to reference
+    builtins: &'cfg BuiltinRegistry,
+    /// A map from extensions to the IDs of the filetypes which claim them
+    ///
+    /// (Keys are lowercased unless `case_sensitive_extensions` is set.)
+    by_extension: HashMap<String, Vec<&'cfg str>>,
+    /// The IDs of all filetypes which declare at least one header
+    with_header: Vec<&'cfg str>,
+    /// How many bytes must be read from the start of a file to test every header
+    prefix_len: usize,
+    /// The globs of every `[override.<glob>]` which forces a file handler or filetype
+    override_globs: GlobSet,
+    /// The globs and overrides corresponding to each pattern in `override_globs`, in the same
+    /// order
+    handler_overrides: Vec<(&'cfg str, &'cfg Override)>,
+    /// The globs used to decide which directories are handed to directory handlers
+    directory_claims: DirectoryClaims,
+    /// The globs and overrides corresponding to each pattern in `directory_claims.overrides`
+    directory_overrides: Vec<(&'cfg str, &'cfg Override)>,
+    /// The filetype IDs corresponding to each pattern in `directory_claims.names`
+    directory_filetypes: Vec<&'cfg str>,
+    /// Settings which aren't part of the configuration file
+    options: Options,
+    /// The concurrency limits for external handlers
+    throttle: Throttle,
+}
+
+impl<'cfg> Dispatcher<'cfg> {
+    /// Index the given configuration for fast lookup
+    pub fn new(config: &'cfg Root, builtins: &'cfg BuiltinRegistry, options: Options) -> Self {
+        let mut by_extension: HashMap<String, Vec<&str>> = HashMap::new();
+        let mut with_header = Vec::new();
+        // Always read at least one byte so empty files can be told apart from non-empty ones
+        let mut prefix_len = 1;
+        for (id, filetype) in &config.filetypes {
+            for ext in filetype.extension.iter().flatten() {
+                let ext = if config.case_sensitive_extensions {
+                    ext.to_owned()
+                } else {
+                    ext.to_lowercase()
+                };
+                by_extension.entry(ext).or_default().push(id.as_str());
+            }
+            if let Some(ref headers) = filetype.header {
+                with_header.push(id.as_str());
+                for header in headers.iter() {
+                    let len = filetype.header_offset.saturating_add(header.len());
+                    prefix_len = prefix_len.max(len);
+                }
+            }
+        }
+
+        // `config::parse` has already rejected invalid globs, so it's safe to skip them here
+        let mut globs = GlobSetBuilder::new();
+        let mut dir_globs = GlobSetBuilder::new();
+        let (mut handler_overrides, mut directory_overrides) = (Vec::new(), Vec::new());
+        for (pattern, override_) in &config.overrides {
+            let chain = override_.handler.as_deref().unwrap_or(&[]);
+            let is_directory = match override_.filetype.as_deref() {
+                Some(id) => config.filetypes.get(id).map_or(false, |x| x.directory_name.is_some()),
+                None => is_directory_chain(config, chain),
+            };
+            let is_noop = chain.is_empty() && override_.filetype.is_none();
+            if let (Ok(glob), false) = (build_glob(pattern), is_noop) {
+                if is_directory {
+                    dir_globs.add(glob);
+                    directory_overrides.push((pattern.as_str(), override_));
+                } else {
+                    globs.add(glob);
+                    handler_overrides.push((pattern.as_str(), override_));
+                }
+            }
+        }
+        let mut name_globs = GlobSetBuilder::new();
+        let mut directory_filetypes = Vec::new();
+        for (id, filetype) in &config.filetypes {
+            for name in filetype.directory_name.iter().flatten() {
+                if let Ok(glob) = build_glob(name) {
+                    name_globs.add(glob);
+                    directory_filetypes.push(id.as_str());
+                }
+            }
+        }
+
+        let build = |x: GlobSetBuilder| x.build().unwrap_or_else(|_| GlobSet::empty());
+        let directory_claims =
+            DirectoryClaims { overrides: build(dir_globs), names: build(name_globs) };
+        let throttle = Throttle::new(config, options.subprocess_jobs);
+        let dispatcher = Self { config, builtins, by_extension, with_header, prefix_len,
+                                override_globs: build(globs), handler_overrides,
+                                directory_claims, directory_overrides, directory_filetypes,
+                                options, throttle };
+        dispatcher.note_ignored_args();
+        dispatcher
+    }
+
+    /// Mention any `handler_args` which built-in handlers will ignore in the debug output, in
+    /// case they're typos
+    fn note_ignored_args(&self) {
+        for (id, filetype) in &self.config.filetypes {
+            let chains = self.handler_chains(filetype).unwrap_or_default();
+            for handler_id in chains.into_iter().flatten() {
+                if let Some(ResolvedHandler::Builtin(builtin)) = self.resolve_handler(handler_id) {
+                    for key in filetype.handler_args.keys() {
+                        if !builtin.args.contains(&key.as_str()) {
+                            debug!("Built-in handler {} ignores handler_args.{} for filetype {}",
+                                   handler_id, key, id);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Find the `[override.<glob>]` which forces a handler for the given root-relative path,
+    /// along with its glob
+    ///
+    /// If more than one matches, the last one in the configuration file wins.
+    pub fn match_override(&self, rel_path: &Path) -> Option<(&'cfg str, &'cfg Override)> {
+        self.override_globs.matches(rel_path).into_iter().max().map(|x| self.handler_overrides[x])
+    }
+
+    /// Look up the IDs of the filetypes which claim the given path's extension
+    ///
+    /// Multi-part extensions like `tar.gz` are supported by trying every suffix of the filename
+    /// which follows a period, from longest to shortest, and using the first one that matches.
+    /// (A leading period, as in `.bashrc`, doesn't count.)
+    ///
+    /// Matching is case-insensitive unless `case_sensitive_extensions` is set.
+    ///
+    /// When more than one filetype matches, they are listed in ID order, with ordering by
+    /// `priority` left to the callers.
+    pub fn match_extension(&self, path: &Path) -> &[&'cfg str] {
+        let name = match path.file_name() {
+            Some(name) if self.config.case_sensitive_extensions => name.to_string_lossy(),
+            Some(name) => name.to_string_lossy().to_lowercase().into(),
+            None => return &[],
+        };
+        name.char_indices()
+            .skip(1)
+            .filter(|(_, chr)| *chr == '.')
+            .find_map(|(idx, _)| self.by_extension.get(&name[idx + 1..]))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Remove the given extension from the end of the given filename, respecting the
+    /// `case_sensitive_extensions` setting
+    fn strip_extension<'a>(&self, name: &'a str, ext: &str) -> Option<&'a str> {
+        let split = name.len().checked_sub(ext.len())?;
+        let (stem, suffix) = (name.get(..split)?, name.get(split..)?);
+        let matches = if self.config.case_sensitive_extensions {
+            suffix == ext
+        } else {
+            suffix.eq_ignore_ascii_case(ext)
+        };
+        if matches {
+            stem.strip_suffix('.')
+        } else {
+            None
+        }
+    }
+
+    /// Look up the IDs of the filetypes with a header which matches the given file prefix
+    ///
+    /// Results are ordered from most to least specific (ie. longest matched header first), with
+    /// ties broken by `priority` and then by filetype ID.
+    pub fn match_header(&self, prefix: &[u8]) -> Vec<&'cfg str> {
+        let mut matches: Vec<_> = self
+            .with_header
+            .iter()
+            .filter_map(|id| {
+                matched_header_len(&self.config.filetypes[*id], prefix).map(|len| (len, *id))
+            })
+            .collect();
+        let priority = |id: &str| self.config.filetypes[id].priority;
+        matches.sort_by(|a, b| {
+            b.0.cmp(&a.0).then(priority(b.1).cmp(&priority(a.1))).then(a.1.cmp(b.1))
+        });
+        matches.into_iter().map(|(_, id)| id).collect()
+    }
+
+    /// Sort filetype IDs by descending `priority`, then by ID
+    fn sort_by_priority(&self, ids: &mut [&'cfg str]) {
+        let filetypes = &self.config.filetypes;
+        ids.sort_by(|a, b| filetypes[*b].priority.cmp(&filetypes[*a].priority).then(a.cmp(b)));
+    }
+
+    /// Decide which filetype the given file should be treated as
+    ///
+    /// This is the first of the candidates returned by [`detect_all`](Self::detect_all).
+    pub fn detect(&self, path: &Path, prefix: &[u8]) -> Option<&'cfg str> {
+        self.detect_all(path, prefix).first().copied()
+    }
+
+    /// List the filetypes the given file could be, in the order they should be tried
+    ///
+    /// `prefix` should be as many bytes from the start of the file as the longest header needs,
+    /// or the whole file if it's shorter than that.
+    ///
+    /// Candidates come from the first of these groups which isn't empty, sorted by `priority`:
+    ///
+    /// 1. Filetypes claiming the extension whose header also matches, followed by those which
+    ///    don't declare a header to check
+    /// 2. Filetypes whose header matches, regardless of extension (eg. a JPEG saved as `.png`
+    ///    or an extensionless file), with the longest matching header first
+    /// 3. Filetypes claiming the extension whose header didn't match (so the handler can report
+    ///    the corruption)
+    pub fn detect_all(&self, path: &Path, prefix: &[u8]) -> Vec<&'cfg str> {
+        let by_ext = self.match_extension(path);
+        let filetypes = &self.config.filetypes;
+
+        let (mut checked, mut unchecked): (Vec<_>, Vec<_>) = by_ext
+            .iter()
+            .filter(|id| {
+                let filetype = &filetypes[**id];
+                filetype.header.is_none() || header_matches(filetype, prefix)
+            })
+            .partition(|id| filetypes[**id].header.is_some());
+        self.sort_by_priority(&mut checked);
+        self.sort_by_priority(&mut unchecked);
+        checked.extend(unchecked);
+        if !checked.is_empty() {
+            return checked;
+        }
+
+        let by_header = self.match_header(prefix);
+        if !by_header.is_empty() {
+            return by_header;
+        }
+
+        let mut mismatched = by_ext.to_vec();
+        self.sort_by_priority(&mut mismatched);
+        mismatched
+    }
+
+    /// Check whether the filetype chosen by a file's header disagrees with the one its
+    /// extension claims, returning an [`Outcome::Mismatched`] if so
+    ///
+    /// Filetypes without a `header` are exempt, as are filetypes related by `container` (eg. a
+    /// `.cbz` with a Zip header, or a `.jar` which looks like an Android package).
+    pub fn check_mismatch(&self, path: &Path, prefix: &[u8]) -> Option<Outcome<'cfg>> {
+        let filetypes = &self.config.filetypes;
+        let mut by_ext = self.match_extension(path).to_vec();
+        let exempt = |id: &&str| {
+            let filetype = &filetypes[*id];
+            filetype.header.is_none() || header_matches(filetype, prefix)
+        };
+        if by_ext.is_empty() || by_ext.iter().any(exempt) {
+            return None;
+        }
+
+        let by_header = *self.match_header(prefix).first()?;
+        if by_ext.iter().any(|id| self.are_related(id, by_header)) {
+            return None;
+        }
+        self.sort_by_priority(&mut by_ext);
+        Some(Outcome::Mismatched {
+            by_extension: &filetypes[by_ext[0]].description,
+            by_header: &filetypes[by_header].description,
+        })
+    }
+
+    /// Check whether two filetypes are the same or share an ancestor via `container`
+    fn are_related(&self, first: &str, second: &str) -> bool {
+        let second = self.ancestors(second);
+        self.ancestors(first).iter().any(|x| second.contains(x))
+    }
+
+    /// List the given filetype ID followed by the IDs of everything reachable from it through
+    /// `container` (along every branch, for filetypes with more than one candidate)
+    fn ancestors(&self, id: &str) -> Vec<&'cfg str> {
+        let mut found = Vec::new();
+        let mut pending: Vec<_> = self.config.filetypes.get_key_value(id).into_iter().collect();
+        while let Some((id, filetype)) = pending.pop() {
+            // `config::parse` rejects cycles, but don't hang if that ever regresses
+            if found.contains(&id.as_str()) {
+                continue;
+            }
+            found.push(id.as_str());
+            pending.extend(filetype.container.iter().flatten().rev().filter_map(|x| {
+                self.config.filetypes.get_key_value(x)
+            }));
+        }
+        found
+    }
+
+    /// Read the prefix of the given file and [`detect`](Self::detect) its type without
+    /// running any handlers
+    pub fn identify(&self, path: &Path) -> io::Result<Option<&'cfg str>> {
+        read_prefix(path, self.prefix_len).map(|prefix| self.detect(path, &prefix))
+    }
+
+    /// Resolve a handler ID to either a built-in or a `[handler.*]` entry
+    ///
+    /// `[handler.*]` entries take precedence so users can replace built-ins if they need to.
+    pub fn resolve_handler(&self, id: &str) -> Option<ResolvedHandler<'cfg>> {
+        if let Some(handler) = self.config.handlers.get(id) {
+            Some(ResolvedHandler::External(handler))
+        } else {
+            self.builtins.get(id).map(ResolvedHandler::Builtin)
+        }
+    }
+
+    /// Whether the given `[handler.*]` entry's `platforms` allow it to be used here
+    pub fn runs_here(&self, handler: &Handler) -> bool {
+        handler.runs_on(self.options.platform)
+    }
+
+    /// The rules for deciding which directories [`process_directory`](Self::process_directory)
+    /// should be given rather than walking into them
+    pub fn directory_claims(&self) -> DirectoryClaims {
+        self.directory_claims.clone()
+    }
+
+    /// Find the `[override.<glob>]` which forces a directory handler for the given
+    /// root-relative path, along with its glob
+    ///
+    /// If more than one matches, the last one in the configuration file wins.
+    pub fn match_directory_override(&self, rel_path: &Path)
+            -> Option<(&'cfg str, &'cfg Override)> {
+        let matches = self.directory_claims.overrides.matches(rel_path);
+        matches.into_iter().max().map(|x| self.directory_overrides[x])
+    }
+
+    /// Run the appropriate directory handler on a directory
+    /// [claimed](DirectoryClaims::claims) by one, and report the outcome
+    ///
+    /// Overrides take precedence over `directory_name`, just as they do over detection for
+    /// files.
+    pub fn process_directory(&self, path: &Path, rel_path: &Path,
+                             report: &mut ReportFn<'_, 'cfg>) {
+        if let Some((glob, override_)) = self.match_directory_override(rel_path) {
+            if let Some(id) = self.forced_filetype(override_) {
+                return report(path, self.run_filetypes(&[id], path).1);
+            }
+            let chain = override_.handler.as_deref().unwrap_or(&[]);
+            return report(path, self.run_chain(glob, chain, &NO_HANDLER_ARGS, path));
+        }
+
+        let name = rel_path.file_name().unwrap_or_else(|| rel_path.as_os_str());
+        let matches = self.directory_claims.names.matches(name);
+        let mut candidates: Vec<_> =
+            matches.into_iter().map(|x| self.directory_filetypes[x]).collect();
+        candidates.dedup();
+        self.sort_by_priority(&mut candidates);
+        if candidates.is_empty() {
+            report(path, Outcome::Unrecognized);
+        } else {
+            report(path, self.run_filetypes(&candidates, path).1);
+        }
+    }
+
+    /// Detect the type of the given file, run the appropriate handler on it, and report the
+    /// outcome (plus those of any archive members recursed into) via the given callback
+    ///
+    /// `rel_path` is the path relative to the root it was found under, for matching against
+    /// `[override.<glob>]` globs, which take precedence over detection.
+    ///
+    /// (Displaying the override's `message` is left to the caller, so it can be shown as part of
+    /// the report.)
+    ///
+    /// `len` is the size of the file, if already known from walking the filesystem. Otherwise,
+    /// the file is considered empty if no bytes could be read from it.
+    pub fn process(&self, path: &Path, rel_path: &Path, len: Option<u64>,
+                   report: &mut ReportFn<'_, 'cfg>) {
+        // Overrides which only force a handler have no filetype, so identify them by their glob
+        // in reports
+        let (description, override_) = match self.match_override(rel_path) {
+            Some(matched) => matched,
+            None => return self.process_inner(path, path, len, 0, report),
+        };
+        let chain = override_.handler.as_deref();
+        let len = match len {
+            Some(len) => Some(len),
+            None => read_prefix(path, 1).ok().filter(|x| x.is_empty()).map(|_| 0),
+        };
+        let too_large = match (len, self.options.max_size) {
+            (Some(len), Some(limit)) => len > limit,
+            _ => false,
+        };
+        if let Some(id) = self.forced_filetype(override_) {
+            self.process_as(path, path, len, &[id], 0, report);
+        } else if len == Some(0) {
+            report(path, Outcome::Empty { filetype: description, valid: false });
+        } else if too_large {
+            debug!("{} is larger than the --max-size limit", path.display());
+            report(path, Outcome::TooLarge { filetype: description });
+        } else {
+            let chain = chain.unwrap_or(&[]);
+            report(path, self.run_chain(description, chain, &NO_HANDLER_ARGS, path));
+        }
+    }
+
+    /// Implementation of [`process`](Self::process) which can be called recursively
+    ///
+    /// `display` is the path to use in reports, which differs from `path` when the file
+    /// was extracted from an archive, and `depth` is the number of archives it is nested inside.
+    fn process_inner(&self, path: &Path, display: &Path, len: Option<u64>, depth: usize,
+                     report: &mut ReportFn<'_, 'cfg>) {
+        let prefix = match read_prefix(path, self.prefix_len) {
+            Ok(prefix) => prefix,
+            Err(err) => return report(display, Outcome::Unreadable(err.to_string())),
+        };
+        let candidates = self.detect_all(path, &prefix);
+        if candidates.is_empty() {
+            return report(display, Outcome::Unrecognized);
+        } else if candidates.len() > 1 {
+            debug!("Multiple filetypes match {}. Trying in order: {}", display.display(),
+                   candidates.join(", "));
+        }
+        let len = if len.is_none() && prefix.is_empty() { Some(0) } else { len };
+        if self.options.warn_mismatched && len != Some(0) {
+            if let Some(outcome) = self.check_mismatch(path, &prefix) {
+                report(display, outcome);
+            }
+        }
+        self.process_as(path, display, len, &candidates, depth, report);
+    }
+
+    /// Look up the filetype an override forces, if it forces one that exists
+    fn forced_filetype(&self, override_: &'cfg Override) -> Option<&'cfg str> {
+        override_.filetype.as_deref().filter(|x| self.config.filetypes.contains_key(*x))
+    }
+
+    /// Run the handlers for the given candidate filetypes (most likely first) on a file,
+    /// including any recursion into archive members the winning filetype asks for
+    ///
+    /// `len` is the size of the file if it's known, which it always is for empty files, and is
+    /// checked against the most likely filetype's `max_size` (or `--max-size`).
+    ///
+    /// (Split out of [`process_inner`](Self::process_inner) so overrides which force a
+    /// `filetype` can skip detection.)
+    fn process_as(&self, path: &Path, display: &Path, len: Option<u64>,
+                  candidates: &[&'cfg str], depth: usize, report: &mut ReportFn<'_, 'cfg>) {
+        let filetype = &self.config.filetypes[candidates[0]];
+        if len == Some(0) {
+            return report(display, empty_outcome(filetype));
+        }
+        let limit = filetype.max_size.map(|x| x.0).or(self.options.max_size);
+        if let (Some(len), Some(limit)) = (len, limit) {
+            if len > limit {
+                debug!("{} is {} bytes, but the limit is {}", display.display(), len, limit);
+                return report(display, Outcome::TooLarge { filetype: &filetype.description });
+            }
+        }
+
+        let recurse = self.wants_recursion(filetype);
+        if recurse && self.chain_starts_with_builtin(filetype, "gzip") {
+            let inner_name = path
+                .file_name()
+                .and_then(|x| x.to_str())
+                .and_then(|x| self.strip_extension(x, "gz"))
+                .filter(|x| !self.match_extension(Path::new(x)).is_empty());
+            if let Some(inner_name) = inner_name {
+                if depth < self.options.max_depth {
+                    return self.recurse_gzip(filetype, path, display, inner_name, depth + 1,
+                                             report);
+                }
+                info!("Not recursing more than {} archives deep: {}", self.options.max_depth,
+                      display.display());
+            }
+        }
+
+        let (filetype, outcome) = self.run_filetypes(&candidates, path);
+        let recurse = self.wants_recursion(filetype)
+            && matches!(outcome, Outcome::Passed { handler: "zip", .. })
+            && !self.config.handlers.contains_key("zip");
+        report(display, outcome);
+
+        if recurse {
+            if depth < self.options.max_depth {
+                if let Err(err) = self.recurse_zip(path, display, depth + 1, report) {
+                    let msg = format!("Could not extract archive members: {}", err);
+                    report(display, Outcome::Unreadable(msg));
+                }
+            } else {
+                info!("Not recursing more than {} archives deep: {}", self.options.max_depth,
+                      display.display());
+            }
+        }
+    }
+
+    /// Check whether the first handler in a filetype's (`container`-resolved) fallback chain is
+    /// the given built-in, rather than a `[handler.*]` entry with the same ID
+    fn chain_starts_with_builtin(&self, filetype: &'cfg Filetype, id: &str) -> bool {
+        self.handler_chains(filetype).ok()
+            .and_then(|x| x.first().and_then(|chain| chain.first()))
+            .map_or(false, |x| x == id)
+            && !self.config.handlers.contains_key(id)
+            && self.builtins.contains_key(id)
+    }
+
+    /// Check whether the given filetype, or any filetype in its `container` chain (along any
+    /// branch), has asked for its members to be validated
+    ///
+    /// (This relies on [`config::parse`](crate::config::parse) having already rejected
+    /// `container` cycles.)
+    fn wants_recursion(&self, filetype: &Filetype) -> bool {
+        self.options.recurse
+            || filetype.recurse
+            || filetype.container.iter().flatten()
+                .filter_map(|x| self.config.filetypes.get(x))
+                .any(|x| self.wants_recursion(x))
+    }
+
+    /// Extract each member of a Zip archive to a temporary file and process it as if it had
+    /// been found during the walk, reporting it as `archive.zip!path/inside/archive`.
+    fn recurse_zip(&self, path: &Path, display: &Path, depth: usize,
+                   report: &mut ReportFn<'_, 'cfg>) -> anyhow::Result<()> {
+        let tempdir = self.options.tempdir()?;
+        let mut zip = ZipArchive::new(File::open(path)?)?;
+        for idx in 0..zip.len() {
+            let mut member = zip.by_index(idx)?;
+            if member.is_dir() {
+                continue;
+            }
+
+            // Use only the final component so the temporary file can't escape `tempdir`
+            let name = member.name().to_owned();
+            let temp_path = tempdir.path().join(Path::new(&name).file_name().unwrap_or_else(
+                || "member".as_ref()));
+            io::copy(&mut member, &mut File::create(&temp_path)?)?;
+
+            let len = Some(member.size());
+            drop(member);
+            self.process_inner(&temp_path, &member_display(display, &name), len, depth, report);
+            fs::remove_file(&temp_path)?;
+        }
+        Ok(())
+    }
+
+    /// Decompress a GZip file once, using the decompressed data both to check the CRC and to
+    /// validate the payload, which is detected by stripping `.gz` from `path`.
+    ///
+    /// If the payload's handler can validate streams, it's fed the data directly. Otherwise, the
+    /// data is extracted to a temporary file and processed as though it was found on disk.
+    ///
+    /// (Since a stream can't be rewound, only the first available handler in the payload's
+    /// fallback chain is considered.)
+    fn recurse_gzip(&self, filetype: &'cfg Filetype, path: &Path, display: &Path,
+                    inner_name: &str, depth: usize, report: &mut ReportFn<'_, 'cfg>) {
+        let description = filetype.description.as_str();
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) => return report(display, Outcome::Unreadable(err.to_string())),
+        };
+        let mut decoder =
+            ErrorTap { inner: GzipDecoder::new(BufReader::new(file)), error: None };
+
+        // Errors are recorded by the `ErrorTap`, so they can be ignored here
+        let mut prefix = Vec::with_capacity(self.prefix_len);
+        #[allow(clippy::let_underscore_must_use)]
+        let _ = (&mut decoder).take(self.prefix_len as u64).read_to_end(&mut prefix);
+
+        let inner_path = Path::new(inner_name);
+        let inner_display = member_display(display, inner_name);
+        let inner_filetype = self.detect(inner_path, &prefix).map(|id| &self.config.filetypes[id]);
+        let stream_handler = inner_filetype.and_then(|x| self.stream_handler(x));
+
+        let mut inner_outcome = None;
+        let mut spooled = None;
+        let result = {
+            let mut stream = io::Cursor::new(&prefix).chain(&mut decoder);
+            if let (Some(inner), true) = (inner_filetype, prefix.is_empty()) {
+                inner_outcome = Some(empty_outcome(inner));
+                Ok(())
+            } else if let (Some(inner), Some((id, builtin, func))) =
+                (inner_filetype, stream_handler)
+            {
+                let result = func(&mut stream, &inner.handler_args);
+                inner_outcome = Some(match result {
+                    Ok(weaker) => Outcome::Passed { filetype: &inner.description, handler: id,
+                        confidence: Some(weaker.unwrap_or(builtin.confidence)) },
+                    Err(reason) => {
+                        Outcome::Failed { filetype: &inner.description, handler: id, reason,
+                                          output: None }
+                    },
+                });
+                io::copy(&mut stream, &mut io::sink()).map(|_| ())
+            } else {
+                self.options.tempdir().and_then(|tempdir| {
+                    let temp_path = tempdir.path().join(inner_path);
+                    let len = io::copy(&mut stream, &mut File::create(&temp_path)?)?;
+                    spooled = Some((tempdir, temp_path, len));
+                    Ok(())
+                })
+            }
+        };
+
+        // Check the GZip layer before the payload, since its verdict would be meaningless
+        if let Some(err) = decoder.error {
+            let reason = FailureType::InvalidContent(err);
+            return report(display, Outcome::Failed { filetype: description, handler: "gzip",
+                                                     reason, output: None });
+        } else if let Err(err) = result {
+            let msg = format!("Could not extract GZip payload: {}", err);
+            return report(display, Outcome::Unreadable(msg));
+        }
+        debug!("Decompressed {} bytes from {} gzip member(s) in {}", decoder.inner.total_out(),
+               decoder.inner.members(), display.display());
+        let confidence = self.builtins.get("gzip").map(|x| x.confidence);
+        report(display, Outcome::Passed { filetype: description, handler: "gzip", confidence });
+
+        if let Some(outcome) = inner_outcome {
+            report(&inner_display, outcome);
+        } else if let Some((_tempdir, temp_path, len)) = spooled {
+            self.process_inner(&temp_path, &inner_display, Some(len), depth, report);
+        }
+    }
+
+    /// Find the first available handler in a filetype's fallback chain, if it supports streams
+    ///
+    /// (Only the first chain is considered for filetypes with more than one candidate
+    /// `container`, since a stream can only be read once.)
+    fn stream_handler(&self, filetype: &'cfg Filetype)
+            -> Option<(&'cfg str, &'cfg Builtin, StreamHandlerFn)> {
+        let chain = *self.handler_chains(filetype).ok()?.first()?;
+        chain.iter().find_map(|id| self.resolve_handler(id).map(|x| (id, x))).and_then(
+            |(id, handler)| match handler {
+                ResolvedHandler::Builtin(builtin) => {
+                    builtin.stream_handler.map(|func| (id.as_str(), builtin, func))
+                },
+                ResolvedHandler::External(_) => None,
+            },
+        )
+    }
+
+    /// Find the fallback chains to try for a filetype: its own `handler` if it has one or, if
+    /// not, the chains found by doing the same for each of its `container` candidates in order
+    /// (with duplicates left out).
+    ///
+    /// Returns a message explaining the problem if no chain could be found.
+    ///
+    /// (This relies on [`config::parse`](crate::config::parse) having already rejected
+    /// `container` cycles.)
+    pub fn handler_chains(&self, filetype: &'cfg Filetype)
+            -> Result<Vec<&'cfg [String]>, String> {
+        if let Some(chain) = filetype.handler.as_deref() {
+            return Ok(vec![chain]);
+        }
+        let containers = filetype.container.as_deref()
+            .ok_or_else(|| "filetype has neither a handler nor a container".to_owned())?;
+
+        let mut chains = Vec::new();
+        for id in containers {
+            let container = self.config.filetypes.get(id)
+                .ok_or_else(|| format!("'container' not found: {}", id))?;
+            for chain in self.handler_chains(container)? {
+                if !chains.contains(&chain) {
+                    chains.push(chain);
+                }
+            }
+        }
+        Ok(chains)
+    }
+
+    /// Try each of the given candidate filetypes in turn until one reaches a verdict that ends
+    /// the format-level fallback chain, returning it along with the filetype that reached it
+    ///
+    /// A pass from any candidate ends the chain, as does any failure other than
+    /// [`InvalidContent`](FailureType::InvalidContent), which only means the file isn't a valid
+    /// instance of *that* format. If no candidate passes, the first failure is reported.
+    fn run_filetypes(&self, candidates: &[&'cfg str], path: &Path)
+            -> (&'cfg Filetype, Outcome<'cfg>) {
+        let filetypes = &self.config.filetypes;
+        let first = &filetypes[candidates[0]];
+        if candidates.len() == 1 {
+            return (first, self.run_filetype(first, path));
+        }
+
+        let mut failure = None;
+        let mut reasons = Vec::new();
+        for id in candidates {
+            let filetype = &filetypes[*id];
+            match self.run_filetype(filetype, path) {
+                Outcome::Unverified { reasons: these, .. } => reasons.extend(
+                    these.into_iter().map(|x| format!("{}: {}", filetype.description, x))),
+                outcome @ Outcome::Failed { reason: FailureType::InvalidContent(_), .. } => {
+                    failure.get_or_insert((filetype, outcome));
+                },
+                outcome => return (filetype, outcome),
+            }
+        }
+        failure.unwrap_or((first, Outcome::Unverified { filetype: &first.description, reasons }))
+    }
+
+    /// Run the handler chain for an already-detected filetype on the given path
+    ///
+    /// If the filetype has no `handler` of its own, its `container` chain is followed until one
+    /// is found, but results are still reported under the most specific `description`.
+    ///
+    /// If that leads to more than one chain (because of a `container` with more than one
+    /// candidate), they're tried in turn the same way as [`run_filetypes`](Self::run_filetypes)
+    /// tries candidate filetypes, since failing as one kind of container may only mean the file
+    /// is the other kind.
+    fn run_filetype(&self, filetype: &'cfg Filetype, path: &Path) -> Outcome<'cfg> {
+        let description = filetype.description.as_str();
+        let args = &filetype.handler_args;
+        let chains = match self.handler_chains(filetype) {
+            Ok(chains) => chains,
+            Err(reason) => {
+                return Outcome::Unverified { filetype: description, reasons: vec![reason] }
+            },
+        };
+        if let [chain] = chains[..] {
+            return self.run_chain(description, chain, args, path);
+        }
+
+        let mut failure = None;
+        let mut reasons = Vec::new();
+        for chain in chains {
+            match self.run_chain(description, chain, args, path) {
+                Outcome::Unverified { reasons: these, .. } => reasons.extend(these),
+                outcome @ Outcome::Failed { reason: FailureType::InvalidContent(_), .. } => {
+                    failure.get_or_insert(outcome);
+                },
+                outcome => return outcome,
+            }
+        }
+        failure.unwrap_or(Outcome::Unverified { filetype: description, reasons })
+    }
+
+    /// Walk a fallback chain of handler IDs, following the rules documented on [`FailureType`]
+    ///
+    /// * Handlers which aren't available (including ones with `platforms` which don't include
+    ///   this one) are skipped.
+    /// * [`UnsupportedFormat`](FailureType::UnsupportedFormat) and
+    ///   [`HandlerUnavailable`](FailureType::HandlerUnavailable) move on to the next handler.
+    /// * Success or any other failure ends the chain and is reported as the outcome.
+    /// * If the chain runs out without a verdict, the file is reported as
+    ///   [`Unverified`](Outcome::Unverified) rather than corrupt.
+    ///
+    /// `args` is the `handler_args` of the filetype the chain is being run for, if any.
+    pub fn run_chain(&self, description: &'cfg str, chain: &'cfg [String], args: &HandlerArgs,
+                     path: &Path) -> Outcome<'cfg> {
+        let mut reasons = Vec::new();
+        for handler_id in chain {
+            let resolved = self.resolve_handler(handler_id);
+            let confidence = match resolved {
+                Some(ResolvedHandler::Builtin(builtin)) => Some(builtin.confidence),
+                Some(ResolvedHandler::External(handler)) => handler.confidence,
+                None => None,
+            };
+            let (result, output) = match resolved {
+                Some(ResolvedHandler::Builtin(builtin)) => ((builtin.handler)(path, args), None),
+                Some(ResolvedHandler::External(handler)) if !self.runs_here(handler) => {
+                    reasons.push(format!("{}: not used on this platform", handler_id));
+                    continue;
+                },
+                Some(ResolvedHandler::External(handler)) => {
+                    let _permits = self.throttle.acquire(handler_id);
+                    let (result, output) = subprocess::run(handler, path, args, &self.options);
+                    if let Some(output) = output.as_ref().filter(|x| !x.is_empty()) {
+                        debug!("Output from {} for {}:\n{}", handler_id, path.display(), output);
+                    }
+                    (result.map(|()| None), output)
+                },
+                None => {
+                    reasons.push(format!("{}: unrecognized handler", handler_id));
+                    continue;
+                },
+            };
+
+            match result {
+                Ok(weaker) => {
+                    return Outcome::Passed { filetype: description, handler: handler_id,
+                                             confidence: weaker.or(confidence) }
+                },
+                Err(FailureType::UnsupportedFormat(msg))
+                | Err(FailureType::HandlerUnavailable(msg)) => {
+                    reasons.push(format!("{}: {}", handler_id, msg));
+                },
+                Err(reason) => {
+                    return Outcome::Failed { filetype: description, handler: handler_id, reason,
+                                             output }
+                },
+            }
+        }
+        Outcome::Unverified { filetype: description, reasons }
+    }
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtin_handlers::HandlerResult;
+
+    /// Fake handler which always succeeds
+    fn always_ok(_path: &Path, _args: &HandlerArgs) -> HandlerResult {
+        Ok(None)
+    }
+
+    /// Fake handler which always passes, but at a lower confidence than it's registered with
+    fn always_weak(_path: &Path, _args: &HandlerArgs) -> HandlerResult {
+        Ok(Some(Confidence::WellFormed))
+    }
+
+    /// Fake handler which always reports corruption
+    fn always_invalid(_path: &Path, _args: &HandlerArgs) -> HandlerResult {
+        Err(FailureType::InvalidContent("fake corruption".to_owned()))
+    }
+
+    /// Fake handler which always reports an unsupported format variant
+    fn always_unsupported(_path: &Path, _args: &HandlerArgs) -> HandlerResult {
+        Err(FailureType::UnsupportedFormat("fake unsupported".to_owned()))
+    }
+
+    /// Fake handler which always reports a failure to read the file
+    fn always_io_error(_path: &Path, _args: &HandlerArgs) -> HandlerResult {
+        Err(FailureType::IoError("fake I/O error".to_owned()))
+    }
+
+    /// Fake handler which always reports an internal error
+    fn always_internal(_path: &Path, _args: &HandlerArgs) -> HandlerResult {
+        Err(FailureType::InternalError("fake internal error".to_owned()))
+    }
+
+    /// Fake stream handler which passes if the stream contains `good`
+    fn stream_if_good(reader: &mut dyn Read, _args: &HandlerArgs) -> HandlerResult {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).map_err(|e| FailureType::IoError(e.to_string()))?;
+        if data == b"good" {
+            Ok(None)
+        } else {
+            Err(FailureType::InvalidContent("not good".to_owned()))
+        }
+    }
+
+    /// Fake handler which passes if the file contains `good`
+    fn path_if_good(path: &Path, _args: &HandlerArgs) -> HandlerResult {
+        let mut file = File::open(path).map_err(|e| FailureType::IoError(e.to_string()))?;
+        stream_if_good(&mut file, &NO_HANDLER_ARGS)
+    }
+
+    /// Fake handler which passes if the filetype sets `handler_args.verdict = "pass"`
+    fn if_arg_set(_path: &Path, args: &HandlerArgs) -> HandlerResult {
+        match args.get("verdict").map(String::as_str) {
+            Some("pass") => Ok(None),
+            _ => Err(FailureType::InvalidContent("verdict not set".to_owned())),
+        }
+    }
+
+    /// Build a registry containing only the fake handlers
+    fn fake_builtins() -> BuiltinRegistry {
+        let fake = |description, handler| {
+            Builtin { description, handler, stream_handler: None, args: &[],
+                      confidence: Confidence::WellFormed }
+        };
+        let mut m = BuiltinRegistry::new();
+        m.insert("ok", fake("Always passes", always_ok));
+        m.insert("invalid", fake("Always fails", always_invalid));
+        m.insert("unsupported", fake("Always unsupported", always_unsupported));
+        m.insert("io_error", fake("Always unreadable", always_io_error));
+        m.insert("internal", fake("Always breaks", always_internal));
+        m.insert("if_good", fake("Passes if the file contains 'good'", path_if_good));
+        m.insert("stream_if_good", Builtin {
+            description: "Passes if the stream contains 'good'",
+            handler: path_if_good,
+            stream_handler: Some(stream_if_good),
+            args: &[],
+            confidence: Confidence::DataHash,
+        });
+        m.insert("weak", Builtin { description: "Always passes weakly", handler: always_weak,
+            stream_handler: None, args: &[], confidence: Confidence::DataHash });
+        m.insert("gzip", Builtin { description: "The real GZip handler",
+            handler: crate::builtin_handlers::gzip, stream_handler: None, args: &[],
+            confidence: Confidence::DataHash });
+        m.insert("zip", fake("The real Zip handler", crate::builtin_handlers::zip));
+        m.insert("if_arg", Builtin {
+            description: "Passes if handler_args.verdict is 'pass'",
+            handler: if_arg_set,
+            stream_handler: None,
+            args: &["verdict"],
+            confidence: Confidence::WellFormed,
+        });
+        m
+    }
+
+    /// Write a Zip file containing the given members
+    fn write_zip(path: &Path, members: &[(&str, &[u8])]) {
+        use std::io::Write;
+        let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+        for (name, data) in members {
+            zip.start_file(*name, zip::write::FileOptions::default()).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    /// Run [`Dispatcher::process`] and collect the displayed paths and outcomes
+    fn collect<'cfg>(dispatcher: &Dispatcher<'cfg>, path: &Path)
+            -> Vec<(std::path::PathBuf, Outcome<'cfg>)> {
+        let mut results = Vec::new();
+        dispatcher.process(path, path, None, &mut |path, outcome| {
+            results.push((path.to_owned(), outcome));
+        });
+        results
+    }
+
+    /// Parse a configuration for use with the fake handlers
+    fn parse(toml_str: &str) -> Root {
+        crate::config::parse(toml_str, &|x| fake_builtins().contains_key(x)).unwrap()
+    }
+
+    /// Filetypes disabled by a user's configuration should be reported as unrecognized again
+    #[test]
+    fn test_disabled_filetypes() {
+        let base = r#"
+            [filetype.zip]
+            description = "Zip archive"
+            extension = "zip"
+            handler = "ok"
+        "#;
+        let is_builtin = |x: &str| fake_builtins().contains_key(x);
+        let config = crate::config::parse_overlay(base, "[filetype.zip]\ndisabled = true\n", None,
+                                                  &is_builtin, true).unwrap();
+        let builtins = fake_builtins();
+        let dispatcher = Dispatcher::new(&config, &builtins, Options::default());
+        assert_eq!(dispatcher.detect(Path::new("foo.zip"), b"PK\x03\x04"), None);
+    }
+
+    #[test]
+    fn test_extension_dispatch() {
+        let config = parse(
+            r#"
+            [filetype.good]
+            description = "Good"
+            extension = ["good", "fine"]
+            handler = "ok"
+
+            [filetype.bad]
+            description = "Bad"
+            extension = "bad"
+            handler = "invalid"
+        "#,
+        );
+        let builtins = fake_builtins();
+        let dispatcher = Dispatcher::new(&config, &builtins, Options::default());
+
+        assert_eq!(dispatcher.detect(Path::new("foo.good"), b""), Some("good"));
+        assert_eq!(dispatcher.detect(Path::new("dir/foo.fine"), b""), Some("good"));
+        assert_eq!(dispatcher.detect(Path::new("foo.bad"), b""), Some("bad"));
+        assert_eq!(dispatcher.detect(Path::new("foo.other"), b""), None);
+        assert_eq!(dispatcher.detect(Path::new("good"), b""), None);
+
+        let good = &config.filetypes["good"];
+        assert!(matches!(dispatcher.run_filetype(good, Path::new("x")), Outcome::Passed { .. }));
+        let bad = &config.filetypes["bad"];
+        assert!(matches!(
+            dispatcher.run_filetype(bad, Path::new("x")),
+            Outcome::Failed { reason: FailureType::InvalidContent(_), .. }
+        ));
+    }
+
+    #[test]
+    fn test_header_dispatch() {
+        let config = parse(
+            r#"
+            [filetype.png]
+            description = "PNG"
+            extension = "png"
+            handler = "ok"
+            header = [137, 80, 78, 71]
+
+            [filetype.jpeg]
+            description = "JPEG"
+            extension = "jpg"
+            handler = "ok"
+            header = [255, 216, 255]
+
+            [filetype.tar]
+            description = "Tar"
+            extension = "tar"
+            handler = "ok"
+            header = [[117, 115, 116, 97, 114, 0], [117, 115, 116, 97, 114, 32]]
+            header_offset = 4
+
+            [filetype.wave]
+            description = "WAVE"
+            extension = "wav"
+            handler = "ok"
+            header = "52494646 ???????? 57415645"
+        "#,
+        );
+        let builtins = fake_builtins();
+        let dispatcher = Dispatcher::new(&config, &builtins, Options::default());
+        assert_eq!(dispatcher.prefix_len, 12);
+
+        // Extension and header agree
+        assert_eq!(dispatcher.detect(Path::new("a.png"), b"\x89PNG\r\n"), Some("png"));
+        // Header overrules a wrong or missing extension
+        assert_eq!(dispatcher.detect(Path::new("a.png"), b"\xff\xd8\xff\xe0"), Some("jpeg"));
+        assert_eq!(dispatcher.detect(Path::new("a"), b"\xff\xd8\xff\xe0"), Some("jpeg"));
+        // Extension is used if nothing else matches, so the handler can report corruption
+        assert_eq!(dispatcher.detect(Path::new("a.png"), b"\x00\x00"), Some("png"));
+
+        // Alternative headers at an offset
+        assert_eq!(dispatcher.match_header(b"\0\0\0\0ustar\0"), vec!["tar"]);
+        assert_eq!(dispatcher.match_header(b"\0\0\0\0ustar "), vec!["tar"]);
+
+        // Wildcard bytes match anything, but only within the pattern's length
+        assert_eq!(dispatcher.match_header(b"RIFF\x24\x08\0\0WAVEfmt "), vec!["wave"]);
+        assert!(dispatcher.match_header(b"RIFF\x24\x08\0\0AVI LIST").is_empty());
+        assert!(dispatcher.match_header(b"RIFF\x24\x08\0\0WAV").is_empty());
+
+        // Files shorter than the header or its offset must not match (or panic)
+        assert!(dispatcher.match_header(b"\0\0\0\0usta").is_empty());
+        assert!(dispatcher.match_header(b"\0\0").is_empty());
+        assert!(dispatcher.match_header(b"").is_empty());
+    }
+
+    /// Filetypes with no magic number (like raw Brotli) should be recognized by extension alone,
+    /// whatever their contents look like, and never by sniffing
+    #[test]
+    fn test_extension_only_dispatch() {
+        let config = parse(
+            r#"
+            [filetype.brotli]
+            description = "Brotli compressed"
+            extension = "br"
+            handler = "ok"
+
+            [filetype.gzip]
+            description = "GZip compressed"
+            extension = "gz"
+            handler = "ok"
+            header = [31, 139]
+        "#,
+        );
+        let builtins = fake_builtins();
+        let dispatcher = Dispatcher::new(&config, &builtins, Options::default());
+
+        for prefix in &[&b"\x1b\x61\x00\x00"[..], b"\x1f\x8b\x08", b""] {
+            assert_eq!(dispatcher.detect_all(Path::new("a.br"), prefix), vec!["brotli"]);
+            assert!(dispatcher.check_mismatch(Path::new("a.br"), prefix).is_none());
+        }
+        assert_eq!(dispatcher.detect(Path::new("a"), b"\x1b\x61\x00\x00"), None);
+        assert_eq!(dispatcher.detect(Path::new("a.gz"), b"\x1b\x61\x00\x00"), Some("gzip"));
+    }
+
+    /// Filetypes sharing an extension should be tried in `priority` order, as a fallback chain
+    #[test]
+    fn test_ambiguous_filetypes() {
+        let config = parse(
+            r#"
+            [filetype.inno]
+            description = "Inno Setup Installer"
+            extension = "exe"
+            handler = "if_good"
+            header = [77, 90]
+
+            [filetype.nsis]
+            description = "NSIS Installer"
+            extension = "exe"
+            handler = "ok"
+            header = [77, 90]
+            priority = 10
+            allow_ambiguous = true
+
+            [filetype.sfx]
+            description = "Some other self-extractor"
+            extension = "exe"
+            handler = "invalid"
+            header = [83, 70, 88]
+            priority = -5
+
+            [filetype.sfx2]
+            description = "Yet another self-extractor"
+            extension = "exe"
+            handler = "invalid"
+            header = [83, 70, 88]
+            priority = -5
+            allow_ambiguous = true
+        "#,
+        );
+        let builtins = fake_builtins();
+        let dispatcher = Dispatcher::new(&config, &builtins, Options::default());
+
+        // Different headers narrow it down, and priority (then ID) orders what's left
+        let exe = Path::new("setup.exe");
+        assert_eq!(dispatcher.detect_all(exe, b"MZ\x90\x00"), vec!["nsis", "inno"]);
+        assert_eq!(dispatcher.detect_all(exe, b"SFX!"), vec!["sfx", "sfx2"]);
+        assert_eq!(dispatcher.detect_all(exe, b"\x00\x00"),
+                   vec!["nsis", "inno", "sfx", "sfx2"]);
+        assert_eq!(dispatcher.match_header(b"MZ\x90\x00"), vec!["nsis", "inno"]);
+
+        let tempdir = TempDir::new().unwrap();
+        let path = tempdir.path().join("setup.exe");
+        fs::write(&path, b"MZ evil").unwrap();
+        let candidates = dispatcher.detect_all(&path, b"MZ");
+        assert!(matches!(dispatcher.run_filetypes(&candidates, &path).1,
+                         Outcome::Passed { filetype: "NSIS Installer", handler: "ok", .. }));
+
+        // `InvalidContent` moves on to the next filetype, and the first failure is reported
+        let reversed = ["inno", "nsis"];
+        assert!(matches!(dispatcher.run_filetypes(&reversed, &path).1,
+                         Outcome::Passed { filetype: "NSIS Installer", .. }));
+        let sfx = ["sfx", "inno", "sfx2"];
+        assert!(matches!(dispatcher.run_filetypes(&sfx, &path).1,
+                         Outcome::Failed { filetype: "Some other self-extractor", .. }));
+    }
+
+    /// Headers contradicting extensions should be reported, except between related filetypes
+    #[test]
+    fn test_mismatched_extensions() {
+        let config = parse(
+            r#"
+            [filetype.png]
+            description = "PNG"
+            extension = "png"
+            handler = "ok"
+            header = [137, 80, 78, 71]
+
+            [filetype.jpeg]
+            description = "JPEG"
+            extension = "jpg"
+            handler = "ok"
+            header = [255, 216, 255]
+
+            [filetype.zip]
+            description = "Zip"
+            extension = "zip"
+            handler = "ok"
+            header = [80, 75, 3, 4]
+
+            [filetype.cbz]
+            container = "zip"
+            description = "Comic Book Archive"
+            extension = "cbz"
+            header = [80, 75, 3, 4, 20]
+
+            [filetype.jar]
+            container = "zip"
+            description = "Java ARchive"
+            extension = "jar"
+            header = [80, 75, 3, 4, 10]
+
+            [filetype.text]
+            description = "Plain text"
+            extension = "txt"
+            handler = "ok"
+        "#,
+        );
+        let builtins = fake_builtins();
+        let dispatcher = Dispatcher::new(&config, &builtins, Options::default());
+        let jpeg = b"\xff\xd8\xff\xe0";
+        let mismatch = |name, prefix| dispatcher.check_mismatch(Path::new(name), prefix);
+
+        assert!(matches!(mismatch("photo.png", jpeg),
+                         Some(Outcome::Mismatched { by_extension: "PNG", by_header: "JPEG" })));
+        assert!(mismatch("photo.jpg", jpeg).is_none());
+        assert!(mismatch("photo", jpeg).is_none(), "No extension to contradict");
+        assert!(mismatch("photo.txt", jpeg).is_none(), "Filetypes without headers are exempt");
+        assert!(mismatch("photo.png", b"\x00\x00").is_none(), "Nothing to contradict it");
+        assert!(mismatch("comic.cbz", b"PK\x03\x04\x0a").is_none(), "Share a container");
+        assert!(mismatch("comic.cbz", b"PK\x03\x04\x00").is_none(), "Container of it");
+        assert!(matches!(mismatch("comic.cbz", jpeg), Some(Outcome::Mismatched { .. })));
+
+        // The warning comes before the verdict from the header-selected filetype
+        let tempdir = TempDir::new().unwrap();
+        let path = tempdir.path().join("photo.png");
+        fs::write(&path, jpeg).unwrap();
+        let results = collect(&dispatcher, &path);
+        assert!(matches!(results[0].1, Outcome::Mismatched { .. }));
+        assert!(matches!(results[1].1, Outcome::Passed { filetype: "JPEG", .. }));
+
+        let options = Options { warn_mismatched: false, ..Options::default() };
+        let dispatcher = Dispatcher::new(&config, &builtins, options);
+        assert_eq!(collect(&dispatcher, &path).len(), 1);
+    }
+
+    /// Extensionless files and unclaimed extensions should fall back to the most specific header
+    #[test]
+    fn test_header_only_dispatch() {
+        let config = parse(
+            r#"
+            [filetype.zip]
+            description = "Zip"
+            extension = "zip"
+            handler = "ok"
+            header = [80, 75, 3, 4]
+
+            [filetype.apk]
+            description = "Android package"
+            extension = "apk"
+            handler = "ok"
+            header = [80, 75, 3, 4, 20, 0, 8, 0]
+
+            [filetype.text]
+            description = "Plain text"
+            extension = "txt"
+            handler = "ok"
+        "#,
+        );
+        let builtins = fake_builtins();
+        let dispatcher = Dispatcher::new(&config, &builtins, Options::default());
+
+        // Longest header wins, regardless of ID order
+        let apk_prefix = b"PK\x03\x04\x14\x00\x08\x00";
+        assert_eq!(dispatcher.match_header(apk_prefix), vec!["apk", "zip"]);
+        assert_eq!(dispatcher.detect(Path::new("Makefile"), apk_prefix), Some("apk"));
+        assert_eq!(dispatcher.detect(Path::new("blob.bin"), apk_prefix), Some("apk"));
+        assert_eq!(dispatcher.detect(Path::new("blob"), b"PK\x03\x04\x0a\x00"), Some("zip"));
+
+        // ...but a claimed extension with no header still takes precedence over sniffing
+        assert_eq!(dispatcher.detect(Path::new("a.txt"), apk_prefix), Some("text"));
+        assert_eq!(dispatcher.detect(Path::new("blob"), b"hello"), None);
+
+        // `identify` reads the prefix itself
+        let tempdir = TempDir::new().unwrap();
+        let (blob, text) = (tempdir.path().join("blob"), tempdir.path().join("README"));
+        fs::write(&blob, apk_prefix).unwrap();
+        fs::write(&text, b"hello").unwrap();
+        assert_eq!(dispatcher.identify(&blob).unwrap(), Some("apk"));
+        assert_eq!(dispatcher.identify(&text).unwrap(), None);
+        assert!(dispatcher.identify(&tempdir.path().join("missing")).is_err());
+    }
+
+    /// `[override.<glob>]` globs should force a handler chain, with later overrides winning, and
+    /// the deprecated `[[override]]` form should behave the same
+    #[test]
+    fn test_handler_overrides() {
+        let filetypes = r#"
+            [filetype.text]
+            description = "Plain text"
+            extension = ["txt", "bak"]
+            handler = "invalid"
+        "#;
+        let current = r#"
+            ignore = ["*.partial"]
+
+            [override."*.bak"]
+            handler = "ok"
+
+            [override."special/*.bak"]
+            handler = ["unsupported", "io_error"]
+        "#;
+        let legacy = r#"
+            [[override]]
+            path = "*.bak"
+            handler = "ok"
+
+            [[override]]
+            path = "special/*.bak"
+            handler = ["unsupported", "io_error"]
+
+            [[override]]
+            path = "*.partial"
+            ignore = true
+        "#;
+        for overrides in &[current, legacy] {
+            let config = parse(&format!("{}{}", overrides, filetypes));
+            let builtins = fake_builtins();
+            let dispatcher = Dispatcher::new(&config, &builtins, Options::default());
+
+            let glob = |path| dispatcher.match_override(Path::new(path)).map(|(x, _)| x);
+            assert_eq!(glob("a.bak"), Some("*.bak"));
+            assert_eq!(glob("nested/dir/a.bak"), Some("*.bak"));
+            assert_eq!(glob("special/a.bak"), Some("special/*.bak"));
+            assert_eq!(glob("a.txt"), None);
+            assert_eq!(glob("a.partial"), None, "ignore globs are the walker's job");
+            assert_eq!(config.ignore, vec!["*.partial"]);
+
+            let tempdir = TempDir::new().unwrap();
+            let path = tempdir.path().join("a.bak");
+            fs::write(&path, b"x").unwrap();
+            let run = |rel_path: &str| {
+                let mut results = Vec::new();
+                dispatcher.process(&path, Path::new(rel_path), None,
+                                   &mut |_, outcome| results.push(outcome));
+                results.pop().unwrap()
+            };
+
+            // Overrides take precedence over the extension and are matched on the relative path
+            assert!(matches!(run("a.bak"),
+                             Outcome::Passed { filetype: "*.bak", handler: "ok", .. }));
+            assert!(matches!(run("special/a.bak"), Outcome::Failed {
+                filetype: "special/*.bak", handler: "io_error", ..
+            }));
+            assert!(matches!(run("a.txt"), Outcome::Failed { filetype: "Plain text", .. }));
+        }
+    }
+
+    /// Overrides which force a `filetype` should get everything its definition specifies
+    #[test]
+    fn test_filetype_overrides() {
+        let config = parse(
+            r#"
+            [filetype.zip]
+            description = "Zip archive"
+            extension = "zip"
+            handler = "zip"
+
+            [filetype.cbz]
+            container = "zip"
+            description = "Comic Book Archive (Zip)"
+            extension = "cbz"
+            recurse = true
+
+            [filetype.good]
+            description = "Good"
+            extension = "good"
+            handler = "ok"
+
+            [override."*.dat"]
+            filetype = "cbz"
+        "#,
+        );
+        let builtins = fake_builtins();
+        let dispatcher = Dispatcher::new(&config, &builtins, Options::default());
+        let tempdir = TempDir::new().unwrap();
+
+        let comic = tempdir.path().join("comic.dat");
+        write_zip(&comic, &[("a.good", b"x")]);
+        let results = collect(&dispatcher, &comic);
+        assert_eq!(results.len(), 2, "Should recurse like the forced filetype does");
+        assert!(matches!(results[0].1, Outcome::Passed {
+            filetype: "Comic Book Archive (Zip)", handler: "zip", ..
+        }));
+        assert!(matches!(results[1].1, Outcome::Passed { filetype: "Good", .. }));
+
+        let empty = tempdir.path().join("empty.dat");
+        fs::write(&empty, b"").unwrap();
+        assert!(matches!(collect(&dispatcher, &empty)[0].1,
+                         Outcome::Empty { filetype: "Comic Book Archive (Zip)", valid: false }));
+    }
+
+    #[test]
+    fn test_external_overrides_builtin() {
+        let config = parse(
+            r#"
+            [handler.ok]
+            argv = ["true"]
+        "#,
+        );
+        let builtins = fake_builtins();
+        let dispatcher = Dispatcher::new(&config, &builtins, Options::default());
+        assert!(matches!(dispatcher.resolve_handler("ok"), Some(ResolvedHandler::External(_))));
+        assert!(matches!(dispatcher.resolve_handler("invalid"), Some(ResolvedHandler::Builtin(_))));
+        assert!(dispatcher.resolve_handler("missing").is_none());
+    }
+
+    /// Run every fake handler at every position in a three-element fallback chain where the
+    /// other positions are filled by handlers that would produce a distinct result if reached
+    #[test]
+    fn test_fallback_chain() {
+        let config = parse("");
+        let builtins = fake_builtins();
+        let dispatcher = Dispatcher::new(&config, &builtins, Options::default());
+
+        for position in 0..3 {
+            for tested in &["ok", "invalid", "unsupported", "io_error", "internal"] {
+                // Earlier positions must be skipped over and later ones must never be reached
+                let mut chain = vec!["unsupported".to_owned(); 3];
+                chain[position] = (*tested).to_owned();
+                if position < 2 {
+                    chain[position + 1] = "internal".to_owned();
+                }
+
+                let outcome =
+                    dispatcher.run_chain("Test", &chain, &NO_HANDLER_ARGS, Path::new("x"));
+                let ctx = format!("{} at position {}", tested, position);
+                match (*tested, outcome) {
+                    ("ok", Outcome::Passed { handler, .. }) => assert_eq!(handler, "ok", "{}", ctx),
+                    ("invalid", Outcome::Failed { reason: FailureType::InvalidContent(_), .. })
+                    | ("io_error", Outcome::Failed { reason: FailureType::IoError(_), .. }) => {},
+                    ("unsupported", Outcome::Failed { handler, .. }) if position < 2 => {
+                        assert_eq!(handler, "internal", "{}", ctx);
+                    },
+                    ("unsupported", Outcome::Unverified { reasons, .. }) if position == 2 => {
+                        assert_eq!(reasons.len(), 3, "{}", ctx);
+                    },
+                    ("internal", Outcome::Failed { handler, reason, .. }) => {
+                        assert!(matches!(reason, FailureType::InternalError(_)), "{}", ctx);
+                        assert_eq!(chain.iter().position(|x| x == handler), Some(position));
+                    },
+                    _ => panic!("Unexpected outcome for {}", ctx),
+                }
+            }
+        }
+    }
+
+    /// Unavailable handlers should be skipped like unsupported ones
+    #[test]
+    fn test_fallback_chain_skips_unavailable() {
+        let config = parse(
+            r#"
+            [handler.external]
+            argv = ["verify_files_no_such_command"]
+        "#,
+        );
+        let builtins = fake_builtins();
+        let dispatcher = Dispatcher::new(&config, &builtins, Options::default());
+        let chain = vec!["missing".to_owned(), "external".to_owned(), "ok".to_owned()];
+        assert!(matches!(
+            dispatcher.run_chain("Test", &chain, &NO_HANDLER_ARGS, Path::new("x")),
+            Outcome::Passed { handler: "ok", .. }
+        ));
+        let chain = vec!["missing".to_owned(), "unsupported".to_owned()];
+        match dispatcher.run_chain("Test", &chain, &NO_HANDLER_ARGS, Path::new("x")) {
+            Outcome::Unverified { reasons, .. } => assert_eq!(reasons.len(), 2),
+            _ => panic!("Chain with no usable handlers should be Unverified"),
+        }
+    }
+
+    /// Handlers for other platforms should be skipped without trying to run them
+    #[test]
+    fn test_fallback_chain_skips_other_platforms() {
+        let config = parse(
+            r#"
+            [handler.external]
+            argv = ["verify_files_no_such_command"]
+            platforms = ["linux", "macos"]
+        "#,
+        );
+        let builtins = fake_builtins();
+        let chain = vec!["external".to_owned(), "ok".to_owned()];
+        for &(platform, skipped) in
+            &[(Some(Platform::Windows), true), (None, true), (Some(Platform::Linux), false)]
+        {
+            let options = Options { platform, ..Options::default() };
+            let dispatcher = Dispatcher::new(&config, &builtins, options);
+            assert!(matches!(
+                dispatcher.run_chain("Test", &chain, &NO_HANDLER_ARGS, Path::new("x")),
+                Outcome::Passed { handler: "ok", .. }
+            ));
+            match dispatcher.run_chain("Test", &chain[..1], &NO_HANDLER_ARGS, Path::new("x")) {
+                Outcome::Unverified { reasons, .. } => assert_eq!(
+                    reasons == ["external: not used on this platform"], skipped, "{:?}", reasons),
+                _ => panic!("Chain with no usable handlers should be Unverified"),
+            }
+        }
+    }
+
+    /// A builtin which reports a weaker pass should override its registered confidence
+    #[test]
+    fn test_weaker_pass() {
+        let config = parse("");
+        let builtins = fake_builtins();
+        let dispatcher = Dispatcher::new(&config, &builtins, Options::default());
+        let chain = vec!["weak".to_owned()];
+        assert!(matches!(
+            dispatcher.run_chain("Test", &chain, &NO_HANDLER_ARGS, Path::new("x")),
+            Outcome::Passed { handler: "weak", confidence: Some(Confidence::WellFormed), .. }
+        ));
+    }
+
+    /// External handlers' exit statuses should be reported like builtin results
+    #[cfg(unix)]
+    #[test]
+    fn test_external_handlers() {
+        let config = parse(
+            r#"
+            [handler.pass]
+            argv = ["true"]
+            confidence = "full-hash"
+
+            [handler.unrated]
+            argv = ["true"]
+
+            [handler.fail]
+            argv = ["false"]
+        "#,
+        );
+        let builtins = fake_builtins();
+        let dispatcher = Dispatcher::new(&config, &builtins, Options::default());
+        let chain = vec!["pass".to_owned()];
+        assert!(matches!(
+            dispatcher.run_chain("Test", &chain, &NO_HANDLER_ARGS, Path::new("x")),
+            Outcome::Passed { handler: "pass", confidence: Some(Confidence::FullHash), .. }
+        ));
+        let chain = vec!["unrated".to_owned()];
+        assert!(matches!(
+            dispatcher.run_chain("Test", &chain, &NO_HANDLER_ARGS, Path::new("x")),
+            Outcome::Passed { handler: "unrated", confidence: None, .. }
+        ));
+        let chain = vec!["ok".to_owned()];
+        assert!(matches!(
+            dispatcher.run_chain("Test", &chain, &NO_HANDLER_ARGS, Path::new("x")),
+            Outcome::Passed { handler: "ok", confidence: Some(Confidence::WellFormed), .. }
+        ));
+        let chain = vec!["fail".to_owned(), "ok".to_owned()];
+        assert!(matches!(
+            dispatcher.run_chain("Test", &chain, &NO_HANDLER_ARGS, Path::new("x")),
+            Outcome::Failed { handler: "fail", .. }
+        ));
+    }
+
+    /// Filetypes' `handler_args` should reach built-ins and `{arg:key}` tokens
+    #[test]
+    fn test_handler_args() {
+        let config = parse(
+            r#"
+            [filetype.set]
+            description = "Set"
+            extension = "set"
+            handler = "if_arg"
+            handler_args = { verdict = "pass" }
+
+            [filetype.unset]
+            description = "Unset"
+            extension = "unset"
+            handler = "if_arg"
+
+            [filetype.external]
+            description = "External"
+            extension = "ext"
+            handler = "check"
+            handler_args = { expected = "yes" }
+
+            [handler.check]
+            argv = ["sh", "-c", "test \"$0\" = yes", "{arg:expected}"]
+        "#,
+        );
+        let builtins = fake_builtins();
+        let dispatcher = Dispatcher::new(&config, &builtins, Options::default());
+        let run = |id: &'static str| {
+            let filetype = &config.filetypes[id];
+            dispatcher.run_filetype(filetype, Path::new("x"))
+        };
+        assert!(matches!(run("set"), Outcome::Passed { handler: "if_arg", .. }));
+        assert!(matches!(run("unset"), Outcome::Failed { handler: "if_arg", .. }));
+        #[cfg(unix)]
+        {
+            assert!(matches!(run("external"), Outcome::Passed { handler: "check", .. }));
+            let chain = vec!["check".to_owned()];
+            assert!(matches!(
+                dispatcher.run_chain("Test", &chain, &NO_HANDLER_ARGS, Path::new("x")),
+                Outcome::Failed { handler: "check", .. }
+            ));
+        }
+    }
+
+    /// Filetypes with no handler of their own should borrow their container's
+    #[test]
+    fn test_container_dispatch() {
+        let config = parse(
+            r#"
+            [filetype.zip]
+            description = "Zip archive"
+            extension = "zip"
+            handler = "ok"
+
+            [filetype.cbz]
+            container = "zip"
+            description = "Comic Book Archive (Zip)"
+            extension = "cbz"
+
+            [filetype.special_cbz]
+            container = "cbz"
+            description = "Special Comic Book Archive"
+            extension = "scbz"
+
+            [filetype.epub]
+            container = "zip"
+            description = "ePub e-book"
+            extension = "epub"
+            handler = "invalid"
+        "#,
+        );
+        let builtins = fake_builtins();
+        let dispatcher = Dispatcher::new(&config, &builtins, Options::default());
+
+        for (id, description) in &[("cbz", "Comic Book Archive (Zip)"),
+                                   ("special_cbz", "Special Comic Book Archive")] {
+            match dispatcher.run_filetype(&config.filetypes[*id], Path::new("x")) {
+                Outcome::Passed { filetype, handler, .. } => {
+                    assert_eq!(filetype, *description);
+                    assert_eq!(handler, "ok");
+                },
+                _ => panic!("{} should have used its container's handler", id),
+            }
+        }
+
+        // A filetype's own handler must win over its container's
+        assert!(matches!(
+            dispatcher.run_filetype(&config.filetypes["epub"], Path::new("x")),
+            Outcome::Failed { filetype: "ePub e-book", handler: "invalid", .. }
+        ));
+    }
+
+    /// Filetypes with several candidate containers should try each until one isn't a definite
+    /// "this isn't my format"
+    #[test]
+    fn test_container_candidates() {
+        let config = parse(
+            r#"
+            [filetype.tiff]
+            description = "TIFF image"
+            extension = "tif"
+            handler = "invalid"
+
+            [filetype.bigtiff]
+            description = "BigTIFF image"
+            extension = "btf"
+            handler = "ok"
+
+            [filetype.ole2]
+            description = "OLE2 compound file"
+            extension = "ole"
+            handler = "unsupported"
+
+            [filetype.dng]
+            container = ["tiff", "bigtiff"]
+            description = "Digital Negative"
+            extension = "dng"
+
+            [filetype.corrupt]
+            container = ["tiff", "ole2"]
+            description = "Either way, corrupt"
+            extension = "xyz"
+        "#,
+        );
+        let builtins = fake_builtins();
+        let dispatcher = Dispatcher::new(&config, &builtins, Options::default());
+        let run = |id: &str| dispatcher.run_filetype(&config.filetypes[id], Path::new("x"));
+
+        assert!(matches!(run("dng"),
+                         Outcome::Passed { filetype: "Digital Negative", handler: "ok", .. }));
+        assert!(matches!(run("corrupt"), Outcome::Failed { handler: "invalid", .. }),
+                "A definite failure should beat a candidate which couldn't decide");
+        assert!(dispatcher.are_related("dng", "bigtiff"));
+        assert!(!dispatcher.are_related("dng", "ole2"));
+    }
+
+    /// A missing container should be reported rather than panicking, even if validation was skipped
+    #[test]
+    fn test_container_dispatch_missing() {
+        let config: Root = toml_edit::de::from_str(
+            r#"
+            [filetype.cbz]
+            container = "zip"
+            description = "Comic Book Archive (Zip)"
+            extension = "cbz"
+        "#,
+        )
+        .unwrap();
+        let builtins = fake_builtins();
+        let dispatcher = Dispatcher::new(&config, &builtins, Options::default());
+        match dispatcher.run_filetype(&config.filetypes["cbz"], Path::new("x")) {
+            Outcome::Unverified { reasons, .. } => assert!(reasons[0].contains("zip")),
+            _ => panic!("A missing container should produce an Unverified outcome"),
+        }
+    }
+
+    /// Zip members should be validated with their own handlers when recursion is requested
+    #[test]
+    fn test_zip_recursion() {
+        let toml_str = r#"
+            [filetype.zip]
+            description = "Zip archive"
+            extension = "zip"
+            handler = "zip"
+
+            [filetype.cbz]
+            container = "zip"
+            description = "Comic Book Archive (Zip)"
+            extension = "cbz"
+            recurse = true
+
+            [filetype.good]
+            description = "Good"
+            extension = "good"
+            handler = "ok"
+
+            [filetype.bad]
+            description = "Bad"
+            extension = "bad"
+            handler = "invalid"
+        "#;
+        let config = parse(toml_str);
+        let builtins = fake_builtins();
+        let tempdir = TempDir::new().unwrap();
+
+        let inner = tempdir.path().join("inner.zip");
+        write_zip(&inner, &[("a.good", b"x")]);
+        let nested = fs::read(&inner).unwrap();
+        let outer = tempdir.path().join("outer.zip");
+        write_zip(&outer, &[("dir/a.good", b"x"), ("b.bad", b"x"), ("inner.zip", &nested)]);
+        let comic = tempdir.path().join("comic.cbz");
+        fs::copy(&outer, &comic).unwrap();
+
+        // No recursion unless requested
+        let dispatcher = Dispatcher::new(&config, &builtins, Options::default());
+        assert_eq!(collect(&dispatcher, &outer).len(), 1);
+
+        // ...but the `recurse` key should be inherited through `container` (and not leak into
+        // nested archives which don't set it themselves)
+        assert_eq!(collect(&dispatcher, &comic).len(), 4);
+
+        let options = Options { recurse: true, ..Options::default() };
+        let dispatcher = Dispatcher::new(&config, &builtins, options);
+        let results = collect(&dispatcher, &outer);
+        let prefix = outer.to_str().unwrap();
+        let displayed: Vec<_> = results.iter().map(|(path, _)| path.to_str().unwrap()).collect();
+        assert_eq!(displayed, vec![
+            prefix.to_owned(),
+            format!("{}!dir/a.good", prefix),
+            format!("{}!b.bad", prefix),
+            format!("{}!inner.zip", prefix),
+            format!("{}!inner.zip!a.good", prefix),
+        ]);
+        assert!(matches!(results[1].1, Outcome::Passed { filetype: "Good", .. }));
+        assert!(matches!(results[2].1, Outcome::Failed { filetype: "Bad", .. }));
+        assert!(matches!(results[4].1, Outcome::Passed { filetype: "Good", .. }));
+
+        // Nesting should be bounded
+        let options = Options { recurse: true, max_depth: 1, ..Options::default() };
+        let dispatcher = Dispatcher::new(&config, &builtins, options);
+        assert_eq!(collect(&dispatcher, &outer).len(), 4);
+    }
+
+    /// The longest matching multi-part extension should win
+    #[test]
+    fn test_composite_extensions() {
+        let config = parse(
+            r#"
+            [filetype.gzip]
+            description = "GZip compressed"
+            extension = "gz"
+            handler = "ok"
+
+            [filetype.targz]
+            description = "Tar archive (GZip compressed)"
+            extension = ["tar.gz", "tgz"]
+            handler = "ok"
+
+            [filetype.sourcemap]
+            description = "JavaScript source map"
+            extension = "js.map"
+            handler = "ok"
+        "#,
+        );
+        let builtins = fake_builtins();
+        let dispatcher = Dispatcher::new(&config, &builtins, Options::default());
+        for (path, expected) in &[("foo.tar.gz", Some("targz")),
+                                  ("dir.d/foo.tar.gz", Some("targz")),
+                                  ("foo.bar.tar.gz", Some("targz")),
+                                  ("foo.tgz", Some("targz")),
+                                  ("foo.gz", Some("gzip")),
+                                  ("foo.tar.bz2.gz", Some("gzip")),
+                                  ("user.js.map", Some("sourcemap")),
+                                  ("user.map", None),
+                                  (".gz", None),
+                                  ("tar.gz", Some("gzip")),
+                                  ("foo.tar", None)] {
+            assert_eq!(dispatcher.detect(Path::new(path), b""), *expected, "{}", path);
+        }
+    }
+
+    /// Extensions should be case-insensitive by default, but with an opt-out
+    #[test]
+    fn test_case_insensitive_extensions() {
+        let toml_str = r#"
+            [filetype.jpeg]
+            description = "JPEG"
+            extension = ["jpg", "JPEG"]
+            handler = "ok"
+
+            [filetype.targz]
+            description = "Tar archive (GZip compressed)"
+            extension = "tar.gz"
+            handler = "ok"
+        "#;
+        let config = parse(toml_str);
+        let builtins = fake_builtins();
+        let dispatcher = Dispatcher::new(&config, &builtins, Options::default());
+
+        // Make sure both variants really exist side by side on whatever the test runs on
+        let tempdir = TempDir::new().unwrap();
+        let (lower, upper) = (tempdir.path().join("foo.jpg"), tempdir.path().join("foo.JPG"));
+        fs::write(&lower, b"x").unwrap();
+        fs::write(&upper, b"x").unwrap();
+        for path in &[&lower, &upper] {
+            let results = collect(&dispatcher, path);
+            assert!(matches!(results[0].1, Outcome::Passed { filetype: "JPEG", .. }),
+                    "{}", path.display());
+        }
+        assert_eq!(dispatcher.detect(Path::new("foo.Jpg"), b""), Some("jpeg"));
+        assert_eq!(dispatcher.detect(Path::new("foo.jpeg"), b""), Some("jpeg"));
+        assert_eq!(dispatcher.detect(Path::new("foo.TaR.gZ"), b""), Some("targz"));
+        assert_eq!(dispatcher.strip_extension("foo.JSON.Gz", "gz"), Some("foo.JSON"));
+
+        let config = parse(&format!("case_sensitive_extensions = true\n{}", toml_str));
+        let dispatcher = Dispatcher::new(&config, &builtins, Options::default());
+        assert!(matches!(collect(&dispatcher, &lower)[0].1, Outcome::Passed { .. }));
+        assert!(matches!(collect(&dispatcher, &upper)[0].1, Outcome::Unrecognized));
+        assert_eq!(dispatcher.detect(Path::new("foo.jpeg"), b""), None);
+        assert_eq!(dispatcher.detect(Path::new("foo.JPEG"), b""), Some("jpeg"));
+        assert_eq!(dispatcher.detect(Path::new("foo.TaR.gZ"), b""), None);
+        assert_eq!(dispatcher.strip_extension("foo.JSON.Gz", "gz"), None);
+    }
+
+    /// GZip payloads should be validated by the handler for the name with `.gz` stripped
+    #[test]
+    fn test_gzip_recursion() {
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        let config = parse(
+            r#"
+            [filetype.gzip]
+            description = "GZip compressed"
+            extension = "gz"
+            handler = "gzip"
+
+            [filetype.streamed]
+            description = "Streamed"
+            extension = "streamed"
+            handler = "stream_if_good"
+
+            [filetype.spooled]
+            description = "Spooled"
+            extension = "spooled"
+            handler = "if_good"
+        "#,
+        );
+        let builtins = fake_builtins();
+        let options = Options { recurse: true, ..Options::default() };
+        let dispatcher = Dispatcher::new(&config, &builtins, options);
+        let tempdir = TempDir::new().unwrap();
+
+        let write_gz = |name: &str, data: &[u8]| {
+            let path = tempdir.path().join(name);
+            let mut encoder = GzEncoder::new(File::create(&path).unwrap(), Default::default());
+            encoder.write_all(data).unwrap();
+            encoder.finish().unwrap();
+            path
+        };
+
+        for (name, data, payload_ok) in &[("a.streamed.gz", b"good", true),
+                                          ("b.streamed.gz", b"evil", false),
+                                          ("c.spooled.gz", b"good", true),
+                                          ("d.spooled.gz", b"evil", false)] {
+            let path = write_gz(name, *data);
+            let results = collect(&dispatcher, &path);
+            assert_eq!(results.len(), 2, "{}", name);
+            assert!(matches!(results[0].1, Outcome::Passed { handler: "gzip", .. }), "{}", name);
+
+            let inner_name = name.strip_suffix(".gz").unwrap();
+            assert_eq!(results[1].0, member_display(&path, inner_name));
+            assert_eq!(matches!(results[1].1, Outcome::Passed { .. }), *payload_ok, "{}", name);
+        }
+
+        // Payloads with no recognized extension just get the CRC check
+        let path = write_gz("e.unknown.gz", b"good");
+        let results = collect(&dispatcher, &path);
+        assert_eq!(results.len(), 1);
+        assert!(matches!(results[0].1, Outcome::Passed { handler: "gzip", .. }));
+
+        // Corruption in the GZip layer should be blamed on it, not on the payload
+        let path = write_gz("f.streamed.gz", b"good");
+        let mut data = fs::read(&path).unwrap();
+        let crc_offset = data.len() - 8;
+        data[crc_offset] ^= 0xFF;
+        fs::write(&path, data).unwrap();
+        let results = collect(&dispatcher, &path);
+        assert_eq!(results.len(), 1);
+        assert!(matches!(results[0].1, Outcome::Failed { handler: "gzip", .. }));
+    }
+
+    /// Files over the size limit should be skipped, with a filetype's own `max_size` taking
+    /// precedence over `--max-size`
+    #[test]
+    fn test_max_size() {
+        let config = parse(
+            r#"
+            [filetype.image]
+            description = "Disk image"
+            extension = "img"
+            handler = "ok"
+            max_size = 4
+
+            [filetype.text]
+            description = "Plain text"
+            extension = "txt"
+            handler = "ok"
+
+            [override."*.dat"]
+            filetype = "image"
+
+            [override."*.bin"]
+            handler = "ok"
+        "#,
+        );
+        let builtins = fake_builtins();
+        let tempdir = TempDir::new().unwrap();
+        let (image, text) = (tempdir.path().join("a.img"), tempdir.path().join("a.txt"));
+        fs::write(&image, b"x").unwrap();
+        fs::write(&text, b"x").unwrap();
+
+        let check = |dispatcher: &Dispatcher<'_>, path: &Path, len| {
+            let mut results = Vec::new();
+            dispatcher.process(path, path, Some(len), &mut |_, outcome| results.push(outcome));
+            match results.pop().unwrap() {
+                Outcome::Passed { .. } => "passed",
+                Outcome::TooLarge { filetype: "Disk image" } => "skipped",
+                outcome => panic!("Unexpected outcome: {:?}", outcome),
+            }
+        };
+        let dispatcher = Dispatcher::new(&config, &builtins, Options::default());
+        assert_eq!(check(&dispatcher, &image, 4), "passed", "The limit itself is allowed");
+        assert_eq!(check(&dispatcher, &image, 5), "skipped");
+        assert_eq!(check(&dispatcher, &text, 1 << 40), "passed", "No limit unless configured");
+        assert_eq!(check(&dispatcher, &tempdir.path().join("a.dat"), 5), "skipped",
+                   "Forced filetypes should have their limit applied too");
+
+        let options = Options { max_size: Some(2), ..Options::default() };
+        let dispatcher = Dispatcher::new(&config, &builtins, options);
+        assert_eq!(check(&dispatcher, &image, 4), "passed", "max_size takes precedence");
+        assert_eq!(check(&dispatcher, &text, 2), "passed");
+        assert!(matches!(collect(&dispatcher, &text)[0], (_, Outcome::Passed { .. })),
+                "Unknown lengths aren't checked");
+        let mut results = Vec::new();
+        dispatcher.process(&text, &text, Some(3), &mut |_, outcome| results.push(outcome));
+        assert!(matches!(results[0], Outcome::TooLarge { filetype: "Plain text" }));
+        let bin = tempdir.path().join("a.bin");
+        dispatcher.process(&bin, &bin, Some(3), &mut |_, outcome| results.push(outcome));
+        assert!(matches!(results[1], Outcome::TooLarge { filetype: "*.bin" }),
+                "Overrides which only force a handler should have the limit applied too");
+    }
+
+    /// Zero-byte files should get a uniform verdict without any handler being run
+    #[test]
+    fn test_empty_files() {
+        use flate2::write::GzEncoder;
+
+        let config = parse(
+            r#"
+            [filetype.gzip]
+            description = "GZip compressed"
+            extension = "gz"
+            handler = "gzip"
+
+            [filetype.jpeg]
+            description = "JPEG"
+            extension = "jpg"
+            handler = "internal"
+
+            [filetype.text]
+            description = "Plain text"
+            extension = "txt"
+            handler = "internal"
+            valid_if_empty = true
+        "#,
+        );
+        let builtins = fake_builtins();
+        let options = Options { recurse: true, ..Options::default() };
+        let dispatcher = Dispatcher::new(&config, &builtins, options);
+        let tempdir = TempDir::new().unwrap();
+
+        let (jpeg, text) = (tempdir.path().join("a.jpg"), tempdir.path().join("a.txt"));
+        fs::write(&jpeg, b"").unwrap();
+        fs::write(&text, b"").unwrap();
+        let results = collect(&dispatcher, &jpeg);
+        assert!(matches!(results[0].1, Outcome::Empty { filetype: "JPEG", valid: false }));
+        let results = collect(&dispatcher, &text);
+        assert!(matches!(results[0].1, Outcome::Empty { filetype: "Plain text", valid: true }));
+
+        // A known length from the walker takes precedence over reading the file
+        let mut results = Vec::new();
+        dispatcher.process(&jpeg, &jpeg, Some(10), &mut |_, outcome| results.push(outcome));
+        assert!(matches!(results[0], Outcome::Failed { handler: "internal", .. }));
+
+        // Empty GZip payloads are still reported as such
+        let path = tempdir.path().join("b.jpg.gz");
+        GzEncoder::new(File::create(&path).unwrap(), Default::default()).finish().unwrap();
+        let results = collect(&dispatcher, &path);
+        assert_eq!(results.len(), 2);
+        assert!(matches!(results[0].1, Outcome::Passed { handler: "gzip", .. }));
+        assert!(matches!(results[1].1, Outcome::Empty { filetype: "JPEG", valid: false }));
+    }
+}
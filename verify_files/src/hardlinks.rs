@@ -0,0 +1,220 @@
+//! Tracking of already-verified files so hardlinks to the same content are only checked once
+//!
+//! (Backup trees built with tools like rsnapshot can contain dozens of links to each inode, and
+//! re-verifying each one only repeats the same expensive work.)
+//!
+//! **NOTE:** This is written to be safe to share between walker threads. If a second link is
+//! reached while the first is still being verified, the second waits for the first's verdict
+//! rather than duplicating the work.
+
+// Standard library imports
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::hash::Hash;
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex, MutexGuard};
+
+/// A value which uniquely identifies a file's content on the current system
+#[cfg(unix)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct FileId {
+    /// The ID of the device containing the file
+    device: u64,
+    /// The inode number of the file within that device
+    inode: u64,
+}
+
+/// A value which uniquely identifies a file's content on the current system
+#[cfg(windows)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct FileId {
+    /// The serial number of the volume containing the file
+    volume: u64,
+    /// The file's index within that volume
+    index: u64,
+}
+
+/// A placeholder for platforms where hardlinks can't be detected
+#[cfg(not(any(unix, windows)))]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct FileId(());
+
+impl FileId {
+    /// Identify the file at the given path, if it could possibly have other hardlinks
+    ///
+    /// Returns `None` for files with only one link, since they can't be deduplicated.
+    #[cfg(unix)]
+    pub fn for_path(_path: &Path, metadata: &Metadata) -> Option<Self> {
+        use std::os::unix::fs::MetadataExt;
+        if metadata.nlink() > 1 {
+            Some(Self { device: metadata.dev(), inode: metadata.ino() })
+        } else {
+            None
+        }
+    }
+
+    /// Identify the file at the given path, if it could possibly have other hardlinks
+    ///
+    /// Returns `None` for files with only one link, since they can't be deduplicated. (The handle
+    /// opened to ask is closed again, so nothing is held open while the walk continues.)
+    #[cfg(windows)]
+    pub fn for_path(path: &Path, _metadata: &Metadata) -> Option<Self> {
+        let handle = winapi_util::Handle::from_path_any(path).ok()?;
+        let info = winapi_util::file::information(&handle).ok()?;
+        if info.number_of_links() > 1 {
+            Some(Self { volume: info.volume_serial_number(), index: info.file_index() })
+        } else {
+            None
+        }
+    }
+
+    /// Identify the file at the given path, if it could possibly have other hardlinks
+    #[cfg(not(any(unix, windows)))]
+    pub fn for_path(_path: &Path, _metadata: &Metadata) -> Option<Self> {
+        None
+    }
+}
+
+/// The state of a file ID which has been claimed by some path
+enum Entry<V> {
+    /// The claiming path is still being verified
+    Pending,
+    /// The claiming path was verified and this is its verdict
+    Done(PathBuf, V),
+}
+
+/// The result of [`Tracker::claim`]
+pub enum Claim<'a, K: Eq + Hash, V> {
+    /// This is the first path to reach the file. Verify it and pass the verdict to
+    /// [`Ticket::finish`].
+    First(Ticket<'a, K, V>),
+    /// Another path already reached the file, and this is its path and verdict
+    Duplicate(PathBuf, V),
+}
+
+/// A registry of file IDs which have been (or are being) verified
+pub struct Tracker<K: Eq + Hash, V> {
+    /// The state of each file ID claimed so far
+    seen: Mutex<HashMap<K, Entry<V>>>,
+    /// Signalled whenever a pending entry is finished or abandoned
+    changed: Condvar,
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> Tracker<K, V> {
+    /// Create an empty tracker
+    pub fn new() -> Self {
+        Self { seen: Mutex::new(HashMap::new()), changed: Condvar::new() }
+    }
+
+    /// Lock the map, ignoring poisoning since entries are only ever replaced wholesale
+    fn lock(&self) -> MutexGuard<'_, HashMap<K, Entry<V>>> {
+        self.seen.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// Claim the given file ID on behalf of `path`, blocking if another path is being verified
+    pub fn claim(&self, id: K, path: &Path) -> Claim<'_, K, V> {
+        let mut seen = self.lock();
+        loop {
+            match seen.get(&id) {
+                None => {
+                    seen.insert(id.clone(), Entry::Pending);
+                    return Claim::First(Ticket { tracker: self, id: Some(id) });
+                },
+                Some(Entry::Done(first, verdict)) if first != path => {
+                    return Claim::Duplicate(first.clone(), verdict.clone());
+                },
+                // The same path reached twice (eg. two overlapping roots) is verified again
+                Some(Entry::Done(..)) => {
+                    return Claim::First(Ticket { tracker: self, id: None });
+                },
+                Some(Entry::Pending) => {
+                    seen = self.changed.wait(seen).unwrap_or_else(|e| e.into_inner());
+                },
+            }
+        }
+    }
+}
+
+/// An obligation to report the verdict for a claimed file ID
+///
+/// If dropped without calling [`finish`](Self::finish) (eg. due to a panic), the claim is released
+/// so another link can be verified in its place.
+pub struct Ticket<'a, K: Eq + Hash, V> {
+    /// The tracker the claim was made against
+    tracker: &'a Tracker<K, V>,
+    /// The ID which was claimed, or `None` if nothing needs to be recorded
+    id: Option<K>,
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> Ticket<'_, K, V> {
+    /// Record the verdict for the claimed file, waking anything waiting on it
+    pub fn finish(mut self, path: &Path, verdict: V) {
+        if let Some(id) = self.id.take() {
+            self.tracker.lock().insert(id, Entry::Done(path.to_owned(), verdict));
+            self.tracker.changed.notify_all();
+        }
+    }
+}
+
+impl<K: Eq + Hash, V> Drop for Ticket<'_, K, V> {
+    fn drop(&mut self) {
+        if let Some(id) = self.id.take() {
+            let mut seen = self.tracker.seen.lock().unwrap_or_else(|e| e.into_inner());
+            seen.remove(&id);
+            self.tracker.changed.notify_all();
+        }
+    }
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_claims() {
+        let tracker: Tracker<u32, &str> = Tracker::new();
+        let (a, b) = (Path::new("a"), Path::new("b"));
+
+        match tracker.claim(1, a) {
+            Claim::First(ticket) => ticket.finish(a, "verdict"),
+            Claim::Duplicate(..) => panic!("First claim reported as duplicate"),
+        }
+        assert!(matches!(tracker.claim(1, b), Claim::Duplicate(ref x, "verdict") if x == a));
+        assert!(matches!(tracker.claim(1, a), Claim::First(_)), "Same path should re-verify");
+
+        // Abandoned claims must be released rather than deadlocking later links
+        drop(tracker.claim(2, a));
+        assert!(matches!(tracker.claim(2, b), Claim::First(_)));
+    }
+
+    /// A second link reached while the first is pending must wait rather than re-verify
+    #[test]
+    fn test_concurrent_claims() {
+        let tracker: Arc<Tracker<u32, u32>> = Arc::new(Tracker::new());
+        let ticket = match tracker.claim(1, Path::new("first")) {
+            Claim::First(ticket) => ticket,
+            Claim::Duplicate(..) => panic!("First claim reported as duplicate"),
+        };
+
+        let waiters: Vec<_> = (0..4)
+            .map(|idx| {
+                let tracker = Arc::clone(&tracker);
+                thread::spawn(move || {
+                    match tracker.claim(1, Path::new(&format!("link{}", idx))) {
+                        Claim::First(_) => None,
+                        Claim::Duplicate(_, verdict) => Some(verdict),
+                    }
+                })
+            })
+            .collect();
+
+        ticket.finish(Path::new("first"), 42);
+        for waiter in waiters {
+            assert_eq!(waiter.join().unwrap(), Some(42));
+        }
+    }
+}
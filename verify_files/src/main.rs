@@ -14,6 +14,9 @@
 #![allow(clippy::blanket_clippy_restriction_lints)]
 #![forbid(unsafe_code)] // Enforce my policy of only allowing it in my own code as a last resort
 
+// Standard library imports
+use std::process::ExitCode;
+
 // 3rd-party imports
 use anyhow::{Context, Result};
 use clap::Parser;
@@ -22,12 +25,17 @@ use clap::Parser;
 mod app;
 mod builtin_handlers;
 mod config;
+mod dispatch;
+mod hardlinks;
+mod subprocess;
+mod tempdir;
+mod throttle;
 mod validators;
 
 /// Boilerplate to parse command-line arguments, set up logging, and handle bubbled-up `Error`s.
 ///
 /// See `app::main` for the application-specific logic.
-fn main() -> Result<()> {
+fn main() -> Result<ExitCode> {
     // Parse command-line arguments (exiting on parse error, --version, or --help)
     let opts = app::CliOpts::parse();
 
@@ -0,0 +1,1220 @@
+//! Support for running the external commands defined in `[handler.*]` tables
+
+// Standard library imports
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, ExitStatus, Stdio};
+use std::sync::Once;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// 3rd-party crate imports
+use faccess::PathExt as _;
+use log::{debug, warn};
+
+// Local Imports
+use crate::builtin_handlers::FailureType;
+use crate::config::{Handler, HandlerArgs, InputKind, Limits};
+use crate::dispatch::Options;
+use crate::tempdir::TempDir;
+
+/// The token in `argv` which is replaced with the path to be checked
+pub const PATH_TOKEN: &str = "{path}";
+
+/// The token in `argv` which is replaced with [`DEVNULL`]
+pub const DEVNULL_TOKEN: &str = "{devnull}";
+
+/// The token in `argv` which is replaced with the file name of the path to be checked
+pub const BASENAME_TOKEN: &str = "{basename}";
+
+/// The token in `argv` which is replaced with the extension (without the period) of the path to
+/// be checked
+pub const EXT_TOKEN: &str = "{ext}";
+
+/// The token in `argv` which is replaced with the directory containing the path to be checked
+pub const DIR_TOKEN: &str = "{dir}";
+
+/// The token in `argv` which is replaced with the path to a scratch directory which is deleted
+/// once the command exits
+pub const TMPDIR_TOKEN: &str = "{tmpdir}";
+
+/// All fixed substitution tokens understood by [`build_argv`]
+///
+/// (`{arg:key}` tokens, which are replaced with entries from the filetype's
+/// [`handler_args`](crate::config::Filetype::handler_args), are recognized by [`arg_token_key`].)
+pub const TOKENS: &[&str] =
+    &[PATH_TOKEN, DEVNULL_TOKEN, BASENAME_TOKEN, EXT_TOKEN, DIR_TOKEN, TMPDIR_TOKEN];
+
+/// The start of a token which is replaced with an entry from the filetype's `handler_args`
+const ARG_TOKEN_PREFIX: &str = "{arg:";
+
+/// If `token` is an `{arg:key}` token, return the `key`
+pub fn arg_token_key(token: &str) -> Option<&str> {
+    token
+        .strip_prefix(ARG_TOKEN_PREFIX)
+        .and_then(|x| x.strip_suffix('}'))
+        .filter(|x| !x.is_empty() && x.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'))
+}
+
+/// The platform's equivalent to `/dev/null`
+#[cfg(not(windows))]
+pub const DEVNULL: &str = "/dev/null";
+
+/// The platform's equivalent to `/dev/null`
+#[cfg(windows)]
+pub const DEVNULL: &str = "NUL";
+
+/// The maximum number of characters of `stderr` to quote in a failure message
+const STDERR_EXCERPT_LEN: usize = 200;
+
+/// How many bytes of each of a handler's output streams to keep for display
+pub const OUTPUT_CAP: usize = 4096;
+
+/// How many bytes from the end of `stderr` to keep for recognizing out-of-memory errors
+const TAIL_LEN: usize = 1024;
+
+/// The longest partial line of `stderr` to keep for matching `fail_if_stderr` across reads
+const MAX_WINDOW: usize = 64 * 1024;
+
+/// How often to check whether a handler with a timeout has exited
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Expand the substitution tokens in a single argument for the given path
+///
+/// * `{basename}` is the last component of the path, including its extension.
+/// * `{ext}` is the part of `{basename}` after the last period, or empty if there isn't one.
+/// * `{dir}` is the directory containing the path, or `.` if the path has no parent component
+///   (eg. a file in the current directory given as a bare filename).
+/// * `{tmpdir}` is `tmpdir`, which the caller is responsible for creating and cleaning up. (It
+///   expands to an empty string if `None`.)
+/// * `{arg:key}` is the entry for `key` in `args`, or an empty string if there isn't one.
+///
+/// (Substitution is done on `OsString`s so non-UTF-8 paths survive intact.)
+pub fn expand_tokens(arg: &str, path: &Path, tmpdir: Option<&Path>, args: &HandlerArgs)
+        -> OsString {
+    let mut expanded = OsString::new();
+    let mut rest = arg;
+    while let Some(start) = rest.find('{') {
+        expanded.push(&rest[..start]);
+        rest = &rest[start..];
+
+        let arg_token = rest.find('}').map(|end| &rest[..=end]);
+        if let Some((token, key)) = arg_token.and_then(|x| arg_token_key(x).map(|key| (x, key))) {
+            expanded.push(args.get(key).map_or("", String::as_str));
+            rest = &rest[token.len()..];
+            continue;
+        }
+
+        let token = match TOKENS.iter().find(|x| rest.starts_with(**x)) {
+            Some(token) => *token,
+            None => {
+                expanded.push("{");
+                rest = &rest[1..];
+                continue;
+            },
+        };
+        match token {
+            PATH_TOKEN => expanded.push(path),
+            DEVNULL_TOKEN => expanded.push(DEVNULL),
+            BASENAME_TOKEN => expanded.push(path.file_name().unwrap_or_default()),
+            EXT_TOKEN => expanded.push(path.extension().unwrap_or_default()),
+            DIR_TOKEN => match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => expanded.push(parent),
+                _ => expanded.push("."),
+            },
+            TMPDIR_TOKEN => expanded.push(tmpdir.unwrap_or_else(|| Path::new(""))),
+            _ => unreachable!("Token in TOKENS without an expansion: {}", token),
+        }
+        rest = &rest[token.len()..];
+    }
+    expanded.push(rest);
+    expanded
+}
+
+/// Expand the substitution tokens in a handler's `argv` for the given path
+///
+/// As documented on [`Handler::argv`], the path is appended if no `{path}` tokens are present
+/// unless `append_path` is `false` (eg. because the file is being fed to `stdin` instead).
+pub fn build_argv(argv: &[String], path: &Path, tmpdir: Option<&Path>, args: &HandlerArgs,
+                  append_path: bool) -> Vec<OsString> {
+    let mut result: Vec<OsString> =
+        argv.iter().map(|arg| expand_tokens(arg, path, tmpdir, args)).collect();
+    if append_path && !argv.iter().any(|x| x.contains(PATH_TOKEN)) {
+        result.push(path.into());
+    }
+    result
+}
+
+/// Find the executable `argv0` would run, searching `PATH` if it isn't a path itself
+///
+/// On Windows, each extension in `PATHEXT` is tried too (so `7z` finds `7z.exe` and a
+/// `wrapper.cmd` can be named as just `wrapper`) and the returned path includes it.
+///
+/// (Used to warn about missing handlers up front rather than partway through a long run.)
+pub fn find_executable(argv0: &str) -> Option<PathBuf> {
+    find_executable_in(argv0, env::var_os("PATH").as_deref())
+}
+
+/// Like [`find_executable`], but searching `search_path` (a list of directories in the form `PATH`
+/// takes) instead of our own `PATH`
+pub fn find_executable_in(argv0: &str, search_path: Option<&OsStr>) -> Option<PathBuf> {
+    #[cfg(windows)]
+    let extensions: Vec<OsString> = match env::var_os("PATHEXT") {
+        Some(pathext) => pathext
+            .to_string_lossy()
+            .split(';')
+            .filter(|x| !x.is_empty())
+            .map(OsString::from)
+            .collect(),
+        None => [".COM", ".EXE", ".BAT", ".CMD"].iter().map(OsString::from).collect(),
+    };
+    #[cfg(not(windows))]
+    let extensions: Vec<OsString> = Vec::new();
+
+    let resolve = |candidate: PathBuf| {
+        if candidate.is_file() && candidate.executable() {
+            return Some(candidate);
+        }
+        extensions
+            .iter()
+            .map(|ext| {
+                let mut with_ext = candidate.clone().into_os_string();
+                with_ext.push(ext);
+                PathBuf::from(with_ext)
+            })
+            .find(|x| x.is_file())
+    };
+
+    let argv0 = Path::new(argv0);
+    if argv0.components().count() > 1 || argv0.is_absolute() {
+        return resolve(argv0.to_owned());
+    }
+    env::split_paths(search_path?).find_map(|dir| resolve(dir.join(argv0)))
+}
+
+/// Run an external handler on the given path and map its result into the same form as the
+/// built-in handlers use
+///
+/// * Exit statuses are interpreted according to the handler's
+///   [`exit_codes`](Handler::exit_codes). By default, zero means the file passed and anything else
+///   (including death by signal) is [`InvalidContent`](FailureType::InvalidContent).
+/// * If the command couldn't be started because it isn't installed (or isn't executable), the
+///   result is [`HandlerUnavailable`](FailureType::HandlerUnavailable) so the fallback chain can
+///   move on.
+/// * If the handler sets [`fail_if_stderr`](Handler::fail_if_stderr) and that string appears in
+///   what the command wrote to `stderr`, it's [`InvalidContent`](FailureType::InvalidContent) even
+///   if the exit status was zero.
+/// * If the handler has `input = "stdin"`, the file is fed to the command's `stdin` rather than
+///   passing its path. The command is free to exit without reading all of it.
+/// * If the command is stopped by one of its [`limits`](Handler::limits), the result is
+///   [`InternalError`](FailureType::InternalError), naming the limit.
+/// * If the command runs longer than its [`timeout`](Handler::timeout) (or the default from
+///   `options` if it doesn't set one), it's killed and the result is
+///   [`InternalError`](FailureType::InternalError), since a hang isn't evidence of corruption.
+///
+/// The subprocess's output is captured rather than inherited, so it can't intermingle with ours,
+/// and returned (capped to [`OUTPUT_CAP`] bytes per stream) if the command ran at all.
+///
+/// `args` supplies the values for `{arg:key}` substitution tokens.
+pub fn run(handler: &Handler, path: &Path, args: &HandlerArgs, options: &Options)
+        -> (Result<(), FailureType>, Option<HandlerOutput>) {
+    let mut output = None;
+    let result = run_inner(handler, path, args, options, &mut output);
+    (result, output)
+}
+
+/// The body of [`run`], which stores the command's output in `output` once it's finished
+fn run_inner(handler: &Handler, path: &Path, args: &HandlerArgs, options: &Options,
+             output: &mut Option<HandlerOutput>) -> Result<(), FailureType> {
+    // Held until the function returns so it outlives the child, however the child exits
+    let mut templates = handler.argv.iter().chain(&handler.cwd).chain(handler.env.values());
+    let tmpdir = if templates.any(|x| x.contains(TMPDIR_TOKEN)) {
+        Some(options.tempdir().map_err(|e| {
+            FailureType::InternalError(format!("Could not create scratch directory: {}", e))
+        })?)
+    } else {
+        None
+    };
+    let tmpdir_path = tmpdir.as_ref().map(TempDir::path);
+
+    let use_stdin = handler.input == InputKind::Stdin;
+    let argv = build_argv(&handler.argv, path, tmpdir_path, args, !use_stdin);
+    let argv0 = match argv.first() {
+        Some(x) => x,
+        None => return Err(FailureType::InternalError("Handler has an empty argv".to_owned())),
+    };
+
+    // Open the file before spawning anything so an unreadable file is reported as such
+    let stdin_file = if use_stdin {
+        Some(File::open(path).map_err(|e| FailureType::IoError(e.to_string()))?)
+    } else {
+        None
+    };
+
+    // argv[0] is looked up in the PATH the command will get, which may not be ours
+    let search_path = handler.env.iter()
+        .find(|(name, _)| is_path_var(name))
+        .map(|(_, value)| expand_tokens(value, path, tmpdir_path, args))
+        .or_else(|| env::var_os("PATH"));
+    let launch = wrap_command(handler, &argv, search_path.as_deref())?;
+    let mut command = Command::new(&launch[0]);
+    command
+        .args(&launch[1..])
+        .stdin(if use_stdin { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    if let Some(ref cwd) = handler.cwd {
+        command.current_dir(expand_tokens(cwd, path, tmpdir_path, args));
+    }
+    if handler.env_clear {
+        command.env_clear();
+    }
+    for (name, value) in &handler.env {
+        command.env(name, expand_tokens(value, path, tmpdir_path, args));
+    }
+
+    // Windows has priority classes rather than niceness levels, and applies them at creation
+    #[cfg(windows)]
+    if handler.nice.or(options.nice).is_some() {
+        /// The `BELOW_NORMAL_PRIORITY_CLASS` process creation flag from the Win32 API
+        const BELOW_NORMAL_PRIORITY_CLASS: u32 = 0x0000_4000;
+        std::os::windows::process::CommandExt::creation_flags(&mut command,
+                                                              BELOW_NORMAL_PRIORITY_CLASS);
+    }
+
+    // Give the handler its own process group so anything it spawns can be killed along with it
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(err) => {
+            let msg = format!("Could not run {}: {}", argv0.to_string_lossy(), err);
+            return match err.kind() {
+                io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied => {
+                    Err(FailureType::HandlerUnavailable(msg))
+                },
+                _ => Err(FailureType::InternalError(msg)),
+            };
+        },
+    };
+    let feeder = stdin_file.map(|file| feed_stdin(file, child.stdin.take()));
+
+    let timeout = handler.timeout.map(|x| x.0).or(options.handler_timeout);
+    let finished = wait_with_timeout(child, timeout, handler.fail_if_stderr.as_deref())
+        .map_err(|err| {
+            FailureType::InternalError(format!("Error waiting for {}: {}",
+                                               argv0.to_string_lossy(), err))
+        })?;
+    *output = Some(HandlerOutput {
+        stdout: finished.stdout.render(),
+        stderr: finished.stderr.render(),
+    });
+
+    let status = match finished.status {
+        Some(status) => status,
+        None => {
+            return Err(FailureType::InternalError(format!(
+                "{}: handler timed out after {:?}", argv0.to_string_lossy(),
+                timeout.unwrap_or_default())));
+        },
+    };
+    if let Some(Err(err)) = feeder.map(|x| x.join().unwrap_or(Ok(()))) {
+        return Err(FailureType::IoError(format!("Could not feed file to {}: {}",
+                                                argv0.to_string_lossy(), err)));
+    }
+
+    if let Some(msg) = exceeded_limit(&handler.limits, status, &finished.stderr) {
+        return Err(FailureType::InternalError(format!("{} {}", argv0.to_string_lossy(), msg)));
+    }
+
+    // Death by signal counts as a failure, since it's most likely a crash on malformed input
+    let exit_codes = &handler.exit_codes;
+    let status_msg = || format!("{} {}", argv0.to_string_lossy(), status);
+    match status.code() {
+        Some(code) if exit_codes.success.contains(&code) => {},
+        Some(code) if exit_codes.unsupported.contains(&code) => {
+            return Err(FailureType::UnsupportedFormat(status_msg()));
+        },
+        Some(code) if exit_codes.internal.contains(&code) => {
+            return Err(FailureType::InternalError(status_msg()));
+        },
+        _ => return Err(FailureType::InvalidContent(status_msg())),
+    }
+
+    if let Some(ref excerpt) = finished.stderr.matched {
+        return Err(FailureType::InvalidContent(format!(
+            "{} reported an error: {}", argv0.to_string_lossy(), excerpt)));
+    }
+    Ok(())
+}
+
+/// Log a warning, but only the first time it's requested with the given `once`
+///
+/// (For unsupported settings which would otherwise produce a warning for every file.)
+fn warn_once(once: &Once, message: &str) {
+    once.call_once(|| warn!("{}", message));
+}
+
+/// Whether the environment variable `name` is the one executables are looked up in
+fn is_path_var(name: &str) -> bool {
+    if cfg!(windows) { name.eq_ignore_ascii_case("PATH") } else { name == "PATH" }
+}
+
+/// Resolve `argv[0]` against `search_path` and wrap `argv` so the command runs with the priority
+/// and resource limits `handler` asks for
+///
+/// **NOTE:** Calling `setpriority` or `setrlimit` between `fork` and `exec` requires `unsafe`
+/// (which this crate forbids) so, on Unix, this chains through `nice`, `ionice`, and `/bin/sh`
+/// (to apply limits with `ulimit`) which then `exec` the real command. `argv[0]` is resolved
+/// whether or not any wrappers are needed, so they can't mask "command not found" errors and the
+/// same executable runs either way.
+///
+/// Being unable to set a priority is only a warning, since it doesn't affect the verdict.
+#[cfg(unix)]
+fn wrap_command(handler: &Handler, argv: &[OsString], search_path: Option<&OsStr>)
+        -> Result<Vec<OsString>, FailureType> {
+    static NICE_WARNED: Once = Once::new();
+    static IONICE_WARNED: Once = Once::new();
+
+    let mut wrapped: Vec<OsString> = Vec::new();
+    if let Some(nice) = handler.nice {
+        match find_executable("nice") {
+            Some(nice_path) => {
+                wrapped.extend([nice_path.into(), "-n".into(), nice.to_string().into()]);
+            },
+            None => warn_once(&NICE_WARNED, "Could not find nice. Handlers which set 'nice' \
+                                             will run at normal priority."),
+        }
+    }
+    if let Some(class) = handler.ionice_class {
+        // `-t` makes it run the command anyway if the class can't be set
+        match find_executable("ionice") {
+            Some(ionice_path) => wrapped.extend([ionice_path.into(), "-c".into(),
+                                                 class.number().to_string().into(), "-t".into()]),
+            None => warn_once(&IONICE_WARNED, "Could not find ionice (Linux-only). Handlers' \
+                                               'ionice_class' settings will be ignored."),
+        }
+    }
+
+    let limits = &handler.limits;
+    if !limits.is_empty() {
+        let mut script = String::new();
+        if let Some(memory_mb) = limits.memory_mb {
+            script.push_str(&format!("ulimit -v {} && ", memory_mb.saturating_mul(1024)));
+        }
+        if let Some(cpu_seconds) = limits.cpu_seconds {
+            script.push_str(&format!("ulimit -t {} && ", cpu_seconds));
+        }
+        script.push_str("exec \"$@\"");
+        wrapped.extend(["/bin/sh".into(), "-c".into(), script.into(),
+                        "verify_files_limits".into()]);
+    }
+
+    let argv0 = argv[0].to_string_lossy();
+    let resolved = find_executable_in(&argv0, search_path).ok_or_else(|| {
+        FailureType::HandlerUnavailable(format!("Could not find {} to run", argv0))
+    })?;
+    wrapped.push(resolved.into());
+    wrapped.extend_from_slice(&argv[1..]);
+    Ok(wrapped)
+}
+
+/// Resolve `argv[0]` against `search_path` and apply whatever of the resource limits `handler`
+/// asks for are supported
+///
+/// `Command` only looks for `.exe` files when searching `PATH`, so `argv[0]` is always resolved
+/// with [`find_executable`] to get `PATHEXT` semantics. (`Command` then runs any `.bat` or `.cmd`
+/// file it's pointed at via `cmd /c`, with the escaping batch files need.)
+///
+/// Limits aren't yet supported on this platform, so they're warned about once and ignored.
+/// `nice` is handled by setting the process's priority class instead.
+#[cfg(not(unix))]
+fn wrap_command(handler: &Handler, argv: &[OsString], search_path: Option<&OsStr>)
+        -> Result<Vec<OsString>, FailureType> {
+    static LIMITS_WARNED: Once = Once::new();
+    static IONICE_WARNED: Once = Once::new();
+    if !handler.limits.is_empty() {
+        warn_once(&LIMITS_WARNED, "Handler resource limits aren't supported on this platform \
+                                   and will be ignored");
+    }
+    if handler.ionice_class.is_some() {
+        warn_once(&IONICE_WARNED, "I/O priorities aren't supported on this platform. \
+                                   Handlers' 'ionice_class' settings will be ignored.");
+    }
+
+    let argv0 = argv[0].to_string_lossy();
+    let resolved = find_executable_in(&argv0, search_path).ok_or_else(|| {
+        FailureType::HandlerUnavailable(format!("Could not find {} to run", argv0))
+    })?;
+    let mut resolved_argv = vec![resolved.into_os_string()];
+    resolved_argv.extend_from_slice(&argv[1..]);
+    Ok(resolved_argv)
+}
+
+/// Lower the priority of this process by `increment` (as with `nice -n`) so verification yields
+/// to interactive use of the machine
+///
+/// This covers the built-in handlers as well as the external ones, which inherit it.
+///
+/// **NOTE:** `setpriority` requires `unsafe` (which this crate forbids), so this runs `renice`
+/// on the current process instead. On Linux, that only affects the main thread, so this must be
+/// called before any worker threads are spawned for them to inherit it.
+#[cfg(unix)]
+pub fn lower_own_priority(increment: u8) {
+    let result = Command::new("renice")
+        .args(["-n", &increment.to_string(), "-p", &std::process::id().to_string()])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    match result {
+        Ok(status) if status.success() => debug!("Lowered own priority by {}", increment),
+        Ok(status) => warn!("Could not lower own priority: renice {}", status),
+        Err(err) => warn!("Could not lower own priority: Could not run renice: {}", err),
+    }
+}
+
+/// Lower the priority of this process by `increment` (as with `nice -n`) so verification yields
+/// to interactive use of the machine
+///
+/// (Not supported on this platform, so only external handlers are run at lower priority.)
+#[cfg(not(unix))]
+pub fn lower_own_priority(_increment: u8) {
+    warn!("Lowering the priority of built-in handlers isn't supported on this platform. Only \
+           external handlers will run at lower priority.");
+}
+
+/// If the command's output suggests it was stopped by one of its resource limits, return a
+/// message naming the limit
+///
+/// **NOTE:** Running out of memory isn't reported in any consistent way, so this has to guess
+/// from crash signals and `stderr` mentioning memory or allocation failures.
+#[cfg(unix)]
+fn exceeded_limit(limits: &Limits, status: ExitStatus, stderr: &Capture) -> Option<String> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let signal = status.signal();
+    if let Some(cpu_seconds) = limits.cpu_seconds {
+        // SIGXCPU is sent at the soft limit and SIGKILL at the hard limit
+        if signal == Some(libc::SIGXCPU) || signal == Some(libc::SIGKILL) {
+            return Some(format!("exceeded limits.cpu_seconds ({}s)", cpu_seconds));
+        }
+    }
+    if let Some(memory_mb) = limits.memory_mb {
+        let crashed =
+            matches!(signal, Some(libc::SIGABRT | libc::SIGSEGV | libc::SIGBUS | libc::SIGKILL));
+        let stderr = format!("{}\n{}", String::from_utf8_lossy(&stderr.head),
+                             String::from_utf8_lossy(&stderr.tail)).to_lowercase();
+        let complained = ["memory", "out of space", "alloc"].iter().any(|x| stderr.contains(x));
+        if !status.success() && (crashed || complained) {
+            return Some(format!("probably exceeded limits.memory_mb ({} MiB)", memory_mb));
+        }
+    }
+    None
+}
+
+/// If the command's output suggests it was stopped by one of its resource limits, return a
+/// message naming the limit
+#[cfg(not(unix))]
+fn exceeded_limit(_limits: &Limits, _status: ExitStatus, _stderr: &Capture) -> Option<String> {
+    None
+}
+
+/// Copy `file` into the child's `stdin` in the background, then close it
+///
+/// The child exiting without reading everything (eg. because it found an error early) isn't
+/// treated as an error.
+fn feed_stdin(mut file: File, stdin: Option<ChildStdin>) -> thread::JoinHandle<io::Result<()>> {
+    thread::spawn(move || {
+        let mut stdin = match stdin {
+            Some(stdin) => stdin,
+            None => return Ok(()),
+        };
+        match io::copy(&mut file, &mut stdin) {
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+            result => result.map(|_| ()),
+        }
+    })
+}
+
+/// The output streams captured from a handler, capped to [`OUTPUT_CAP`] bytes each
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct HandlerOutput {
+    /// What the handler wrote to `stdout`, decoded lossily
+    pub stdout: String,
+    /// What the handler wrote to `stderr`, decoded lossily
+    pub stderr: String,
+}
+
+impl HandlerOutput {
+    /// Whether the handler didn't write anything at all
+    pub fn is_empty(&self) -> bool {
+        self.stdout.is_empty() && self.stderr.is_empty()
+    }
+}
+
+impl fmt::Display for HandlerOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let streams = [("stdout", &self.stdout), ("stderr", &self.stderr)];
+        let mut first = true;
+        for (name, text) in streams.iter().filter(|(_, text)| !text.is_empty()) {
+            if !first {
+                writeln!(f)?;
+            }
+            first = false;
+            write!(f, "{}:", name)?;
+            for line in text.trim_end().lines() {
+                write!(f, "\n\t{}", line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What was read from one of a handler's output streams
+#[derive(Default)]
+struct Capture {
+    /// The first [`OUTPUT_CAP`] bytes
+    head: Vec<u8>,
+    /// The last [`TAIL_LEN`] bytes
+    tail: Vec<u8>,
+    /// How many bytes were read in total
+    total: u64,
+    /// An excerpt around the first match for the string being watched for, if any
+    matched: Option<String>,
+}
+
+impl Capture {
+    /// Read `pipe` to the end, keeping only what's needed and watching for `needle`
+    ///
+    /// (The whole stream is searched, not just what's kept, so messages can't slip past
+    /// `fail_if_stderr` just because a tool was chatty.)
+    fn read<R: Read>(mut pipe: R, needle: Option<&str>) -> Self {
+        let mut capture = Self::default();
+        let mut window = Vec::new();
+        let mut buf = [0; 8192];
+        loop {
+            let chunk = match pipe.read(&mut buf) {
+                Ok(0) => break,
+                Ok(len) => &buf[..len],
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+            capture.total += chunk.len() as u64;
+            let room = OUTPUT_CAP.saturating_sub(capture.head.len()).min(chunk.len());
+            capture.head.extend_from_slice(&chunk[..room]);
+            capture.tail.extend_from_slice(chunk);
+            let excess = capture.tail.len().saturating_sub(TAIL_LEN);
+            capture.tail.drain(..excess);
+
+            if let (Some(needle), None) = (needle, &capture.matched) {
+                window.extend_from_slice(chunk);
+                capture.matched = stderr_excerpt(&String::from_utf8_lossy(&window), needle);
+
+                // Keep the current partial line, plus enough for a match spanning two reads
+                let line_start = window.iter().rposition(|&x| x == b'\n').map_or(0, |x| x + 1);
+                let keep_from = line_start
+                    .min(window.len().saturating_sub(needle.len()))
+                    .max(window.len().saturating_sub(MAX_WINDOW));
+                window.drain(..keep_from);
+            }
+        }
+        capture
+    }
+
+    /// Render what was kept for display, noting if anything was discarded
+    fn render(&self) -> String {
+        let mut text = String::from_utf8_lossy(&self.head).into_owned();
+        if self.total > self.head.len() as u64 {
+            text.push_str(&format!("\n[output truncated after {} of {} bytes]",
+                                   self.head.len(), self.total));
+        }
+        text
+    }
+}
+
+/// The result of [`wait_with_timeout`]
+struct Finished {
+    /// The child's exit status, or `None` if it had to be killed for running too long
+    status: Option<ExitStatus>,
+    /// What the child wrote to `stdout`
+    stdout: Capture,
+    /// What the child wrote to `stderr`, with matches for `fail_if_stderr` noted
+    stderr: Capture,
+}
+
+/// Collect the output of `child`, killing it if it's still running after `timeout`
+///
+/// Either way, the child is reaped before returning so long runs don't accumulate zombie
+/// processes.
+fn wait_with_timeout(mut child: Child, timeout: Option<Duration>, needle: Option<&str>)
+        -> io::Result<Finished> {
+    // Drain the pipes in the background so a chatty child can't block on a full pipe buffer
+    fn drain<R: Read + Send + 'static>(pipe: Option<R>, needle: Option<String>)
+            -> thread::JoinHandle<Capture> {
+        thread::spawn(move || pipe.map(|x| Capture::read(x, needle.as_deref())).unwrap_or_default())
+    }
+    let stdout = drain(child.stdout.take(), None);
+    let stderr = drain(child.stderr.take(), needle.map(str::to_owned));
+
+    let status = match timeout {
+        None => Some(child.wait()?),
+        Some(timeout) => {
+            let deadline = Instant::now() + timeout;
+            loop {
+                if let Some(status) = child.try_wait()? {
+                    break Some(status);
+                }
+                let now = Instant::now();
+                if now >= deadline {
+                    kill_tree(&mut child);
+                    child.wait()?;
+                    break None;
+                }
+                thread::sleep(POLL_INTERVAL.min(deadline - now));
+            }
+        },
+    };
+
+    // Killing the whole process group closes the pipes, so these can't block forever
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+    Ok(Finished { status, stdout, stderr })
+}
+
+/// Kill `child` and, where supported, everything else in its process group
+///
+/// **NOTE:** Signalling a process group needs `unsafe` FFI (which this crate forbids) so, on
+/// Unix, this asks the `kill` command to do it and falls back to killing just the child.
+fn kill_tree(child: &mut Child) {
+    #[cfg(unix)]
+    #[allow(clippy::let_underscore_must_use)]
+    let _ = Command::new("kill")
+        .args(["-KILL", "--", &format!("-{}", child.id())])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+
+    // Fails harmlessly if the child is already dead (but not yet reaped)
+    #[allow(clippy::let_underscore_must_use)]
+    let _ = child.kill();
+}
+
+/// If `needle` occurs in `stderr`, return the line(s) containing its first occurrence, trimmed
+/// and truncated to [`STDERR_EXCERPT_LEN`] characters
+///
+/// (`stderr` is expected to have been decoded lossily so non-UTF-8 output can't dodge the check.)
+fn stderr_excerpt(stderr: &str, needle: &str) -> Option<String> {
+    let start = stderr.find(needle)?;
+    let end = start + needle.len();
+    let line_start = stderr[..start].rfind('\n').map_or(0, |x| x + 1);
+    let line_end = stderr[end..].find('\n').map_or(stderr.len(), |x| end + x);
+
+    let excerpt = stderr[line_start..line_end].trim();
+    if excerpt.chars().count() > STDERR_EXCERPT_LEN {
+        let mut truncated: String = excerpt.chars().take(STDERR_EXCERPT_LEN).collect();
+        truncated.push('…');
+        Some(truncated)
+    } else {
+        Some(excerpt.to_owned())
+    }
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::NO_HANDLER_ARGS;
+
+    /// Parse a `[handler.*]` table for testing
+    fn handler(toml_str: &str) -> Handler {
+        toml_edit::de::from_str(toml_str).unwrap()
+    }
+
+    /// Run a handler, discarding its output
+    fn verdict(handler: &Handler, path: &Path, options: &Options) -> Result<(), FailureType> {
+        run(handler, path, &NO_HANDLER_ARGS, options).0
+    }
+
+    /// Convert a list of string literals into an `argv`
+    fn strings(argv: &[&str]) -> Vec<String> {
+        argv.iter().map(|x| (*x).to_owned()).collect()
+    }
+
+    #[test]
+    fn test_build_argv() {
+        let path = Path::new("/tmp/some file.zip");
+        let argv = |x: &[&str]| build_argv(&strings(x), path, None, &NO_HANDLER_ARGS, true);
+
+        assert_eq!(argv(&["unzip", "-t"]), vec!["unzip", "-t", "/tmp/some file.zip"]);
+        assert_eq!(argv(&["unzip", "-t", "{path}", "-q"]),
+                   vec!["unzip", "-t", "/tmp/some file.zip", "-q"]);
+        assert_eq!(argv(&["tool", "--in={path}", "--log={path}.log"]),
+                   vec!["tool", "--in=/tmp/some file.zip", "--log=/tmp/some file.zip.log"]);
+        assert_eq!(build_argv(&strings(&["xmllint", "-"]), path, None, &NO_HANDLER_ARGS, false),
+                   vec!["xmllint", "-"]);
+    }
+
+    #[test]
+    fn test_devnull_token() {
+        let path = Path::new("in.avi");
+        let argv = build_argv(&strings(&["ffmpeg", "-i", "{path}", "-f", "null", "{devnull}"]),
+                              path, None, &NO_HANDLER_ARGS, true);
+        #[cfg(not(windows))]
+        assert_eq!(argv, vec!["ffmpeg", "-i", "in.avi", "-f", "null", "/dev/null"]);
+        #[cfg(windows)]
+        assert_eq!(argv, vec!["ffmpeg", "-i", "in.avi", "-f", "null", "NUL"]);
+
+        // {devnull} alone doesn't suppress appending the path
+        let argv =
+            build_argv(&strings(&["tool", "--out={devnull}"]), path, None, &NO_HANDLER_ARGS, true);
+        #[cfg(not(windows))]
+        assert_eq!(argv, vec!["tool", "--out=/dev/null", "in.avi"]);
+        #[cfg(windows)]
+        assert_eq!(argv, vec!["tool", "--out=NUL", "in.avi"]);
+    }
+
+    #[test]
+    fn test_path_component_tokens() {
+        let argv = |path: &str| build_argv(&strings(&["tool", "--format={ext}", "{dir}",
+                                                      "{basename}"]),
+                                           Path::new(path), None, &NO_HANDLER_ARGS, false);
+        assert_eq!(argv("photos/IMG_0001.JPG"),
+                   vec!["tool", "--format=JPG", "photos", "IMG_0001.JPG"]);
+        assert_eq!(argv("archive.tar.gz"), vec!["tool", "--format=gz", ".", "archive.tar.gz"]);
+        assert_eq!(argv("/README"), vec!["tool", "--format=", "/", "README"]);
+
+        // Unknown and malformed tokens are left alone (validation rejects the former)
+        assert_eq!(expand_tokens("{ext}{{ext}}{nope}", Path::new("a.b"), None, &NO_HANDLER_ARGS),
+                   "b{b}{nope}");
+    }
+
+    #[test]
+    fn test_arg_tokens() {
+        let mut args = HandlerArgs::new();
+        args.insert("multipage".to_owned(), "true".to_owned());
+        args.insert("format".to_owned(), "tiff".to_owned());
+        let expand = |arg| expand_tokens(arg, Path::new("a.tif"), None, &args);
+
+        assert_eq!(expand("--format={arg:format}"), "--format=tiff");
+        assert_eq!(expand("{arg:multipage}/{arg:format}/{ext}"), "true/tiff/tif");
+        assert_eq!(expand("--missing={arg:missing}"), "--missing=");
+        assert_eq!(expand("{arg:}{arg:a b}{arg:format"), "{arg:}{arg:a b}{arg:format");
+
+        assert_eq!(arg_token_key("{arg:format}"), Some("format"));
+        assert_eq!(arg_token_key("{arg:}"), None);
+        assert_eq!(arg_token_key("{path}"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_paths() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = Path::new(OsStr::from_bytes(b"/tmp/\xff.zip"));
+        let argv = build_argv(&strings(&["unzip", "--in={path}", "{basename}", "{dir}"]), path,
+                              None, &NO_HANDLER_ARGS, true);
+        assert_eq!(argv[1].as_bytes(), b"--in=/tmp/\xff.zip");
+        assert_eq!(argv[2].as_bytes(), b"\xff.zip");
+        assert_eq!(argv[3].as_bytes(), b"/tmp");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_exit_status() {
+        let defaults = Options::default();
+        let path = Path::new("/dev/null");
+        assert!(verdict(&handler(r#"argv = ["/bin/true"]"#), path, &defaults).is_ok());
+        assert!(matches!(verdict(&handler(r#"argv = ["/bin/false"]"#), path, &defaults),
+                         Err(FailureType::InvalidContent(_))));
+        for argv0 in &["/nonexistent/verify_files_test", "verify_files_no_such_command"] {
+            let missing = handler(&format!("argv = [{:?}]", argv0));
+            assert!(matches!(verdict(&missing, path, &defaults),
+                             Err(FailureType::HandlerUnavailable(_))));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_exit_code_mapping() {
+        let defaults = Options::default();
+        let exiting = |code: i32| handler(&format!(
+            "argv = [\"sh\", \"-c\", \"exit {}\", \"sh\"]\n\
+             exit_codes.success = [0, 1]\n\
+             exit_codes.unsupported = [2]\n\
+             exit_codes.internal = [7]", code));
+        let path = Path::new("/dev/null");
+
+        assert!(verdict(&exiting(0), path, &defaults).is_ok());
+        assert!(verdict(&exiting(1), path, &defaults).is_ok());
+        assert!(matches!(verdict(&exiting(2), path, &defaults),
+                         Err(FailureType::UnsupportedFormat(_))));
+        assert!(matches!(verdict(&exiting(7), path, &defaults),
+                         Err(FailureType::InternalError(_))));
+        assert!(matches!(verdict(&exiting(3), path, &defaults),
+                         Err(FailureType::InvalidContent(_))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_executable() {
+        assert!(find_executable("sh").is_some());
+        assert_eq!(find_executable("/bin/sh"), Some(PathBuf::from("/bin/sh")));
+        assert_eq!(find_executable("verify_files_no_such_command"), None);
+        assert_eq!(find_executable("/nonexistent/sh"), None);
+
+        // Directories and non-executable files don't count
+        let dir = crate::tempdir::TempDir::new().unwrap();
+        let plain = dir.path().join("plain");
+        std::fs::write(&plain, b"#!/bin/sh\n").unwrap();
+        assert_eq!(find_executable(&dir.path().to_string_lossy()), None);
+        assert_eq!(find_executable(&plain.to_string_lossy()), None);
+    }
+
+    /// Drive letters, UNC paths, and long (`\\?\`-prefixed) paths must reach handlers unaltered
+    #[cfg(windows)]
+    #[test]
+    fn test_windows_paths() {
+        let cases = [
+            (r"C:\Users\me\backup.zip", r"C:\Users\me"),
+            (r"\\server\share\backup.zip", r"\\server\share\"),
+            (r"\\?\C:\very\long\backup.zip", r"\\?\C:\very\long"),
+        ];
+        for (raw, dir) in &cases {
+            let path = Path::new(raw);
+            let argv = &strings(&["7z", "t", "{path}"]);
+            assert_eq!(build_argv(argv, path, None, &NO_HANDLER_ARGS, true),
+                       vec![OsString::from("7z"), "t".into(), raw.into()]);
+            assert_eq!(expand_tokens("{dir}|{basename}|{ext}", path, None, &NO_HANDLER_ARGS),
+                       OsString::from(format!("{}|backup.zip|zip", dir)));
+        }
+        assert_eq!(expand_tokens("{devnull}", Path::new("C:\\x"), None, &NO_HANDLER_ARGS),
+                   OsString::from("NUL"));
+    }
+
+    /// `argv[0]` must be resolved with `PATHEXT` semantics, including batch file wrappers
+    #[cfg(windows)]
+    #[test]
+    fn test_windows_resolution() {
+        let defaults = Options::default();
+        let cmd = find_executable("cmd").expect("cmd should be found via PATHEXT");
+        assert!(cmd.extension().map_or(false, |x| x.eq_ignore_ascii_case("exe")));
+        assert!(find_executable("cmd.exe").is_some());
+
+        let dir = crate::tempdir::TempDir::new().unwrap();
+        let script = dir.path().join("wrapper.cmd");
+        std::fs::write(&script, "@if exist %1 (exit /b 0) else (exit /b 1)\r\n").unwrap();
+        let stem = dir.path().join("wrapper");
+        for argv0 in &[&script, &stem] {
+            assert!(find_executable(&argv0.to_string_lossy()).is_some());
+            let wrapper = handler(&format!("argv = [{:?}]", argv0.to_string_lossy()));
+            assert!(verdict(&wrapper, &script, &defaults).is_ok());
+            assert!(matches!(verdict(&wrapper, &dir.path().join("missing"), &defaults),
+                             Err(FailureType::InvalidContent(_))));
+        }
+
+        let missing = handler(r#"argv = ["verify_files_no_such_command", "t"]"#);
+        assert!(matches!(verdict(&missing, &script, &defaults),
+                         Err(FailureType::HandlerUnavailable(_))));
+    }
+
+    /// The subprocess must not inherit our stdout/stderr, nor block waiting on stdin
+    #[cfg(unix)]
+    #[test]
+    fn test_output_captured() {
+        let defaults = Options::default();
+        let noisy = handler(r#"argv = ["sh", "-c", "cat; echo noise; echo noise >&2", "sh"]"#);
+        let (result, output) = run(&noisy, Path::new("/dev/null"), &NO_HANDLER_ARGS, &defaults);
+        assert!(result.is_ok());
+        assert_eq!(output, Some(HandlerOutput { stdout: "noise\n".to_owned(),
+                                                stderr: "noise\n".to_owned() }));
+        assert_eq!(output.unwrap().to_string(), "stdout:\n\tnoise\nstderr:\n\tnoise");
+
+        // Binary output is rendered lossily rather than being dropped
+        let binary = handler(r#"argv = ["sh", "-c", 'printf "\377ok\n"', "sh"]"#);
+        let output = run(&binary, Path::new("/dev/null"), &NO_HANDLER_ARGS, &defaults).1.unwrap();
+        assert_eq!(output.stdout, "\u{FFFD}ok\n");
+        assert!(output.stderr.is_empty());
+
+        // Commands which never ran have no output to show
+        let missing = handler(r#"argv = ["verify_files_no_such_command"]"#);
+        assert_eq!(run(&missing, Path::new("/dev/null"), &NO_HANDLER_ARGS, &defaults).1, None);
+    }
+
+    /// Output beyond the cap must be discarded, with a note saying so
+    #[cfg(unix)]
+    #[test]
+    fn test_output_capped() {
+        let defaults = Options::default();
+        let flood =
+            handler(r#"argv = ["sh", "-c", "head -c 100000 /dev/zero | tr '\\0' x", "sh"]"#);
+        let output = run(&flood, Path::new("/dev/null"), &NO_HANDLER_ARGS, &defaults).1.unwrap();
+        let (kept, marker) = output.stdout.split_at(OUTPUT_CAP);
+        assert_eq!(kept, "x".repeat(OUTPUT_CAP));
+        assert_eq!(marker, "\n[output truncated after 4096 of 100000 bytes]");
+    }
+
+    #[test]
+    fn test_stderr_excerpt() {
+        let stderr = "Testing archive\nfoo.txt: CRC failed in foo.txt\nAll OK\n";
+        assert_eq!(stderr_excerpt(stderr, "CRC failed").as_deref(),
+                   Some("foo.txt: CRC failed in foo.txt"));
+        assert_eq!(stderr_excerpt(stderr, "Testing archive\nfoo").as_deref(),
+                   Some("Testing archive\nfoo.txt: CRC failed in foo.txt"));
+        assert_eq!(stderr_excerpt(stderr, "ERROR"), None);
+
+        let long = format!("ERROR: {}", "x".repeat(500));
+        let excerpt = stderr_excerpt(&long, "ERROR").unwrap();
+        assert_eq!(excerpt.chars().count(), STDERR_EXCERPT_LEN + 1);
+        assert!(excerpt.starts_with("ERROR: xxx") && excerpt.ends_with('…'));
+    }
+
+    /// A zero exit status shouldn't hide errors the tool only reported on `stderr`
+    #[cfg(unix)]
+    #[test]
+    fn test_fail_if_stderr() {
+        let defaults = Options::default();
+        let path = Path::new("/dev/null");
+        let chatty = |needle: &str| handler(&format!(
+            "argv = [\"sh\", \"-c\", \"echo 'Checking'; echo 'ERROR: bad CRC' >&2\", \"sh\"]\n\
+             fail_if_stderr = {:?}", needle));
+
+        match verdict(&chatty("ERROR"), path, &defaults) {
+            Err(FailureType::InvalidContent(msg)) => assert!(msg.contains("ERROR: bad CRC")),
+            other => panic!("Expected InvalidContent, got {:?}", other),
+        }
+        assert!(verdict(&chatty("WARNING"), path, &defaults).is_ok());
+        // Only stderr is checked
+        assert!(verdict(&chatty("Checking"), path, &defaults).is_ok());
+
+        // Non-UTF-8 output around the match must not hide it
+        let binary = handler(
+            r#"argv = ["sh", "-c", 'printf "\377\376 ERROR \377" >&2', "sh"]
+               fail_if_stderr = "ERROR""#);
+        assert!(matches!(verdict(&binary, path, &defaults), Err(FailureType::InvalidContent(_))));
+
+        // Matches past the point where output stops being kept must still be caught
+        let chatty = handler(
+            r#"argv = ["sh", "-c", "head -c 100000 /dev/zero | tr '\\0' x >&2; echo ERROR >&2"]
+               fail_if_stderr = "ERROR""#);
+        assert!(matches!(verdict(&chatty, path, &defaults), Err(FailureType::InvalidContent(_))));
+    }
+
+    /// Hung handlers must be killed (along with anything they spawned) and not count as failures
+    #[cfg(unix)]
+    #[test]
+    fn test_timeout() {
+        let defaults = Options::default();
+        let path = Path::new("/dev/null");
+        let short = Options { handler_timeout: Some(Duration::from_millis(200)),
+                              ..Options::default() };
+
+        let start = Instant::now();
+        match verdict(&handler(r#"argv = ["sh", "-c", "sleep 30", "sh"]"#), path, &short) {
+            Err(FailureType::InternalError(msg)) => assert!(msg.contains("timed out"), "{}", msg),
+            other => panic!("Expected a timeout, got {:?}", other),
+        }
+
+        // A grandchild holding the pipes open must not keep us waiting
+        let forking = handler(r#"argv = ["sh", "-c", "sleep 30 & sleep 30", "sh"]"#);
+        assert!(matches!(verdict(&forking, path, &short), Err(FailureType::InternalError(_))));
+
+        // The handler's own timeout takes precedence over the default
+        let own = handler("argv = [\"sh\", \"-c\", \"sleep 30\", \"sh\"]\ntimeout = \"200ms\"");
+        assert!(matches!(verdict(&own, path, &defaults), Err(FailureType::InternalError(_))));
+        assert!(start.elapsed() < Duration::from_secs(10), "Timed-out handlers weren't killed");
+
+        // ...and quick handlers are unaffected
+        let quick = handler(r#"argv = ["/bin/true"]"#);
+        assert!(verdict(&quick, path, &short).is_ok());
+    }
+
+    /// Files should be streamed to `stdin`, even if the handler doesn't read all of it
+    #[cfg(unix)]
+    #[test]
+    fn test_stdin_input() {
+        let defaults = Options::default();
+        let dir = crate::tempdir::TempDir::new().unwrap();
+        let (small, large) = (dir.path().join("small"), dir.path().join("large"));
+        std::fs::write(&small, b"hello ERROR").unwrap();
+        std::fs::write(&large, vec![b'x'; 4 * 1024 * 1024]).unwrap();
+        let stdin_handler = |script: &str, extra: &str| handler(&format!(
+            "argv = [\"sh\", \"-c\", {:?}, \"sh\"]\ninput = \"stdin\"\n{}", script, extra));
+
+        // ...and without the path being appended
+        let matches = stdin_handler(r#"test "$(cat)" = "hello ERROR" && test "$#" = 0"#, "");
+        assert!(verdict(&matches, &small, &defaults).is_ok());
+        assert!(matches!(verdict(&matches, &large, &defaults),
+                         Err(FailureType::InvalidContent(_))));
+
+        // A child which exits early mustn't cause a spurious I/O error
+        assert!(verdict(&stdin_handler("exit 0", ""), &large, &defaults).is_ok());
+        assert!(verdict(&stdin_handler("head -c 1 >/dev/null", ""), &large, &defaults).is_ok());
+
+        // fail_if_stderr and timeouts still apply
+        let echo = stdin_handler("cat >&2", "fail_if_stderr = \"ERROR\"");
+        assert!(matches!(verdict(&echo, &small, &defaults), Err(FailureType::InvalidContent(_))));
+        let hung = stdin_handler("sleep 30", "");
+        let short = Options { handler_timeout: Some(Duration::from_millis(200)),
+                              ..Options::default() };
+        assert!(matches!(verdict(&hung, &large, &short),
+                         Err(FailureType::InternalError(_))));
+
+        let missing = dir.path().join("missing");
+        assert!(matches!(verdict(&matches, &missing, &defaults), Err(FailureType::IoError(_))));
+    }
+
+    /// `cwd` should be expanded like `argv` and applied to the subprocess
+    #[cfg(unix)]
+    #[test]
+    fn test_cwd() {
+        let defaults = Options::default();
+        let dir = crate::tempdir::TempDir::new().unwrap();
+        let path = dir.path().join("file.txt");
+        std::fs::write(&path, b"x").unwrap();
+
+        let in_dir = handler(r#"argv = ["sh", "-c", 'test -e "$1"', "sh", "{basename}"]
+                                cwd = "{dir}""#);
+        assert!(verdict(&in_dir, &path, &defaults).is_ok());
+        let elsewhere = handler(r#"argv = ["sh", "-c", 'test -e "$1"', "sh", "{basename}"]
+                                   cwd = "/""#);
+        assert!(matches!(verdict(&elsewhere, &path, &defaults),
+                         Err(FailureType::InvalidContent(_))));
+    }
+
+    /// Scratch directories must exist while the command runs and be removed however it exits
+    #[cfg(unix)]
+    #[test]
+    fn test_tmpdir_token() {
+        let scratch = crate::tempdir::TempDir::new().unwrap();
+        let options = Options { scratch_dir: Some(scratch.path().to_owned()),
+                                handler_timeout: Some(Duration::from_millis(500)),
+                                ..Options::default() };
+        let path = Path::new("/dev/null");
+        let is_empty = || std::fs::read_dir(scratch.path()).unwrap().next().is_none();
+
+        // Read-only leftovers must not prevent cleanup
+        let script = r#"mkdir "$1/o" && touch "$1/o/f" && chmod a-w "$1/o/f" "$1/o""#;
+        let messy = handler(&format!("argv = [\"sh\", \"-c\", '{}', \"sh\", \"{{tmpdir}}\"]",
+                                     script));
+        assert!(verdict(&messy, path, &options).is_ok());
+        assert!(is_empty(), "Scratch directory was leaked");
+
+        let failing = handler(r#"argv = ["sh", "-c", 'test -d "$1" && exit 1', "sh", "{tmpdir}"]"#);
+        assert!(matches!(verdict(&failing, path, &options), Err(FailureType::InvalidContent(_))));
+        let hung = handler(r#"argv = ["sh", "-c", 'touch "$1/f"; sleep 30', "sh", "{tmpdir}"]"#);
+        assert!(matches!(verdict(&hung, path, &options), Err(FailureType::InternalError(_))));
+        assert!(is_empty(), "Scratch directory was leaked");
+
+        // cwd can use it too
+        let in_tmpdir = handler(r#"argv = ["sh", "-c", 'touch here && test -e "$1/here"', "sh",
+                                           "{tmpdir}"]
+                                   cwd = "{tmpdir}""#);
+        assert!(verdict(&in_tmpdir, path, &options).is_ok());
+        assert!(is_empty(), "Scratch directory was leaked");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_env() {
+        let defaults = Options::default();
+        let path = Path::new("/dev/null");
+        std::env::set_var("VERIFY_FILES_TEST_INHERITED", "1");
+
+        let with_env = |extra: &str| handler(&format!(
+            "argv = [\"/bin/sh\", \"-c\", 'test \"$LANG\" = C && test -d \"$SCRATCH\" && \
+             test -n \"$VERIFY_FILES_TEST_INHERITED\"', \"sh\"]\n\
+             env = {{ LANG = \"C\", SCRATCH = \"{{tmpdir}}\" }}\n{}", extra));
+        assert!(verdict(&with_env(""), path, &defaults).is_ok());
+        assert!(matches!(verdict(&with_env("env_clear = true"), path, &defaults),
+                         Err(FailureType::InvalidContent(_))));
+
+        // Overriding PATH must change where argv[0] is found, with or without wrappers
+        use std::os::unix::fs::PermissionsExt;
+        let bin = crate::tempdir::TempDir::new().unwrap();
+        let tool = bin.path().join("verify_files_test_tool");
+        std::fs::write(&tool, b"#!/bin/sh\nexit 0\n").unwrap();
+        std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let on_path = |extra: &str| handler(&format!(
+            "argv = [\"verify_files_test_tool\"]\nenv = {{ PATH = {:?} }}\n{}",
+            bin.path().to_string_lossy(), extra));
+        let wrappers =
+            ["", "nice = 5", "limits.cpu_seconds = 5", "nice = 5\nlimits.memory_mb = 64"];
+        for extra in &wrappers {
+            assert!(verdict(&on_path(extra), path, &defaults).is_ok(), "{:?}", extra);
+        }
+        let elsewhere = handler("argv = [\"verify_files_test_tool\"]\nnice = 5");
+        assert!(matches!(verdict(&elsewhere, path, &defaults),
+                         Err(FailureType::HandlerUnavailable(_))));
+    }
+
+    /// Runaway handlers must be stopped by their limits and not reported as corruption
+    #[cfg(unix)]
+    #[test]
+    fn test_limits() {
+        let defaults = Options::default();
+        let path = Path::new("/dev/null");
+        let limited = |script: &str, limits: &str| handler(&format!(
+            "argv = [\"sh\", \"-c\", {:?}, \"sh\"]\nlimits = {{ {} }}", script, limits));
+
+        let hog = limited(r#"x=a; while :; do x="$x$x"; done"#, "memory_mb = 64");
+        match verdict(&hog, path, &defaults) {
+            Err(FailureType::InternalError(msg)) => assert!(msg.contains("memory_mb"), "{}", msg),
+            other => panic!("Expected the memory limit to trigger, got {:?}", other),
+        }
+        let spinner = limited("while :; do :; done", "cpu_seconds = 1");
+        match verdict(&spinner, path, &defaults) {
+            Err(FailureType::InternalError(msg)) => assert!(msg.contains("cpu_seconds"), "{}", msg),
+            other => panic!("Expected the CPU limit to trigger, got {:?}", other),
+        }
+
+        // Well-behaved commands and ordinary failures are unaffected
+        let quick = limited("exit 0", "memory_mb = 64, cpu_seconds = 5");
+        assert!(verdict(&quick, path, &defaults).is_ok());
+        assert!(matches!(verdict(&limited("exit 1", "memory_mb = 64"), path, &defaults),
+                         Err(FailureType::InvalidContent(_))));
+        let missing = handler(r#"argv = ["verify_files_no_such_command"]
+                                 limits.cpu_seconds = 5"#);
+        assert!(matches!(verdict(&missing, path, &defaults),
+                         Err(FailureType::HandlerUnavailable(_))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_priority() {
+        let defaults = Options::default();
+        let path = Path::new("/dev/null");
+        let niced = handler(r#"argv = ["sh", "-c", 'test "$(nice)" -ge 5', "sh"]
+                               nice = 5"#);
+        assert!(verdict(&niced, path, &defaults).is_ok());
+
+        // With limits too, every wrapper must be chained correctly
+        let both = handler(r#"argv = ["sh", "-c", 'test "$(nice)" -ge 5', "sh"]
+                              nice = 5
+                              limits.cpu_seconds = 5"#);
+        assert!(verdict(&both, path, &defaults).is_ok());
+
+        let missing = handler(r#"argv = ["verify_files_no_such_command"]
+                                 nice = 5"#);
+        assert!(matches!(verdict(&missing, path, &defaults),
+                         Err(FailureType::HandlerUnavailable(_))));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_ionice_class() {
+        let defaults = Options::default();
+        let idle = handler(r#"argv = ["sh", "-c", 'test "$(ionice -p $$)" = idle', "sh"]
+                              ionice_class = "idle""#);
+        assert!(verdict(&idle, Path::new("/dev/null"), &defaults).is_ok());
+    }
+}
@@ -0,0 +1,125 @@
+//! A minimal self-cleaning temporary directory
+//!
+//! (Used for things like extracting archive members so they can be handed to handlers which
+//! only accept paths.)
+
+// Standard library imports
+use std::env;
+use std::fs;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A counter to keep directory names unique within this process
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A uniquely-named directory which is recursively deleted when dropped
+pub struct TempDir {
+    /// The path to the directory
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Create a new directory inside the system's temporary directory
+    pub fn new() -> io::Result<Self> {
+        Self::new_in(&env::temp_dir())
+    }
+
+    /// Create a new directory inside the given parent directory
+    pub fn new_in(parent: &Path) -> io::Result<Self> {
+        loop {
+            let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+            let path = parent.join(format!("verify_files-{}-{}", process::id(), count));
+
+            // `create_dir` fails if the path already exists, which makes this race-free
+            match fs::create_dir(&path) {
+                Ok(()) => return Ok(Self { path }),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// The path to the directory
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Recursively clear the read-only flag on everything under `path` (without following symlinks)
+/// so it can be deleted
+///
+/// (On Unix, it's a read-only *directory* that prevents deletion, while on Windows it's a
+/// read-only file, so both are made writable.)
+fn make_writable(path: &Path) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(path)?;
+    if metadata.file_type().is_symlink() {
+        return Ok(());
+    }
+
+    let mut permissions = metadata.permissions();
+    if permissions.readonly() {
+        // Only grant access to the owner, rather than to everyone like `set_readonly(false)`
+        #[cfg(unix)]
+        permissions.set_mode(permissions.mode() | 0o700);
+        #[cfg(not(unix))]
+        #[allow(clippy::permissions_set_readonly_false)]
+        permissions.set_readonly(false);
+        fs::set_permissions(path, permissions)?;
+    }
+    if metadata.is_dir() {
+        for entry in fs::read_dir(path)? {
+            make_writable(&entry?.path())?;
+        }
+    }
+    Ok(())
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        // Subprocesses given scratch directories may leave read-only files behind
+        if fs::remove_dir_all(&self.path).is_err() {
+            #[allow(clippy::let_underscore_must_use)]
+            let _ = make_writable(&self.path).and_then(|()| fs::remove_dir_all(&self.path));
+        }
+    }
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_creates_and_cleans_up() {
+        let (first, second) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        assert_ne!(first.path(), second.path());
+        assert!(first.path().is_dir());
+
+        let path = first.path().to_owned();
+        fs::write(path.join("file"), b"test").unwrap();
+        drop(first);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_cleans_up_read_only_contents() {
+        let tempdir = TempDir::new().unwrap();
+        let path = tempdir.path().to_owned();
+        let subdir = path.join("subdir");
+        fs::create_dir(&subdir).unwrap();
+        fs::write(subdir.join("file"), b"test").unwrap();
+
+        for target in &[subdir.join("file"), subdir.clone()] {
+            let mut permissions = fs::metadata(target).unwrap().permissions();
+            permissions.set_readonly(true);
+            fs::set_permissions(target, permissions).unwrap();
+        }
+        drop(tempdir);
+        assert!(!path.exists());
+    }
+}
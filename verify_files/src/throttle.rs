@@ -0,0 +1,146 @@
+//! Limits on how many external handlers may run at once
+//!
+//! (Sixteen concurrent `ffmpeg` processes are a very different load from sixteen concurrent
+//! built-in CRC checks, so subprocesses get a budget of their own rather than sharing whatever
+//! limits the rest of the processing is under.)
+//!
+//! **NOTE:** To rule out deadlocks, a permit is only ever held for the duration of a single
+//! subprocess, never across a whole fallback chain, and the per-handler permit is always acquired
+//! before the global one.
+
+// Standard library imports
+use std::collections::BTreeMap;
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+
+// 3rd-party crate imports
+use log::debug;
+
+// Local Imports
+use crate::config::Root;
+
+/// A counting semaphore
+pub struct Semaphore {
+    /// How many more permits can be handed out
+    available: Mutex<usize>,
+    /// Signalled whenever a permit is returned
+    returned: Condvar,
+}
+
+impl Semaphore {
+    /// Create a semaphore which allows `permits` holders at once
+    pub fn new(permits: usize) -> Self {
+        Self { available: Mutex::new(permits), returned: Condvar::new() }
+    }
+
+    /// Lock the count, ignoring poisoning since it's only ever incremented or decremented
+    fn lock(&self) -> MutexGuard<'_, usize> {
+        self.available.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Block until a permit is available, then take it
+    pub fn acquire(&self) -> Permit<'_> {
+        let mut available = self.lock();
+        while *available == 0 {
+            available = self.returned.wait(available).unwrap_or_else(PoisonError::into_inner);
+        }
+        *available -= 1;
+        Permit { semaphore: self }
+    }
+}
+
+/// A permit taken from a [`Semaphore`], which is returned when dropped
+pub struct Permit<'a> {
+    /// The semaphore to return the permit to
+    semaphore: &'a Semaphore,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        *self.semaphore.lock() += 1;
+        self.semaphore.returned.notify_one();
+    }
+}
+
+/// The concurrency limits for external handlers
+pub struct Throttle {
+    /// The limit shared by all external handlers
+    global: Semaphore,
+    /// Limits for the handlers which set `max_concurrency`
+    per_handler: BTreeMap<String, Semaphore>,
+}
+
+impl Throttle {
+    /// Set up limits of `jobs` subprocesses overall, plus whatever `max_concurrency` limits the
+    /// configuration sets
+    pub fn new(config: &Root, jobs: usize) -> Self {
+        let jobs = jobs.max(1);
+        let per_handler: BTreeMap<_, _> = config
+            .handlers
+            .iter()
+            .filter_map(|(id, handler)| handler.max_concurrency.map(|x| (id, x)))
+            .collect();
+
+        debug!("Running at most {} external handler(s) at once", jobs);
+        for (id, limit) in &per_handler {
+            debug!("Running at most {} instance(s) of handler {} at once", limit.min(&jobs), id);
+        }
+        Self {
+            global: Semaphore::new(jobs),
+            per_handler: per_handler
+                .into_iter()
+                .map(|(id, limit)| (id.clone(), Semaphore::new(limit)))
+                .collect(),
+        }
+    }
+
+    /// Block until the given handler may be run, returning permits to hold while it runs
+    pub fn acquire(&self, handler_id: &str) -> (Option<Permit<'_>>, Permit<'_>) {
+        let own = self.per_handler.get(handler_id).map(Semaphore::acquire);
+        (own, self.global.acquire())
+    }
+}
+
+// ----==== Tests ====----
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    /// Run 8 threads which each briefly hold permits for `handler_id`, returning the highest
+    /// number of holders seen at once
+    fn peak_holders(throttle: &Throttle, handler_id: &str) -> usize {
+        let (current, peak) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    let _permits = throttle.acquire(handler_id);
+                    let holders = current.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(holders, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(20));
+                    current.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+        peak.into_inner()
+    }
+
+    #[test]
+    fn test_limits() {
+        let config = crate::config::parse(r#"
+            [handler.clamav]
+            argv = ["clamscan"]
+            max_concurrency = 1
+
+            [handler.ffmpeg]
+            argv = ["ffmpeg"]
+        "#, &|_| false).unwrap();
+        let throttle = Throttle::new(&config, 3);
+
+        assert_eq!(peak_holders(&throttle, "ffmpeg"), 3);
+        assert_eq!(peak_holders(&throttle, "clamav"), 1);
+        assert_eq!(peak_holders(&throttle, "not_a_handler"), 3);
+    }
+}